@@ -0,0 +1,43 @@
+//! 编译时记录 git commit 和锁定的 candle 版本，`GET /info` 和启动信息里用
+
+use std::path::Path;
+use std::process::Command;
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    let commit = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !commit.trim().is_empty()).then(|| commit.trim().to_string())
+}
+
+/// Cargo.lock 里 `name = "candle-core"` 下一行的 version
+fn candle_version() -> Option<String> {
+    let lock = std::fs::read_to_string("Cargo.lock").ok()?;
+    let mut lines = lock.lines();
+    lines.find(|line| *line == r#"name = "candle-core""#)?;
+    let version = lines.next()?.strip_prefix("version = ")?;
+    Some(version.trim_matches('"').to_string())
+}
+
+fn main() {
+    let unknown = || "unknown".to_string();
+    println!(
+        "cargo:rustc-env=LOCAL_LLM_GIT_COMMIT={}",
+        git_commit().unwrap_or_else(unknown)
+    );
+    println!(
+        "cargo:rustc-env=LOCAL_LLM_CANDLE_VERSION={}",
+        candle_version().unwrap_or_else(unknown)
+    );
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    // 不在 git 仓库里（例如从 crate 包构建）时不监视，否则每次都会重新运行
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! 网络层面的访问限制，和 API key 互相独立：
+//!
+//! - `localhost_only`: 强制只监听 127.0.0.1（即使 `address` 被配成 0.0.0.0），
+//!   同时拒绝非本机地址的连接
+//! - `allow_ips`: 客户端地址白名单（CIDR 或单个 IP），空表示不限制
+//!
+//! 判断用的是 TCP 对端地址，不看 `X-Real-IP` 之类可以伪造的 header；
+//! 放在反向代理后面时需要把代理的地址加进白名单。
+
+use std::fmt;
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Method, Status};
+use rocket::{Build, Data, Request, Response, Rocket};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::config::ServerConfig;
+use crate::api::api_error;
+
+/// 被拒绝的请求改写到这个不存在的路径，不会进入任何 handler
+const DENIED_PATH: &str = "/__access_denied";
+
+/// `ServerConfig.access`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessConfig {
+    pub localhost_only: bool,
+    /// 例如 `["127.0.0.1", "192.168.1.0/24", "fd00::/8"]`
+    pub allow_ips: Vec<String>,
+}
+
+/// 一段地址：`addr/prefix`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_matches(u32::from(net).into(), u32::from(ip).into(), 32, self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// 高 `prefix` 位相同
+fn prefix_matches(net: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    let shift = bits - prefix;
+    shift >= bits || (net >> shift) == (ip >> shift)
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid IP or CIDR `{s}`");
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = IpAddr::from_str(addr)
+            .map_err(|_| invalid())?
+            .to_canonical();
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => bits,
+        };
+        if prefix > bits {
+            return Err(invalid());
+        }
+        Ok(IpNet { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// 解析好的访问策略，ignite 时放进 managed state
+#[derive(Debug, Clone, Default)]
+pub struct AccessPolicy {
+    localhost_only: bool,
+    allow: Vec<IpNet>,
+}
+
+impl AccessPolicy {
+    pub fn from_config(config: &AccessConfig) -> Result<Self, String> {
+        let allow = config
+            .allow_ips
+            .iter()
+            .map(|s| s.parse())
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            localhost_only: config.localhost_only,
+            allow,
+        })
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.localhost_only || !self.allow.is_empty()
+    }
+
+    /// 对端地址未知（例如本地测试客户端没设置）时，有限制就拒绝
+    pub fn allows(&self, peer: Option<IpAddr>) -> bool {
+        if !self.is_restricted() {
+            return true;
+        }
+        let Some(ip) = peer.map(|ip| ip.to_canonical()) else {
+            return false;
+        };
+        if self.localhost_only && !ip.is_loopback() {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip))
+    }
+}
+
+/// `localhost_only` 时把监听地址改成 127.0.0.1（已经是 loopback 的地址保持不变）
+pub fn restrict_bind_address(figment: Figment) -> Figment {
+    let localhost_only = figment
+        .extract_inner::<bool>("access.localhost_only")
+        .unwrap_or(false);
+    if !localhost_only {
+        return figment;
+    }
+    let address = figment.extract_inner::<IpAddr>("address").ok();
+    if address.is_some_and(|ip| ip.is_loopback()) {
+        return figment;
+    }
+    if let Some(address) = address {
+        warn!(%address, "localhost_only is set, binding to 127.0.0.1 instead");
+    }
+    figment.merge(("address", IpAddr::V4(Ipv4Addr::LOCALHOST)))
+}
+
+/// 被拒绝时记在 request-local cache 里，响应阶段替换成 403
+#[derive(Debug, Clone, Copy)]
+struct Denied(Option<IpAddr>);
+
+pub struct AccessControl;
+
+#[rocket::async_trait]
+impl Fairing for AccessControl {
+    fn info(&self) -> Info {
+        Info {
+            name: "Client IP access control",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let config = rocket
+            .state::<ServerConfig>()
+            .map(|config| config.access.clone())
+            .unwrap_or_default();
+        match AccessPolicy::from_config(&config) {
+            Ok(policy) => {
+                if !policy.allow.is_empty() {
+                    let nets: Vec<String> = policy.allow.iter().map(ToString::to_string).collect();
+                    info!("allowing clients from {}", nets.join(", "));
+                }
+                Ok(rocket.manage(policy))
+            }
+            Err(e) => {
+                error!("{e}");
+                Err(rocket)
+            }
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let Some(policy) = req.rocket().state::<AccessPolicy>() else {
+            return;
+        };
+        let peer = req.remote().map(|addr| addr.ip());
+        if policy.allows(peer) {
+            return;
+        }
+        req.local_cache(|| Some(Denied(peer)));
+        req.set_method(Method::Get);
+        req.set_uri(Origin::parse(DENIED_PATH).expect("valid path"));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(Denied(peer)) = *req.local_cache(|| None::<Denied>) else {
+            return;
+        };
+        let peer = peer.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+        let error = api_error(
+            Status::Forbidden,
+            "ip_not_allowed",
+            format!("client address {peer} is not allowed"),
+        );
+        let body = serde_json::to_string(&error.into_response()).unwrap_or_default();
+        *res = Response::new();
+        res.set_status(Status::Forbidden);
+        res.set_header(ContentType::JSON);
+        res.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_matching() {
+        let net: IpNet = "192.168.1.0/24".parse().unwrap();
+        assert!(net.contains(ip("192.168.1.77")));
+        assert!(!net.contains(ip("192.168.2.1")));
+        // IPv4-mapped IPv6 按 IPv4 处理
+        assert!(net.contains(ip("::ffff:192.168.1.5")));
+
+        let single: IpNet = "10.0.0.1".parse().unwrap();
+        assert!(single.contains(ip("10.0.0.1")));
+        assert!(!single.contains(ip("10.0.0.2")));
+
+        let any: IpNet = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("8.8.8.8")));
+        assert!(!any.contains(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+
+        let v6: IpNet = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(ip("fd12::1")));
+
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("localhost".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn localhost_only_rejects_remote_peers() {
+        let policy = AccessPolicy::from_config(&AccessConfig {
+            localhost_only: true,
+            allow_ips: Vec::new(),
+        })
+        .unwrap();
+        assert!(policy.allows(Some(ip("127.0.0.1"))));
+        assert!(policy.allows(Some(ip("::1"))));
+        assert!(!policy.allows(Some(ip("192.168.1.5"))));
+        assert!(!policy.allows(None));
+        assert!(AccessPolicy::default().allows(None));
+    }
+}
@@ -0,0 +1,149 @@
+//! 运维类接口（`/admin/*`）
+//!
+//! 破坏性操作（unload / delete，以及 `/models/<name>/cache/clear` 的 evict）在模型忙时
+//! 需要两阶段确认，见 `confirm` 模块。删除的模型进回收站（`/admin/trash`），见 `trash` 模块。
+
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::{AppState, LoadError};
+use crate::config::ServerConfig;
+use crate::confirm::{AdminAction, Impact};
+use crate::integrity::{self, ScanOptions};
+use crate::types::{AdminActionResponse, IntegrityScanRequest, JobAcceptedResponse};
+
+/// 后台扫描 GGUF 缓存目录：POST /admin/integrity/scan
+/// body 可选：`{"redownload": true}` 会重新下载损坏的 hub 文件
+#[utoipa::path(
+    tag = "admin",
+    request_body(content = Option<IntegrityScanRequest>),
+    responses((status = 202, description = "scan started; poll GET /jobs/{id}", body = JobAcceptedResponse))
+)]
+#[post("/admin/integrity/scan", data = "<req>")]
+pub async fn integrity_scan(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    req: Option<Json<IntegrityScanRequest>>,
+) -> status::Custom<Json<JobAcceptedResponse>> {
+    let options = ScanOptions {
+        cache_dir: config.model_cache_dir(),
+        redownload: req.map(|r| r.redownload).unwrap_or(false),
+    };
+    let job_id = state.jobs.spawn_blocking("integrity_scan", move |job| {
+        integrity::scan(&options, Some(job))
+    });
+
+    status::Custom(Status::Accepted, Json(JobAcceptedResponse { job_id }))
+}
+
+/// 破坏性操作前的检查，通过时返回当前的影响评估：
+/// - `force=true` 直接执行
+/// - 带了 `confirm` token 时必须有效（一次性、60 秒内、同一操作和模型）
+/// - 模型空闲时直接执行
+/// - 否则返回 409 + 影响评估 + 新的确认 token
+pub(crate) fn confirm_destructive(
+    state: &AppState,
+    action: AdminAction,
+    model_name: &str,
+    force: bool,
+    confirm: Option<&str>,
+) -> Result<Impact, ApiError> {
+    let impact = state.impact(model_name);
+    if force {
+        return Ok(impact);
+    }
+    if let Some(token) = confirm {
+        if state.confirmations.redeem(token, action, model_name) {
+            return Ok(impact);
+        }
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_confirmation",
+            format!("confirmation token is invalid or expired for {action} of `{model_name}`"),
+        ));
+    }
+    if impact.is_idle() {
+        return Ok(impact);
+    }
+
+    let confirmation = state.confirmations.issue(action, model_name, impact);
+    Err(api_error(
+        Status::Conflict,
+        "confirmation_required",
+        format!(
+            "model `{model_name}` has {} in-flight and {} queued requests; \
+             repeat the call with ?confirm=<token> or ?force=true",
+            impact.in_flight, impact.queued
+        ),
+    )
+    .with_confirmation(confirmation))
+}
+
+pub(crate) fn load_error(e: LoadError) -> ApiError {
+    let (status, error) = match &e {
+        LoadError::NotFound(_) => (Status::NotFound, "model_not_found"),
+        _ => (Status::Conflict, "invalid_state"),
+    };
+    api_error(status, error, e.to_string())
+}
+
+/// 卸载模型（保留注册信息）：POST /admin/models/<name>/unload[?force=true|?confirm=<token>]
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, body = AdminActionResponse),
+        (status = 400, description = "invalid confirmation token", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model is busy, confirmation required", body = ErrorResponse)
+    )
+)]
+#[post("/admin/models/<name>/unload?<force>&<confirm>")]
+pub async fn unload_model(
+    state: &State<Arc<AppState>>,
+    name: &str,
+    force: Option<bool>,
+    confirm: Option<&str>,
+) -> Result<Json<AdminActionResponse>, ApiError> {
+    let action = AdminAction::Unload;
+    let impact = confirm_destructive(state, action, name, force.unwrap_or(false), confirm)?;
+    state.unload_model(name).map_err(load_error)?;
+    Ok(Json(AdminActionResponse {
+        action,
+        model_name: name.to_string(),
+        impact,
+    }))
+}
+
+/// 卸载并删除模型：DELETE /admin/models/<name>[?force=true|?confirm=<token>]
+///
+/// 只是软删除：模型进回收站，保留期内可以用 `POST /admin/trash/<name>/restore` 恢复，见 `trash`
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, body = AdminActionResponse),
+        (status = 400, description = "invalid confirmation token", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model is busy, confirmation required", body = ErrorResponse)
+    )
+)]
+#[delete("/admin/models/<name>?<force>&<confirm>")]
+pub async fn delete_model(
+    state: &State<Arc<AppState>>,
+    name: &str,
+    force: Option<bool>,
+    confirm: Option<&str>,
+) -> Result<Json<AdminActionResponse>, ApiError> {
+    let action = AdminAction::Delete;
+    let impact = confirm_destructive(state, action, name, force.unwrap_or(false), confirm)?;
+    state.delete_model(name).map_err(load_error)?;
+    Ok(Json(AdminActionResponse {
+        action,
+        model_name: name.to_string(),
+        impact,
+    }))
+}
@@ -0,0 +1,374 @@
+//! 自带前端的管理页用的 JSON 接口（`/admin/api/*`）。
+//!
+//! 和公开的推理 / 模型接口不同，这里按页面需要组织数据，只挂在 `/`（没有 `/api/v1` 别名），
+//! 字段可能随前端一起变，外部程序应该用公开接口：
+//! - `GET /admin/api/models`：每个模型的状态、进行中 / 排队的请求数，加载中时带已等待的时间和下载进度
+//! - `POST /admin/api/models/<name>/load` / `unload`：后台加载（进度轮询上面的列表）、卸载（忙时两阶段确认）
+//! - `GET /admin/api/queue`：permit 占用、等 permit 的请求数和所有进行中的请求
+//! - `DELETE /admin/api/requests/<id>`：取消请求
+//! - `GET /admin/api/usage?minutes=&bucket=`：请求数 / 错误数 / token 数 / p95 延迟的时间序列，
+//!   空的时间段补 0，前端直接画图
+//!
+//! 和其他 `/admin` 接口一样受 `require_api_key` 保护。
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::admin::{confirm_destructive, load_error};
+use crate::api::{api_error, check_memory, ApiError};
+use crate::app_state::AppState;
+use crate::confirm::{AdminAction, Impact};
+use crate::generations::{ActiveRequest, CancelledRequest};
+use crate::model_registry::{ModelMetadata, ModelStatus};
+use crate::perf_history::now_ms;
+use crate::pull::{PullProgress, PullStatus};
+use crate::request_log::{aggregate, MetricsRow, RequestLogEntry};
+
+/// 这些接口的路径前缀
+pub const ADMIN_API: &str = "/admin/api/";
+/// `usage` 默认看最近多少分钟
+const DEFAULT_USAGE_MINUTES: u64 = 60;
+/// `usage` 默认的时间段长度（秒）
+const DEFAULT_USAGE_BUCKET_SECS: u64 = 60;
+/// 一条序列最多多少个点
+const MAX_USAGE_POINTS: u64 = 1440;
+
+/// 管理页上的一个模型
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AdminModel {
+    pub name: String,
+    pub status: ModelStatus,
+    pub engine_kind: String,
+    /// 状态为 Error 时的原因
+    pub error: Option<String>,
+    /// 正在执行 / 排队的请求数
+    pub active: Impact,
+    /// 启动以来结束的请求数
+    pub requests_total: u64,
+    /// 状态为 Loading 时的进度
+    pub load: Option<LoadProgress>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LoadProgress {
+    /// 进入 Loading 到现在
+    pub elapsed_ms: u64,
+    /// 权重还在下载时（`POST /models/<name>/pull`）的下载进度
+    pub download: Option<PullProgress>,
+}
+
+/// 推理队列
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QueueView {
+    /// permit 总数（`max_concurrent_infer`）
+    pub capacity: usize,
+    pub in_use: usize,
+    /// 等 permit 的请求数
+    pub waiting: usize,
+    /// 最多几个请求排队，None 表示不限
+    pub max_waiting: Option<usize>,
+    /// 排队中和执行中的请求，最早开始的在前
+    pub requests: Vec<ActiveRequest>,
+}
+
+/// 一个时间段的用量
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct UsagePoint {
+    /// 时间段起点（unix 秒）
+    pub bucket_start: u64,
+    pub requests: usize,
+    pub errors: usize,
+    pub tokens: usize,
+    pub latency_p95_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UsageResponse {
+    pub bucket_secs: u64,
+    /// 所有模型合计，每个时间段一个点
+    pub total: Vec<UsagePoint>,
+    /// 模型名 -> 和 `total` 对齐的序列
+    pub models: BTreeMap<String, Vec<UsagePoint>>,
+}
+
+fn admin_model(state: &AppState, meta: ModelMetadata) -> AdminModel {
+    let load = (meta.status == ModelStatus::Loading).then(|| LoadProgress {
+        elapsed_ms: meta
+            .last_updated
+            .and_then(|at| at.elapsed().ok())
+            .map_or(0, |elapsed| elapsed.as_millis() as u64),
+        download: state
+            .pulls
+            .get(&meta.name)
+            .filter(|pull| pull.status() == PullStatus::Downloading)
+            .map(|pull| pull.snapshot()),
+    });
+    AdminModel {
+        active: state.impact(&meta.name),
+        requests_total: state.metrics.requests(&meta.name),
+        engine_kind: meta.engine_kind.to_string(),
+        error: meta.error.map(|e| e.message),
+        status: meta.status,
+        load,
+        name: meta.name,
+    }
+}
+
+/// 把（时间段，模型）的聚合结果排成 `[from, to)` 里每个时间段一个点的序列，没有请求的时间段补 0
+fn series(rows: &[MetricsRow], from_secs: u64, to_secs: u64, bucket_secs: u64) -> Vec<UsagePoint> {
+    let by_bucket: BTreeMap<u64, &MetricsRow> = rows.iter().map(|r| (r.bucket_start, r)).collect();
+    let first = from_secs - from_secs % bucket_secs;
+    (first..to_secs)
+        .step_by(bucket_secs as usize)
+        .map(|bucket_start| match by_bucket.get(&bucket_start) {
+            Some(row) => UsagePoint {
+                bucket_start,
+                requests: row.requests,
+                errors: row.errors,
+                tokens: row.tokens,
+                latency_p95_ms: row.latency_p95_ms,
+            },
+            None => UsagePoint {
+                bucket_start,
+                requests: 0,
+                errors: 0,
+                tokens: 0,
+                latency_p95_ms: None,
+            },
+        })
+        .collect()
+}
+
+fn usage(
+    entries: Vec<RequestLogEntry>,
+    from_secs: u64,
+    to_secs: u64,
+    bucket_secs: u64,
+) -> UsageResponse {
+    let mut by_model: BTreeMap<String, Vec<MetricsRow>> = BTreeMap::new();
+    for row in aggregate(&entries, bucket_secs) {
+        by_model.entry(row.model.clone()).or_default().push(row);
+    }
+    // 合计的 p95 不能由各模型的 p95 算出来，整体再聚合一次
+    let all: Vec<RequestLogEntry> = entries
+        .into_iter()
+        .map(|e| RequestLogEntry {
+            model: String::new(),
+            ..e
+        })
+        .collect();
+    UsageResponse {
+        bucket_secs,
+        total: series(
+            &aggregate(&all, bucket_secs),
+            from_secs,
+            to_secs,
+            bucket_secs,
+        ),
+        models: by_model
+            .into_iter()
+            .map(|(model, rows)| (model, series(&rows, from_secs, to_secs, bucket_secs)))
+            .collect(),
+    }
+}
+
+/// 所有模型及加载进度：GET /admin/api/models
+#[utoipa::path(
+    tag = "admin-ui",
+    responses((status = 200, body = Vec<AdminModel>))
+)]
+#[get("/admin/api/models")]
+pub async fn models(state: &State<Arc<AppState>>) -> Json<Vec<AdminModel>> {
+    let mut models = state.list_models();
+    models.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(
+        models
+            .into_iter()
+            .map(|meta| admin_model(state, meta))
+            .collect(),
+    )
+}
+
+/// 后台加载：POST /admin/api/models/<name>/load[?force=true]
+///
+/// 立即返回 202，进度轮询 `GET /admin/api/models`；`force` 跳过内存检查
+#[utoipa::path(
+    tag = "admin-ui",
+    responses(
+        (status = 202, body = AdminModel),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model is already loading or loaded", body = ErrorResponse),
+        (status = 507, description = "estimated memory exceeds available memory", body = ErrorResponse)
+    )
+)]
+#[post("/admin/api/models/<name>/load?<force>")]
+pub async fn load_model(
+    state: &State<Arc<AppState>>,
+    name: &str,
+    force: Option<bool>,
+) -> Result<status::Custom<Json<AdminModel>>, ApiError> {
+    check_memory(state, name, force.unwrap_or(false))?;
+    let meta = state.load_model_in_background(name).map_err(load_error)?;
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(admin_model(state, meta)),
+    ))
+}
+
+/// 卸载：POST /admin/api/models/<name>/unload[?force=true|?confirm=<token>]
+///
+/// 模型忙时和 `/admin/models/<name>/unload` 一样返回 409 + 确认 token
+#[utoipa::path(
+    tag = "admin-ui",
+    responses(
+        (status = 200, body = AdminModel),
+        (status = 400, description = "invalid confirmation token", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model is busy, confirmation required", body = ErrorResponse)
+    )
+)]
+#[post("/admin/api/models/<name>/unload?<force>&<confirm>")]
+pub async fn unload_model(
+    state: &State<Arc<AppState>>,
+    name: &str,
+    force: Option<bool>,
+    confirm: Option<&str>,
+) -> Result<Json<AdminModel>, ApiError> {
+    confirm_destructive(
+        state,
+        AdminAction::Unload,
+        name,
+        force.unwrap_or(false),
+        confirm,
+    )?;
+    let meta = state.unload_model(name).map_err(load_error)?;
+    Ok(Json(admin_model(state, meta)))
+}
+
+/// 推理队列：GET /admin/api/queue
+#[utoipa::path(
+    tag = "admin-ui",
+    responses((status = 200, body = QueueView))
+)]
+#[get("/admin/api/queue")]
+pub async fn queue(state: &State<Arc<AppState>>) -> Json<QueueView> {
+    let capacity = state.max_concurrent_infer;
+    Json(QueueView {
+        capacity,
+        in_use: capacity.saturating_sub(state.semaphore.available_permits()),
+        waiting: state.queue.len(),
+        max_waiting: state.queue.max_len(),
+        requests: state.generations.active(),
+    })
+}
+
+/// 取消排队中或执行中的请求：DELETE /admin/api/requests/<request_id>
+#[utoipa::path(
+    tag = "admin-ui",
+    responses(
+        (status = 200, body = CancelledRequest),
+        (status = 404, description = "no such queued or running request", body = ErrorResponse)
+    )
+)]
+#[delete("/admin/api/requests/<request_id>")]
+pub async fn cancel_request(
+    state: &State<Arc<AppState>>,
+    request_id: &str,
+) -> Result<Json<CancelledRequest>, ApiError> {
+    state
+        .generations
+        .cancel(request_id)
+        .map(Json)
+        .ok_or_else(|| {
+            api_error(
+                Status::NotFound,
+                "request_not_found",
+                format!("no queued or running request `{request_id}`"),
+            )
+        })
+}
+
+/// 用量时间序列：GET /admin/api/usage?minutes=&bucket=
+///
+/// 最近 `minutes` 分钟（默认 60），每 `bucket` 秒（默认 60）一个点，一条序列最多 1440 个点
+#[utoipa::path(
+    tag = "admin-ui",
+    params(
+        ("minutes" = Option<u64>, Query, description = "how far back to look (default 60)"),
+        ("bucket" = Option<u64>, Query, description = "seconds per point (default 60)")
+    ),
+    responses(
+        (status = 200, body = UsageResponse),
+        (status = 400, description = "invalid window or bucket", body = ErrorResponse)
+    )
+)]
+#[get("/admin/api/usage?<minutes>&<bucket>")]
+pub async fn usage_series(
+    state: &State<Arc<AppState>>,
+    minutes: Option<u64>,
+    bucket: Option<u64>,
+) -> Result<Json<UsageResponse>, ApiError> {
+    let window_secs = minutes.unwrap_or(DEFAULT_USAGE_MINUTES).saturating_mul(60);
+    let bucket_secs = bucket.unwrap_or(DEFAULT_USAGE_BUCKET_SECS);
+    if window_secs == 0 || bucket_secs == 0 || window_secs / bucket_secs > MAX_USAGE_POINTS {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            format!(
+                "`minutes` and `bucket` must be positive and give at most {MAX_USAGE_POINTS} points"
+            ),
+        ));
+    }
+    let to_secs = now_ms() / 1000 + 1;
+    let from_secs = to_secs.saturating_sub(window_secs);
+    let entries = state.request_log.range(from_secs * 1000, to_secs * 1000);
+    Ok(Json(usage(entries, from_secs, to_secs, bucket_secs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_log::RequestKind;
+
+    fn entry(at_secs: u64, model: &str, ok: bool, tokens: usize) -> RequestLogEntry {
+        RequestLogEntry {
+            at_ms: at_secs * 1000,
+            model: model.to_string(),
+            kind: RequestKind::Collect,
+            ok,
+            latency_ms: 100,
+            prompt_bytes: 2,
+            tokens,
+            error: None,
+            tags: Default::default(),
+        }
+    }
+
+    #[test]
+    fn usage_series_are_aligned_and_zero_filled() {
+        let entries = vec![
+            entry(120, "a", true, 5),
+            entry(130, "b", false, 0),
+            entry(250, "a", true, 7),
+        ];
+        let usage = usage(entries, 110, 300, 60);
+
+        let starts: Vec<_> = usage.total.iter().map(|p| p.bucket_start).collect();
+        assert_eq!(starts, [60, 120, 180, 240]);
+        let requests: Vec<_> = usage.total.iter().map(|p| p.requests).collect();
+        assert_eq!(requests, [0, 2, 0, 1]);
+        assert_eq!(usage.total[1].errors, 1);
+        assert_eq!(usage.total[2].latency_p95_ms, None);
+
+        let a: Vec<_> = usage.models["a"].iter().map(|p| p.tokens).collect();
+        assert_eq!(a, [0, 5, 0, 7]);
+        assert_eq!(usage.models["b"].len(), 4);
+    }
+}
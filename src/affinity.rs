@@ -0,0 +1,139 @@
+//! CPU 推理的核心绑定：多路服务器上跨 NUMA 节点访问内存会让 token/s 减半，
+//! 所以可以按模型把计算线程固定在指定的核心或 NUMA 节点上（manifest 的 `cpu_cores` / `numa_node`）。
+//!
+//! 每个 CPU 副本建一个自己的 rayon 线程池，每个线程绑一个核心；candle 的 CPU 算子
+//! （matmul、量化 matmul）跑在当前线程池里，所以加载和解码都在池里执行即可。
+//! 权重也在池里加载，按 first-touch 分配在同一个节点的内存上。
+//!
+//! 只在 Linux 上真正绑定，其他平台只限制线程数。
+
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// 计算线程绑定到哪些 CPU
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CpuAffinity {
+    /// 核心编号，写法同 `taskset -c`：`"0-15,32-47"`
+    Cores(Vec<usize>),
+    /// 这个 NUMA 节点上的所有核心（加载时从 sysfs 读取）
+    NumaNode(usize),
+}
+
+impl CpuAffinity {
+    /// 解析 `"0-3,8,10-11"` 这样的核心列表
+    pub fn parse_cores(list: &str) -> anyhow::Result<Self> {
+        Ok(Self::Cores(parse_cpu_list(list)?))
+    }
+
+    /// 展开成核心编号
+    pub fn cores(&self) -> anyhow::Result<Vec<usize>> {
+        match self {
+            Self::Cores(cores) => Ok(cores.clone()),
+            Self::NumaNode(node) => {
+                let path = format!("/sys/devices/system/node/node{node}/cpulist");
+                let list = std::fs::read_to_string(Path::new(&path))
+                    .with_context(|| format!("NUMA node {node} not found (`{path}`)"))?;
+                parse_cpu_list(list.trim())
+            }
+        }
+    }
+
+    /// 建一个线程池，每个线程绑一个核心
+    pub fn thread_pool(&self, model_name: &str) -> anyhow::Result<rayon::ThreadPool> {
+        let cores = self.cores()?;
+        let name = model_name.to_string();
+        let pinned = cores.clone();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(cores.len())
+            .thread_name(move |i| format!("{name}-cpu{i}"))
+            .start_handler(move |i| {
+                if let Err(e) = pin_current_thread(pinned[i]) {
+                    warn!(core = pinned[i], "failed to pin thread: {e}");
+                }
+            })
+            .build()?;
+        info!(
+            model = %model_name,
+            threads = cores.len(),
+            "compute threads pinned to cores {cores:?}"
+        );
+        Ok(pool)
+    }
+}
+
+fn parse_cpu_list(list: &str) -> anyhow::Result<Vec<usize>> {
+    let invalid = || anyhow::anyhow!("invalid CPU list `{list}` (expected e.g. `0-15,32-47`)");
+    let mut cores = Vec::new();
+    for part in list.split(',').map(str::trim) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (part, part),
+        };
+        let start: usize = start.parse().map_err(|_| invalid())?;
+        let end: usize = end.parse().map_err(|_| invalid())?;
+        if start > end {
+            return Err(invalid());
+        }
+        cores.extend(start..=end);
+    }
+    cores.sort_unstable();
+    cores.dedup();
+    Ok(cores)
+}
+
+#[cfg(target_os = "linux")]
+fn pin_current_thread(core: usize) -> std::io::Result<()> {
+    // SAFETY: cpu_set_t 是普通的位图，全零是合法的空集合
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_current_thread(_core: usize) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "thread pinning is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_taskset_style_lists() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11").unwrap(),
+            [0, 1, 2, 3, 8, 10, 11]
+        );
+        assert_eq!(parse_cpu_list("5, 2-3,3").unwrap(), [2, 3, 5]);
+        for bad in ["", "a", "3-1", "1-", "-2"] {
+            assert!(parse_cpu_list(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pool_threads_run_on_the_configured_core() {
+        let pool = CpuAffinity::Cores(vec![0]).thread_pool("test").unwrap();
+        let allowed = pool.install(|| unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set);
+            (0..libc::CPU_SETSIZE as usize)
+                .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(allowed, [0]);
+        assert_eq!(pool.current_num_threads(), 1);
+    }
+}
@@ -1,29 +1,245 @@
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use rocket::{get, post, Shutdown, State};
+use rocket::{catch, get, post, Request, Shutdown, State};
+use rocket::http::{ContentType, Status};
+use rocket::response::status;
 use rocket::response::stream::{Event, EventStream};
 use rocket::serde::json::Json;
 use rocket::tokio::select;
-use rocket::tokio::sync::mpsc;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::time::Instant;
+use tracing::{info, warn};
 
+use crate::admin::{confirm_destructive, load_error};
+use crate::api_keys::{ApiKey, ProfileError};
 use crate::app_state::AppState;
-use crate::model_registry::ModelStatus;
+use crate::config::ServerConfig;
+use crate::confirm::AdminAction;
+use crate::device::DeviceSpec;
+use crate::engine::SamplingParams;
+use crate::health::{assess, HealthStatus, HealthThresholds};
+use crate::jobs::JobRecord;
+use crate::memory::{self, MemoryEstimate};
+use crate::metrics;
+use crate::model_registry::{
+    EngineKind, HubArtifacts, LocalArtifacts, ModelError, ModelMetadata, ModelStatus, RegistryError,
+};
+use crate::generations::WithRequestId;
+use crate::pipeline::{InferencePipeline, PipelineError, StreamChunk};
+use crate::preemption::Priority;
+use crate::request_log::RequestKind;
+use crate::router::AUTO_MODEL;
+use crate::scratch::ScratchOwner;
+use crate::stream_stats::{ChunkRate, StatsTicker};
+use crate::tags;
 use crate::types::{
     HealthResponse,
+    InferMode,
     InferRequest,
     InferResponse,
+    JobInfoResponse,
     LoadModelRequest,
     LoadModelResponse,
+    ModelCacheResponse,
+    ModelErrorInfo,
     ModelInfoResponse,
+    ReplicaCacheInfo,
+    ReplicaLoadTimings,
+    ScratchReleaseResponse,
+    RouterInfoResponse,
+    SharedPrefixCompletion,
+    SharedPrefixRequest,
+    SharedPrefixResponse,
+    ContinueRequest,
+    ContinueResponse,
+    UnloadModelRequest,
+    RegisterModelRequest,
+    AdminActionResponse,
 };
 
+pub use crate::error::ApiError;
+
+pub(crate) fn api_error(status: Status, error: &str, message: impl Into<String>) -> ApiError {
+    ApiError::new(status, error, message)
+}
+
+/// pipeline 错误对应的 HTTP 状态
+pub(crate) fn pipeline_error(e: PipelineError) -> ApiError {
+    let (status, error) = match &e {
+        PipelineError::ModelNotFound(_) => (Status::NotFound, "model_not_found"),
+        PipelineError::NotLoaded { .. } | PipelineError::NoEngine(_) => {
+            (Status::Conflict, "model_not_loaded")
+        }
+        PipelineError::DeviceUnavailable { .. } => {
+            (Status::UnprocessableEntity, "device_unavailable")
+        }
+        PipelineError::NoAutoCandidate { .. } => (Status::ServiceUnavailable, "no_model_available"),
+        PipelineError::Timeout { .. } => (Status::GatewayTimeout, "timeout"),
+        PipelineError::ContextTooLarge { .. } => (Status::PayloadTooLarge, "context_too_large"),
+        PipelineError::MemoryBusy { .. } => (Status::TooManyRequests, "memory_busy"),
+        PipelineError::QueueFull { position, .. } => {
+            let position = *position;
+            return api_error(Status::TooManyRequests, "queue_full", e.to_string())
+                .with_queue_position(position);
+        }
+        PipelineError::QueueTimeout { position, .. } => {
+            let position = *position;
+            return api_error(Status::ServiceUnavailable, "queue_timeout", e.to_string())
+                .with_queue_position(position);
+        }
+        PipelineError::Closed => (Status::ServiceUnavailable, "shutting_down"),
+        PipelineError::Cancelled => (Status::new(499), "request_cancelled"),
+        PipelineError::Inference(_) => (Status::InternalServerError, "inference_failed"),
+        PipelineError::OutputRejected { attempts } => {
+            let attempts = attempts.clone();
+            return api_error(
+                Status::UnprocessableEntity,
+                "output_validation_failed",
+                e.to_string(),
+            )
+            .with_attempts(attempts);
+        }
+    };
+    api_error(status, error, e.to_string())
+}
+
+pub(crate) fn profile_error(e: ProfileError) -> ApiError {
+    let (status, error) = match &e {
+        ProfileError::MissingModel => (Status::BadRequest, "model_required"),
+        ProfileError::ModelNotAllowed(_) => (Status::Forbidden, "model_not_allowed"),
+        ProfileError::TokenInputWithSystemPrompt => (Status::UnprocessableEntity, "invalid_input"),
+    };
+    api_error(status, error, e.to_string())
+}
+
+/// prompt 超过 `max_prompt_bytes` 时返回 413
+pub(crate) fn check_prompt_size(prompt: &str, config: &ServerConfig) -> Result<(), ApiError> {
+    if prompt.len() <= config.max_prompt_bytes {
+        return Ok(());
+    }
+    Err(api_error(
+        Status::PayloadTooLarge,
+        "prompt_too_large",
+        format!(
+            "prompt is {} bytes, maximum allowed is {} bytes",
+            prompt.len(),
+            config.max_prompt_bytes
+        ),
+    )
+    .with_max_bytes(config.max_prompt_bytes as u64))
+}
+
+/// `input_ids` 和 `prompt` 只能给一个，且不能为空
+pub(crate) fn check_token_input(req: &InferRequest) -> Result<(), ApiError> {
+    let message = match &req.input_ids {
+        Some(_) if !req.prompt.is_empty() => "provide either prompt or input_ids, not both",
+        Some(ids) if ids.is_empty() => "input_ids must not be empty",
+        _ => return Ok(()),
+    };
+    Err(ApiError::validation(message))
+}
+
+/// `validate` 的参数要合法，而且只能用在非流式文本请求上
+pub(crate) fn check_validation(req: &InferRequest) -> Result<(), ApiError> {
+    let Some(validation) = &req.validate else {
+        return Ok(());
+    };
+    validation.verify().map_err(ApiError::validation)?;
+    if req.uses_token_ids() {
+        return Err(ApiError::validation(
+            "validate is not supported with input_ids / return_token_ids",
+        ));
+    }
+    if req.mode == InferMode::Throughput {
+        // 攒批不用请求的采样参数，重试只会得到同样的输出
+        return Err(ApiError::validation("validate is not supported in throughput mode"));
+    }
+    Ok(())
+}
+
+/// `tags` 的键和值要合法，见 `tags::check`
+pub(crate) fn check_tags(req: &InferRequest) -> Result<(), ApiError> {
+    tags::check(&req.tags).map_err(ApiError::validation)
+}
+
+/// 带了未知 API key 时的 401
+#[catch(401)]
+pub fn unauthorized() -> ApiError {
+    api_error(Status::Unauthorized, "invalid_api_key", "unknown API key")
+}
+
+/// 没有匹配的路由
+#[catch(404)]
+pub fn not_found(req: &Request) -> ApiError {
+    api_error(
+        Status::NotFound,
+        "not_found",
+        format!("no route for {} {}", req.method(), req.uri().path()),
+    )
+}
+
+/// 请求体解析失败（JSON 格式不对、缺字段、类型不对）
+#[catch(422)]
+pub fn unprocessable() -> ApiError {
+    ApiError::validation("request body is malformed or does not match the expected schema")
+}
+
+/// 请求体超过 Rocket `limits.json` 时的 413（默认 catcher 只有 HTML）
+#[catch(413)]
+pub fn payload_too_large(req: &Request) -> ApiError {
+    let limit = req.limits().get("json").map(|l| l.as_u64());
+    let message = match limit {
+        Some(limit) => format!("request body exceeds the maximum of {} bytes", limit),
+        None => "request body too large".to_string(),
+    };
+    let error = api_error(Status::PayloadTooLarge, "payload_too_large", message);
+    match limit {
+        Some(limit) => error.with_max_bytes(limit),
+        None => error,
+    }
+}
+
+/// Prometheus 文本格式的运行指标：GET /metrics
+#[utoipa::path(
+    tag = "ops",
+    responses((status = 200, description = "Prometheus text format", body = String, content_type = "text/plain"))
+)]
+#[get("/metrics")]
+pub async fn get_metrics(state: &State<Arc<AppState>>) -> (ContentType, String) {
+    let prometheus = ContentType::new("text", "plain").with_params(("version", "0.0.4"));
+    let mut out = state.metrics.render();
+    let available = state.semaphore.available_permits();
+    metrics::render_permits(&mut out, available, state.max_concurrent_infer);
+    state.tag_metrics.render(&mut out);
+    (prometheus, out)
+}
+
+/// unhealthy 时返回 503，其余情况 200
+#[utoipa::path(
+    tag = "ops",
+    responses(
+        (status = 200, description = "ok or degraded", body = HealthResponse),
+        (status = 503, description = "unhealthy", body = HealthResponse)
+    )
+)]
 #[get("/health")]
-pub async fn health() -> Json<HealthResponse> {
-    Json(HealthResponse {
-        status: "ok".to_string(),
-    })
+pub async fn get_health(state: &State<Arc<AppState>>) -> status::Custom<Json<HealthResponse>> {
+    let report = assess(state, &HealthThresholds::default());
+    let code = match report.status {
+        HealthStatus::Unhealthy => Status::ServiceUnavailable,
+        HealthStatus::Ok | HealthStatus::Degraded => Status::Ok,
+    };
+    status::Custom(
+        code,
+        Json(HealthResponse {
+            status: report.status,
+            reasons: report.reasons,
+        }),
+    )
 }
 
+#[utoipa::path(tag = "models", responses((status = 200, body = [ModelInfoResponse])))]
 #[get("/models")]
 pub async fn list_models(
     state: &State<Arc<AppState>>,
@@ -31,236 +247,861 @@ pub async fn list_models(
     let models = state.list_models();
     let resp: Vec<ModelInfoResponse> = models
         .into_iter()
-        .map(|m| ModelInfoResponse {
-            name: m.name,
-            status: format!("{:?}", m.status),
-            engine_kind: format!("{:?}", m.engine_kind),
-        })
+        .map(|m| model_info(state, m))
         .collect();
 
     Json(resp)
 }
 
-#[post("/load", data = "<req>")]
-pub async fn load_model(
-    state: &State<Arc<AppState>>,
-    req: Json<LoadModelRequest>,
-) -> Json<LoadModelResponse> {
-    let model_name = &req.model_name;
-
-    match state.load_model(model_name) {
-        Ok(meta) => Json(LoadModelResponse {
-            model_name: meta.name,
-            status: format!("{:?}", meta.status),
-            message: "model loaded (DummyEngine)".to_string(),
-        }),
-        Err(e) => Json(LoadModelResponse {
-            model_name: model_name.clone(),
-            status: "Error".to_string(),
-            message: e,
-        }),
+pub(crate) fn model_info(state: &AppState, m: ModelMetadata) -> ModelInfoResponse {
+    ModelInfoResponse {
+        status: m.status,
+        engine_kind: m.engine_kind.to_string(),
+        placements: m.placements.clone(),
+        error: m.error.as_ref().map(error_info),
+        scratch_expires_in_secs: state
+            .scratch
+            .get(&m.name)
+            .map(|lease| lease.expires_in().as_secs()),
+        self_test: m.self_test,
+        name: m.name,
     }
 }
 
-/// 非流式：POST /infer
-#[post("/infer", data = "<req>", rank = 2)]
-pub async fn infer(
+/// 运行时注册模型：POST /models（注册后用 /load 加载）
+///
+/// 同名模型正在加载或已加载时拒绝，未加载 / 出错的会被替换
+#[utoipa::path(
+    tag = "models",
+    request_body = RegisterModelRequest,
+    responses(
+        (status = 201, description = "registered, not loaded yet", body = ModelInfoResponse),
+        (status = 400, description = "invalid name, engine kind or weights source", body = ErrorResponse),
+        (status = 409, description = "the name is a router, or a model with it is loading or loaded", body = ErrorResponse)
+    )
+)]
+#[post("/models", data = "<req>")]
+pub async fn register_model(
     state: &State<Arc<AppState>>,
-    req: Json<InferRequest>,
-) -> Json<InferResponse> {
-    let model_name = &req.model_name;
-
-    let meta = state.registry.get_model(model_name);
-    if meta.is_none() {
-        return Json(InferResponse {
-            model_name: model_name.clone(),
-            output: format!("Error: model `{}` not found", model_name),
-        });
+    req: Json<RegisterModelRequest>,
+) -> Result<status::Custom<Json<ModelInfoResponse>>, ApiError> {
+    let meta = registration(state, req.into_inner())
+        .map_err(|message| api_error(Status::BadRequest, "invalid_input", message))?;
+    if state.registry.get_router(&meta.name).is_some() {
+        return Err(api_error(
+            Status::Conflict,
+            "model_exists",
+            format!("`{}` is already a router", meta.name),
+        ));
     }
-    let meta = meta.unwrap();
-    if !matches!(meta.status, ModelStatus::Loaded) {
-        return Json(InferResponse {
-            model_name: model_name.clone(),
-            output: format!(
-                "Error: model `{}` is not loaded (status = {:?})",
-                model_name, meta.status
-            ),
-        });
+    if let Some(existing) = state.registry.get_model(&meta.name) {
+        if matches!(existing.status, ModelStatus::Loading | ModelStatus::Loaded) {
+            return Err(api_error(
+                Status::Conflict,
+                "model_exists",
+                format!("model `{}` is already {:?}", meta.name, existing.status),
+            ));
+        }
+        // 同名模型换了权重，旧的 embedding 不能再用
+        state.embedding_cache.invalidate(&meta.name);
     }
+    info!(
+        model = %meta.name,
+        engine = %meta.engine_kind,
+        path = %meta.path,
+        "registered model"
+    );
+    state.registry.register(meta.clone());
+    Ok(status::Custom(
+        Status::Created,
+        Json(model_info(state, meta)),
+    ))
+}
 
-    let engine = state.get_engine(model_name);
-    if engine.is_none() {
-        return Json(InferResponse {
-            model_name: model_name.clone(),
-            output: format!("Error: no engine instance for model `{}`", model_name),
-        });
+/// 校验注册请求并转成 registry 条目
+fn registration(state: &AppState, req: RegisterModelRequest) -> Result<ModelMetadata, String> {
+    let name = req.name.trim();
+    if name.is_empty() || name == AUTO_MODEL || name.contains('/') {
+        return Err(format!("`{name}` is not a valid model name"));
+    }
+    let engine_kind = EngineKind::new(req.engine_kind);
+    let kinds = state.factories.kinds();
+    if !kinds.contains(&engine_kind) {
+        let kinds: Vec<_> = kinds.iter().map(|k| k.to_string()).collect();
+        return Err(format!(
+            "unknown engine_kind `{engine_kind}` (available: {})",
+            kinds.join(", ")
+        ));
     }
-    let engine = engine.unwrap();
+    let mut meta = ModelMetadata::new(name, "", &req.quantization, engine_kind)
+        .with_placements(req.placements)
+        .with_fallbacks(req.fallbacks)
+        .with_sampling(req.sampling);
+    meta = match (req.gguf, req.hf_repo, req.hf_file) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+            return Err("give either `gguf` or `hf_repo` + `hf_file`, not both".to_string())
+        }
+        (Some(gguf), None, None) => {
+            if !gguf.is_file() {
+                return Err(format!("GGUF file `{}` does not exist", gguf.display()));
+            }
+            meta.with_artifacts(LocalArtifacts {
+                gguf,
+                tokenizer: req.tokenizer,
+                sha256: req.sha256,
+            })
+        }
+        (None, Some(repo), Some(file)) => meta.with_hub_artifacts(HubArtifacts {
+            tokenizer_repo: req.tokenizer_repo.unwrap_or_else(|| repo.clone()),
+            repo,
+            file,
+        }),
+        (None, Some(_), None) | (None, None, Some(_)) => {
+            return Err("`hf_repo` and `hf_file` must be given together".to_string())
+        }
+        (None, None, None) => meta,
+    };
+    if let Some(n) = req.context_window {
+        meta = meta.with_context_window(n);
+    }
+    if let Some(ms) = req.timeout_ms {
+        meta = meta.with_timeout(Duration::from_millis(ms));
+    }
+    Ok(meta)
+}
 
-    let permit = state.semaphore.clone().acquire_owned().await.unwrap();
+#[utoipa::path(tag = "models", responses((status = 200, body = [RouterInfoResponse])))]
+#[get("/routers")]
+pub async fn list_routers(state: &State<Arc<AppState>>) -> Json<Vec<RouterInfoResponse>> {
+    let mut routers: Vec<_> = state
+        .registry
+        .list_routers()
+        .into_iter()
+        .map(|r| RouterInfoResponse {
+            name: r.name,
+            rules: r.rules,
+            default: r.default,
+        })
+        .collect();
+    routers.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(routers)
+}
 
-    let prompt = req.prompt.clone();
-    let result = engine.generate(&prompt, 64).await;
+fn cache_response(state: &AppState, model_name: &str) -> Result<ModelCacheResponse, ApiError> {
+    let replicas = state.replicas(model_name).ok_or_else(|| {
+        api_error(
+            Status::NotFound,
+            "model_not_loaded",
+            format!("model `{model_name}` is not loaded"),
+        )
+    })?;
+    Ok(ModelCacheResponse {
+        model_name: model_name.to_string(),
+        replicas: replicas
+            .instances()
+            .iter()
+            .map(|i| ReplicaCacheInfo {
+                device: i.device,
+                stats: i.engine.cache_stats(),
+            })
+            .collect(),
+    })
+}
 
-    drop(permit);
+/// 各副本的加载耗时，引擎不统计时跳过
+fn load_timings(state: &AppState, model_name: &str) -> Vec<ReplicaLoadTimings> {
+    state.replicas(model_name).map_or_else(Vec::new, |replicas| {
+        replicas
+            .instances()
+            .iter()
+            .filter_map(|i| {
+                i.engine.load_timings().map(|timings| ReplicaLoadTimings {
+                    device: i.device,
+                    timings,
+                })
+            })
+            .collect()
+    })
+}
 
-    let output = match result {
-        Ok(text) => text,
-        Err(e) => format!("Error during inference: {}", e),
-    };
+/// KV cache 使用情况：GET /models/<name>/cache
+#[utoipa::path(
+    tag = "models",
+    responses(
+        (status = 200, body = ModelCacheResponse),
+        (status = 404, description = "model not loaded", body = ErrorResponse)
+    )
+)]
+#[get("/models/<name>/cache")]
+pub async fn model_cache(
+    state: &State<Arc<AppState>>,
+    name: &str,
+) -> Result<Json<ModelCacheResponse>, ApiError> {
+    cache_response(state, name).map(Json)
+}
 
-    Json(InferResponse {
-        model_name: model_name.clone(),
-        output,
-    })
+/// 加载前估算内存：GET /models/<name>/estimate?ctx=8192，`ctx` 默认为模型的上下文窗口
+#[utoipa::path(
+    tag = "models",
+    params(("ctx" = Option<usize>, Query, description = "context length in tokens")),
+    responses(
+        (status = 200, body = MemoryEstimate),
+        (status = 400, description = "invalid context length", body = ErrorResponse),
+        (status = 404, description = "model or its GGUF file not found", body = ErrorResponse)
+    )
+)]
+#[get("/models/<name>/estimate?<ctx>")]
+pub async fn estimate_model_memory(
+    state: &State<Arc<AppState>>,
+    name: &str,
+    ctx: Option<usize>,
+) -> Result<Json<MemoryEstimate>, ApiError> {
+    let meta = state.registry.get_model(name).ok_or_else(|| {
+        api_error(
+            Status::NotFound,
+            "model_not_found",
+            format!("model `{name}` not found"),
+        )
+    })?;
+    let context_length = ctx.unwrap_or(meta.context_window);
+    if context_length == 0 {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            "ctx must be greater than 0",
+        ));
+    }
+    let path = memory::gguf_path(&meta).ok_or_else(|| {
+        api_error(
+            Status::NotFound,
+            "weights_not_found",
+            format!("no local GGUF file for `{name}`, download the model first"),
+        )
+    })?;
+    let name = name.to_string();
+    rocket::tokio::task::spawn_blocking(move || memory::estimate(&name, &path, context_length))
+        .await
+        .map_err(|e| api_error(Status::InternalServerError, "estimate_failed", e.to_string()))?
+        .map(Json)
+        .map_err(|e| api_error(Status::InternalServerError, "estimate_failed", e.to_string()))
 }
 
-/// 流式 SSE：POST /infer?stream=true
-#[post("/infer?<stream>", data = "<req>", rank = 1)]
-pub async fn infer_stream(
+/// 放不下时拒绝加载（`force` 跳过）；没有本地 GGUF 或读不了头时不拦
+pub(crate) fn check_memory(
+    state: &AppState,
+    model_name: &str,
+    force: bool,
+) -> Result<(), ApiError> {
+    if force {
+        return Ok(());
+    }
+    let Some(meta) = state.registry.get_model(model_name) else {
+        return Ok(());
+    };
+    let Some(path) = memory::gguf_path(&meta) else {
+        return Ok(());
+    };
+    match memory::estimate(&meta.name, &path, meta.context_window) {
+        Ok(MemoryEstimate {
+            fits: Some(false),
+            warning,
+            ..
+        }) => Err(api_error(
+            Status::InsufficientStorage,
+            "insufficient_memory",
+            format!(
+                "not enough memory to load `{}`: {} (pass force=true to load anyway)",
+                meta.name,
+                warning.unwrap_or_default()
+            ),
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            warn!(model = %meta.name, "memory estimate failed: {e}");
+            Ok(())
+        }
+    }
+}
+
+/// 释放 KV cache（不卸载权重）：POST /models/<name>/cache/clear，返回清理后的统计。
+/// 模型忙时需要确认（evict），见 `admin::confirm_destructive`
+#[utoipa::path(
+    tag = "models",
+    responses(
+        (status = 200, body = ModelCacheResponse),
+        (status = 400, description = "invalid confirmation token", body = ErrorResponse),
+        (status = 404, description = "model not loaded", body = ErrorResponse),
+        (status = 409, description = "model is busy, confirmation required", body = ErrorResponse)
+    )
+)]
+#[post("/models/<name>/cache/clear?<force>&<confirm>")]
+pub async fn clear_model_cache(
     state: &State<Arc<AppState>>,
-    req: Json<InferRequest>,
-    stream: bool,
-    mut shutdown: Shutdown,
-) -> EventStream![] {
-    let state = state.inner().clone(); // Arc<AppState>
-    let model_name = req.model_name.clone();
-    let prompt = req.prompt.clone();
+    name: &str,
+    force: Option<bool>,
+    confirm: Option<&str>,
+) -> Result<Json<ModelCacheResponse>, ApiError> {
+    confirm_destructive(
+        state,
+        AdminAction::Evict,
+        name,
+        force.unwrap_or(false),
+        confirm,
+    )?;
+    if let Some(replicas) = state.replicas(name) {
+        // candle 清理要等正在跑的 decode 释放锁，还会跑一次前向，放到 blocking 线程里
+        rocket::tokio::task::spawn_blocking(move || {
+            replicas.instances().iter().try_for_each(|instance| {
+                instance
+                    .engine
+                    .clear_cache()
+                    .map_err(|e| format!("failed to clear cache on {}: {e}", instance.device))
+            })
+        })
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r)
+        .map_err(|e| api_error(Status::InternalServerError, "cache_clear_failed", e))?;
+    }
+    cache_response(state, name).map(Json)
+}
 
+/// 模型事件流（加载开始 / 成功 / 失败 / 重试）：GET /events
+#[utoipa::path(
+    tag = "models",
+    responses((status = 200, description = "SSE stream of model events", body = String, content_type = "text/event-stream"))
+)]
+#[get("/events")]
+pub async fn model_events(state: &State<Arc<AppState>>, mut shutdown: Shutdown) -> EventStream![] {
+    let mut rx = state.events.subscribe();
     EventStream! {
-        if !stream {
-            // 情况 1：没带 stream=true
-            yield Event::data("stream=false not supported on this endpoint");
-            return;
+        loop {
+            select! {
+                msg = rx.recv() => match msg {
+                    Ok(event) => yield Event::json(&event).event("model"),
+                    // 订阅者太慢，丢了一些事件，继续即可
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            }
         }
+    }
+}
 
-        // 情况 2：检查模型是否存在
-        let meta_opt = state.registry.get_model(&model_name);
-        if meta_opt.is_none() {
-            yield Event::data(format!("Error: model `{}` not found", model_name));
-            return;
-        }
-        let meta = meta_opt.unwrap();
-        if !matches!(meta.status, ModelStatus::Loaded) {
-            yield Event::data(format!(
-                "Error: model `{}` is not loaded (status = {:?})",
-                model_name, meta.status
-            ));
-            return;
-        }
+#[utoipa::path(tag = "ops", responses((status = 200, body = [JobInfoResponse])))]
+#[get("/jobs")]
+pub async fn list_jobs(state: &State<Arc<AppState>>) -> Json<Vec<JobInfoResponse>> {
+    Json(state.jobs.list().iter().map(job_info).collect())
+}
 
-        // 情况 3：检查 engine 是否存在
-        let engine_opt = state.get_engine(&model_name);
-        if engine_opt.is_none() {
-            yield Event::data(format!("Error: no engine instance for `{}`", model_name));
-            return;
-        }
-        let engine = engine_opt.unwrap();
+#[utoipa::path(
+    tag = "ops",
+    responses((status = 200, body = JobInfoResponse), (status = 404, description = "job not found"))
+)]
+#[get("/jobs/<id>")]
+pub async fn get_job(state: &State<Arc<AppState>>, id: &str) -> Option<Json<JobInfoResponse>> {
+    state.jobs.get(id).as_ref().map(job_info).map(Json)
+}
 
-        // 获取 semaphore permit，控制并发
-        let semaphore = state.semaphore.clone();
-        let permit = semaphore.acquire_owned().await.unwrap();
+pub(crate) fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
 
-        // 建立 channel
-        let (tx, mut rx) = mpsc::channel::<String>(32);
+fn job_info(job: &JobRecord) -> JobInfoResponse {
+    JobInfoResponse {
+        id: job.id.clone(),
+        kind: job.kind.clone(),
+        status: job.status,
+        created_at: unix_secs(job.created_at),
+        finished_at: job.finished_at.map(unix_secs),
+        progress: job.progress,
+        result: job.result.clone(),
+        error: job.error.clone(),
+    }
+}
 
-        // 后台任务：调用 engine.generate_stream
-        rocket::tokio::spawn(async move {
-            let _permit = permit; // 生命周期结束自动释放
-            let _ = engine.generate_stream(&prompt, 128, tx).await;
-        });
+#[utoipa::path(
+    tag = "models",
+    request_body = LoadModelRequest,
+    responses(
+        (status = 200, description = "load result, including failures", body = LoadModelResponse),
+        (status = 404, description = "scratch session not found", body = ErrorResponse),
+        (status = 409, description = "placements cannot change while the model is loading", body = ErrorResponse),
+        (status = 507, description = "estimated memory exceeds available memory", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/load", data = "<req>")]
+pub async fn load_model(
+    state: &State<Arc<AppState>>,
+    key: ApiKey,
+    req: Json<LoadModelRequest>,
+) -> Result<Json<LoadModelResponse>, ApiError> {
+    let model_name = &req.model_name;
+    check_memory(state, model_name, req.force)?;
+    // 未知模型留给下面的加载报错
+    if !req.placements.is_empty() {
+        if let Err(e @ RegistryError::IllegalTransition { .. }) =
+            state.registry.set_placements(model_name, req.placements.clone())
+        {
+            return Err(api_error(Status::Conflict, "invalid_state", e.to_string()));
+        }
+    }
 
-        // 真正的 SSE 主循环
-        loop {
-            select! {
-                maybe_chunk = rx.recv() => {
-                    match maybe_chunk {
-                        Some(text) => {
-                            // 每个 chunk 一个 SSE 事件
-                            yield Event::data(text);
-                        }
-                        None => {
-                            // 生成结束
-                            break;
-                        }
+    let result = match &req.scratch {
+        Some(scratch) => {
+            // 默认绑定到调用方的 API key，指定了 session 时绑定到 session
+            let owner = match &scratch.session_id {
+                Some(id) => {
+                    if state.sessions.get(id).is_none() {
+                        return Err(api_error(
+                            Status::NotFound,
+                            "session_not_found",
+                            format!("session `{id}` not found"),
+                        ));
                     }
+                    Some(ScratchOwner::Session(id.clone()))
                 }
-                _ = &mut shutdown => {
-                    // 客户端断开 或 服务器关闭
-                    break;
-                }
+                None => key.key.map(ScratchOwner::ApiKey),
+            };
+            state.load_scratch(model_name, owner, scratch.ttl()).await
+        }
+        None if req.wait => state.load_model_and_wait(model_name).await,
+        None => state.load_model_in_background(model_name),
+    };
+
+    Ok(match result {
+        Ok(meta) => Json(LoadModelResponse {
+            model_name: meta.name,
+            status: meta.status,
+            message: match &req.scratch {
+                Some(scratch) => format!(
+                    "model loaded as scratch ({} engine), unloads in {} s",
+                    meta.engine_kind,
+                    scratch.ttl().as_secs()
+                ),
+                None if meta.status == ModelStatus::Loading => format!(
+                    "loading in background ({} engine); watch GET /models or GET /events",
+                    meta.engine_kind
+                ),
+                None => format!("model loaded ({} engine)", meta.engine_kind),
+            },
+            error: None,
+            load_timings: load_timings(state, model_name),
+        }),
+        Err(e) => {
+            // 失败后 registry 里的状态可能是 Error（加载失败）或原状态（非法迁移）
+            let meta = state.registry.get_model(model_name);
+            let mut message = e.to_string();
+            if e.is_transient() && req.scratch.is_none() && state.load_retry.max_attempts > 1 {
+                message.push_str(" (automatic retry scheduled)");
             }
+            Json(LoadModelResponse {
+                model_name: model_name.clone(),
+                status: meta.as_ref().map_or(ModelStatus::Error, |m| m.status),
+                message,
+                error: meta.and_then(|m| m.error).as_ref().map(error_info),
+                load_timings: Vec::new(),
+            })
         }
-    }
+    })
 }
 
+/// 卸载模型，释放引擎和权重，状态回到 Unloaded：POST /unload
+/// 和 `/admin/models/<name>/unload` 一样，模型忙时需要 `force` 或确认 token
+#[utoipa::path(
+    tag = "models",
+    request_body = UnloadModelRequest,
+    responses(
+        (status = 200, body = AdminActionResponse),
+        (status = 400, description = "invalid confirmation token", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model is busy (confirmation required) or not loaded", body = ErrorResponse)
+    )
+)]
+#[post("/unload", data = "<req>")]
+pub async fn unload_model(
+    state: &State<Arc<AppState>>,
+    req: Json<UnloadModelRequest>,
+) -> Result<Json<AdminActionResponse>, ApiError> {
+    let action = AdminAction::Unload;
+    let impact =
+        confirm_destructive(state, action, &req.model_name, req.force, req.confirm.as_deref())?;
+    state.unload_model(&req.model_name).map_err(load_error)?;
+    Ok(Json(AdminActionResponse {
+        action,
+        model_name: req.model_name.clone(),
+        impact,
+    }))
+}
 
-/// GET SSE：/infer_stream?model_name=xxx&prompt=yyy
-#[get("/infer_stream?<model_name>&<prompt>")]
-pub async fn infer_stream_get(
+/// 卸载调用方 API key 创建的全部 scratch 模型：DELETE /scratch
+#[utoipa::path(
+    tag = "models",
+    responses(
+        (status = 200, body = ScratchReleaseResponse),
+        (status = 400, description = "no API key", body = ErrorResponse)
+    ),
+    security(("api_key" = []))
+)]
+#[delete("/scratch")]
+pub async fn release_scratch(
     state: &State<Arc<AppState>>,
-    model_name: &str,
-    prompt: &str,
-    mut shutdown: Shutdown,
-) -> EventStream![] {
-    let state = state.inner().clone();
-    let model_name = model_name.to_string();
-    let prompt = prompt.to_string();
+    key: ApiKey,
+) -> Result<Json<ScratchReleaseResponse>, ApiError> {
+    let Some(key) = key.key else {
+        return Err(api_error(
+            Status::BadRequest,
+            "api_key_required",
+            "scratch models are released per API key; send X-API-Key",
+        ));
+    };
+    let released_models = state.release_scratch(&ScratchOwner::ApiKey(key));
+    Ok(Json(ScratchReleaseResponse { released_models }))
+}
 
-    EventStream! {
-        // 1) 校验模型是否存在 & 已加载
-        let meta_opt = state.registry.get_model(&model_name);
-        if meta_opt.is_none() {
-            yield Event::data(format!("Error: model `{}` not found", model_name));
-            return;
-        }
-        let meta = meta_opt.unwrap();
-        if !matches!(meta.status, ModelStatus::Loaded) {
-            yield Event::data(format!(
-                "Error: model `{}` is not loaded (status = {:?})",
-                model_name, meta.status
-            ));
-            return;
-        }
+fn error_info(error: &ModelError) -> ModelErrorInfo {
+    ModelErrorInfo {
+        message: error.message.clone(),
+        at: unix_secs(error.at),
+        attempts: error.attempts,
+        reasons: error.reasons.clone(),
+    }
+}
 
-        // 2) 获取 engine
-        let engine_opt = state.get_engine(&model_name);
-        if engine_opt.is_none() {
-            yield Event::data(format!("Error: no engine instance for `{}`", model_name));
-            return;
-        }
-        let engine = engine_opt.unwrap();
+/// 非流式：POST /infer
+#[utoipa::path(
+    tag = "inference",
+    request_body = InferRequest,
+    params(("stream" = Option<bool>, Query, description = "`true` returns an SSE stream of text chunks")),
+    responses(
+        (status = 200, content(
+            ("application/json" = InferResponse),
+            ("text/event-stream" = String)
+        )),
+        (status = 400, description = "profile violation", body = ErrorResponse),
+        (status = 401, description = "unknown API key", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model not loaded", body = ErrorResponse),
+        (status = 413, description = "prompt too large, or its KV cache exceeds the memory limit", body = ErrorResponse),
+        (status = 422, description = "invalid input", body = ErrorResponse),
+        (status = 429, description = "KV cache memory limit reached right now; retry later", body = ErrorResponse),
+        (status = 500, description = "inference failed", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/infer", data = "<req>", rank = 2)]
+pub async fn infer(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    key: ApiKey,
+    req: Json<InferRequest>,
+) -> Result<Json<InferResponse>, ApiError> {
+    check_prompt_size(&req.prompt, config)?;
+    check_token_input(&req)?;
+    check_validation(&req)?;
+    check_tags(&req)?;
+    let mut req = req.into_inner();
+    key.apply(&mut req).map_err(profile_error)?;
+
+    let pipeline = InferencePipeline::new(state.inner().clone());
+    let done = pipeline.collect(&req).await.map_err(pipeline_error)?;
 
-        // 3) 并发控制
-        let semaphore = state.semaphore.clone();
-        let permit = semaphore.acquire_owned().await.unwrap();
+    Ok(Json(InferResponse {
+        model_name: req.model_name.clone(),
+        output: done.output,
+        served_by: Some(done.served_by),
+        finish_reason: Some(done.finish_reason),
+        output_ids: done.output_ids,
+        trace: done.trace,
+        applied_template: done.applied_template,
+        usage: done.usage,
+    }))
+}
 
-        // 4) 建 channel
-        let (tx, mut rx) = mpsc::channel::<String>(32);
+/// 一次共享前缀请求最多带多少个后缀
+const MAX_SHARED_PREFIX_SUFFIXES: usize = 256;
 
-        // 5) 后台推理任务（流式写入 tx）
-        rocket::tokio::spawn(async move {
-            let _permit = permit; // 保证推理期间占用 slot
-            let _ = engine.generate_stream(&prompt, 128, tx).await;
-        });
+/// 共享前缀批量：POST /infer/shared_prefix，前缀只 prefill 一次
+#[utoipa::path(
+    tag = "inference",
+    request_body = SharedPrefixRequest,
+    responses(
+        (status = 200, body = SharedPrefixResponse),
+        (status = 400, description = "invalid input or profile violation", body = ErrorResponse),
+        (status = 401, description = "unknown API key", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model not loaded", body = ErrorResponse),
+        (status = 413, description = "prompt too large", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/infer/shared_prefix", data = "<req>")]
+pub async fn infer_shared_prefix(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    key: ApiKey,
+    req: Json<SharedPrefixRequest>,
+) -> Result<Json<SharedPrefixResponse>, ApiError> {
+    let req = req.into_inner();
+    if req.suffixes.is_empty() || req.suffixes.len() > MAX_SHARED_PREFIX_SUFFIXES {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            format!("suffixes must contain 1 to {MAX_SHARED_PREFIX_SUFFIXES} entries"),
+        ));
+    }
+    check_prompt_size(&req.prefix, config)?;
+    for suffix in &req.suffixes {
+        check_prompt_size(suffix, config)?;
+    }
+    let mut infer_req = InferRequest {
+        model_name: req.model_name,
+        prompt: req.prefix,
+        device: req.device,
+        mode: InferMode::Interactive,
+        input_ids: None,
+        return_token_ids: false,
+        max_tokens: req.max_tokens,
+        sampling: SamplingParams::default(),
+        priority: Priority::default(),
+        trace: false,
+        auto_template: false,
+        private: false,
+        client: None,
+        validate: None,
+        tags: Default::default(),
+        request_id: None,
+        rate_client: None,
+    };
+    key.apply(&mut infer_req).map_err(profile_error)?;
+
+    let pipeline = InferencePipeline::new(state.inner().clone());
+    let (served_by, generation) = pipeline
+        .collect_shared_prefix(&infer_req, &req.suffixes)
+        .await
+        .map_err(pipeline_error)?;
+    Ok(Json(SharedPrefixResponse {
+        model_name: infer_req.model_name,
+        served_by,
+        completions: generation
+            .completions
+            .into_iter()
+            .map(|g| SharedPrefixCompletion {
+                output: g.text,
+                finish_reason: g.finish_reason,
+            })
+            .collect(),
+        prefix_tokens: generation.prefix_tokens,
+        prefill_tokens_saved: generation.prefill_tokens_saved,
+    }))
+}
 
-        // 6) 主循环：把 channel 里的 chunk 以 SSE 事件发给前端
+/// 继续生成：POST /infer/continue，接着（可能改过的）部分输出写，未改动的部分复用 KV cache
+#[utoipa::path(
+    tag = "inference",
+    request_body = ContinueRequest,
+    responses(
+        (status = 200, body = ContinueResponse),
+        (status = 400, description = "invalid input or profile violation", body = ErrorResponse),
+        (status = 401, description = "unknown API key", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model not loaded", body = ErrorResponse),
+        (status = 413, description = "prompt too large", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/infer/continue", data = "<req>")]
+pub async fn infer_continue(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    key: ApiKey,
+    req: Json<ContinueRequest>,
+) -> Result<Json<ContinueResponse>, ApiError> {
+    let req = req.into_inner();
+    check_prompt_size(&req.prompt, config)?;
+    check_prompt_size(&req.partial, config)?;
+    let mut infer_req = InferRequest {
+        model_name: req.model_name,
+        prompt: req.prompt,
+        device: req.device,
+        mode: InferMode::Interactive,
+        input_ids: None,
+        return_token_ids: false,
+        max_tokens: req.max_tokens,
+        sampling: req.sampling,
+        priority: Priority::default(),
+        trace: false,
+        auto_template: false,
+        private: false,
+        client: None,
+        validate: None,
+        tags: Default::default(),
+        request_id: None,
+        rate_client: None,
+    };
+    key.apply(&mut infer_req).map_err(profile_error)?;
+
+    let pipeline = InferencePipeline::new(state.inner().clone());
+    let (served_by, generation) = pipeline
+        .collect_continuation(&infer_req, &req.partial)
+        .await
+        .map_err(pipeline_error)?;
+    Ok(Json(ContinueResponse {
+        model_name: infer_req.model_name,
+        served_by,
+        output: generation.text,
+        finish_reason: generation.finish_reason,
+        reused_tokens: generation.reused_tokens,
+    }))
+}
+
+/// 把 pipeline 的输出 channel 转成 SSE：每个 chunk 一个事件，
+/// 校验失败时只发一条错误事件，生成中途失败时最后发一条 `event: error`，
+/// 客户端太慢被丢掉 chunk 时发 `event: gap`（data 是丢掉的个数），正常结束前发一条 `event: usage`（token 数），
+/// 另外定时发 `event: stats`（见 `stream_stats`）。第一个事件是 `event: request`（data 是 request id），
+/// 和响应头 `X-Request-Id` 一样用来暂停 / 继续 / 取消（`DELETE /requests/<id>`）
+fn sse_stream(
+    state: &Arc<AppState>,
+    req: InferRequest,
+    mut shutdown: Shutdown,
+) -> WithRequestId<EventStream![]> {
+    let pipeline = InferencePipeline::new(state.clone());
+    let stats_every = state.streaming.stats_interval();
+    let handle = state.generations.register(
+        &req.model_name,
+        RequestKind::Stream,
+        req.client.as_deref(),
+        req.request_id.as_deref(),
+    );
+    let request_id = handle.id().to_string();
+    let first = Event::data(request_id.clone()).event("request");
+    WithRequestId::new(EventStream! {
+        yield first;
+        let mut rx = match pipeline.stream_as(&req, handle).await {
+            Ok(rx) => rx,
+            Err(e) => {
+                yield Event::data(format!("Error: {}", e));
+                return;
+            }
+        };
+
+        let mut rate = ChunkRate::default();
+        let mut ticker = StatsTicker::new(stats_every);
         loop {
             select! {
                 maybe_chunk = rx.recv() => {
                     match maybe_chunk {
-                        Some(text) => {
+                        Some(StreamChunk::Text(text)) => {
+                            rate.record(Instant::now(), 1);
                             yield Event::data(text);
                         }
+                        Some(StreamChunk::Gap { dropped }) => {
+                            yield Event::data(dropped.to_string()).event("gap");
+                        }
+                        Some(StreamChunk::Usage(usage)) => {
+                            yield Event::json(&usage).event("usage");
+                        }
+                        Some(StreamChunk::Error(message)) => {
+                            yield Event::data(format!("Error: {message}")).event("error");
+                            break;
+                        }
+                        // 生成结束，最后报一次总数
                         None => {
+                            if stats_every.is_some() {
+                                yield Event::json(&rate.snapshot(Instant::now())).event("stats");
+                            }
                             break;
                         }
                     }
                 }
+                _ = ticker.tick() => {
+                    yield Event::json(&rate.snapshot(Instant::now())).event("stats");
+                }
                 _ = &mut shutdown => {
+                    // 客户端断开 或 服务器关闭
                     break;
                 }
             }
         }
+    }, request_id)
+}
+
+/// 流式 SSE：POST /infer?stream=true
+#[post("/infer?stream=true", data = "<req>", rank = 1)]
+pub async fn infer_stream(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    key: ApiKey,
+    req: Json<InferRequest>,
+    shutdown: Shutdown,
+) -> Result<WithRequestId<EventStream![]>, ApiError> {
+    check_prompt_size(&req.prompt, config)?;
+    check_tags(&req)?;
+    if req.uses_token_ids() {
+        return Err(api_error(
+            Status::UnprocessableEntity,
+            "token_ids_not_streamable",
+            "input_ids / return_token_ids are only supported for non-streaming requests",
+        ));
+    }
+    if req.validate.is_some() {
+        return Err(ApiError::validation(
+            "validate is only supported for non-streaming requests",
+        ));
     }
-}
\ No newline at end of file
+    let mut req = req.into_inner();
+    key.apply(&mut req).map_err(profile_error)?;
+
+    Ok(sse_stream(state, req, shutdown))
+}
+
+/// GET SSE：/infer_stream?model_name=xxx&prompt=yyy[&device=cuda:0]
+#[utoipa::path(
+    tag = "inference",
+    responses(
+        (status = 200, description = "SSE stream of text chunks", body = String, content_type = "text/event-stream"),
+        (status = 413, description = "prompt too large", body = ErrorResponse),
+        (status = 422, description = "invalid device", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[get("/infer_stream?<model_name>&<prompt>&<device>")]
+pub async fn infer_stream_get(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    model_name: &str,
+    prompt: &str,
+    device: Option<&str>,
+    key: ApiKey,
+    shutdown: Shutdown,
+) -> Result<WithRequestId<EventStream![]>, ApiError> {
+    check_prompt_size(prompt, config)?;
+    let device = device
+        .map(|d| d.parse::<DeviceSpec>())
+        .transpose()
+        .map_err(|e| api_error(Status::UnprocessableEntity, "invalid_device", e))?;
+
+    let mut req = InferRequest {
+        model_name: model_name.to_string(),
+        prompt: prompt.to_string(),
+        device,
+        mode: InferMode::default(),
+        input_ids: None,
+        return_token_ids: false,
+        max_tokens: None,
+        sampling: SamplingParams::default(),
+        priority: Priority::default(),
+        trace: false,
+        auto_template: false,
+        private: false,
+        client: None,
+        validate: None,
+        tags: Default::default(),
+        request_id: None,
+        rate_client: None,
+    };
+    key.apply(&mut req).map_err(profile_error)?;
+    Ok(sse_stream(state, req, shutdown))
+}
@@ -0,0 +1,391 @@
+//! API key 与每个 key 的参数 profile
+//!
+//! key 通过 `X-API-Key` 或 `Authorization: Bearer <key>` 传入，profile 在配置里按 key 定义：
+//! ```toml
+//! [default.api_keys.sk-analytics]
+//! default_model = "mistral-7b"      # 请求没写 model_name 时使用
+//! max_tokens = 256                  # 生成长度上限，请求里更大的值会被压到这里
+//! allowed_models = ["mistral-7b", "auto"]  # 为空表示不限制
+//! system_prompt = "Answer in English and keep it short."  # 强制加在 prompt 前面
+//! rag_namespace = "analytics"       # RAG 文档的命名空间，没写时用 key 本身
+//! ```
+//! 默认不带 key 的请求不受限制；带了未知 key 返回 401。
+//!
+//! 部署在共享网络上时配置 `require_api_key = true`（`RequireApiKey` fairing）：推理（`/infer*`、
+//! `/v1/*`、`/sessions`、`/assistant`、`/rag`、`/tools`、`/routers`）、模型管理（`/load`、`/unload`、
+//! `/models`、`/catalog`、`/jobs`、`/events`）和 `/admin` 都必须带 `api_keys` 里配置过的 key，
+//! 否则 401。`/health`、`/metrics`、`/info`、文档和静态前端仍然公开。
+
+use std::io::Cursor;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Header, Method, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{Build, Data, Response, Rocket};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::api::api_error;
+use crate::config::ServerConfig;
+use crate::logging::RequestId;
+use crate::rate_limit::RateClient;
+use crate::types::InferRequest;
+use crate::versioning::API_V1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiKeyProfile {
+    pub default_model: Option<String>,
+    pub max_tokens: Option<usize>,
+    /// 允许请求的模型名（真实模型、router 或 `auto`），为空表示不限制
+    pub allowed_models: Vec<String>,
+    pub system_prompt: Option<String>,
+    /// 隐私模式：这个 key 的 prompt / 输出不以原文写进日志（见 `privacy`）
+    pub privacy: bool,
+    /// RAG 文档的命名空间，几个 key 写同一个值时共享文档；None 时用 key 本身
+    pub rag_namespace: Option<String>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ProfileError {
+    #[error("model_name is required (no default model for this API key)")]
+    MissingModel,
+    #[error("model `{0}` is not allowed for this API key")]
+    ModelNotAllowed(String),
+    #[error("input_ids cannot be combined with the system prompt forced by this API key")]
+    TokenInputWithSystemPrompt,
+}
+
+impl ApiKeyProfile {
+    /// 补全 / 检查模型名
+    pub fn resolve_model(&self, model_name: &str) -> Result<String, ProfileError> {
+        let model_name = match (model_name.is_empty(), &self.default_model) {
+            (false, _) => model_name.to_string(),
+            (true, Some(default)) => default.clone(),
+            (true, None) => return Err(ProfileError::MissingModel),
+        };
+        if !self.allowed_models.is_empty() && !self.allowed_models.contains(&model_name) {
+            return Err(ProfileError::ModelNotAllowed(model_name));
+        }
+        Ok(model_name)
+    }
+
+    /// 把 profile 套到推理请求上：默认模型、模型白名单、max_tokens 上限、强制 system prompt、隐私模式
+    pub fn apply(&self, req: &mut InferRequest) -> Result<(), ProfileError> {
+        req.model_name = self.resolve_model(&req.model_name)?;
+        req.private |= self.privacy;
+        if let Some(cap) = self.max_tokens {
+            req.max_tokens = Some(req.max_tokens.map_or(cap, |n| n.min(cap)));
+        }
+        if let Some(system) = &self.system_prompt {
+            if req.input_ids.is_some() {
+                return Err(ProfileError::TokenInputWithSystemPrompt);
+            }
+            req.prompt = format!("{system}\n\n{}", req.prompt);
+        }
+        Ok(())
+    }
+}
+
+/// 请求携带的 API key；没带 key 时 `key` 为 None，profile 为空（不做限制）。
+/// 部署开启了 `privacy` 时所有 profile 都带上隐私模式
+#[derive(Debug, Clone, Default)]
+pub struct ApiKey {
+    pub key: Option<String>,
+    pub profile: ApiKeyProfile,
+    /// 这个 HTTP 请求的 id（见 `logging::RequestId`），`apply` 时带进推理请求
+    pub request_id: Option<String>,
+    /// 通过限流时记账的客户端（见 `rate_limit`），`apply` 时带进推理请求
+    pub rate_client: Option<String>,
+}
+
+impl ApiKey {
+    /// 套用 profile（见 `ApiKeyProfile::apply`），并记下发起请求的 key 和 request id
+    pub fn apply(&self, req: &mut InferRequest) -> Result<(), ProfileError> {
+        self.profile.apply(req)?;
+        req.client = self.label();
+        req.request_id = self.request_id.clone();
+        req.rate_client = self.rate_client.clone();
+        Ok(())
+    }
+
+    /// 管理接口里展示的 key：只保留开头 6 个字符
+    pub fn label(&self) -> Option<String> {
+        let key = self.key.as_ref()?;
+        Some(match key.char_indices().nth(6) {
+            Some((end, _)) => format!("{}…", &key[..end]),
+            None => key.clone(),
+        })
+    }
+
+    /// 这个 key 能读写的 RAG 命名空间；不带 key 的请求共用空命名空间
+    pub fn rag_namespace(&self) -> String {
+        self.profile
+            .rag_namespace
+            .clone()
+            .or_else(|| self.key.clone())
+            .unwrap_or_default()
+    }
+}
+
+pub(crate) fn key_from_headers<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    req.headers().get_one("X-API-Key").or_else(|| {
+        req.headers()
+            .get_one("Authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+    })
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKey {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = req.rocket().state::<ServerConfig>();
+        let privacy = config.is_some_and(|config| config.privacy);
+        let request_id = Some(RequestId::of(req).0);
+        let rate_client = RateClient::admitted(req).map(|c| c.0);
+        let Some(key) = key_from_headers(req) else {
+            let mut anonymous = ApiKey {
+                request_id,
+                rate_client,
+                ..ApiKey::default()
+            };
+            anonymous.profile.privacy = privacy;
+            return Outcome::Success(anonymous);
+        };
+        let profile = config.and_then(|config| config.api_keys.get(key));
+        match profile {
+            Some(profile) => Outcome::Success(ApiKey {
+                key: Some(key.to_string()),
+                profile: ApiKeyProfile {
+                    privacy: profile.privacy || privacy,
+                    ..profile.clone()
+                },
+                request_id,
+                rate_client,
+            }),
+            None => Outcome::Error((Status::Unauthorized, "unknown API key".to_string())),
+        }
+    }
+}
+
+/// `require_api_key` 时需要 key 的路径前缀（`/api/v1` 下的同名路径也一样）
+const PROTECTED_PREFIXES: &[&str] = &[
+    "/infer",
+    "/load",
+    "/unload",
+    "/admin",
+    "/models",
+    "/catalog",
+    "/scratch",
+    "/sessions",
+    "/assistant",
+    "/rag",
+    "/requests",
+    "/jobs",
+    "/events",
+    "/routers",
+    "/tools",
+    "/v1/",
+];
+
+/// 被拒绝的请求改写到这个不存在的路径，不会进入任何 handler
+const UNAUTHORIZED_PATH: &str = "/__unauthorized";
+
+/// 按 Rocket 路由时看到的样子判断：空段去掉、percent-decode，再去掉 `/api/v1`，
+/// 所以 `/%6Coad`、`/api//v1/load` 和 `/load` 一样受保护。解析不了的路径当作受保护
+pub fn is_protected(path: &str) -> bool {
+    match Origin::parse(path) {
+        Ok(origin) => is_protected_path(&origin),
+        Err(_) => true,
+    }
+}
+
+fn is_protected_path(origin: &Origin<'_>) -> bool {
+    let path: String = origin
+        .path()
+        .segments()
+        .map(|segment| format!("/{segment}"))
+        .collect();
+    let path = match path.strip_prefix(API_V1) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => &path,
+    };
+    PROTECTED_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+}
+
+/// 被拒绝时记在 request-local cache 里（没带 key / key 未知），响应阶段替换成 401
+#[derive(Debug, Clone, Copy)]
+struct Rejected(&'static str);
+
+/// `require_api_key` 时拒绝没带有效 key 访问受保护路径的请求
+pub struct RequireApiKey;
+
+#[rocket::async_trait]
+impl Fairing for RequireApiKey {
+    fn info(&self) -> Info {
+        Info {
+            name: "API key authentication",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        if let Some(config) = rocket.state::<ServerConfig>() {
+            if config.require_api_key && config.api_keys.is_empty() {
+                warn!(
+                    "require_api_key is set but no api_keys are configured; \
+                     every protected route will return 401"
+                );
+            }
+        }
+        Ok(rocket)
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let Some(config) = req.rocket().state::<ServerConfig>() else {
+            return;
+        };
+        if !config.require_api_key || !is_protected_path(req.uri()) {
+            return;
+        }
+        let rejected = match key_from_headers(req) {
+            None => "api_key_required",
+            Some(key) if !config.api_keys.contains_key(key) => "invalid_api_key",
+            Some(_) => return,
+        };
+        req.local_cache(|| Some(Rejected(rejected)));
+        req.set_method(Method::Get);
+        req.set_uri(Origin::parse(UNAUTHORIZED_PATH).expect("valid path"));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(Rejected(code)) = *req.local_cache(|| None::<Rejected>) else {
+            return;
+        };
+        let message = match code {
+            "api_key_required" => "this endpoint requires an API key (Authorization: Bearer <key>)",
+            _ => "unknown API key",
+        };
+        let error = api_error(Status::Unauthorized, code, message);
+        let body = serde_json::to_string(&error.into_response()).unwrap_or_default();
+        *res = Response::new();
+        res.set_status(Status::Unauthorized);
+        res.set_header(ContentType::JSON);
+        res.set_header(Header::new("WWW-Authenticate", "Bearer"));
+        res.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::SamplingParams;
+
+    fn request(model_name: &str, prompt: &str) -> InferRequest {
+        InferRequest {
+            model_name: model_name.to_string(),
+            prompt: prompt.to_string(),
+            device: None,
+            mode: Default::default(),
+            input_ids: None,
+            return_token_ids: false,
+            max_tokens: Some(1000),
+            sampling: SamplingParams::default(),
+            priority: Default::default(),
+            trace: false,
+            auto_template: false,
+            private: false,
+            client: None,
+            validate: None,
+            tags: Default::default(),
+            request_id: None,
+            rate_client: None,
+        }
+    }
+
+    #[test]
+    fn profile_fills_defaults_and_enforces_limits() {
+        let profile = ApiKeyProfile {
+            default_model: Some("small".to_string()),
+            max_tokens: Some(128),
+            allowed_models: vec!["small".to_string(), "auto".to_string()],
+            system_prompt: Some("be brief".to_string()),
+            privacy: true,
+            rag_namespace: None,
+        };
+
+        let mut req = request("", "hi");
+        profile.apply(&mut req).unwrap();
+        assert_eq!(req.model_name, "small");
+        assert_eq!(req.max_tokens, Some(128));
+        assert_eq!(req.prompt, "be brief\n\nhi");
+        assert!(req.private);
+
+        assert_eq!(
+            profile.apply(&mut request("big", "hi")),
+            Err(ProfileError::ModelNotAllowed("big".to_string()))
+        );
+    }
+
+    #[test]
+    fn protected_paths_cover_inference_and_management() {
+        for path in [
+            "/infer",
+            "/infer_stream",
+            "/api/v1/infer",
+            "/load",
+            "/admin/export",
+            "/v1/chat/completions",
+            "/jobs",
+            "/jobs/42",
+            "/events",
+            "/routers",
+            "/tools/calculator",
+            "/api/v1/jobs/42",
+        ] {
+            assert!(is_protected(path), "{path}");
+        }
+        for path in ["/health", "/api/v1/health", "/metrics", "/info", "/docs", "/index.html"] {
+            assert!(!is_protected(path), "{path}");
+        }
+    }
+
+    /// Rocket 路由前会合并空段、percent-decode，检查也要按同样的路径来
+    #[test]
+    fn encoded_and_doubled_slashes_do_not_bypass_the_check() {
+        for path in [
+            "/%6Coad",
+            "/api/v1//load",
+            "/api//v1/load",
+            "//load",
+            "/api/v1/%69nfer",
+            "/%61dmin/export",
+            "/v1//chat/completions",
+        ] {
+            assert!(is_protected(path), "{path}");
+        }
+        assert!(!is_protected("/api//v1/health"));
+        assert!(!is_protected("/api/v1x/load"));
+    }
+
+    #[test]
+    fn empty_profile_changes_nothing() {
+        let mut req = request("big", "hi");
+        ApiKeyProfile::default().apply(&mut req).unwrap();
+        assert_eq!(req.model_name, "big");
+        assert_eq!(req.max_tokens, Some(1000));
+        assert_eq!(req.prompt, "hi");
+        assert!(!req.private);
+
+        assert_eq!(
+            ApiKeyProfile::default().apply(&mut request("", "hi")),
+            Err(ProfileError::MissingModel)
+        );
+    }
+}
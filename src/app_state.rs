@@ -1,72 +1,1037 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use parking_lot::RwLock;
+use thiserror::Error;
 use tokio::sync::Semaphore;
+use tracing::{info, warn};
 
-use crate::engine::{DummyEngine, CandleEngine, InferenceEngine};
-use crate::model_registry::{EngineKind, ModelMetadata, ModelRegistry, ModelStatus};
+use crate::balancer::ReplicaSet;
+use crate::batcher::{BatchConfig, Batcher};
+use crate::catalog::Catalog;
+use crate::confirm::{ConfirmationStore, Impact};
+use crate::device::DeviceSpec;
+use crate::diffusion::ImageStore;
+use crate::discovery::{DiscoveryConfig, ModelDiscovery};
+use crate::embedding_cache::{EmbeddingCache, EmbeddingCacheConfig};
+use crate::engine::{CandleEngine, InferenceEngine};
+use crate::engine_factory::EngineFactories;
+use crate::events::{EventBus, ModelEvent};
+use crate::generations::GenerationRegistry;
+use crate::jobs::JobRegistry;
+use crate::kv_budget::{KvBudget, KvBudgetConfig};
+use crate::loader::LoaderPool;
+use crate::metrics::Metrics;
+use crate::model_registry::{
+    EngineKind, Modality, ModelMetadata, ModelRegistry, ModelStatus, RegistryError,
+};
+use crate::perf_history::PerfHistory;
+use crate::pipeline::StreamConfig;
+use crate::preemption::PreemptionRegistry;
+use crate::pull::ModelPulls;
+use crate::queue::{AdmissionQueue, QueueConfig};
+use crate::rag::RagProfiles;
+use crate::rate_limit::{RateLimitConfig, RateLimiter};
+use crate::request_log::RequestLog;
+use crate::scratch::{ScratchOwner, ScratchRegistry};
+use crate::self_test::{self, SelfTestConfig};
+use crate::session::SessionStore;
+use crate::standby::{Standby, StandbyConfig};
+use crate::tags::{TagMetrics, TagsConfig};
+use crate::time_slice::{TimeSliceConfig, TimeSlicer};
+use crate::tools::{Tool, ToolRegistry};
+use crate::trash::{Trash, TrashConfig};
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("model `{0}` not found")]
+    NotFound(String),
+    #[error(transparent)]
+    InvalidState(RegistryError),
+    #[error("no engine factory registered for kind `{0}`")]
+    NoFactory(EngineKind),
+    #[error("model `{0}` is already loaded and cannot be turned into a scratch load")]
+    AlreadyLoaded(String),
+    #[error("failed to init {kind} engine for `{model}` on {device}: {message}")]
+    Init {
+        kind: EngineKind,
+        model: String,
+        device: DeviceSpec,
+        message: String,
+    },
+    #[error("`{model}` failed its self-test: {failures}")]
+    SelfTest { model: String, failures: String },
+}
+
+impl LoadError {
+    /// 只有引擎初始化失败（网络、临时 OOM 等）才值得重试
+    pub fn is_transient(&self) -> bool {
+        matches!(self, LoadError::Init { .. })
+    }
+}
+
+/// 加载失败后的自动重试策略（指数退避）
+#[derive(Debug, Clone)]
+pub struct LoadRetryPolicy {
+    /// 总尝试次数（含第一次），1 表示不重试
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+}
+
+impl LoadRetryPolicy {
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// 第 `failed_attempts` 次失败之后应等待的时间
+    pub fn backoff(&self, failed_attempts: u32) -> Duration {
+        let exp = failed_attempts.saturating_sub(1) as i32;
+        let secs = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exp);
+        Duration::from_secs_f64(secs.min(self.max_backoff.as_secs_f64()))
+    }
+}
+
+impl Default for LoadRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(2),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2.0,
+        }
+    }
+}
+
+/// 模型在某个设备上的一个 engine 实例
+#[derive(Clone)]
+pub struct EngineInstance {
+    pub device: DeviceSpec,
+    pub engine: Arc<dyn InferenceEngine>,
+    /// 负载均衡权重
+    pub weight: u32,
+    /// 已分配到这个实例、还没结束的请求数（排队 + 执行中）
+    outstanding: Arc<AtomicUsize>,
+    /// 其中已经拿到 permit、正在执行的
+    running: Arc<AtomicUsize>,
+}
+
+impl EngineInstance {
+    pub fn new(device: DeviceSpec, engine: Arc<dyn InferenceEngine>) -> Self {
+        Self {
+            device,
+            engine,
+            weight: 1,
+            outstanding: Arc::new(AtomicUsize::new(0)),
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// 是否是同一个副本（clone 出来的也算）
+    pub fn same_replica(&self, other: &EngineInstance) -> bool {
+        Arc::ptr_eq(&self.outstanding, &other.outstanding)
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// 计入一个请求，guard drop 时减回去
+    pub fn track(&self) -> InflightGuard {
+        self.outstanding.fetch_add(1, Ordering::Relaxed);
+        InflightGuard {
+            outstanding: self.outstanding.clone(),
+            running: self.running.clone(),
+            started: false,
+        }
+    }
+}
+
+pub struct InflightGuard {
+    outstanding: Arc<AtomicUsize>,
+    running: Arc<AtomicUsize>,
+    started: bool,
+}
+
+impl InflightGuard {
+    /// 拿到 permit、开始执行时调用，之后不再算作排队
+    pub fn start(&mut self) {
+        if !self.started {
+            self.started = true;
+            self.running.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.outstanding.fetch_sub(1, Ordering::Relaxed);
+        if self.started {
+            self.running.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
 
 /// 全局共享状态：
 /// - registry: 记录模型元信息和状态
-/// - engines: model_name -> 对应 InferenceEngine 实例
+/// - engines: model_name -> 各 placement 上的 InferenceEngine 副本，按模型的策略均衡
+/// - factories: engine_kind -> 引擎构造函数
 /// - semaphore: 控制最多 N 个并发推理任务
+/// - events: 模型加载等事件的广播
+/// - jobs: 后台任务（扫描等）的状态
+/// - sessions: 服务端保存的多轮对话
+/// - batcher: throughput 模式请求的攒批调度
+/// - confirmations: 破坏性运维操作的确认 token
+/// - scratch: 临时加载的模型及其租约
+/// - metrics: 运行指标（permit 等待时间等）
+/// - images: 文生图 job 生成的 PNG
+/// - rag: embedding + 对话模型组成的 RAG profile 及其文档
+/// - embedding_cache: 按（模型，文本哈希）缓存的 embedding 向量
+/// - discovery: 模型目录的位置和从中发现的 GGUF
+/// - pulls: `POST /models/<name>/pull` 的下载进度
+/// - request_log: 最近的推理请求明细，`GET /admin/export` 导出
+/// - kv_budget: 进行中请求的 KV cache 估算和软上限
+/// - offline: 离线模式，不访问 hub
+/// - loader: 模型加载专用的低优先级线程池
+/// - standby: 主模型的热备实例，主模型重新加载或出错时接管
+/// - rate_limits: 每个客户端（API key / IP）的请求数和 token 数令牌桶
+/// - time_slicer: 分时设备上各个生成轮流使用设备
+/// - tag_metrics: 按请求标签统计的请求数和 token 数
+/// - trash: 软删除的模型，保留期内可以恢复
+/// - queue: 等推理 permit 的请求队列，有长度和等待时间上限
 pub struct AppState {
     pub registry: Arc<ModelRegistry>,
-    pub engines: RwLock<HashMap<String, Arc<dyn InferenceEngine>>>,
+    pub engines: RwLock<HashMap<String, Arc<ReplicaSet>>>,
+    pub factories: EngineFactories,
     pub semaphore: Arc<Semaphore>,
+    /// 等 `semaphore` 的请求，满了或等太久时拒绝
+    pub queue: Arc<AdmissionQueue>,
+    pub events: EventBus,
+    pub jobs: Arc<JobRegistry>,
+    pub sessions: SessionStore,
+    pub batcher: Batcher,
+    pub confirmations: ConfirmationStore,
+    pub scratch: ScratchRegistry,
+    pub metrics: Arc<Metrics>,
+    /// 可以被 high 请求抢占的 low 生成
+    pub preemption: PreemptionRegistry,
+    /// 正在进行的流式生成，可以暂停 / 继续
+    pub generations: GenerationRegistry,
+    pub images: ImageStore,
+    pub load_retry: LoadRetryPolicy,
+    pub streaming: StreamConfig,
+    /// `GET /catalog` 可安装的模型
+    pub catalog: Catalog,
+    /// assistant 循环可以调用的工具
+    pub tools: ToolRegistry,
+    pub rag: RagProfiles,
+    pub embedding_cache: EmbeddingCache,
+    pub discovery: ModelDiscovery,
+    pub pulls: ModelPulls,
+    pub request_log: RequestLog,
+    /// 每个模型最近的延迟 / 速度样本（`GET /models/<name>/perf`）
+    pub perf: PerfHistory,
+    /// 加载后、标记 Loaded 之前跑的自检
+    pub self_test: SelfTestConfig,
+    /// 进行中请求的 KV cache 记账和软上限
+    pub kv_budget: Arc<KvBudget>,
+    /// 离线模式：candle 只用本地 / 缓存里的文件，`pull` 被拒绝
+    pub offline: bool,
+    /// 构造引擎和自检在这里执行，不和推理抢 tokio 的线程
+    pub loader: LoaderPool,
+    /// 热备实例（不在 `engines` 里，重新加载期间仍然可用）
+    pub standby: Standby,
+    /// 按客户端限流
+    pub rate_limits: RateLimiter,
+    /// 显存只够放权重的设备上按轮次分时生成
+    pub time_slicer: TimeSlicer,
+    /// 按请求标签（只限配置过的键）统计的用量
+    pub tag_metrics: TagMetrics,
+    /// `DELETE /admin/models/<name>` 删掉的模型，过了保留期才硬删除
+    pub trash: Trash,
     pub max_concurrent_infer: usize,
 }
 
-impl AppState {
-    pub fn new(max_concurrent_infer: usize) -> Arc<Self> {
-        Arc::new(Self {
-            registry: Arc::new(ModelRegistry::new()),
+/// AppState 的 builder：嵌入方可以替换 registry、注册自定义引擎
+pub struct AppStateBuilder {
+    registry: Option<ModelRegistry>,
+    factories: EngineFactories,
+    max_concurrent_infer: usize,
+    queue: QueueConfig,
+    load_retry: LoadRetryPolicy,
+    batching: BatchConfig,
+    streaming: StreamConfig,
+    catalog: Option<Catalog>,
+    tools: ToolRegistry,
+    rag: RagProfiles,
+    embedding_cache: EmbeddingCacheConfig,
+    discovery: DiscoveryConfig,
+    perf: Option<PerfHistory>,
+    self_test: SelfTestConfig,
+    kv_budget: KvBudgetConfig,
+    offline: bool,
+    loader_threads: usize,
+    standby: StandbyConfig,
+    rate_limit: RateLimitConfig,
+    time_slice: TimeSliceConfig,
+    tags: TagsConfig,
+    trash: TrashConfig,
+}
+
+impl AppStateBuilder {
+    pub fn registry(mut self, registry: ModelRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    pub fn max_concurrent_infer(mut self, max_concurrent_infer: usize) -> Self {
+        self.max_concurrent_infer = max_concurrent_infer;
+        self
+    }
+
+    /// 请求队列的长度和等待时间上限（默认不限长度，最多等 5 分钟）
+    pub fn queue(mut self, config: QueueConfig) -> Self {
+        self.queue = config;
+        self
+    }
+
+    pub fn load_retry(mut self, policy: LoadRetryPolicy) -> Self {
+        self.load_retry = policy;
+        self
+    }
+
+    /// throughput 模式的攒批窗口和批大小
+    pub fn batching(mut self, config: BatchConfig) -> Self {
+        self.batching = config;
+        self
+    }
+
+    /// 流式输出的 channel 容量和溢出策略
+    pub fn streaming(mut self, config: StreamConfig) -> Self {
+        self.streaming = config;
+        self
+    }
+
+    /// 替换内置的模型目录
+    pub fn catalog(mut self, catalog: Catalog) -> Self {
+        self.catalog = Some(catalog);
+        self
+    }
+
+    /// 替换工具表（默认只有内置工具）
+    pub fn tools(mut self, tools: ToolRegistry) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// 注册（或覆盖）一个同名工具
+    pub fn tool(mut self, tool: Arc<dyn Tool>) -> Self {
+        self.tools.register(tool);
+        self
+    }
+
+    /// RAG profile（默认没有）
+    pub fn rag(mut self, profiles: RagProfiles) -> Self {
+        self.rag = profiles;
+        self
+    }
+
+    /// embedding 缓存（默认 10000 条，只在内存里）
+    pub fn embedding_cache(mut self, config: EmbeddingCacheConfig) -> Self {
+        self.embedding_cache = config;
+        self
+    }
+
+    /// 自动发现 GGUF 的目录（默认 `models`）
+    pub fn discovery(mut self, config: DiscoveryConfig) -> Self {
+        self.discovery = config;
+        self
+    }
+
+    /// 性能历史（默认只在内存里，重启后清空）
+    pub fn perf_history(mut self, history: PerfHistory) -> Self {
+        self.perf = Some(history);
+        self
+    }
+
+    /// 加载后的自检（默认关闭）
+    pub fn self_test(mut self, config: SelfTestConfig) -> Self {
+        self.self_test = config;
+        self
+    }
+
+    /// KV cache 软上限（默认不限，只记账）
+    pub fn kv_budget(mut self, config: KvBudgetConfig) -> Self {
+        self.kv_budget = config;
+        self
+    }
+
+    /// 离线模式（默认关闭）：内置的 candle 工厂只用本地文件和 hf-hub 缓存，缺文件时直接报错，
+    /// 不去连 hub。要在 `engine_factory` 之前调用，否则会覆盖自定义的 candle 工厂
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        if offline {
+            self.factories
+                .register(EngineKind::CANDLE, |meta: &ModelMetadata, device| {
+                    Ok(CandleEngine::new_offline(meta, device)? as Arc<dyn InferenceEngine>)
+                });
+        }
+        self
+    }
+
+    /// 同时加载几个模型（默认 1），加载线程的优先级低于推理
+    pub fn loader_threads(mut self, threads: usize) -> Self {
+        self.loader_threads = threads;
+        self
+    }
+
+    /// 给主模型配一个热备实例（默认没有）
+    pub fn standby(mut self, config: StandbyConfig) -> Self {
+        self.standby = config;
+        self
+    }
+
+    /// 每个客户端每分钟的请求数 / token 数上限（默认不限）
+    pub fn rate_limit(mut self, config: RateLimitConfig) -> Self {
+        self.rate_limit = config;
+        self
+    }
+
+    /// 分时的设备和一轮的长度（默认不分时）
+    pub fn time_slice(mut self, config: TimeSliceConfig) -> Self {
+        self.time_slice = config;
+        self
+    }
+
+    /// 哪些请求标签作为 `/metrics` 的标签（默认都不）
+    pub fn tags(mut self, config: TagsConfig) -> Self {
+        self.tags = config;
+        self
+    }
+
+    /// 软删除的模型保留多久、硬删除时是否删权重（默认保留 7 天，不删权重）
+    pub fn trash(mut self, config: TrashConfig) -> Self {
+        self.trash = config;
+        self
+    }
+
+    /// 注册（或覆盖）某个 engine_kind 对应的引擎工厂
+    pub fn engine_factory<F>(mut self, kind: impl Into<EngineKind>, factory: F) -> Self
+    where
+        F: Fn(&ModelMetadata, DeviceSpec) -> anyhow::Result<Arc<dyn InferenceEngine>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.factories.register(kind, factory);
+        self
+    }
+
+    /// 整体替换工厂表（例如不想要内置的 candle）
+    pub fn engine_factories(mut self, factories: EngineFactories) -> Self {
+        self.factories = factories;
+        self
+    }
+
+    pub fn build(self) -> Arc<AppState> {
+        let semaphore = Arc::new(Semaphore::new(self.max_concurrent_infer));
+        let metrics = Arc::new(Metrics::new());
+        let queue = Arc::new(AdmissionQueue::new(&self.queue));
+        Arc::new(AppState {
+            registry: Arc::new(self.registry.unwrap_or_default()),
             engines: RwLock::new(HashMap::new()),
-            semaphore: Arc::new(Semaphore::new(max_concurrent_infer)),
-            max_concurrent_infer,
+            factories: self.factories,
+            batcher: Batcher::new(
+                self.batching,
+                semaphore.clone(),
+                metrics.clone(),
+                queue.clone(),
+            ),
+            semaphore,
+            queue,
+            events: EventBus::new(),
+            jobs: Arc::new(JobRegistry::new()),
+            sessions: SessionStore::new(),
+            confirmations: ConfirmationStore::default(),
+            scratch: ScratchRegistry::default(),
+            metrics,
+            preemption: PreemptionRegistry::default(),
+            generations: GenerationRegistry::default(),
+            images: ImageStore::default(),
+            load_retry: self.load_retry,
+            streaming: self.streaming,
+            catalog: self.catalog.unwrap_or_else(Catalog::bundled),
+            tools: self.tools,
+            rag: self.rag,
+            embedding_cache: EmbeddingCache::new(&self.embedding_cache),
+            discovery: ModelDiscovery::new(self.discovery),
+            pulls: ModelPulls::new(),
+            request_log: RequestLog::new(),
+            perf: self.perf.unwrap_or_default(),
+            self_test: self.self_test,
+            kv_budget: KvBudget::new(&self.kv_budget),
+            offline: self.offline,
+            loader: LoaderPool::new(self.loader_threads),
+            standby: Standby::new(self.standby),
+            rate_limits: RateLimiter::new(self.rate_limit),
+            time_slicer: TimeSlicer::new(&self.time_slice),
+            tag_metrics: TagMetrics::new(self.tags),
+            trash: Trash::new(self.trash),
+            max_concurrent_infer: self.max_concurrent_infer,
         })
     }
+}
+
+impl AppState {
+    pub fn builder() -> AppStateBuilder {
+        AppStateBuilder {
+            registry: None,
+            factories: EngineFactories::with_builtin(),
+            max_concurrent_infer: 10,
+            queue: QueueConfig::default(),
+            load_retry: LoadRetryPolicy::default(),
+            batching: BatchConfig::default(),
+            streaming: StreamConfig::default(),
+            catalog: None,
+            tools: ToolRegistry::default(),
+            rag: RagProfiles::default(),
+            embedding_cache: EmbeddingCacheConfig::default(),
+            discovery: DiscoveryConfig::default(),
+            perf: None,
+            self_test: SelfTestConfig::default(),
+            kv_budget: KvBudgetConfig::default(),
+            offline: false,
+            loader_threads: 1,
+            standby: StandbyConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            time_slice: TimeSliceConfig::default(),
+            tags: TagsConfig::default(),
+            trash: TrashConfig::default(),
+        }
+    }
+
+    pub fn new(max_concurrent_infer: usize) -> Arc<Self> {
+        Self::builder()
+            .max_concurrent_infer(max_concurrent_infer)
+            .build()
+    }
+
+    /// 使用外部传入的 registry 构造（测试 fixture 用）
+    pub fn with_registry(registry: ModelRegistry, max_concurrent_infer: usize) -> Arc<Self> {
+        Self::builder()
+            .registry(registry)
+            .max_concurrent_infer(max_concurrent_infer)
+            .build()
+    }
 
     pub fn list_models(&self) -> Vec<ModelMetadata> {
         self.registry.list_models()
     }
 
-    /// 加载模型：根据 EngineKind 创建对应 Engine，并放入 engines 映射中
-    pub fn load_model(&self, model_name: &str) -> Result<ModelMetadata, String> {
+    /// 加载模型（单次尝试）：根据 EngineKind 创建对应 Engine，并放入 engines 映射中
+    pub fn load_model(&self, model_name: &str) -> Result<ModelMetadata, LoadError> {
+        let (meta, attempt) = self.begin_load(model_name)?;
+        self.loader.install(|| self.finish_load(meta, attempt))
+    }
+
+    /// 标记为 Loading 并发出 LoadStarted，返回（状态为 Loading 的）元数据和这是第几次尝试
+    fn begin_load(&self, model_name: &str) -> Result<(ModelMetadata, u32), LoadError> {
         // 先从 registry 拿元数据
         let meta = self
             .registry
             .get_model(model_name)
-            .ok_or_else(|| format!("model `{}` not found", model_name))?;
+            .ok_or_else(|| LoadError::NotFound(model_name.to_string()))?;
 
-        // 标记为 Loading
-        let _ = self.registry.set_status(model_name, ModelStatus::Loading);
+        // 标记为 Loading（正在加载中的模型会被拒绝）
+        let attempt = meta.error.as_ref().map_or(0, |e| e.attempts) + 1;
+        let meta = self
+            .registry
+            .set_status(model_name, ModelStatus::Loading)
+            .map_err(LoadError::InvalidState)?;
+        self.events.emit(ModelEvent::LoadStarted {
+            model: model_name.to_string(),
+            attempt,
+        });
+        Ok((meta, attempt))
+    }
 
-        // 根据 engine_kind 创建具体 Engine
-        let engine: Arc<dyn InferenceEngine> = match meta.engine_kind {
-            EngineKind::Dummy => DummyEngine::new(model_name),
-            EngineKind::Candle => CandleEngine::new(model_name)
-                .map_err(|e| format!("failed to init CandleEngine for `{}`: {e}", model_name))?,
+    /// 构造引擎（GGUF 加载等，会阻塞）、自检，最后标记为 Loaded 或 Error
+    fn finish_load(&self, meta: ModelMetadata, attempt: u32) -> Result<ModelMetadata, LoadError> {
+        let model_name = meta.name.as_str();
+        // 根据 engine_kind 找到工厂，在每个 placement 上创建具体 Engine
+        let instances = match self.factories.get(&meta.engine_kind) {
+            Some(factory) => meta
+                .placements
+                .iter()
+                .enumerate()
+                .map(|(i, &device)| {
+                    factory(&meta, device)
+                        .map(|engine| {
+                            EngineInstance::new(device, engine).with_weight(meta.weight_of(i))
+                        })
+                        .map_err(|e| LoadError::Init {
+                            kind: meta.engine_kind.clone(),
+                            model: model_name.to_string(),
+                            device,
+                            message: e.to_string(),
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>(),
+            None => Err(LoadError::NoFactory(meta.engine_kind.clone())),
+        };
+        // 自检只跑第一个副本；没通过时引擎直接丢掉
+        let instances = instances.and_then(|instances| {
+            let text = meta.modalities.contains(&Modality::Text);
+            if !self.self_test.enabled || !text || instances.is_empty() {
+                return Ok(instances);
+            }
+            let report = self_test::run_blocking(instances[0].engine.clone(), &self.self_test);
+            let _ = self.registry.set_self_test(model_name, report.clone());
+            if report.passed {
+                Ok(instances)
+            } else {
+                Err(LoadError::SelfTest {
+                    model: model_name.to_string(),
+                    failures: report.failures(),
+                })
+            }
+        });
+        let instances = match instances {
+            Ok(instances) => instances,
+            Err(e) => {
+                let _ = self.registry.set_error(model_name, e.to_string());
+                self.events.emit(ModelEvent::LoadFailed {
+                    model: model_name.to_string(),
+                    attempt,
+                    error: e.to_string(),
+                });
+                return Err(e);
+            }
         };
 
         {
             let mut guard = self.engines.write();
-            guard.insert(model_name.to_string(), engine);
+            let replicas = ReplicaSet::new(instances, meta.balance);
+            guard.insert(model_name.to_string(), Arc::new(replicas));
         }
 
         // 成功后标记为 Loaded
         let meta = self
             .registry
             .set_status(model_name, ModelStatus::Loaded)
-            .ok_or_else(|| format!("failed to update status for `{}`", model_name))?;
+            .map_err(LoadError::InvalidState)?;
+        self.events.emit(ModelEvent::Loaded {
+            model: model_name.to_string(),
+            attempt,
+        });
+        self.warm_standby(&meta);
 
         Ok(meta)
     }
 
-    /// 获取已加载的 InferenceEngine
+    /// 主模型上线后在热备设备上用同样的权重再建一个实例；失败时保留旧实例，只记日志
+    fn warm_standby(&self, meta: &ModelMetadata) {
+        if !self.standby.covers(&meta.name) {
+            return;
+        }
+        let Some(factory) = self.factories.get(&meta.engine_kind) else {
+            return;
+        };
+        let device = self.standby.device();
+        match factory(meta, device) {
+            Ok(engine) => {
+                self.standby.replace(EngineInstance::new(device, engine));
+                info!(model = %meta.name, %device, "standby instance is warm");
+            }
+            Err(e) => {
+                warn!(model = %meta.name, %device, "failed to build the standby instance: {e}")
+            }
+        }
+    }
+
+    /// 加载模型；遇到暂时性失败时按 `load_retry` 在后台自动重试。
+    /// 返回第一次尝试的结果，后续进展通过 events 和 registry 状态观察
+    pub fn load_model_with_retries(
+        self: &Arc<Self>,
+        model_name: &str,
+    ) -> Result<ModelMetadata, LoadError> {
+        // 普通加载会把 scratch 模型转成常驻
+        self.scratch.remove(model_name);
+        let result = self.load_model(model_name);
+        if let Err(e) = &result {
+            if e.is_transient() {
+                self.schedule_retry(model_name.to_string(), 1);
+            }
+        }
+        result
+    }
+
+    /// 同 `load_model_with_retries`，在阻塞线程上执行，路由里等待加载完成时不占 rocket 的 worker
+    pub async fn load_model_and_wait(
+        self: &Arc<Self>,
+        model_name: &str,
+    ) -> Result<ModelMetadata, LoadError> {
+        let state = self.clone();
+        let model_name = model_name.to_string();
+        tokio::task::spawn_blocking(move || state.load_model_with_retries(&model_name))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    /// 在后台加载：标记为 Loading 后立即返回，引擎在 `loader` 线程池里构造，
+    /// 完成后变成 Loaded / Error（通过 events 和 registry 状态观察），暂时性失败同样自动重试
+    pub fn load_model_in_background(
+        self: &Arc<Self>,
+        model_name: &str,
+    ) -> Result<ModelMetadata, LoadError> {
+        self.scratch.remove(model_name);
+        let (meta, attempt) = self.begin_load(model_name)?;
+        let loading = meta.clone();
+
+        tokio::spawn(self.clone().finish_load_on_loader(meta, attempt, 1));
+        Ok(loading)
+    }
+
+    /// 在 `loader` 线程池里构造引擎，不占 tokio 的线程；暂时性失败时安排重试，
+    /// `failed_attempts` 是这次也失败的话已经失败的次数
+    async fn finish_load_on_loader(
+        self: Arc<Self>,
+        meta: ModelMetadata,
+        attempt: u32,
+        failed_attempts: u32,
+    ) {
+        let model_name = meta.name.clone();
+        let worker = self.clone();
+        let result = self
+            .loader
+            .run(move || worker.finish_load(meta, attempt))
+            .await;
+        match result {
+            Ok(Err(e)) if e.is_transient() => self.schedule_retry(model_name, failed_attempts),
+            Ok(_) => {}
+            // 构造引擎时 panic：不能让模型一直停在 Loading
+            Err(e) => {
+                let message = format!("engine construction panicked: {e}");
+                let _ = self.registry.set_error(&model_name, message.clone());
+                self.events.emit(ModelEvent::LoadFailed {
+                    model: model_name,
+                    attempt,
+                    error: message,
+                });
+            }
+        }
+    }
+
+    /// 第 `failed_attempts` 次失败后安排下一次尝试；次数用尽则停在 Error
+    fn schedule_retry(self: &Arc<Self>, model_name: String, failed_attempts: u32) {
+        if failed_attempts >= self.load_retry.max_attempts {
+            let reasons = self
+                .registry
+                .get_model(&model_name)
+                .and_then(|m| m.error)
+                .map(|e| e.reasons)
+                .unwrap_or_default();
+            self.events.emit(ModelEvent::GaveUp {
+                model: model_name,
+                reasons,
+            });
+            return;
+        }
+
+        let delay = self.load_retry.backoff(failed_attempts);
+        self.events.emit(ModelEvent::RetryScheduled {
+            model: model_name.clone(),
+            attempt: failed_attempts + 1,
+            delay_ms: delay.as_millis() as u64,
+        });
+
+        let state = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            // 期间可能被手动加载过，只有仍处于 Error 才继续
+            let still_failed = state
+                .registry
+                .get_model(&model_name)
+                .is_some_and(|m| m.status == ModelStatus::Error);
+            if !still_failed {
+                return;
+            }
+            if let Ok((meta, attempt)) = state.begin_load(&model_name) {
+                state
+                    .finish_load_on_loader(meta, attempt, failed_attempts + 1)
+                    .await;
+            }
+        });
+    }
+
+    /// 获取已加载的 InferenceEngine（按负载均衡策略选一个副本）
     pub fn get_engine(&self, model_name: &str) -> Option<Arc<dyn InferenceEngine>> {
-        let guard = self.engines.read();
-        guard.get(model_name).cloned()
+        self.get_engine_on(model_name, None).map(|i| i.engine)
+    }
+
+    /// 已加载模型的全部副本
+    pub fn replicas(&self, model_name: &str) -> Option<Arc<ReplicaSet>> {
+        self.engines.read().get(model_name).cloned()
+    }
+
+    /// 模型所有实例上未完成的请求数
+    pub fn queue_depth(&self, model_name: &str) -> usize {
+        self.engines
+            .read()
+            .get(model_name)
+            .map_or(0, |replicas| replicas.outstanding())
+    }
+
+    /// 临时加载：`ttl` 到期或 `owner` 离开（见 `release_scratch`）时自动卸载。
+    /// 已经常驻加载的模型不能转成 scratch，加载失败时不做重试；
+    /// 和 `load_model_and_wait` 一样在 blocking 线程里加载
+    pub async fn load_scratch(
+        self: &Arc<Self>,
+        model_name: &str,
+        owner: Option<ScratchOwner>,
+        ttl: Duration,
+    ) -> Result<ModelMetadata, LoadError> {
+        let loaded = self
+            .registry
+            .get_model(model_name)
+            .is_some_and(|m| m.status == ModelStatus::Loaded);
+        if loaded && self.scratch.get(model_name).is_none() {
+            return Err(LoadError::AlreadyLoaded(model_name.to_string()));
+        }
+
+        let state = self.clone();
+        let name = model_name.to_string();
+        let meta = tokio::task::spawn_blocking(move || state.load_model(&name))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))?;
+        let lease = self.scratch.insert(model_name, owner, ttl);
+
+        let state = self.clone();
+        let model_name = model_name.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(ttl).await;
+            // 期间被释放、转成常驻或重新加载过时什么都不做
+            if state.scratch.remove_if(&model_name, lease) {
+                info!(model = %model_name, "scratch model reached its TTL, unloading");
+                let _ = state.unload_model(&model_name);
+            }
+        });
+        Ok(meta)
+    }
+
+    /// 卸载某个创建者的全部 scratch 模型，返回被卸载的模型名
+    pub fn release_scratch(&self, owner: &ScratchOwner) -> Vec<String> {
+        let released = self.scratch.remove_owned_by(owner);
+        for model_name in &released {
+            let _ = self.unload_model(model_name);
+        }
+        released
+    }
+
+    /// 卸载 / 删除模型会影响到的请求数
+    pub fn impact(&self, model_name: &str) -> Impact {
+        match self.replicas(model_name) {
+            Some(replicas) => {
+                let in_flight = replicas.running();
+                Impact {
+                    in_flight,
+                    queued: replicas.outstanding().saturating_sub(in_flight),
+                }
+            }
+            None => Impact::default(),
+        }
+    }
+
+    /// 卸载模型：移除 engine 副本，状态回到 Unloaded。
+    /// 已经拿到 engine 的请求会继续执行完，新请求会收到 model_not_loaded。
+    /// 属于某个 RAG profile 的模型会连同配对的模型一起卸载
+    pub fn unload_model(&self, model_name: &str) -> Result<ModelMetadata, LoadError> {
+        if self.registry.get_model(model_name).is_none() {
+            return Err(LoadError::NotFound(model_name.to_string()));
+        }
+        let meta = self
+            .registry
+            .set_status(model_name, ModelStatus::Unloaded)
+            .map_err(LoadError::InvalidState)?;
+        self.engines.write().remove(model_name);
+        if self.standby.covers(model_name) {
+            self.standby.clear();
+        }
+        self.scratch.remove(model_name);
+        self.events.emit(ModelEvent::Unloaded {
+            model: model_name.to_string(),
+        });
+        for partner in self.rag.partners_of(model_name) {
+            let loaded = self
+                .registry
+                .get_model(&partner)
+                .is_some_and(|m| m.status == ModelStatus::Loaded);
+            if loaded {
+                info!(model = %partner, partner = %model_name, "unloading rag partner model");
+                let _ = self.unload_model(&partner);
+            }
+        }
+        Ok(meta)
+    }
+
+    /// 软删除模型：先卸载（已加载时），再从 registry 移到回收站，权重文件不动
+    pub fn delete_model(&self, model_name: &str) -> Result<ModelMetadata, LoadError> {
+        let meta = self
+            .registry
+            .get_model(model_name)
+            .ok_or_else(|| LoadError::NotFound(model_name.to_string()))?;
+        // 正在加载的模型在这里会因为非法迁移被拒绝
+        if matches!(meta.status, ModelStatus::Loaded | ModelStatus::Loading) {
+            self.unload_model(model_name)?;
+        }
+        let meta = self
+            .registry
+            .unregister(model_name)
+            .ok_or_else(|| LoadError::NotFound(model_name.to_string()))?;
+        let mut trashed = meta.clone();
+        trashed.status = ModelStatus::Unloaded;
+        trashed.error = None;
+        trashed.self_test = None;
+        self.trash.put(trashed);
+        self.events.emit(ModelEvent::Deleted {
+            model: model_name.to_string(),
+        });
+        Ok(meta)
+    }
+
+    /// 从回收站恢复（状态为 Unloaded）；同名模型或 router 已经重新注册时拒绝
+    pub fn restore_model(&self, model_name: &str) -> Result<ModelMetadata, LoadError> {
+        if self.registry.get_model(model_name).is_some()
+            || self.registry.get_router(model_name).is_some()
+        {
+            return Err(LoadError::InvalidState(RegistryError::NameConflict(
+                model_name.to_string(),
+            )));
+        }
+        let mut meta = self
+            .trash
+            .take(model_name)
+            .ok_or_else(|| LoadError::NotFound(model_name.to_string()))?;
+        meta.last_updated = Some(SystemTime::now());
+        self.registry.register(meta.clone());
+        info!(model = %model_name, "restored from the trash");
+        self.events.emit(ModelEvent::Restored {
+            model: model_name.to_string(),
+        });
+        Ok(meta)
+    }
+
+    /// 按模型的负载均衡策略选一个副本；`device` 有值时只在该设备的副本里选
+    pub fn get_engine_on(
+        &self,
+        model_name: &str,
+        device: Option<DeviceSpec>,
+    ) -> Option<EngineInstance> {
+        let replicas = self.engines.read().get(model_name)?.clone();
+        replicas.pick(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fake_registry;
+
+    #[rocket::async_test]
+    async fn load_model_registers_engine() {
+        let state = AppState::with_registry(fake_registry(), 1);
+        assert!(state.get_engine("dummy-a").is_none());
+
+        let meta = state.load_model("dummy-a").unwrap();
+        assert!(matches!(meta.status, ModelStatus::Loaded));
+
+        let engine = state.get_engine("dummy-a").unwrap();
+        let out = engine.generate("ping", 8).await.unwrap();
+        assert_eq!(out, "[dummy-a DUMMY] PING");
+    }
+
+    #[rocket::async_test]
+    async fn engines_are_built_on_the_loader_pool() {
+        let threads = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let seen = threads.clone();
+        let state = AppState::builder()
+            .registry(fake_registry())
+            .engine_factory(EngineKind::DUMMY, move |meta: &ModelMetadata, _device| {
+                let name = std::thread::current().name().map(str::to_string);
+                seen.lock().push(name);
+                Ok(crate::engine::DummyEngine::new(&meta.name) as Arc<dyn InferenceEngine>)
+            })
+            .build();
+
+        state.load_model("dummy-a").unwrap();
+        state.load_model_in_background("dummy-b").unwrap();
+        while state.get_engine("dummy-b").is_none() {
+            rocket::tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let loader = Some("model-loader-0".to_string());
+        assert_eq!(*threads.lock(), [loader.clone(), loader]);
+    }
+
+    #[rocket::async_test]
+    async fn scratch_load_is_unloaded_after_ttl() {
+        let state = AppState::with_registry(fake_registry(), 1);
+        state
+            .load_scratch("dummy-a", None, Duration::from_millis(20))
+            .await
+            .unwrap();
+        assert!(state.scratch.get("dummy-a").is_some());
+
+        // 常驻模型不能转成 scratch
+        state.load_model("dummy-b").unwrap();
+        assert!(matches!(
+            state
+                .load_scratch("dummy-b", None, Duration::from_secs(60))
+                .await,
+            Err(LoadError::AlreadyLoaded(_))
+        ));
+
+        rocket::tokio::time::sleep(Duration::from_millis(80)).await;
+        let meta = state.registry.get_model("dummy-a").unwrap();
+        assert_eq!(meta.status, ModelStatus::Unloaded);
+        assert!(state.get_engine("dummy-a").is_none());
+        assert!(state.scratch.get("dummy-a").is_none());
+    }
+
+    #[test]
+    fn load_unknown_model_fails() {
+        let state = AppState::with_registry(fake_registry(), 1);
+        assert!(matches!(
+            state.load_model("missing"),
+            Err(LoadError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_and_is_capped() {
+        let policy = LoadRetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(2), Duration::from_secs(2));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(4), Duration::from_secs(5));
     }
 }
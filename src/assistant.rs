@@ -0,0 +1,386 @@
+//! Assistant API：服务端执行工具调用的循环（`POST /assistant/runs`）
+//!
+//! 请求里列出这次允许用的工具（必须已在 `tools::ToolRegistry` 注册），prompt 前面加一段
+//! system 说明工具和调用格式。模型输出 `<tool_call>{"name": ..., "arguments": {...}}</tool_call>`
+//! 时由服务端执行工具，把这一轮输出和 `tool:` 结果追加到对话里继续生成，
+//! 直到模型给出不含工具调用的回答，或者达到 `max_steps`。
+//!
+//! `stream: true` 时每一步是一个带类型的 SSE 事件：`tool_call` / `tool_result` / `answer`，
+//! 中途失败发 `error`。
+
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Json;
+use rocket::tokio::select;
+use rocket::tokio::sync::mpsc;
+use rocket::{Either, Shutdown, State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api::{api_error, check_prompt_size, pipeline_error, profile_error, ApiError};
+use crate::api_keys::{ApiKey, ApiKeyProfile};
+use crate::app_state::AppState;
+use crate::config::ServerConfig;
+use crate::engine::SamplingParams;
+use crate::pipeline::{InferencePipeline, PipelineError};
+use crate::preemption::Priority;
+use crate::session::{render_turns, ChatRole, ChatTurn};
+use crate::tools::{ToolError, ToolRegistry};
+use crate::types::{InferMode, InferRequest};
+
+/// 不指定时最多执行几轮生成
+pub const DEFAULT_MAX_STEPS: usize = 5;
+/// `max_steps` 的上限
+pub const MAX_STEPS_LIMIT: usize = 16;
+
+const TOOL_CALL_OPEN: &str = "<tool_call>";
+const TOOL_CALL_CLOSE: &str = "</tool_call>";
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AssistantRunRequest {
+    /// API key 的 profile 配了默认模型时可以省略
+    #[serde(default)]
+    pub model: String,
+    pub messages: Vec<ChatTurn>,
+    /// 这次允许模型调用的工具名
+    pub tools: Vec<String>,
+    /// 最多生成几轮（每次工具调用算一轮），默认 5，最大 16
+    #[serde(default)]
+    pub max_steps: Option<usize>,
+    /// 每轮的生成长度
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub stream: bool,
+}
+
+/// 循环中的一步，流式时 `type` 也是 SSE 的事件名
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AssistantStep {
+    ToolCall {
+        step: usize,
+        name: String,
+        #[schema(value_type = Object)]
+        arguments: serde_json::Value,
+    },
+    /// `output` 和 `error` 只有一个
+    ToolResult {
+        step: usize,
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        output: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// 最终回答；`finish_reason` 为 `stop`，用完 `max_steps` 时为 `max_steps`（内容是最后一轮输出）
+    Answer {
+        content: String,
+        finish_reason: String,
+    },
+}
+
+impl AssistantStep {
+    fn event_name(&self) -> &'static str {
+        match self {
+            AssistantStep::ToolCall { .. } => "tool_call",
+            AssistantStep::ToolResult { .. } => "tool_result",
+            AssistantStep::Answer { .. } => "answer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AssistantRunResponse {
+    pub model: String,
+    pub answer: String,
+    pub finish_reason: String,
+    /// 所有步骤，最后一个是 `answer`
+    pub steps: Vec<AssistantStep>,
+}
+
+/// 工具名 + 参数
+type ToolCall = (String, serde_json::Value);
+
+/// 从模型输出里取第一个工具调用：没有时 None，格式不对时 `Some(Err)`。
+/// 返回值里的 `usize` 是调用结束的位置，之后的内容不进入对话
+fn parse_tool_call(output: &str) -> Option<(Result<ToolCall, String>, usize)> {
+    let start = output.find(TOOL_CALL_OPEN)?;
+    let body_start = start + TOOL_CALL_OPEN.len();
+    let Some(len) = output[body_start..].find(TOOL_CALL_CLOSE) else {
+        return Some((Err("missing </tool_call>".to_string()), output.len()));
+    };
+    let end = body_start + len + TOOL_CALL_CLOSE.len();
+    let call = serde_json::from_str::<serde_json::Value>(&output[body_start..body_start + len])
+        .map_err(|e| e.to_string())
+        .and_then(|call| match call["name"].as_str() {
+            Some(name) => Ok((name.to_string(), call["arguments"].clone())),
+            None => Err("`name` must be a string".to_string()),
+        });
+    Some((call, end))
+}
+
+/// 放在对话最前面的工具说明
+fn tools_prompt(tools: &ToolRegistry) -> String {
+    let list = tools
+        .list()
+        .into_iter()
+        .map(|tool| format!("- {}: {}", tool.name, tool.description))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "You can call these tools:\n{list}\n\
+         To call a tool, reply with only {TOOL_CALL_OPEN}{{\"name\": \"...\", \"arguments\": {{...}}}}{TOOL_CALL_CLOSE}. \
+         The result comes back as a tool message. Reply without a tool call once you have the final answer."
+    )
+}
+
+/// 一次 run 需要的全部输入，校验在进入循环之前完成
+struct AssistantRun {
+    pipeline: InferencePipeline,
+    profile: ApiKeyProfile,
+    base: InferRequest,
+    /// 只包含这次 run 启用的工具
+    tools: ToolRegistry,
+    turns: Vec<ChatTurn>,
+    max_steps: usize,
+}
+
+impl AssistantRun {
+    /// 执行循环，每一步发到 `steps`；接收端关闭（客户端断开）时停止
+    async fn run(
+        mut self,
+        steps: &mpsc::UnboundedSender<AssistantStep>,
+    ) -> Result<(), PipelineError> {
+        let mut last_output = String::new();
+        for step in 1..=self.max_steps {
+            let mut infer = self.base.clone();
+            infer.prompt = render_turns(&self.turns);
+            self.profile
+                .apply(&mut infer)
+                .map_err(|e| PipelineError::Inference(e.to_string()))?;
+            let output = self.pipeline.collect(&infer).await?.output;
+
+            let Some((call, end)) = parse_tool_call(&output) else {
+                let _ = steps.send(AssistantStep::Answer {
+                    content: output,
+                    finish_reason: "stop".to_string(),
+                });
+                return Ok(());
+            };
+            let (name, result) = match call {
+                Ok((name, arguments)) => {
+                    let sent = steps.send(AssistantStep::ToolCall {
+                        step,
+                        name: name.clone(),
+                        arguments: arguments.clone(),
+                    });
+                    if sent.is_err() {
+                        return Ok(());
+                    }
+                    let result = self.call_tool(&name, arguments).await;
+                    (name, result)
+                }
+                Err(e) => (String::new(), Err(format!("invalid tool call: {e}"))),
+            };
+            let (output_text, error) = match &result {
+                Ok(text) => (Some(text.clone()), None),
+                Err(e) => (None, Some(e.clone())),
+            };
+            let sent = steps.send(AssistantStep::ToolResult {
+                step,
+                name,
+                output: output_text,
+                error,
+            });
+            if sent.is_err() {
+                return Ok(());
+            }
+
+            self.turns.push(ChatTurn {
+                role: ChatRole::Assistant,
+                content: output[..end].to_string(),
+            });
+            self.turns.push(ChatTurn {
+                role: ChatRole::Tool,
+                content: result.unwrap_or_else(|e| format!("error: {e}")),
+            });
+            last_output = output;
+        }
+        let _ = steps.send(AssistantStep::Answer {
+            content: last_output,
+            finish_reason: "max_steps".to_string(),
+        });
+        Ok(())
+    }
+
+    /// 没启用的工具和工具自身的失败都作为结果返回给模型，让它自己处理
+    async fn call_tool(&self, name: &str, arguments: serde_json::Value) -> Result<String, String> {
+        self.tools.call(name, arguments).await.map_err(|e| match e {
+            ToolError::NotFound(_) => format!("tool `{name}` is not enabled for this run"),
+            e => e.to_string(),
+        })
+    }
+}
+
+/// 执行 assistant 循环：POST /assistant/runs
+#[utoipa::path(
+    tag = "inference",
+    request_body = AssistantRunRequest,
+    responses(
+        (status = 200, content(
+            ("application/json" = AssistantRunResponse),
+            ("text/event-stream" = AssistantStep)
+        )),
+        (status = 400, description = "invalid input, unknown tool or profile violation", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model not loaded", body = ErrorResponse),
+        (status = 413, description = "prompt too large", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/assistant/runs", data = "<req>")]
+pub async fn create_run(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    key: ApiKey,
+    req: Json<AssistantRunRequest>,
+    mut shutdown: Shutdown,
+) -> Result<Either<Json<AssistantRunResponse>, EventStream![]>, ApiError> {
+    let req = req.into_inner();
+    let invalid = |message: String| api_error(Status::BadRequest, "invalid_input", message);
+    if req.messages.is_empty() {
+        return Err(invalid("messages must not be empty".to_string()));
+    }
+    if req.tools.is_empty() {
+        return Err(invalid("tools must not be empty".to_string()));
+    }
+    let max_steps = req.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+    if !(1..=MAX_STEPS_LIMIT).contains(&max_steps) {
+        return Err(invalid(format!(
+            "max_steps must be between 1 and {MAX_STEPS_LIMIT}"
+        )));
+    }
+    let tools = state.tools.subset(&req.tools).map_err(|name| {
+        api_error(
+            Status::BadRequest,
+            "unknown_tool",
+            format!(
+                "tool `{name}` is not registered (available: {})",
+                state.tools.names().join(", ")
+            ),
+        )
+    })?;
+
+    let mut turns = vec![ChatTurn {
+        role: ChatRole::System,
+        content: tools_prompt(&tools),
+    }];
+    turns.extend(req.messages);
+    let mut base = InferRequest {
+        model_name: req.model,
+        prompt: render_turns(&turns),
+        device: None,
+        mode: InferMode::default(),
+        input_ids: None,
+        return_token_ids: false,
+        max_tokens: req.max_tokens,
+        sampling: SamplingParams::default(),
+        priority: Priority::default(),
+        trace: false,
+        auto_template: false,
+        private: false,
+        client: None,
+        validate: None,
+        tags: Default::default(),
+        request_id: None,
+        rate_client: None,
+    };
+    check_prompt_size(&base.prompt, config)?;
+    // 先解析一次模型名；每轮的 prompt 在循环里重新套 profile
+    let mut resolved = base.clone();
+    key.apply(&mut resolved).map_err(profile_error)?;
+    base.model_name = resolved.model_name.clone();
+
+    // 模型不存在 / 没加载时直接返回错误，不开始循环
+    let pipeline = InferencePipeline::new(state.inner().clone());
+    pipeline.validate(&resolved).map_err(pipeline_error)?;
+
+    let run = AssistantRun {
+        pipeline,
+        profile: key.profile,
+        base,
+        tools,
+        turns,
+        max_steps,
+    };
+    let model = resolved.model_name;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    if !req.stream {
+        run.run(&tx).await.map_err(pipeline_error)?;
+        drop(tx);
+        let mut steps = Vec::new();
+        while let Some(step) = rx.recv().await {
+            steps.push(step);
+        }
+        let (answer, finish_reason) = match steps.last() {
+            Some(AssistantStep::Answer {
+                content,
+                finish_reason,
+            }) => (content.clone(), finish_reason.clone()),
+            _ => (String::new(), "stop".to_string()),
+        };
+        return Ok(Either::Left(Json(AssistantRunResponse {
+            model,
+            answer,
+            finish_reason,
+            steps,
+        })));
+    }
+
+    // 循环放到后台任务里，客户端断开后 channel 关闭，循环在下一步停止
+    let (error_tx, mut error_rx) = mpsc::unbounded_channel();
+    rocket::tokio::spawn(async move {
+        if let Err(e) = run.run(&tx).await {
+            let _ = error_tx.send(e.to_string());
+        }
+    });
+    Ok(Either::Right(EventStream! {
+        loop {
+            select! {
+                maybe_step = rx.recv() => match maybe_step {
+                    Some(step) => yield Event::json(&step).event(step.event_name()),
+                    None => break,
+                },
+                _ = &mut shutdown => return,
+            }
+        }
+        if let Some(message) = error_rx.recv().await {
+            yield Event::data(format!("Error: {message}")).event("error");
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_first_tool_call() {
+        let output = r#"Let me check. <tool_call>{"name": "calculator", "arguments": {"expression": "1+1"}}</tool_call> ignored"#;
+        let (call, end) = parse_tool_call(output).unwrap();
+        let (name, arguments) = call.unwrap();
+        assert_eq!(name, "calculator");
+        assert_eq!(arguments["expression"], "1+1");
+        assert!(output[..end].ends_with(TOOL_CALL_CLOSE));
+
+        assert!(parse_tool_call("no tools needed").is_none());
+        let (call, _) = parse_tool_call("<tool_call>{not json}</tool_call>").unwrap();
+        assert!(call.is_err());
+        let (call, _) = parse_tool_call("<tool_call>{\"name\": \"x\"").unwrap();
+        assert_eq!(call.unwrap_err(), "missing </tool_call>");
+    }
+}
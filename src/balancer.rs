@@ -0,0 +1,141 @@
+//! 同一个模型的多个 engine 副本之间的负载均衡
+//!
+//! 副本来自 `placements`（同一设备可以重复出现），每个副本有一个权重（`weights`，默认 1）。
+//! - `least_outstanding`: 选 未完成请求数 / 权重 最小的副本，一样时选靠前的
+//! - `weighted_round_robin`: 按权重轮询
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::app_state::EngineInstance;
+use crate::device::DeviceSpec;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalancePolicy {
+    #[default]
+    LeastOutstanding,
+    WeightedRoundRobin,
+}
+
+/// 一个模型已加载的全部副本
+pub struct ReplicaSet {
+    instances: Vec<EngineInstance>,
+    policy: BalancePolicy,
+    next: AtomicUsize,
+}
+
+impl ReplicaSet {
+    pub fn new(instances: Vec<EngineInstance>, policy: BalancePolicy) -> Self {
+        Self {
+            instances,
+            policy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn instances(&self) -> &[EngineInstance] {
+        &self.instances
+    }
+
+    /// 按策略选一个副本；`device` 有值时只在该设备的副本里选
+    pub fn pick(&self, device: Option<DeviceSpec>) -> Option<EngineInstance> {
+        let eligible: Vec<&EngineInstance> = self
+            .instances
+            .iter()
+            .filter(|i| device.is_none_or(|d| i.device == d))
+            .collect();
+
+        let picked = match self.policy {
+            BalancePolicy::LeastOutstanding => eligible.into_iter().reduce(|best, i| {
+                // outstanding / weight 更小的优先，交叉相乘避免浮点
+                let lhs = i.outstanding() as u64 * best.weight as u64;
+                let rhs = best.outstanding() as u64 * i.weight as u64;
+                if lhs < rhs {
+                    i
+                } else {
+                    best
+                }
+            }),
+            BalancePolicy::WeightedRoundRobin => {
+                let total: usize = eligible.iter().map(|i| i.weight as usize).sum();
+                if total == 0 {
+                    return None;
+                }
+                let mut slot = self.next.fetch_add(1, Ordering::Relaxed) % total;
+                eligible.into_iter().find(|i| {
+                    let hit = slot < i.weight as usize;
+                    slot = slot.saturating_sub(i.weight as usize);
+                    hit
+                })
+            }
+        };
+        picked.cloned()
+    }
+
+    /// 所有副本上未完成的请求数
+    pub fn outstanding(&self) -> usize {
+        self.instances.iter().map(|i| i.outstanding()).sum()
+    }
+
+    /// 所有副本上正在执行的请求数
+    pub fn running(&self) -> usize {
+        self.instances.iter().map(|i| i.running()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::DummyEngine;
+
+    fn replicas(policy: BalancePolicy, weights: &[u32]) -> ReplicaSet {
+        let instances = weights
+            .iter()
+            .map(|&w| EngineInstance::new(DeviceSpec::Cpu, DummyEngine::new("m")).with_weight(w))
+            .collect();
+        ReplicaSet::new(instances, policy)
+    }
+
+    fn index_of(set: &ReplicaSet, picked: &EngineInstance) -> usize {
+        set.instances()
+            .iter()
+            .position(|i| i.same_replica(picked))
+            .unwrap()
+    }
+
+    #[test]
+    fn least_outstanding_spreads_by_weight() {
+        let set = replicas(BalancePolicy::LeastOutstanding, &[1, 2]);
+        let mut guards = Vec::new();
+        let mut picks = Vec::new();
+        for _ in 0..3 {
+            let picked = set.pick(None).unwrap();
+            picks.push(index_of(&set, &picked));
+            guards.push(picked.track());
+        }
+        // 空闲时选第一个，之后按 outstanding / weight
+        assert_eq!(picks, vec![0, 1, 1]);
+        assert_eq!(set.outstanding(), 3);
+
+        drop(guards);
+        assert_eq!(set.outstanding(), 0);
+    }
+
+    #[test]
+    fn weighted_round_robin_follows_weights() {
+        let set = replicas(BalancePolicy::WeightedRoundRobin, &[3, 1]);
+        let picks: Vec<_> = (0..8)
+            .map(|_| index_of(&set, &set.pick(None).unwrap()))
+            .collect();
+        assert_eq!(picks, vec![0, 0, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn device_filter_applies_before_balancing() {
+        let set = replicas(BalancePolicy::WeightedRoundRobin, &[1]);
+        assert!(set.pick(Some(DeviceSpec::Cuda(0))).is_none());
+        assert!(set.pick(Some(DeviceSpec::Cpu)).is_some());
+    }
+}
@@ -0,0 +1,294 @@
+//! throughput 模式的攒批调度：同一个模型副本上的请求最多等 `window`，
+//! 或者攒够 `max_batch` 个就一起交给 `generate_batch`，整批只占一个并发 permit，
+//! 在 `AdmissionQueue` 里也只占一个位置；排不上队或等太久时整批失败。
+//!
+//! 代价是每个请求多了最多 `window` 的延迟，只适合非交互的批量任务。
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rocket::tokio::sync::{oneshot, Semaphore};
+
+use crate::engine::InferenceEngine;
+use crate::metrics::Metrics;
+use crate::queue::{AdmissionQueue, QueueRefusal};
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// 第一个请求到达后最多等多久
+    pub window: Duration,
+    pub max_batch: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_millis(20),
+            max_batch: 8,
+        }
+    }
+}
+
+/// 一批里单个请求的失败原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchError {
+    /// 整批没排上队或等太久
+    Queue(QueueRefusal),
+    /// 推理失败，或者服务关闭时整批被丢弃
+    Failed(String),
+}
+
+struct Pending {
+    prompt: String,
+    reply: oneshot::Sender<Result<String, BatchError>>,
+}
+
+struct Queue {
+    /// 每次新开一批加一，防止过期的定时任务把下一批提前发出去
+    generation: u64,
+    engine: Arc<dyn InferenceEngine>,
+    max_tokens: usize,
+    items: Vec<Pending>,
+}
+
+pub struct Batcher {
+    config: BatchConfig,
+    semaphore: Arc<Semaphore>,
+    metrics: Arc<Metrics>,
+    admission: Arc<AdmissionQueue>,
+    queues: Arc<Mutex<HashMap<String, Queue>>>,
+    next_generation: AtomicU64,
+}
+
+impl Batcher {
+    pub fn new(
+        config: BatchConfig,
+        semaphore: Arc<Semaphore>,
+        metrics: Arc<Metrics>,
+        admission: Arc<AdmissionQueue>,
+    ) -> Self {
+        Self {
+            config,
+            semaphore,
+            metrics,
+            admission,
+            queues: Arc::new(Mutex::new(HashMap::new())),
+            next_generation: AtomicU64::new(0),
+        }
+    }
+
+    /// 提交一个请求，`key` 相同（同一模型副本）的请求会合并成一批。
+    /// 推理错误以字符串返回，因为同一批的结果要分发给多个调用方
+    pub async fn submit(
+        &self,
+        key: &str,
+        engine: Arc<dyn InferenceEngine>,
+        prompt: &str,
+        max_tokens: usize,
+    ) -> Result<String, BatchError> {
+        let (tx, rx) = oneshot::channel();
+        let pending = Pending {
+            prompt: prompt.to_string(),
+            reply: tx,
+        };
+
+        let (full, new_generation) = {
+            let mut queues = self.queues.lock();
+            let queue = queues.entry(key.to_string()).or_insert_with(|| Queue {
+                generation: self.next_generation.fetch_add(1, Ordering::Relaxed) + 1,
+                engine,
+                max_tokens,
+                items: Vec::new(),
+            });
+            queue.items.push(pending);
+            let new_generation = (queue.items.len() == 1).then_some(queue.generation);
+            let full = if queue.items.len() >= self.config.max_batch {
+                queues.remove(key)
+            } else {
+                None
+            };
+            (full, new_generation)
+        };
+
+        match full {
+            Some(batch) => self.spawn_run(batch),
+            None => {
+                if let Some(generation) = new_generation {
+                    self.spawn_timer(key.to_string(), generation);
+                }
+            }
+        }
+
+        rx.await
+            .unwrap_or_else(|_| Err(BatchError::Failed("batch was dropped".to_string())))
+    }
+
+    /// 窗口到期后把还在排队的这一批发出去
+    fn spawn_timer(&self, key: String, generation: u64) {
+        let queues = self.queues.clone();
+        let semaphore = self.semaphore.clone();
+        let metrics = self.metrics.clone();
+        let admission = self.admission.clone();
+        let window = self.config.window;
+        rocket::tokio::spawn(async move {
+            rocket::tokio::time::sleep(window).await;
+            let batch = {
+                let mut queues = queues.lock();
+                match queues.get(&key) {
+                    Some(queue) if queue.generation == generation => queues.remove(&key),
+                    _ => None,
+                }
+            };
+            if let Some(batch) = batch {
+                run(batch, semaphore, metrics, admission).await;
+            }
+        });
+    }
+
+    fn spawn_run(&self, batch: Queue) {
+        rocket::tokio::spawn(run(
+            batch,
+            self.semaphore.clone(),
+            self.metrics.clone(),
+            self.admission.clone(),
+        ));
+    }
+}
+
+async fn run(
+    batch: Queue,
+    semaphore: Arc<Semaphore>,
+    metrics: Arc<Metrics>,
+    admission: Arc<AdmissionQueue>,
+) {
+    let (prompts, replies): (Vec<_>, Vec<_>) =
+        batch.items.into_iter().map(|p| (p.prompt, p.reply)).unzip();
+    let permit = match admission.enter(&semaphore) {
+        Ok(ticket) => ticket.wait(metrics.acquire(semaphore.clone())).await,
+        Err(refusal) => Err(refusal),
+    };
+    let _permit = match permit {
+        Ok(Ok(permit)) => permit,
+        // 服务关闭，调用方会收到 "batch was dropped"
+        Ok(Err(_)) => return,
+        Err(refusal) => {
+            for reply in replies {
+                let _ = reply.send(Err(BatchError::Queue(refusal)));
+            }
+            return;
+        }
+    };
+    let outputs = batch
+        .engine
+        .generate_batch(&prompts, batch.max_tokens)
+        .await;
+    for (reply, output) in replies.into_iter().zip(outputs) {
+        let _ = reply.send(output.map_err(|e| BatchError::Failed(e.to_string())));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use async_trait::async_trait;
+    use rocket::tokio::sync::mpsc;
+
+    use crate::queue::QueueConfig;
+
+    /// 记录每次 generate_batch 的批大小
+    #[derive(Default)]
+    struct RecordingEngine {
+        batches: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl InferenceEngine for RecordingEngine {
+        async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+            Ok(prompt.to_uppercase())
+        }
+
+        async fn generate_stream(
+            &self,
+            _prompt: &str,
+            _max_tokens: usize,
+            _sender: mpsc::Sender<String>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        async fn generate_batch(
+            &self,
+            prompts: &[String],
+            _max_tokens: usize,
+        ) -> Vec<Result<String>> {
+            self.batches.lock().push(prompts.len());
+            prompts.iter().map(|p| Ok(p.to_uppercase())).collect()
+        }
+    }
+
+    #[rocket::async_test]
+    async fn requests_within_window_share_a_batch() {
+        let engine = Arc::new(RecordingEngine::default());
+        let config = BatchConfig {
+            window: Duration::from_millis(30),
+            max_batch: 3,
+        };
+        let batcher = Batcher::new(
+            config,
+            Arc::new(Semaphore::new(1)),
+            Arc::new(Metrics::new()),
+            Arc::new(AdmissionQueue::new(&QueueConfig::default())),
+        );
+
+        let submit = |prompt: &'static str| {
+            let engine: Arc<dyn InferenceEngine> = engine.clone();
+            let batcher = &batcher;
+            async move { batcher.submit("m", engine, prompt, 8).await }
+        };
+        // 前 3 个攒满立即执行，第 4 个等窗口到期
+        let (a, b, c, d) = rocket::tokio::join!(submit("a"), submit("b"), submit("c"), submit("d"));
+        assert_eq!(
+            [a, b, c, d].map(Result::unwrap),
+            ["A", "B", "C", "D"].map(String::from)
+        );
+        assert_eq!(*engine.batches.lock(), vec![3, 1]);
+    }
+    /// 整批在 `AdmissionQueue` 里排队，等太久时每个请求都收到排队错误
+    #[rocket::async_test]
+    async fn batches_wait_in_the_admission_queue() {
+        let engine: Arc<dyn InferenceEngine> = Arc::new(RecordingEngine::default());
+        let semaphore = Arc::new(Semaphore::new(1));
+        let admission = Arc::new(AdmissionQueue::new(&QueueConfig {
+            max_len: Some(1),
+            max_wait_ms: 30,
+        }));
+        let batcher = Batcher::new(
+            BatchConfig {
+                window: Duration::from_millis(1),
+                max_batch: 2,
+            },
+            semaphore.clone(),
+            Arc::new(Metrics::new()),
+            admission.clone(),
+        );
+        let _busy = semaphore.clone().acquire_owned().await.unwrap();
+
+        let (a, b) = rocket::tokio::join!(
+            batcher.submit("m", engine.clone(), "a", 8),
+            batcher.submit("m", engine.clone(), "b", 8)
+        );
+        for result in [a, b] {
+            match result {
+                Err(BatchError::Queue(QueueRefusal::TimedOut { position, .. })) => {
+                    assert_eq!(position, 1)
+                }
+                other => panic!("expected a queue timeout, got {other:?}"),
+            }
+        }
+        assert!(admission.is_empty());
+    }
+}
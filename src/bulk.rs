@@ -0,0 +1,245 @@
+//! 批量加载 / 卸载：`POST /models/bulk` 一次提交一组操作（例如“卸载 A，加载 B 和 C”），
+//! 在后台 job 里按依赖顺序执行，不用客户端自己排先后、和别的请求抢状态。
+//!
+//! - 提交时先整体校验：模型都存在、同一个模型只出现一次、要卸载的模型空闲（或 `force`），
+//!   有一项不通过就整批拒绝，什么都不执行
+//! - 执行顺序：先卸载（腾出内存），再加载，各自保持请求里的顺序
+//! - 某一步失败后不再继续，剩下的操作标记为 `skipped`；已经完成的不回滚
+//! - 已经是目标状态的模型直接算完成（不会重新加载）
+//!
+//! 结果（`GET /jobs/<id>` 的 `result`）里逐项给出结果，`completed` 表示是否全部成功。
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::AppState;
+use crate::model_registry::ModelStatus;
+use crate::types::JobAcceptedResponse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    Load,
+    Unload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BulkOperation {
+    pub action: BulkAction,
+    pub model_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BulkModelsRequest {
+    pub operations: Vec<BulkOperation>,
+    /// 要卸载的模型还有请求在跑时仍然卸载（同 `/admin/models/<name>/unload?force=true`）
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkOutcome {
+    Done,
+    Failed,
+    /// 前面的操作失败，没有执行
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BulkOperationResult {
+    pub action: BulkAction,
+    pub model_name: String,
+    pub outcome: BulkOutcome,
+    /// 执行后模型的状态（跳过的操作没有）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<ModelStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// 批量操作 job 的结果，按执行顺序排列
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BulkModelsResult {
+    /// 所有操作都成功
+    pub completed: bool,
+    pub operations: Vec<BulkOperationResult>,
+}
+
+/// 校验整批操作，返回执行顺序（先卸载后加载）
+fn plan(state: &AppState, req: &BulkModelsRequest) -> Result<Vec<BulkOperation>, ApiError> {
+    if req.operations.is_empty() {
+        return Err(api_error(
+            Status::BadRequest,
+            "empty_operations",
+            "operations must not be empty",
+        ));
+    }
+    let mut seen = HashSet::new();
+    for op in &req.operations {
+        if !seen.insert(op.model_name.as_str()) {
+            return Err(api_error(
+                Status::BadRequest,
+                "conflicting_operations",
+                format!("model `{}` appears more than once", op.model_name),
+            ));
+        }
+        if state.registry.get_model(&op.model_name).is_none() {
+            return Err(api_error(
+                Status::NotFound,
+                "model_not_found",
+                format!("model `{}` not found", op.model_name),
+            ));
+        }
+        if op.action == BulkAction::Unload && !req.force {
+            let impact = state.impact(&op.model_name);
+            if !impact.is_idle() {
+                return Err(api_error(
+                    Status::Conflict,
+                    "model_busy",
+                    format!(
+                        "model `{}` has {} in-flight and {} queued requests; \
+                         repeat the call with force=true",
+                        op.model_name, impact.in_flight, impact.queued
+                    ),
+                ));
+            }
+        }
+    }
+    let (unloads, loads): (Vec<_>, Vec<_>) = req
+        .operations
+        .iter()
+        .cloned()
+        .partition(|op| op.action == BulkAction::Unload);
+    Ok(unloads.into_iter().chain(loads).collect())
+}
+
+fn execute(state: &Arc<AppState>, op: &BulkOperation) -> Result<ModelStatus, String> {
+    let current = state
+        .registry
+        .get_model(&op.model_name)
+        .map(|m| m.status)
+        .ok_or_else(|| format!("model `{}` not found", op.model_name))?;
+    let result = match (op.action, current) {
+        (BulkAction::Load, ModelStatus::Loaded) | (BulkAction::Unload, ModelStatus::Unloaded) => {
+            return Ok(current)
+        }
+        (BulkAction::Load, _) => state.load_model_with_retries(&op.model_name),
+        (BulkAction::Unload, _) => state.unload_model(&op.model_name),
+    };
+    result.map(|meta| meta.status).map_err(|e| e.to_string())
+}
+
+fn run(state: &Arc<AppState>, ops: Vec<BulkOperation>, progress: impl Fn(f32)) -> BulkModelsResult {
+    let total = ops.len();
+    let mut failed = false;
+    let mut results = Vec::with_capacity(total);
+    for (i, op) in ops.into_iter().enumerate() {
+        let result = if failed {
+            BulkOperationResult {
+                action: op.action,
+                model_name: op.model_name,
+                outcome: BulkOutcome::Skipped,
+                status: None,
+                error: None,
+            }
+        } else {
+            let executed = execute(state, &op);
+            failed = executed.is_err();
+            BulkOperationResult {
+                outcome: if failed {
+                    BulkOutcome::Failed
+                } else {
+                    BulkOutcome::Done
+                },
+                status: state.registry.get_model(&op.model_name).map(|m| m.status),
+                error: executed.err(),
+                action: op.action,
+                model_name: op.model_name,
+            }
+        };
+        results.push(result);
+        progress((i + 1) as f32 / total as f32);
+    }
+    BulkModelsResult {
+        completed: !failed,
+        operations: results,
+    }
+}
+
+/// 批量加载 / 卸载：POST /models/bulk（返回 job id，结果见 `BulkModelsResult`）
+#[utoipa::path(
+    tag = "models",
+    request_body = BulkModelsRequest,
+    responses(
+        (status = 202, description = "operations started; poll GET /jobs/{id}", body = JobAcceptedResponse),
+        (status = 400, description = "empty or conflicting operations", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "a model to unload is busy", body = ErrorResponse)
+    )
+)]
+#[post("/models/bulk", data = "<req>")]
+pub async fn bulk_models(
+    state: &State<Arc<AppState>>,
+    req: Json<BulkModelsRequest>,
+) -> Result<status::Custom<Json<JobAcceptedResponse>>, ApiError> {
+    let ops = plan(state, &req)?;
+    let app = state.inner().clone();
+    let job_id = state.jobs.spawn_blocking("models_bulk", move |job| {
+        Ok(run(&app, ops, |p| job.set_progress(p)))
+    });
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(JobAcceptedResponse { job_id }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_state;
+
+    fn op(action: BulkAction, model_name: &str) -> BulkOperation {
+        BulkOperation {
+            action,
+            model_name: model_name.to_string(),
+        }
+    }
+
+    #[test]
+    fn plan_puts_unloads_first_and_rejects_duplicates() {
+        let state = test_state();
+        let req = BulkModelsRequest {
+            operations: vec![
+                op(BulkAction::Load, "dummy-b"),
+                op(BulkAction::Unload, "dummy-a"),
+            ],
+            force: false,
+        };
+        let order: Vec<_> = plan(&state, &req)
+            .unwrap()
+            .into_iter()
+            .map(|op| op.action)
+            .collect();
+        assert_eq!(order, [BulkAction::Unload, BulkAction::Load]);
+
+        let req = BulkModelsRequest {
+            operations: vec![
+                op(BulkAction::Load, "dummy-a"),
+                op(BulkAction::Unload, "dummy-a"),
+            ],
+            force: false,
+        };
+        let err = plan(&state, &req).unwrap_err();
+        assert_eq!(err.status(), Status::BadRequest);
+        assert_eq!(err.code(), "conflicting_operations");
+    }
+}
@@ -0,0 +1,179 @@
+//! 取消正在进行的推理：`DELETE /requests/<request_id>`。
+//!
+//! 每个登记的生成（见 `generations`）有一个 `CancelToken`。取消时 pipeline 丢掉排队或执行中的 future，
+//! permit 和 KV cache 记账随之释放；引擎的解码循环是同步的，丢 future 停不下来，
+//! 所以执行期间把 token 放在 task-local 里，引擎在算每个 token 前用 `requested()` 检查，
+//! 发现取消就提前返回，CPU / GPU 立即空出来。candle 在绑定的线程池里计算时，`enter` 把 token 带过去。
+//!
+//! 流式生成的暂停（见 `generations`）也记在同一个 token 上：逐 token 推送的引擎在算下一个 token 前
+//! 调 `wait_while_paused`，暂停期间解码线程停在这里，解码状态和 KV cache 原样留着。
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+use rocket::tokio::sync::Notify;
+
+/// 暂停时最多隔多久检查一次要不要放弃等待
+const PAUSE_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+    paused: Mutex<bool>,
+    resumed: Condvar,
+}
+
+/// 一个请求的取消信号，clone 出来的都指向同一个
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<Inner>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+        self.0.notify.notify_waiters();
+        // 暂停中的解码也要醒过来
+        let _paused = self.0.paused.lock();
+        self.0.resumed.notify_all();
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        *self.0.paused.lock() = paused;
+        self.0.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.0.paused.lock()
+    }
+
+    /// 阻塞到没有暂停、被取消或 `give_up` 返回 true
+    fn wait_resumed(&self, give_up: &dyn Fn() -> bool) {
+        let mut paused = self.0.paused.lock();
+        while *paused && !self.is_cancelled() && !give_up() {
+            self.0.resumed.wait_for(&mut paused, PAUSE_POLL);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// 被取消时完成
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.0.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+rocket::tokio::task_local! {
+    static CURRENT: CancelToken;
+}
+
+thread_local! {
+    static ENTERED: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+/// 在 `token` 下执行：`fut` 里（含引擎）可以用 `current` / `requested` 取到它
+pub async fn scope<F: Future>(token: CancelToken, fut: F) -> F::Output {
+    CURRENT.scope(token, fut).await
+}
+
+/// 当前任务的取消信号，不在 `scope` 里时为 None
+pub fn current() -> Option<CancelToken> {
+    CURRENT.try_with(CancelToken::clone).ok()
+}
+
+/// 把 `token` 带到别的线程（例如 rayon 线程池）上的同步计算里
+pub fn enter<T>(token: Option<CancelToken>, f: impl FnOnce() -> T) -> T {
+    let previous = ENTERED.with(|entered| entered.replace(token));
+    let result = f();
+    ENTERED.with(|entered| *entered.borrow_mut() = previous);
+    result
+}
+
+/// 引擎的解码循环里调用：当前请求是否已经被取消
+pub fn requested() -> bool {
+    ENTERED
+        .with(|entered| entered.borrow().as_ref().map(CancelToken::is_cancelled))
+        .or_else(|| CURRENT.try_with(CancelToken::is_cancelled).ok())
+        .unwrap_or(false)
+}
+
+/// 逐 token 推送的引擎在算下一个 token 前调用：当前请求暂停时阻塞，直到继续、被取消或
+/// `give_up` 返回 true（例如接收方已经断开）。在 tokio 的 worker 上调用时要先 `block_in_place`
+pub fn wait_while_paused(give_up: impl Fn() -> bool) {
+    let token = ENTERED
+        .with(|entered| entered.borrow().clone())
+        .or_else(current);
+    if let Some(token) = token {
+        token.wait_resumed(&give_up);
+    }
+}
+
+/// 引擎因为取消提前返回时的错误
+#[derive(Debug, thiserror::Error)]
+#[error("request was cancelled")]
+pub struct Cancelled;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn engines_see_the_token_of_the_enclosing_scope() {
+        assert!(!requested() && current().is_none());
+        let token = CancelToken::default();
+        let waiter = {
+            let token = token.clone();
+            rocket::tokio::spawn(async move { token.cancelled().await })
+        };
+        scope(token.clone(), async {
+            assert!(!requested());
+            token.cancel();
+            assert!(requested());
+            // 同步计算换了线程也能看到
+            let entered = current();
+            let seen = std::thread::spawn(move || enter(entered, requested));
+            assert!(seen.join().unwrap());
+        })
+        .await;
+        assert!(!requested());
+        rocket::tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn paused_decoding_waits_until_resumed_or_cancelled() {
+        let token = CancelToken::default();
+        token.set_paused(true);
+        let resumed = {
+            let token = token.clone();
+            std::thread::spawn(move || enter(Some(token), || wait_while_paused(|| false)))
+        };
+        std::thread::sleep(Duration::from_millis(80));
+        assert!(!resumed.is_finished());
+        token.set_paused(false);
+        resumed.join().unwrap();
+
+        token.set_paused(true);
+        let cancelled = {
+            let token = token.clone();
+            std::thread::spawn(move || enter(Some(token), || wait_while_paused(|| false)))
+        };
+        token.cancel();
+        cancelled.join().unwrap();
+        // 没有 token 时直接返回
+        wait_while_paused(|| false);
+    }
+}
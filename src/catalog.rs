@@ -0,0 +1,256 @@
+//! 模型目录：`GET /catalog` 列出验证过能跑的 GGUF 模型（参数量、量化选项、文件大小、内存需求），
+//! `POST /catalog/<id>/install` 一步完成注册 + 下载 + 加载。
+//!
+//! 默认使用编译进来的 `catalog.toml`；`ServerConfig.model_catalog` 可以换成本地文件或 http(s) 地址。
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Context;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::AppState;
+use crate::model_registry::{EngineKind, HubArtifacts, ModelMetadata, ModelStatus};
+use crate::types::JobAcceptedResponse;
+
+const BUNDLED: &str = include_str!("catalog.toml");
+
+fn default_engine_kind() -> EngineKind {
+    EngineKind::CANDLE
+}
+
+/// 一种量化版本对应的文件
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CatalogQuant {
+    pub quantization: String,
+    /// 仓库里的 GGUF 文件名
+    pub file: String,
+    pub size_gb: f64,
+    /// 默认上下文下的大致内存峰值
+    pub ram_gb: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CatalogEntry {
+    /// 安装后的模型名
+    pub id: String,
+    pub name: String,
+    pub repo: String,
+    pub tokenizer_repo: String,
+    /// 参数量，例如 `7B`
+    pub parameters: String,
+    pub context_window: usize,
+    pub default_quant: String,
+    pub quants: Vec<CatalogQuant>,
+    #[serde(default = "default_engine_kind")]
+    #[schema(value_type = String)]
+    pub engine_kind: EngineKind,
+}
+
+impl CatalogEntry {
+    /// 按名字找量化版本（不区分大小写），None 时用 `default_quant`
+    pub fn quant(&self, quantization: Option<&str>) -> Option<&CatalogQuant> {
+        let wanted = quantization.unwrap_or(&self.default_quant);
+        self.quants
+            .iter()
+            .find(|q| q.quantization.eq_ignore_ascii_case(wanted))
+    }
+
+    /// 注册用的元数据，权重从 hub 下载
+    pub fn metadata(&self, quant: &CatalogQuant) -> ModelMetadata {
+        ModelMetadata::new(
+            &self.id,
+            "",
+            &quant.quantization.to_lowercase(),
+            self.engine_kind.clone(),
+        )
+        .with_hub_artifacts(HubArtifacts {
+            repo: self.repo.clone(),
+            file: quant.file.clone(),
+            tokenizer_repo: self.tokenizer_repo.clone(),
+        })
+        .with_context_window(self.context_window)
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Catalog {
+    #[serde(default)]
+    pub models: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    /// 编译进来的目录
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED).expect("bundled catalog.toml is invalid")
+    }
+
+    /// `source` 是 http(s) 地址时下载，否则当作本地文件路径
+    pub fn load(source: &str) -> anyhow::Result<Self> {
+        let text = if source.starts_with("http://") || source.starts_with("https://") {
+            crate::hub_stream::agent_builder()?
+                .build()
+                .get(source)
+                .call()
+                .with_context(|| format!("failed to fetch catalog `{source}`"))?
+                .into_string()?
+        } else {
+            std::fs::read_to_string(source)
+                .with_context(|| format!("failed to read catalog `{source}`"))?
+        };
+        Self::parse(&text).with_context(|| format!("invalid catalog `{source}`"))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let catalog: Catalog = toml::from_str(text)?;
+        let mut ids = HashSet::new();
+        for entry in &catalog.models {
+            if !ids.insert(entry.id.as_str()) {
+                anyhow::bail!("duplicate catalog id `{}`", entry.id);
+            }
+            if entry.quant(None).is_none() {
+                anyhow::bail!(
+                    "`{}`: default_quant `{}` is not one of its quants",
+                    entry.id,
+                    entry.default_quant
+                );
+            }
+        }
+        Ok(catalog)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&CatalogEntry> {
+        self.models.iter().find(|m| m.id == id)
+    }
+}
+
+/// 安装 job 成功时的结果
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CatalogInstallResult {
+    pub model_name: String,
+    pub quantization: String,
+    pub status: ModelStatus,
+}
+
+/// 可安装的模型：GET /catalog
+#[utoipa::path(
+    tag = "models",
+    responses((status = 200, body = [CatalogEntry]))
+)]
+#[get("/catalog")]
+pub async fn list_catalog(state: &State<Arc<AppState>>) -> Json<Vec<CatalogEntry>> {
+    Json(state.catalog.models.clone())
+}
+
+/// 注册并在后台下载、加载目录里的模型：POST /catalog/<id>/install?quant=Q4_K_M
+#[utoipa::path(
+    tag = "models",
+    responses(
+        (status = 202, description = "install started; its result is a CatalogInstallResult", body = JobAcceptedResponse),
+        (status = 400, description = "unknown quantization", body = ErrorResponse),
+        (status = 404, description = "not in the catalog", body = ErrorResponse),
+        (status = 409, description = "a model with this name is loading or loaded", body = ErrorResponse)
+    )
+)]
+#[post("/catalog/<id>/install?<quant>")]
+pub async fn install_catalog_model(
+    state: &State<Arc<AppState>>,
+    id: &str,
+    quant: Option<&str>,
+) -> Result<status::Custom<Json<JobAcceptedResponse>>, ApiError> {
+    let entry = state.catalog.get(id).ok_or_else(|| {
+        api_error(
+            Status::NotFound,
+            "catalog_entry_not_found",
+            format!("`{id}` is not in the model catalog"),
+        )
+    })?;
+    let chosen = entry.quant(quant).ok_or_else(|| {
+        let options: Vec<_> = entry
+            .quants
+            .iter()
+            .map(|q| q.quantization.as_str())
+            .collect();
+        api_error(
+            Status::BadRequest,
+            "invalid_input",
+            format!(
+                "`{id}` has no quantization `{}` (available: {})",
+                quant.unwrap_or_default(),
+                options.join(", ")
+            ),
+        )
+    })?;
+
+    // 失败或卸载过的同名模型可以重新安装，正在用的不行
+    if let Some(existing) = state.registry.get_model(id) {
+        if matches!(existing.status, ModelStatus::Loading | ModelStatus::Loaded) {
+            return Err(api_error(
+                Status::Conflict,
+                "model_exists",
+                format!("model `{id}` is already {:?}", existing.status),
+            ));
+        }
+    }
+    state.registry.register(entry.metadata(chosen));
+
+    let app = state.inner().clone();
+    let model_name = entry.id.clone();
+    let quantization = chosen.quantization.clone();
+    let job_id = state.jobs.spawn_blocking("catalog_install", move |_job| {
+        let meta = app.load_model(&model_name)?;
+        Ok(CatalogInstallResult {
+            model_name: meta.name,
+            quantization,
+            status: meta.status,
+        })
+    });
+
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(JobAcceptedResponse { job_id }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_catalog_is_valid() {
+        let catalog = Catalog::bundled();
+        let mistral = catalog.get("mistral-7b-instruct").unwrap();
+        assert_eq!(mistral.quant(None).unwrap().quantization, "Q4_K_M");
+        assert_eq!(
+            mistral.quant(Some("q2_k")).unwrap().file,
+            "mistral-7b-instruct-v0.1.Q2_K.gguf"
+        );
+        let meta = mistral.metadata(mistral.quant(None).unwrap());
+        assert_eq!(meta.engine_kind, EngineKind::CANDLE);
+        assert_eq!(meta.hub_artifacts.unwrap().repo, mistral.repo);
+    }
+
+    #[test]
+    fn default_quant_must_exist() {
+        let text = r#"
+            [[models]]
+            id = "x"
+            name = "X"
+            repo = "acme/x"
+            tokenizer_repo = "acme/x"
+            parameters = "1B"
+            context_window = 2048
+            default_quant = "Q4_K_M"
+            quants = [{ quantization = "Q8_0", file = "x.gguf", size_gb = 1.0, ram_gb = 2.0 }]
+        "#;
+        let err = Catalog::parse(text).unwrap_err();
+        assert!(err.to_string().contains("default_quant"), "{err}");
+    }
+}
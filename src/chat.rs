@@ -0,0 +1,468 @@
+//! 多轮对话接口（`/sessions/*`）：历史保存在服务端，超长时按 session 配置压缩，
+//! 开启 `memory` 时较早的轮次折叠成长期记忆；session 自带的生成默认值可以用 PATCH 修改。
+//! 每次生成都会广播给 `GET /sessions/<id>/stream` 的订阅者（见 `session_stream`）
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Json;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::time::Instant;
+use rocket::{Either, Shutdown, State};
+
+use crate::api::{
+    api_error, check_prompt_size, pipeline_error, profile_error, unix_secs, ApiError,
+};
+use crate::api_keys::ApiKey;
+use crate::app_state::AppState;
+use crate::config::ServerConfig;
+use crate::pipeline::{InferencePipeline, StreamChunk, COLLECT_MAX_TOKENS, STREAM_MAX_TOKENS};
+use crate::preemption::Priority;
+use crate::router::AUTO_MODEL;
+use crate::scratch::ScratchOwner;
+use crate::session::{ChatSession, GenerationDefaults, SessionMemory};
+use crate::session_stream::{LiveEvent, LiveSubscription};
+use crate::stream_stats::{ChunkRate, StatsTicker, StreamStats};
+use crate::types::{
+    ChatMessageRequest, ChatMessageResponse, CreateSessionRequest, InferMode, InferRequest,
+    ScratchReleaseResponse, SessionMemoryResponse, SessionResponse, UpdateSessionMemoryRequest,
+};
+
+fn session_response(session: ChatSession) -> SessionResponse {
+    SessionResponse {
+        id: session.id,
+        model_name: session.model_name,
+        options: session.options,
+        turns: session.turns,
+        created_at: unix_secs(session.created_at),
+    }
+}
+
+fn memory_response(session_id: &str, memory: SessionMemory) -> SessionMemoryResponse {
+    SessionMemoryResponse {
+        session_id: session_id.to_string(),
+        content: memory.content,
+        summarized_turns: memory.summarized_turns,
+        updated_at: memory.updated_at.map(unix_secs),
+        edited: memory.edited,
+    }
+}
+
+fn session_not_found(id: &str) -> ApiError {
+    api_error(
+        Status::NotFound,
+        "session_not_found",
+        format!("session `{id}` not found"),
+    )
+}
+
+/// 创建 session：POST /sessions
+#[utoipa::path(
+    tag = "sessions",
+    request_body = CreateSessionRequest,
+    responses(
+        (status = 200, body = SessionResponse),
+        (status = 404, description = "model not found", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/sessions", data = "<req>")]
+pub async fn create_session(
+    state: &State<Arc<AppState>>,
+    key: ApiKey,
+    req: Json<CreateSessionRequest>,
+) -> Result<Json<SessionResponse>, ApiError> {
+    let mut req = req.into_inner();
+    req.model_name = key
+        .profile
+        .resolve_model(&req.model_name)
+        .map_err(profile_error)?;
+    let registry = &state.registry;
+    let known = req.model_name == AUTO_MODEL
+        || registry.get_model(&req.model_name).is_some()
+        || registry.get_router(&req.model_name).is_some();
+    if !known {
+        return Err(api_error(
+            Status::NotFound,
+            "model_not_found",
+            format!("model `{}` not found", req.model_name),
+        ));
+    }
+    let session = state.sessions.create(&req.model_name, req.options);
+    Ok(Json(session_response(session)))
+}
+
+#[utoipa::path(
+    tag = "sessions",
+    responses(
+        (status = 200, body = SessionResponse),
+        (status = 404, description = "session not found", body = ErrorResponse)
+    )
+)]
+#[get("/sessions/<id>")]
+pub async fn get_session(
+    state: &State<Arc<AppState>>,
+    id: &str,
+) -> Result<Json<SessionResponse>, ApiError> {
+    state
+        .sessions
+        .get(id)
+        .map(|s| Json(session_response(s)))
+        .ok_or_else(|| session_not_found(id))
+}
+
+/// 修改 session 的 system prompt 和采样默认值：PATCH /sessions/<id>，只覆盖给了的字段
+#[utoipa::path(
+    tag = "sessions",
+    request_body = GenerationDefaults,
+    responses(
+        (status = 200, body = SessionResponse),
+        (status = 404, description = "session not found", body = ErrorResponse)
+    )
+)]
+#[patch("/sessions/<id>", data = "<req>")]
+pub async fn update_session(
+    state: &State<Arc<AppState>>,
+    id: &str,
+    req: Json<GenerationDefaults>,
+) -> Result<Json<SessionResponse>, ApiError> {
+    state
+        .sessions
+        .update_defaults(id, req.into_inner())
+        .map(|s| Json(session_response(s)))
+        .ok_or_else(|| session_not_found(id))
+}
+
+/// 关闭 session，同时卸载绑定到它的 scratch 模型：DELETE /sessions/<id>
+#[utoipa::path(
+    tag = "sessions",
+    responses(
+        (status = 200, body = ScratchReleaseResponse),
+        (status = 404, description = "session not found", body = ErrorResponse)
+    )
+)]
+#[delete("/sessions/<id>")]
+pub async fn close_session(
+    state: &State<Arc<AppState>>,
+    id: &str,
+) -> Result<Json<ScratchReleaseResponse>, ApiError> {
+    state
+        .sessions
+        .remove(id)
+        .ok_or_else(|| session_not_found(id))?;
+    let released_models = state.release_scratch(&ScratchOwner::Session(id.to_string()));
+    Ok(Json(ScratchReleaseResponse { released_models }))
+}
+
+/// 查看 session 的长期记忆：GET /sessions/<id>/memory
+#[utoipa::path(
+    tag = "sessions",
+    responses(
+        (status = 200, body = SessionMemoryResponse),
+        (status = 404, description = "session not found", body = ErrorResponse)
+    )
+)]
+#[get("/sessions/<id>/memory")]
+pub async fn get_session_memory(
+    state: &State<Arc<AppState>>,
+    id: &str,
+) -> Result<Json<SessionMemoryResponse>, ApiError> {
+    let session = state
+        .sessions
+        .get(id)
+        .ok_or_else(|| session_not_found(id))?;
+    Ok(Json(memory_response(id, session.memory)))
+}
+
+/// 手动改写长期记忆：PUT /sessions/<id>/memory
+#[utoipa::path(
+    tag = "sessions",
+    request_body = UpdateSessionMemoryRequest,
+    responses(
+        (status = 200, body = SessionMemoryResponse),
+        (status = 413, description = "memory too large", body = ErrorResponse),
+        (status = 404, description = "session not found", body = ErrorResponse)
+    )
+)]
+#[put("/sessions/<id>/memory", data = "<req>")]
+pub async fn update_session_memory(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    id: &str,
+    req: Json<UpdateSessionMemoryRequest>,
+) -> Result<Json<SessionMemoryResponse>, ApiError> {
+    check_prompt_size(&req.content, config)?;
+    let memory = state
+        .sessions
+        .set_memory(id, &req.content)
+        .ok_or_else(|| session_not_found(id))?;
+    Ok(Json(memory_response(id, memory)))
+}
+
+/// 订阅 session 的实时输出：GET /sessions/<id>/stream。
+/// 正在生成时先补发已经输出的部分；session 关闭时流结束
+#[utoipa::path(
+    tag = "sessions",
+    responses(
+        (status = 200, description = "SSE stream of start / token / done / error events", body = String, content_type = "text/event-stream"),
+        (status = 404, description = "session not found", body = ErrorResponse)
+    )
+)]
+#[get("/sessions/<id>/stream")]
+pub async fn stream_session(
+    state: &State<Arc<AppState>>,
+    id: &str,
+    shutdown: Shutdown,
+) -> Result<EventStream![], ApiError> {
+    state
+        .sessions
+        .get(id)
+        .ok_or_else(|| session_not_found(id))?;
+    let subscription = state.sessions.live.subscribe(id);
+    Ok(live_events(
+        subscription,
+        false,
+        state.streaming.stats_interval(),
+        shutdown,
+    ))
+}
+
+/// 按事件更新速度统计：start 开始计时，done / error 时取出最终值
+fn track_rate(rate: &mut Option<ChunkRate>, event: &LiveEvent) -> Option<StreamStats> {
+    let now = Instant::now();
+    match event {
+        LiveEvent::Start { .. } => *rate = Some(ChunkRate::new(now)),
+        LiveEvent::Token { .. } => {
+            if let Some(rate) = rate {
+                rate.record(now, 1);
+            }
+        }
+        LiveEvent::Done { .. } | LiveEvent::Error { .. } => {
+            return rate.take().map(|mut rate| rate.snapshot(now));
+        }
+    }
+    None
+}
+
+/// 订阅转成 SSE；`once` 时收到这次生成的 done / error 就结束。
+/// 生成进行中时定时发 `event: stats`，结束前再发一次最终值
+fn live_events(
+    subscription: LiveSubscription,
+    once: bool,
+    stats_every: Option<Duration>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let LiveSubscription {
+        backlog,
+        mut receiver,
+    } = subscription;
+    EventStream! {
+        let mut rate = None;
+        let mut ticker = StatsTicker::new(stats_every);
+        for event in backlog {
+            track_rate(&mut rate, &event);
+            yield Event::json(&event).event(event.event_name());
+        }
+        loop {
+            select! {
+                msg = receiver.recv() => match msg {
+                    Ok(event) => {
+                        let finished = matches!(event, LiveEvent::Done { .. } | LiveEvent::Error { .. });
+                        if let Some(stats) = track_rate(&mut rate, &event) {
+                            if stats_every.is_some() {
+                                yield Event::json(&stats).event("stats");
+                            }
+                        }
+                        yield Event::json(&event).event(event.event_name());
+                        if once && finished {
+                            break;
+                        }
+                    }
+                    // 订阅者太慢，丢了一些 token
+                    Err(RecvError::Lagged(dropped)) => {
+                        yield Event::data(dropped.to_string()).event("gap");
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => {
+                    if let Some(rate) = &mut rate {
+                        yield Event::json(&rate.snapshot(Instant::now())).event("stats");
+                    }
+                }
+                _ = &mut shutdown => break,
+            }
+        }
+    }
+}
+
+fn generation_in_progress(id: &str) -> ApiError {
+    api_error(
+        Status::Conflict,
+        "generation_in_progress",
+        format!("session `{id}` is still answering the previous message"),
+    )
+}
+
+/// 发一条消息：POST /sessions/<id>/messages。`stream: true` 时以 SSE 返回，
+/// 生成在后台任务里跑完并写入历史，发送方断开也不影响其他订阅者
+#[utoipa::path(
+    tag = "sessions",
+    request_body = ChatMessageRequest,
+    responses(
+        (status = 200, body = ChatMessageResponse),
+        (status = 400, description = "invalid request", body = ErrorResponse),
+        (status = 404, description = "session not found", body = ErrorResponse),
+        (status = 409, description = "model not loaded or generation in progress", body = ErrorResponse),
+        (status = 500, description = "inference failed", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/sessions/<id>/messages", data = "<req>")]
+pub async fn send_message(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    key: ApiKey,
+    id: &str,
+    req: Json<ChatMessageRequest>,
+    shutdown: Shutdown,
+) -> Result<Either<Json<ChatMessageResponse>, EventStream![]>, ApiError> {
+    check_prompt_size(&req.content, config)?;
+    if req.render_debug && req.stream {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            "render_debug is only supported for non-streaming requests",
+        ));
+    }
+    let session = state
+        .sessions
+        .get(id)
+        .ok_or_else(|| session_not_found(id))?;
+
+    // 上下文窗口里要给生成留出空间
+    let context_window = match session.options.context_window {
+        Some(n) => n,
+        None => state
+            .registry
+            .get_model(&session.model_name)
+            .map_or(crate::model_registry::DEFAULT_CONTEXT_WINDOW, |m| {
+                m.context_window
+            }),
+    };
+    let defaults = &session.options.defaults;
+    let requested_tokens = req.max_tokens.or(defaults.max_tokens);
+    let default_tokens = if req.stream {
+        STREAM_MAX_TOKENS
+    } else {
+        COLLECT_MAX_TOKENS
+    };
+    let max_tokens = requested_tokens.unwrap_or(default_tokens);
+    let max_tokens = key
+        .profile
+        .max_tokens
+        .map_or(max_tokens, |cap| cap.min(max_tokens));
+    let sampling = req.sampling.or(defaults.sampling);
+    let budget = context_window.saturating_sub(max_tokens).max(1);
+    let session = state
+        .sessions
+        .fold_memory(id, &req.content, budget)
+        .ok_or_else(|| session_not_found(id))?;
+    let (prompt, compression) = session.build_prompt(&req.content, budget);
+
+    let pipeline = InferencePipeline::new(state.inner().clone());
+    let mut infer = InferRequest {
+        model_name: session.model_name.clone(),
+        prompt,
+        device: req.device,
+        mode: InferMode::default(),
+        input_ids: None,
+        return_token_ids: false,
+        max_tokens: requested_tokens,
+        sampling,
+        priority: Priority::default(),
+        trace: false,
+        auto_template: false,
+        private: false,
+        client: None,
+        validate: None,
+        tags: Default::default(),
+        request_id: None,
+        rate_client: None,
+    };
+    key.apply(&mut infer).map_err(profile_error)?;
+    let rendered_prompt = req.render_debug.then(|| infer.prompt.clone());
+
+    let live = &state.sessions.live;
+    if !live.begin(id, &req.content) {
+        return Err(generation_in_progress(id));
+    }
+    let failed = |e| {
+        let error = pipeline_error(e);
+        live.finish(
+            id,
+            LiveEvent::Error {
+                message: error.to_string(),
+            },
+        );
+        error
+    };
+
+    if req.stream {
+        let mut rx = pipeline.stream(&infer).await.map_err(failed)?;
+        let subscription = live.subscribe(id);
+        let stats_every = state.streaming.stats_interval();
+        let state = state.inner().clone();
+        let (id, message) = (id.to_string(), req.into_inner().content);
+        rocket::tokio::spawn(async move {
+            let live = &state.sessions.live;
+            let mut words = Vec::new();
+            while let Some(chunk) = rx.recv().await {
+                match chunk {
+                    StreamChunk::Text(text) => {
+                        live.token(&id, &text);
+                        words.push(text);
+                    }
+                    StreamChunk::Gap { .. } | StreamChunk::Usage(_) => {}
+                    StreamChunk::Error(message) => {
+                        live.finish(&id, LiveEvent::Error { message });
+                        return;
+                    }
+                }
+            }
+            // 引擎按词推送，拼回去时补空格
+            let reply = words.join(" ");
+            state.sessions.record_exchange(&id, &message, &reply);
+            live.finish(&id, LiveEvent::Done { reply });
+        });
+        return Ok(Either::Right(live_events(
+            subscription,
+            true,
+            stats_every,
+            shutdown,
+        )));
+    }
+
+    let done = pipeline.collect(&infer).await.map_err(failed)?;
+    state
+        .sessions
+        .record_exchange(id, &req.content, &done.output);
+    live.finish(
+        id,
+        LiveEvent::Done {
+            reply: done.output.clone(),
+        },
+    );
+
+    Ok(Either::Left(Json(ChatMessageResponse {
+        session_id: session.id,
+        model_name: session.model_name,
+        reply: done.output,
+        served_by: done.served_by,
+        finish_reason: done.finish_reason,
+        compression,
+        rendered_prompt,
+    })))
+}
@@ -0,0 +1,95 @@
+//! 对话模型的 prompt 模板：chat 微调过的模型直接喂裸文本，输出往往答非所问。
+//!
+//! 模型在 manifest 里用 `chat_template = "mistral" | "chatml" | "llama3" | "zephyr"` 声明模板；
+//! 请求带 `auto_template: true` 且 prompt 里没有任何模板标记时，pipeline 按模型的模板包一层，
+//! 非流式响应的 `applied_template` 说明用了哪个模板。已经带标记的 prompt 原样发给引擎。
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatTemplate {
+    /// `[INST] ... [/INST]`（Mistral / Llama 2 instruct）
+    Mistral,
+    /// `<|im_start|>user ... <|im_end|>`（Qwen、OpenHermes 等）
+    #[serde(rename = "chatml")]
+    ChatMl,
+    /// `<|start_header_id|>user<|end_header_id|> ...`
+    Llama3,
+    /// `<|user|> ... </s> <|assistant|>`
+    Zephyr,
+}
+
+impl ChatTemplate {
+    const ALL: [ChatTemplate; 4] = [Self::Mistral, Self::ChatMl, Self::Llama3, Self::Zephyr];
+
+    /// 出现任何一个就认为 prompt 已经套过模板
+    fn markers(self) -> &'static [&'static str] {
+        match self {
+            Self::Mistral => &["[INST]", "[/INST]"],
+            Self::ChatMl => &["<|im_start|>", "<|im_end|>"],
+            Self::Llama3 => &["<|start_header_id|>", "<|eot_id|>"],
+            Self::Zephyr => &["<|user|>", "<|assistant|>", "<|system|>"],
+        }
+    }
+
+    /// 把一条用户消息包成这个模板，末尾留给模型接着写回答
+    pub fn wrap(self, prompt: &str) -> String {
+        match self {
+            Self::Mistral => format!("[INST] {prompt} [/INST]"),
+            Self::ChatMl => {
+                format!("<|im_start|>user\n{prompt}<|im_end|>\n<|im_start|>assistant\n")
+            }
+            Self::Llama3 => format!(
+                "<|start_header_id|>user<|end_header_id|>\n\n{prompt}<|eot_id|>\
+                 <|start_header_id|>assistant<|end_header_id|>\n\n"
+            ),
+            Self::Zephyr => format!("<|user|>\n{prompt}</s>\n<|assistant|>\n"),
+        }
+    }
+}
+
+/// prompt 里是否已经有某个模板的标记（不限于模型自己的模板）
+pub fn has_markers(prompt: &str) -> bool {
+    ChatTemplate::ALL
+        .iter()
+        .flat_map(|t| t.markers())
+        .any(|marker| prompt.contains(marker))
+}
+
+/// 需要时套上模板，返回新的 prompt 和用了的模板；模型没有模板或 prompt 已经有标记时原样返回
+pub fn apply(template: Option<ChatTemplate>, prompt: &str) -> (String, Option<ChatTemplate>) {
+    match template {
+        Some(template) if !has_markers(prompt) => (template.wrap(prompt), Some(template)),
+        _ => (prompt.to_string(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_prompts_are_wrapped_once() {
+        let (prompt, applied) = apply(Some(ChatTemplate::ChatMl), "hi");
+        assert_eq!(
+            prompt,
+            "<|im_start|>user\nhi<|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert_eq!(applied, Some(ChatTemplate::ChatMl));
+
+        // 已经套过（哪怕是别的模板）就不再动
+        let wrapped = ChatTemplate::Mistral.wrap("hi");
+        assert_eq!(apply(Some(ChatTemplate::ChatMl), &wrapped), (wrapped, None));
+        assert_eq!(apply(None, "hi"), ("hi".to_string(), None));
+    }
+
+    #[test]
+    fn every_template_is_detected_by_its_own_markers() {
+        for template in ChatTemplate::ALL {
+            assert!(has_markers(&template.wrap("hi")), "{template:?}");
+        }
+        assert!(!has_markers("user: hi\nassistant:"));
+    }
+}
@@ -0,0 +1,185 @@
+//! 命令行参数：同一台机器上跑多个实例时，端口、配置文件、并发数等不用改代码。
+//!
+//! 每个参数也可以用环境变量（`LOCAL_LLM_*`）给出。优先级从低到高：
+//! 配置文件（`--config`，默认 `Rocket.toml`）→ `ROCKET_*` 环境变量 → 命令行参数。
+//!
+//! ```text
+//! local-llm-server --config /etc/llm/a.toml --port 8001 --max-concurrent-infer 4
+//! LOCAL_LLM_PORT=8002 LOCAL_LLM_MODELS_DIR=/data/llm-b local-llm-server
+//! ```
+
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use clap::Parser;
+use rocket::figment::providers::{Env, Format, Serialized, Toml};
+use rocket::figment::{Figment, Profile};
+
+use crate::logging::LogFormat;
+
+#[derive(Debug, Clone, Default, Parser)]
+#[command(
+    name = "local-llm-server",
+    version,
+    about = "Local LLM inference server"
+)]
+pub struct Cli {
+    /// Config file in Rocket.toml format [default: Rocket.toml, or $ROCKET_CONFIG]
+    #[arg(short, long, env = "LOCAL_LLM_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Address to bind to
+    #[arg(short, long, env = "LOCAL_LLM_ADDRESS")]
+    pub address: Option<IpAddr>,
+    /// Port to listen on
+    #[arg(short, long, env = "LOCAL_LLM_PORT")]
+    pub port: Option<u16>,
+    /// Maximum number of inference requests running at once
+    #[arg(long, env = "LOCAL_LLM_MAX_CONCURRENT_INFER")]
+    pub max_concurrent_infer: Option<usize>,
+    /// Directory of the web frontend
+    #[arg(long, env = "LOCAL_LLM_STATIC_DIR")]
+    pub static_dir: Option<PathBuf>,
+    /// Models directory: local GGUF files in it are discovered, downloads are cached in <DIR>/hub
+    /// (same as $HF_HOME); overrides `discovery.dir`
+    #[arg(long, env = "LOCAL_LLM_MODELS_DIR")]
+    pub models_dir: Option<PathBuf>,
+    /// Never contact the Hugging Face Hub; only use local or already cached model files
+    #[arg(long, env = "LOCAL_LLM_OFFLINE")]
+    pub offline: bool,
+    /// Log format; `json` writes one JSON object per line [default: text]
+    #[arg(long, env = "LOCAL_LLM_LOG_FORMAT", value_enum)]
+    pub log_format: Option<LogFormat>,
+}
+
+impl Cli {
+    /// 同 `rocket::Config::figment`，但配置文件可以用 `--config` 指定，最后叠加命令行参数
+    pub fn figment(&self) -> Figment {
+        let file = match &self.config {
+            Some(path) => path.clone(),
+            None => PathBuf::from(Env::var_or("ROCKET_CONFIG", "Rocket.toml")),
+        };
+        let mut figment = Figment::from(rocket::Config::default())
+            .merge(Toml::file(file).nested())
+            .merge(Env::prefixed("ROCKET_").ignore(&["PROFILE"]).global())
+            .select(Profile::from_env_or(
+                "ROCKET_PROFILE",
+                rocket::Config::DEFAULT_PROFILE,
+            ));
+        if let Some(address) = self.address {
+            figment = figment.merge(Serialized::global("address", address));
+        }
+        if let Some(port) = self.port {
+            figment = figment.merge(Serialized::global("port", port));
+        }
+        if let Some(n) = self.max_concurrent_infer {
+            figment = figment.merge(Serialized::global("max_concurrent_infer", n));
+        }
+        if let Some(dir) = &self.static_dir {
+            figment = figment.merge(Serialized::global("static_dir", dir));
+        }
+        // 下载缓存（`apply_env`）和自动发现用同一个目录
+        if let Some(dir) = &self.models_dir {
+            figment = figment.merge(Serialized::global("discovery.dir", dir));
+        }
+        // 只能打开，不带 `--offline` 时保留配置文件里的值
+        if self.offline {
+            figment = figment.merge(Serialized::global("offline", true));
+        }
+        if let Some(format) = self.log_format {
+            figment = figment.merge(Serialized::global("log_format", format));
+        }
+        figment
+    }
+
+    /// 让 hf-hub 把模型下载到 `--models-dir`（`<DIR>/hub`）；要在启动 runtime 之前调用
+    pub fn apply_env(&self) {
+        if let Some(dir) = &self.models_dir {
+            std::env::set_var("HF_HOME", dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("server.toml");
+        std::fs::write(
+            &file,
+            "[default]\nport = 9000\nstatic_dir = \"web\"\nmax_concurrent_infer = 3\n",
+        )
+        .unwrap();
+        let config = file.to_str().unwrap();
+
+        let cli = Cli::try_parse_from(["local-llm-server", "--config", config]).unwrap();
+        let rocket: rocket::Config = cli.figment().extract().unwrap();
+        let server: ServerConfig = cli.figment().extract().unwrap();
+        assert_eq!(rocket.port, 9000);
+        assert_eq!(server.static_dir, PathBuf::from("web"));
+        assert_eq!(server.max_concurrent_infer, 3);
+        assert!(!server.offline);
+        assert_eq!(server.log_format, LogFormat::Text);
+
+        let cli = Cli::try_parse_from([
+            "local-llm-server",
+            "--config",
+            config,
+            "--port",
+            "9100",
+            "--address",
+            "0.0.0.0",
+            "--max-concurrent-infer",
+            "8",
+            "--offline",
+            "--log-format",
+            "json",
+        ])
+        .unwrap();
+        let rocket: rocket::Config = cli.figment().extract().unwrap();
+        let server: ServerConfig = cli.figment().extract().unwrap();
+        assert_eq!(rocket.port, 9100);
+        assert_eq!(rocket.address.to_string(), "0.0.0.0");
+        assert_eq!(server.static_dir, PathBuf::from("web"));
+        assert_eq!(server.max_concurrent_infer, 8);
+        assert!(server.offline);
+        assert_eq!(server.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn models_dir_is_also_the_discovery_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("server.toml");
+        std::fs::write(
+            &file,
+            "[default.discovery]\ndir = \"scan\"\non_startup = false\n",
+        )
+        .unwrap();
+        let config = file.to_str().unwrap();
+
+        let cli = Cli::try_parse_from(["local-llm-server", "--config", config]).unwrap();
+        let server: ServerConfig = cli.figment().extract().unwrap();
+        assert_eq!(server.discovery.dir, PathBuf::from("scan"));
+
+        let cli = Cli::try_parse_from([
+            "local-llm-server",
+            "--config",
+            config,
+            "--models-dir",
+            "/data/llm",
+        ])
+        .unwrap();
+        let server: ServerConfig = cli.figment().extract().unwrap();
+        assert_eq!(server.discovery.dir, PathBuf::from("/data/llm"));
+        assert!(!server.discovery.on_startup);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        assert!(Cli::try_parse_from(["local-llm-server", "--port", "http"]).is_err());
+        assert!(Cli::try_parse_from(["local-llm-server", "--address", "localhost:80"]).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! 响应压缩 fairing：按 Accept-Encoding 协商 gzip / deflate
+//!
+//! 只压缩大小已知的非流式响应（模型列表、批量结果等）；SSE 是逐事件 flush 的，
+//! 压缩会让浏览器攒到缓冲区满才看到 token，所以保持原样。
+
+use std::io::{Cursor, Write};
+
+use flate2::write::{DeflateEncoder, GzEncoder};
+use flate2::Compression as Level;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{ContentType, Header};
+use rocket::{Request, Response};
+
+use crate::config::ServerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn encode(self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Gzip => {
+                let mut enc = GzEncoder::new(Vec::new(), Level::default());
+                enc.write_all(data)?;
+                enc.finish()
+            }
+            Encoding::Deflate => {
+                let mut enc = DeflateEncoder::new(Vec::new(), Level::default());
+                enc.write_all(data)?;
+                enc.finish()
+            }
+        }
+    }
+}
+
+/// 解析 Accept-Encoding，返回客户端可接受的首选编码（gzip 优先）。
+/// `*` 只代表没有单独列出的编码，`gzip;q=0, *` 不会选 gzip
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let entries: Vec<(String, f32)> = accept_encoding
+        .split(',')
+        .map(|part| {
+            let mut pieces = part.trim().split(';');
+            let name = pieces.next().unwrap_or("").trim().to_ascii_lowercase();
+            let q = pieces
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (name, q)
+        })
+        .collect();
+    let listed = |enc: Encoding| {
+        entries.iter().any(|(name, _)| match enc {
+            Encoding::Gzip => name == "gzip" || name == "x-gzip",
+            Encoding::Deflate => name == "deflate",
+        })
+    };
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for (name, q) in &entries {
+        let q = *q;
+        if q <= 0.0 {
+            continue;
+        }
+
+        let candidates: &[Encoding] = match name.as_str() {
+            "gzip" | "x-gzip" => &[Encoding::Gzip],
+            "deflate" => &[Encoding::Deflate],
+            "*" => &[Encoding::Gzip, Encoding::Deflate],
+            _ => &[],
+        };
+        for &enc in candidates {
+            if name == "*" && listed(enc) {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((cur, cur_q)) => {
+                    q > cur_q || (q == cur_q && cur != Encoding::Gzip && enc == Encoding::Gzip)
+                }
+            };
+            if better {
+                best = Some((enc, q));
+            }
+        }
+    }
+    best.map(|(enc, _)| enc)
+}
+
+pub struct Compression;
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let min_bytes = match req.rocket().state::<ServerConfig>() {
+            Some(config) if config.compression => config.compression_min_bytes,
+            _ => return,
+        };
+
+        if res.content_type() == Some(ContentType::EventStream)
+            || res.headers().contains("Content-Encoding")
+        {
+            return;
+        }
+        // 流式 body（大小未知）一律不动
+        match res.body().preset_size() {
+            Some(size) if size >= min_bytes => {}
+            _ => return,
+        }
+
+        let encoding = match req.headers().get_one("Accept-Encoding").and_then(negotiate) {
+            Some(enc) => enc,
+            None => return,
+        };
+
+        let body = match res.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(_) => return,
+        };
+        match encoding.encode(&body) {
+            Ok(compressed) => {
+                res.set_header(Header::new("Content-Encoding", encoding.as_str()));
+                res.set_sized_body(compressed.len(), Cursor::new(compressed));
+            }
+            Err(_) => res.set_sized_body(body.len(), Cursor::new(body)),
+        }
+        res.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_prefers_gzip_and_respects_q() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, gzip;q=0.5"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("gzip;q=0, deflate"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br, identity"), None);
+    }
+
+    #[test]
+    fn wildcard_skips_codings_listed_explicitly() {
+        assert_eq!(negotiate("gzip;q=0, *"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*, gzip;q=0"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("gzip;q=0, deflate;q=0, *"), None);
+        assert_eq!(negotiate("gzip;q=0.5, *"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("*;q=0.5, gzip"), Some(Encoding::Gzip));
+    }
+}
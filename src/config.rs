@@ -0,0 +1,227 @@
+//! 服务端配置：从 Rocket 的 figment 读取（`Rocket.toml` 或 `ROCKET_*` 环境变量），
+//! 常用的几项也可以用命令行参数覆盖，见 `cli`
+//!
+//! 例如：
+//! ```toml
+//! [default]
+//! max_concurrent_infer = 10      # 同时进行的推理请求数
+//! static_dir = "static"
+//! serve_static = true
+//! spa_fallback = false
+//! max_prompt_bytes = 65536
+//! compression = true
+//! compression_min_bytes = 1024
+//! model_cache_dir = "/data/hf-cache/hub"   # 不填则用 hf-hub 默认缓存目录
+//! model_manifest = "/opt/models/models.toml" # registry 的模型列表，不填用内置的 models.toml
+//! model_catalog = "https://example.com/catalog.toml" # `GET /catalog` 的来源，不填用内置目录
+//! privacy = true                 # 日志里不出现 prompt / 输出原文，也可以按 API key 开启
+//! perf_history = "/var/lib/llm/perf.jsonl" # 每个模型的延迟 / 速度样本，不填则重启后清空
+//! offline = true                 # 不访问 hub：candle 只用本地 / 已缓存的文件（也可以用 `--offline`）
+//! loader_threads = 1             # 同时加载几个模型，加载线程优先级低于推理
+//! log_format = "json"            # 日志每行一个 JSON 对象（默认 "text"），见 `logging`
+//! require_api_key = true         # 推理、模型管理和 /admin 必须带 `api_keys` 里的 key，见 `api_keys`
+//!
+//! [default.access]             # 客户端地址限制，见 `access`
+//! localhost_only = true         # 只监听 127.0.0.1，拒绝非本机连接
+//! allow_ips = ["192.168.1.0/24"]
+//!
+//! [default.queue]              # permit 都被占用时的请求队列，见 `queue`
+//! max_len = 64                  # 最多几个请求排队，满了返回 429 `queue_full`，不填表示不限
+//! max_wait_ms = 30000           # 最多排多久，超时返回 503 `queue_timeout`，0 表示不限（默认 5 分钟）
+//!
+//! [default.stream]             # 流式输出缓冲，见 `pipeline::StreamConfig`
+//! capacity = 32
+//! overflow = "drop_oldest"      # block / drop_oldest / abort
+//! idle_timeout_ms = 60000       # 客户端多久不读就结束这条流并释放 permit，0 表示不限
+//! max_duration_ms = 600000      # 一条流最长持续多久，0（默认）表示不限
+//! stats_interval_ms = 1000      # SSE 里 `event: stats`（chunk 数和速度）的间隔，0 表示不发
+//!
+//! [default.kv_budget]          # 进行中请求的 KV cache 合计上限，见 `kv_budget`
+//! soft_limit_mb = 4096
+//! queue_timeout_ms = 2000       # 放不下时最多等这么久，仍然放不下返回 503
+//!
+//! [default.rate_limit]         # 每个 API key / 客户端 IP 的限流，超出时 429 + Retry-After，见 `rate_limit`
+//! requests_per_minute = 60
+//! tokens_per_minute = 20000
+//!
+//! [default.tags]               # 哪些请求标签作为 /metrics 的标签，每个键最多记 max_values 个值，见 `tags`
+//! metric_keys = ["app", "feature"]
+//! max_values = 20
+//!
+//! [default.trash]              # 删除的模型保留多久才硬删除，硬删除时是否删权重文件，见 `trash`
+//! retention_secs = 604800
+//! purge_weights = false
+//!
+//! [default.time_slice]         # 显存只够放权重的 GPU 上各模型轮流生成，见 `time_slice`
+//! devices = ["cuda:0"]
+//! turn_ms = 2000
+//!
+//! [default.standby]            # 主模型的热备实例，重新加载 / 出错时接管，见 `standby`
+//! model = "mistral-7b"
+//! device = "cuda:1"
+//!
+//! [default.self_test]          # 加载后先跑几条内置 prompt，没通过就不上线，见 `self_test`
+//! enabled = true
+//! max_tokens = 32
+//! max_latency_ms = 30000
+//!
+//! [default.discovery]          # 自动注册目录里的 GGUF，见 `discovery`
+//! dir = "models"               # `--models-dir` 会覆盖这里
+//! on_startup = true
+//!
+//! [default.embedding_cache]    # 按（模型，文本哈希）缓存 embedding，见 `embedding_cache`
+//! max_entries = 10000
+//! dir = "/var/lib/llm/embeddings" # 不填则只在内存里
+//!
+//! [default.rag.docs]           # embedding + 对话模型组成的 RAG profile，见 `rag`
+//! embedding_model = "minilm"
+//! chat_model = "mistral-7b"
+//! top_k = 3
+//! keyword_weight = 0.3           # BM25 关键词得分的权重，0 为纯向量检索
+//!
+//! [[default.tools.register]]    # assistant 循环的工具，见 `tools`
+//! name = "weather"
+//! kind = "http_fetch"
+//! allow_hosts = ["api.weather.gov"]
+//!
+//! [default.api_keys.sk-team-a]   # 每个 API key 的 profile，见 `api_keys` 模块
+//! default_model = "mistral-7b"
+//!
+//! [default.limits]
+//! json = "1 MiB"   # 请求体上限，由 Rocket 自身的 limits 控制
+//! ```
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::access::AccessConfig;
+use crate::api_keys::ApiKeyProfile;
+use crate::discovery::DiscoveryConfig;
+use crate::embedding_cache::EmbeddingCacheConfig;
+use crate::kv_budget::KvBudgetConfig;
+use crate::logging::LogFormat;
+use crate::pipeline::StreamConfig;
+use crate::queue::QueueConfig;
+use crate::rag::RagProfileConfig;
+use crate::rate_limit::RateLimitConfig;
+use crate::self_test::SelfTestConfig;
+use crate::standby::StandbyConfig;
+use crate::tags::TagsConfig;
+use crate::time_slice::TimeSliceConfig;
+use crate::tools::ToolsConfig;
+use crate::trash::TrashConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// 同时进行的推理请求数（semaphore permit 数）
+    pub max_concurrent_infer: usize,
+    /// 等 permit 的请求队列的长度和等待时间上限
+    pub queue: QueueConfig,
+    /// 静态前端目录
+    pub static_dir: PathBuf,
+    /// false 时只提供 API，不挂载前端
+    pub serve_static: bool,
+    /// 找不到文件的 GET（浏览器访问）回退到 `index.html`，给前端路由用
+    pub spa_fallback: bool,
+    /// 单个 prompt 的最大字节数（UTF-8），超出返回 413
+    pub max_prompt_bytes: usize,
+    /// 是否对非流式响应做 gzip / deflate 压缩
+    pub compression: bool,
+    /// 小于这个字节数的响应不压缩
+    pub compression_min_bytes: usize,
+    /// GGUF 缓存目录（完整性扫描用），None 表示 hf-hub 默认位置
+    pub model_cache_dir: Option<PathBuf>,
+    /// 模型 manifest，None 表示内置的 `models.toml`
+    pub model_manifest: Option<PathBuf>,
+    /// 模型目录（本地路径或 http(s) 地址），None 表示内置的 `catalog.toml`
+    pub model_catalog: Option<String>,
+    /// 整个部署开启隐私模式，日志里 prompt / 输出只记哈希和长度
+    pub privacy: bool,
+    /// `GET /models/<name>/perf` 的样本文件，None 表示只保存在内存里
+    pub perf_history: Option<PathBuf>,
+    /// API key -> profile（默认模型、max_tokens 上限、模型白名单、强制 system prompt、隐私模式）
+    pub api_keys: HashMap<String, ApiKeyProfile>,
+    /// 推理、模型管理和 `/admin` 接口必须带 `api_keys` 里的 key，见 `api_keys::RequireApiKey`
+    pub require_api_key: bool,
+    /// 流式输出的 channel 容量、客户端读得慢时的处理方式，以及 idle / 总时长上限
+    pub stream: StreamConfig,
+    /// 只监听本机、客户端 IP 白名单
+    pub access: AccessConfig,
+    /// 运维注册的工具（HTTP 白名单、本地搜索、shell），以及各自的超时和输出上限
+    pub tools: ToolsConfig,
+    /// profile 名 -> embedding 模型 + 对话模型；`/v1/chat/completions` 可以直接用 profile 名
+    pub rag: HashMap<String, RagProfileConfig>,
+    /// 启动时 / `POST /models/rescan` 扫描哪个目录里的 GGUF
+    pub discovery: DiscoveryConfig,
+    /// embedding 向量缓存的条数上限和持久化目录
+    pub embedding_cache: EmbeddingCacheConfig,
+    /// 加载后的自检：非空输出、能输出结束符、耗时上限
+    pub self_test: SelfTestConfig,
+    /// 进行中请求的 KV cache 软上限，超出时拒绝 / 排队长上下文请求
+    pub kv_budget: KvBudgetConfig,
+    /// 离线部署：不访问 hub，模型文件必须是本地路径或已在缓存里
+    pub offline: bool,
+    /// 模型加载线程数（默认 1），见 `loader`
+    pub loader_threads: usize,
+    /// 日志格式：文本或 JSON Lines
+    pub log_format: LogFormat,
+    /// 主模型的热备实例
+    pub standby: StandbyConfig,
+    /// 按客户端限流
+    pub rate_limit: RateLimitConfig,
+    /// GPU 上的分时调度
+    pub time_slice: TimeSliceConfig,
+    /// 请求标签进 `/metrics` 的方式
+    pub tags: TagsConfig,
+    /// 软删除模型的保留期
+    pub trash: TrashConfig,
+}
+
+impl ServerConfig {
+    pub fn model_cache_dir(&self) -> PathBuf {
+        self.model_cache_dir
+            .clone()
+            .unwrap_or_else(|| hf_hub::Cache::default().path().clone())
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_infer: 10,
+            queue: QueueConfig::default(),
+            static_dir: PathBuf::from("static"),
+            serve_static: true,
+            spa_fallback: false,
+            max_prompt_bytes: 64 * 1024,
+            compression: true,
+            compression_min_bytes: 1024,
+            model_cache_dir: None,
+            model_manifest: None,
+            model_catalog: None,
+            privacy: false,
+            perf_history: None,
+            api_keys: HashMap::new(),
+            require_api_key: false,
+            stream: StreamConfig::default(),
+            access: AccessConfig::default(),
+            tools: ToolsConfig::default(),
+            rag: HashMap::new(),
+            discovery: DiscoveryConfig::default(),
+            embedding_cache: EmbeddingCacheConfig::default(),
+            self_test: SelfTestConfig::default(),
+            kv_budget: KvBudgetConfig::default(),
+            offline: false,
+            loader_threads: 1,
+            log_format: LogFormat::Text,
+            standby: StandbyConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            time_slice: TimeSliceConfig::default(),
+            tags: TagsConfig::default(),
+            trash: TrashConfig::default(),
+        }
+    }
+}
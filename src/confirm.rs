@@ -0,0 +1,164 @@
+//! 破坏性运维操作（unload / delete / evict）的两阶段确认
+//!
+//! 模型忙（有执行中或排队的请求）时，第一次调用不执行，而是返回影响评估和一个
+//! 确认 token；带着 token 再调用一次才真正执行。`force=true` 跳过确认。
+//! 避免不小心把同事正在跑的长批量任务打断。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// 确认 token 的有效期
+pub const CONFIRMATION_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminAction {
+    /// 卸载 engine，保留注册信息
+    Unload,
+    /// 卸载并从 registry 删除
+    Delete,
+    /// 清空 KV cache
+    Evict,
+}
+
+impl fmt::Display for AdminAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AdminAction::Unload => "unload",
+            AdminAction::Delete => "delete",
+            AdminAction::Evict => "evict",
+        };
+        f.write_str(s)
+    }
+}
+
+/// 操作会影响到的请求数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Impact {
+    /// 正在执行
+    pub in_flight: usize,
+    /// 已分配到模型、还在等并发 permit 或攒批
+    pub queued: usize,
+}
+
+impl Impact {
+    pub fn is_idle(&self) -> bool {
+        self.in_flight == 0 && self.queued == 0
+    }
+}
+
+/// 需要确认时返回给调用方的内容
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ConfirmationRequired {
+    pub action: AdminAction,
+    pub model_name: String,
+    pub impact: Impact,
+    /// 第二次调用时通过 `?confirm=` 带回来
+    pub confirm_token: String,
+    pub expires_in_secs: u64,
+}
+
+struct Pending {
+    action: AdminAction,
+    model_name: String,
+    expires_at: Instant,
+}
+
+/// 已发出、还没使用的确认 token
+pub struct ConfirmationStore {
+    pending: Mutex<HashMap<String, Pending>>,
+    next_id: AtomicU64,
+    ttl: Duration,
+}
+
+impl ConfirmationStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            ttl,
+        }
+    }
+
+    /// 为 (action, model) 发一个新 token，顺便清掉过期的
+    pub fn issue(
+        &self,
+        action: AdminAction,
+        model_name: &str,
+        impact: Impact,
+    ) -> ConfirmationRequired {
+        let now = Instant::now();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let nonce = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let token = format!("confirm-{id}-{nonce:08x}");
+
+        let mut pending = self.pending.lock();
+        pending.retain(|_, p| p.expires_at > now);
+        pending.insert(
+            token.clone(),
+            Pending {
+                action,
+                model_name: model_name.to_string(),
+                expires_at: now + self.ttl,
+            },
+        );
+
+        ConfirmationRequired {
+            action,
+            model_name: model_name.to_string(),
+            impact,
+            confirm_token: token,
+            expires_in_secs: self.ttl.as_secs(),
+        }
+    }
+
+    /// 消费 token：必须未过期，且是为同一个操作和模型发的。token 只能用一次
+    pub fn redeem(&self, token: &str, action: AdminAction, model_name: &str) -> bool {
+        let mut pending = self.pending.lock();
+        match pending.get(token) {
+            Some(p) if p.action == action && p.model_name == model_name => {
+                let valid = p.expires_at > Instant::now();
+                pending.remove(token);
+                valid
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for ConfirmationStore {
+    fn default() -> Self {
+        Self::new(CONFIRMATION_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_single_use_and_scoped() {
+        let store = ConfirmationStore::default();
+        let issued = store.issue(AdminAction::Unload, "m", Impact::default());
+
+        assert!(!store.redeem(&issued.confirm_token, AdminAction::Delete, "m"));
+        assert!(!store.redeem(&issued.confirm_token, AdminAction::Unload, "other"));
+        assert!(store.redeem(&issued.confirm_token, AdminAction::Unload, "m"));
+        assert!(!store.redeem(&issued.confirm_token, AdminAction::Unload, "m"));
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let store = ConfirmationStore::new(Duration::ZERO);
+        let issued = store.issue(AdminAction::Evict, "m", Impact::default());
+        assert!(!store.redeem(&issued.confirm_token, AdminAction::Evict, "m"));
+    }
+}
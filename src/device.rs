@@ -0,0 +1,118 @@
+//! 设备描述：`cpu` / `cuda:<n>` / `metal:<n>`
+//!
+//! registry 里每个模型有一组 placements（加载时每个设备各建一个 engine 实例），
+//! 请求可以用 `device` 指定跑在哪一个上。
+
+use std::fmt;
+use std::str::FromStr;
+
+use candle_core::Device;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::openapi::schema::{ObjectBuilder, Schema, SchemaType};
+use utoipa::openapi::RefOr;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DeviceSpec {
+    #[default]
+    Cpu,
+    Cuda(usize),
+    Metal(usize),
+}
+
+impl DeviceSpec {
+    /// 构造 candle 设备；没有编译对应后端时返回错误（CUDA 需要 `cuda` feature）
+    pub fn to_candle(self) -> candle_core::Result<Device> {
+        match self {
+            DeviceSpec::Cpu => Ok(Device::Cpu),
+            DeviceSpec::Cuda(n) if cfg!(feature = "cuda") => Device::new_cuda(n),
+            DeviceSpec::Cuda(_) => Err(candle_core::Error::Msg(format!(
+                "cannot use {self}: the server was built without the `cuda` feature \
+                 (rebuild with `cargo build --features cuda`)"
+            ))),
+            DeviceSpec::Metal(n) => Device::new_metal(n),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSpec::Cpu => f.write_str("cpu"),
+            DeviceSpec::Cuda(n) => write!(f, "cuda:{n}"),
+            DeviceSpec::Metal(n) => write!(f, "metal:{n}"),
+        }
+    }
+}
+
+impl FromStr for DeviceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase();
+        let (kind, ordinal) = match s.split_once(':') {
+            Some((kind, n)) => {
+                let n = n
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid device ordinal in `{s}`"))?;
+                (kind.to_string(), n)
+            }
+            None => (s.clone(), 0),
+        };
+        match kind.as_str() {
+            "cpu" => Ok(DeviceSpec::Cpu),
+            "cuda" | "gpu" => Ok(DeviceSpec::Cuda(ordinal)),
+            "metal" => Ok(DeviceSpec::Metal(ordinal)),
+            _ => Err(format!(
+                "unknown device `{s}` (expected cpu, cuda:<n> or metal:<n>)"
+            )),
+        }
+    }
+}
+
+impl Serialize for DeviceSpec {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DeviceSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// 在 OpenAPI 里和 serde 一样表示成字符串
+impl<'s> ToSchema<'s> for DeviceSpec {
+    fn schema() -> (&'s str, RefOr<Schema>) {
+        let schema = ObjectBuilder::new()
+            .schema_type(SchemaType::String)
+            .pattern(Some(r"^(cpu|cuda:\d+|metal:\d+)$"))
+            .example(Some("cuda:0".into()))
+            .build();
+        ("DeviceSpec", schema.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_displays_round_trip() {
+        for s in ["cpu", "cuda:0", "cuda:3", "metal:1"] {
+            assert_eq!(s.parse::<DeviceSpec>().unwrap().to_string(), s);
+        }
+        assert_eq!("CUDA".parse::<DeviceSpec>().unwrap(), DeviceSpec::Cuda(0));
+        assert!("tpu:0".parse::<DeviceSpec>().is_err());
+        assert!("cuda:x".parse::<DeviceSpec>().is_err());
+    }
+
+    #[cfg(not(feature = "cuda"))]
+    #[test]
+    fn cuda_without_feature_explains_how_to_enable_it() {
+        let err = DeviceSpec::Cuda(0).to_candle().unwrap_err().to_string();
+        assert!(err.contains("--features cuda"), "{err}");
+    }
+}
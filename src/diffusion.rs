@@ -0,0 +1,351 @@
+//! 文生图：Stable Diffusion 引擎（`engine_kind = "diffusion"`）和生成结果的暂存
+//!
+//! - 目前支持 SD 1.5 结构的权重（diffusers 目录布局），本地文件见 `DiffusionArtifacts`，
+//!   没有本地文件时从 hub 下载 `path` 指定的仓库（默认 SD 1.5）
+//! - 生成很慢，`POST /v1/images/generations` 以 job 形式运行，PNG 暂存在 `ImageStore` 里，
+//!   通过 `GET /v1/images/<job_id>/<index>` 取回
+//! - FLUX 等更新的结构 candle 0.4 还没有实现，需要时再加一个 `StableDiffusionConfig` 之外的分支
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use candle_core::{DType, Device, Module, Tensor};
+use candle_transformers::models::stable_diffusion::{
+    build_clip_transformer, clip::ClipTextTransformer, unet_2d::UNet2DConditionModel,
+    vae::AutoEncoderKL, StableDiffusionConfig,
+};
+use hf_hub::api::sync::Api;
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Distribution, StandardNormal};
+use rocket::tokio::sync::mpsc;
+use tokenizers::Tokenizer;
+use tracing::info;
+
+use crate::device::DeviceSpec;
+use crate::engine::InferenceEngine;
+use crate::model_registry::{DiffusionFiles, ModelMetadata};
+
+pub const DEFAULT_IMAGE_SIZE: usize = 512;
+pub const MAX_IMAGE_SIZE: usize = 1024;
+pub const DEFAULT_STEPS: usize = 30;
+pub const MAX_STEPS: usize = 150;
+pub const DEFAULT_GUIDANCE_SCALE: f64 = 7.5;
+/// 一次请求最多生成几张
+pub const MAX_IMAGES: usize = 4;
+/// `ImageStore` 最多保留多少个 job 的结果，超出后丢弃最早的
+pub const MAX_STORED_JOBS: usize = 32;
+
+/// 没有本地文件时默认下载的权重
+const DEFAULT_DIFFUSION_REPO: &str = "stable-diffusion-v1-5/stable-diffusion-v1-5";
+/// SD 1.5 用的 CLIP tokenizer
+const CLIP_TOKENIZER_REPO: &str = "openai/clip-vit-base-patch32";
+const PAD_TOKEN: &str = "<|endoftext|>";
+/// latent 空间和 VAE 之间的缩放系数（SD 1.5）
+const VAE_SCALE: f64 = 0.18215;
+
+/// 一次文生图的参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageParams {
+    pub width: usize,
+    pub height: usize,
+    pub steps: usize,
+    /// classifier-free guidance；<= 1 时不做 guidance
+    pub guidance_scale: f64,
+    /// 张数
+    pub n: usize,
+    /// 第 i 张用 `seed + i`，结果可复现
+    pub seed: u64,
+}
+
+impl Default for ImageParams {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_IMAGE_SIZE,
+            height: DEFAULT_IMAGE_SIZE,
+            steps: DEFAULT_STEPS,
+            guidance_scale: DEFAULT_GUIDANCE_SCALE,
+            n: 1,
+            seed: 0,
+        }
+    }
+}
+
+impl ImageParams {
+    /// 解析 OpenAI 风格的 `"512x768"`（宽 x 高）
+    pub fn parse_size(size: &str) -> Option<(usize, usize)> {
+        let (w, h) = size.split_once('x')?;
+        Some((w.trim().parse().ok()?, h.trim().parse().ok()?))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [("width", self.width), ("height", self.height)] {
+            if value == 0 || value > MAX_IMAGE_SIZE || value % 8 != 0 {
+                return Err(format!(
+                    "{name} must be a multiple of 8 between 8 and {MAX_IMAGE_SIZE}, got {value}"
+                ));
+            }
+        }
+        if self.steps == 0 || self.steps > MAX_STEPS {
+            return Err(format!("steps must be between 1 and {MAX_STEPS}"));
+        }
+        if !(0.0..=30.0).contains(&self.guidance_scale) {
+            return Err("guidance_scale must be between 0 and 30".to_string());
+        }
+        if self.n == 0 || self.n > MAX_IMAGES {
+            return Err(format!("n must be between 1 and {MAX_IMAGES}"));
+        }
+        Ok(())
+    }
+}
+
+/// RGB8 像素编码成 PNG
+pub fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(rgb)?;
+    Ok(out)
+}
+
+/// job id -> 生成的 PNG，只保留最近 `MAX_STORED_JOBS` 个 job
+#[derive(Default)]
+pub struct ImageStore {
+    inner: Mutex<StoredImages>,
+}
+
+#[derive(Default)]
+struct StoredImages {
+    images: HashMap<String, Vec<Vec<u8>>>,
+    order: VecDeque<String>,
+}
+
+impl ImageStore {
+    pub fn insert(&self, job_id: &str, images: Vec<Vec<u8>>) {
+        let mut inner = self.inner.lock();
+        if inner.images.insert(job_id.to_string(), images).is_none() {
+            inner.order.push_back(job_id.to_string());
+        }
+        while inner.order.len() > MAX_STORED_JOBS {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.images.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, job_id: &str, index: usize) -> Option<Vec<u8>> {
+        self.inner.lock().images.get(job_id)?.get(index).cloned()
+    }
+}
+
+/// candle 的 Stable Diffusion 1.5 实现
+pub struct DiffusionEngine {
+    model_name: String,
+    device: Device,
+    tokenizer: Tokenizer,
+    pad_id: u32,
+    max_prompt_tokens: usize,
+    clip: ClipTextTransformer,
+    unet: UNet2DConditionModel,
+    vae: AutoEncoderKL,
+}
+
+impl DiffusionEngine {
+    pub fn new(meta: &ModelMetadata, device: DeviceSpec) -> Result<Arc<Self>> {
+        let device = device.to_candle()?;
+        let files = match &meta.diffusion_artifacts {
+            Some(local) => local.verify()?,
+            None => Self::download(&meta.path)?,
+        };
+
+        // 尺寸在生成时按请求决定，这里只用它的网络结构
+        let config = StableDiffusionConfig::v1_5(None, None, None);
+        let tokenizer = Tokenizer::from_file(&files.tokenizer)
+            .map_err(|e| anyhow!("Error loading tokenizer: {e}"))?;
+        let pad_id = *tokenizer
+            .get_vocab(true)
+            .get(PAD_TOKEN)
+            .ok_or_else(|| anyhow!("tokenizer has no `{PAD_TOKEN}` token"))?;
+
+        let start = Instant::now();
+        let clip = build_clip_transformer(&config.clip, &files.clip, &device, DType::F32)?;
+        let unet = config.build_unet(&files.unet, &device, 4, false, DType::F32)?;
+        let vae = config.build_vae(&files.vae, &device, DType::F32)?;
+        info!(
+            model = %meta.name,
+            build_ms = start.elapsed().as_millis() as u64,
+            "diffusion pipeline built"
+        );
+
+        Ok(Arc::new(Self {
+            model_name: meta.name.clone(),
+            device,
+            tokenizer,
+            pad_id,
+            max_prompt_tokens: config.clip.max_position_embeddings,
+            clip,
+            unet,
+            vae,
+        }))
+    }
+
+    /// `repo` 为空时使用默认的 SD 1.5 仓库
+    fn download(repo: &str) -> Result<DiffusionFiles> {
+        let repo = if repo.is_empty() {
+            DEFAULT_DIFFUSION_REPO
+        } else {
+            repo
+        };
+        let api = Api::new()?;
+        let weights = api.model(repo.to_string());
+        Ok(DiffusionFiles {
+            tokenizer: api
+                .model(CLIP_TOKENIZER_REPO.to_string())
+                .get("tokenizer.json")?,
+            clip: weights.get("text_encoder/model.safetensors")?,
+            unet: weights.get("unet/diffusion_pytorch_model.safetensors")?,
+            vae: weights.get("vae/diffusion_pytorch_model.safetensors")?,
+        })
+    }
+
+    /// CLIP 文本 embedding，补齐 / 截断到固定长度
+    fn embed(&self, text: &str) -> Result<Tensor> {
+        let mut tokens = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(|e| anyhow!("Error encoding tokenizer: {e}"))?
+            .get_ids()
+            .to_vec();
+        tokens.resize(self.max_prompt_tokens, self.pad_id);
+        let tokens = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
+        Ok(self.clip.forward(&tokens)?)
+    }
+
+    /// 初始噪声在 CPU 上按 seed 生成（CPU 后端不支持 `set_seed`），各设备结果一致
+    fn initial_latents(&self, params: &ImageParams, seed: u64) -> Result<Tensor> {
+        let shape = (1, 4, params.height / 8, params.width / 8);
+        let len = 4 * (params.height / 8) * (params.width / 8);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let noise: Vec<f32> = (0..len).map(|_| StandardNormal.sample(&mut rng)).collect();
+        Ok(Tensor::from_vec(noise, shape, &Device::Cpu)?.to_device(&self.device)?)
+    }
+
+    fn generate_inner(
+        &self,
+        prompt: &str,
+        params: &ImageParams,
+        progress: &dyn Fn(f32),
+    ) -> Result<Vec<Vec<u8>>> {
+        let guided = params.guidance_scale > 1.0;
+        let text = self.embed(prompt)?;
+        let text = if guided {
+            Tensor::cat(&[self.embed("")?, text], 0)?
+        } else {
+            text
+        };
+
+        let config = StableDiffusionConfig::v1_5(None, None, None);
+        let total_steps = (params.n * params.steps) as f32;
+        let mut images = Vec::with_capacity(params.n);
+        for i in 0..params.n {
+            let scheduler = config.build_scheduler(params.steps)?;
+            let mut latents = (self.initial_latents(params, params.seed + i as u64)?
+                * scheduler.init_noise_sigma())?;
+
+            let timesteps = scheduler.timesteps().to_vec();
+            for (step, &timestep) in timesteps.iter().enumerate() {
+                let input = if guided {
+                    Tensor::cat(&[&latents, &latents], 0)?
+                } else {
+                    latents.clone()
+                };
+                let input = scheduler.scale_model_input(input, timestep)?;
+                let noise = self.unet.forward(&input, timestep as f64, &text)?;
+                let noise = if guided {
+                    let parts = noise.chunk(2, 0)?;
+                    (&parts[0] + ((&parts[1] - &parts[0])? * params.guidance_scale)?)?
+                } else {
+                    noise
+                };
+                latents = scheduler.step(&noise, timestep, &latents)?;
+                progress((i * params.steps + step + 1) as f32 / total_steps);
+            }
+
+            let image = self.vae.decode(&(&latents / VAE_SCALE)?)?;
+            let image = ((image / 2.)? + 0.5)?.to_device(&Device::Cpu)?;
+            let image = (image.clamp(0f32, 1.)? * 255.)?
+                .to_dtype(DType::U8)?
+                .get(0)?;
+            let (_, height, width) = image.dims3()?;
+            let rgb = image.permute((1, 2, 0))?.flatten_all()?.to_vec1::<u8>()?;
+            images.push(encode_png(width as u32, height as u32, &rgb)?);
+        }
+        Ok(images)
+    }
+}
+
+#[async_trait]
+impl InferenceEngine for DiffusionEngine {
+    async fn generate(&self, _prompt: &str, _max_tokens: usize) -> Result<String> {
+        anyhow::bail!("model `{}` generates images, not text", self.model_name)
+    }
+
+    async fn generate_stream(
+        &self,
+        _prompt: &str,
+        _max_tokens: usize,
+        _sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        anyhow::bail!("model `{}` generates images, not text", self.model_name)
+    }
+
+    fn generate_images(
+        &self,
+        prompt: &str,
+        params: &ImageParams,
+        progress: &dyn Fn(f32),
+    ) -> Result<Vec<Vec<u8>>> {
+        self.generate_inner(prompt, params, progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_are_validated() {
+        assert!(ImageParams::default().validate().is_ok());
+        assert_eq!(ImageParams::parse_size("512x768"), Some((512, 768)));
+        assert_eq!(ImageParams::parse_size("large"), None);
+
+        let odd = ImageParams {
+            width: 500,
+            ..Default::default()
+        };
+        assert!(odd.validate().unwrap_err().contains("width"));
+        let many = ImageParams {
+            n: MAX_IMAGES + 1,
+            ..Default::default()
+        };
+        assert!(many.validate().is_err());
+    }
+
+    #[test]
+    fn store_keeps_recent_jobs() {
+        let store = ImageStore::default();
+        let png = encode_png(1, 1, &[255, 0, 0]).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+
+        for i in 0..=MAX_STORED_JOBS {
+            store.insert(&format!("job-{i}"), vec![png.clone()]);
+        }
+        assert!(store.get("job-0", 0).is_none());
+        assert_eq!(store.get(&format!("job-{MAX_STORED_JOBS}"), 0), Some(png));
+        assert!(store.get("job-1", 1).is_none());
+    }
+}
@@ -0,0 +1,372 @@
+//! 自动发现本地 GGUF：启动时（`discovery.on_startup`）和 `POST /models/rescan` 时扫描
+//! `discovery.dir`（默认 `./models/`，含子目录）下的 `*.gguf`，读 GGUF 头后注册到 registry。
+//!
+//! - 名字取文件名去掉量化后缀，例如 `mistral-7b-instruct-v0.1.Q4_K_M.gguf` -> `mistral-7b-instruct-v0.1`；
+//!   和已有模型重名时加上量化后缀，仍然重名就跳过
+//! - 量化取 `general.file_type`，没有时从文件名里取；上下文窗口取 `<arch>.context_length`
+//! - 只注册 candle 能加载的架构（llama 系，包括 Mistral）；tokenizer 用同目录下的 `tokenizer.json`
+//! - 已经在 registry 里的文件（例如 manifest 里写了的）不重复注册
+//! - 之前发现的文件被删掉后，重新扫描时把未加载的条目移除
+//! - 不进 hf-hub 缓存目录：`--models-dir` 同时是下载缓存的 `HF_HOME`，缓存里的文件归 hub 模型管
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use candle_core::quantized::gguf_file;
+use parking_lot::Mutex;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::AppState;
+use crate::model_registry::{
+    EngineKind, LocalArtifacts, ModelMetadata, ModelStatus, DEFAULT_CONTEXT_WINDOW,
+};
+
+/// candle 引擎（`quantized_llama`）能加载的 `general.architecture`
+const SUPPORTED_ARCHITECTURES: &[&str] = &["llama"];
+
+/// `ServerConfig.discovery`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    pub dir: PathBuf,
+    /// 启动时扫描一次
+    pub on_startup: bool,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("models"),
+            on_startup: true,
+        }
+    }
+}
+
+/// 扫描到并注册的一个模型
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DiscoveredModel {
+    pub name: String,
+    pub path: String,
+    pub architecture: String,
+    pub quantization: String,
+    pub context_window: usize,
+}
+
+/// 没有注册的文件及原因
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct RescanResponse {
+    pub dir: String,
+    pub added: Vec<DiscoveredModel>,
+    /// 文件已不存在、被移出 registry 的模型
+    pub removed: Vec<String>,
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// 扫描配置，以及之前发现的模型（名字 -> 文件）
+pub struct ModelDiscovery {
+    pub config: DiscoveryConfig,
+    found: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl ModelDiscovery {
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            config,
+            found: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// 文件名里的量化后缀（`.Q4_K_M`、`-q8_0`、`.f16` 等），返回（去掉后缀的部分，小写的后缀）
+fn split_quant_suffix(stem: &str) -> (&str, Option<String>) {
+    let is_quant = |s: &str| {
+        let s = s.to_ascii_lowercase();
+        let digit_after = |prefix: &str| {
+            s.strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        };
+        digit_after("q") || digit_after("iq") || matches!(s.as_str(), "f16" | "f32" | "bf16")
+    };
+    match stem.rfind(['.', '-']) {
+        Some(at) if at > 0 && is_quant(&stem[at + 1..]) => {
+            (&stem[..at], Some(stem[at + 1..].to_ascii_lowercase()))
+        }
+        _ => (stem, None),
+    }
+}
+
+/// 模型名只保留小写字母、数字、`.`、`-`
+fn slug(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9' | '.') => out.push(c),
+            _ if !out.is_empty() && !out.ends_with('-') => out.push('-'),
+            _ => {}
+        }
+    }
+    out.trim_end_matches('-').to_string()
+}
+
+/// llama.cpp 的 `general.file_type`
+fn file_type_name(file_type: u32) -> Option<&'static str> {
+    Some(match file_type {
+        0 => "f32",
+        1 => "f16",
+        2 => "q4_0",
+        3 => "q4_1",
+        7 => "q8_0",
+        8 => "q5_0",
+        9 => "q5_1",
+        10 => "q2_k",
+        11 => "q3_k_s",
+        12 => "q3_k_m",
+        13 => "q3_k_l",
+        14 => "q4_k_s",
+        15 => "q4_k_m",
+        16 => "q5_k_s",
+        17 => "q5_k_m",
+        18 => "q6_k",
+        _ => return None,
+    })
+}
+
+/// 读 GGUF 头，得到还没决定名字的注册信息
+fn inspect(path: &Path) -> Result<DiscoveredModel, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let content =
+        gguf_file::Content::read(&mut file).map_err(|e| format!("not a valid GGUF file: {e}"))?;
+    let get = |key: &str| content.metadata.get(key);
+    let architecture = get("general.architecture")
+        .and_then(|v| v.to_string().ok())
+        .cloned()
+        .ok_or("GGUF has no `general.architecture`")?;
+    if !SUPPORTED_ARCHITECTURES.contains(&architecture.as_str()) {
+        return Err(format!("architecture `{architecture}` is not supported"));
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (base, suffix) = split_quant_suffix(&stem);
+    let quantization = get("general.file_type")
+        .and_then(|v| v.to_u32().ok())
+        .and_then(file_type_name)
+        .map(str::to_string)
+        .or(suffix)
+        .unwrap_or_else(|| "unknown".to_string());
+    let context_window = get(&format!("{architecture}.context_length"))
+        .and_then(|v| v.to_u32().ok())
+        .map_or(DEFAULT_CONTEXT_WINDOW, |n| n as usize);
+    let mut name = slug(base);
+    if name.is_empty() {
+        name = get("general.name")
+            .and_then(|v| v.to_string().ok())
+            .map(|s| slug(s))
+            .unwrap_or_default();
+    }
+    if name.is_empty() {
+        return Err("cannot derive a model name".to_string());
+    }
+    Ok(DiscoveredModel {
+        name,
+        path: path.display().to_string(),
+        architecture,
+        quantization,
+        context_window,
+    })
+}
+
+/// 递归收集 `*.gguf`，跳过 `skip`（hf-hub 缓存）
+fn collect_gguf_files(dir: &Path, skip: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if !same_file(&path, skip) {
+                collect_gguf_files(&path, skip, out)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "gguf") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// 扫描目录，注册新文件、移除已删除的文件；目录不存在时什么都不做
+pub fn rescan(state: &AppState) -> std::io::Result<RescanResponse> {
+    let dir = &state.discovery.config.dir;
+    let mut report = RescanResponse {
+        dir: dir.display().to_string(),
+        ..Default::default()
+    };
+    let mut files = Vec::new();
+    if dir.is_dir() {
+        let hub_cache = hf_hub::Cache::default().path().clone();
+        collect_gguf_files(dir, &hub_cache, &mut files)?;
+    }
+    files.sort();
+
+    let mut found = state.discovery.found.lock();
+    // 文件没了：未加载的条目移出 registry，加载着的保留到卸载为止
+    found.retain(|name, path| {
+        if path.is_file() {
+            return true;
+        }
+        let Some(meta) = state.registry.get_model(name) else {
+            return false;
+        };
+        let ours = meta
+            .artifacts
+            .as_ref()
+            .is_some_and(|a| a.gguf.as_path() == path.as_path());
+        if !ours {
+            return false;
+        }
+        if matches!(meta.status, ModelStatus::Loading | ModelStatus::Loaded) {
+            return true;
+        }
+        state.registry.unregister(name);
+        report.removed.push(name.clone());
+        false
+    });
+
+    let registered: Vec<PathBuf> = state
+        .registry
+        .list_models()
+        .into_iter()
+        .filter_map(|m| m.artifacts.map(|a| a.gguf))
+        .collect();
+    for path in files {
+        if registered.iter().any(|known| same_file(known, &path)) {
+            continue;
+        }
+        let mut model = match inspect(&path) {
+            Ok(model) => model,
+            Err(reason) => {
+                report.skipped.push(SkippedFile {
+                    path: path.display().to_string(),
+                    reason,
+                });
+                continue;
+            }
+        };
+        let taken = |name: &str| {
+            state.registry.get_model(name).is_some() || state.registry.get_router(name).is_some()
+        };
+        if taken(&model.name) {
+            model.name = format!("{}-{}", model.name, model.quantization);
+        }
+        if taken(&model.name) {
+            report.skipped.push(SkippedFile {
+                path: path.display().to_string(),
+                reason: format!("a model named `{}` already exists", model.name),
+            });
+            continue;
+        }
+
+        info!(
+            model = %model.name,
+            architecture = %model.architecture,
+            quantization = %model.quantization,
+            path = %model.path,
+            "registered discovered model"
+        );
+        state.registry.register(
+            ModelMetadata::new(&model.name, "", &model.quantization, EngineKind::CANDLE)
+                .with_artifacts(LocalArtifacts {
+                    gguf: path.clone(),
+                    tokenizer: None,
+                    sha256: None,
+                })
+                .with_context_window(model.context_window),
+        );
+        found.insert(model.name.clone(), path);
+        report.added.push(model);
+    }
+    Ok(report)
+}
+
+/// 重新扫描模型目录：POST /models/rescan
+#[utoipa::path(
+    tag = "models",
+    responses(
+        (status = 200, body = RescanResponse),
+        (status = 500, description = "the directory could not be read", body = ErrorResponse)
+    )
+)]
+#[post("/models/rescan")]
+pub async fn rescan_models(state: &State<Arc<AppState>>) -> Result<Json<RescanResponse>, ApiError> {
+    let state = state.inner().clone();
+    rocket::tokio::task::spawn_blocking(move || rescan(&state))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r.map_err(|e| e.to_string()))
+        .map(Json)
+        .map_err(|e| {
+            api_error(
+                Status::InternalServerError,
+                "rescan_failed",
+                format!("failed to scan the models directory: {e}"),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_drop_the_quantization_suffix() {
+        assert_eq!(
+            split_quant_suffix("mistral-7b-instruct-v0.1.Q4_K_M"),
+            ("mistral-7b-instruct-v0.1", Some("q4_k_m".to_string()))
+        );
+        assert_eq!(
+            split_quant_suffix("llama-2-7b-chat-q8_0"),
+            ("llama-2-7b-chat", Some("q8_0".to_string()))
+        );
+        assert_eq!(
+            split_quant_suffix("tinyllama.F16").1.as_deref(),
+            Some("f16")
+        );
+        assert_eq!(split_quant_suffix("phi-2"), ("phi-2", None));
+        assert_eq!(slug("My Model_v2 (chat)"), "my-model-v2-chat");
+    }
+
+    #[test]
+    fn scanning_skips_the_hub_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let hub = dir.path().join("hub");
+        let snapshot = hub.join("models--acme--foo/snapshots/c0ffee");
+        std::fs::create_dir_all(&snapshot).unwrap();
+        std::fs::create_dir_all(dir.path().join("local")).unwrap();
+        std::fs::write(snapshot.join("foo.gguf"), b"").unwrap();
+        std::fs::write(dir.path().join("local/bar.gguf"), b"").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"").unwrap();
+
+        let mut files = Vec::new();
+        collect_gguf_files(dir.path(), &hub, &mut files).unwrap();
+        assert_eq!(files, vec![dir.path().join("local/bar.gguf")]);
+    }
+}
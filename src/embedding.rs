@@ -0,0 +1,127 @@
+//! 句向量模型（`engine_kind = "embedding"`）：BERT 结构的 sentence-transformers 模型，
+//! 例如 all-MiniLM-L6-v2。输出按 attention mask 做 mean pooling，再做 L2 归一化，
+//! 所以两个向量的点积就是余弦相似度。
+//!
+//! `path` 是 hub 仓库名（需要 `config.json`、`tokenizer.json`、`model.safetensors`），
+//! 为空时使用默认的 all-MiniLM-L6-v2。embedding 模型不能生成文本。
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use hf_hub::api::sync::Api;
+use rocket::tokio::sync::mpsc;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+use tracing::info;
+
+use crate::device::DeviceSpec;
+use crate::engine::InferenceEngine;
+use crate::model_registry::ModelMetadata;
+
+const DEFAULT_EMBEDDING_REPO: &str = "sentence-transformers/all-MiniLM-L6-v2";
+/// 超过这个长度的文本截断（BERT 的位置编码通常是 512）
+const MAX_INPUT_TOKENS: usize = 512;
+
+pub struct EmbeddingEngine {
+    model_name: String,
+    device: Device,
+    tokenizer: Tokenizer,
+    model: BertModel,
+}
+
+impl EmbeddingEngine {
+    pub fn new(meta: &ModelMetadata, device: DeviceSpec) -> Result<Arc<Self>> {
+        let device = device.to_candle()?;
+        let repo = if meta.path.is_empty() {
+            DEFAULT_EMBEDDING_REPO
+        } else {
+            &meta.path
+        };
+        let start = Instant::now();
+        let api = Api::new()?.model(repo.to_string());
+        let config: Config =
+            serde_json::from_str(&std::fs::read_to_string(api.get("config.json")?)?)?;
+        let mut tokenizer = Tokenizer::from_file(api.get("tokenizer.json")?)
+            .map_err(|e| anyhow!("Error loading tokenizer: {e}"))?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_INPUT_TOKENS,
+                ..Default::default()
+            }))
+            .map_err(|e| anyhow!("Error configuring tokenizer: {e}"))?;
+
+        let weights = api.get("model.safetensors")?;
+        // safetensors 是只读 mmap，加载期间文件不会被修改
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let model = BertModel::load(vb, &config)?;
+        info!(
+            model = %meta.name,
+            repo = %repo,
+            build_ms = start.elapsed().as_millis() as u64,
+            "embedding model built"
+        );
+
+        Ok(Arc::new(Self {
+            model_name: meta.name.clone(),
+            device,
+            tokenizer,
+            model,
+        }))
+    }
+
+    fn embed_inner(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|e| anyhow!("Error encoding tokenizer: {e}"))?;
+        let rows = |f: fn(&tokenizers::Encoding) -> &[u32]| {
+            encodings
+                .iter()
+                .map(|e| Tensor::new(f(e), &self.device))
+                .collect::<candle_core::Result<Vec<_>>>()
+                .and_then(|rows| Tensor::stack(&rows, 0))
+        };
+        let input_ids = rows(|e| e.get_ids())?;
+        let mask = rows(|e| e.get_attention_mask())?.to_dtype(DTYPE)?;
+        let token_type_ids = input_ids.zeros_like()?;
+
+        // [batch, seq, hidden] -> 只对真实 token 求平均 -> L2 归一化
+        let hidden = self.model.forward(&input_ids, &token_type_ids)?;
+        let mask = mask.unsqueeze(2)?;
+        let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+        let pooled = summed.broadcast_div(&mask.sum(1)?)?;
+        let norm = pooled.sqr()?.sum_keepdim(1)?.sqrt()?;
+        Ok(pooled.broadcast_div(&norm)?.to_vec2::<f32>()?)
+    }
+}
+
+#[async_trait]
+impl InferenceEngine for EmbeddingEngine {
+    async fn generate(&self, _prompt: &str, _max_tokens: usize) -> Result<String> {
+        anyhow::bail!(
+            "`{}` is an embedding model and cannot generate text",
+            self.model_name
+        )
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        _sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        self.generate(prompt, max_tokens).await.map(|_| ())
+    }
+
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.embed_inner(texts)
+    }
+}
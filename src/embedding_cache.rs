@@ -0,0 +1,267 @@
+//! embedding 缓存：按（模型名，文本的 sha256）保存向量，重复导入同样的文档、
+//! 重复编码常见的查询时不用再跑模型。
+//!
+//! 内存里最多保留 `max_entries` 条（先进先出淘汰）；配置了 `dir` 时每条向量另存为
+//! `<dir>/<模型名>/<sha256>.f32`（小端 f32），内存里没有时从磁盘读回，重启后仍然有效。
+//! 模型被重新注册（权重可能变了）时清掉它的缓存。
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+/// `ServerConfig.embedding_cache`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmbeddingCacheConfig {
+    /// 内存里最多保留的向量数，0 表示不缓存
+    pub max_entries: usize,
+    /// 持久化目录，None 表示只在内存里
+    pub dir: Option<PathBuf>,
+}
+
+impl Default for EmbeddingCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            dir: None,
+        }
+    }
+}
+
+type Key = (String, [u8; 32]);
+
+#[derive(Default)]
+struct Inner {
+    vectors: HashMap<Key, Arc<Vec<f32>>>,
+    order: VecDeque<Key>,
+}
+
+/// 命中 / 未命中计数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EmbeddingCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+pub struct EmbeddingCache {
+    max_entries: usize,
+    dir: Option<PathBuf>,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for EmbeddingCache {
+    fn default() -> Self {
+        Self::new(&EmbeddingCacheConfig::default())
+    }
+}
+
+fn content_hash(text: &str) -> [u8; 32] {
+    Sha256::digest(text.as_bytes()).into()
+}
+
+/// 模型名里不能出现在路径中的字符换成 `_`
+fn model_dir(dir: &Path, model: &str) -> PathBuf {
+    let name: String = model
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    dir.join(name)
+}
+
+fn hex(hash: &[u8; 32]) -> String {
+    hash.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+impl EmbeddingCache {
+    pub fn new(config: &EmbeddingCacheConfig) -> Self {
+        Self {
+            max_entries: config.max_entries,
+            dir: config.dir.clone(),
+            inner: Mutex::new(Inner::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn path(&self, (model, hash): &Key) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(model_dir(dir, model).join(format!("{}.f32", hex(hash))))
+    }
+
+    /// 按 `texts` 的顺序返回已缓存的向量，没有的为 None
+    pub fn get_many(&self, model: &str, texts: &[String]) -> Vec<Option<Arc<Vec<f32>>>> {
+        texts
+            .iter()
+            .map(|text| {
+                let key = (model.to_string(), content_hash(text));
+                let found = self.get(&key);
+                let counter = if found.is_some() {
+                    &self.hits
+                } else {
+                    &self.misses
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                found
+            })
+            .collect()
+    }
+
+    fn get(&self, key: &Key) -> Option<Arc<Vec<f32>>> {
+        if self.max_entries == 0 {
+            return None;
+        }
+        if let Some(vector) = self.inner.lock().vectors.get(key) {
+            return Some(vector.clone());
+        }
+        let vector = Arc::new(read_vector(&self.path(key)?)?);
+        self.remember(key.clone(), vector.clone());
+        Some(vector)
+    }
+
+    /// 存入一条向量；写盘失败只打日志
+    pub fn insert(&self, model: &str, text: &str, vector: Vec<f32>) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let key = (model.to_string(), content_hash(text));
+        if let Some(path) = self.path(&key) {
+            if let Err(e) = write_vector(&path, &vector) {
+                warn!(path = %path.display(), "failed to write: {e}");
+            }
+        }
+        self.remember(key, Arc::new(vector));
+    }
+
+    fn remember(&self, key: Key, vector: Arc<Vec<f32>>) {
+        let mut inner = self.inner.lock();
+        if inner.vectors.insert(key.clone(), vector).is_none() {
+            inner.order.push_back(key);
+        }
+        while inner.order.len() > self.max_entries {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.vectors.remove(&oldest);
+            }
+        }
+    }
+
+    /// 删除 `model` 的所有向量（内存和磁盘）
+    pub fn invalidate(&self, model: &str) {
+        let mut inner = self.inner.lock();
+        inner.vectors.retain(|(m, _), _| m != model);
+        inner.order.retain(|(m, _)| m != model);
+        drop(inner);
+        if let Some(dir) = &self.dir {
+            let dir = model_dir(dir, model);
+            if dir.exists() {
+                if let Err(e) = std::fs::remove_dir_all(&dir) {
+                    warn!(dir = %dir.display(), "failed to remove: {e}");
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> EmbeddingCacheStats {
+        EmbeddingCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.inner.lock().vectors.len(),
+        }
+    }
+}
+
+/// 读不到或长度不对时当作没有缓存
+fn read_vector(path: &Path) -> Option<Vec<f32>> {
+    let bytes = std::fs::read(path).ok()?;
+    if bytes.is_empty() || bytes.len() % 4 != 0 {
+        return None;
+    }
+    Some(
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+    )
+}
+
+/// 先写临时文件再改名，并发读不会读到写了一半的文件
+fn write_vector(path: &Path, vector: &[f32]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let bytes: Vec<u8> = vector.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn keyed_by_model_and_content() {
+        let cache = EmbeddingCache::default();
+        cache.insert("a", "hello", vec![1.0, 0.0]);
+
+        let found = cache.get_many("a", &texts(&["hello", "other"]));
+        assert_eq!(found[0].as_deref(), Some(&vec![1.0, 0.0]));
+        assert!(found[1].is_none());
+        assert!(cache.get_many("b", &texts(&["hello"]))[0].is_none());
+        assert_eq!(
+            cache.stats(),
+            EmbeddingCacheStats {
+                hits: 1,
+                misses: 2,
+                entries: 1
+            }
+        );
+    }
+
+    #[test]
+    fn evicts_the_oldest_entries() {
+        let cache = EmbeddingCache::new(&EmbeddingCacheConfig {
+            max_entries: 2,
+            dir: None,
+        });
+        for (i, text) in ["x", "y", "z"].into_iter().enumerate() {
+            cache.insert("m", text, vec![i as f32]);
+        }
+        let found = cache.get_many("m", &texts(&["x", "y", "z"]));
+        assert!(found[0].is_none());
+        assert!(found[1].is_some() && found[2].is_some());
+    }
+
+    #[test]
+    fn vectors_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = EmbeddingCacheConfig {
+            max_entries: 10,
+            dir: Some(dir.path().to_path_buf()),
+        };
+        EmbeddingCache::new(&config).insert("org/minilm", "hello", vec![0.5, -0.25]);
+
+        let cache = EmbeddingCache::new(&config);
+        let found = cache.get_many("org/minilm", &texts(&["hello"]));
+        assert_eq!(found[0].as_deref(), Some(&vec![0.5, -0.25]));
+
+        cache.invalidate("org/minilm");
+        let cache = EmbeddingCache::new(&config);
+        assert!(cache.get_many("org/minilm", &texts(&["hello"]))[0].is_none());
+    }
+}
@@ -1,9 +1,14 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use async_trait::async_trait;
 use rocket::tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 // Candle 相关
 use candle_core::quantized::gguf_file;
@@ -11,7 +16,140 @@ use candle_core::{Device, Tensor};
 use candle_transformers::generation::LogitsProcessor;
 use candle_transformers::models::quantized_llama as qllama;
 use hf_hub::api::sync::Api;
+use hf_hub::Cache;
 use tokenizers::Tokenizer; // ✅ 用 candle_core
+use tracing::info;
+
+use crate::cancel::{self, Cancelled};
+use crate::chat_template::{self, ChatTemplate};
+use crate::device::DeviceSpec;
+use crate::diffusion::ImageParams;
+use crate::hub_stream::{self, HubWeights};
+use crate::memory;
+use crate::model_registry::{HubArtifacts, LocalArtifacts, ModelMetadata, ModelSource};
+use crate::repetition::{RepetitionConfig, RepetitionDetector};
+use crate::stop::{
+    CustomStops, DecodeStep, EosTokens, RepetitionStop, StopCriteria, StopCriteriaFactory, StopSet,
+};
+
+/// 生成结束的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// 模型输出了结束符
+    Stop,
+    /// 达到 max_tokens
+    Length,
+    /// 检测到退化的重复输出，提前停止
+    Repetition,
+}
+
+/// 采样参数，不填的字段用引擎自己的默认值
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct SamplingParams {
+    /// 0 表示 greedy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// 只在概率最高的 k 个 token 里采样（先于 top_p），greedy 时无效
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl SamplingParams {
+    /// 逐字段合并：`self` 里没填的用 `defaults` 补上
+    pub fn or(self, defaults: SamplingParams) -> SamplingParams {
+        SamplingParams {
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            top_k: self.top_k.or(defaults.top_k),
+            seed: self.seed.or(defaults.seed),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == SamplingParams::default()
+    }
+}
+
+/// 一次完整生成的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generation {
+    pub text: String,
+    pub finish_reason: FinishReason,
+}
+
+/// token 级生成的结果，`ids` 只包含新生成的部分
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenGeneration {
+    pub ids: Vec<u32>,
+    pub finish_reason: FinishReason,
+}
+
+/// 共享前缀批量生成的结果，`completions` 和后缀一一对应，只包含续写部分
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedPrefixGeneration {
+    pub completions: Vec<Generation>,
+    /// 前缀的 token 数，不按 token 处理的引擎为 0
+    pub prefix_tokens: usize,
+    /// 和逐条完整 prefill 相比省掉的 token 数
+    pub prefill_tokens_saved: usize,
+}
+
+/// 接着一段（可能被用户改过的）部分输出继续生成的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContinuedGeneration {
+    /// 部分输出 + 续写
+    pub text: String,
+    pub finish_reason: FinishReason,
+    /// 从 KV cache 复用、不用重新 prefill 的 token 数
+    pub reused_tokens: usize,
+}
+
+/// 参考答案在模型下的对数概率（质量指标，例如比较不同量化版本）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReferenceScore {
+    /// 参考答案每个 token 的 log p 之和
+    pub logprob: f64,
+    pub tokens: usize,
+}
+
+/// KV cache 的使用情况（`GET /models/<name>/cache`）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct CacheStats {
+    /// 正在解码的序列数
+    pub active_sequences: usize,
+    /// cache 里保存的 token 数
+    pub cached_tokens: usize,
+    /// 每个 block 的 token 数
+    pub block_size: usize,
+    pub blocks_allocated: usize,
+    pub blocks_free: usize,
+    pub prefix_lookups: u64,
+    pub prefix_hits: u64,
+    /// prefix_hits / prefix_lookups，没有请求时为 0
+    pub prefix_hit_rate: f64,
+}
+
+/// 加载各阶段耗时（毫秒），`/load` 的响应里按副本返回。
+/// tokenizer 和权重并行加载，所以各阶段之和可能大于 `total_ms`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LoadTimings {
+    /// 从 hf-hub 下载（或确认缓存）。首次拉取时边下载边加载，和 parse / build 重叠
+    pub download_ms: u64,
+    /// 解析 GGUF 头
+    pub parse_ms: u64,
+    /// 多线程预读 tensor 数据，边下载边加载时为 0
+    pub read_ms: u64,
+    /// 构建模型权重
+    pub build_ms: u64,
+    pub tokenizer_ms: u64,
+    pub total_ms: u64,
+}
 
 /// 统一的推理引擎抽象
 #[async_trait]
@@ -19,6 +157,49 @@ pub trait InferenceEngine: Send + Sync {
     /// 一次性生成完整结果
     async fn generate(&self, prompt: &str, max_tokens: usize) -> Result<String>;
 
+    /// 同 `generate`，但带上结束原因。默认认为是正常结束
+    async fn complete(&self, prompt: &str, max_tokens: usize) -> Result<Generation> {
+        Ok(Generation {
+            text: self.generate(prompt, max_tokens).await?,
+            finish_reason: FinishReason::Stop,
+        })
+    }
+
+    /// 带采样参数的 `complete`。默认忽略参数，支持自定义采样的引擎覆盖它
+    async fn complete_sampled(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        _sampling: &SamplingParams,
+    ) -> Result<Generation> {
+        self.complete(prompt, max_tokens).await
+    }
+
+    /// 把文本 prompt 编码成 token id，和 `generate` 使用同一套模板
+    fn encode_prompt(&self, _prompt: &str) -> Result<Vec<u32>> {
+        anyhow::bail!("engine does not support token-level input/output")
+    }
+
+    /// 把 token id 解码回文本
+    fn decode_ids(&self, _ids: &[u32]) -> Result<String> {
+        anyhow::bail!("engine does not support token-level input/output")
+    }
+
+    /// 生成结果的 token 数（不套模板、不加特殊 token），用于响应里的 `usage`。
+    /// 不能分词的引擎返回 None
+    fn count_tokens(&self, _text: &str) -> Option<usize> {
+        None
+    }
+
+    /// 直接从 token id 开始生成，跳过 encode/decode（调用方自己管理分词）
+    async fn complete_ids(
+        &self,
+        _input_ids: &[u32],
+        _max_tokens: usize,
+    ) -> Result<TokenGeneration> {
+        anyhow::bail!("engine does not support token-level input/output")
+    }
+
     /// 流式生成：把结果按 chunk 推送到 sender 中
     async fn generate_stream(
         &self,
@@ -26,8 +207,178 @@ pub trait InferenceEngine: Send + Sync {
         max_tokens: usize,
         sender: mpsc::Sender<String>,
     ) -> Result<()>;
+
+    /// 带采样参数的 `generate_stream`，默认忽略参数
+    async fn generate_stream_sampled(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        _sampling: &SamplingParams,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        self.generate_stream(prompt, max_tokens, sender).await
+    }
+
+    /// 一次处理一批 prompt（throughput 模式）。默认逐个调用 `generate`，
+    /// 能真正批量解码的引擎可以覆盖它
+    async fn generate_batch(&self, prompts: &[String], max_tokens: usize) -> Vec<Result<String>> {
+        let mut outputs = Vec::with_capacity(prompts.len());
+        for prompt in prompts {
+            outputs.push(self.generate(prompt, max_tokens).await);
+        }
+        outputs
+    }
+
+    /// 同一个前缀接多个后缀（例如用同一份评分标准批改很多答案）。
+    /// 默认逐条拼接后调用 `complete`，能复用 KV cache 的引擎只 prefill 一次前缀
+    async fn complete_shared_prefix(
+        &self,
+        prefix: &str,
+        suffixes: &[String],
+        max_tokens: usize,
+    ) -> Result<SharedPrefixGeneration> {
+        let mut completions = Vec::with_capacity(suffixes.len());
+        for suffix in suffixes {
+            completions.push(
+                self.complete(&format!("{prefix}{suffix}"), max_tokens)
+                    .await?,
+            );
+        }
+        Ok(SharedPrefixGeneration {
+            completions,
+            prefix_tokens: 0,
+            prefill_tokens_saved: 0,
+        })
+    }
+
+    /// 接着 `partial`（模型之前的输出，可能被改过）继续生成。
+    /// 默认把两段拼成新 prompt；能复用 KV cache 的引擎只重算改动之后的部分
+    async fn continue_generation(
+        &self,
+        prompt: &str,
+        partial: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<ContinuedGeneration> {
+        let generation = self
+            .complete_sampled(&format!("{prompt}{partial}"), max_tokens, sampling)
+            .await?;
+        Ok(ContinuedGeneration {
+            text: format!("{partial}{}", generation.text),
+            finish_reason: generation.finish_reason,
+            reused_tokens: 0,
+        })
+    }
+
+    /// 流式的 `continue_generation`：只推送 `partial` 之后新生成的部分。
+    /// 默认等 `continue_generation` 算完再按词推送，逐 token 解码的引擎边算边推
+    async fn continue_stream(
+        &self,
+        prompt: &str,
+        partial: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let generation = self
+            .continue_generation(prompt, partial, max_tokens, sampling)
+            .await?;
+        let added = generation
+            .text
+            .strip_prefix(partial)
+            .unwrap_or(&generation.text);
+        for word in added.split_whitespace() {
+            if sender.send(word.to_string()).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// 给定 prompt 时 `reference` 作为回答的对数概率，不采样
+    async fn score(&self, _prompt: &str, _reference: &str) -> Result<ReferenceScore> {
+        anyhow::bail!("engine does not support scoring")
+    }
+
+    /// 每段文本一个 L2 归一化的向量（点积即余弦相似度），见 `embedding` 模块
+    async fn embed(&self, _texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        anyhow::bail!("engine does not produce embeddings")
+    }
+
+    /// KV cache 使用情况；不维护 cache 的引擎返回 None
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// 释放 KV cache 占用的内存，权重保持加载
+    fn clear_cache(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// 加载各阶段耗时；不统计的引擎返回 None
+    fn load_timings(&self) -> Option<LoadTimings> {
+        None
+    }
+
+    /// 每个 token 的 KV cache 字节数，`kv_budget` 准入时用；返回 None 的引擎不记账
+    fn kv_bytes_per_token(&self) -> Option<u64> {
+        None
+    }
+
+    /// 文生图：返回 `params.n` 张 PNG。耗时且阻塞，调用方放在 blocking 线程里执行，
+    /// `progress` 汇报 0.0 ~ 1.0 的进度
+    fn generate_images(
+        &self,
+        _prompt: &str,
+        _params: &ImageParams,
+        _progress: &dyn Fn(f32),
+    ) -> Result<Vec<Vec<u8>>> {
+        anyhow::bail!("engine does not generate images")
+    }
+}
+
+/// 逐 token 解码的引擎边解码边按词推送（和 dummy 引擎一样按空白切开，chunk 里不带空白）。
+/// 每个新 token 之后用到目前为止解码出的全文调 `push`，还没结束的最后一个词留到 `finish`
+pub(crate) struct WordSender<'a> {
+    sender: &'a mpsc::Sender<String>,
+    /// 全文里已经推送到哪个字节
+    sent: usize,
 }
 
+impl<'a> WordSender<'a> {
+    pub(crate) fn new(sender: &'a mpsc::Sender<String>) -> Self {
+        Self { sender, sent: 0 }
+    }
+
+    /// 推送 `text` 里已经完整的词，暂停时阻塞到继续（见 `cancel::wait_while_paused`）；
+    /// 接收方断开时返回 false。在解码线程上调用，不能直接放在 tokio 的 worker 上
+    pub(crate) fn push(&mut self, text: &str) -> bool {
+        let pending = text.get(self.sent..).unwrap_or_default();
+        if let Some(end) = pending.rfind(char::is_whitespace) {
+            for word in pending[..end].split_whitespace() {
+                if self.sender.blocking_send(word.to_string()).is_err() {
+                    return false;
+                }
+            }
+            self.sent += end;
+        }
+        cancel::wait_while_paused(|| self.sender.is_closed());
+        !self.sender.is_closed()
+    }
+
+    /// 生成结束，推送剩下的词
+    pub(crate) fn finish(self, text: &str) {
+        for word in text.get(self.sent..).unwrap_or_default().split_whitespace() {
+            if self.sender.blocking_send(word.to_string()).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// dummy 引擎 embedding 的维度
+const DUMMY_EMBEDDING_DIM: usize = 64;
+
 /// Dummy 实现：只做字符串处理和延迟模拟
 pub struct DummyEngine {
     pub model_name: String,
@@ -43,12 +394,110 @@ impl DummyEngine {
 
 #[async_trait]
 impl InferenceEngine for DummyEngine {
-    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+    async fn generate(&self, prompt: &str, max_tokens: usize) -> Result<String> {
+        Ok(self.complete(prompt, max_tokens).await?.text)
+    }
+
+    async fn complete(&self, prompt: &str, _max_tokens: usize) -> Result<Generation> {
         // 模拟一点延迟
         rocket::tokio::time::sleep(Duration::from_millis(50)).await;
 
-        let output = format!("[{} DUMMY] {}", self.model_name, prompt.to_uppercase());
-        Ok(output)
+        // 按词做重复检测，方便测试 finish_reason
+        let mut detector = RepetitionDetector::new(RepetitionConfig::default());
+        let mut words = Vec::new();
+        let mut finish_reason = FinishReason::Stop;
+        for word in prompt.split_whitespace() {
+            words.push(word.to_uppercase());
+            if detector.push(word) {
+                finish_reason = FinishReason::Repetition;
+                break;
+            }
+        }
+
+        let text = if finish_reason == FinishReason::Repetition {
+            format!("[{} DUMMY] {}", self.model_name, words.join(" "))
+        } else {
+            format!("[{} DUMMY] {}", self.model_name, prompt.to_uppercase())
+        };
+        Ok(Generation {
+            text,
+            finish_reason,
+        })
+    }
+
+    /// 用字节当 token
+    fn encode_prompt(&self, prompt: &str) -> Result<Vec<u32>> {
+        Ok(prompt.bytes().map(u32::from).collect())
+    }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        Some(text.len())
+    }
+
+    fn decode_ids(&self, ids: &[u32]) -> Result<String> {
+        let bytes = ids
+            .iter()
+            .map(|&id| u8::try_from(id).map_err(|_| anyhow::anyhow!("token id {id} out of range")))
+            .collect::<Result<Vec<u8>>>()?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    async fn complete_ids(&self, input_ids: &[u32], max_tokens: usize) -> Result<TokenGeneration> {
+        rocket::tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // 和文本接口一样：原样转成大写，超过 max_tokens 截断
+        let text = self.decode_ids(input_ids)?.to_uppercase();
+        let mut ids = self.encode_prompt(&text)?;
+        let finish_reason = if ids.len() > max_tokens {
+            ids.truncate(max_tokens);
+            FinishReason::Length
+        } else {
+            FinishReason::Stop
+        };
+        Ok(TokenGeneration { ids, finish_reason })
+    }
+
+    /// 字节当 token：prompt 里出现过的字节概率高一些
+    async fn score(&self, prompt: &str, reference: &str) -> Result<ReferenceScore> {
+        let seen = prompt.to_uppercase();
+        let logprob = reference
+            .bytes()
+            .map(|b| {
+                if seen.as_bytes().contains(&b.to_ascii_uppercase()) {
+                    -0.5
+                } else {
+                    -3.0
+                }
+            })
+            .sum();
+        Ok(ReferenceScore {
+            logprob,
+            tokens: reference.len(),
+        })
+    }
+
+    /// 按词哈希的词袋向量：有相同词的文本相似度高
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let mut vector = vec![0.0f32; DUMMY_EMBEDDING_DIM];
+                for word in text
+                    .split(|c: char| !c.is_alphanumeric())
+                    .filter(|w| !w.is_empty())
+                {
+                    let hash = word
+                        .to_lowercase()
+                        .bytes()
+                        .fold(0xcbf29ce484222325u64, |h, b| {
+                            (h ^ u64::from(b)).wrapping_mul(0x100000001b3)
+                        });
+                    vector[(hash % DUMMY_EMBEDDING_DIM as u64) as usize] += 1.0;
+                }
+                let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-6);
+                vector.iter().map(|v| v / norm).collect()
+            })
+            .collect())
     }
 
     async fn generate_stream(
@@ -77,135 +526,759 @@ impl InferenceEngine for DummyEngine {
     }
 }
 
+/// 模型没有指定来源（`ModelMetadata::source`）时用的 Mistral Q2_K 权重 + tokenizer（需要联网或已有缓存）
+const DEFAULT_GGUF_REPO: &str = "TheBloke/Mistral-7B-Instruct-v0.1-GGUF";
+const DEFAULT_GGUF_FILE: &str = "mistral-7b-instruct-v0.1.Q2_K.gguf";
+const DEFAULT_TOKENIZER_REPO: &str = "mistralai/Mistral-7B-v0.1";
+
+/// 统计用的 block 大小（candle 的 cache 是连续 tensor，这里按 token 数折算）
+const KV_BLOCK_TOKENS: usize = 16;
+
+/// 生成时每喂这么多 token 留一个 cache 快照
+const CHECKPOINT_EVERY: usize = 32;
+/// 最多保留几个快照（每个快照持有自己那一段 cache，只留最近的）
+const MAX_CHECKPOINTS: usize = 4;
+
+/// 模型权重 + 当前 KV cache 里对应的 token
+struct DecodeState {
+    model: qllama::ModelWeights,
+    cached_tokens: Vec<u32>,
+    /// `cached_tokens` 前缀对应的 cache 快照，按位置递增。candle 的 cache 没法截断，
+    /// 新 prompt 从中间开始和 cache 不一致（例如用户改了输出的结尾）时回到最近的快照
+    checkpoints: Vec<Checkpoint>,
+}
+
+struct Checkpoint {
+    /// 快照里已经喂进去的 token 数
+    tokens: usize,
+    model: qllama::ModelWeights,
+}
+
+fn push_checkpoint(checkpoints: &mut Vec<Checkpoint>, tokens: usize, model: &qllama::ModelWeights) {
+    if checkpoints.last().is_some_and(|c| c.tokens >= tokens) {
+        return;
+    }
+    if checkpoints.len() == MAX_CHECKPOINTS {
+        checkpoints.remove(0);
+    }
+    // cache 里的 tensor 不会原地修改，clone 只复制引用
+    checkpoints.push(Checkpoint {
+        tokens,
+        model: model.clone(),
+    });
+}
+
+/// 新 prompt 从哪里开始算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResumeFrom {
+    Start,
+    /// 整个 cache 都是 prompt 的前缀
+    Cache,
+    /// 回到第 i 个快照
+    Checkpoint(usize),
+}
+
+/// `checkpoints` 是各快照的位置（递增）；快照至少要留一个 token 给 prompt 去喂
+fn resume_from(cached: &[u32], checkpoints: &[usize], prompt: &[u32]) -> ResumeFrom {
+    if !cached.is_empty() && prompt.len() > cached.len() && prompt.starts_with(cached) {
+        return ResumeFrom::Cache;
+    }
+    let common = cached
+        .iter()
+        .zip(prompt)
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(prompt.len().saturating_sub(1));
+    checkpoints
+        .iter()
+        .rposition(|&tokens| tokens > 0 && tokens <= common)
+        .map_or(ResumeFrom::Start, ResumeFrom::Checkpoint)
+}
+
 use std::sync::Mutex;
 pub struct CandleEngine {
     model_name: String,
     device: Device,
-    model: Mutex<qllama::ModelWeights>,
+    state: Mutex<DecodeState>,
     tokenizer: Tokenizer,
+    prefix_lookups: AtomicU64,
+    prefix_hits: AtomicU64,
+    timings: LoadTimings,
+    kv_bytes_per_token: Option<u64>,
+    /// 配置了 `cpu_affinity` 的 CPU 副本：绑定核心的计算线程池
+    pool: Option<rayon::ThreadPool>,
+    /// `add_stop_criteria` 加上的停止条件
+    stops: CustomStops,
 }
 
 impl CandleEngine {
-    pub fn new(model_name: &str) -> anyhow::Result<Arc<Self>> {
-        // 1) 设备：先用 CPU，后面你可以改成 metal/cuda
-        let device = Device::Cpu;
+    /// 从 `meta` 记录的来源加载，见 `candle_source`
+    pub fn new(meta: &ModelMetadata, device: DeviceSpec) -> anyhow::Result<Arc<Self>> {
+        Self::from_source(meta, candle_source(meta), device)
+    }
 
-        // 2) 通过 hf-hub 下载 GGUF 权重
-        let repo = "TheBloke/Mistral-7B-Instruct-v0.1-GGUF";
-        let filename = "mistral-7b-instruct-v0.1.Q2_K.gguf";
+    /// 离线模式：只用本地文件和 hf-hub 缓存里已有的文件，见 `offline_source`
+    pub fn new_offline(meta: &ModelMetadata, device: DeviceSpec) -> anyhow::Result<Arc<Self>> {
+        Self::from_source(meta, offline_source(meta)?, device)
+    }
 
-        let api = Api::new()?;
-        let api = api.model(repo.to_string());
-        let model_path = api.get(filename)?;
+    /// 从指定的本地文件或 hub 仓库加载；`meta` 提供名字和 CPU 绑定
+    pub fn from_source(
+        meta: &ModelMetadata,
+        source: ModelSource,
+        device: DeviceSpec,
+    ) -> anyhow::Result<Arc<Self>> {
+        // 在绑定的线程池里加载，权重按 first-touch 分配在同一个 NUMA 节点上
+        let pool = match (&meta.cpu_affinity, &device) {
+            (Some(affinity), DeviceSpec::Cpu) => Some(affinity.thread_pool(&meta.name)?),
+            _ => None,
+        };
+        let mut engine = match &pool {
+            Some(pool) => pool.install(|| Self::load(&meta.name, source, device))?,
+            None => Self::load(&meta.name, source, device)?,
+        };
+        engine.pool = pool;
+        Ok(Arc::new(engine))
+    }
 
-        let mut file = std::fs::File::open(&model_path)?;
-        let start = std::time::Instant::now();
+    /// 在结束符和重复检测之外再加一个停止条件，之后每次生成都生效
+    pub fn add_stop_criteria(&self, factory: StopCriteriaFactory) {
+        self.stops.add(factory);
+    }
 
-        let content = gguf_file::Content::read(&mut file)?;
-        let mut total_size_in_bytes = 0usize;
-        for (_, tensor) in content.tensor_infos.iter() {
-            let elem_count = tensor.shape.elem_count();
-            total_size_in_bytes +=
-                elem_count * tensor.ggml_dtype.type_size() / tensor.ggml_dtype.block_size();
-        }
-        println!(
-            "[Candle] loaded {} tensors ({}) in {:.2}s",
-            content.tensor_infos.len(),
-            format_size(total_size_in_bytes),
-            start.elapsed().as_secs_f32(),
-        );
+    /// 在绑定的线程池里执行计算（没有配置时直接执行），请求的取消信号一起带过去
+    fn compute<T: Send>(&self, f: impl FnOnce() -> T + Send) -> T {
+        let token = cancel::current();
+        match &self.pool {
+            Some(pool) => pool.install(|| cancel::enter(token, f)),
+            None => cancel::enter(token, f),
+        }
+    }
 
-        let model = qllama::ModelWeights::from_gguf(content, &mut file, &device)?;
-        println!("[Candle] model built for {}", model_name);
+    fn load(model_name: &str, source: ModelSource, device: DeviceSpec) -> anyhow::Result<Self> {
+        let started = Instant::now();
+        let mut timings = LoadTimings::default();
+        // 1) 设备：由 registry 的 placements 决定
+        let device = device.to_candle()?;
 
-        // 3) 下载 tokenizer
-        let api = Api::new()?;
-        let repo_tok = "mistralai/Mistral-7B-v0.1";
-        let api = api.model(repo_tok.to_string());
-        let tokenizer_path = api.get("tokenizer.json")?;
+        // 2) 权重和 tokenizer：本地文件，或者从 hf-hub 取；
+        //    首次拉取时边下载边加载，见 `hub_stream`
+        let phase = Instant::now();
+        let (weights, tokenizer) = match source {
+            ModelSource::Local(local) => {
+                let (model_path, tokenizer_path) = local.verify()?;
+                (
+                    HubWeights::Cached(model_path),
+                    TokenizerFile::Local(tokenizer_path),
+                )
+            }
+            ModelSource::Hub(hub) => (
+                hub_stream::open(&hub.repo, &hub.file)?,
+                TokenizerFile::Hub(hub.tokenizer_repo),
+            ),
+        };
+        timings.download_ms = elapsed_ms(phase);
 
-        let tokenizer = Tokenizer::from_file(tokenizer_path)
-            .map_err(|e| anyhow::anyhow!("Error loading tokenizer: {e}"))?;
+        // 3) tokenizer 放到单独线程，和权重加载重叠
+        let tokenizer = std::thread::spawn(move || {
+            let phase = Instant::now();
+            let path = match tokenizer {
+                TokenizerFile::Local(path) => path,
+                TokenizerFile::Hub(repo) => Api::new()?.model(repo).get("tokenizer.json")?,
+            };
+            Tokenizer::from_file(path)
+                .map(|tokenizer| (tokenizer, elapsed_ms(phase)))
+                .map_err(|e| anyhow::anyhow!("Error loading tokenizer: {e}"))
+        });
 
-        Ok(Arc::new(Self {
+        // 4) 权重
+        let (model, kv_bytes_per_token) = match weights {
+            HubWeights::Cached(model_path) => {
+                Self::load_cached(&model_path, &device, &mut timings)?
+            }
+            HubWeights::Streaming {
+                mut reader,
+                download,
+            } => {
+                // 解析和构建时读到还没下载的部分会等待下载线程
+                let phase = Instant::now();
+                let content = gguf_file::Content::read(&mut reader)?;
+                timings.parse_ms = elapsed_ms(phase);
+                let kv_bytes_per_token = memory::kv_bytes_per_token(&content).ok();
+                let phase = Instant::now();
+                let model = qllama::ModelWeights::from_gguf(content, &mut reader, &device)?;
+                timings.build_ms = elapsed_ms(phase);
+                let (path, took) = download
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("download thread panicked"))??;
+                timings.download_ms = took.as_millis() as u64;
+                info!(path = %path.display(), "downloaded and cached");
+                (model, kv_bytes_per_token)
+            }
+        };
+        info!(model = %model_name, "model built");
+
+        let (tokenizer, tokenizer_ms) = tokenizer
+            .join()
+            .map_err(|_| anyhow::anyhow!("tokenizer loading thread panicked"))??;
+        timings.tokenizer_ms = tokenizer_ms;
+        timings.total_ms = elapsed_ms(started);
+
+        Ok(Self {
             model_name: model_name.to_string(),
             device,
-            model: Mutex::new(model),
+            state: Mutex::new(DecodeState {
+                model,
+                cached_tokens: Vec::new(),
+                checkpoints: Vec::new(),
+            }),
             tokenizer,
-        }))
+            prefix_lookups: AtomicU64::new(0),
+            prefix_hits: AtomicU64::new(0),
+            timings,
+            kv_bytes_per_token,
+            pool: None,
+            stops: CustomStops::default(),
+        })
     }
 
-    /// 简单的 greedy / 有温度采样，这里做一个“非流式”生成
-    fn generate_inner(&self, prompt: &str, max_tokens: usize) -> anyhow::Result<String> {
-        let sample_len: usize = max_tokens;
-        let temperature: f64 = 0.8;
-        let top_p: Option<f64> = None;
-        let seed: u64 = 42;
-        // 目前没用到，可先注释掉或前缀 _
-        // let repeat_penalty: f32 = 1.1;
-        // let repeat_last_n: usize = 64;
+    /// 解析 GGUF 头 → 多线程预读 tensor 数据 → 顺序构建（此时读的是 page cache）。
+    /// 同时返回从 GGUF 头算出的每 token KV cache 字节数
+    fn load_cached(
+        model_path: &Path,
+        device: &Device,
+        timings: &mut LoadTimings,
+    ) -> anyhow::Result<(qllama::ModelWeights, Option<u64>)> {
+        let phase = Instant::now();
+        let mut file = std::fs::File::open(model_path)?;
+        let content = gguf_file::Content::read(&mut file)?;
+        timings.parse_ms = elapsed_ms(phase);
+        let kv_bytes_per_token = memory::kv_bytes_per_token(&content).ok();
 
-        let temperature = if temperature == 0.0 {
-            None
-        } else {
-            Some(temperature)
-        };
+        let phase = Instant::now();
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(PREFETCH_MAX_THREADS);
+        let total_size_in_bytes = prefetch_tensor_data(model_path, &content, threads)?;
+        timings.read_ms = elapsed_ms(phase);
+        info!(
+            tensors = content.tensor_infos.len(),
+            size = %format_size(total_size_in_bytes as usize),
+            threads,
+            read_ms = timings.read_ms,
+            "tensors read"
+        );
+
+        let phase = Instant::now();
+        let model = qllama::ModelWeights::from_gguf(content, &mut file, device)?;
+        timings.build_ms = elapsed_ms(phase);
+        Ok((model, kv_bytes_per_token))
+    }
 
-        let prompt_str = format!("[INST] {prompt} [/INST]");
+    /// 按 Mistral instruct 模板编码 prompt；已经套过模板的（见 `chat_template`）原样编码
+    fn encode_inner(&self, prompt: &str) -> anyhow::Result<Vec<u32>> {
+        let prompt_str = match chat_template::has_markers(prompt) {
+            true => prompt.to_string(),
+            false => ChatTemplate::Mistral.wrap(prompt),
+        };
         let tokens = self
             .tokenizer
             .encode(prompt_str, true)
             .map_err(|e| anyhow::anyhow!("Error encoding tokenizer: {e}"))?;
-        let mut prompt_tokens = tokens.get_ids().to_vec();
-        let to_sample = sample_len.saturating_sub(1);
+        Ok(tokens.get_ids().to_vec())
+    }
+
+    /// 简单的 greedy / 有温度采样，这里做一个“非流式”生成
+    fn generate_inner(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> anyhow::Result<Generation> {
+        let (prompt_tokens, generated, _) = self.sample_ids(
+            self.encode_inner(prompt)?,
+            max_tokens,
+            sampling,
+            &mut |_| true,
+        )?;
+
+        // decode 回字符串
+        let mut out_tokens = prompt_tokens;
+        out_tokens.extend(generated.ids.iter());
+        let decoded = self
+            .tokenizer
+            .decode(&out_tokens, true)
+            .map_err(|e| anyhow::anyhow!("Error decoding: {e}"))?;
+
+        Ok(Generation {
+            text: decoded,
+            finish_reason: generated.finish_reason,
+        })
+    }
+
+    /// 接着 `partial` 生成：prompt 按模板编码，`partial` 直接接在 `[/INST]` 后面，
+    /// 和上一次生成相同的部分从 cache（或快照）复用
+    fn continue_inner(
+        &self,
+        prompt: &str,
+        partial: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> anyhow::Result<ContinuedGeneration> {
+        let (ids, partial_ids) = self.encode_continuation(prompt, partial)?;
+        let (_, generated, reused_tokens) =
+            self.sample_ids(ids, max_tokens, sampling, &mut |_| true)?;
+
+        let mut out_tokens = partial_ids;
+        out_tokens.extend(generated.ids.iter());
+        Ok(ContinuedGeneration {
+            text: self.decode_ids(&out_tokens)?,
+            finish_reason: generated.finish_reason,
+            reused_tokens,
+        })
+    }
+
+    /// 续写的输入：按模板编码的 prompt 后面直接接 `partial`；同时返回 `partial` 的 token
+    fn encode_continuation(
+        &self,
+        prompt: &str,
+        partial: &str,
+    ) -> anyhow::Result<(Vec<u32>, Vec<u32>)> {
+        let mut ids = self.encode_inner(prompt)?;
+        let partial_ids = self
+            .tokenizer
+            .encode(partial, false)
+            .map_err(|e| anyhow::anyhow!("Error encoding tokenizer: {e}"))?
+            .get_ids()
+            .to_vec();
+        ids.extend(&partial_ids);
+        Ok((ids, partial_ids))
+    }
+
+    /// 流式生成：从 `ids` 开始边解码边按词推送新生成的文本，暂停时停在解码循环里
+    fn stream_inner(
+        &self,
+        ids: Vec<u32>,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        sender: &mpsc::Sender<String>,
+    ) -> anyhow::Result<()> {
+        let text_of = |ids: &[u32]| self.tokenizer.decode(ids, true).unwrap_or_default();
+        let mut words = WordSender::new(sender);
+        let (_, generated, _) = self.sample_ids(ids, max_tokens, sampling, &mut |ids| {
+            words.push(&text_of(ids))
+        })?;
+        words.finish(&text_of(&generated.ids));
+        Ok(())
+    }
+
+    /// 从 token id 开始采样，返回（截断后的 prompt，新生成的 token，从 cache 复用的 token 数）。
+    /// 每算下一个 token 前用已经生成的 token 调 `on_token`，返回 false 时提前结束
+    fn sample_ids(
+        &self,
+        mut prompt_tokens: Vec<u32>,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        on_token: &mut dyn FnMut(&[u32]) -> bool,
+    ) -> anyhow::Result<(Vec<u32>, TokenGeneration, usize)> {
+        if prompt_tokens.is_empty() {
+            anyhow::bail!("prompt has no tokens");
+        }
+        let to_sample = max_tokens.saturating_sub(1);
 
         if prompt_tokens.len() + to_sample > qllama::MAX_SEQ_LEN - 10 {
             let to_remove = prompt_tokens.len() + to_sample + 10 - qllama::MAX_SEQ_LEN;
             prompt_tokens = prompt_tokens[prompt_tokens.len().saturating_sub(to_remove)..].to_vec();
         }
 
-        let mut all_tokens = vec![];
-        let mut logits_processor = LogitsProcessor::new(seed, temperature, top_p);
-
         //  关键：从 Mutex 中拿一个可变的 model 引用
-        let mut model = self
-            .model
+        let mut state = self
+            .state
             .lock()
-            .map_err(|_| anyhow::anyhow!("failed to lock model mutex"))?;
+            .map_err(|_| anyhow::anyhow!("failed to lock model mutex for `{}`", self.model_name))?;
+
+        // 新 prompt 以 cache 中的 token 开头（例如多轮对话）时只需要跑新增部分，
+        // 只有前面一段相同（例如改了上次输出的结尾）时从最近的快照开始
+        self.prefix_lookups.fetch_add(1, Ordering::Relaxed);
+        let cached = std::mem::take(&mut state.cached_tokens);
+        let mut checkpoints = std::mem::take(&mut state.checkpoints);
+        let positions: Vec<usize> = checkpoints.iter().map(|c| c.tokens).collect();
+        let index_pos = match resume_from(&cached, &positions, &prompt_tokens) {
+            ResumeFrom::Cache => cached.len(),
+            ResumeFrom::Checkpoint(i) => {
+                state.model = checkpoints[i].model.clone();
+                checkpoints[i].tokens
+            }
+            ResumeFrom::Start => 0, // index_pos 为 0 时 candle 会丢掉旧的 cache
+        };
+        if index_pos > 0 {
+            self.prefix_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        checkpoints.retain(|c| c.tokens <= index_pos);
 
-        // 1) 先跑 prompt
-        let input = Tensor::new(prompt_tokens.as_slice(), &self.device)?.unsqueeze(0)?;
-        let mut logits = model.forward(&input, 0)?; // ✅ 用可变 model
-        logits = logits.squeeze(0)?;
-        let mut next_token = logits_processor.sample(&logits)?;
-        all_tokens.push(next_token);
+        let mut fed_tokens = prompt_tokens.clone();
+        let generated = self.decode(
+            &mut state.model,
+            &prompt_tokens[index_pos..],
+            index_pos,
+            max_tokens,
+            sampling,
+            &mut fed_tokens,
+            Some(&mut checkpoints),
+            on_token,
+        )?;
+        state.cached_tokens = fed_tokens;
+        state.checkpoints = checkpoints;
+        drop(state);
+
+        Ok((prompt_tokens, generated, index_pos))
+    }
+
+    /// 从 `index_pos` 开始喂 `feed`，再采样最多 `max_tokens` 个 token。
+    /// 喂进模型的生成 token 追加到 `fed_tokens`，方便调用方记录 cache 内容；
+    /// 给了 `checkpoints` 时 prompt 喂完和之后每 `CHECKPOINT_EVERY` 个位置留一个快照；
+    /// 每算下一个 token 前用已经生成的 token 调 `on_token`（流式推送、暂停），返回 false 时提前结束
+    #[allow(clippy::too_many_arguments)]
+    fn decode(
+        &self,
+        model: &mut qllama::ModelWeights,
+        feed: &[u32],
+        mut index_pos: usize,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        fed_tokens: &mut Vec<u32>,
+        mut checkpoints: Option<&mut Vec<Checkpoint>>,
+        on_token: &mut dyn FnMut(&[u32]) -> bool,
+    ) -> anyhow::Result<TokenGeneration> {
+        let temperature: f64 = sampling.temperature.unwrap_or(0.8);
+        let top_p: Option<f64> = sampling.top_p;
+        let top_k: Option<usize> = sampling.top_k.filter(|&k| k > 0);
+        let seed: u64 = sampling.seed.unwrap_or(42);
+        // 目前没用到，可先注释掉或前缀 _
+        // let repeat_penalty: f32 = 1.1;
+        // let repeat_last_n: usize = 64;
+
+        let temperature = if temperature == 0.0 {
+            None
+        } else {
+            Some(temperature)
+        };
+        let max_tokens = max_tokens.max(1);
+        let started = Instant::now();
+        let mut all_tokens = vec![];
+        let mut logits_processor = LogitsProcessor::new(seed, temperature, top_p);
+
+        // 1) 先跑 prompt。candle 的 mask 只有 seq_len × seq_len，
+        //    cache 非空时多 token 输入会广播失败，只能逐个喂
+        let chunks: Vec<&[u32]> = if index_pos == 0 {
+            vec![feed]
+        } else {
+            feed.chunks(1).collect()
+        };
+        let mut logits = None;
+        for chunk in chunks {
+            let input = Tensor::new(chunk, &self.device)?.unsqueeze(0)?;
+            logits = Some(model.forward(&input, index_pos)?.squeeze(0)?);
+            index_pos += chunk.len();
+        }
+        let logits = logits.ok_or_else(|| anyhow::anyhow!("nothing to feed the model"))?;
+        if let Some(checkpoints) = checkpoints.as_deref_mut() {
+            push_checkpoint(checkpoints, index_pos, model);
+        }
+        let mut next_token = logits_processor.sample(&keep_top_k(&logits, top_k)?)?;
 
         let eos_token = *self.tokenizer.get_vocab(true).get("</s>").unwrap_or(&0);
+        let text_of = |ids: &[u32]| self.tokenizer.decode(ids, true).unwrap_or_default();
 
-        // 2) 继续采样
-        for _ in 0..to_sample {
-            let input = Tensor::new(&[next_token], &self.device)?.unsqueeze(0)?;
-            let logits = model.forward(&input, 0)?.squeeze(0)?;
-            next_token = logits_processor.sample(&logits)?;
-            if next_token == eos_token {
+        // 2) 继续采样；每个 token 问一遍停止条件（结束符、重复检测和 `add_stop_criteria` 加的）
+        let mut stops = self.stops.extend(
+            StopSet::new()
+                .with(EosTokens::new([eos_token]))
+                .with(RepetitionStop::default()),
+        );
+        let mut finish_reason = FinishReason::Length;
+        loop {
+            all_tokens.push(next_token);
+            let step = DecodeStep::new(&all_tokens, started.elapsed(), &text_of);
+            if let Some(reason) = stops.should_stop(&step) {
+                finish_reason = reason;
                 break;
             }
-            all_tokens.push(next_token);
+            if all_tokens.len() >= max_tokens || !on_token(&all_tokens) {
+                break;
+            }
+            if cancel::requested() {
+                return Err(Cancelled.into());
+            }
+            let input = Tensor::new(&[next_token], &self.device)?.unsqueeze(0)?;
+            let logits = model.forward(&input, index_pos)?.squeeze(0)?;
+            index_pos += 1;
+            fed_tokens.push(next_token);
+            if let Some(checkpoints) = checkpoints.as_deref_mut() {
+                if index_pos.is_multiple_of(CHECKPOINT_EVERY) {
+                    push_checkpoint(checkpoints, index_pos, model);
+                }
+            }
+            next_token = logits_processor.sample(&keep_top_k(&logits, top_k)?)?;
+        }
+        if all_tokens.last() == Some(&eos_token) {
+            all_tokens.pop();
         }
 
-        // 3) decode 回字符串
-        let mut out_tokens = prompt_tokens.clone();
-        out_tokens.extend(all_tokens.iter());
-        let decoded = self
+        Ok(TokenGeneration {
+            ids: all_tokens,
+            finish_reason,
+        })
+    }
+
+    /// prompt 一次 prefill，之后逐个喂参考答案的 token，累加每一步给下一个 token 的 log p
+    fn score_inner(&self, prompt: &str, reference: &str) -> anyhow::Result<ReferenceScore> {
+        let prompt_ids = self.encode_inner(prompt)?;
+        let reference_ids = self
             .tokenizer
-            .decode(&out_tokens, true)
-            .map_err(|e| anyhow::anyhow!("Error decoding: {e}"))?;
+            .encode(reference, false)
+            .map_err(|e| anyhow::anyhow!("Error encoding tokenizer: {e}"))?
+            .get_ids()
+            .to_vec();
+        if reference_ids.is_empty() {
+            anyhow::bail!("reference has no tokens");
+        }
+        if prompt_ids.len() + reference_ids.len() > qllama::MAX_SEQ_LEN - 10 {
+            anyhow::bail!(
+                "prompt ({} tokens) plus reference ({} tokens) does not fit the context",
+                prompt_ids.len(),
+                reference_ids.len()
+            );
+        }
+
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow::anyhow!("failed to lock model mutex for `{}`", self.model_name))?;
+        let input = Tensor::new(prompt_ids.as_slice(), &self.device)?.unsqueeze(0)?;
+        let mut logits = state.model.forward(&input, 0)?.squeeze(0)?;
+        let mut index_pos = prompt_ids.len();
+        let mut fed_tokens = prompt_ids;
+        let mut logprob = 0.0;
+        for (i, &id) in reference_ids.iter().enumerate() {
+            logprob += token_logprob(&logits, id)?;
+            // 最后一个 token 不需要再往后算
+            if i + 1 < reference_ids.len() {
+                let input = Tensor::new(&[id], &self.device)?.unsqueeze(0)?;
+                logits = state.model.forward(&input, index_pos)?.squeeze(0)?;
+                index_pos += 1;
+                fed_tokens.push(id);
+            }
+        }
+        state.cached_tokens = fed_tokens;
+        state.checkpoints.clear();
 
-        Ok(decoded)
+        Ok(ReferenceScore {
+            logprob,
+            tokens: reference_ids.len(),
+        })
     }
+
+    /// 前缀只 prefill 一次，之后每个后缀都从前缀的 KV cache 快照开始解码。
+    /// 前缀和上一次调用相同时连 prefill 也省掉
+    fn shared_prefix_inner(
+        &self,
+        prefix: &str,
+        suffixes: &[String],
+        max_tokens: usize,
+    ) -> anyhow::Result<SharedPrefixGeneration> {
+        let encode = |text: String, special: bool| {
+            self.tokenizer
+                .encode(text, special)
+                .map(|tokens| tokens.get_ids().to_vec())
+                .map_err(|e| anyhow::anyhow!("Error encoding tokenizer: {e}"))
+        };
+        // 和 `encode_inner` 同一个模板，拆成前后两段
+        let prefix_ids = encode(format!("[INST] {prefix}"), true)?;
+        let suffix_ids = suffixes
+            .iter()
+            .map(|suffix| encode(format!("{suffix} [/INST]"), false))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let longest = suffix_ids.iter().map(Vec::len).max().unwrap_or(0);
+        if prefix_ids.len() + longest + max_tokens > qllama::MAX_SEQ_LEN - 10 {
+            anyhow::bail!(
+                "prefix ({} tokens) plus the longest suffix ({longest} tokens) leaves no room \
+                 for {max_tokens} new tokens",
+                prefix_ids.len()
+            );
+        }
+
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow::anyhow!("failed to lock model mutex for `{}`", self.model_name))?;
+        self.prefix_lookups.fetch_add(1, Ordering::Relaxed);
+        let cached = state.cached_tokens == prefix_ids;
+        if cached {
+            self.prefix_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let input = Tensor::new(prefix_ids.as_slice(), &self.device)?.unsqueeze(0)?;
+            state.model.forward(&input, 0)?;
+        }
+        // cache 里的 tensor 不会原地修改，clone 只复制引用，可以当快照用
+        let snapshot = state.model.clone();
+
+        let mut completions = Vec::with_capacity(suffix_ids.len());
+        for ids in &suffix_ids {
+            let mut model = snapshot.clone();
+            let generated = self.decode(
+                &mut model,
+                ids,
+                prefix_ids.len(),
+                max_tokens,
+                &SamplingParams::default(),
+                &mut Vec::new(),
+                None,
+                &mut |_| true,
+            )?;
+            completions.push(Generation {
+                text: self.decode_ids(&generated.ids)?,
+                finish_reason: generated.finish_reason,
+            });
+        }
+        state.model = snapshot;
+        state.cached_tokens = prefix_ids.clone();
+        state.checkpoints.clear();
+        drop(state);
+
+        let prefills = suffix_ids.len() - usize::from(!cached);
+        Ok(SharedPrefixGeneration {
+            completions,
+            prefix_tokens: prefix_ids.len(),
+            prefill_tokens_saved: prefix_ids.len() * prefills,
+        })
+    }
+}
+
+/// tokenizer.json 在本地，还是要从哪个 hub 仓库下载
+enum TokenizerFile {
+    Local(PathBuf),
+    Hub(String),
+}
+
+/// candle 模型的权重来源：`ModelMetadata::source`，没有指定时用默认的 Mistral 7B
+pub(crate) fn candle_source(meta: &ModelMetadata) -> ModelSource {
+    meta.source().unwrap_or_else(|| {
+        ModelSource::Hub(HubArtifacts {
+            repo: DEFAULT_GGUF_REPO.to_string(),
+            file: DEFAULT_GGUF_FILE.to_string(),
+            tokenizer_repo: DEFAULT_TOKENIZER_REPO.to_string(),
+        })
+    })
+}
+
+/// 离线模式下的来源：hub 上的 GGUF 和 tokenizer 换成 hf-hub 缓存里的路径，
+/// 缓存里没有时报错（而不是等网络超时）；本地来源原样返回
+pub(crate) fn offline_source(meta: &ModelMetadata) -> anyhow::Result<ModelSource> {
+    let hub = match candle_source(meta) {
+        ModelSource::Hub(hub) => hub,
+        local => return Ok(local),
+    };
+    let cache = Cache::default();
+    let cached = |repo: &str, file: &str| {
+        cache.model(repo.to_string()).get(file).ok_or_else(|| {
+            anyhow::anyhow!(
+                "offline mode: `{repo}/{file}` for model `{}` is not in the model cache ({}); \
+                 copy it there from a connected machine (POST /models/{}/pull fills the cache) \
+                 or register the model with a local GGUF path",
+                meta.name,
+                cache.path().display(),
+                meta.name
+            )
+        })
+    };
+    Ok(ModelSource::Local(LocalArtifacts {
+        gguf: cached(&hub.repo, &hub.file)?,
+        tokenizer: Some(cached(&hub.tokenizer_repo, "tokenizer.json")?),
+        sha256: None,
+    }))
 }
 
 // 小工具：人类可读的字节数
+/// 预读线程数上限，NVMe 上再多收益不大
+const PREFETCH_MAX_THREADS: usize = 8;
+
+/// `log_softmax(logits)[id]`
+fn token_logprob(logits: &Tensor, id: u32) -> anyhow::Result<f64> {
+    let logits = logits.to_dtype(candle_core::DType::F32)?.to_vec1::<f32>()?;
+    let target = *logits
+        .get(id as usize)
+        .ok_or_else(|| anyhow::anyhow!("token id {id} is outside the logits"))?;
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let sum: f64 = logits.iter().map(|&l| f64::from(l - max).exp()).sum();
+    Ok(f64::from(target - max) - sum.ln())
+}
+
+fn elapsed_ms(since: Instant) -> u64 {
+    since.elapsed().as_millis() as u64
+}
+
+pub(crate) fn tensor_bytes(info: &gguf_file::TensorInfo) -> u64 {
+    let dtype = info.ggml_dtype;
+    (info.shape.elem_count() * dtype.type_size() / dtype.block_size()) as u64
+}
+
+/// 把 tensor 数据区切成 `threads` 段，每个线程用自己的文件句柄读一段（内容丢弃），
+/// 让之后 `from_gguf` 的顺序读命中 page cache。返回 tensor 数据的总字节数
+fn prefetch_tensor_data(
+    path: &Path,
+    content: &gguf_file::Content,
+    threads: usize,
+) -> std::io::Result<u64> {
+    let data_len = content
+        .tensor_infos
+        .values()
+        .map(|t| t.offset + tensor_bytes(t))
+        .max()
+        .unwrap_or(0);
+    let start = content.tensor_data_offset;
+    let end = start + data_len;
+    let per_thread = data_len.div_ceil(threads.max(1) as u64).max(1);
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1) as u64)
+            .map(|i| {
+                let from = (start + i * per_thread).min(end);
+                let to = (from + per_thread).min(end);
+                scope.spawn(move || read_range(path, from, to))
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|w| {
+                w.join()
+                    .unwrap_or_else(|_| Err(std::io::Error::other("prefetch thread panicked")))
+            })
+            .sum::<std::io::Result<u64>>()
+    })?;
+    Ok(content.tensor_infos.values().map(tensor_bytes).sum())
+}
+
+/// 读 `[from, to)`，返回读到的字节数；文件提前结束时报错
+fn read_range(path: &Path, from: u64, to: u64) -> std::io::Result<u64> {
+    if from >= to {
+        return Ok(0);
+    }
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(from))?;
+    let mut buf = vec![0u8; 4 << 20];
+    let mut remaining = to - from;
+    while remaining > 0 {
+        let want = remaining.min(buf.len() as u64) as usize;
+        let n = file.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        remaining -= n as u64;
+    }
+    Ok(to - from)
+}
+
 fn format_size(size: usize) -> String {
     const KB: f64 = 1024.0;
     const MB: f64 = KB * 1024.0;
@@ -225,8 +1298,52 @@ fn format_size(size: usize) -> String {
 #[async_trait]
 impl InferenceEngine for CandleEngine {
     async fn generate(&self, prompt: &str, max_tokens: usize) -> Result<String> {
-        let out = self.generate_inner(prompt, max_tokens)?;
-        Ok(out)
+        Ok(self.complete(prompt, max_tokens).await?.text)
+    }
+
+    async fn complete(&self, prompt: &str, max_tokens: usize) -> Result<Generation> {
+        self.complete_sampled(prompt, max_tokens, &SamplingParams::default())
+            .await
+    }
+
+    async fn complete_sampled(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<Generation> {
+        self.compute(|| self.generate_inner(prompt, max_tokens, sampling))
+    }
+
+    fn encode_prompt(&self, prompt: &str) -> Result<Vec<u32>> {
+        self.encode_inner(prompt)
+    }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        let tokens = self.tokenizer.encode(text, false).ok()?;
+        Some(tokens.get_ids().len())
+    }
+
+    fn decode_ids(&self, ids: &[u32]) -> Result<String> {
+        self.tokenizer
+            .decode(ids, true)
+            .map_err(|e| anyhow::anyhow!("Error decoding: {e}"))
+    }
+
+    async fn complete_ids(&self, input_ids: &[u32], max_tokens: usize) -> Result<TokenGeneration> {
+        let vocab_size = self.tokenizer.get_vocab_size(true) as u32;
+        if let Some(bad) = input_ids.iter().find(|&&id| id >= vocab_size) {
+            anyhow::bail!("token id {bad} is outside the vocabulary (size {vocab_size})");
+        }
+        let (_, generated, _) = self.compute(|| {
+            self.sample_ids(
+                input_ids.to_vec(),
+                max_tokens,
+                &SamplingParams::default(),
+                &mut |_| true,
+            )
+        })?;
+        Ok(generated)
     }
 
     async fn generate_stream(
@@ -235,13 +1352,322 @@ impl InferenceEngine for CandleEngine {
         max_tokens: usize,
         sender: mpsc::Sender<String>,
     ) -> Result<()> {
-        let full = self.generate(prompt, max_tokens).await?;
-        for w in full.split_whitespace() {
-            if sender.send(w.to_string()).await.is_err() {
-                break;
+        self.generate_stream_sampled(prompt, max_tokens, &SamplingParams::default(), sender)
+            .await
+    }
+
+    async fn generate_stream_sampled(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        // 解码线程边算边推送，暂停时停在解码循环里，不能占着 tokio 的 worker
+        rocket::tokio::task::block_in_place(|| {
+            self.compute(|| {
+                self.stream_inner(self.encode_inner(prompt)?, max_tokens, sampling, &sender)
+            })
+        })
+    }
+
+    async fn complete_shared_prefix(
+        &self,
+        prefix: &str,
+        suffixes: &[String],
+        max_tokens: usize,
+    ) -> Result<SharedPrefixGeneration> {
+        self.compute(|| self.shared_prefix_inner(prefix, suffixes, max_tokens))
+    }
+
+    async fn continue_generation(
+        &self,
+        prompt: &str,
+        partial: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<ContinuedGeneration> {
+        self.compute(|| self.continue_inner(prompt, partial, max_tokens, sampling))
+    }
+
+    async fn continue_stream(
+        &self,
+        prompt: &str,
+        partial: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        rocket::tokio::task::block_in_place(|| {
+            self.compute(|| {
+                let (ids, _) = self.encode_continuation(prompt, partial)?;
+                self.stream_inner(ids, max_tokens, sampling, &sender)
+            })
+        })
+    }
+
+    async fn score(&self, prompt: &str, reference: &str) -> Result<ReferenceScore> {
+        self.compute(|| self.score_inner(prompt, reference))
+    }
+
+    fn load_timings(&self) -> Option<LoadTimings> {
+        Some(self.timings.clone())
+    }
+
+    fn kv_bytes_per_token(&self) -> Option<u64> {
+        self.kv_bytes_per_token
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        // 拿不到锁说明正在解码
+        let (active_sequences, cached_tokens) = match self.state.try_lock() {
+            Ok(state) => (0, state.cached_tokens.len()),
+            Err(_) => (1, 0),
+        };
+        let total_blocks = qllama::MAX_SEQ_LEN / KV_BLOCK_TOKENS;
+        let blocks_allocated = cached_tokens.div_ceil(KV_BLOCK_TOKENS);
+        let prefix_lookups = self.prefix_lookups.load(Ordering::Relaxed);
+        let prefix_hits = self.prefix_hits.load(Ordering::Relaxed);
+        Some(CacheStats {
+            active_sequences,
+            cached_tokens,
+            block_size: KV_BLOCK_TOKENS,
+            blocks_allocated,
+            blocks_free: total_blocks.saturating_sub(blocks_allocated),
+            prefix_lookups,
+            prefix_hits,
+            prefix_hit_rate: if prefix_lookups == 0 {
+                0.0
+            } else {
+                prefix_hits as f64 / prefix_lookups as f64
+            },
+        })
+    }
+
+    fn clear_cache(&self) -> Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow::anyhow!("failed to lock model mutex for `{}`", self.model_name))?;
+        // candle 没有直接清空 cache 的接口：在位置 0 跑一个 token 会用 1 个 token 的 cache 替换掉旧的
+        let bos = self.tokenizer.token_to_id("<s>").unwrap_or(1);
+        let input = Tensor::new(&[bos], &self.device)?.unsqueeze(0)?;
+        let model = &mut state.model;
+        self.compute(|| model.forward(&input, 0))?;
+        state.cached_tokens.clear();
+        state.checkpoints.clear();
+        Ok(())
+    }
+}
+
+/// 把前 k 大以外的 logits 置为 -inf（candle 的 `LogitsProcessor` 没有 top_k）；并列的都保留
+fn keep_top_k(logits: &Tensor, top_k: Option<usize>) -> candle_core::Result<Tensor> {
+    let values = logits.to_dtype(candle_core::DType::F32)?.to_vec1::<f32>()?;
+    let Some(k) = top_k.filter(|&k| k < values.len()) else {
+        return Ok(logits.clone());
+    };
+    let mut sorted = values.clone();
+    sorted.sort_by(|a, b| b.total_cmp(a));
+    let threshold = sorted[k - 1];
+    let kept: Vec<f32> = values
+        .into_iter()
+        .map(|v| if v >= threshold { v } else { f32::NEG_INFINITY })
+        .collect();
+    Tensor::new(kept, logits.device())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::quantized::{GgmlDType, QTensor};
+
+    #[test]
+    fn resumes_from_the_latest_checkpoint_before_an_edit() {
+        let cached = [1, 2, 3, 4, 5, 6, 7, 8];
+        let checkpoints = [3, 5, 7];
+        // cache 整个是前缀
+        assert_eq!(
+            resume_from(&cached, &checkpoints, &[1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            ResumeFrom::Cache
+        );
+        // 第 6 个 token 之后被改掉：回到位置 5
+        assert_eq!(
+            resume_from(&cached, &checkpoints, &[1, 2, 3, 4, 5, 6, 0, 0]),
+            ResumeFrom::Checkpoint(1)
+        );
+        // 和 cache 完全一样时要留一个 token 去喂
+        assert_eq!(
+            resume_from(&cached, &checkpoints, &cached),
+            ResumeFrom::Checkpoint(2)
+        );
+        assert_eq!(
+            resume_from(&cached, &checkpoints, &[1, 2, 0]),
+            ResumeFrom::Start
+        );
+        assert_eq!(resume_from(&[], &[], &[1]), ResumeFrom::Start);
+    }
+
+    #[test]
+    fn top_k_masks_everything_else() {
+        let logits = Tensor::new(&[0.5f32, 3.0, -1.0, 2.0], &Device::Cpu).unwrap();
+        let kept = keep_top_k(&logits, Some(2))
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap();
+        assert_eq!(kept, [f32::NEG_INFINITY, 3.0, f32::NEG_INFINITY, 2.0]);
+        // 采样只会落在保留的 token 上
+        let mut processor = LogitsProcessor::new(7, Some(5.0), None);
+        for _ in 0..20 {
+            let token = processor
+                .sample(&keep_top_k(&logits, Some(2)).unwrap())
+                .unwrap();
+            assert!(token == 1 || token == 3);
+        }
+        assert_eq!(
+            keep_top_k(&logits, None).unwrap().to_vec1::<f32>().unwrap()[0],
+            0.5
+        );
+    }
+
+    /// 用 tiny 引擎的内置权重和逐字符词表搭一个本地的 CandleEngine
+    fn tiny_candle_engine(dir: &Path) -> Arc<CandleEngine> {
+        let gguf = dir.join("tiny.gguf");
+        std::fs::write(&gguf, crate::tiny::builtin_gguf().unwrap()).unwrap();
+        let mut vocab: serde_json::Map<String, serde_json::Value> = (b' '..=b'~')
+            .map(|c| ((c as char).to_string(), (c - b' ').into()))
+            .collect();
+        vocab.insert("<s>".into(), 95.into());
+        vocab.insert("</s>".into(), 96.into());
+        let tokenizer = serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": null,
+            "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "?" }
+        });
+        std::fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+
+        let mut meta = ModelMetadata::new(
+            "tiny-candle",
+            "",
+            "",
+            crate::model_registry::EngineKind::CANDLE,
+        );
+        meta.artifacts = Some(crate::model_registry::LocalArtifacts {
+            gguf,
+            tokenizer: None,
+            sha256: None,
+        });
+        CandleEngine::new(&meta, DeviceSpec::Cpu).unwrap()
+    }
+
+    /// 每一步都从位置 0 重算完整上下文时 greedy 会选的 token
+    fn full_context_argmax(context: &[u32]) -> u32 {
+        let mut reader = std::io::Cursor::new(crate::tiny::builtin_gguf().unwrap());
+        let content = gguf_file::Content::read(&mut reader).unwrap();
+        let mut model =
+            qllama::ModelWeights::from_gguf(content, &mut reader, &Device::Cpu).unwrap();
+        let input = Tensor::new(context, &Device::Cpu)
+            .unwrap()
+            .unsqueeze(0)
+            .unwrap();
+        let logits: Vec<f32> = model
+            .forward(&input, 0)
+            .unwrap()
+            .squeeze(0)
+            .unwrap()
+            .to_vec1()
+            .unwrap();
+        (0..logits.len())
+            .max_by(|&a, &b| logits[a].total_cmp(&logits[b]))
+            .unwrap() as u32
+    }
+
+    /// 解码循环里位置跟着往后走：生成的每个 token 都和完整上下文重算的一致，
+    /// 接着上一轮的 prompt 复用 cache 时也一样
+    #[test]
+    fn candle_decoding_matches_full_context_forward() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = tiny_candle_engine(dir.path());
+        let greedy = SamplingParams {
+            temperature: Some(0.0),
+            ..SamplingParams::default()
+        };
+
+        let mut context = crate::tiny::encode("Hello");
+        for round in 0..2 {
+            let (_, generated, reused) = engine
+                .sample_ids(context.clone(), 8, &greedy, &mut |_| true)
+                .unwrap();
+            assert!(!generated.ids.is_empty());
+            assert_eq!(reused > 0, round > 0);
+            for &id in &generated.ids {
+                assert_eq!(id, full_context_argmax(&context));
+                context.push(id);
             }
-            rocket::tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+            context.extend(crate::tiny::encode(" more").into_iter().skip(1));
         }
-        Ok(())
+    }
+
+    /// 流式生成边解码边推送，拼起来和非流式的 greedy 输出一样（只有新生成的部分）
+    #[rocket::async_test]
+    async fn candle_streams_tokens_from_the_decode_loop() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = tiny_candle_engine(dir.path());
+        let greedy = SamplingParams {
+            temperature: Some(0.0),
+            ..SamplingParams::default()
+        };
+        let (_, generated, _) = engine
+            .sample_ids(
+                engine.encode_inner("Hello").unwrap(),
+                12,
+                &greedy,
+                &mut |_| true,
+            )
+            .unwrap();
+        let expected = engine.decode_ids(&generated.ids).unwrap();
+
+        let (tx, mut rx) = mpsc::channel(64);
+        let streaming = {
+            let engine = engine.clone();
+            rocket::tokio::spawn(async move {
+                engine
+                    .generate_stream_sampled("Hello", 12, &greedy, tx)
+                    .await
+            })
+        };
+        let mut words = Vec::new();
+        while let Some(word) = rx.recv().await {
+            words.push(word);
+        }
+        streaming.await.unwrap().unwrap();
+        assert_eq!(words, expected.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn prefetch_reads_every_tensor() {
+        let a = Tensor::zeros((4, 32), candle_core::DType::F32, &Device::Cpu).unwrap();
+        let b = Tensor::zeros((3, 32), candle_core::DType::F32, &Device::Cpu).unwrap();
+        let a = QTensor::quantize(&a, GgmlDType::F32).unwrap();
+        let b = QTensor::quantize(&b, GgmlDType::F32).unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        gguf_file::write(file.as_file_mut(), &[], &[("a", &a), ("b", &b)]).unwrap();
+
+        let mut reader = std::fs::File::open(file.path()).unwrap();
+        let content = gguf_file::Content::read(&mut reader).unwrap();
+        for threads in [1, 3, 64] {
+            let bytes = prefetch_tensor_data(file.path(), &content, threads).unwrap();
+            assert_eq!(bytes, 7 * 32 * 4);
+        }
+
+        // 截断的文件在预读阶段就报错
+        let len = file.as_file().metadata().unwrap().len();
+        file.as_file().set_len(len - 16).unwrap();
+        assert!(prefetch_tensor_data(file.path(), &content, 2).is_err());
     }
 }
@@ -0,0 +1,74 @@
+//! 引擎工厂注册表：EngineKind（字符串）-> 构造函数
+//!
+//! 内置 `dummy` / `candle` / `diffusion` / `embedding` / `tiny` 五种；下游可以通过 `AppState::builder().engine_factory(..)`
+//! 注册自己的 InferenceEngine 实现，而不需要改这里的代码。
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::device::DeviceSpec;
+use crate::diffusion::DiffusionEngine;
+use crate::embedding::EmbeddingEngine;
+use crate::engine::{CandleEngine, DummyEngine, InferenceEngine};
+use crate::model_registry::{EngineKind, ModelMetadata};
+use crate::tiny::TinyEngine;
+
+/// 根据模型元信息在指定设备上构造一个引擎实例
+pub type EngineFactory = Arc<
+    dyn Fn(&ModelMetadata, DeviceSpec) -> anyhow::Result<Arc<dyn InferenceEngine>> + Send + Sync,
+>;
+
+#[derive(Clone, Default)]
+pub struct EngineFactories {
+    factories: HashMap<EngineKind, EngineFactory>,
+}
+
+impl EngineFactories {
+    /// 空表（不含内置引擎）
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// 带内置 dummy / candle / diffusion / embedding / tiny 的表
+    pub fn with_builtin() -> Self {
+        let mut factories = Self::empty();
+        factories.register(EngineKind::DUMMY, |meta: &ModelMetadata, _device| {
+            Ok(DummyEngine::new(&meta.name) as Arc<dyn InferenceEngine>)
+        });
+        factories.register(EngineKind::CANDLE, |meta: &ModelMetadata, device| {
+            Ok(CandleEngine::new(meta, device)? as Arc<dyn InferenceEngine>)
+        });
+        factories.register(EngineKind::DIFFUSION, |meta: &ModelMetadata, device| {
+            Ok(DiffusionEngine::new(meta, device)? as Arc<dyn InferenceEngine>)
+        });
+        factories.register(EngineKind::EMBEDDING, |meta: &ModelMetadata, device| {
+            Ok(EmbeddingEngine::new(meta, device)? as Arc<dyn InferenceEngine>)
+        });
+        factories.register(EngineKind::TINY, |meta: &ModelMetadata, device| {
+            Ok(TinyEngine::new(meta, device)? as Arc<dyn InferenceEngine>)
+        });
+        factories
+    }
+
+    /// 注册（或覆盖）某个 kind 的工厂
+    pub fn register<F>(&mut self, kind: impl Into<EngineKind>, factory: F)
+    where
+        F: Fn(&ModelMetadata, DeviceSpec) -> anyhow::Result<Arc<dyn InferenceEngine>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.factories.insert(kind.into(), Arc::new(factory));
+    }
+
+    pub fn get(&self, kind: &EngineKind) -> Option<EngineFactory> {
+        self.factories.get(kind).cloned()
+    }
+
+    /// 已注册的 kind 列表（排序后，方便展示）
+    pub fn kinds(&self) -> Vec<EngineKind> {
+        let mut kinds: Vec<_> = self.factories.keys().cloned().collect();
+        kinds.sort();
+        kinds
+    }
+}
@@ -0,0 +1,162 @@
+//! HTTP 接口的错误：每个错误都是一个状态码加 `{"error": {"code", "message"}}` 的 JSON 体。
+//!
+//! 找不到模型是 404，模型没加载 / 状态不对是 409，请求参数校验失败是 422，过载（稍后重试，例如请求队列满了）是 429，
+//! 其余状态码（400 / 401 / 403 / 413 / 503 …）用 `Other`。`code` 是机器可读的错误码，客户端应该按它分支，
+//! `message` 只给人看。
+
+use rocket::http::Status;
+use rocket::response::{self, status, Responder};
+use rocket::serde::json::Json;
+use rocket::Request;
+use thiserror::Error;
+
+use crate::confirm::ConfirmationRequired;
+use crate::output_validation::ValidationAttempt;
+use crate::types::{ErrorBody, ErrorResponse};
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    /// 404：模型、会话等不存在
+    #[error("{}", .0.message)]
+    NotFound(ErrorBody),
+    /// 409：模型没加载，或者当前状态不允许这个操作
+    #[error("{}", .0.message)]
+    Conflict(ErrorBody),
+    /// 422：请求参数校验失败
+    #[error("{}", .0.message)]
+    Validation(ErrorBody),
+    /// 429：服务过载，稍后重试
+    #[error("{}", .0.message)]
+    Overloaded(ErrorBody),
+    #[error("{}", .1.message)]
+    Other(Status, ErrorBody),
+}
+
+fn body(code: &str, message: impl Into<String>) -> ErrorBody {
+    ErrorBody {
+        code: code.to_string(),
+        message: message.into(),
+        max_bytes: None,
+        confirmation: None,
+        attempts: None,
+        queue_position: None,
+    }
+}
+
+impl ApiError {
+    /// 按状态码选变体
+    pub fn new(status: Status, code: &str, message: impl Into<String>) -> Self {
+        let body = body(code, message);
+        match status.code {
+            404 => ApiError::NotFound(body),
+            409 => ApiError::Conflict(body),
+            422 => ApiError::Validation(body),
+            429 => ApiError::Overloaded(body),
+            _ => ApiError::Other(status, body),
+        }
+    }
+
+    /// 422 `invalid_input`
+    pub fn validation(message: impl Into<String>) -> Self {
+        ApiError::Validation(body("invalid_input", message))
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::Validation(_) => Status::UnprocessableEntity,
+            ApiError::Overloaded(_) => Status::TooManyRequests,
+            ApiError::Other(status, _) => *status,
+        }
+    }
+
+    pub fn body(&self) -> &ErrorBody {
+        match self {
+            ApiError::NotFound(body)
+            | ApiError::Conflict(body)
+            | ApiError::Validation(body)
+            | ApiError::Overloaded(body)
+            | ApiError::Other(_, body) => body,
+        }
+    }
+
+    fn body_mut(&mut self) -> &mut ErrorBody {
+        match self {
+            ApiError::NotFound(body)
+            | ApiError::Conflict(body)
+            | ApiError::Validation(body)
+            | ApiError::Overloaded(body)
+            | ApiError::Other(_, body) => body,
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        &self.body().code
+    }
+
+    /// 413 时带上上限
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.body_mut().max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// 需要二次确认时带上确认 token
+    pub fn with_confirmation(mut self, confirmation: ConfirmationRequired) -> Self {
+        self.body_mut().confirmation = Some(Box::new(confirmation));
+        self
+    }
+
+    /// 输出校验失败时带上每一次的尝试
+    pub fn with_attempts(mut self, attempts: Vec<ValidationAttempt>) -> Self {
+        self.body_mut().attempts = Some(attempts);
+        self
+    }
+
+    /// 请求队列拒绝时带上队列位置
+    pub fn with_queue_position(mut self, position: usize) -> Self {
+        self.body_mut().queue_position = Some(position);
+        self
+    }
+
+    pub fn into_response(self) -> ErrorResponse {
+        let error = match self {
+            ApiError::NotFound(body)
+            | ApiError::Conflict(body)
+            | ApiError::Validation(body)
+            | ApiError::Overloaded(body)
+            | ApiError::Other(_, body) => body,
+        };
+        ErrorResponse { error }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        status::Custom(status, Json(self.into_response())).respond_to(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_picks_the_variant_and_the_body_is_an_envelope() {
+        let e = ApiError::new(Status::NotFound, "model_not_found", "model `x` not found");
+        assert!(matches!(e, ApiError::NotFound(_)));
+        assert_eq!(e.to_string(), "model `x` not found");
+        assert_eq!(
+            serde_json::to_value(e.into_response()).unwrap(),
+            serde_json::json!({
+                "error": { "code": "model_not_found", "message": "model `x` not found" }
+            })
+        );
+
+        let e = ApiError::new(Status::PayloadTooLarge, "prompt_too_large", "too big").with_max_bytes(8);
+        assert_eq!(e.status(), Status::PayloadTooLarge);
+        assert_eq!(e.body().max_bytes, Some(8));
+        assert_eq!(ApiError::validation("bad").status(), Status::UnprocessableEntity);
+    }
+}
@@ -0,0 +1,89 @@
+//! 服务内部事件总线（tokio broadcast），目前用于模型加载和下载过程
+//!
+//! 订阅方：`GET /events`（SSE）、测试、嵌入方自己的监控。
+
+use rocket::tokio::sync::broadcast;
+use serde::Serialize;
+
+/// 每个订阅者最多缓存的事件数，慢订阅者会丢最旧的事件
+const EVENT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ModelEvent {
+    /// 开始第 `attempt` 次加载
+    LoadStarted {
+        model: String,
+        attempt: u32,
+    },
+    Loaded {
+        model: String,
+        attempt: u32,
+    },
+    LoadFailed {
+        model: String,
+        attempt: u32,
+        error: String,
+    },
+    /// 已安排 `delay_ms` 毫秒后进行第 `attempt` 次加载
+    RetryScheduled {
+        model: String,
+        attempt: u32,
+        delay_ms: u64,
+    },
+    /// 重试次数用尽，停在 Error
+    GaveUp {
+        model: String,
+        reasons: Vec<String>,
+    },
+    Unloaded {
+        model: String,
+    },
+    /// 已从 registry 删除（进了回收站）
+    Deleted {
+        model: String,
+    },
+    /// 从回收站恢复
+    Restored {
+        model: String,
+    },
+    /// 从回收站硬删除
+    Purged {
+        model: String,
+    },
+    /// `POST /models/<name>/pull` 下载完成
+    Pulled {
+        model: String,
+    },
+    PullFailed {
+        model: String,
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ModelEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        Self { sender }
+    }
+
+    /// 没有订阅者时直接丢弃
+    pub fn emit(&self, event: ModelEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ModelEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,45 @@
+//! 静态前端挂载：目录可配置、可关闭，目录不存在时退化为纯 API 服务
+
+use rocket::fairing::AdHoc;
+use rocket::fs::{FileServer, NamedFile};
+use rocket::State;
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+
+/// SPA 回退：排在 FileServer（rank 10）之后，只响应浏览器页面请求
+#[get("/<_..>", format = "text/html", rank = 20)]
+pub async fn spa_fallback(config: &State<ServerConfig>) -> Option<NamedFile> {
+    NamedFile::open(config.static_dir.join("index.html"))
+        .await
+        .ok()
+}
+
+/// 在 ignite 阶段根据 ServerConfig 决定是否挂载静态文件
+pub fn fairing() -> AdHoc {
+    AdHoc::on_ignite("Static frontend", |rocket| async move {
+        let config = match rocket.state::<ServerConfig>() {
+            Some(config) => config.clone(),
+            None => return rocket,
+        };
+
+        if !config.serve_static {
+            info!("static frontend disabled, serving API only");
+            return rocket;
+        }
+        if !config.static_dir.is_dir() {
+            warn!(
+                dir = %config.static_dir.display(),
+                "static dir not found, serving API only"
+            );
+            return rocket;
+        }
+
+        let rocket = rocket.mount("/", FileServer::from(&config.static_dir));
+        if config.spa_fallback {
+            rocket.mount("/", routes![spa_fallback])
+        } else {
+            rocket
+        }
+    })
+}
@@ -0,0 +1,421 @@
+//! 正在进行的流式生成：每条流有一个 request id（响应头 `X-Request-Id`），
+//! `POST /infer/<request_id>/pause` 暂停、`POST /infer/<request_id>/resume` 继续。
+//!
+//! 暂停时 pipeline 不再从引擎取 chunk；逐 token 解码的引擎（candle、tiny）在算下一个 token 前
+//! 检查暂停（`cancel::wait_while_paused`），解码停在循环里，解码状态和 KV cache 原样留在内存里，
+//! permit 也继续占着；其他引擎在下一次发送时阻塞。已经生成的部分照常发给客户端，方便先审阅再决定是否继续。
+//! 暂停的时间同样计入 `max_duration_ms`。session 的流（`/sessions/*`）不支持暂停。
+//!
+//! 非流式的推理（collect、共享前缀、续写）也在这里登记，只是不能暂停；
+//! `GET /admin/requests/active` 列出所有排队中和执行中的请求，方便找出占着机器的请求。
+//!
+//! 所有登记的请求都可以用 `DELETE /requests/<request_id>` 取消（见 `cancel`），排队中的直接出队，
+//! 执行中的停止解码并释放 permit。id 可以是 generation id（流式响应头 `X-Request-Id`、SSE 的第一个
+//! `event: request`），也可以是 HTTP 请求的 id：非流式请求带上自己的 `X-Request-Id` 头（见 `logging`），
+//! 就能在结果返回之前从另一个连接取消它。
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use rocket::http::{Header, Status};
+use rocket::serde::json::Json;
+use rocket::tokio::sync::{mpsc, watch};
+use rocket::State;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::AppState;
+use crate::cancel::CancelToken;
+use crate::request_log::RequestKind;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+struct Running {
+    model: String,
+    kind: RequestKind,
+    client: Option<String>,
+    /// HTTP 请求的 id（见 `logging`），取消时也认这个
+    http_id: Option<String>,
+    started: Instant,
+    /// 拿到 permit 之后为 true
+    admitted: bool,
+    tokens: Arc<AtomicUsize>,
+    paused: watch::Sender<bool>,
+    cancel: CancelToken,
+}
+
+impl Running {
+    fn state(&self) -> ActiveState {
+        match (self.admitted, *self.paused.borrow()) {
+            (_, true) => ActiveState::Paused,
+            (true, false) => ActiveState::Running,
+            (false, false) => ActiveState::Queued,
+        }
+    }
+}
+
+/// request id -> 正在进行的生成
+#[derive(Default)]
+pub struct GenerationRegistry {
+    running: Arc<Mutex<HashMap<String, Running>>>,
+    next_id: AtomicU64,
+}
+
+/// 一条生成的登记，drop 时注销
+pub struct GenerationHandle {
+    id: String,
+    running: Arc<Mutex<HashMap<String, Running>>>,
+    tokens: Arc<AtomicUsize>,
+    paused: watch::Receiver<bool>,
+    cancel: CancelToken,
+}
+
+impl Drop for GenerationHandle {
+    fn drop(&mut self) {
+        self.running.lock().remove(&self.id);
+    }
+}
+
+impl GenerationHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// `DELETE /requests/<id>` 触发的取消信号
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    /// router / fallback 解析出实际的模型后更新
+    pub fn set_model(&self, model: &str) {
+        if let Some(generation) = self.running.lock().get_mut(&self.id) {
+            generation.model = model.to_string();
+        }
+    }
+
+    /// 拿到 permit、开始执行
+    pub fn set_running(&self) {
+        if let Some(generation) = self.running.lock().get_mut(&self.id) {
+            generation.admitted = true;
+        }
+    }
+
+    /// 记下新生成的 token 数
+    pub fn add_tokens(&self, tokens: usize) {
+        self.tokens.fetch_add(tokens, Ordering::Relaxed);
+    }
+
+    /// 把引擎的输出转给 `to`，暂停期间不取；`to` 关闭时停止
+    pub async fn relay(&self, mut from: mpsc::Receiver<String>, to: mpsc::Sender<String>) {
+        let mut paused = self.paused.clone();
+        while let Some(text) = from.recv().await {
+            if paused.wait_for(|paused| !paused).await.is_err() || to.send(text).await.is_err() {
+                break;
+            }
+            self.add_tokens(1);
+        }
+    }
+}
+
+impl GenerationRegistry {
+    /// 分配 request id 并登记，`client` 是发起请求的 API key（见 `ApiKey::label`），
+    /// `http_id` 是 HTTP 请求的 id（见 `InferRequest::request_id`）。
+    /// 流式生成在拿到 permit 之前就可以暂停，开始后立即停在第一个 chunk 上
+    pub fn register(
+        &self,
+        model: &str,
+        kind: RequestKind,
+        client: Option<&str>,
+        http_id: Option<&str>,
+    ) -> GenerationHandle {
+        let id = format!("gen-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let (tx, rx) = watch::channel(false);
+        let tokens = Arc::new(AtomicUsize::new(0));
+        let cancel = CancelToken::default();
+        self.running.lock().insert(
+            id.clone(),
+            Running {
+                model: model.to_string(),
+                kind,
+                client: client.map(str::to_string),
+                http_id: http_id.map(str::to_string),
+                started: Instant::now(),
+                admitted: false,
+                tokens: tokens.clone(),
+                paused: tx,
+                cancel: cancel.clone(),
+            },
+        );
+        GenerationHandle {
+            id,
+            running: self.running.clone(),
+            tokens,
+            paused: rx,
+            cancel,
+        }
+    }
+
+    /// 取消一个请求，`id` 是 generation id 或 HTTP 请求的 id；不存在（已经结束）时返回 None
+    pub fn cancel(&self, id: &str) -> Option<CancelledRequest> {
+        let running = self.running.lock();
+        let (generation_id, generation) = running.get_key_value(id).or_else(|| {
+            running
+                .iter()
+                .find(|(_, g)| g.http_id.as_deref() == Some(id))
+        })?;
+        generation.cancel.cancel();
+        Some(CancelledRequest {
+            request_id: generation_id.clone(),
+            model: generation.model.clone(),
+            was: generation.state(),
+        })
+    }
+
+    /// 设置暂停状态；不存在（已经结束）或不是流式生成时返回 None
+    pub fn set_paused(&self, id: &str, paused: bool) -> Option<GenerationStateResponse> {
+        let running = self.running.lock();
+        let generation = running.get(id).filter(|g| g.kind == RequestKind::Stream)?;
+        generation.paused.send_replace(paused);
+        generation.cancel.set_paused(paused);
+        Some(GenerationStateResponse {
+            request_id: id.to_string(),
+            model: generation.model.clone(),
+            paused,
+        })
+    }
+
+    /// 所有排队中和执行中的请求，最早开始的在前
+    pub fn active(&self) -> Vec<ActiveRequest> {
+        let mut active: Vec<_> = self
+            .running
+            .lock()
+            .iter()
+            .map(|(id, generation)| ActiveRequest {
+                request_id: id.clone(),
+                model: generation.model.clone(),
+                kind: generation.kind,
+                state: generation.state(),
+                elapsed_ms: generation.started.elapsed().as_millis() as u64,
+                tokens: generation.tokens.load(Ordering::Relaxed),
+                client: generation.client.clone(),
+            })
+            .collect();
+        active.sort_by_key(|r| Reverse(r.elapsed_ms));
+        active
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActiveState {
+    /// 还在等 permit
+    Queued,
+    Running,
+    Paused,
+}
+
+/// 一个排队中或执行中的请求
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ActiveRequest {
+    pub request_id: String,
+    /// router / fallback 解析出实际的模型后是实际执行的模型
+    pub model: String,
+    pub kind: RequestKind,
+    pub state: ActiveState,
+    /// 从登记（含排队）到现在
+    pub elapsed_ms: u64,
+    /// 到目前为止生成的 token 数；一次性返回的请求在结束前一直是 0
+    pub tokens: usize,
+    /// 发起请求的 API key（只保留开头几个字符），不带 key 时为 null
+    pub client: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct GenerationStateResponse {
+    pub request_id: String,
+    pub model: String,
+    pub paused: bool,
+}
+
+/// `DELETE /requests/<request_id>` 的结果
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct CancelledRequest {
+    /// generation id（用 HTTP 请求的 id 取消时也返回对应的 generation id）
+    pub request_id: String,
+    pub model: String,
+    /// 取消时的状态
+    pub was: ActiveState,
+}
+
+/// 响应加上 `X-Request-Id` 头
+#[derive(Responder)]
+pub struct WithRequestId<R> {
+    inner: R,
+    request_id: Header<'static>,
+}
+
+impl<R> WithRequestId<R> {
+    pub fn new(inner: R, request_id: String) -> Self {
+        Self {
+            inner,
+            request_id: Header::new(REQUEST_ID_HEADER, request_id),
+        }
+    }
+}
+
+fn set_paused(
+    state: &AppState,
+    request_id: &str,
+    paused: bool,
+) -> Result<Json<GenerationStateResponse>, ApiError> {
+    state
+        .generations
+        .set_paused(request_id, paused)
+        .map(Json)
+        .ok_or_else(|| {
+            api_error(
+                Status::NotFound,
+                "generation_not_found",
+                format!("no running generation `{request_id}`"),
+            )
+        })
+}
+
+/// 暂停一条流式生成：POST /infer/<request_id>/pause（已经暂停时不变）
+#[utoipa::path(
+    tag = "inference",
+    responses(
+        (status = 200, body = GenerationStateResponse),
+        (status = 404, description = "no running generation with this id", body = ErrorResponse)
+    )
+)]
+#[post("/infer/<request_id>/pause")]
+pub async fn pause_generation(
+    state: &State<Arc<AppState>>,
+    request_id: &str,
+) -> Result<Json<GenerationStateResponse>, ApiError> {
+    set_paused(state, request_id, true)
+}
+
+/// 继续一条暂停的生成：POST /infer/<request_id>/resume
+#[utoipa::path(
+    tag = "inference",
+    responses(
+        (status = 200, body = GenerationStateResponse),
+        (status = 404, description = "no running generation with this id", body = ErrorResponse)
+    )
+)]
+#[post("/infer/<request_id>/resume")]
+pub async fn resume_generation(
+    state: &State<Arc<AppState>>,
+    request_id: &str,
+) -> Result<Json<GenerationStateResponse>, ApiError> {
+    set_paused(state, request_id, false)
+}
+
+/// 取消一个排队中或执行中的请求：DELETE /requests/<request_id>。
+/// 请求随即以 499 `request_cancelled` 结束（流式请求以一条 `event: error` 结束）
+#[utoipa::path(
+    tag = "inference",
+    responses(
+        (status = 200, body = CancelledRequest),
+        (status = 404, description = "no running request with this id", body = ErrorResponse)
+    )
+)]
+#[delete("/requests/<request_id>")]
+pub async fn cancel_request(
+    state: &State<Arc<AppState>>,
+    request_id: &str,
+) -> Result<Json<CancelledRequest>, ApiError> {
+    state
+        .generations
+        .cancel(request_id)
+        .map(Json)
+        .ok_or_else(|| {
+            api_error(
+                Status::NotFound,
+                "request_not_found",
+                format!("no running request `{request_id}`"),
+            )
+        })
+}
+
+/// 排队中和执行中的请求：GET /admin/requests/active
+#[utoipa::path(
+    tag = "admin",
+    responses((status = 200, body = Vec<ActiveRequest>))
+)]
+#[get("/admin/requests/active")]
+pub async fn active_requests(state: &State<Arc<AppState>>) -> Json<Vec<ActiveRequest>> {
+    Json(state.generations.active())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn active_requests_report_state_and_tokens() {
+        let registry = GenerationRegistry::default();
+        let stream = registry.register("a", RequestKind::Stream, Some("sk-a…"), None);
+        let collect = registry.register("b", RequestKind::Collect, None, None);
+        stream.set_running();
+        stream.add_tokens(3);
+
+        let mut active = registry.active();
+        active.sort_by(|a, b| a.request_id.cmp(&b.request_id));
+        let summary: Vec<_> = active
+            .iter()
+            .map(|r| (r.model.as_str(), r.state, r.tokens, r.client.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("a", ActiveState::Running, 3, Some("sk-a…")),
+                ("b", ActiveState::Queued, 0, None)
+            ]
+        );
+
+        // 非流式的请求不能暂停
+        assert!(registry.set_paused(collect.id(), true).is_none());
+        registry.set_paused(stream.id(), true).unwrap();
+        let active = registry.active();
+        let paused = active.iter().find(|r| r.request_id == stream.id());
+        assert_eq!(paused.unwrap().state, ActiveState::Paused);
+        // 解码循环看的是 cancel token 上的暂停状态
+        assert!(stream.cancel_token().is_paused());
+        registry.set_paused(stream.id(), false).unwrap();
+        assert!(!stream.cancel_token().is_paused());
+
+        drop(stream);
+        drop(collect);
+        assert!(registry.active().is_empty());
+    }
+
+    #[test]
+    fn requests_are_cancelled_by_generation_or_http_id() {
+        let registry = GenerationRegistry::default();
+        let first = registry.register("a", RequestKind::Collect, None, Some("req-1"));
+        let second = registry.register("a", RequestKind::Stream, None, None);
+        second.set_running();
+
+        let cancelled = registry.cancel("req-1").unwrap();
+        assert_eq!(cancelled.request_id, first.id());
+        assert_eq!(cancelled.was, ActiveState::Queued);
+        assert!(first.cancel_token().is_cancelled());
+        assert!(!second.cancel_token().is_cancelled());
+
+        assert_eq!(
+            registry.cancel(second.id()).unwrap().was,
+            ActiveState::Running
+        );
+        assert!(second.cancel_token().is_cancelled());
+        drop(second);
+        assert!(registry.cancel("gen-2").is_none());
+    }
+}
@@ -0,0 +1,147 @@
+//! `GET /health` 的三态健康评估：ok / degraded / unhealthy
+//!
+//! 依据：
+//! - 最近请求的错误率（见 `Metrics::error_rate`，样本太少时不看）
+//! - 等 permit 的请求数相对 `max_concurrent_infer` 的比例
+//! - 最近一段时间内的模型加载失败
+//!
+//! unhealthy 时返回 503，负载均衡器可以据此摘掉这台机器。
+
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::app_state::AppState;
+use crate::model_registry::ModelStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Degraded,
+    Unhealthy,
+}
+
+/// 判定阈值
+#[derive(Debug, Clone)]
+pub struct HealthThresholds {
+    /// 样本数少于这个时不看错误率
+    pub min_samples: usize,
+    pub degraded_error_rate: f64,
+    pub unhealthy_error_rate: f64,
+    /// 排队数 / max_concurrent_infer
+    pub degraded_queue_ratio: f64,
+    pub unhealthy_queue_ratio: f64,
+    /// 多久以内的加载失败算“最近”
+    pub load_failure_window: Duration,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            min_samples: 10,
+            degraded_error_rate: 0.1,
+            unhealthy_error_rate: 0.5,
+            degraded_queue_ratio: 1.0,
+            unhealthy_queue_ratio: 4.0,
+            load_failure_window: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// 评估结果，`reasons` 说明为什么不是 ok
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub reasons: Vec<String>,
+}
+
+impl HealthReport {
+    fn raise(&mut self, status: HealthStatus, reason: String) {
+        self.status = self.status.max(status);
+        self.reasons.push(reason);
+    }
+}
+
+pub fn assess(state: &AppState, thresholds: &HealthThresholds) -> HealthReport {
+    let mut report = HealthReport {
+        status: HealthStatus::Ok,
+        reasons: Vec::new(),
+    };
+
+    let (error_rate, samples) = state.metrics.error_rate();
+    if samples >= thresholds.min_samples {
+        let reason = format!(
+            "{:.0}% of the last {samples} requests failed",
+            error_rate * 100.0
+        );
+        if error_rate >= thresholds.unhealthy_error_rate {
+            report.raise(HealthStatus::Unhealthy, reason);
+        } else if error_rate >= thresholds.degraded_error_rate {
+            report.raise(HealthStatus::Degraded, reason);
+        }
+    }
+
+    let waiting = state.metrics.waiting_requests();
+    let limit = state.max_concurrent_infer.max(1);
+    let ratio = waiting as f64 / limit as f64;
+    let reason = format!("{waiting} requests waiting for {limit} inference slots");
+    if ratio >= thresholds.unhealthy_queue_ratio {
+        report.raise(HealthStatus::Unhealthy, reason);
+    } else if ratio >= thresholds.degraded_queue_ratio {
+        report.raise(HealthStatus::Degraded, reason);
+    }
+
+    let now = SystemTime::now();
+    let mut failed: Vec<String> = state
+        .list_models()
+        .into_iter()
+        .filter(|m| m.status == ModelStatus::Error)
+        .filter(|m| {
+            m.error.as_ref().is_some_and(|e| {
+                now.duration_since(e.at)
+                    .is_ok_and(|age| age <= thresholds.load_failure_window)
+            })
+        })
+        .map(|m| m.name)
+        .collect();
+    if !failed.is_empty() {
+        failed.sort();
+        report.raise(
+            HealthStatus::Degraded,
+            format!("recent load failures: {}", failed.join(", ")),
+        );
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_state;
+
+    #[test]
+    fn error_rate_and_load_failures_lower_the_status() {
+        let state = test_state();
+        let thresholds = HealthThresholds::default();
+        assert_eq!(assess(&state, &thresholds).status, HealthStatus::Ok);
+
+        state
+            .registry
+            .set_status("dummy-a", ModelStatus::Loading)
+            .unwrap();
+        state.registry.set_error("dummy-a", "boom").unwrap();
+        let report = assess(&state, &thresholds);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.reasons, vec!["recent load failures: dummy-a"]);
+
+        for i in 0..10 {
+            state.metrics.record_outcome(i % 2 == 0);
+        }
+        let report = assess(&state, &thresholds);
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert_eq!(report.reasons.len(), 2);
+    }
+}
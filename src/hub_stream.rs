@@ -0,0 +1,548 @@
+//! 首次从 hf-hub 拉取 GGUF 时边下载边加载，而不是等整个文件下载完再解析。
+//!
+//! 下载线程把响应体写进临时文件，加载方通过 `GrowingFile` 读同一个文件，
+//! 读到还没下载到的位置时阻塞等待。下载完成后按 hf-hub 的缓存布局
+//! （`blobs/<etag>`、`snapshots/<commit>/<file>`、`refs/main`）放好，下次直接命中缓存。
+//!
+//! 同一个 etag 同时只下载一次：后台 pull 和 `/load` 撞上时，后来的一方跟着已有的下载读，
+//! 不会再 `File::create` 同一个临时文件把对方写了一半的数据截断。
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use hf_hub::Cache;
+use parking_lot::{Condvar, Mutex};
+use tracing::info;
+
+/// 每写这么多字节通知一次读取方
+const NOTIFY_CHUNK: usize = 1 << 20;
+
+#[derive(Debug, Default)]
+struct Progress {
+    written: u64,
+    /// None：仍在下载；Some(Err)：下载失败，读取方拿到同样的错误
+    finished: Option<Result<(), String>>,
+}
+
+/// 下载线程和读取方共享的进度
+#[derive(Debug, Default)]
+pub struct DownloadProgress {
+    state: Mutex<Progress>,
+    changed: Condvar,
+    /// 服务器给出的文件大小，`SeekFrom::End` 需要
+    total: Option<u64>,
+}
+
+impl DownloadProgress {
+    pub fn new(total: Option<u64>) -> Self {
+        Self {
+            total,
+            ..Self::default()
+        }
+    }
+
+    /// 已经写进临时文件的字节数
+    pub fn written(&self) -> u64 {
+        self.state.lock().written
+    }
+
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    fn advance(&self, written: u64) {
+        self.state.lock().written = written;
+        self.changed.notify_all();
+    }
+
+    fn finish(&self, result: Result<(), String>) {
+        self.state.lock().finished = Some(result);
+        self.changed.notify_all();
+    }
+
+    /// 等到 `pos` 处有数据，返回可读的字节数；0 表示已到文件末尾
+    fn wait_for(&self, pos: u64) -> io::Result<u64> {
+        let mut state = self.state.lock();
+        loop {
+            if state.written > pos {
+                return Ok(state.written - pos);
+            }
+            match &state.finished {
+                Some(Ok(())) => return Ok(0),
+                Some(Err(e)) => return Err(io::Error::other(format!("download failed: {e}"))),
+                None => self.changed.wait(&mut state),
+            }
+        }
+    }
+}
+
+/// 把 `source` 全部写进 `sink`，边写边更新 `progress`
+pub fn copy_with_progress(
+    mut source: impl Read,
+    mut sink: File,
+    progress: &DownloadProgress,
+) -> io::Result<u64> {
+    let result = (|| {
+        let mut buf = vec![0u8; NOTIFY_CHUNK];
+        let mut written = 0u64;
+        loop {
+            let n = source.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            sink.write_all(&buf[..n])?;
+            written += n as u64;
+            progress.advance(written);
+        }
+        sink.flush()?;
+        if let Some(total) = progress.total.filter(|&t| t != written) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("expected {total} bytes, got {written}"),
+            ));
+        }
+        Ok(written)
+    })();
+    progress.finish(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+    result
+}
+
+/// 读取正在下载的文件，没下载到的部分阻塞等待
+pub struct GrowingFile {
+    file: File,
+    pos: u64,
+    progress: Arc<DownloadProgress>,
+}
+
+impl GrowingFile {
+    pub fn open(path: &Path, progress: Arc<DownloadProgress>) -> io::Result<Self> {
+        Ok(Self {
+            file: File::open(path)?,
+            pos: 0,
+            progress,
+        })
+    }
+
+    pub fn progress(&self) -> &Arc<DownloadProgress> {
+        &self.progress
+    }
+}
+
+impl Read for GrowingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.progress.wait_for(self.pos)?;
+        let want = (buf.len() as u64).min(available) as usize;
+        if want == 0 {
+            return Ok(0);
+        }
+        self.file.seek(SeekFrom::Start(self.pos))?;
+        let n = self.file.read(&mut buf[..want])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for GrowingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+            SeekFrom::End(d) => match self.progress.total {
+                Some(total) => total.checked_add_signed(d),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "file size unknown while downloading",
+                    ))
+                }
+            },
+        };
+        self.pos = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// 权重文件的来源
+pub enum HubWeights {
+    /// 已在缓存里
+    Cached(PathBuf),
+    /// 正在下载；`download` 结束时返回缓存里的路径和下载耗时
+    Streaming {
+        reader: GrowingFile,
+        download: JoinHandle<anyhow::Result<(PathBuf, Duration)>>,
+    },
+}
+
+/// 正在进行的一次下载，同一 etag 的其他调用方跟着它读
+struct InFlight {
+    tmp_path: PathBuf,
+    progress: Arc<DownloadProgress>,
+    /// 放进缓存后的路径；None：还没结束
+    installed: Mutex<Option<Result<PathBuf, String>>>,
+    done: Condvar,
+}
+
+impl InFlight {
+    fn finish(&self, result: &anyhow::Result<PathBuf>) {
+        *self.installed.lock() = Some(result.as_ref().cloned().map_err(|e| format!("{e:#}")));
+        self.done.notify_all();
+    }
+
+    fn wait(&self) -> anyhow::Result<PathBuf> {
+        let mut installed = self.installed.lock();
+        loop {
+            match &*installed {
+                Some(result) => return result.clone().map_err(anyhow::Error::msg),
+                None => self.done.wait(&mut installed),
+            }
+        }
+    }
+}
+
+/// 按 etag 记录正在进行的下载；临时文件也按 etag 命名，所以同一 etag 只能有一个写入方
+fn in_flight() -> &'static Mutex<HashMap<String, Arc<InFlight>>> {
+    static IN_FLIGHT: OnceLock<Mutex<HashMap<String, Arc<InFlight>>>> = OnceLock::new();
+    IN_FLIGHT.get_or_init(Default::default)
+}
+
+/// 缓存命中时直接返回路径，否则开始后台下载并返回可以立刻开始读的 `GrowingFile`；
+/// 同一文件已经在下载时跟着那次下载读
+pub fn open(repo: &str, filename: &str) -> anyhow::Result<HubWeights> {
+    let cache = Cache::default();
+    if let Some(path) = cache.model(repo.to_string()).get(filename) {
+        return Ok(HubWeights::Cached(path));
+    }
+    let started = Instant::now();
+    let remote = RemoteFile::resolve(&cache, repo, filename)?;
+    start(cache, remote, started)
+}
+
+fn start(cache: Cache, remote: RemoteFile, started: Instant) -> anyhow::Result<HubWeights> {
+    let mut downloads = in_flight().lock();
+    // resolve 期间别人可能刚下载完
+    if let Some(path) = cache.model(remote.repo.clone()).get(&remote.filename) {
+        return Ok(HubWeights::Cached(path));
+    }
+    if let Some(active) = downloads.get(&remote.etag).cloned() {
+        // 临时文件在持锁时才会被挪走，这里一定还在
+        let reader = GrowingFile::open(&active.tmp_path, active.progress.clone())?;
+        drop(downloads);
+        info!(
+            repo = remote.repo,
+            filename = remote.filename,
+            "joining an in-flight download"
+        );
+        let download =
+            std::thread::spawn(move || active.wait().map(|path| (path, started.elapsed())));
+        return Ok(HubWeights::Streaming { reader, download });
+    }
+
+    let tmp_dir = cache.path().join("tmp");
+    std::fs::create_dir_all(&tmp_dir)?;
+    let tmp_path = tmp_dir.join(format!("{}.part", remote.etag));
+    let sink = File::create(&tmp_path)?;
+
+    let progress = Arc::new(DownloadProgress::new(remote.size));
+    let reader = GrowingFile::open(&tmp_path, progress.clone())?;
+    let active = Arc::new(InFlight {
+        tmp_path: tmp_path.clone(),
+        progress: progress.clone(),
+        installed: Mutex::new(None),
+        done: Condvar::new(),
+    });
+    downloads.insert(remote.etag.clone(), active.clone());
+    drop(downloads);
+    info!(
+        repo = remote.repo,
+        filename = remote.filename,
+        bytes = remote.size,
+        "streaming weights from the hub"
+    );
+
+    let download = std::thread::spawn(move || {
+        let copied = copy_with_progress(remote.body, sink, &progress);
+        // 持锁放进缓存并注销，跟随方要么在挪走之前打开临时文件，要么直接命中缓存
+        let mut downloads = in_flight().lock();
+        let result = copied.map_err(anyhow::Error::from).and_then(|_| {
+            install(
+                &cache,
+                repo_folder(&remote.repo),
+                &remote.filename,
+                &remote.etag,
+                &remote.commit,
+                &tmp_path,
+            )
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        downloads.remove(&remote.etag);
+        drop(downloads);
+        active.finish(&result);
+        result.map(|path| (path, started.elapsed()))
+    });
+
+    Ok(HubWeights::Streaming { reader, download })
+}
+
+/// TLS 配置和 hf-hub 一致的 HTTP 客户端
+pub(crate) fn agent_builder() -> anyhow::Result<ureq::AgentBuilder> {
+    let tls = Arc::new(ureq::native_tls::TlsConnector::new()?);
+    Ok(ureq::AgentBuilder::new().tls_connector(tls))
+}
+
+/// 一次下载需要的远端信息，`body` 是已经打开的响应体
+struct RemoteFile {
+    repo: String,
+    filename: String,
+    etag: String,
+    commit: String,
+    size: Option<u64>,
+    body: Box<dyn Read + Send>,
+}
+
+impl RemoteFile {
+    /// 和 hf-hub 一样：第一跳拿 commit / etag，再跟随重定向（通常是 CDN）取文件
+    fn resolve(cache: &Cache, repo: &str, filename: &str) -> anyhow::Result<Self> {
+        let agent = agent_builder()?.redirects(0).build();
+        let token = cache.token();
+        let get = |url: &str| {
+            let request = agent.get(url);
+            match &token {
+                Some(token) => request.set("Authorization", &format!("Bearer {token}")),
+                None => request,
+            }
+        };
+
+        let url = format!("https://huggingface.co/{repo}/resolve/main/{filename}");
+        let mut response = get(&url).call()?;
+        let etag = response
+            .header("x-linked-etag")
+            .or_else(|| response.header("etag"))
+            .ok_or_else(|| anyhow::anyhow!("missing etag for {repo}/{filename}"))?
+            .replace('"', "");
+        let commit = response
+            .header("x-repo-commit")
+            .ok_or_else(|| anyhow::anyhow!("missing x-repo-commit for {repo}/{filename}"))?
+            .to_string();
+
+        // 最多跟随几次重定向
+        for _ in 0..5 {
+            if !(300..400).contains(&response.status()) {
+                break;
+            }
+            let location = response
+                .header("location")
+                .ok_or_else(|| anyhow::anyhow!("redirect without location"))?;
+            let next = match location.starts_with('/') {
+                true => format!("https://huggingface.co{location}"),
+                false => location.to_string(),
+            };
+            response = get(&next).call()?;
+        }
+
+        let size = response
+            .header("content-length")
+            .and_then(|len| len.parse().ok());
+        Ok(Self {
+            repo: repo.to_string(),
+            filename: filename.to_string(),
+            etag,
+            commit,
+            size,
+            body: response.into_reader(),
+        })
+    }
+}
+
+/// `acme/foo` → `models--acme--foo`
+fn repo_folder(repo: &str) -> String {
+    format!("models--{}", repo.replace('/', "--"))
+}
+
+/// 把下载好的临时文件放进 hf-hub 缓存，返回 snapshot 里的路径
+fn install(
+    cache: &Cache,
+    folder: String,
+    filename: &str,
+    etag: &str,
+    commit: &str,
+    tmp_path: &Path,
+) -> anyhow::Result<PathBuf> {
+    let repo_dir = cache.path().join(folder);
+    let blob = repo_dir.join("blobs").join(etag);
+    std::fs::create_dir_all(repo_dir.join("blobs"))?;
+    std::fs::rename(tmp_path, &blob)?;
+
+    let pointer = repo_dir.join("snapshots").join(commit).join(filename);
+    if let Some(parent) = pointer.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let _ = std::fs::remove_file(&pointer);
+    let depth = Path::new(filename).components().count() + 1;
+    let relative = (0..depth)
+        .fold(PathBuf::new(), |p, _| p.join(".."))
+        .join("blobs")
+        .join(etag);
+    link_snapshot(&blob, &relative, &pointer)?;
+
+    std::fs::create_dir_all(repo_dir.join("refs"))?;
+    std::fs::write(repo_dir.join("refs").join("main"), commit)?;
+    Ok(pointer)
+}
+
+/// snapshot 里的文件是指向 blob 的相对符号链接，和 hf-hub 一致
+#[cfg(unix)]
+fn link_snapshot(_blob: &Path, relative: &Path, pointer: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(relative, pointer)
+}
+
+/// 其他平台上建符号链接常常需要额外权限，直接复制一份 blob
+#[cfg(not(unix))]
+fn link_snapshot(blob: &Path, _relative: &Path, pointer: &Path) -> io::Result<()> {
+    std::fs::copy(blob, pointer).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每次只吐一小段、并且会停顿的数据源
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(1));
+            let n = buf.len().min(7).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn reader_follows_download() {
+        let data: Vec<u8> = (0..500u32).map(|i| i as u8).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.part");
+        let sink = File::create(&path).unwrap();
+        let progress = Arc::new(DownloadProgress::new(Some(data.len() as u64)));
+        let mut reader = GrowingFile::open(&path, progress.clone()).unwrap();
+
+        let source = Trickle {
+            data: data.clone(),
+            pos: 0,
+        };
+        let writer = std::thread::spawn(move || copy_with_progress(source, sink, &progress));
+
+        let mut tail = [0u8; 10];
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        reader.read_exact(&mut tail).unwrap();
+        assert_eq!(tail, data[490..]);
+
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+        assert_eq!(writer.join().unwrap().unwrap(), 500);
+    }
+
+    #[test]
+    fn short_download_fails_the_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.part");
+        let sink = File::create(&path).unwrap();
+        let progress = Arc::new(DownloadProgress::new(Some(100)));
+        let mut reader = GrowingFile::open(&path, progress.clone()).unwrap();
+
+        assert!(copy_with_progress(&[1u8; 40][..], sink, &progress).is_err());
+        let mut buf = Vec::new();
+        let err = reader.read_to_end(&mut buf).unwrap_err();
+        assert!(err.to_string().contains("download failed"), "{err}");
+    }
+
+    fn remote(etag: &str, body: impl Read + Send + 'static) -> RemoteFile {
+        RemoteFile {
+            repo: "acme/foo".to_string(),
+            filename: "foo.gguf".to_string(),
+            etag: etag.to_string(),
+            commit: "c0ffee".to_string(),
+            size: Some(500),
+            body: Box::new(body),
+        }
+    }
+
+    #[test]
+    fn concurrent_opens_share_one_download() {
+        let data: Vec<u8> = (0..500u32).map(|i| i as u8).collect();
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path().to_path_buf());
+        let trickle = Trickle {
+            data: data.clone(),
+            pos: 0,
+        };
+        let first = start(cache.clone(), remote("shared", trickle), Instant::now()).unwrap();
+        // 第二方的响应体不会被读：要是也写临时文件，就会把第一方的数据截断
+        let second = start(
+            cache.clone(),
+            remote("shared", &[0u8; 0][..]),
+            Instant::now(),
+        )
+        .unwrap();
+
+        let mut paths = Vec::new();
+        for weights in [first, second] {
+            let HubWeights::Streaming {
+                mut reader,
+                download,
+            } = weights
+            else {
+                panic!("expected a streaming download");
+            };
+            let mut all = Vec::new();
+            reader.read_to_end(&mut all).unwrap();
+            assert_eq!(all, data);
+            paths.push(download.join().unwrap().unwrap().0);
+        }
+        assert_eq!(paths[0], paths[1]);
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), data);
+        assert!(in_flight().lock().get("shared").is_none());
+
+        // 下载完之后直接命中缓存
+        let again = start(cache, remote("shared", &[0u8; 0][..]), Instant::now()).unwrap();
+        assert!(matches!(again, HubWeights::Cached(path) if path == paths[0]));
+    }
+
+    #[test]
+    fn install_uses_hub_cache_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path().to_path_buf());
+        let tmp = dir.path().join("x.part");
+        std::fs::write(&tmp, b"gguf").unwrap();
+
+        let path = install(
+            &cache,
+            repo_folder("acme/foo"),
+            "foo.gguf",
+            "abc",
+            "c0ffee",
+            &tmp,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"gguf");
+        assert_eq!(
+            cache.model("acme/foo".to_string()).get("foo.gguf"),
+            Some(path)
+        );
+    }
+}
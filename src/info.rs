@@ -0,0 +1,142 @@
+//! 这个二进制能做什么（`GET /info`）：版本、git commit、编译进来的 feature 和 candle 后端、
+//! 支持的模型结构、编译期的上限。写 bug 报告时附上，客户端也可以据此调整请求。
+//! 启动时打印同样内容的一行摘要（`banner`）。
+
+use std::sync::Arc;
+
+use candle_core::utils;
+use candle_transformers::models::quantized_llama as qllama;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::app_state::AppState;
+use crate::model_registry::{EngineKind, DEFAULT_CONTEXT_WINDOW};
+use crate::{assistant, diffusion, pipeline};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// 由 build.rs 写入，不在 git 仓库里构建时为 `unknown`
+pub const GIT_COMMIT: &str = env!("LOCAL_LLM_GIT_COMMIT");
+pub const CANDLE_VERSION: &str = env!("LOCAL_LLM_CANDLE_VERSION");
+
+/// 本 crate 的 cargo feature 及是否打开
+const FEATURES: &[(&str, bool)] = &[("cuda", cfg!(feature = "cuda"))];
+
+/// 各引擎能加载的模型结构
+const ARCHITECTURES: &[(EngineKind, &[&str])] = &[
+    (EngineKind::CANDLE, &["llama", "mistral"]),
+    (EngineKind::TINY, &["llama"]),
+    (EngineKind::EMBEDDING, &["bert"]),
+    (EngineKind::DIFFUSION, &["stable-diffusion"]),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ServerInfo {
+    pub version: String,
+    pub git_commit: String,
+    pub candle_version: String,
+    /// 编译时打开的 cargo feature
+    pub features: Vec<String>,
+    /// candle 编译进来的后端和指令集：cuda / metal / mkl / accelerate / avx / neon / simd128 / f16c
+    pub accelerators: Vec<String>,
+    /// 注册了引擎工厂的 engine_kind
+    pub engine_kinds: Vec<String>,
+    /// 这些引擎能加载的模型结构
+    pub architectures: Vec<String>,
+    pub limits: BuildLimits,
+}
+
+/// 编译期的上限（运行时配置的上限见 `ServerConfig`）
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BuildLimits {
+    /// GGUF 引擎 KV cache 的最大长度（prompt + 生成）
+    pub max_seq_len: usize,
+    /// 模型没有配置时的 context window
+    pub default_context_window: usize,
+    /// 非流式请求默认生成的 token 数
+    pub collect_max_tokens: usize,
+    /// 流式请求默认生成的 token 数
+    pub stream_max_tokens: usize,
+    pub max_image_size: usize,
+    pub max_images: usize,
+    pub max_diffusion_steps: usize,
+    pub max_assistant_steps: usize,
+}
+
+impl ServerInfo {
+    pub fn collect(state: &AppState) -> Self {
+        let kinds = state.factories.kinds();
+        let mut architectures: Vec<String> = ARCHITECTURES
+            .iter()
+            .filter(|(kind, _)| kinds.contains(kind))
+            .flat_map(|(_, archs)| archs.iter().map(|a| a.to_string()))
+            .collect();
+        architectures.sort();
+        architectures.dedup();
+        let accelerators = [
+            ("cuda", utils::cuda_is_available()),
+            ("metal", utils::metal_is_available()),
+            ("mkl", utils::has_mkl()),
+            ("accelerate", utils::has_accelerate()),
+            ("avx", utils::with_avx()),
+            ("neon", utils::with_neon()),
+            ("simd128", utils::with_simd128()),
+            ("f16c", utils::with_f16c()),
+        ];
+        Self {
+            version: VERSION.to_string(),
+            git_commit: GIT_COMMIT.to_string(),
+            candle_version: CANDLE_VERSION.to_string(),
+            features: enabled(FEATURES),
+            accelerators: enabled(&accelerators),
+            engine_kinds: kinds.iter().map(|k| k.to_string()).collect(),
+            architectures,
+            limits: BuildLimits {
+                max_seq_len: qllama::MAX_SEQ_LEN,
+                default_context_window: DEFAULT_CONTEXT_WINDOW,
+                collect_max_tokens: pipeline::COLLECT_MAX_TOKENS,
+                stream_max_tokens: pipeline::STREAM_MAX_TOKENS,
+                max_image_size: diffusion::MAX_IMAGE_SIZE,
+                max_images: diffusion::MAX_IMAGES,
+                max_diffusion_steps: diffusion::MAX_STEPS,
+                max_assistant_steps: assistant::MAX_STEPS_LIMIT,
+            },
+        }
+    }
+
+    /// 启动时打印的一行摘要
+    pub fn banner(&self) -> String {
+        let list = |items: &[String]| {
+            if items.is_empty() {
+                "none".to_string()
+            } else {
+                items.join(", ")
+            }
+        };
+        format!(
+            "local-llm-server {} ({}), candle {}, features: {}, accelerators: {}, engines: {}",
+            self.version,
+            self.git_commit,
+            self.candle_version,
+            list(&self.features),
+            list(&self.accelerators),
+            list(&self.engine_kinds),
+        )
+    }
+}
+
+fn enabled(flags: &[(&str, bool)]) -> Vec<String> {
+    flags
+        .iter()
+        .filter(|(_, on)| *on)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// 版本和能力：GET /info
+#[utoipa::path(tag = "ops", responses((status = 200, body = ServerInfo)))]
+#[get("/info")]
+pub async fn get_info(state: &State<Arc<AppState>>) -> Json<ServerInfo> {
+    Json(ServerInfo::collect(state))
+}
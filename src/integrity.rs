@@ -0,0 +1,260 @@
+//! GGUF 完整性扫描：遍历缓存目录，逐个检查
+//! 1) GGUF 头能否解析、tensor 数据区是否完整；
+//! 2) sha256 是否和记录的一致。
+//!
+//! 记录的 checksum 有两个来源：hf-hub 缓存里 LFS 文件的 blob 名本身就是 sha256；
+//! 其他文件可以放一个同名的 `<file>.sha256` sidecar。
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use candle_core::quantized::gguf_file;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::jobs::JobHandle;
+
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub cache_dir: PathBuf,
+    /// 损坏的 hub 文件是否删除后重新下载
+    pub redownload: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Ok,
+    /// 能解析但没有可对比的 checksum
+    Unverified,
+    ParseError,
+    Truncated,
+    ChecksumMismatch,
+}
+
+impl FileStatus {
+    pub fn is_corrupt(self) -> bool {
+        matches!(
+            self,
+            FileStatus::ParseError | FileStatus::Truncated | FileStatus::ChecksumMismatch
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub size: u64,
+    /// 来自 hf-hub 缓存时的 repo id
+    pub repo: Option<String>,
+    pub status: FileStatus,
+    pub detail: Option<String>,
+    pub expected_sha256: Option<String>,
+    pub actual_sha256: Option<String>,
+    pub redownloaded: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanReport {
+    pub cache_dir: PathBuf,
+    pub scanned: usize,
+    pub corrupt: usize,
+    pub files: Vec<FileReport>,
+}
+
+/// 扫描整个目录；`job` 用于汇报进度
+pub fn scan(options: &ScanOptions, job: Option<&JobHandle>) -> anyhow::Result<ScanReport> {
+    let mut paths = Vec::new();
+    collect_gguf_files(&options.cache_dir, &mut paths)?;
+    paths.sort();
+
+    let mut files = Vec::with_capacity(paths.len());
+    for (i, path) in paths.iter().enumerate() {
+        let mut report = check_file(path);
+        if report.status.is_corrupt() && options.redownload {
+            if let Err(e) = redownload(&options.cache_dir, path) {
+                report.detail = Some(format!(
+                    "{}; re-download failed: {e}",
+                    report.detail.unwrap_or_default()
+                ));
+            } else {
+                report = FileReport {
+                    redownloaded: true,
+                    ..check_file(path)
+                };
+            }
+        }
+        files.push(report);
+        if let Some(job) = job {
+            job.set_progress((i + 1) as f32 / paths.len() as f32);
+        }
+    }
+
+    Ok(ScanReport {
+        cache_dir: options.cache_dir.clone(),
+        scanned: files.len(),
+        corrupt: files.iter().filter(|f| f.status.is_corrupt()).count(),
+        files,
+    })
+}
+
+/// 检查单个 GGUF 文件
+pub fn check_file(path: &Path) -> FileReport {
+    let mut report = FileReport {
+        path: path.to_path_buf(),
+        size: std::fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        repo: hub_location(path).map(|(repo, _)| repo),
+        status: FileStatus::Ok,
+        detail: None,
+        expected_sha256: expected_sha256(path),
+        actual_sha256: None,
+        redownloaded: false,
+    };
+
+    if let Err((status, detail)) = check_structure(path, report.size) {
+        report.status = status;
+        report.detail = Some(detail);
+        return report;
+    }
+
+    match sha256_file(path) {
+        Ok(actual) => {
+            report.status = match &report.expected_sha256 {
+                Some(expected) if *expected == actual => FileStatus::Ok,
+                Some(_) => FileStatus::ChecksumMismatch,
+                None => FileStatus::Unverified,
+            };
+            report.actual_sha256 = Some(actual);
+        }
+        Err(e) => {
+            report.status = FileStatus::ParseError;
+            report.detail = Some(format!("failed to read file: {e}"));
+        }
+    }
+    report
+}
+
+/// 解析 GGUF 头，并确认所有 tensor 的数据都落在文件范围内
+fn check_structure(path: &Path, size: u64) -> Result<(), (FileStatus, String)> {
+    let mut file = File::open(path).map_err(|e| (FileStatus::ParseError, e.to_string()))?;
+    let content = gguf_file::Content::read(&mut file)
+        .map_err(|e| (FileStatus::ParseError, format!("invalid GGUF: {e}")))?;
+
+    let data_end = content
+        .tensor_infos
+        .values()
+        .map(|t| {
+            let bytes = t.shape.elem_count() * t.ggml_dtype.type_size() / t.ggml_dtype.block_size();
+            content.tensor_data_offset + t.offset + bytes as u64
+        })
+        .max()
+        .unwrap_or(content.tensor_data_offset);
+    if data_end > size {
+        return Err((
+            FileStatus::Truncated,
+            format!("tensor data ends at byte {data_end} but file is {size} bytes"),
+        ));
+    }
+    Ok(())
+}
+
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// 记录的 checksum：hub blob 名 > `.sha256` sidecar
+fn expected_sha256(path: &Path) -> Option<String> {
+    let is_sha = |s: &str| s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit());
+
+    if let Ok(blob) = std::fs::canonicalize(path) {
+        if let Some(name) = blob.file_name().and_then(|n| n.to_str()) {
+            if is_sha(name) {
+                return Some(name.to_ascii_lowercase());
+            }
+        }
+    }
+
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    let text = std::fs::read_to_string(PathBuf::from(sidecar)).ok()?;
+    let token = text.split_whitespace().next()?;
+    is_sha(token).then(|| token.to_ascii_lowercase())
+}
+
+/// hf-hub 缓存布局：`models--{org}--{name}/snapshots/{rev}/{filename}`
+fn hub_location(path: &Path) -> Option<(String, String)> {
+    let components: Vec<_> = path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    let snap = components.iter().rposition(|c| c == "snapshots")?;
+    let repo_dir = components.get(snap.checked_sub(1)?)?;
+    let repo = repo_dir.strip_prefix("models--")?.replace("--", "/");
+    let filename = components.get(snap + 2..)?.join("/");
+    (!filename.is_empty()).then_some((repo, filename))
+}
+
+fn collect_gguf_files(dir: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_gguf_files(&path, out)?;
+        } else if path.extension().is_some_and(|ext| ext == "gguf") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// 删除损坏的 blob 和 snapshot 指针，再从 hub 重新下载
+fn redownload(cache_dir: &Path, path: &Path) -> anyhow::Result<()> {
+    let (repo, filename) = hub_location(path)
+        .ok_or_else(|| anyhow::anyhow!("not a hub-managed file, cannot re-download"))?;
+
+    if let Ok(blob) = std::fs::canonicalize(path) {
+        if blob != path {
+            std::fs::remove_file(&blob)?;
+        }
+    }
+    std::fs::remove_file(path)?;
+
+    let api = hf_hub::api::sync::ApiBuilder::new()
+        .with_cache_dir(cache_dir.to_path_buf())
+        .with_progress(false)
+        .build()?;
+    api.model(repo).download(&filename)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hub_location() {
+        let path = Path::new("/cache/models--TheBloke--Foo-GGUF/snapshots/abc123/foo.Q2_K.gguf");
+        assert_eq!(
+            hub_location(path),
+            Some(("TheBloke/Foo-GGUF".to_string(), "foo.Q2_K.gguf".to_string()))
+        );
+        assert_eq!(hub_location(Path::new("/models/local.gguf")), None);
+    }
+}
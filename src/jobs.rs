@@ -0,0 +1,163 @@
+//! 后台任务登记表：耗时操作（完整性扫描等）以 job 形式运行，
+//! 客户端拿到 job id 后通过 `GET /jobs/<id>` 轮询状态和结果。
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub kind: String,
+    pub status: JobStatus,
+    pub created_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+    /// 0.0 ~ 1.0，任务自己汇报
+    pub progress: Option<f32>,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct JobRegistry {
+    next_id: AtomicU64,
+    jobs: RwLock<HashMap<String, JobRecord>>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记一个新任务，返回 id（状态 Pending）
+    pub fn create(&self, kind: &str) -> String {
+        let id = format!("job-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let record = JobRecord {
+            id: id.clone(),
+            kind: kind.to_string(),
+            status: JobStatus::Pending,
+            created_at: SystemTime::now(),
+            finished_at: None,
+            progress: None,
+            result: None,
+            error: None,
+        };
+        self.jobs.write().insert(id.clone(), record);
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobRecord> {
+        self.jobs.read().get(id).cloned()
+    }
+
+    /// 按创建时间倒序
+    pub fn list(&self) -> Vec<JobRecord> {
+        let mut jobs: Vec<_> = self.jobs.read().values().cloned().collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        jobs
+    }
+
+    pub fn start(&self, id: &str) {
+        self.update(id, |job| job.status = JobStatus::Running);
+    }
+
+    pub fn set_progress(&self, id: &str, progress: f32) {
+        self.update(id, |job| job.progress = Some(progress.clamp(0.0, 1.0)));
+    }
+
+    pub fn succeed(&self, id: &str, result: serde_json::Value) {
+        self.update(id, |job| {
+            job.status = JobStatus::Succeeded;
+            job.progress = Some(1.0);
+            job.result = Some(result);
+            job.finished_at = Some(SystemTime::now());
+        });
+    }
+
+    pub fn fail(&self, id: &str, error: impl Into<String>) {
+        let error = error.into();
+        self.update(id, |job| {
+            job.status = JobStatus::Failed;
+            job.error = Some(error);
+            job.finished_at = Some(SystemTime::now());
+        });
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut JobRecord)) {
+        if let Some(job) = self.jobs.write().get_mut(id) {
+            f(job);
+        }
+    }
+
+    /// 在阻塞线程池里运行任务，结果序列化后写回登记表
+    pub fn spawn_blocking<T, F>(self: &Arc<Self>, kind: &str, work: F) -> String
+    where
+        T: Serialize,
+        F: FnOnce(&JobHandle) -> anyhow::Result<T> + Send + 'static,
+    {
+        let id = self.create(kind);
+        let handle = JobHandle {
+            id: id.clone(),
+            jobs: self.clone(),
+        };
+        rocket::tokio::task::spawn_blocking(move || {
+            handle.jobs.start(&handle.id);
+            match work(&handle).and_then(|r| Ok(serde_json::to_value(r)?)) {
+                Ok(result) => handle.jobs.succeed(&handle.id, result),
+                Err(e) => handle.jobs.fail(&handle.id, format!("{e:#}")),
+            }
+        });
+        id
+    }
+
+    /// 同 `spawn_blocking`，但任务本身是 async 的（例如要调用推理引擎）
+    pub fn spawn<T, F, Fut>(self: &Arc<Self>, kind: &str, work: F) -> String
+    where
+        T: Serialize,
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = anyhow::Result<T>> + Send + 'static,
+    {
+        let id = self.create(kind);
+        let work = work(JobHandle {
+            id: id.clone(),
+            jobs: self.clone(),
+        });
+        let jobs = self.clone();
+        let job_id = id.clone();
+        rocket::tokio::spawn(async move {
+            jobs.start(&job_id);
+            match work.await.and_then(|r| Ok(serde_json::to_value(r)?)) {
+                Ok(result) => jobs.succeed(&job_id, result),
+                Err(e) => jobs.fail(&job_id, format!("{e:#}")),
+            }
+        });
+        id
+    }
+}
+
+/// 传给任务本身，用来汇报进度
+pub struct JobHandle {
+    pub id: String,
+    jobs: Arc<JobRegistry>,
+}
+
+impl JobHandle {
+    pub fn set_progress(&self, progress: f32) {
+        self.jobs.set_progress(&self.id, progress);
+    }
+}
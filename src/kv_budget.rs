@@ -0,0 +1,174 @@
+//! KV cache 的软内存上限（`[default.kv_budget] soft_limit_mb = 4096`）：
+//! 准入时按「prompt + max_tokens」估算这条请求的 KV cache 大小并记账，请求结束时归还。
+//! 加上它会超过上限时先等 `queue_timeout_ms`，仍然放不下就拒绝（503，稍后重试），
+//! 单条请求就比上限大时直接拒绝（413）——而不是让进程被 OOM kill。
+//!
+//! 每个 token 的字节数由引擎给出（`InferenceEngine::kv_bytes_per_token`），不提供的引擎不记账。
+//! 没有配置上限时只记账，不拦截。
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rocket::tokio::sync::Notify;
+use rocket::tokio::time::{timeout_at, Instant};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KvBudgetConfig {
+    /// 所有进行中请求的 KV cache 合计上限，None 表示不限
+    pub soft_limit_mb: Option<u64>,
+    /// 放不下时最多等多久（毫秒）别的请求结束，0 表示立即拒绝
+    pub queue_timeout_ms: u64,
+}
+
+/// 准入失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetRefusal {
+    /// 单条请求就超过上限，重试也没用
+    TooLarge { required: u64, limit: u64 },
+    /// 现在放不下，别的请求结束后可以重试
+    Busy {
+        required: u64,
+        in_use: u64,
+        limit: u64,
+    },
+}
+
+pub struct KvBudget {
+    limit: Option<u64>,
+    queue_timeout: Duration,
+    in_use: Mutex<u64>,
+    released: Notify,
+}
+
+impl KvBudget {
+    pub fn new(config: &KvBudgetConfig) -> Arc<Self> {
+        Arc::new(Self {
+            limit: config.soft_limit_mb.map(|mb| mb << 20),
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            in_use: Mutex::new(0),
+            released: Notify::new(),
+        })
+    }
+
+    pub fn limit_bytes(&self) -> Option<u64> {
+        self.limit
+    }
+
+    /// 当前记账的字节数
+    pub fn in_use_bytes(&self) -> u64 {
+        *self.in_use.lock()
+    }
+
+    fn try_reserve(self: &Arc<Self>, bytes: u64) -> Result<KvReservation, BudgetRefusal> {
+        let mut in_use = self.in_use.lock();
+        if let Some(limit) = self.limit {
+            if bytes > limit {
+                return Err(BudgetRefusal::TooLarge {
+                    required: bytes,
+                    limit,
+                });
+            }
+            if *in_use + bytes > limit {
+                return Err(BudgetRefusal::Busy {
+                    required: bytes,
+                    in_use: *in_use,
+                    limit,
+                });
+            }
+        }
+        *in_use += bytes;
+        Ok(KvReservation {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    /// 记账 `bytes`；放不下时等别的请求归还，最多等 `queue_timeout_ms`
+    pub async fn reserve(self: &Arc<Self>, bytes: u64) -> Result<KvReservation, BudgetRefusal> {
+        let deadline = Instant::now() + self.queue_timeout;
+        loop {
+            // 先登记等待再检查，避免错过检查和等待之间的归还
+            let released = self.released.notified();
+            match self.try_reserve(bytes) {
+                Err(BudgetRefusal::Busy { .. }) if Instant::now() < deadline => {
+                    if timeout_at(deadline, released).await.is_err() {
+                        return self.try_reserve(bytes);
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// 一条请求记账的 KV cache，drop 时归还
+pub struct KvReservation {
+    budget: Arc<KvBudget>,
+    bytes: u64,
+}
+
+impl KvReservation {
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for KvReservation {
+    fn drop(&mut self) {
+        *self.budget.in_use.lock() -= self.bytes;
+        self.budget.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(limit_mb: u64, queue_timeout_ms: u64) -> Arc<KvBudget> {
+        KvBudget::new(&KvBudgetConfig {
+            soft_limit_mb: Some(limit_mb),
+            queue_timeout_ms,
+        })
+    }
+
+    #[rocket::async_test]
+    async fn refuses_oversized_and_busy_requests() {
+        let budget = budget(10, 0);
+        let first = budget.reserve(6 << 20).await.unwrap();
+        assert_eq!(budget.in_use_bytes(), 6 << 20);
+        assert!(matches!(
+            budget.reserve(11 << 20).await,
+            Err(BudgetRefusal::TooLarge { .. })
+        ));
+        assert!(matches!(
+            budget.reserve(6 << 20).await,
+            Err(BudgetRefusal::Busy { in_use, .. }) if in_use == 6 << 20
+        ));
+        drop(first);
+        assert_eq!(budget.in_use_bytes(), 0);
+        assert!(budget.reserve(6 << 20).await.is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn queued_request_is_admitted_when_memory_is_released() {
+        let budget = budget(10, 1_000);
+        let first = budget.reserve(8 << 20).await.unwrap();
+        let release = async {
+            rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(first);
+        };
+        let (_, second) = rocket::tokio::join!(release, budget.reserve(8 << 20));
+        assert_eq!(second.unwrap().bytes(), 8 << 20);
+    }
+
+    #[rocket::async_test]
+    async fn without_a_limit_it_only_counts() {
+        let budget = KvBudget::new(&KvBudgetConfig::default());
+        let _a = budget.reserve(u64::MAX / 2).await.unwrap();
+        let _b = budget.reserve(1 << 40).await.unwrap();
+        assert_eq!(budget.limit_bytes(), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! 本地 LLM 推理服务的核心库
+//!
+//! - `device`: 设备描述（cpu / cuda:n / metal:n），`affinity` 把 CPU 推理的计算线程绑定到指定核心 / NUMA 节点
+//! - `engine`: InferenceEngine 抽象与 Dummy / Candle 实现，`repetition` 负责解码时的重复检测
+//! - `stop`: 每个解码步调用的停止条件（结束符、停止串、时间上限、重复检测），嵌入本库时可以自己扩展
+//! - `diffusion`: Stable Diffusion 文生图引擎，以及 `/v1/images/generations` 结果的暂存
+//! - `tiny`: 内置的小模型（`engine_kind = "tiny"`），输出可复现，用于端到端测试
+//! - `engine_factory`: EngineKind -> 引擎构造函数的注册表（可扩展）
+//! - `model_registry`: 模型元信息与状态，`manifest` 提供离线部署用的本地模型清单，`hub_stream` 负责首次拉取时边下载边加载，
+//!   `discovery` 自动注册模型目录里的 GGUF（启动时和 `POST /models/rescan`），
+//!   `pull` 提前把 hub 上的权重下载进缓存（`POST /models/<name>/pull`，进度走 SSE）
+//! - `catalog`: 可一键安装的 GGUF 模型目录（`GET /catalog`），`quant_bench` 对比同一模型的不同量化版本
+//! - `self_test`: 加载后、上线前跑几条内置 prompt 检查输出、结束符和耗时，没通过就停在 Error
+//! - `loader`: 模型加载专用的低优先级线程池，加载时不拖慢正在进行的生成
+//! - `memory`: 加载前按 GGUF 头估算内存（`GET /models/<name>/estimate`），放不下时拒绝加载
+//! - `kv_budget`: 请求准入时估算 KV cache，超过软上限时排队或拒绝长上下文请求
+//! - `queue`: 等推理 permit 的请求队列，满了返回 429、等太久返回 503，都带队列位置
+//! - `app_state`: 全局共享状态（registry + engines + 并发控制），`scratch` 管理临时加载的模型
+//! - `bulk`: 一次提交一组加载 / 卸载操作（`POST /models/bulk`），先卸载后加载，在后台 job 里执行
+//! - `balancer`: 同一模型多个副本之间的负载均衡，`standby` 给主模型配一个热备实例，重新加载或出错时接管
+//! - `batcher`: `mode: "throughput"` 请求的攒批调度
+//! - `generations`: 正在进行的流式生成（响应头 `X-Request-Id`），可以暂停 / 继续（`/infer/<id>/pause`、`/resume`），以及 `/admin/requests/active` 列出的进行中请求；`cancel` 负责 `DELETE /requests/<id>` 的取消信号
+//! - `preemption`: `priority: "high"` 的请求没有空闲 permit 时抢占运行最久的 `low` 生成，
+//!   `time_slice` 让显存只够放权重的 GPU 上的几个模型按轮次分时生成
+//! - `events`: 模型加载事件广播（`GET /events`）
+//! - `jobs`: 后台任务登记表（`GET /jobs/<id>`）
+//! - `info`: 版本、git commit、编译进来的 feature 等能力信息（`GET /info`，启动时打印摘要）
+//! - `metrics`: permit 等待时间等运行指标（`GET /metrics`），`health` 据此给出 ok / degraded / unhealthy，
+//!   `request_log` 记录每个推理请求，可导出成 JSON Lines / CSV（`GET /admin/export`）
+//! - `perf_history`: 每个模型最近一小时 / 一天的延迟和速度分位数（`GET /models/<name>/perf`）
+//! - `integrity` / `admin`: GGUF 缓存完整性扫描、卸载 / 删除模型等运维接口，`confirm` 负责破坏性操作的二次确认
+//! - `admin_api`: 自带前端的管理页用的 `/admin/api/*`（加载进度、队列、取消、用量曲线），不属于稳定的公开接口
+//! - `pipeline`: 所有推理路由共用的 validate → admit → execute 流程
+//! - `router`: 虚拟 router 模型，按 prompt 语言 / 正则转发到真实模型
+//! - `chat_template`: chat 微调模型的 prompt 模板，请求带 `auto_template` 时给裸 prompt 自动套上
+//! - `output_validation`: 请求带 `validate` 时检查完整输出（JSON / 正则 / 最大长度），没通过自动换 seed 重试
+//! - `session` / `chat`: 服务端多轮对话，`prompt_compression` 负责超长历史的压缩，`session_stream` 把生成广播给订阅者
+//! - `stream_stats`: 流式输出里定时发送的 chunk 速度（`event: stats`）
+//! - `token_trace`: 请求带 `trace` 时记录逐 token 的解码耗时，用于排查周期性卡顿
+//! - `embedding` / `rag`: 句向量模型，以及 embedding + 对话模型配对的 RAG profile（`/rag/<profile>/*`）
+//! - `embedding_cache`: 按（模型，文本哈希）缓存 embedding 向量，可以持久化到磁盘
+//! - `assistant`: 服务端执行工具调用的循环（`POST /assistant/runs`），工具在 `tools` 里注册（`GET /tools`）
+//! - `api`: Rocket 路由，`build_rocket` 负责组装
+//! - `error`: HTTP 错误（`ApiError`）：404 / 409 / 422 / 429 等状态码，统一的 `{error: {code, message}}` 响应体
+//! - `openai`: OpenAI 兼容接口（`/v1/chat/completions` 含 `chat.completion.chunk` 流式格式，`/v1/completions` 旧版文本补全，`/v1/models` 模型列表，`/v1/images/generations` 文生图）
+//! - `versioning`: 原生接口的 `/api/v1` 前缀，旧路径作为兼容别名（`X-API-Version`、`Deprecation` 响应头）
+//! - `openapi`: 由路由和 `types` 生成的 OpenAPI 文档（`GET /openapi.json`，Swagger UI 在 `/docs`）
+//! - `config` / `frontend`: 服务端配置与静态前端挂载，`api_keys` 为每个 API key 提供参数 profile（`require_api_key` 时推理和管理接口必须带 key），`cli` 是命令行参数
+//! - `compression`: 非流式响应的 gzip / deflate 压缩
+//! - `access`: 只监听本机、客户端 IP 白名单（在 API key 之外的网络层限制），`rate_limit` 按 API key / IP 限制每分钟的请求数和 token 数
+//! - `privacy`: 隐私模式下日志里的 prompt / 输出只记哈希和长度
+//! - `logging`: `tracing` 结构化日志（文本或 JSON），每个请求一个 UUID（`X-Request-Id`）
+//! - `tags`: 请求带的自由格式标签，记进日志和请求日志，配置过的键作为 `/metrics` 标签
+//! - `trash`: 删除的模型先进回收站（可恢复，权重留在磁盘上），过了保留期由后台任务硬删除
+//!
+//! 二进制 `main.rs` 只是一个很薄的启动入口，其他 Rust 程序可以直接依赖本库嵌入服务。
+
+#[macro_use]
+extern crate rocket;
+
+pub mod access;
+pub mod admin;
+pub mod admin_api;
+pub mod affinity;
+pub mod api;
+pub mod api_keys;
+pub mod app_state;
+pub mod assistant;
+pub mod balancer;
+pub mod batcher;
+pub mod bulk;
+pub mod cancel;
+pub mod catalog;
+pub mod chat;
+pub mod chat_template;
+pub mod cli;
+pub mod compression;
+pub mod config;
+pub mod confirm;
+pub mod device;
+pub mod diffusion;
+pub mod discovery;
+pub mod embedding;
+pub mod embedding_cache;
+pub mod engine;
+pub mod engine_factory;
+pub mod error;
+pub mod events;
+pub mod frontend;
+pub mod generations;
+pub mod health;
+pub mod info;
+pub mod hub_stream;
+pub mod integrity;
+pub mod jobs;
+pub mod kv_budget;
+pub mod loader;
+pub mod logging;
+pub mod manifest;
+pub mod memory;
+pub mod metrics;
+pub mod model_registry;
+pub mod openai;
+pub mod openapi;
+pub mod output_validation;
+pub mod perf_history;
+pub mod pipeline;
+pub mod preemption;
+pub mod privacy;
+pub mod prompt_compression;
+pub mod pull;
+pub mod quant_bench;
+pub mod queue;
+pub mod rag;
+pub mod rate_limit;
+pub mod repetition;
+pub mod request_log;
+pub mod router;
+pub mod scratch;
+pub mod self_test;
+pub mod session;
+pub mod session_stream;
+pub mod standby;
+pub mod stop;
+pub mod stream_stats;
+pub mod tags;
+pub mod time_slice;
+pub mod tiny;
+pub mod token_trace;
+pub mod tools;
+pub mod trash;
+pub mod types;
+pub mod versioning;
+
+#[doc(hidden)]
+pub mod testing;
+
+use std::sync::Arc;
+
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::{Build, Rocket, Route};
+
+use api::{
+    clear_model_cache, estimate_model_memory, get_health, get_job, get_metrics, infer,
+    infer_continue, infer_shared_prefix, infer_stream, infer_stream_get, list_jobs, list_models,
+    list_routers, load_model, model_cache, model_events, not_found, payload_too_large,
+    register_model, release_scratch, unauthorized, unload_model, unprocessable,
+};
+use app_state::AppState;
+use config::ServerConfig;
+
+/// 根据给定的 AppState 组装 Rocket 实例（`#[launch]` 和测试共用），
+/// 配置来自默认 figment（`Rocket.toml` + `ROCKET_*`）
+pub fn build_rocket(state: Arc<AppState>) -> Rocket<Build> {
+    build_rocket_with(rocket::Config::figment(), state)
+}
+
+/// 同 `build_rocket`，但使用调用方给定的 figment
+pub fn build_rocket_with(figment: Figment, state: Arc<AppState>) -> Rocket<Build> {
+    tracing::info!("{}", info::ServerInfo::collect(&state).banner());
+    tracing::info!(
+        max_concurrent_infer = state.max_concurrent_infer,
+        "inference concurrency"
+    );
+
+    // 原生接口挂在 /api/v1，不带前缀的旧路径作为兼容别名（响应带弃用头，见 `versioning`）
+    let native: Vec<Route> = [
+        routes![
+            get_health,         // GET  /health （ok / degraded / unhealthy）
+            get_metrics,        // GET  /metrics （Prometheus 格式）
+            info::get_info,     // GET  /info （版本、feature、支持的模型结构、编译期上限）
+            model_events,       // GET  /events （模型加载事件 SSE）
+            list_models,
+            register_model,     // POST /models （运行时注册模型，不持久化）
+            discovery::rescan_models, // POST /models/rescan（扫描模型目录里的 GGUF）
+            model_cache,        // GET  /models/<name>/cache
+            clear_model_cache,  // POST /models/<name>/cache/clear
+            estimate_model_memory, // GET /models/<name>/estimate?ctx=
+            perf_history::model_perf, // GET /models/<name>/perf（p50 / p95）
+            pull::pull_model,   // POST /models/<name>/pull （后台下载权重，返回 job id）
+            pull::pull_events,  // GET  /models/<name>/pull/events （下载进度 SSE）
+            list_routers,       // GET  /routers （虚拟 router 模型）
+            load_model,
+            unload_model,       // POST /unload （释放引擎和权重）
+            bulk::bulk_models,  // POST /models/bulk （批量加载 / 卸载，返回 job id）
+            release_scratch,    // DELETE /scratch （释放调用方的 scratch 模型）
+            infer,              // POST /infer         （非流式）
+            infer_stream,       // POST /infer?stream=true （curl 用）
+            infer_stream_get,   // GET  /infer_stream?model_name=&prompt= （前端用）
+            infer_shared_prefix, // POST /infer/shared_prefix （共享前缀批量）
+            infer_continue,     // POST /infer/continue （接着部分输出继续生成）
+            generations::pause_generation, // POST /infer/<request_id>/pause
+            generations::resume_generation, // POST /infer/<request_id>/resume
+            generations::active_requests, // GET /admin/requests/active
+            generations::cancel_request, // DELETE /requests/<request_id>
+            list_jobs,
+            get_job,
+        ],
+        routes![
+            catalog::list_catalog,
+            catalog::install_catalog_model, // POST /catalog/<id>/install?quant=
+            quant_bench::benchmark_catalog_model, // POST /catalog/<id>/benchmark（返回 job id）
+        ],
+        routes![
+            assistant::create_run, // POST /assistant/runs
+            tools::list_tools,     // GET  /tools
+        ],
+        routes![
+            rag::add_documents, // POST /rag/<profile>/documents
+            rag::upsert_documents, // PUT  /rag/<profile>/documents（按 id 增量更新）
+            rag::delete_document, // DELETE /rag/<profile>/documents/<id>
+            rag::compact_profile, // POST /rag/<profile>/compact
+            rag::load_profile,  // POST /rag/<profile>/load（embedding 和对话模型一起加载）
+        ],
+        routes![
+            admin::integrity_scan,
+            admin::unload_model, // POST   /admin/models/<name>/unload
+            admin::delete_model, // DELETE /admin/models/<name>（软删除，进回收站）
+            trash::list_trash,   // GET    /admin/trash
+            trash::restore_model, // POST  /admin/trash/<name>/restore
+            trash::purge_model,  // DELETE /admin/trash/<name>（立即硬删除）
+            request_log::export, // GET    /admin/export?from=&to=&format=jsonl|csv
+        ],
+        routes![
+            chat::create_session,
+            chat::get_session,
+            chat::update_session, // PATCH /sessions/<id>（system prompt / 采样默认值）
+            chat::close_session, // DELETE /sessions/<id>（同时释放 scratch 模型）
+            chat::send_message,
+            chat::stream_session, // GET /sessions/<id>/stream（SSE，多个订阅者共享同一次生成）
+            chat::get_session_memory,
+            chat::update_session_memory, // PUT /sessions/<id>/memory（手动改写记忆）
+        ],
+    ]
+    .concat();
+
+    rocket::custom(access::restrict_bind_address(figment))
+        .attach(AdHoc::config::<ServerConfig>())
+        .attach(logging::RequestIds)
+        .attach(access::AccessControl)
+        .attach(api_keys::RequireApiKey)
+        .attach(rate_limit::RateLimit)
+        .attach(frontend::fairing())
+        .attach(trash::fairing())
+        .attach(compression::Compression)
+        .attach(versioning::ApiVersioning)
+        .manage(state)
+        .register(
+            "/",
+            catchers![not_found, payload_too_large, unauthorized, unprocessable],
+        )
+        .mount("/", native.clone())
+        .mount(versioning::API_V1, native)
+        .mount(
+            "/",
+            routes![
+                openai::list_models,      // GET  /v1/models
+                openai::chat_completions, // POST /v1/chat/completions
+                openai::completions,      // POST /v1/completions（旧版文本补全）
+                openai::image_generations, // POST /v1/images/generations（返回 job id）
+                openai::get_image,        // GET  /v1/images/<job_id>/<index>
+                openapi::openapi_json,    // GET /openapi.json
+                openapi::swagger_ui,      // GET /docs
+            ],
+        )
+        // 管理页专用，不是稳定接口，没有 /api/v1 别名
+        .mount(
+            "/",
+            routes![
+                admin_api::models,         // GET    /admin/api/models（含加载进度）
+                admin_api::load_model,     // POST   /admin/api/models/<name>/load
+                admin_api::unload_model,   // POST   /admin/api/models/<name>/unload
+                admin_api::queue,          // GET    /admin/api/queue
+                admin_api::cancel_request, // DELETE /admin/api/requests/<id>
+                admin_api::usage_series,   // GET    /admin/api/usage?minutes=&bucket=
+            ],
+        )
+}
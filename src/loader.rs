@@ -0,0 +1,110 @@
+//! 模型加载专用的线程池：读 GGUF、构造引擎、自检都在这里执行，不占 rocket / tokio 的线程，
+//! 线程优先级调低（Linux 上 nice 10），加载时正在进行的生成不会明显变卡。
+//!
+//! 并行度由配置 `loader_threads` 决定，默认 1：同时只加载一个模型，其余排队。
+//! candle 的 CPU 算子跑在当前 rayon 线程池里，所以加载时的计算也被限制在池里。
+
+use std::panic::{self, AssertUnwindSafe};
+
+use rocket::tokio::sync::oneshot;
+use tracing::warn;
+
+/// 加载线程的 nice 值
+#[cfg(target_os = "linux")]
+const LOADER_NICE: i32 = 10;
+
+pub struct LoaderPool {
+    pool: rayon::ThreadPool,
+}
+
+impl LoaderPool {
+    /// `threads` 为 0 时按 1 处理
+    pub fn new(threads: usize) -> Self {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .thread_name(|i| format!("model-loader-{i}"))
+            .start_handler(|_| {
+                if let Err(e) = lower_current_thread_priority() {
+                    warn!("failed to lower loader thread priority: {e}");
+                }
+            })
+            .build()
+            .expect("failed to build the model loader thread pool");
+        Self { pool }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// 在加载线程上执行并阻塞等待结果（同步加载用），panic 原样传回调用方
+    pub fn install<T: Send>(&self, work: impl FnOnce() -> T + Send) -> T {
+        self.pool.install(work)
+    }
+
+    /// 在加载线程上执行，不占 tokio 的线程；`work` panic 时返回 panic 信息
+    pub async fn run<T: Send + 'static>(
+        &self,
+        work: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, String> {
+        let (tx, rx) = oneshot::channel();
+        self.pool.spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(work)).map_err(|payload| {
+                payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string())
+            });
+            let _ = tx.send(result);
+        });
+        rx.await
+            .unwrap_or_else(|_| Err("loader thread pool shut down".to_string()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lower_current_thread_priority() -> std::io::Result<()> {
+    // Linux 上 nice 值是线程级的，who = 0 只影响当前线程
+    // SAFETY: 只修改当前线程的调度优先级
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, LOADER_NICE) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lower_current_thread_priority() -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn work_runs_on_loader_threads_and_panics_are_caught() {
+        let loader = LoaderPool::new(0);
+        assert_eq!(loader.threads(), 1);
+
+        let name = loader
+            .run(|| std::thread::current().name().map(str::to_string))
+            .await
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("model-loader-0"));
+        assert_eq!(loader.install(|| 1 + 1), 2);
+
+        let err = loader.run(|| panic!("boom")).await.unwrap_err();
+        assert_eq!(err, "boom");
+        // panic 之后线程池还能继续用
+        assert_eq!(loader.run(|| 3).await, Ok(3));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn loader_threads_run_at_lower_priority() {
+        let loader = LoaderPool::new(1);
+        let nice = loader.install(|| unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) });
+        assert!(nice >= LOADER_NICE, "nice = {nice}");
+    }
+}
@@ -0,0 +1,116 @@
+//! 结构化日志：用 `tracing` 输出，`log_format = "json"`（或 `--log-format json`）时每行一个 JSON 对象，
+//! 方便直接导入日志系统；级别由 `RUST_LOG` 控制，默认 `info`。
+//!
+//! 每个 HTTP 请求在进入时分配一个 UUID（`RequestIds` fairing），响应头 `X-Request-Id` 带回给客户端；
+//! 客户端自己带了合法的 `X-Request-Id` 请求头时沿用它（可以用来取消非流式请求，见 `generations`）。
+//! 请求结束时记一条带方法、路径、状态码和耗时的 `http` 事件。推理请求的日志都在 `infer` span 里，
+//! 带着同一个 `request_id` 和模型名；`record_request` 的事件另外带 token 数和延迟。
+//! 流式生成的响应头仍然是可以暂停 / 继续的 generation id（见 `generations`），span 里两个 id 都有。
+
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request, Response};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+use crate::generations::REQUEST_ID_HEADER;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// 人读的单行文本
+    #[default]
+    Text,
+    /// 每行一个 JSON 对象，span 字段放在 `span` / `spans` 里
+    Json,
+}
+
+/// 安装全局 subscriber；已经装过（例如嵌入到别的程序里）时什么都不做
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let _ = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().try_init(),
+    };
+}
+
+/// 这个 HTTP 请求的 id，由 `RequestIds` 在请求进入时分配
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+struct Started {
+    id: RequestId,
+    at: Instant,
+}
+
+/// 客户端给的 id：1-128 个字母、数字或 `-_.:`
+fn client_id(req: &Request<'_>) -> Option<String> {
+    let id = req.headers().get_one(REQUEST_ID_HEADER)?;
+    let valid = (1..=128).contains(&id.len())
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+    valid.then(|| id.to_string())
+}
+
+fn started<'r>(req: &'r Request<'_>) -> &'r Started {
+    req.local_cache(|| Started {
+        id: RequestId(client_id(req).unwrap_or_else(|| Uuid::new_v4().to_string())),
+        at: Instant::now(),
+    })
+}
+
+impl RequestId {
+    /// 没挂 `RequestIds` 时在第一次取的时候分配
+    pub fn of(req: &Request<'_>) -> RequestId {
+        started(req).id.clone()
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::of(req))
+    }
+}
+
+/// 给每个请求分配 id、加 `X-Request-Id` 响应头，并在响应时记一条 `http` 事件
+pub struct RequestIds;
+
+#[rocket::async_trait]
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request ids and access log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        started(req);
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Started { id, at } = started(req);
+        // 流式生成已经带了自己的 generation id
+        if !res.headers().contains(REQUEST_ID_HEADER) {
+            res.set_header(Header::new(REQUEST_ID_HEADER, id.0.clone()));
+        }
+        tracing::info!(
+            target: "http",
+            request_id = %id.0,
+            method = %req.method(),
+            path = %req.uri().path(),
+            status = res.status().code,
+            latency_ms = at.elapsed().as_millis() as u64,
+            "request finished"
+        );
+    }
+}
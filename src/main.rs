@@ -1,38 +1,87 @@
-#[macro_use]
-extern crate rocket;
+use clap::Parser;
+use local_llm_server::app_state::AppState;
+use local_llm_server::build_rocket_with;
+use local_llm_server::catalog::Catalog;
+use local_llm_server::cli::Cli;
+use local_llm_server::config::ServerConfig;
+use local_llm_server::discovery;
+use local_llm_server::logging;
+use local_llm_server::model_registry::ModelRegistry;
+use local_llm_server::perf_history::PerfHistory;
+use local_llm_server::rag::RagProfiles;
+use local_llm_server::tools::ToolRegistry;
+use tracing::{info, warn};
 
-mod api;
-mod app_state;
-mod engine;
-mod model_registry;
-mod types;
+fn main() {
+    let cli = Cli::parse();
+    // 环境变量要在 runtime 的线程启动之前设置
+    cli.apply_env();
+    let figment = cli.figment();
+    let config: ServerConfig = figment.extract().expect("invalid server configuration");
+    logging::init(config.log_format);
 
-use std::sync::Arc;
+    // registry 只来自 manifest，没有配置时用内置的 models.toml
+    let registry = match &config.model_manifest {
+        Some(path) => ModelRegistry::from_manifest(path).expect("failed to load model manifest"),
+        None => ModelRegistry::bundled(),
+    };
+    // 远程目录拉不下来时不影响启动，退回内置目录
+    let catalog = match &config.model_catalog {
+        // 离线时不去拉远程目录
+        Some(source) if config.offline && source.starts_with("http") => {
+            info!(%source, "offline mode, using the bundled catalog");
+            Catalog::bundled()
+        }
+        Some(source) => Catalog::load(source).unwrap_or_else(|e| {
+            warn!("{e:#}, using the bundled catalog");
+            Catalog::bundled()
+        }),
+        None => Catalog::bundled(),
+    };
+    // 历史文件读写失败时只是少了历史数据，不影响启动
+    let perf = match &config.perf_history {
+        Some(path) => PerfHistory::open(path).unwrap_or_else(|e| {
+            warn!("{e:#}, keeping the history in memory only");
+            PerfHistory::default()
+        }),
+        None => PerfHistory::default(),
+    };
+    let state = AppState::builder()
+        .registry(registry)
+        .catalog(catalog)
+        .max_concurrent_infer(config.max_concurrent_infer)
+        .queue(config.queue.clone())
+        .streaming(config.stream.clone())
+        .tools(ToolRegistry::from_config(&config.tools).expect("invalid tools configuration"))
+        .rag(RagProfiles::from_config(&config.rag).expect("invalid rag configuration"))
+        .discovery(config.discovery.clone())
+        .embedding_cache(config.embedding_cache.clone())
+        .perf_history(perf)
+        .self_test(config.self_test.clone())
+        .kv_budget(config.kv_budget.clone())
+        .offline(config.offline)
+        .loader_threads(config.loader_threads)
+        .standby(config.standby.clone())
+        .rate_limit(config.rate_limit.clone())
+        .time_slice(config.time_slice.clone())
+        .tags(config.tags.clone())
+        .trash(config.trash.clone())
+        .build();
+    if config.discovery.on_startup {
+        match discovery::rescan(&state) {
+            Ok(report) => info!(
+                dir = %report.dir,
+                added = report.added.len(),
+                skipped = report.skipped.len(),
+                "scanned the model directory"
+            ),
+            Err(e) => warn!(
+                dir = %config.discovery.dir.display(),
+                "failed to scan the model directory: {e}"
+            ),
+        }
+    }
 
-use api::{health, infer, infer_stream, infer_stream_get, list_models, load_model};
-use app_state::AppState;
-
-
-
-
-
-#[launch]
-fn rocket() -> _ {
-    let max_concurrent_infer = 10;
-    let state = AppState::new(max_concurrent_infer);
-
-    rocket::build()
-        .manage(state as Arc<AppState>)
-        .mount(
-            "/",
-            routes![
-                health,
-                list_models,
-                load_model,
-                infer,              // POST /infer         （非流式）
-                infer_stream,       // POST /infer?stream=true （curl 用）
-                infer_stream_get,   // GET  /infer_stream?model_name=&prompt= （前端用）
-            ],
-        )
-        .mount("/", rocket::fs::FileServer::from("static"))
+    // 同 `#[launch]`：启动失败时 rocket::Error 在 drop 时打印原因并退出
+    let _ = rocket::execute(build_rocket_with(figment, state).launch());
 }
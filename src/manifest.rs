@@ -0,0 +1,448 @@
+//! 模型 manifest（TOML）：registry 在启动时据此构造（`ModelRegistry::new`）。
+//! 没有配置 `model_manifest` 时用编译进来的 `models.toml`。
+//!
+//! 权重来源三选一：本地 `gguf`、hub 上的 `hf_repo` + `hf_file`、文生图的 `diffusion_dir`。
+//! 只用本地文件的 manifest 加载时完全不访问 hub，适合离线部署。
+//!
+//! ```toml
+//! [[models]]
+//! name = "mistral-7b"
+//! engine_kind = "candle"          # 默认 candle
+//! gguf = "mistral-7b-instruct-v0.1.Q2_K.gguf"   # 相对路径相对于 manifest 所在目录
+//! tokenizer = "mistral-tokenizer.json"          # 可选，默认同目录 tokenizer.json
+//! sha256 = "..."                  # 可选，加载前校验
+//! quantization = "q2_k"
+//! placements = ["cpu"]           # 同一设备写多次就是多个副本
+//! weights = [1]                   # 可选：副本权重
+//! balance = "least_outstanding"   # 或 "weighted_round_robin"
+//! context_window = 4096          # 可选
+//! fallbacks = ["llama-3b"]        # 可选：未加载 / 出错 / 超时时依次尝试
+//! timeout_ms = 30000              # 可选：非流式生成超时
+//! modalities = ["text"]           # 可选，默认只有 text
+//! numa_node = 0                   # 可选：CPU 计算线程绑定到这个 NUMA 节点的核心
+//! # cpu_cores = "0-15,32-47"      # 或者直接列出核心，和 numa_node 二选一
+//! sampling = { temperature = 0.7, top_p = 0.9 }  # 可选：请求没填的采样参数用这里的
+//! chat_template = "mistral"       # 可选：mistral / chatml / llama3 / zephyr，见 `chat_template`
+//!
+//! # 从 hub 下载（首次加载时缓存到本地）
+//! [[models]]
+//! name = "mistral-7b-q4"
+//! hf_repo = "TheBloke/Mistral-7B-Instruct-v0.1-GGUF"
+//! hf_file = "mistral-7b-instruct-v0.1.Q4_K_M.gguf"
+//! tokenizer_repo = "mistralai/Mistral-7B-v0.1"  # 可选，默认同 hf_repo
+//! quantization = "q4_k_m"
+//!
+//! # 文生图模型：用 diffusion_dir（diffusers 目录布局）代替 gguf，模态自动为 image
+//! [[models]]
+//! name = "sd-1.5"
+//! engine_kind = "diffusion"
+//! diffusion_dir = "stable-diffusion-v1-5"
+//! tokenizer = "clip-tokenizer.json"            # 可选，默认 diffusion_dir/tokenizer.json
+//!
+//! # 虚拟 router：按 prompt 语言 / 正则选模型，见 `router` 模块
+//! [[routers]]
+//! name = "by-lang"
+//! default = "mistral-7b"
+//! rules = [{ when = { language = "zh" }, model = "qwen-7b" }]
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::affinity::CpuAffinity;
+use crate::balancer::BalancePolicy;
+use crate::chat_template::ChatTemplate;
+use crate::device::DeviceSpec;
+use crate::engine::SamplingParams;
+use crate::model_registry::{
+    DiffusionArtifacts, EngineKind, HubArtifacts, LocalArtifacts, Modality, ModelMetadata,
+};
+use crate::router::{RoutingRule, VirtualRouter, AUTO_MODEL};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    pub name: String,
+    #[serde(default = "default_engine_kind")]
+    pub engine_kind: EngineKind,
+    /// 文本模型的本地权重，和 `hf_repo` + `hf_file`、`diffusion_dir` 三选一
+    #[serde(default)]
+    pub gguf: Option<PathBuf>,
+    #[serde(default)]
+    pub hf_repo: Option<String>,
+    #[serde(default)]
+    pub hf_file: Option<String>,
+    /// 提供 `tokenizer.json` 的 hub 仓库，默认同 `hf_repo`
+    #[serde(default)]
+    pub tokenizer_repo: Option<String>,
+    #[serde(default)]
+    pub diffusion_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub tokenizer: Option<PathBuf>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default = "default_quantization")]
+    pub quantization: String,
+    #[serde(default)]
+    pub placements: Vec<DeviceSpec>,
+    #[serde(default)]
+    pub weights: Vec<u32>,
+    #[serde(default)]
+    pub balance: BalancePolicy,
+    #[serde(default)]
+    pub context_window: Option<usize>,
+    #[serde(default)]
+    pub fallbacks: Vec<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub modalities: Vec<Modality>,
+    /// 写法同 `taskset -c`，见 `affinity`
+    #[serde(default)]
+    pub cpu_cores: Option<String>,
+    #[serde(default)]
+    pub numa_node: Option<usize>,
+    /// 这个模型的默认采样参数，请求里填了的字段优先
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    /// chat 微调模型的 prompt 模板，请求带 `auto_template` 时自动套上
+    #[serde(default)]
+    pub chat_template: Option<ChatTemplate>,
+}
+
+impl ManifestEntry {
+    fn cpu_affinity(&self) -> anyhow::Result<Option<CpuAffinity>> {
+        match (&self.cpu_cores, self.numa_node) {
+            (Some(_), Some(_)) => anyhow::bail!(
+                "model `{}` sets both `cpu_cores` and `numa_node`",
+                self.name
+            ),
+            (Some(cores), None) => CpuAffinity::parse_cores(cores)
+                .map(Some)
+                .with_context(|| format!("model `{}`", self.name)),
+            (None, node) => Ok(node.map(CpuAffinity::NumaNode)),
+        }
+    }
+}
+
+fn default_engine_kind() -> EngineKind {
+    EngineKind::CANDLE
+}
+
+fn default_quantization() -> String {
+    "unknown".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestRouter {
+    pub name: String,
+    pub default: String,
+    #[serde(default)]
+    pub rules: Vec<RoutingRule>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelManifest {
+    #[serde(default)]
+    pub models: Vec<ManifestEntry>,
+    #[serde(default)]
+    pub routers: Vec<ManifestRouter>,
+    /// 相对路径的基准目录（manifest 文件所在目录）
+    #[serde(skip)]
+    pub base_dir: PathBuf,
+}
+
+const BUNDLED: &str = include_str!("models.toml");
+
+impl ModelManifest {
+    /// 编译进来的 `models.toml`，相对路径相对于工作目录
+    pub fn bundled() -> Self {
+        let mut manifest = Self::parse(BUNDLED).expect("bundled models.toml is invalid");
+        manifest.base_dir = PathBuf::from(".");
+        manifest
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read manifest `{}`", path.display()))?;
+        let mut manifest =
+            Self::parse(&text).with_context(|| format!("invalid manifest `{}`", path.display()))?;
+        manifest.base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(manifest)
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let manifest: Self = toml::from_str(text)?;
+        let mut seen = std::collections::HashSet::new();
+        for entry in &manifest.models {
+            if entry.name == AUTO_MODEL {
+                anyhow::bail!("model name `{AUTO_MODEL}` is reserved");
+            }
+            if !seen.insert(entry.name.as_str()) {
+                anyhow::bail!("duplicate model name `{}`", entry.name);
+            }
+            if entry.hf_repo.is_some() != entry.hf_file.is_some() {
+                anyhow::bail!("model `{}` needs both `hf_repo` and `hf_file`", entry.name);
+            }
+            let sources = [
+                entry.gguf.is_some(),
+                entry.hf_repo.is_some(),
+                entry.diffusion_dir.is_some(),
+            ];
+            if sources.into_iter().filter(|&s| s).count() != 1 {
+                anyhow::bail!(
+                    "model `{}` needs exactly one of `gguf`, `hf_repo` + `hf_file` and `diffusion_dir`",
+                    entry.name
+                );
+            }
+            entry.cpu_affinity()?;
+        }
+        Ok(manifest)
+    }
+
+    /// 取出 router 配置；规则的目标必须是 manifest 里的模型
+    pub fn routers(&mut self) -> anyhow::Result<Vec<VirtualRouter>> {
+        std::mem::take(&mut self.routers)
+            .into_iter()
+            .map(|r| {
+                let router = VirtualRouter::new(&r.name, r.rules, &r.default)?;
+                if let Some(target) = router
+                    .targets()
+                    .find(|t| !self.models.iter().any(|m| m.name == *t))
+                {
+                    anyhow::bail!("router `{}` targets unknown model `{target}`", r.name);
+                }
+                Ok(router)
+            })
+            .collect()
+    }
+
+    /// 转成 registry 条目，相对路径按 `base_dir` 解析
+    pub fn into_metadata(self) -> Vec<ModelMetadata> {
+        let base_dir = self.base_dir;
+        let resolve = |p: PathBuf| if p.is_absolute() { p } else { base_dir.join(p) };
+
+        self.models
+            .into_iter()
+            .map(|entry| {
+                // parse 时已经校验过
+                let affinity = entry.cpu_affinity().ok().flatten();
+                let mut meta =
+                    ModelMetadata::new(&entry.name, "", &entry.quantization, entry.engine_kind)
+                        .with_placements(entry.placements)
+                        .with_balance(entry.balance, entry.weights)
+                        .with_fallbacks(entry.fallbacks)
+                        .with_sampling(entry.sampling)
+                        .with_chat_template(entry.chat_template);
+                meta = match (
+                    entry.gguf,
+                    entry.hf_repo.zip(entry.hf_file),
+                    entry.diffusion_dir,
+                ) {
+                    (Some(gguf), _, _) => meta.with_artifacts(LocalArtifacts {
+                        gguf: resolve(gguf),
+                        tokenizer: entry.tokenizer.map(resolve),
+                        sha256: entry.sha256,
+                    }),
+                    (None, Some((repo, file)), _) => meta.with_hub_artifacts(HubArtifacts {
+                        tokenizer_repo: entry.tokenizer_repo.unwrap_or_else(|| repo.clone()),
+                        repo,
+                        file,
+                    }),
+                    (None, None, Some(dir)) => meta.with_diffusion_artifacts(DiffusionArtifacts {
+                        dir: resolve(dir),
+                        tokenizer: entry.tokenizer.map(resolve),
+                    }),
+                    // parse 时已经保证三者有其一
+                    (None, None, None) => meta,
+                };
+                meta = meta.with_modalities(entry.modalities);
+                if let Some(affinity) = affinity {
+                    meta = meta.with_cpu_affinity(affinity);
+                }
+                if let Some(n) = entry.context_window {
+                    meta = meta.with_context_window(n);
+                }
+                if let Some(ms) = entry.timeout_ms {
+                    meta = meta.with_timeout(std::time::Duration::from_millis(ms));
+                }
+                meta
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model_registry::ModelRegistry;
+
+    #[test]
+    fn parses_entries_with_defaults_and_relative_paths() {
+        let mut manifest = ModelManifest::parse(
+            r#"
+            [[models]]
+            name = "a"
+            gguf = "weights/a.gguf"
+
+            [[models]]
+            name = "b"
+            engine_kind = "dummy"
+            gguf = "/abs/b.gguf"
+            tokenizer = "tok.json"
+            quantization = "q4_k_m"
+            placements = ["cuda:1", "cpu"]
+            "#,
+        )
+        .unwrap();
+        manifest.base_dir = PathBuf::from("/srv/models");
+
+        let metas = manifest.into_metadata();
+        assert_eq!(metas[0].engine_kind, EngineKind::CANDLE);
+        assert_eq!(metas[0].placements, vec![DeviceSpec::Cpu]);
+        let a = metas[0].artifacts.as_ref().unwrap();
+        assert_eq!(a.gguf, PathBuf::from("/srv/models/weights/a.gguf"));
+        assert_eq!(metas[0].path, "/srv/models/weights/a.gguf");
+
+        let b = metas[1].artifacts.as_ref().unwrap();
+        assert_eq!(b.gguf, PathBuf::from("/abs/b.gguf"));
+        assert_eq!(b.tokenizer, Some(PathBuf::from("/srv/models/tok.json")));
+        assert_eq!(
+            metas[1].placements,
+            vec![DeviceSpec::Cuda(1), DeviceSpec::Cpu]
+        );
+    }
+
+    #[test]
+    fn rejects_duplicates_and_unknown_fields() {
+        let dup =
+            "[[models]]\nname = \"a\"\ngguf = \"a\"\n[[models]]\nname = \"a\"\ngguf = \"b\"\n";
+        assert!(ModelManifest::parse(dup).is_err());
+        assert!(
+            ModelManifest::parse("[[models]]\nname = \"a\"\ngguf = \"a\"\nrepo = \"x\"\n").is_err()
+        );
+    }
+
+    #[test]
+    fn hub_entries_and_default_sampling() {
+        let manifest = ModelManifest::parse(
+            "[[models]]\nname = \"m\"\nhf_repo = \"org/m-GGUF\"\nhf_file = \"m.Q4_K_M.gguf\"\n\
+             sampling = { temperature = 0.7, top_k = 40 }\n",
+        )
+        .unwrap();
+        let meta = manifest.into_metadata().remove(0);
+        assert_eq!(meta.path, "hf://org/m-GGUF/m.Q4_K_M.gguf");
+        let hub = meta.hub_artifacts.unwrap();
+        assert_eq!(hub.tokenizer_repo, "org/m-GGUF");
+        assert_eq!(meta.sampling.temperature, Some(0.7));
+        assert_eq!(meta.sampling.top_k, Some(40));
+        assert!(meta.artifacts.is_none());
+
+        let half = "[[models]]\nname = \"m\"\nhf_repo = \"org/m-GGUF\"\n";
+        assert!(ModelManifest::parse(half).is_err());
+        let both = "[[models]]\nname = \"m\"\ngguf = \"m\"\nhf_repo = \"a\"\nhf_file = \"b\"\n";
+        assert!(ModelManifest::parse(both).is_err());
+    }
+
+    #[test]
+    fn bundled_manifest_builds_the_default_registry() {
+        let registry = ModelRegistry::bundled();
+        let mistral = registry.get_model("mistral-7b").unwrap();
+        assert_eq!(mistral.engine_kind, EngineKind::CANDLE);
+        assert!(mistral.hub_artifacts.is_some());
+        assert_eq!(
+            registry.get_model("llama-3b").unwrap().engine_kind,
+            EngineKind::DUMMY
+        );
+    }
+
+    #[test]
+    fn parses_cpu_affinity() {
+        let manifest = ModelManifest::parse(
+            "[[models]]\nname = \"a\"\ngguf = \"a\"\ncpu_cores = \"0-1,4\"\n\
+             [[models]]\nname = \"b\"\ngguf = \"b\"\nnuma_node = 1\n",
+        )
+        .unwrap();
+        let metas = manifest.into_metadata();
+        assert_eq!(
+            metas[0].cpu_affinity,
+            Some(CpuAffinity::Cores(vec![0, 1, 4]))
+        );
+        assert_eq!(metas[1].cpu_affinity, Some(CpuAffinity::NumaNode(1)));
+
+        let both = "[[models]]\nname = \"a\"\ngguf = \"a\"\ncpu_cores = \"0\"\nnuma_node = 0\n";
+        assert!(ModelManifest::parse(both).is_err());
+        let bad = "[[models]]\nname = \"a\"\ngguf = \"a\"\ncpu_cores = \"3-1\"\n";
+        assert!(ModelManifest::parse(bad).is_err());
+    }
+
+    #[test]
+    fn diffusion_entries_use_diffusion_dir() {
+        let mut manifest = ModelManifest::parse(
+            "[[models]]\nname = \"sd\"\nengine_kind = \"diffusion\"\ndiffusion_dir = \"sd15\"\n",
+        )
+        .unwrap();
+        manifest.base_dir = PathBuf::from("/srv/models");
+        let meta = manifest.into_metadata().remove(0);
+        assert_eq!(meta.engine_kind, EngineKind::DIFFUSION);
+        assert_eq!(meta.modalities, vec![Modality::Image]);
+        assert!(meta.artifacts.is_none());
+        let artifacts = meta.diffusion_artifacts.unwrap();
+        assert_eq!(artifacts.dir, PathBuf::from("/srv/models/sd15"));
+
+        let both = "[[models]]\nname = \"a\"\ngguf = \"a\"\ndiffusion_dir = \"d\"\n";
+        assert!(ModelManifest::parse(both).is_err());
+        assert!(ModelManifest::parse("[[models]]\nname = \"a\"\n").is_err());
+    }
+
+    #[test]
+    fn parses_routers_and_checks_targets() {
+        let models = "[[models]]\nname = \"qwen\"\ngguf = \"q.gguf\"\n[[models]]\nname = \"mistral\"\ngguf = \"m.gguf\"\n";
+        let mut manifest = ModelManifest::parse(&format!(
+            "{models}[[routers]]\nname = \"by-lang\"\ndefault = \"mistral\"\nrules = [{{ when = {{ language = \"zh\" }}, model = \"qwen\" }}]\n"
+        ))
+        .unwrap();
+        let routers = manifest.routers().unwrap();
+        assert_eq!(routers[0].route("你好"), "qwen");
+        assert_eq!(routers[0].route("hello"), "mistral");
+
+        let mut manifest = ModelManifest::parse(&format!(
+            "{models}[[routers]]\nname = \"by-lang\"\ndefault = \"llama\"\n"
+        ))
+        .unwrap();
+        assert!(manifest.routers().is_err());
+    }
+
+    #[test]
+    fn local_artifacts_never_touch_the_hub() {
+        let dir = tempfile::tempdir().unwrap();
+        let gguf = dir.path().join("m.gguf");
+        std::fs::write(&gguf, b"weights").unwrap();
+
+        let artifacts = LocalArtifacts {
+            gguf: gguf.clone(),
+            tokenizer: None,
+            sha256: None,
+        };
+        let err = artifacts.verify().unwrap_err().to_string();
+        assert!(err.contains("never fall back to the hub"), "{err}");
+
+        std::fs::write(dir.path().join("tokenizer.json"), b"{}").unwrap();
+        assert!(artifacts.verify().is_ok());
+
+        let bad = LocalArtifacts {
+            sha256: Some("0".repeat(64)),
+            ..artifacts
+        };
+        assert!(bad
+            .verify()
+            .unwrap_err()
+            .to_string()
+            .contains("checksum mismatch"));
+    }
+}
@@ -0,0 +1,181 @@
+//! 加载前的内存估算：权重 + KV cache + 运行时开销
+//!
+//! 权重按 GGUF 里每个 tensor 的实际字节数累加；KV cache 按架构参数
+//! （层数、KV head 数、head 维度）和上下文长度计算，quantized llama 的 KV cache 是 f32。
+//! 可用内存读 `/proc/meminfo` 的 `MemAvailable`，其他平台不做判断。
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use candle_core::quantized::gguf_file;
+use hf_hub::Cache;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::engine::{candle_source, tensor_bytes};
+use crate::model_registry::{EngineKind, ModelMetadata, ModelSource};
+
+/// KV cache 每个元素的字节数（f32）
+const KV_ELEMENT_BYTES: u64 = 4;
+/// 固定开销：tokenizer、logits、临时 buffer 等
+const BASE_OVERHEAD_BYTES: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MemoryEstimate {
+    pub model_name: String,
+    pub context_length: usize,
+    pub weights_bytes: u64,
+    pub kv_cache_bytes: u64,
+    pub overhead_bytes: u64,
+    pub total_bytes: u64,
+    /// 当前可用内存，无法获取时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_bytes: Option<u64>,
+    /// 是否放得下，无法获取可用内存时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fits: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// 本地已有的 GGUF：模型指向的本地文件，或者 hf 缓存里已下载的 candle 权重
+pub fn gguf_path(meta: &ModelMetadata) -> Option<PathBuf> {
+    if meta.engine_kind != EngineKind::CANDLE
+        && meta.artifacts.is_none()
+        && meta.hub_artifacts.is_none()
+    {
+        return None;
+    }
+    match candle_source(meta) {
+        ModelSource::Local(local) => local.gguf.is_file().then_some(local.gguf),
+        ModelSource::Hub(hub) => Cache::default().model(hub.repo).get(&hub.file),
+    }
+}
+
+/// 读 GGUF 头估算 `context_length` 下需要的内存
+pub fn estimate(
+    model_name: &str,
+    path: &Path,
+    context_length: usize,
+) -> anyhow::Result<MemoryEstimate> {
+    let mut file = std::fs::File::open(path)?;
+    let content = gguf_file::Content::read(&mut file)
+        .with_context(|| format!("failed to read GGUF header of `{}`", path.display()))?;
+    estimate_content(model_name, &content, context_length)
+}
+
+fn estimate_content(
+    model_name: &str,
+    content: &gguf_file::Content,
+    context_length: usize,
+) -> anyhow::Result<MemoryEstimate> {
+    let weights_bytes: u64 = content.tensor_infos.values().map(tensor_bytes).sum();
+    let kv_cache_bytes = kv_bytes_per_token(content)? * context_length as u64;
+    let overhead_bytes = BASE_OVERHEAD_BYTES + weights_bytes / 20;
+    let total_bytes = weights_bytes + kv_cache_bytes + overhead_bytes;
+
+    let available_bytes = available_memory();
+    let fits = available_bytes.map(|available| total_bytes <= available);
+    let warning = match (fits, available_bytes) {
+        (Some(false), Some(available)) => Some(format!(
+            "needs about {} MiB but only {} MiB is available",
+            total_bytes >> 20,
+            available >> 20
+        )),
+        _ => None,
+    };
+    Ok(MemoryEstimate {
+        model_name: model_name.to_string(),
+        context_length,
+        weights_bytes,
+        kv_cache_bytes,
+        overhead_bytes,
+        total_bytes,
+        available_bytes,
+        fits,
+        warning,
+    })
+}
+
+/// 每个 token 的 K + V：2 × 层数 × KV head 数 × head 维度 × 元素大小
+pub(crate) fn kv_bytes_per_token(content: &gguf_file::Content) -> anyhow::Result<u64> {
+    let get = |key: &str| content.metadata.get(key);
+    let arch = get("general.architecture")
+        .context("GGUF has no `general.architecture`")?
+        .to_string()?
+        .clone();
+    let read_u32 = |name: &str| -> anyhow::Result<u64> {
+        let key = format!("{arch}.{name}");
+        let value = get(&key).with_context(|| format!("GGUF has no `{key}`"))?;
+        Ok(value.to_u32()? as u64)
+    };
+    let block_count = read_u32("block_count")?;
+    let embedding_length = read_u32("embedding_length")?;
+    let head_count = read_u32("attention.head_count")?.max(1);
+    // 没有 GQA 时 head_count_kv 不写
+    let head_count_kv = read_u32("attention.head_count_kv").unwrap_or(head_count);
+    let head_dim = embedding_length / head_count;
+    Ok(2 * block_count * head_count_kv * head_dim * KV_ELEMENT_BYTES)
+}
+
+/// `/proc/meminfo` 里的 `MemAvailable`（字节）
+pub fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    parse_mem_available(&meminfo)
+}
+
+fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::quantized::{GgmlDType, QTensor};
+    use candle_core::{Device, Tensor};
+
+    #[test]
+    fn estimate_counts_weights_and_grouped_kv_heads() {
+        let weight = Tensor::zeros((8, 32), candle_core::DType::F32, &Device::Cpu).unwrap();
+        let weight = QTensor::quantize(&weight, GgmlDType::F32).unwrap();
+        let arch = gguf_file::Value::String("llama".to_string());
+        let blocks = gguf_file::Value::U32(2);
+        let embedding = gguf_file::Value::U32(64);
+        let heads = gguf_file::Value::U32(4);
+        let kv_heads = gguf_file::Value::U32(2);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        gguf_file::write(
+            file.as_file_mut(),
+            &[
+                ("general.architecture", &arch),
+                ("llama.block_count", &blocks),
+                ("llama.embedding_length", &embedding),
+                ("llama.attention.head_count", &heads),
+                ("llama.attention.head_count_kv", &kv_heads),
+            ],
+            &[("w", &weight)],
+        )
+        .unwrap();
+
+        let estimate = estimate("tiny", file.path(), 100).unwrap();
+        assert_eq!(estimate.weights_bytes, 8 * 32 * 4);
+        // 2 × 2 层 × 2 个 KV head × 16 维 × 4 字节 × 100 token
+        assert_eq!(estimate.kv_cache_bytes, 2 * 2 * 2 * 16 * 4 * 100);
+        assert_eq!(
+            estimate.total_bytes,
+            estimate.weights_bytes + estimate.kv_cache_bytes + estimate.overhead_bytes
+        );
+    }
+
+    #[test]
+    fn parses_mem_available() {
+        let meminfo =
+            "MemTotal:       16000000 kB\nMemFree:         1000 kB\nMemAvailable:    2048 kB\n";
+        assert_eq!(parse_mem_available(meminfo), Some(2048 * 1024));
+        assert_eq!(parse_mem_available("MemTotal: 1 kB\n"), None);
+    }
+}
@@ -0,0 +1,466 @@
+//! 运行指标，`GET /metrics` 以 Prometheus 文本格式导出
+//!
+//! - `permit_wait_seconds`: 请求等待并发 permit 的时间（histogram）
+//! - `waiting_requests`: 当前正在等 permit 的请求数（gauge）
+//! - `stream_errors_total`: 流式生成中途失败的次数（counter）
+//! - `stream_tokens_generated_total` / `stream_tokens_undelivered_total{reason}`:
+//!   流式请求生成的 token 数，以及因断开、溢出、出错没送到客户端的部分，用来估算浪费的算力
+//! - `preemptions_total`: low 优先级生成被 high 请求抢占的次数（counter）
+//! - `requests_total{model,kind,outcome}`: 结束的推理请求数（counter）
+//! - `inference_duration_seconds{model}`: 每个模型的端到端推理延迟，含排队（histogram）
+//! - `tokens_generated_total{model}`: 所有推理请求生成的 token 数，文本输出按词近似（counter）
+//! - `inference_permits{state}` / `inference_permits_total`: 并发 permit 的占用情况（gauge，由 `/metrics` 现算）
+//! - 最近 `OUTCOME_WINDOW` 个请求的成败，给 `/health` 算错误率
+//!
+//! 并发打满时延迟先体现在等待时间上，不用等用户来抱怨才发现。
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use rocket::tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+
+use crate::request_log::RequestKind;
+
+/// 计算错误率时看最近多少个请求
+pub const OUTCOME_WINDOW: usize = 100;
+
+/// histogram 的桶上界（秒）
+const WAIT_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// 推理延迟的桶上界（秒），生成一段文本通常要几百毫秒到几十秒
+const LATENCY_BUCKETS: [f64; 11] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// 固定桶的累积 histogram
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// 每个桶（含最后的 +Inf）的计数，不累积
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: Duration) {
+        let secs = value.as_secs_f64();
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&b| secs <= b)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        self.render_series(out, name, "");
+    }
+
+    /// 只写样本行，`labels` 形如 `model="a"`，没有标签时为空
+    fn render_series(&self, out: &mut String, name: &str, labels: &str) {
+        let (prefix, suffix) = match labels.is_empty() {
+            true => (String::new(), String::new()),
+            false => (format!("{labels},"), format!("{{{labels}}}")),
+        };
+        let mut cumulative = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = self
+                .bounds
+                .get(i)
+                .map_or("+Inf".to_string(), |b| b.to_string());
+            let _ = writeln!(out, "{name}_bucket{{{prefix}le=\"{le}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_sum{suffix} {}", self.sum().as_secs_f64());
+        let _ = writeln!(out, "{name}_count{suffix} {cumulative}");
+    }
+}
+
+/// 标签值里的反斜杠、引号和换行要转义
+pub(crate) fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// 流式 token 没送达的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndeliveredReason {
+    /// 客户端断开或服务器关闭
+    Disconnect,
+    /// 客户端读得太慢，按 `OverflowPolicy` 丢弃或中止
+    Overflow,
+    Error,
+}
+
+impl UndeliveredReason {
+    const ALL: [UndeliveredReason; 3] = [
+        UndeliveredReason::Disconnect,
+        UndeliveredReason::Overflow,
+        UndeliveredReason::Error,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UndeliveredReason::Disconnect => "disconnect",
+            UndeliveredReason::Overflow => "overflow",
+            UndeliveredReason::Error => "error",
+        }
+    }
+}
+
+pub struct Metrics {
+    pub permit_wait: Histogram,
+    waiting_requests: Arc<AtomicUsize>,
+    /// true 表示成功
+    outcomes: Mutex<VecDeque<bool>>,
+    stream_errors: AtomicU64,
+    stream_tokens_generated: AtomicU64,
+    /// 按 `UndeliveredReason::ALL` 的顺序
+    stream_tokens_undelivered: [AtomicU64; 3],
+    preemptions: AtomicU64,
+    /// (模型, 类型, 是否成功) -> 请求数
+    requests: Mutex<BTreeMap<(String, RequestKind, bool), u64>>,
+    inference_latency: Mutex<BTreeMap<String, Histogram>>,
+    tokens_generated: Mutex<BTreeMap<String, u64>>,
+}
+
+/// 等待期间计入 `waiting_requests`，请求被取消时也能减回去
+struct WaitingGuard(Arc<AtomicUsize>);
+
+impl Drop for WaitingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            permit_wait: Histogram::new(&WAIT_BUCKETS),
+            waiting_requests: Arc::new(AtomicUsize::new(0)),
+            outcomes: Mutex::new(VecDeque::with_capacity(OUTCOME_WINDOW)),
+            stream_errors: AtomicU64::new(0),
+            stream_tokens_generated: AtomicU64::new(0),
+            stream_tokens_undelivered: Default::default(),
+            preemptions: AtomicU64::new(0),
+            requests: Mutex::new(BTreeMap::new()),
+            inference_latency: Mutex::new(BTreeMap::new()),
+            tokens_generated: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// 记录一个请求的结果（只统计服务端原因的失败，参数错误不算）
+    pub fn record_outcome(&self, ok: bool) {
+        let mut outcomes = self.outcomes.lock();
+        if outcomes.len() == OUTCOME_WINDOW {
+            outcomes.pop_front();
+        }
+        outcomes.push_back(ok);
+    }
+
+    /// 最近请求的（错误率，样本数）
+    pub fn error_rate(&self) -> (f64, usize) {
+        let outcomes = self.outcomes.lock();
+        if outcomes.is_empty() {
+            return (0.0, 0);
+        }
+        let failed = outcomes.iter().filter(|ok| !**ok).count();
+        (failed as f64 / outcomes.len() as f64, outcomes.len())
+    }
+
+    pub fn record_stream_error(&self) {
+        self.stream_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stream_errors(&self) -> u64 {
+        self.stream_errors.load(Ordering::Relaxed)
+    }
+
+    /// 一次流式请求结束时记账
+    pub fn record_stream_tokens(
+        &self,
+        generated: usize,
+        undelivered: usize,
+        reason: UndeliveredReason,
+    ) {
+        self.stream_tokens_generated
+            .fetch_add(generated as u64, Ordering::Relaxed);
+        self.stream_tokens_undelivered[reason as usize]
+            .fetch_add(undelivered as u64, Ordering::Relaxed);
+    }
+
+    pub fn stream_tokens_undelivered(&self, reason: UndeliveredReason) -> u64 {
+        self.stream_tokens_undelivered[reason as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn record_preemption(&self) {
+        self.preemptions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn preemptions(&self) -> u64 {
+        self.preemptions.load(Ordering::Relaxed)
+    }
+
+    /// 一个推理请求结束时记账；`tokens` 为 None 表示失败
+    pub fn record_request(
+        &self,
+        model: &str,
+        kind: RequestKind,
+        latency: Duration,
+        tokens: Option<usize>,
+    ) {
+        *self
+            .requests
+            .lock()
+            .entry((model.to_string(), kind, tokens.is_some()))
+            .or_default() += 1;
+        self.inference_latency
+            .lock()
+            .entry(model.to_string())
+            .or_insert_with(|| Histogram::new(&LATENCY_BUCKETS))
+            .observe(latency);
+        if let Some(tokens) = tokens {
+            *self
+                .tokens_generated
+                .lock()
+                .entry(model.to_string())
+                .or_default() += tokens as u64;
+        }
+    }
+
+    /// 某个模型结束的请求数（成功和失败都算）
+    pub fn requests(&self, model: &str) -> u64 {
+        self.requests
+            .lock()
+            .iter()
+            .filter(|((m, _, _), _)| m == model)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    pub fn waiting_requests(&self) -> usize {
+        self.waiting_requests.load(Ordering::Relaxed)
+    }
+
+    /// 获取并发 permit，同时记录等待时间
+    pub async fn acquire(
+        &self,
+        semaphore: Arc<Semaphore>,
+    ) -> Result<OwnedSemaphorePermit, AcquireError> {
+        self.waiting_requests.fetch_add(1, Ordering::Relaxed);
+        let _waiting = WaitingGuard(self.waiting_requests.clone());
+        let started = Instant::now();
+        let permit = semaphore.acquire_owned().await?;
+        self.permit_wait.observe(started.elapsed());
+        Ok(permit)
+    }
+
+    /// Prometheus 文本格式
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.permit_wait.render(
+            &mut out,
+            "llm_permit_wait_seconds",
+            "Time requests spent waiting for an inference permit.",
+        );
+        let _ = writeln!(
+            out,
+            "# HELP llm_waiting_requests Requests currently waiting for an inference permit."
+        );
+        let _ = writeln!(out, "# TYPE llm_waiting_requests gauge");
+        let _ = writeln!(out, "llm_waiting_requests {}", self.waiting_requests());
+        let _ = writeln!(
+            out,
+            "# HELP llm_stream_errors_total Streaming generations that failed after starting."
+        );
+        let _ = writeln!(out, "# TYPE llm_stream_errors_total counter");
+        let _ = writeln!(out, "llm_stream_errors_total {}", self.stream_errors());
+        let _ = writeln!(
+            out,
+            "# HELP llm_stream_tokens_generated_total Tokens generated for streaming requests."
+        );
+        let _ = writeln!(out, "# TYPE llm_stream_tokens_generated_total counter");
+        let _ = writeln!(
+            out,
+            "llm_stream_tokens_generated_total {}",
+            self.stream_tokens_generated.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "# HELP llm_stream_tokens_undelivered_total Streamed tokens generated but never delivered to the client."
+        );
+        let _ = writeln!(out, "# TYPE llm_stream_tokens_undelivered_total counter");
+        for reason in UndeliveredReason::ALL {
+            let _ = writeln!(
+                out,
+                "llm_stream_tokens_undelivered_total{{reason=\"{}\"}} {}",
+                reason.as_str(),
+                self.stream_tokens_undelivered(reason)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP llm_preemptions_total Low-priority generations paused for a high-priority request."
+        );
+        let _ = writeln!(out, "# TYPE llm_preemptions_total counter");
+        let _ = writeln!(out, "llm_preemptions_total {}", self.preemptions());
+        self.render_requests(&mut out);
+        out
+    }
+
+    fn render_requests(&self, out: &mut String) {
+        let _ = writeln!(
+            out,
+            "# HELP llm_requests_total Inference requests that finished."
+        );
+        let _ = writeln!(out, "# TYPE llm_requests_total counter");
+        for ((model, kind, ok), count) in self.requests.lock().iter() {
+            let _ = writeln!(
+                out,
+                "llm_requests_total{{model=\"{}\",kind=\"{}\",outcome=\"{}\"}} {count}",
+                label_value(model),
+                kind.as_str(),
+                if *ok { "ok" } else { "error" }
+            );
+        }
+        let name = "llm_inference_duration_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {name} End-to-end inference latency per model, including queueing."
+        );
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        for (model, histogram) in self.inference_latency.lock().iter() {
+            histogram.render_series(out, name, &format!("model=\"{}\"", label_value(model)));
+        }
+        let _ = writeln!(
+            out,
+            "# HELP llm_tokens_generated_total Tokens generated per model (text output approximated by words)."
+        );
+        let _ = writeln!(out, "# TYPE llm_tokens_generated_total counter");
+        for (model, tokens) in self.tokens_generated.lock().iter() {
+            let _ = writeln!(
+                out,
+                "llm_tokens_generated_total{{model=\"{}\"}} {tokens}",
+                label_value(model)
+            );
+        }
+    }
+}
+
+/// 并发 permit 的占用情况：`available` 个空闲，共 `total` 个
+pub fn render_permits(out: &mut String, available: usize, total: usize) {
+    let _ = writeln!(
+        out,
+        "# HELP llm_inference_permits Inference permits by state."
+    );
+    let _ = writeln!(out, "# TYPE llm_inference_permits gauge");
+    let in_use = total.saturating_sub(available);
+    let _ = writeln!(
+        out,
+        "llm_inference_permits{{state=\"available\"}} {available}"
+    );
+    let _ = writeln!(out, "llm_inference_permits{{state=\"in_use\"}} {in_use}");
+    let _ = writeln!(
+        out,
+        "# HELP llm_inference_permits_total Configured number of concurrent inference permits."
+    );
+    let _ = writeln!(out, "# TYPE llm_inference_permits_total gauge");
+    let _ = writeln!(out, "llm_inference_permits_total {total}");
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let h = Histogram::new(&WAIT_BUCKETS);
+        h.observe(Duration::from_micros(500));
+        h.observe(Duration::from_millis(30));
+        h.observe(Duration::from_secs(60));
+        assert_eq!(h.count(), 3);
+
+        let mut out = String::new();
+        h.render(&mut out, "wait", "help");
+        assert!(out.contains("wait_bucket{le=\"0.001\"} 1\n"));
+        assert!(out.contains("wait_bucket{le=\"0.05\"} 2\n"));
+        assert!(out.contains("wait_bucket{le=\"10\"} 2\n"));
+        assert!(out.contains("wait_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("wait_count 3\n"));
+    }
+
+    #[test]
+    fn requests_are_labelled_by_model_kind_and_outcome() {
+        let metrics = Metrics::new();
+        let latency = Duration::from_millis(300);
+        metrics.record_request("a", RequestKind::Collect, latency, Some(4));
+        metrics.record_request("a", RequestKind::Collect, latency, None);
+        metrics.record_request("b\"x", RequestKind::Stream, latency, Some(2));
+        assert_eq!(metrics.requests("a"), 2);
+
+        let out = metrics.render();
+        for line in [
+            "llm_requests_total{model=\"a\",kind=\"collect\",outcome=\"ok\"} 1\n",
+            "llm_requests_total{model=\"a\",kind=\"collect\",outcome=\"error\"} 1\n",
+            "llm_requests_total{model=\"b\\\"x\",kind=\"stream\",outcome=\"ok\"} 1\n",
+            "llm_inference_duration_seconds_bucket{model=\"a\",le=\"0.25\"} 0\n",
+            "llm_inference_duration_seconds_bucket{model=\"a\",le=\"0.5\"} 2\n",
+            "llm_inference_duration_seconds_count{model=\"a\"} 2\n",
+            "llm_tokens_generated_total{model=\"a\"} 4\n",
+        ] {
+            assert!(out.contains(line), "missing {line:?} in\n{out}");
+        }
+    }
+
+    #[rocket::async_test]
+    async fn acquire_tracks_waiting_requests() {
+        let metrics = Arc::new(Metrics::new());
+        let semaphore = Arc::new(Semaphore::new(1));
+        let held = metrics.acquire(semaphore.clone()).await.unwrap();
+
+        let waiter = {
+            let metrics = metrics.clone();
+            let semaphore = semaphore.clone();
+            rocket::tokio::spawn(async move { metrics.acquire(semaphore).await.map(drop) })
+        };
+        rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(metrics.waiting_requests(), 1);
+
+        drop(held);
+        waiter.await.unwrap().unwrap();
+        assert_eq!(metrics.waiting_requests(), 0);
+        assert_eq!(metrics.permit_wait.count(), 2);
+        assert!(metrics.permit_wait.sum() >= Duration::from_millis(20));
+    }
+}
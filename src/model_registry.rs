@@ -1,10 +1,27 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
 
 use parking_lot::RwLock;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Copy, Serialize)]
+use crate::affinity::CpuAffinity;
+use crate::balancer::BalancePolicy;
+use crate::chat_template::ChatTemplate;
+use crate::device::DeviceSpec;
+use crate::engine::SamplingParams;
+use crate::integrity::sha256_file;
+use crate::manifest::ModelManifest;
+use crate::router::{VirtualRouter, AUTO_MODEL};
+use crate::self_test::SelfTestReport;
+
+/// 线上格式是 snake_case（`"loaded"`），不依赖 Debug 输出
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
 pub enum ModelStatus {
     Unloaded,
     Loading,
@@ -12,10 +29,237 @@ pub enum ModelStatus {
     Error,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
-pub enum EngineKind {
-    Dummy,
-    Candle, // 以后可以打开这一行
+impl ModelStatus {
+    /// 合法的状态迁移：
+    /// - Unloaded / Error -> Loading（加载 / 重试）
+    /// - Loading -> Loaded / Error
+    /// - Loaded -> Loading（重新加载）/ Unloaded
+    /// - Error -> Unloaded（清除错误）
+    pub fn can_transition_to(self, next: ModelStatus) -> bool {
+        use ModelStatus::*;
+        matches!(
+            (self, next),
+            (Unloaded, Loading)
+                | (Error, Loading)
+                | (Loading, Loaded)
+                | (Loading, Error)
+                | (Loaded, Loading)
+                | (Loaded, Unloaded)
+                | (Error, Unloaded)
+        )
+    }
+}
+
+/// 最近一次失败的详细信息（状态为 Error 时一定存在）
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelError {
+    pub message: String,
+    pub at: SystemTime,
+    /// 连续失败的加载次数，成功加载后清零
+    pub attempts: u32,
+    /// 这一轮连续失败的原因（按时间顺序，最多保留 `MAX_ERROR_REASONS` 条）
+    pub reasons: Vec<String>,
+}
+
+const MAX_ERROR_REASONS: usize = 16;
+
+/// 未指定时的上下文窗口（token 数，与 quantized llama 的 MAX_SEQ_LEN 一致）
+pub const DEFAULT_CONTEXT_WINDOW: usize = 4096;
+
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("model `{0}` not found")]
+    NotFound(String),
+    #[error("name `{0}` is already used by a model")]
+    NameConflict(String),
+    #[error("name `{0}` is reserved")]
+    Reserved(String),
+    #[error("model `{model}` cannot go from {from:?} to {to:?}")]
+    IllegalTransition {
+        model: String,
+        from: ModelStatus,
+        to: ModelStatus,
+    },
+}
+
+/// 引擎类型：用字符串做 key，对应 `EngineFactories` 中注册的工厂
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EngineKind(Cow<'static, str>);
+
+impl EngineKind {
+    pub const DUMMY: EngineKind = EngineKind(Cow::Borrowed("dummy"));
+    pub const CANDLE: EngineKind = EngineKind(Cow::Borrowed("candle"));
+    /// Stable Diffusion 类文生图模型，见 `diffusion` 模块
+    pub const DIFFUSION: EngineKind = EngineKind(Cow::Borrowed("diffusion"));
+    /// BERT 类句向量模型，见 `embedding` 模块
+    pub const EMBEDDING: EngineKind = EngineKind(Cow::Borrowed("embedding"));
+    /// 内置的小模型，输出可复现，用于端到端测试，见 `tiny` 模块
+    pub const TINY: EngineKind = EngineKind(Cow::Borrowed("tiny"));
+
+    pub fn new(kind: impl Into<String>) -> Self {
+        Self(Cow::Owned(kind.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for EngineKind {
+    fn from(kind: &str) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl From<String> for EngineKind {
+    fn from(kind: String) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl fmt::Display for EngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// 模型能处理的输入类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Modality {
+    Text,
+    Vision,
+    /// 文生图：输入文本、输出图像（diffusion 模型）
+    Image,
+    /// 输入文本、输出向量（embedding 模型），不参与文本生成的路由
+    Embedding,
+}
+
+/// 完全本地的模型文件（来自 manifest），加载时不会访问 hub
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalArtifacts {
+    pub gguf: PathBuf,
+    /// 不填时使用 GGUF 同目录下的 `tokenizer.json`
+    pub tokenizer: Option<PathBuf>,
+    /// 填了就在加载前校验
+    pub sha256: Option<String>,
+}
+
+impl LocalArtifacts {
+    /// 校验文件存在、checksum 一致，返回 (gguf, tokenizer) 路径
+    pub fn verify(&self) -> anyhow::Result<(PathBuf, PathBuf)> {
+        if !self.gguf.is_file() {
+            anyhow::bail!("GGUF file `{}` does not exist", self.gguf.display());
+        }
+        let tokenizer = match &self.tokenizer {
+            Some(path) => path.clone(),
+            None => self
+                .gguf
+                .parent()
+                .map(|dir| dir.join("tokenizer.json"))
+                .unwrap_or_else(|| PathBuf::from("tokenizer.json")),
+        };
+        if !tokenizer.is_file() {
+            anyhow::bail!(
+                "tokenizer `{}` does not exist (local models never fall back to the hub)",
+                tokenizer.display()
+            );
+        }
+
+        if let Some(expected) = &self.sha256 {
+            let actual = sha256_file(&self.gguf)?;
+            if !actual.eq_ignore_ascii_case(expected) {
+                anyhow::bail!(
+                    "checksum mismatch for `{}`: expected {expected}, got {actual}",
+                    self.gguf.display()
+                );
+            }
+        }
+        Ok((self.gguf.clone(), tokenizer))
+    }
+}
+
+/// hf-hub 上的 GGUF 仓库（来自模型目录），加载时下载到 hf-hub 缓存
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HubArtifacts {
+    pub repo: String,
+    pub file: String,
+    /// 提供 `tokenizer.json` 的仓库
+    pub tokenizer_repo: String,
+}
+
+/// GGUF 权重的来源
+#[derive(Debug, Clone)]
+pub enum ModelSource {
+    Local(LocalArtifacts),
+    Hub(HubArtifacts),
+}
+
+impl ModelSource {
+    /// 解析 `path`：`hf://<org>/<repo>/<file>` 是 hub 上的文件（tokenizer 取同一个仓库），
+    /// 其他非空字符串是本地 GGUF 路径（tokenizer 取同目录下的 `tokenizer.json`）
+    pub fn parse(path: &str) -> Option<Self> {
+        if let Some(rest) = path.strip_prefix("hf://") {
+            let mut parts = rest.splitn(3, '/');
+            let (org, repo, file) = (parts.next()?, parts.next()?, parts.next()?);
+            if org.is_empty() || repo.is_empty() || file.is_empty() {
+                return None;
+            }
+            let repo = format!("{org}/{repo}");
+            return Some(Self::Hub(HubArtifacts {
+                tokenizer_repo: repo.clone(),
+                repo,
+                file: file.to_string(),
+            }));
+        }
+        (!path.is_empty()).then(|| {
+            Self::Local(LocalArtifacts {
+                gguf: PathBuf::from(path),
+                tokenizer: None,
+                sha256: None,
+            })
+        })
+    }
+}
+
+/// diffusion 模型的本地文件，目录按 diffusers 的布局：
+/// `text_encoder/model.safetensors`、`unet/` 和 `vae/` 下的 `diffusion_pytorch_model.safetensors`
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffusionArtifacts {
+    pub dir: PathBuf,
+    /// CLIP 的 `tokenizer.json`，不填时使用 `dir/tokenizer.json`
+    pub tokenizer: Option<PathBuf>,
+}
+
+/// diffusion 模型加载需要的各个文件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffusionFiles {
+    pub tokenizer: PathBuf,
+    pub clip: PathBuf,
+    pub unet: PathBuf,
+    pub vae: PathBuf,
+}
+
+impl DiffusionArtifacts {
+    /// 校验文件都存在
+    pub fn verify(&self) -> anyhow::Result<DiffusionFiles> {
+        let files = DiffusionFiles {
+            tokenizer: self
+                .tokenizer
+                .clone()
+                .unwrap_or_else(|| self.dir.join("tokenizer.json")),
+            clip: self.dir.join("text_encoder/model.safetensors"),
+            unet: self.dir.join("unet/diffusion_pytorch_model.safetensors"),
+            vae: self.dir.join("vae/diffusion_pytorch_model.safetensors"),
+        };
+        for path in [&files.tokenizer, &files.clip, &files.unet, &files.vae] {
+            if !path.is_file() {
+                anyhow::bail!("diffusion file `{}` does not exist", path.display());
+            }
+        }
+        Ok(files)
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -26,15 +270,45 @@ pub struct ModelMetadata {
     pub quantization: String,
     pub engine_kind: EngineKind,
     pub last_updated: Option<SystemTime>,
+    /// 第一次注册的时间，加载 / 卸载不会改它
+    pub registered_at: Option<SystemTime>,
+    pub error: Option<ModelError>,
+    /// 加载时在哪些设备上各建一个 engine 副本；同一设备可以出现多次
+    pub placements: Vec<DeviceSpec>,
+    /// 与 placements 一一对应的负载均衡权重，缺省为 1
+    pub weights: Vec<u32>,
+    pub balance: BalancePolicy,
+    /// 有值时从本地文件加载；None 时由引擎自己决定（Candle 走 hub）
+    pub artifacts: Option<LocalArtifacts>,
+    /// artifacts 为 None 时从这个 hub 仓库下载；也没有时用引擎的默认模型
+    pub hub_artifacts: Option<HubArtifacts>,
+    /// diffusion 模型的本地文件；None 时从 hub 下载
+    pub diffusion_artifacts: Option<DiffusionArtifacts>,
+    /// 上下文窗口（token 数），chat session 超出时触发压缩
+    pub context_window: usize,
+    /// 本模型未加载、出错或超时时依次尝试的模型
+    pub fallbacks: Vec<String>,
+    /// 单次非流式生成的超时，超时后走 fallback
+    pub timeout: Option<Duration>,
+    /// 支持的输入类型，`auto` 选模型时会检查
+    pub modalities: Vec<Modality>,
+    /// 最近一次加载后的自检结果，没开自检时为 None
+    pub self_test: Option<SelfTestReport>,
+    /// CPU 副本的计算线程绑定到哪些核心，None 时用全局线程池
+    pub cpu_affinity: Option<CpuAffinity>,
+    /// 默认采样参数，请求里没填的字段用这里的
+    pub sampling: SamplingParams,
+    /// chat 微调模型的 prompt 模板，请求带 `auto_template` 时用
+    pub chat_template: Option<ChatTemplate>,
 }
 
 impl ModelMetadata {
-    pub fn new(
-        name: &str,
-        path: &str,
-        quantization: &str,
-        engine_kind: EngineKind,
-    ) -> Self {
+    pub fn new(name: &str, path: &str, quantization: &str, engine_kind: EngineKind) -> Self {
+        let modality = if engine_kind == EngineKind::EMBEDDING {
+            Modality::Embedding
+        } else {
+            Modality::Text
+        };
         Self {
             name: name.to_string(),
             status: ModelStatus::Unloaded,
@@ -42,39 +316,190 @@ impl ModelMetadata {
             quantization: quantization.to_string(),
             engine_kind,
             last_updated: None,
+            registered_at: None,
+            error: None,
+            placements: vec![DeviceSpec::Cpu],
+            weights: Vec::new(),
+            balance: BalancePolicy::default(),
+            artifacts: None,
+            hub_artifacts: None,
+            diffusion_artifacts: None,
+            context_window: DEFAULT_CONTEXT_WINDOW,
+            fallbacks: Vec::new(),
+            timeout: None,
+            modalities: vec![modality],
+            self_test: None,
+            cpu_affinity: None,
+            sampling: SamplingParams::default(),
+            chat_template: None,
+        }
+    }
+
+    pub fn with_modalities(mut self, modalities: Vec<Modality>) -> Self {
+        if !modalities.is_empty() {
+            self.modalities = modalities;
         }
+        self
+    }
+
+    /// 设置副本权重和均衡策略
+    pub fn with_balance(mut self, balance: BalancePolicy, weights: Vec<u32>) -> Self {
+        self.balance = balance;
+        self.weights = weights;
+        self
+    }
+
+    pub fn weight_of(&self, replica: usize) -> u32 {
+        self.weights.get(replica).copied().unwrap_or(1)
+    }
+
+    pub fn with_fallbacks(mut self, fallbacks: Vec<String>) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: SamplingParams) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn with_chat_template(mut self, template: Option<ChatTemplate>) -> Self {
+        self.chat_template = template;
+        self
+    }
+
+    pub fn with_cpu_affinity(mut self, affinity: CpuAffinity) -> Self {
+        self.cpu_affinity = Some(affinity);
+        self
+    }
+
+    pub fn with_context_window(mut self, context_window: usize) -> Self {
+        self.context_window = context_window;
+        self
+    }
+
+    /// 权重来源：`artifacts`，其次 `hub_artifacts`，最后解析 `path`；都没有时为 None，由引擎用默认模型
+    pub fn source(&self) -> Option<ModelSource> {
+        if let Some(local) = &self.artifacts {
+            return Some(ModelSource::Local(local.clone()));
+        }
+        if let Some(hub) = &self.hub_artifacts {
+            return Some(ModelSource::Hub(hub.clone()));
+        }
+        ModelSource::parse(&self.path)
+    }
+
+    pub fn with_artifacts(mut self, artifacts: LocalArtifacts) -> Self {
+        self.path = artifacts.gguf.display().to_string();
+        self.artifacts = Some(artifacts);
+        self
+    }
+
+    pub fn with_hub_artifacts(mut self, hub: HubArtifacts) -> Self {
+        self.path = format!("hf://{}/{}", hub.repo, hub.file);
+        self.hub_artifacts = Some(hub);
+        self
+    }
+
+    /// 文生图模型：设置本地文件，模态改为 image
+    pub fn with_diffusion_artifacts(mut self, artifacts: DiffusionArtifacts) -> Self {
+        self.path = artifacts.dir.display().to_string();
+        self.diffusion_artifacts = Some(artifacts);
+        self.modalities = vec![Modality::Image];
+        self
+    }
+
+    pub fn with_placements(mut self, placements: Vec<DeviceSpec>) -> Self {
+        if !placements.is_empty() {
+            self.placements = placements;
+        }
+        self
     }
 }
 
 #[derive(Debug)]
 pub struct ModelRegistry {
     pub models: RwLock<HashMap<String, ModelMetadata>>,
+    /// 虚拟 router 模型名 -> 路由规则
+    pub routers: RwLock<HashMap<String, VirtualRouter>>,
 }
 
 impl ModelRegistry {
-    pub fn new() -> Self {
-        let mut map = HashMap::new();
-        map.insert(
-            "mistral-7b".to_string(),
-            ModelMetadata::new(
-                "mistral-7b",
-                "./models/mistral-7b",
-                "q4_k_m",
-                EngineKind::Candle,
-            ),
-        );
-        map.insert(
-            "llama-3b".to_string(),
-            ModelMetadata::new(
-                "llama-3b",
-                "./models/llama-3b",
-                "q4_k_m",
-                EngineKind::Dummy,
-            ),
-        );
+    /// 按解析好的 manifest 构造，模型和 router 都来自配置，见 `manifest`
+    pub fn new(mut manifest: ModelManifest) -> anyhow::Result<Self> {
+        let registry = Self::empty();
+        let routers = manifest.routers()?;
+        for meta in manifest.into_metadata() {
+            registry.register(meta);
+        }
+        for router in routers {
+            registry.register_router(router)?;
+        }
+        Ok(registry)
+    }
 
+    /// 编译进来的 `models.toml`
+    pub fn bundled() -> Self {
+        Self::new(ModelManifest::bundled()).expect("bundled models.toml is invalid")
+    }
+
+    /// 空 registry，测试或嵌入时自行 register
+    pub fn empty() -> Self {
         Self {
-            models: RwLock::new(map),
+            models: RwLock::new(HashMap::new()),
+            routers: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// 从 manifest 文件构造，见 `manifest` 模块
+    pub fn from_manifest(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        Self::new(ModelManifest::load(path)?)
+    }
+
+    /// 注册（或覆盖）一个模型条目
+    pub fn register(&self, mut meta: ModelMetadata) {
+        let mut guard = self.models.write();
+        if meta.registered_at.is_none() {
+            meta.registered_at = Some(
+                guard
+                    .get(&meta.name)
+                    .and_then(|old| old.registered_at)
+                    .unwrap_or_else(SystemTime::now),
+            );
+        }
+        guard.insert(meta.name.clone(), meta);
+    }
+
+    /// 注册（或覆盖）一个虚拟 router；不能和已有模型重名
+    pub fn register_router(&self, router: VirtualRouter) -> Result<(), RegistryError> {
+        if router.name == AUTO_MODEL {
+            return Err(RegistryError::Reserved(router.name));
+        }
+        if self.models.read().contains_key(&router.name) {
+            return Err(RegistryError::NameConflict(router.name));
+        }
+        self.routers.write().insert(router.name.clone(), router);
+        Ok(())
+    }
+
+    pub fn get_router(&self, name: &str) -> Option<VirtualRouter> {
+        self.routers.read().get(name).cloned()
+    }
+
+    pub fn list_routers(&self) -> Vec<VirtualRouter> {
+        self.routers.read().values().cloned().collect()
+    }
+
+    /// 请求的模型名是 router 时按 prompt 选出真实模型，否则原样返回
+    pub fn resolve(&self, name: &str, prompt: &str) -> String {
+        match self.routers.read().get(name) {
+            Some(router) => router.route(prompt).to_string(),
+            None => name.to_string(),
         }
     }
 
@@ -83,14 +508,105 @@ impl ModelRegistry {
         guard.values().cloned().collect()
     }
 
-    pub fn set_status(&self, name: &str, status: ModelStatus) -> Option<ModelMetadata> {
+    /// 状态迁移；非法迁移（例如 Loading 时再次 Loading）会被拒绝。
+    /// 进入 Error 请用 `set_error`，以便记录失败原因
+    pub fn set_status(
+        &self,
+        name: &str,
+        status: ModelStatus,
+    ) -> Result<ModelMetadata, RegistryError> {
+        self.transition(name, status, |meta| {
+            if status == ModelStatus::Loaded || status == ModelStatus::Unloaded {
+                meta.error = None;
+            }
+        })
+    }
+
+    /// 迁移到 Error 并记录失败原因，连续失败次数 +1
+    pub fn set_error(
+        &self,
+        name: &str,
+        message: impl Into<String>,
+    ) -> Result<ModelMetadata, RegistryError> {
+        let message = message.into();
+        self.transition(name, ModelStatus::Error, |meta| {
+            let (attempts, mut reasons) = match meta.error.take() {
+                Some(prev) => (prev.attempts + 1, prev.reasons),
+                None => (1, Vec::new()),
+            };
+            reasons.push(message.clone());
+            if reasons.len() > MAX_ERROR_REASONS {
+                reasons.remove(0);
+            }
+            meta.error = Some(ModelError {
+                message,
+                at: SystemTime::now(),
+                attempts,
+                reasons,
+            });
+        })
+    }
+
+    /// 换一组设备（下一次加载生效）；正在加载时不能改
+    pub fn set_placements(
+        &self,
+        name: &str,
+        placements: Vec<DeviceSpec>,
+    ) -> Result<ModelMetadata, RegistryError> {
         let mut guard = self.models.write();
-        if let Some(meta) = guard.get_mut(name) {
-            meta.status = status;
-            meta.last_updated = Some(SystemTime::now());
-            return Some(meta.clone());
+        let meta = guard
+            .get_mut(name)
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?;
+        if meta.status == ModelStatus::Loading {
+            return Err(RegistryError::IllegalTransition {
+                model: name.to_string(),
+                from: ModelStatus::Loading,
+                to: ModelStatus::Loading,
+            });
         }
-        None
+        if !placements.is_empty() {
+            meta.placements = placements;
+        }
+        Ok(meta.clone())
+    }
+
+    /// 记录自检结果，不改变状态
+    pub fn set_self_test(&self, name: &str, report: SelfTestReport) -> Result<(), RegistryError> {
+        let mut guard = self.models.write();
+        let meta = guard
+            .get_mut(name)
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?;
+        meta.self_test = Some(report);
+        Ok(())
+    }
+
+    fn transition(
+        &self,
+        name: &str,
+        status: ModelStatus,
+        update: impl FnOnce(&mut ModelMetadata),
+    ) -> Result<ModelMetadata, RegistryError> {
+        let mut guard = self.models.write();
+        let meta = guard
+            .get_mut(name)
+            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?;
+        if !meta.status.can_transition_to(status) {
+            return Err(RegistryError::IllegalTransition {
+                model: name.to_string(),
+                from: meta.status,
+                to: status,
+            });
+        }
+
+        meta.status = status;
+        meta.last_updated = Some(SystemTime::now());
+        update(meta);
+        Ok(meta.clone())
+    }
+
+    /// 从 registry 移除模型，返回被移除的元信息
+    pub fn unregister(&self, name: &str) -> Option<ModelMetadata> {
+        self.models.write().remove(name)
     }
 
     pub fn get_model(&self, name: &str) -> Option<ModelMetadata> {
@@ -98,3 +614,104 @@ impl ModelRegistry {
         guard.get(name).cloned()
     }
 }
+
+impl Default for ModelRegistry {
+    fn default() -> Self {
+        Self::bundled()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> ModelRegistry {
+        let registry = ModelRegistry::empty();
+        registry.register(ModelMetadata::new("m", "", "none", EngineKind::DUMMY));
+        registry
+    }
+
+    #[test]
+    fn source_falls_back_to_the_path() {
+        let meta = |path: &str| ModelMetadata::new("m", path, "q4_k_m", EngineKind::CANDLE);
+        assert!(meta("").source().is_none());
+        match meta("/srv/models/m.Q4_K_M.gguf").source() {
+            Some(ModelSource::Local(local)) => {
+                assert_eq!(local.gguf, PathBuf::from("/srv/models/m.Q4_K_M.gguf"))
+            }
+            other => panic!("{other:?}"),
+        }
+        match meta("hf://org/m-GGUF/sub/m.gguf").source() {
+            Some(ModelSource::Hub(hub)) => {
+                assert_eq!(
+                    (hub.repo.as_str(), hub.file.as_str()),
+                    ("org/m-GGUF", "sub/m.gguf")
+                )
+            }
+            other => panic!("{other:?}"),
+        }
+        assert!(ModelSource::parse("hf://org/only-repo").is_none());
+
+        // 显式的 artifacts 优先于 path
+        let hub = HubArtifacts {
+            repo: "a/b".to_string(),
+            file: "c.gguf".to_string(),
+            tokenizer_repo: "a/b".to_string(),
+        };
+        let mut with_hub = meta("/local.gguf");
+        with_hub.hub_artifacts = Some(hub.clone());
+        assert!(matches!(with_hub.source(), Some(ModelSource::Hub(h)) if h == hub));
+    }
+
+    #[test]
+    fn rejects_illegal_transitions() {
+        let registry = registry();
+        assert!(matches!(
+            registry.set_status("m", ModelStatus::Loaded),
+            Err(RegistryError::IllegalTransition { .. })
+        ));
+        registry.set_status("m", ModelStatus::Loading).unwrap();
+        assert!(registry.set_status("m", ModelStatus::Loading).is_err());
+        assert!(matches!(
+            registry.set_status("missing", ModelStatus::Loading),
+            Err(RegistryError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn error_details_accumulate_until_loaded() {
+        let registry = registry();
+        registry.set_status("m", ModelStatus::Loading).unwrap();
+        let meta = registry.set_error("m", "disk on fire").unwrap();
+        assert_eq!(meta.status, ModelStatus::Error);
+        assert_eq!(meta.error.as_ref().unwrap().attempts, 1);
+
+        registry.set_status("m", ModelStatus::Loading).unwrap();
+        let meta = registry.set_error("m", "still on fire").unwrap();
+        let error = meta.error.unwrap();
+        assert_eq!(error.attempts, 2);
+        assert_eq!(error.message, "still on fire");
+
+        registry.set_status("m", ModelStatus::Loading).unwrap();
+        let meta = registry.set_status("m", ModelStatus::Loaded).unwrap();
+        assert!(meta.error.is_none());
+    }
+
+    #[test]
+    fn registration_time_survives_status_changes_and_overwrites() {
+        let registry = registry();
+        let registered_at = registry.get_model("m").unwrap().registered_at;
+        assert!(registered_at.is_some());
+
+        registry.set_status("m", ModelStatus::Loading).unwrap();
+        let meta = registry.set_status("m", ModelStatus::Loaded).unwrap();
+        assert_eq!(meta.registered_at, registered_at);
+        assert_ne!(meta.last_updated, None);
+
+        registry.register(ModelMetadata::new("m", "", "q8", EngineKind::DUMMY));
+        assert_eq!(
+            registry.get_model("m").unwrap().registered_at,
+            registered_at
+        );
+    }
+}
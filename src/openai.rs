@@ -0,0 +1,697 @@
+//! OpenAI 兼容接口（`/v1/*`），让现成的 SDK 直接指向本服务
+//!
+//! `POST /v1/chat/completions`：`messages` 按 `role: content` 拼成 prompt，走和 `/infer` 一样的 pipeline。
+//! 每条消息里以 `role:` 开头的行会被转义，`render_debug: true` 时响应里带上最终 prompt。
+//! `stream: true` 时按 OpenAI 的顺序发 SSE：先发只带 role 的 chunk，然后每段文本一个
+//! `delta.content`，再发带 `finish_reason` 的空 chunk，最后是 `data: [DONE]`。
+//! 很多 SDK 写死了这个顺序，改动时要小心。
+//! `model` 是 RAG profile 名时先检索文档插进对话，再交给 profile 的对话模型（见 `rag`）。
+//!
+//! `GET /v1/models`：registry 里的模型，`status` 扩展字段表示是否已加载。
+//!
+//! `POST /v1/completions`：旧版文本补全，`prompt` 原样交给模型，支持 `temperature` 和 `stop`。
+//! 流式时每个事件都是 `text_completion` 对象，最后一个带 `finish_reason`，然后是 `data: [DONE]`。
+//!
+//! `POST /v1/images/generations`：文生图很慢，不像 OpenAI 那样同步返回，而是返回 202 + job id，
+//! job 完成后 `result.images` 里是 PNG 的下载地址。
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use rocket::http::{ContentType, Status};
+use rocket::response::status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Json;
+use rocket::tokio::select;
+use rocket::{Either, Shutdown, State};
+
+use crate::api::{
+    api_error, check_prompt_size, pipeline_error, profile_error, unix_secs, ApiError,
+};
+use crate::api_keys::ApiKey;
+use crate::app_state::AppState;
+use crate::config::ServerConfig;
+use crate::diffusion::ImageParams;
+use crate::engine::{FinishReason, SamplingParams};
+use crate::generations::WithRequestId;
+use crate::model_registry::Modality;
+use crate::pipeline::{
+    AdmittedRequest, InferencePipeline, StreamChunk, StreamReceiver, STREAM_MAX_TOKENS,
+};
+use crate::preemption::Priority;
+use crate::rag;
+use crate::request_log::RequestKind;
+use crate::session::{render_turns, ChatRole, ChatTurn};
+use crate::types::{
+    ChatCompletionChoice, ChatCompletionChunk, ChatCompletionChunkChoice, ChatCompletionRequest,
+    ChatCompletionResponse, ChatDelta, ImageGenerationRequest, ImageGenerationResult, InferMode,
+    InferRequest, JobAcceptedResponse, ModelListResponse, ModelObject, TextCompletionChoice,
+    TextCompletionRequest, TextCompletionResponse,
+};
+
+static NEXT_COMPLETION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// `chatcmpl-1`、`cmpl-2` 这样的 id
+fn completion_id(prefix: &str) -> String {
+    format!(
+        "{prefix}-{}",
+        NEXT_COMPLETION_ID.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// OpenAI 没有 repetition，按正常结束处理
+fn finish_reason_str(reason: FinishReason) -> &'static str {
+    match reason {
+        FinishReason::Length => "length",
+        FinishReason::Stop | FinishReason::Repetition => "stop",
+    }
+}
+
+/// 模型列表：GET /v1/models
+#[utoipa::path(tag = "models", responses((status = 200, body = ModelListResponse)))]
+#[get("/v1/models")]
+pub async fn list_models(state: &State<Arc<AppState>>) -> Json<ModelListResponse> {
+    let mut data: Vec<ModelObject> = state
+        .list_models()
+        .into_iter()
+        .map(|m| ModelObject {
+            created: m.registered_at.map_or(0, unix_secs),
+            object: "model".to_string(),
+            owned_by: "local".to_string(),
+            status: m.status,
+            id: m.name,
+        })
+        .collect();
+    data.sort_by(|a, b| a.id.cmp(&b.id));
+    Json(ModelListResponse {
+        object: "list".to_string(),
+        data,
+    })
+}
+
+/// 对话补全：POST /v1/chat/completions
+#[utoipa::path(
+    tag = "inference",
+    request_body = ChatCompletionRequest,
+    responses(
+        (status = 200, content(
+            ("application/json" = ChatCompletionResponse),
+            ("text/event-stream" = ChatCompletionChunk)
+        )),
+        (status = 400, description = "invalid input or profile violation", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model not loaded", body = ErrorResponse),
+        (status = 413, description = "prompt too large", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/v1/chat/completions", data = "<req>")]
+pub async fn chat_completions(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    key: ApiKey,
+    req: Json<ChatCompletionRequest>,
+    shutdown: Shutdown,
+) -> Result<Either<Json<ChatCompletionResponse>, WithRequestId<EventStream![]>>, ApiError> {
+    let req = req.into_inner();
+    if req.messages.is_empty() {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            "messages must not be empty",
+        ));
+    }
+    if req.render_debug && req.stream {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            "render_debug is only supported for non-streaming requests",
+        ));
+    }
+    if req.grounding && req.stream {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            "grounding is only supported for non-streaming requests",
+        ));
+    }
+    let mut messages = req.messages;
+    let model_name = key
+        .profile
+        .resolve_model(&req.model)
+        .map_err(profile_error)?;
+    let (model_name, rag_sources) = match state.rag.get(&model_name) {
+        Some(rag) => {
+            let sources = rag::augment(state, &rag, &key.rag_namespace(), &mut messages).await?;
+            (rag.config.chat_model.clone(), sources)
+        }
+        None if req.grounding => {
+            return Err(api_error(
+                Status::BadRequest,
+                "invalid_input",
+                "grounding is only supported when `model` is a rag profile",
+            ))
+        }
+        None => (model_name, Vec::new()),
+    };
+    let prompt = render_turns(&messages);
+    check_prompt_size(&prompt, config)?;
+
+    let mut infer = InferRequest {
+        model_name,
+        prompt,
+        device: None,
+        mode: InferMode::default(),
+        input_ids: None,
+        return_token_ids: false,
+        max_tokens: req.max_tokens,
+        sampling: SamplingParams::default(),
+        priority: Priority::default(),
+        trace: false,
+        auto_template: false,
+        private: false,
+        client: None,
+        validate: None,
+        tags: Default::default(),
+        request_id: None,
+        rate_client: None,
+    };
+    key.apply(&mut infer).map_err(profile_error)?;
+
+    let pipeline = InferencePipeline::new(state.inner().clone());
+    let id = completion_id("chatcmpl");
+    let created = unix_secs(SystemTime::now());
+
+    if !req.stream {
+        let rendered_prompt = req.render_debug.then(|| infer.prompt.clone());
+        let done = pipeline.collect(&infer).await.map_err(pipeline_error)?;
+        let grounding = req
+            .grounding
+            .then(|| rag::ground(&done.output, &rag_sources));
+        return Ok(Either::Left(Json(ChatCompletionResponse {
+            id,
+            object: "chat.completion".to_string(),
+            created,
+            model: done.served_by,
+            choices: vec![ChatCompletionChoice {
+                index: 0,
+                message: ChatTurn {
+                    role: ChatRole::Assistant,
+                    content: done.output,
+                },
+                finish_reason: finish_reason_str(done.finish_reason).to_string(),
+            }],
+            rendered_prompt,
+            rag_sources,
+            grounding,
+        })));
+    }
+
+    // 校验失败在开始推流之前就以普通错误响应返回
+    let handle = state.generations.register(
+        &infer.model_name,
+        RequestKind::Stream,
+        infer.client.as_deref(),
+        infer.request_id.as_deref(),
+    );
+    let request_id = handle.id().to_string();
+    let rx = pipeline
+        .stream_as(&infer, handle)
+        .await
+        .map_err(pipeline_error)?;
+    let max_tokens = infer.max_tokens.unwrap_or(STREAM_MAX_TOKENS);
+    let base = ChatCompletionChunk {
+        id,
+        object: "chat.completion.chunk".to_string(),
+        created,
+        model: infer.model_name,
+        choices: Vec::new(),
+    };
+    Ok(Either::Right(WithRequestId::new(
+        chunk_stream(rx, base, max_tokens, shutdown),
+        request_id,
+    )))
+}
+
+fn chunk(base: &ChatCompletionChunk, delta: ChatDelta, finish_reason: Option<&str>) -> Event {
+    let mut chunk = base.clone();
+    chunk.choices.push(ChatCompletionChunkChoice {
+        index: 0,
+        delta,
+        finish_reason: finish_reason.map(str::to_string),
+    });
+    Event::json(&chunk)
+}
+
+/// 把 pipeline 的文本 chunk 转成 `chat.completion.chunk` 事件序列。
+/// 流式接口拿不到引擎的结束原因，chunk 数达到 max_tokens 时按 `length` 报告
+fn chunk_stream(
+    mut rx: StreamReceiver,
+    base: ChatCompletionChunk,
+    max_tokens: usize,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    EventStream! {
+        yield chunk(&base, ChatDelta {
+            role: Some(ChatRole::Assistant),
+            content: Some(String::new()),
+        }, None);
+
+        let mut sent = 0;
+        loop {
+            select! {
+                maybe_text = rx.recv() => match maybe_text {
+                    Some(StreamChunk::Text(text)) => {
+                        // 引擎按词推送，拼接时要补回空格
+                        let content = if sent == 0 { text } else { format!(" {text}") };
+                        sent += 1;
+                        yield chunk(&base, ChatDelta { role: None, content: Some(content) }, None);
+                    }
+                    // OpenAI 格式里没有对应的字段，用 SSE 注释告诉客户端
+                    Some(StreamChunk::Gap { dropped }) => {
+                        yield Event::comment(format!("dropped {dropped} chunks"));
+                    }
+                    // OpenAI 只在 `stream_options.include_usage` 时发 usage，这里不支持
+                    Some(StreamChunk::Usage(_)) => {}
+                    // 中途失败：按 OpenAI 的错误格式发一条 `event: error`，不再发 [DONE]
+                    Some(StreamChunk::Error(message)) => {
+                        let error = serde_json::json!({
+                            "error": { "message": message, "type": "server_error" }
+                        });
+                        yield Event::json(&error).event("error");
+                        return;
+                    }
+                    None => break,
+                },
+                // 客户端断开或服务器关闭，不再发结束事件
+                _ = &mut shutdown => return,
+            }
+        }
+
+        let finish_reason = if sent >= max_tokens { "length" } else { "stop" };
+        yield chunk(&base, ChatDelta::default(), Some(finish_reason));
+        yield Event::data("[DONE]");
+    }
+}
+
+/// 文本补全：POST /v1/completions
+#[utoipa::path(
+    tag = "inference",
+    request_body = TextCompletionRequest,
+    responses(
+        (status = 200, content(
+            ("application/json" = TextCompletionResponse),
+            ("text/event-stream" = TextCompletionResponse)
+        )),
+        (status = 400, description = "invalid input or profile violation", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model not loaded", body = ErrorResponse),
+        (status = 413, description = "prompt too large", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/v1/completions", data = "<req>")]
+pub async fn completions(
+    state: &State<Arc<AppState>>,
+    config: &State<ServerConfig>,
+    key: ApiKey,
+    req: Json<TextCompletionRequest>,
+    shutdown: Shutdown,
+) -> Result<Either<Json<TextCompletionResponse>, WithRequestId<EventStream![]>>, ApiError> {
+    let req = req.into_inner();
+    check_prompt_size(&req.prompt, config)?;
+    let stops = req.stop.map(|stop| stop.into_vec()).unwrap_or_default();
+
+    let mut infer = InferRequest {
+        model_name: req.model,
+        prompt: req.prompt,
+        device: None,
+        mode: InferMode::default(),
+        input_ids: None,
+        return_token_ids: false,
+        max_tokens: req.max_tokens,
+        sampling: SamplingParams {
+            temperature: req.temperature,
+            ..SamplingParams::default()
+        },
+        priority: Priority::default(),
+        trace: false,
+        auto_template: false,
+        private: false,
+        client: None,
+        validate: None,
+        tags: Default::default(),
+        request_id: None,
+        rate_client: None,
+    };
+    key.apply(&mut infer).map_err(profile_error)?;
+
+    let pipeline = InferencePipeline::new(state.inner().clone());
+    let base = TextCompletionResponse {
+        id: completion_id("cmpl"),
+        object: "text_completion".to_string(),
+        created: unix_secs(SystemTime::now()),
+        model: infer.model_name.clone(),
+        choices: Vec::new(),
+    };
+
+    if !req.stream {
+        let done = pipeline.collect(&infer).await.map_err(pipeline_error)?;
+        let (text, finish_reason) = match first_stop(&done.output, &stops) {
+            Some(at) => (done.output[..at].to_string(), "stop"),
+            None => (done.output, finish_reason_str(done.finish_reason)),
+        };
+        let mut response = base;
+        response.model = done.served_by;
+        response.choices.push(TextCompletionChoice {
+            text,
+            index: 0,
+            finish_reason: Some(finish_reason.to_string()),
+        });
+        return Ok(Either::Left(Json(response)));
+    }
+
+    let handle = state.generations.register(
+        &infer.model_name,
+        RequestKind::Stream,
+        infer.client.as_deref(),
+        infer.request_id.as_deref(),
+    );
+    let request_id = handle.id().to_string();
+    let rx = pipeline
+        .stream_as(&infer, handle)
+        .await
+        .map_err(pipeline_error)?;
+    let max_tokens = infer.max_tokens.unwrap_or(STREAM_MAX_TOKENS);
+    let scanner = StopScanner::new(stops);
+    Ok(Either::Right(WithRequestId::new(
+        text_stream(rx, base, scanner, max_tokens, shutdown),
+        request_id,
+    )))
+}
+
+fn text_event(base: &TextCompletionResponse, text: String, finish_reason: Option<&str>) -> Event {
+    let mut event = base.clone();
+    event.choices.push(TextCompletionChoice {
+        text,
+        index: 0,
+        finish_reason: finish_reason.map(str::to_string),
+    });
+    Event::json(&event)
+}
+
+/// 和 `chunk_stream` 一样，只是事件是 `text_completion` 对象；碰到 stop 序列时
+/// 发出它之前的部分就结束，丢掉 receiver 让引擎停止生成
+fn text_stream(
+    mut rx: StreamReceiver,
+    base: TextCompletionResponse,
+    mut scanner: StopScanner,
+    max_tokens: usize,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    EventStream! {
+        let mut sent = 0;
+        let finish_reason = loop {
+            select! {
+                maybe_text = rx.recv() => match maybe_text {
+                    Some(StreamChunk::Text(text)) => {
+                        let content = if sent == 0 { text } else { format!(" {text}") };
+                        sent += 1;
+                        let (text, stopped) = scanner.push(&content);
+                        if !text.is_empty() {
+                            yield text_event(&base, text, None);
+                        }
+                        if stopped {
+                            break "stop";
+                        }
+                    }
+                    Some(StreamChunk::Gap { dropped }) => {
+                        yield Event::comment(format!("dropped {dropped} chunks"));
+                    }
+                    Some(StreamChunk::Usage(_)) => {}
+                    Some(StreamChunk::Error(message)) => {
+                        let error = serde_json::json!({
+                            "error": { "message": message, "type": "server_error" }
+                        });
+                        yield Event::json(&error).event("error");
+                        return;
+                    }
+                    None => {
+                        // 扣住的尾巴最终没凑成 stop 序列，原样发出
+                        let rest = std::mem::take(&mut scanner.held);
+                        if !rest.is_empty() {
+                            yield text_event(&base, rest, None);
+                        }
+                        break if sent >= max_tokens { "length" } else { "stop" };
+                    }
+                },
+                _ = &mut shutdown => return,
+            }
+        };
+        drop(rx);
+        yield text_event(&base, String::new(), Some(finish_reason));
+        yield Event::data("[DONE]");
+    }
+}
+
+/// `text` 里最早出现的 stop 序列的位置
+fn first_stop(text: &str, stops: &[String]) -> Option<usize> {
+    stops
+        .iter()
+        .filter_map(|stop| text.find(stop.as_str()))
+        .min()
+}
+
+/// 流式输出里找 stop 序列：stop 可能跨 chunk，末尾可能是某个 stop 开头的部分先扣住，
+/// 确认不是之后再发
+struct StopScanner {
+    stops: Vec<String>,
+    held: String,
+}
+
+impl StopScanner {
+    fn new(stops: Vec<String>) -> Self {
+        Self {
+            stops,
+            held: String::new(),
+        }
+    }
+
+    /// 返回（现在可以发出的文本，是否遇到了 stop）
+    fn push(&mut self, text: &str) -> (String, bool) {
+        self.held.push_str(text);
+        if let Some(at) = first_stop(&self.held, &self.stops) {
+            self.held.truncate(at);
+            return (std::mem::take(&mut self.held), true);
+        }
+        let keep = self
+            .stops
+            .iter()
+            .map(|stop| {
+                (1..stop.len())
+                    .rev()
+                    .filter(|&n| stop.is_char_boundary(n))
+                    .find(|&n| self.held.ends_with(&stop[..n]))
+                    .unwrap_or(0)
+            })
+            .max()
+            .unwrap_or(0);
+        let ready = self.held.len() - keep;
+        (self.held.drain(..ready).collect(), false)
+    }
+}
+
+fn image_params(req: &ImageGenerationRequest) -> Result<ImageParams, ApiError> {
+    let invalid = |message: String| api_error(Status::BadRequest, "invalid_input", message);
+    let defaults = ImageParams::default();
+    let (width, height) = match &req.size {
+        Some(size) => ImageParams::parse_size(size)
+            .ok_or_else(|| invalid(format!("size must look like 512x512, got `{size}`")))?,
+        None => (defaults.width, defaults.height),
+    };
+    let params = ImageParams {
+        width,
+        height,
+        steps: req.steps.unwrap_or(defaults.steps),
+        guidance_scale: req.guidance_scale.unwrap_or(defaults.guidance_scale),
+        n: req.n.unwrap_or(defaults.n),
+        seed: req.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        }),
+    };
+    params.validate().map_err(invalid)?;
+    Ok(params)
+}
+
+/// 文生图：POST /v1/images/generations，返回 job id，用 `GET /jobs/<id>` 轮询
+#[utoipa::path(
+    tag = "inference",
+    request_body = ImageGenerationRequest,
+    responses(
+        (status = 202, description = "job started; its result is an ImageGenerationResult", body = JobAcceptedResponse),
+        (status = 400, description = "invalid parameters or not an image model", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "model not loaded", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/v1/images/generations", data = "<req>")]
+pub async fn image_generations(
+    state: &State<Arc<AppState>>,
+    key: ApiKey,
+    req: Json<ImageGenerationRequest>,
+) -> Result<status::Custom<Json<JobAcceptedResponse>>, ApiError> {
+    let params = image_params(&req)?;
+    let model_name = key
+        .profile
+        .resolve_model(&req.model)
+        .map_err(profile_error)?;
+
+    let pipeline = InferencePipeline::new(state.inner().clone());
+    let validated = pipeline
+        .validate(&InferRequest {
+            model_name,
+            prompt: req.prompt.clone(),
+            device: req.device,
+            mode: InferMode::default(),
+            input_ids: None,
+            return_token_ids: false,
+            max_tokens: None,
+            sampling: SamplingParams::default(),
+            priority: Priority::default(),
+            trace: false,
+            auto_template: false,
+            private: false,
+            client: None,
+            validate: None,
+            tags: Default::default(),
+            request_id: None,
+            rate_client: None,
+        })
+        .map_err(pipeline_error)?;
+    let generates_images = state
+        .registry
+        .get_model(&validated.model_name)
+        .is_some_and(|m| m.modalities.contains(&Modality::Image));
+    if !generates_images {
+        return Err(api_error(
+            Status::BadRequest,
+            "unsupported_modality",
+            format!("model `{}` does not generate images", validated.model_name),
+        ));
+    }
+
+    let job_id = state.jobs.create("image_generation");
+    let state = state.inner().clone();
+    let id = job_id.clone();
+    let prompt = req.into_inner().prompt;
+    rocket::tokio::spawn(async move {
+        // 和文本推理共用并发 permit，排队期间 job 保持 Pending
+        match pipeline.admit(validated).await {
+            Ok(admitted) => run_image_job(state, id, admitted, prompt, params).await,
+            Err(e) => state.jobs.fail(&id, e.to_string()),
+        }
+    });
+
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(JobAcceptedResponse { job_id }),
+    ))
+}
+
+async fn run_image_job(
+    state: Arc<AppState>,
+    job_id: String,
+    admitted: AdmittedRequest,
+    prompt: String,
+    params: ImageParams,
+) {
+    state.jobs.start(&job_id);
+    let model = admitted.request.model_name.clone();
+    let seed = params.seed;
+    let jobs = state.jobs.clone();
+    let id = job_id.clone();
+    let generated = rocket::tokio::task::spawn_blocking(move || {
+        let engine = admitted.request.engine.clone();
+        let progress = |p: f32| jobs.set_progress(&id, p);
+        let images = engine.generate_images(&prompt, &params, &progress);
+        drop(admitted); // 生成结束立即释放 permit
+        images
+    })
+    .await;
+
+    match generated {
+        Ok(Ok(images)) => {
+            let result = ImageGenerationResult {
+                model,
+                seed,
+                images: (0..images.len())
+                    .map(|i| format!("/v1/images/{job_id}/{i}"))
+                    .collect(),
+            };
+            state.images.insert(&job_id, images);
+            match serde_json::to_value(result) {
+                Ok(result) => state.jobs.succeed(&job_id, result),
+                Err(e) => state.jobs.fail(&job_id, e.to_string()),
+            }
+        }
+        Ok(Err(e)) => state.jobs.fail(&job_id, format!("{e:#}")),
+        Err(e) => state
+            .jobs
+            .fail(&job_id, format!("image generation panicked: {e}")),
+    }
+}
+
+/// 取回文生图 job 生成的 PNG：GET /v1/images/<job_id>/<index>
+#[utoipa::path(
+    tag = "inference",
+    responses(
+        (status = 200, description = "PNG image", body = Vec<u8>, content_type = "image/png"),
+        (status = 404, description = "unknown job, unfinished job or index out of range", body = ErrorResponse)
+    )
+)]
+#[get("/v1/images/<job_id>/<index>")]
+pub async fn get_image(
+    state: &State<Arc<AppState>>,
+    job_id: &str,
+    index: usize,
+) -> Result<(ContentType, Vec<u8>), ApiError> {
+    state
+        .images
+        .get(job_id, index)
+        .map(|png| (ContentType::PNG, png))
+        .ok_or_else(|| {
+            api_error(
+                Status::NotFound,
+                "image_not_found",
+                format!("no image {index} for job `{job_id}`"),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_sequences_split_across_chunks() {
+        let mut scanner = StopScanner::new(vec!["\n\nQ:".to_string()]);
+        assert_eq!(scanner.push("Paris."), ("Paris.".to_string(), false));
+        // 可能是 stop 的开头，先扣住
+        assert_eq!(scanner.push("\n"), (String::new(), false));
+        assert_eq!(scanner.push("\nQ: next"), (String::new(), true));
+
+        let mut scanner = StopScanner::new(vec!["END".to_string()]);
+        assert_eq!(scanner.push("a E"), ("a ".to_string(), false));
+        assert_eq!(scanner.push("ND!"), (String::new(), true));
+        let mut scanner = StopScanner::new(vec!["END".to_string()]);
+        assert_eq!(scanner.push("EN"), (String::new(), false));
+        assert_eq!(scanner.push("D"), (String::new(), true));
+        let mut scanner = StopScanner::new(vec!["END".to_string()]);
+        scanner.push("EN");
+        assert_eq!(scanner.push("ough"), ("ENough".to_string(), false));
+    }
+}
@@ -0,0 +1,280 @@
+//! OpenAPI 文档：`GET /openapi.json` 返回机器可读的接口描述，`GET /docs` 是 Swagger UI
+//!
+//! 路由上的 `#[utoipa::path]` 描述请求 / 响应，`types` 里的结构体通过 `ToSchema` 生成 schema。
+//! 新增路由时记得加到下面的 `paths(...)` 里，`tests/openapi.rs` 会检查是否漏了。
+
+use rocket::response::content::RawHtml;
+use rocket::serde::json::Json;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::versioning::VersionedPaths;
+
+use crate::types::{
+    AdminActionResponse, ChatCompletionChoice, ChatCompletionChunk, ChatCompletionChunkChoice,
+    ChatCompletionRequest, ChatCompletionResponse, ChatDelta, ChatMessageRequest,
+    ChatMessageResponse, ContinueRequest, ContinueResponse, CreateSessionRequest, ErrorBody,
+    ErrorResponse, HealthResponse, ImageGenerationRequest, ImageGenerationResult, InferMode,
+    InferRequest, InferResponse, IntegrityScanRequest, JobAcceptedResponse, JobInfoResponse,
+    LoadModelRequest, LoadModelResponse, ModelCacheResponse, ModelErrorInfo, ModelInfoResponse,
+    ModelListResponse, ModelObject, RegisterModelRequest, ReplicaCacheInfo, ReplicaLoadTimings,
+    RouterInfoResponse, ScratchReleaseResponse, SessionMemoryResponse, SessionResponse,
+    SharedPrefixCompletion, SharedPrefixRequest, SharedPrefixResponse, StopSequences,
+    TextCompletionChoice, TextCompletionRequest, TextCompletionResponse, TokenUsage,
+    UnloadModelRequest, UpdateSessionMemoryRequest,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Local LLM Inference Service"),
+    paths(
+        crate::api::get_health,
+        crate::api::get_metrics,
+        crate::info::get_info,
+        crate::api::model_events,
+        crate::api::list_models,
+        crate::api::register_model,
+        crate::discovery::rescan_models,
+        crate::api::model_cache,
+        crate::api::clear_model_cache,
+        crate::api::estimate_model_memory,
+        crate::perf_history::model_perf,
+        crate::pull::pull_model,
+        crate::pull::pull_events,
+        crate::api::list_routers,
+        crate::api::list_jobs,
+        crate::api::get_job,
+        crate::api::load_model,
+        crate::api::unload_model,
+        crate::bulk::bulk_models,
+        crate::api::release_scratch,
+        crate::catalog::list_catalog,
+        crate::catalog::install_catalog_model,
+        crate::quant_bench::benchmark_catalog_model,
+        crate::api::infer,
+        crate::api::infer_stream_get,
+        crate::api::infer_shared_prefix,
+        crate::api::infer_continue,
+        crate::generations::pause_generation,
+        crate::generations::resume_generation,
+        crate::generations::active_requests,
+        crate::generations::cancel_request,
+        crate::openai::list_models,
+        crate::openai::chat_completions,
+        crate::openai::completions,
+        crate::assistant::create_run,
+        crate::tools::list_tools,
+        crate::rag::add_documents,
+        crate::rag::upsert_documents,
+        crate::rag::delete_document,
+        crate::rag::compact_profile,
+        crate::rag::load_profile,
+        crate::openai::image_generations,
+        crate::openai::get_image,
+        crate::chat::create_session,
+        crate::chat::get_session,
+        crate::chat::update_session,
+        crate::chat::close_session,
+        crate::chat::send_message,
+        crate::chat::stream_session,
+        crate::chat::get_session_memory,
+        crate::chat::update_session_memory,
+        crate::admin::integrity_scan,
+        crate::admin::unload_model,
+        crate::admin::delete_model,
+        crate::trash::list_trash,
+        crate::trash::restore_model,
+        crate::trash::purge_model,
+        crate::request_log::export,
+        crate::admin_api::models,
+        crate::admin_api::load_model,
+        crate::admin_api::unload_model,
+        crate::admin_api::queue,
+        crate::admin_api::cancel_request,
+        crate::admin_api::usage_series,
+    ),
+    components(schemas(
+        HealthResponse,
+        crate::health::HealthStatus,
+        crate::info::ServerInfo,
+        crate::info::BuildLimits,
+        ModelInfoResponse,
+        crate::model_registry::ModelStatus,
+        crate::jobs::JobStatus,
+        ModelErrorInfo,
+        RouterInfoResponse,
+        crate::router::RoutingRule,
+        crate::router::RouteCondition,
+        ModelCacheResponse,
+        ReplicaCacheInfo,
+        crate::engine::CacheStats,
+        crate::memory::MemoryEstimate,
+        crate::discovery::RescanResponse,
+        crate::discovery::DiscoveredModel,
+        crate::discovery::SkippedFile,
+        crate::perf_history::ModelPerfResponse,
+        crate::perf_history::PerfWindow,
+        crate::perf_history::Percentiles,
+        crate::pull::PullStatus,
+        crate::pull::PullProgress,
+        crate::pull::PullResult,
+        crate::pull::PulledFile,
+        crate::request_log::RequestKind,
+        crate::request_log::RequestLogEntry,
+        crate::request_log::MetricsRow,
+        RegisterModelRequest,
+        LoadModelRequest,
+        LoadModelResponse,
+        UnloadModelRequest,
+        ReplicaLoadTimings,
+        crate::engine::LoadTimings,
+        crate::scratch::ScratchOptions,
+        crate::catalog::CatalogEntry,
+        crate::catalog::CatalogQuant,
+        crate::catalog::CatalogInstallResult,
+        crate::quant_bench::QuantBenchmarkRequest,
+        crate::quant_bench::BenchmarkCase,
+        crate::quant_bench::QuantBenchmarkReport,
+        crate::quant_bench::QuantBenchmarkResult,
+        ScratchReleaseResponse,
+        InferMode,
+        crate::preemption::Priority,
+        crate::chat_template::ChatTemplate,
+        crate::output_validation::OutputValidation,
+        crate::output_validation::OutputCheck,
+        InferRequest,
+        InferResponse,
+        TokenUsage,
+        crate::token_trace::TokenTrace,
+        SharedPrefixRequest,
+        SharedPrefixResponse,
+        SharedPrefixCompletion,
+        ContinueRequest,
+        ContinueResponse,
+        crate::generations::GenerationStateResponse,
+        crate::generations::ActiveRequest,
+        crate::generations::ActiveState,
+        crate::generations::CancelledRequest,
+        crate::trash::TrashEntry,
+        crate::admin_api::AdminModel,
+        crate::admin_api::LoadProgress,
+        crate::admin_api::QueueView,
+        crate::admin_api::UsagePoint,
+        crate::admin_api::UsageResponse,
+        crate::self_test::SelfTestReport,
+        crate::self_test::SelfTestCheck,
+        crate::engine::FinishReason,
+        crate::engine::SamplingParams,
+        ChatCompletionRequest,
+        ChatCompletionResponse,
+        ChatCompletionChoice,
+        ChatCompletionChunk,
+        ChatCompletionChunkChoice,
+        ChatDelta,
+        ModelListResponse,
+        ModelObject,
+        TextCompletionRequest,
+        TextCompletionResponse,
+        TextCompletionChoice,
+        StopSequences,
+        crate::assistant::AssistantRunRequest,
+        crate::assistant::AssistantRunResponse,
+        crate::assistant::AssistantStep,
+        crate::tools::ToolInfo,
+        crate::tools::ToolLimits,
+        crate::rag::RagDocumentsRequest,
+        crate::rag::RagDocumentsResponse,
+        crate::rag::RagSource,
+        crate::rag::GroundedSentence,
+        crate::rag::RagDocument,
+        crate::rag::RagUpsertRequest,
+        crate::rag::RagUpsertResponse,
+        crate::rag::DocumentUpsert,
+        crate::rag::RagDeleteResponse,
+        crate::rag::RagCompactResponse,
+        ImageGenerationRequest,
+        ImageGenerationResult,
+        crate::device::DeviceSpec,
+        CreateSessionRequest,
+        SessionResponse,
+        SessionMemoryResponse,
+        UpdateSessionMemoryRequest,
+        crate::session::SessionOptions,
+        crate::session::GenerationDefaults,
+        crate::session::ChatTurn,
+        crate::session::ChatRole,
+        crate::prompt_compression::CompressionStrategy,
+        crate::prompt_compression::CompressionReport,
+        ChatMessageRequest,
+        ChatMessageResponse,
+        JobInfoResponse,
+        JobAcceptedResponse,
+        crate::bulk::BulkModelsRequest,
+        crate::bulk::BulkOperation,
+        crate::bulk::BulkAction,
+        crate::bulk::BulkModelsResult,
+        crate::bulk::BulkOperationResult,
+        crate::bulk::BulkOutcome,
+        IntegrityScanRequest,
+        AdminActionResponse,
+        crate::confirm::AdminAction,
+        crate::confirm::Impact,
+        crate::confirm::ConfirmationRequired,
+        ErrorResponse,
+        ErrorBody,
+        crate::output_validation::ValidationAttempt,
+    )),
+    modifiers(&ApiKeyAuth, &VersionedPaths),
+    tags(
+        (name = "inference", description = "Text generation"),
+        (name = "sessions", description = "Server-side multi-turn chat"),
+        (name = "models", description = "Model registry, loading and KV cache"),
+        (name = "rag", description = "Embedding + chat model pairs and their documents"),
+        (name = "admin", description = "Maintenance operations"),
+        (name = "admin-ui", description = "Unstable endpoints for the bundled admin page"),
+        (name = "ops", description = "Health, metrics and background jobs"),
+    )
+)]
+pub struct ApiDoc;
+
+/// `X-API-Key`（也接受 `Authorization: Bearer`），见 `api_keys`
+struct ApiKeyAuth;
+
+impl Modify for ApiKeyAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+            );
+        }
+    }
+}
+
+#[get("/openapi.json")]
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Swagger UI 从 CDN 加载，服务端不打包前端资源
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Local LLM Inference Service - API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+#[get("/docs")]
+pub async fn swagger_ui() -> RawHtml<&'static str> {
+    RawHtml(SWAGGER_UI)
+}
@@ -0,0 +1,158 @@
+//! 对完整输出的格式校验（请求里的 `validate`）：JSON、正则、最大长度。
+//!
+//! 没通过时服务端自动重试，每次换一个 seed 并调高温度（greedy 解码重试也只会得到同样的输出），
+//! 最多 `retries` 次；全部失败时返回 422 `output_validation_failed`，错误体里带着每一次的输出和原因。
+//! 只支持非流式文本请求。
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::engine::SamplingParams;
+
+/// 默认重试次数
+pub const DEFAULT_RETRIES: u32 = 2;
+/// 重试次数上限
+pub const MAX_RETRIES: u32 = 5;
+/// 每次重试温度增加多少（从请求的温度开始，没填时从 0 开始），最高 `MAX_RETRY_TEMPERATURE`
+const TEMPERATURE_STEP: f64 = 0.3;
+const MAX_RETRY_TEMPERATURE: f64 = 1.5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OutputCheck {
+    /// 整个输出（去掉首尾空白）是合法的 JSON
+    Json,
+    /// 输出里能找到匹配（要整段匹配请加 `^...$`）
+    Regex { pattern: String },
+    /// 输出不超过这么多个字符
+    MaxLength { chars: usize },
+}
+
+/// 请求里的 `validate`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct OutputValidation {
+    pub check: OutputCheck,
+    /// 没通过时最多重试几次，默认 `DEFAULT_RETRIES`，最多 `MAX_RETRIES`
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_retries() -> u32 {
+    DEFAULT_RETRIES
+}
+
+/// 没通过校验的一次生成
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ValidationAttempt {
+    pub output: String,
+    /// 这一次用的采样参数（第一次是请求自己的）
+    pub sampling: SamplingParams,
+    pub reason: String,
+}
+
+impl OutputValidation {
+    /// 请求进来时检查参数本身：正则能编译、重试次数不超过上限
+    pub fn verify(&self) -> Result<(), String> {
+        if self.retries > MAX_RETRIES {
+            return Err(format!(
+                "validate.retries is {}, maximum allowed is {MAX_RETRIES}",
+                self.retries
+            ));
+        }
+        if let OutputCheck::Regex { pattern } = &self.check {
+            Regex::new(pattern).map_err(|e| format!("invalid validate.pattern: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// 通过时 Ok，否则给出原因
+    pub fn check(&self, output: &str) -> Result<(), String> {
+        match &self.check {
+            OutputCheck::Json => serde_json::from_str::<serde_json::Value>(output.trim())
+                .map(|_| ())
+                .map_err(|e| format!("output is not valid JSON: {e}")),
+            OutputCheck::Regex { pattern } => {
+                let re = Regex::new(pattern).map_err(|e| format!("invalid pattern: {e}"))?;
+                if re.is_match(output) {
+                    Ok(())
+                } else {
+                    Err(format!("output does not match `{pattern}`"))
+                }
+            }
+            OutputCheck::MaxLength { chars } => {
+                let len = output.chars().count();
+                if len <= *chars {
+                    Ok(())
+                } else {
+                    Err(format!("output is {len} characters, maximum is {chars}"))
+                }
+            }
+        }
+    }
+}
+
+/// 第 `attempt` 次重试（从 1 开始）的采样参数：seed 往后挪，温度调高
+pub fn retry_sampling(base: SamplingParams, attempt: u32) -> SamplingParams {
+    let temperature = base.temperature.unwrap_or(0.0) + TEMPERATURE_STEP * attempt as f64;
+    SamplingParams {
+        temperature: Some(temperature.min(MAX_RETRY_TEMPERATURE)),
+        seed: Some(base.seed.unwrap_or(0).wrapping_add(attempt as u64)),
+        ..base
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validation(check: OutputCheck) -> OutputValidation {
+        OutputValidation { check, retries: 1 }
+    }
+
+    #[test]
+    fn checks_accept_and_reject_outputs() {
+        let json = validation(OutputCheck::Json);
+        assert!(json.check(" {\"a\": 1}\n").is_ok());
+        assert!(json.check("{\"a\": ").unwrap_err().contains("not valid JSON"));
+
+        let regex = validation(OutputCheck::Regex {
+            pattern: r"^\d+$".to_string(),
+        });
+        assert!(regex.check("42").is_ok());
+        assert!(regex.check("forty-two").is_err());
+
+        let short = validation(OutputCheck::MaxLength { chars: 3 });
+        assert!(short.check("héé").is_ok());
+        assert_eq!(
+            short.check("four").unwrap_err(),
+            "output is 4 characters, maximum is 3"
+        );
+    }
+
+    #[test]
+    fn verify_rejects_bad_patterns_and_too_many_retries() {
+        let bad = validation(OutputCheck::Regex {
+            pattern: "(".to_string(),
+        });
+        assert!(bad.verify().unwrap_err().contains("invalid validate.pattern"));
+        let greedy = OutputValidation {
+            check: OutputCheck::Json,
+            retries: MAX_RETRIES + 1,
+        };
+        assert!(greedy.verify().is_err());
+    }
+
+    #[test]
+    fn retries_move_the_seed_and_raise_the_temperature() {
+        let base = SamplingParams {
+            top_k: Some(40),
+            ..SamplingParams::default()
+        };
+        let first = retry_sampling(base, 1);
+        assert_eq!(first.seed, Some(1));
+        assert_eq!(first.temperature, Some(0.3));
+        assert_eq!(first.top_k, Some(40));
+        assert_eq!(retry_sampling(base, 9).temperature, Some(MAX_RETRY_TEMPERATURE));
+    }
+}
@@ -0,0 +1,334 @@
+//! 每个模型最近的请求性能：`GET /models/<name>/perf` 给出最近一小时 / 一天的
+//! 延迟和 tokens/s 的 p50 / p95，改了配置之后不用外部监控也能看出有没有变慢。
+//!
+//! 每个成功的推理请求记一条样本（端到端延迟、生成的 token 数，按词近似，和流式统计一致）。
+//! 配置了 `perf_history` 文件时样本追加写入（JSON Lines），重启后读回；超过一天的样本丢弃。
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use parking_lot::Mutex;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::AppState;
+
+const HOUR_MS: u64 = 60 * 60 * 1000;
+/// 样本保留多久
+const RETENTION_MS: u64 = 24 * HOUR_MS;
+/// 每个模型最多保留多少条样本
+const MAX_SAMPLES_PER_MODEL: usize = 20_000;
+/// 追加了这么多行之后重写一次文件，去掉过期样本
+const COMPACT_AFTER: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PerfSample {
+    /// 完成时间（unix 毫秒）
+    pub at_ms: u64,
+    pub latency_ms: u64,
+    pub tokens: usize,
+}
+
+impl PerfSample {
+    fn tokens_per_second(&self) -> Option<f64> {
+        (self.latency_ms > 0).then(|| self.tokens as f64 * 1000.0 / self.latency_ms as f64)
+    }
+}
+
+/// 文件里的一行
+#[derive(Serialize, Deserialize)]
+struct PerfLine {
+    model: String,
+    #[serde(flatten)]
+    sample: PerfSample,
+}
+
+#[derive(Default)]
+struct Inner {
+    models: HashMap<String, VecDeque<PerfSample>>,
+    file: Option<PerfFile>,
+}
+
+struct PerfFile {
+    path: PathBuf,
+    writer: File,
+    appended: usize,
+}
+
+/// 按模型保存的性能样本；默认只在内存里
+#[derive(Default)]
+pub struct PerfHistory {
+    inner: Mutex<Inner>,
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+impl PerfHistory {
+    /// 使用 `path` 持久化：读回其中一天以内的样本（坏行跳过），重写文件后继续追加
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut models: HashMap<String, VecDeque<PerfSample>> = HashMap::new();
+        if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let cutoff = now_ms().saturating_sub(RETENTION_MS);
+            for line in text.lines() {
+                let Ok(PerfLine { model, sample }) = serde_json::from_str(line) else {
+                    continue;
+                };
+                if sample.at_ms >= cutoff {
+                    push_capped(models.entry(model).or_default(), sample);
+                }
+            }
+        }
+        let file = rewrite(&path, &models)?;
+        Ok(Self {
+            inner: Mutex::new(Inner {
+                models,
+                file: Some(file),
+            }),
+        })
+    }
+
+    /// 记一次刚完成的请求
+    pub fn record(&self, model: &str, latency: Duration, tokens: usize) {
+        self.record_sample(
+            model,
+            PerfSample {
+                at_ms: now_ms(),
+                latency_ms: latency.as_millis() as u64,
+                tokens,
+            },
+        );
+    }
+
+    pub fn record_sample(&self, model: &str, sample: PerfSample) {
+        let mut inner = self.inner.lock();
+        let Inner { models, file } = &mut *inner;
+        let samples = models.entry(model.to_string()).or_default();
+        let cutoff = sample.at_ms.saturating_sub(RETENTION_MS);
+        while samples.front().is_some_and(|s| s.at_ms < cutoff) {
+            samples.pop_front();
+        }
+        push_capped(samples, sample);
+
+        let Some(perf_file) = file else {
+            return;
+        };
+        let line = PerfLine {
+            model: model.to_string(),
+            sample,
+        };
+        let written = serde_json::to_string(&line)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(writeln!(perf_file.writer, "{json}")?));
+        if let Err(e) = written {
+            warn!(path = %perf_file.path.display(), "failed to append: {e}");
+            return;
+        }
+        perf_file.appended += 1;
+        if perf_file.appended >= COMPACT_AFTER {
+            match rewrite(&perf_file.path, models) {
+                Ok(compacted) => *perf_file = compacted,
+                Err(e) => warn!("{e:#}"),
+            }
+        }
+    }
+
+    /// 截至 `now_ms` 的最近一小时 / 一天；没有任何样本时返回 None
+    pub fn summary(&self, model: &str, now_ms: u64) -> Option<ModelPerfResponse> {
+        let inner = self.inner.lock();
+        let samples = inner.models.get(model).filter(|s| !s.is_empty())?;
+        let window = |span_ms: u64| {
+            let cutoff = now_ms.saturating_sub(span_ms);
+            PerfWindow::from_samples(samples.iter().filter(|s| s.at_ms >= cutoff))
+        };
+        Some(ModelPerfResponse {
+            model: model.to_string(),
+            last_hour: window(HOUR_MS),
+            last_day: window(RETENTION_MS),
+        })
+    }
+}
+
+fn push_capped(samples: &mut VecDeque<PerfSample>, sample: PerfSample) {
+    if samples.len() == MAX_SAMPLES_PER_MODEL {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+/// 只写入内存里保留的样本（先写临时文件再替换），返回追加用的句柄
+fn rewrite(
+    path: &Path,
+    models: &HashMap<String, VecDeque<PerfSample>>,
+) -> anyhow::Result<PerfFile> {
+    let tmp = path.with_extension("tmp");
+    let mut out = String::new();
+    for (model, samples) in models {
+        for &sample in samples {
+            let line = PerfLine {
+                model: model.clone(),
+                sample,
+            };
+            out.push_str(&serde_json::to_string(&line)?);
+            out.push('\n');
+        }
+    }
+    std::fs::write(&tmp, out).with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {}", path.display()))?;
+    let writer = OpenOptions::new()
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(PerfFile {
+        path: path.to_path_buf(),
+        writer,
+        appended: 0,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p95: f64,
+}
+
+impl Percentiles {
+    /// nearest-rank；`values` 为空时返回 None
+    pub(crate) fn of(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let rank = |p: f64| values[((p * values.len() as f64).ceil() as usize).max(1) - 1];
+        Some(Self {
+            p50: rank(0.5),
+            p95: rank(0.95),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PerfWindow {
+    pub requests: usize,
+    /// 端到端延迟（含排队）
+    pub latency_ms: Option<Percentiles>,
+    pub tokens_per_second: Option<Percentiles>,
+}
+
+impl PerfWindow {
+    fn from_samples<'a>(samples: impl Iterator<Item = &'a PerfSample>) -> Self {
+        let samples: Vec<_> = samples.collect();
+        Self {
+            requests: samples.len(),
+            latency_ms: Percentiles::of(samples.iter().map(|s| s.latency_ms as f64).collect()),
+            tokens_per_second: Percentiles::of(
+                samples
+                    .iter()
+                    .filter_map(|s| s.tokens_per_second())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct ModelPerfResponse {
+    pub model: String,
+    pub last_hour: PerfWindow,
+    pub last_day: PerfWindow,
+}
+
+/// 最近的性能：GET /models/<name>/perf
+#[utoipa::path(
+    tag = "models",
+    responses(
+        (status = 200, body = ModelPerfResponse),
+        (status = 404, description = "unknown model without recorded requests", body = ErrorResponse)
+    )
+)]
+#[get("/models/<name>/perf")]
+pub async fn model_perf(
+    state: &State<Arc<AppState>>,
+    name: &str,
+) -> Result<Json<ModelPerfResponse>, ApiError> {
+    if let Some(summary) = state.perf.summary(name, now_ms()) {
+        return Ok(Json(summary));
+    }
+    if state.registry.get_model(name).is_none() {
+        return Err(api_error(
+            Status::NotFound,
+            "model_not_found",
+            format!("model `{name}` not found"),
+        ));
+    }
+    let empty = PerfWindow::from_samples(std::iter::empty());
+    Ok(Json(ModelPerfResponse {
+        model: name.to_string(),
+        last_hour: empty.clone(),
+        last_day: empty,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(at_ms: u64, latency_ms: u64) -> PerfSample {
+        PerfSample {
+            at_ms,
+            latency_ms,
+            tokens: 10,
+        }
+    }
+
+    #[test]
+    fn windows_and_percentiles() {
+        let history = PerfHistory::default();
+        let now = 10 * RETENTION_MS;
+        history.record_sample("m", sample(now - 2 * HOUR_MS, 1000));
+        for latency in [100, 200, 300, 400] {
+            history.record_sample("m", sample(now - 1000, latency));
+        }
+
+        let perf = history.summary("m", now).unwrap();
+        assert_eq!(perf.last_hour.requests, 4);
+        assert_eq!(perf.last_day.requests, 5);
+        let latency = perf.last_hour.latency_ms.unwrap();
+        assert_eq!((latency.p50, latency.p95), (200.0, 400.0));
+        assert_eq!(perf.last_day.latency_ms.unwrap().p50, 300.0);
+        assert_eq!(perf.last_hour.tokens_per_second.unwrap().p95, 100.0);
+        assert!(history.summary("other", now).is_none());
+    }
+
+    #[test]
+    fn history_survives_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("perf.jsonl");
+        let history = PerfHistory::open(&path).unwrap();
+        history.record("m", Duration::from_millis(500), 20);
+        // 过期的样本重启时丢掉
+        history.record_sample("m", sample(1, 100));
+        drop(history);
+
+        let history = PerfHistory::open(&path).unwrap();
+        let perf = history.summary("m", now_ms()).unwrap();
+        assert_eq!(perf.last_day.requests, 1);
+        assert_eq!(perf.last_day.tokens_per_second.unwrap().p50, 40.0);
+    }
+}
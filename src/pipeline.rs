@@ -0,0 +1,1941 @@
+//! 统一的推理流水线：validate → admit → execute → stream / collect
+//!
+//! 所有推理路由（`/infer`、`/infer?stream=true`、`/infer_stream`、`/v1/chat/completions`）
+//! 都走这里，保证校验、并发控制和执行方式不会在各个 endpoint 之间分叉。
+//!
+//! 模型配置了 `fallbacks` 时，主模型未加载、出错或超时会按顺序换下一个模型，
+//! 结果里的 `served_by` 记录实际生成的模型。流式请求只在开始前（未加载）切换。
+//! 配置了热备（见 `standby`）的模型在重新加载或出错时先由热备实例服务，再考虑 fallbacks。
+//!
+//! 请求带 `input_ids` / `return_token_ids` 时走 engine 的 token 级接口，跳过 encode/decode。
+//! `/infer/shared_prefix` 的一批后缀共用一次前缀 prefill，见 `collect_shared_prefix`。
+//!
+//! `priority: "high"` 的请求没有空闲 permit 时会抢占一个 `low` 生成（见 `preemption`），
+//! `low` 的文本生成因此都按流式执行，以便被抢占后接着已生成的部分继续。
+//! 分时设备（见 `time_slice`）上的交互式文本生成同样按流式执行，一轮到时间就交出设备、排队后接着生成。
+//!
+//! 登记过的请求都可以用 `DELETE /requests/<id>` 取消（见 `cancel`）：排队中的出队，执行中的停止生成并释放 permit。
+//!
+//! 请求带的 `tags` 记在 `infer` span 里，请求结束时和用量一起进请求日志和 `/metrics`（见 `tags`）。
+//!
+//! 准入时按 prompt + max_tokens 估算 KV cache，超过 `kv_budget` 的软上限时排队或拒绝。
+//!
+//! 流式输出有两个上限（`StreamConfig`）：客户端太久不读、或者整条流持续太久时
+//! 停止生成、释放 permit，并以一条 `StreamChunk::Error` 结束。
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rocket::tokio::select;
+use rocket::tokio::sync::{mpsc, OwnedSemaphorePermit};
+use rocket::tokio::task::JoinHandle;
+use rocket::tokio::time::{sleep_until, timeout, Instant};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{info, info_span, warn, Instrument, Span};
+
+use crate::app_state::{AppState, InflightGuard};
+use crate::batcher::BatchError;
+use crate::cancel::{self, Cancelled};
+use crate::chat_template::{self, ChatTemplate};
+use crate::device::DeviceSpec;
+use crate::engine::{
+    ContinuedGeneration, FinishReason, Generation, InferenceEngine, SamplingParams,
+    SharedPrefixGeneration,
+};
+use crate::generations::GenerationHandle;
+use crate::kv_budget::{BudgetRefusal, KvReservation};
+use crate::metrics::{Metrics, UndeliveredReason};
+use crate::model_registry::{Modality, ModelStatus};
+use crate::output_validation::{self, ValidationAttempt};
+use crate::preemption::Priority;
+use crate::privacy::describe;
+use crate::prompt_compression::estimate_tokens;
+use crate::queue::QueueRefusal;
+use crate::request_log::RequestKind;
+use crate::router::{pick_auto, AutoCandidate, AUTO_MODEL};
+use crate::tags::{self, RequestTags};
+use crate::token_trace::{tap, TokenTrace, TraceRecorder};
+use crate::types::{InferMode, InferRequest, TokenUsage};
+
+/// 非流式默认生成长度
+pub const COLLECT_MAX_TOKENS: usize = 64;
+/// 流式默认生成长度
+pub const STREAM_MAX_TOKENS: usize = 128;
+/// 流式 channel 默认容量
+pub const STREAM_CHANNEL_CAPACITY: usize = 32;
+/// 客户端默认最多这么久（毫秒）不读
+pub const STREAM_IDLE_TIMEOUT_MS: u64 = 60_000;
+/// 默认每隔这么久（毫秒）发一次 `event: stats`
+pub const STREAM_STATS_INTERVAL_MS: u64 = 1_000;
+
+/// 客户端读得比生成慢、channel 满了时怎么办
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// 等客户端读，生成跟着停下（一直占着 permit）
+    #[default]
+    Block,
+    /// 丢掉最早未发出的 chunk，在断开的位置插入 `StreamChunk::Gap`
+    DropOldest,
+    /// 停止生成并以 `StreamChunk::Error` 结束
+    Abort,
+}
+
+/// 流式输出的缓冲配置（`ServerConfig.stream`）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamConfig {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// 客户端连续这么久（毫秒）没有取走 chunk 就结束这条流；0 表示不限
+    pub idle_timeout_ms: u64,
+    /// 一条流最长持续多久（毫秒），包括生成和等客户端读；0 表示不限
+    pub max_duration_ms: u64,
+    /// SSE 里 `event: stats`（chunk 数和速度）的间隔（毫秒）；0 表示不发
+    pub stats_interval_ms: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            capacity: STREAM_CHANNEL_CAPACITY,
+            overflow: OverflowPolicy::Block,
+            idle_timeout_ms: STREAM_IDLE_TIMEOUT_MS,
+            max_duration_ms: 0,
+            stats_interval_ms: STREAM_STATS_INTERVAL_MS,
+        }
+    }
+}
+
+impl StreamConfig {
+    fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_ms > 0).then(|| Duration::from_millis(self.idle_timeout_ms))
+    }
+
+    fn max_duration(&self) -> Option<Duration> {
+        (self.max_duration_ms > 0).then(|| Duration::from_millis(self.max_duration_ms))
+    }
+
+    pub fn stats_interval(&self) -> Option<Duration> {
+        (self.stats_interval_ms > 0).then(|| Duration::from_millis(self.stats_interval_ms))
+    }
+}
+
+/// 服务端提前结束一条流的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamCutoff {
+    /// `OverflowPolicy::Abort` 下 channel 满了
+    Overflow,
+    /// 客户端超过 `idle_timeout_ms` 没有读
+    Idle,
+    /// 超过 `max_duration_ms`
+    MaxDuration,
+}
+
+impl StreamCutoff {
+    fn message(self, config: &StreamConfig) -> String {
+        match self {
+            StreamCutoff::Overflow => {
+                "client is not reading fast enough, generation aborted".to_string()
+            }
+            StreamCutoff::Idle => format!(
+                "client did not read for {} ms, stream closed",
+                config.idle_timeout_ms
+            ),
+            StreamCutoff::MaxDuration => format!(
+                "stream exceeded the maximum duration of {} ms, generation stopped",
+                config.max_duration_ms
+            ),
+        }
+    }
+}
+
+/// 一次流式请求生成了多少 chunk、客户端实际取走了多少（engine 按 token / 词推送，
+/// 一个 chunk 近似一个 token）。生成任务和 `StreamReceiver` 都结束后在 drop 时记账：
+/// 计入 metrics，有没送达的部分时打一行日志
+pub struct StreamTally {
+    model: String,
+    metrics: Arc<Metrics>,
+    generated: AtomicUsize,
+    delivered: AtomicUsize,
+    overflowed: AtomicBool,
+    failed: AtomicBool,
+    /// 生成的全部文本（包括没送达的），结束时按 tokenizer 统计 `usage`
+    text: Mutex<String>,
+}
+
+impl StreamTally {
+    pub fn new(model: impl Into<String>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            model: model.into(),
+            metrics,
+            generated: AtomicUsize::new(0),
+            delivered: AtomicUsize::new(0),
+            overflowed: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+            text: Mutex::new(String::new()),
+        }
+    }
+
+    /// 记下引擎生成的一个 chunk。引擎按词推送，拼回去时补空格
+    fn record(&self, chunk: &str) {
+        self.generated.fetch_add(1, Ordering::Relaxed);
+        let mut text = self.text.lock();
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(chunk);
+    }
+}
+
+impl Drop for StreamTally {
+    fn drop(&mut self) {
+        let generated = self.generated.load(Ordering::Relaxed);
+        let delivered = self.delivered.load(Ordering::Relaxed);
+        let undelivered = generated.saturating_sub(delivered);
+        let reason = if self.failed.load(Ordering::Relaxed) {
+            UndeliveredReason::Error
+        } else if self.overflowed.load(Ordering::Relaxed) {
+            UndeliveredReason::Overflow
+        } else {
+            // 客户端断开、服务器关闭都算在这里
+            UndeliveredReason::Disconnect
+        };
+        self.metrics
+            .record_stream_tokens(generated, undelivered, reason);
+        if undelivered > 0 {
+            warn!(
+                model = %self.model,
+                reason = reason.as_str(),
+                generated,
+                delivered,
+                "stream ended early"
+            );
+        }
+    }
+}
+
+/// `pipeline.stream` 的接收端，记录客户端取走了多少 chunk
+pub struct StreamReceiver {
+    rx: mpsc::Receiver<StreamChunk>,
+    tally: Option<Arc<StreamTally>>,
+}
+
+impl StreamReceiver {
+    pub async fn recv(&mut self) -> Option<StreamChunk> {
+        let chunk = self.rx.recv().await;
+        if let (Some(StreamChunk::Text(_)), Some(tally)) = (&chunk, &self.tally) {
+            tally.delivered.fetch_add(1, Ordering::Relaxed);
+        }
+        chunk
+    }
+}
+
+/// 流式输出的一项。生成中途失败时最后一项是 `Error`，之后 channel 关闭
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamChunk {
+    Text(String),
+    /// `OverflowPolicy::DropOldest` 下这里丢了 `dropped` 个 chunk
+    Gap {
+        dropped: usize,
+    },
+    Error(String),
+    /// 正常结束时的最后一项：按 tokenizer 统计的 token 数（引擎不能分词时没有这一项）
+    Usage(TokenUsage),
+}
+
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("model `{0}` not found")]
+    ModelNotFound(String),
+    #[error("model `{model}` is not loaded (status = {status:?})")]
+    NotLoaded { model: String, status: ModelStatus },
+    #[error("no engine instance for model `{0}`")]
+    NoEngine(String),
+    #[error("model `{model}` is not available on {device} (placements: {available})")]
+    DeviceUnavailable {
+        model: String,
+        device: DeviceSpec,
+        available: String,
+    },
+    #[error("no loaded model can serve this request (needs {required_tokens} tokens of context)")]
+    NoAutoCandidate { required_tokens: usize },
+    #[error("model `{model}` timed out after {after_ms} ms")]
+    Timeout { model: String, after_ms: u64 },
+    #[error(
+        "request needs about {required_mb} MiB of KV cache, more than the limit of {limit_mb} MiB; \
+         shorten the prompt or max_tokens"
+    )]
+    ContextTooLarge { required_mb: u64, limit_mb: u64 },
+    #[error(
+        "context too large right now: request needs about {required_mb} MiB of KV cache, \
+         {in_use_mb} of {limit_mb} MiB in use; retry later"
+    )]
+    MemoryBusy {
+        required_mb: u64,
+        in_use_mb: u64,
+        limit_mb: u64,
+    },
+    #[error("request queue is full ({max_len} requests waiting); retry later")]
+    QueueFull { position: usize, max_len: usize },
+    #[error(
+        "no inference slot after waiting {waited_ms} ms (queue position {position}); retry later"
+    )]
+    QueueTimeout { position: usize, waited_ms: u64 },
+    #[error("inference service is shutting down")]
+    Closed,
+    #[error("request was cancelled")]
+    Cancelled,
+    #[error("error during inference: {0}")]
+    Inference(String),
+    #[error(
+        "output failed validation after {} attempts: {}",
+        .attempts.len(),
+        .attempts.last().map_or("", |a| a.reason.as_str())
+    )]
+    OutputRejected { attempts: Vec<ValidationAttempt> },
+}
+
+impl PipelineError {
+    /// 服务端自身的失败（计入 `/health` 的错误率），其余是请求本身的问题
+    pub fn is_server_error(&self) -> bool {
+        matches!(
+            self,
+            PipelineError::Timeout { .. } | PipelineError::Closed | PipelineError::Inference(_)
+        )
+    }
+}
+
+/// 通过校验、拿到 engine 的一次推理请求
+pub struct ValidatedRequest {
+    pub model_name: String,
+    pub prompt: String,
+    pub device: DeviceSpec,
+    pub engine: Arc<dyn InferenceEngine>,
+    pub timeout: Option<Duration>,
+    pub priority: Priority,
+    /// 请求的采样参数，没填的字段用模型的默认值补上
+    pub sampling: SamplingParams,
+    /// 按 prompt + max_tokens 估算的 KV cache 字节数，引擎不提供时为 0
+    pub kv_bytes: u64,
+    /// `auto_template` 时给 prompt 套上的模板
+    pub applied_template: Option<ChatTemplate>,
+    /// 请求结束前一直计入实例的排队数
+    _inflight: InflightGuard,
+    /// 准入后记账的 KV cache，请求结束时归还
+    _kv: Option<KvReservation>,
+}
+
+/// 非流式结果
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub output: String,
+    /// 实际生成的模型（router 解析 / fallback 之后）
+    pub served_by: String,
+    pub finish_reason: FinishReason,
+    /// `return_token_ids` 时的输出 token，此时 `output` 为空
+    pub output_ids: Option<Vec<u32>>,
+    /// 请求带 `trace` 时的逐 token 耗时
+    pub trace: Option<TokenTrace>,
+    /// 请求带 `auto_template` 时给 prompt 套上的模板
+    pub applied_template: Option<ChatTemplate>,
+    /// 按模型的 tokenizer 统计的 token 数，见 `token_usage`
+    pub usage: Option<TokenUsage>,
+}
+
+/// 已经拿到并发 permit 的请求，可以直接执行
+pub struct AdmittedRequest {
+    pub request: ValidatedRequest,
+    permit: OwnedSemaphorePermit,
+}
+
+#[derive(Clone)]
+pub struct InferencePipeline {
+    state: Arc<AppState>,
+}
+
+impl InferencePipeline {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+
+    /// 1) 校验：模型存在、已加载、有 engine 实例，且请求的设备在 placements 中
+    ///
+    /// 请求的是虚拟 router 时先按 prompt 选出真实模型
+    pub fn validate(&self, req: &InferRequest) -> Result<ValidatedRequest, PipelineError> {
+        let model_name = self.resolve(req, req.max_tokens.unwrap_or(COLLECT_MAX_TOKENS))?;
+        self.validate_on(&model_name, req)
+    }
+
+    /// 把虚拟模型名（`auto` / router）换成真实模型
+    fn resolve(&self, req: &InferRequest, max_tokens: usize) -> Result<String, PipelineError> {
+        if req.model_name != AUTO_MODEL {
+            return Ok(self.state.registry.resolve(&req.model_name, &req.prompt));
+        }
+
+        let prompt_tokens = match &req.input_ids {
+            Some(ids) => ids.len(),
+            None => estimate_tokens(&req.prompt),
+        };
+        let required_tokens = prompt_tokens + max_tokens;
+        let candidates: Vec<AutoCandidate> = self
+            .state
+            .list_models()
+            .into_iter()
+            .filter(|m| m.status == ModelStatus::Loaded)
+            .map(|m| AutoCandidate {
+                queue_depth: self.state.queue_depth(&m.name),
+                name: m.name,
+                context_window: m.context_window,
+                modalities: m.modalities,
+            })
+            .collect();
+        pick_auto(&candidates, required_tokens, Modality::Text)
+            .map(str::to_string)
+            .ok_or(PipelineError::NoAutoCandidate { required_tokens })
+    }
+
+    /// 依次尝试的模型：解析后的主模型 + 它的 fallbacks（去重）
+    pub fn candidates(
+        &self,
+        req: &InferRequest,
+        max_tokens: usize,
+    ) -> Result<Vec<String>, PipelineError> {
+        let primary = self.resolve(req, max_tokens)?;
+        let mut chain = vec![primary.clone()];
+        if let Some(meta) = self.state.registry.get_model(&primary) {
+            for fallback in meta.fallbacks {
+                if !chain.contains(&fallback) {
+                    chain.push(fallback);
+                }
+            }
+        }
+        Ok(chain)
+    }
+
+    fn validate_on(
+        &self,
+        model_name: &str,
+        req: &InferRequest,
+    ) -> Result<ValidatedRequest, PipelineError> {
+        self.validate_with(model_name, req, false)
+    }
+
+    /// `standby` 为 true 时强制用热备实例（主实例生成出错后的重试）；
+    /// 主模型正在重新加载或停在 Error 时也由热备接管
+    fn validate_with(
+        &self,
+        model_name: &str,
+        req: &InferRequest,
+        standby: bool,
+    ) -> Result<ValidatedRequest, PipelineError> {
+        let meta = self
+            .state
+            .registry
+            .get_model(model_name)
+            .ok_or_else(|| PipelineError::ModelNotFound(model_name.to_string()))?;
+        let standby = match meta.status {
+            ModelStatus::Loaded if !standby => None,
+            ModelStatus::Loaded | ModelStatus::Loading | ModelStatus::Error => {
+                self.state.standby.get(model_name, req.device)
+            }
+            _ => None,
+        };
+        if standby.is_none() && !matches!(meta.status, ModelStatus::Loaded) {
+            return Err(PipelineError::NotLoaded {
+                model: model_name.to_string(),
+                status: meta.status,
+            });
+        }
+        if standby.is_some() {
+            info!(model = %model_name, status = ?meta.status, "served by the standby instance");
+        }
+
+        let instance = match standby.or_else(|| self.state.get_engine_on(model_name, req.device)) {
+            Some(instance) => instance,
+            None => {
+                return Err(match req.device {
+                    Some(device) => PipelineError::DeviceUnavailable {
+                        model: model_name.to_string(),
+                        device,
+                        available: meta
+                            .placements
+                            .iter()
+                            .map(|d| d.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                    },
+                    None => PipelineError::NoEngine(model_name.to_string()),
+                })
+            }
+        };
+
+        // 流式的默认长度更长，按它估算
+        let context_tokens = match &req.input_ids {
+            Some(ids) => ids.len(),
+            None => estimate_tokens(&req.prompt),
+        } + req.max_tokens.unwrap_or(STREAM_MAX_TOKENS);
+        let kv_bytes = instance.engine.kv_bytes_per_token().map_or(0, |per_token| {
+            per_token * context_tokens.min(meta.context_window) as u64
+        });
+
+        let (prompt, applied_template) = match req.auto_template && !req.uses_token_ids() {
+            true => chat_template::apply(meta.chat_template, &req.prompt),
+            false => (req.prompt.clone(), None),
+        };
+
+        Ok(ValidatedRequest {
+            model_name: model_name.to_string(),
+            prompt,
+            device: instance.device,
+            _inflight: instance.track(),
+            engine: instance.engine,
+            timeout: meta.timeout,
+            priority: req.priority,
+            sampling: req.sampling.or(meta.sampling),
+            kv_bytes,
+            applied_template,
+            _kv: None,
+        })
+    }
+
+    /// 2) 准入：在 `queue` 里排队等待 semaphore permit，控制并发；队列满了或等太久时拒绝。
+    ///    high 请求没有空闲 permit 时抢占一个 low 生成，直接拿走它的 permit。
+    ///    拿到 permit 后再按 `kv_budget` 记账 KV cache，放不下时排队或拒绝
+    pub async fn admit(
+        &self,
+        mut request: ValidatedRequest,
+    ) -> Result<AdmittedRequest, PipelineError> {
+        let semaphore = self.state.semaphore.clone();
+        let ticket = self.state.queue.enter(&semaphore).map_err(queue_error)?;
+        let preempted = match request.priority {
+            Priority::High if semaphore.available_permits() == 0 => self.state.preemption.preempt(),
+            _ => None,
+        };
+        let acquire = self.state.metrics.acquire(semaphore);
+        let permit = ticket
+            .wait(async {
+                match preempted {
+                    // 被抢占的一方恰好先结束时交接失败，继续正常排队
+                    Some(handoff) => select! {
+                        permit = acquire => permit,
+                        Ok(permit) = handoff => Ok(permit),
+                    },
+                    None => acquire.await,
+                }
+            })
+            .await
+            .map_err(queue_error)?
+            .map_err(|_| PipelineError::Closed)?;
+        if request.kv_bytes > 0 {
+            let reservation = self
+                .state
+                .kv_budget
+                .reserve(request.kv_bytes)
+                .await
+                .map_err(budget_error)?;
+            request._kv = Some(reservation);
+        }
+        request._inflight.start();
+        Ok(AdmittedRequest { request, permit })
+    }
+
+    /// 3a) 执行并收集完整输出；失败时沿 fallback 链重试，全部失败返回主模型的错误
+    pub async fn collect(&self, req: &InferRequest) -> Result<Completion, PipelineError> {
+        let generations = &self.state.generations;
+        let handle = generations.register(
+            &req.model_name,
+            RequestKind::Collect,
+            req.client.as_deref(),
+            req.request_id.as_deref(),
+        );
+        self.collect_as(req, &handle)
+            .instrument(request_span(req, &handle))
+            .await
+    }
+
+    /// 同 `collect`，执行进度记在 `handle` 上
+    async fn collect_as(
+        &self,
+        req: &InferRequest,
+        handle: &GenerationHandle,
+    ) -> Result<Completion, PipelineError> {
+        let started = Instant::now();
+        let result = cancellable(handle, self.collect_validated(req, handle)).await;
+        match &result {
+            Ok(done) => {
+                self.state.metrics.record_outcome(true);
+                // 文本输出按词近似 token 数，和流式统计一致
+                let tokens = done
+                    .output_ids
+                    .as_ref()
+                    .map_or_else(|| done.output.split_whitespace().count(), |ids| ids.len());
+                self.state
+                    .perf
+                    .record(&done.served_by, started.elapsed(), tokens);
+                if let Some(client) = &req.rate_client {
+                    let used = done.usage.map_or_else(
+                        || estimate_tokens(&req.prompt) + tokens,
+                        |usage| usage.total_tokens,
+                    );
+                    self.state.rate_limits.charge(client, used);
+                }
+                record_request(
+                    &self.state,
+                    &done.served_by,
+                    RequestKind::Collect,
+                    started.elapsed(),
+                    req.prompt.len(),
+                    &req.tags,
+                    Ok(tokens),
+                );
+                info!(
+                    served_by = %done.served_by,
+                    prompt = %describe(&req.prompt, req.private),
+                    output = %describe(&done.output, req.private),
+                    finish_reason = ?done.finish_reason,
+                    "completed"
+                );
+                if let Some(trace) = &done.trace {
+                    info!(served_by = %done.served_by, "token trace: {}", trace.summary());
+                }
+            }
+            Err(e) => {
+                if e.is_server_error() {
+                    self.state.metrics.record_outcome(false);
+                }
+                record_request(
+                    &self.state,
+                    &req.model_name,
+                    RequestKind::Collect,
+                    started.elapsed(),
+                    req.prompt.len(),
+                    &req.tags,
+                    Err(e.to_string()),
+                );
+            }
+        }
+        result
+    }
+
+    /// 请求带 `validate` 时检查输出，没通过就换采样参数重试，见 `output_validation`
+    async fn collect_validated(
+        &self,
+        req: &InferRequest,
+        handle: &GenerationHandle,
+    ) -> Result<Completion, PipelineError> {
+        let Some(validation) = &req.validate else {
+            return self.collect_chain(req, handle).await;
+        };
+        let mut attempts = Vec::new();
+        let mut attempt_req = req.clone();
+        for attempt in 0..=validation.retries {
+            if attempt > 0 {
+                attempt_req.sampling = output_validation::retry_sampling(req.sampling, attempt);
+            }
+            let done = self.collect_chain(&attempt_req, handle).await?;
+            match validation.check(&done.output) {
+                Ok(()) => return Ok(done),
+                Err(reason) => {
+                    warn!(attempt, %reason, "output failed validation");
+                    attempts.push(ValidationAttempt {
+                        output: done.output,
+                        sampling: attempt_req.sampling,
+                        reason,
+                    });
+                }
+            }
+        }
+        Err(PipelineError::OutputRejected { attempts })
+    }
+
+    async fn collect_chain(
+        &self,
+        req: &InferRequest,
+        handle: &GenerationHandle,
+    ) -> Result<Completion, PipelineError> {
+        let max_tokens = req.max_tokens.unwrap_or(COLLECT_MAX_TOKENS);
+        let mut errors = Vec::new();
+        for model_name in self.candidates(req, max_tokens)? {
+            let mut result = self
+                .collect_on(&model_name, req, max_tokens, handle, false)
+                .await;
+            // 主实例生成出错时先在热备上重试一次，再换 fallback
+            if matches!(result, Err(PipelineError::Inference(_)))
+                && self.state.standby.get(&model_name, req.device).is_some()
+            {
+                warn!(model = %model_name, "active instance failed, retrying on the standby");
+                result = self
+                    .collect_on(&model_name, req, max_tokens, handle, true)
+                    .await;
+            }
+            match result {
+                Ok(done) => return Ok(done),
+                Err(PipelineError::Closed) => return Err(PipelineError::Closed),
+                Err(e) => {
+                    warn!(model = %model_name, "failed, trying the next fallback: {e}");
+                    errors.push(e);
+                }
+            }
+        }
+        Err(errors.remove(0))
+    }
+
+    async fn collect_on(
+        &self,
+        model_name: &str,
+        req: &InferRequest,
+        max_tokens: usize,
+        handle: &GenerationHandle,
+        standby: bool,
+    ) -> Result<Completion, PipelineError> {
+        let request = self.validate_with(model_name, req, standby)?;
+        handle.set_model(model_name);
+        let timeout = request.timeout;
+        let applied_template = request.applied_template;
+        let engine = request.engine.clone();
+        let prompt = request.prompt.clone();
+        let completion = |output: String, finish_reason, output_ids: Option<Vec<u32>>| Completion {
+            usage: token_usage(
+                engine.as_ref(),
+                &prompt,
+                req.input_ids.as_deref(),
+                &output,
+                output_ids.as_deref(),
+            ),
+            output,
+            served_by: model_name.to_string(),
+            finish_reason,
+            output_ids,
+            trace: None,
+            applied_template,
+        };
+
+        // token 级请求不进攒批队列，批量接口只处理文本
+        if req.uses_token_ids() {
+            let AdmittedRequest { request, permit } = self.admit(request).await?;
+            handle.set_running();
+            let generate = complete_token_level(request.engine.as_ref(), req, max_tokens);
+            let result = with_timeout(model_name, timeout, generate).await?;
+            drop(permit);
+            let (output, finish_reason, output_ids) =
+                result.map_err(|e| PipelineError::Inference(e.to_string()))?;
+            return Ok(completion(output, finish_reason, output_ids));
+        }
+
+        let sliced = self.state.time_slicer.covers(request.device);
+        match req.mode {
+            // 要逐 token 计时、分时设备上的请求也按流式执行
+            InferMode::Interactive if req.priority == Priority::Low || req.trace || sliced => {
+                let AdmittedRequest { request, permit } = self.admit(request).await?;
+                handle.set_running();
+                let (tx, mut rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+                let generate = async {
+                    if sliced {
+                        let result = generate_time_sliced(
+                            &self.state,
+                            &request,
+                            &request.prompt,
+                            max_tokens,
+                            tx,
+                        )
+                        .await;
+                        drop(permit);
+                        return result;
+                    }
+                    if req.priority == Priority::Low {
+                        return generate_preemptible(
+                            &self.state,
+                            &request.engine,
+                            &request.prompt,
+                            max_tokens,
+                            &request.sampling,
+                            permit,
+                            tx,
+                        )
+                        .await;
+                    }
+                    let result = stream_on_task(
+                        &request.engine,
+                        &request.prompt,
+                        "",
+                        max_tokens,
+                        &request.sampling,
+                        tx,
+                    )
+                    .await;
+                    drop(permit);
+                    result
+                };
+                let mut recorder = TraceRecorder::new(Instant::now());
+                let gather = async {
+                    let mut words = Vec::new();
+                    while let Some(word) = rx.recv().await {
+                        recorder.record(Instant::now());
+                        handle.add_tokens(1);
+                        words.push(word);
+                    }
+                    words
+                };
+                let run = async { rocket::tokio::join!(generate, gather) };
+                let (result, words) = with_timeout(model_name, timeout, run).await?;
+                // 被抢占后重新排队也可能排不上
+                result.map_err(|e| match e.downcast_ref::<QueueRefusal>() {
+                    Some(&refusal) => queue_error(refusal),
+                    None => PipelineError::Inference(format!("{e:#}")),
+                })?;
+                let generation = preempted_generation(words, max_tokens);
+                Ok(Completion {
+                    trace: recorder.finish().filter(|_| req.trace),
+                    ..completion(generation.text, generation.finish_reason, None)
+                })
+            }
+            InferMode::Interactive => {
+                let AdmittedRequest { request, permit } = self.admit(request).await?;
+                handle.set_running();
+                let generate =
+                    request
+                        .engine
+                        .complete_sampled(&request.prompt, max_tokens, &request.sampling);
+                let result = with_timeout(model_name, timeout, generate).await?;
+                drop(permit);
+                let generation = result.map_err(|e| PipelineError::Inference(e.to_string()))?;
+                Ok(completion(generation.text, generation.finish_reason, None))
+            }
+            // 攒批调度自己负责 permit，一整批只占一个；批量接口不报告结束原因
+            InferMode::Throughput => {
+                // 同一批共用 max_tokens，所以它也是 key 的一部分
+                let key = format!("{}@{}/{}", request.model_name, request.device, max_tokens);
+                handle.set_running();
+                let submit = self.state.batcher.submit(
+                    &key,
+                    request.engine.clone(),
+                    &request.prompt,
+                    max_tokens,
+                );
+                let text =
+                    with_timeout(model_name, timeout, submit)
+                        .await?
+                        .map_err(|e| match e {
+                            BatchError::Queue(refusal) => queue_error(refusal),
+                            BatchError::Failed(e) => PipelineError::Inference(e),
+                        })?;
+                Ok(completion(text, FinishReason::Stop, None))
+            }
+        }
+    }
+
+    /// 3c) 共享前缀批量：`req.prompt` 是前缀，每个后缀接在它后面各生成一次。
+    /// 整批占一个 permit、在同一个实例上执行，不走 fallback（换模型就没有 cache 可复用）
+    pub async fn collect_shared_prefix(
+        &self,
+        req: &InferRequest,
+        suffixes: &[String],
+    ) -> Result<(String, SharedPrefixGeneration), PipelineError> {
+        let started = Instant::now();
+        let max_tokens = req.max_tokens.unwrap_or(COLLECT_MAX_TOKENS);
+        let request = self.validate(req)?;
+        let model_name = request.model_name.clone();
+        let handle = self.register(&model_name, RequestKind::SharedPrefix, req);
+        let span = request_span(req, &handle);
+        async {
+            let admitted = cancellable(&handle, self.admit(request)).await?;
+            let AdmittedRequest { request, permit } = admitted;
+            handle.set_running();
+            let generate =
+                request
+                    .engine
+                    .complete_shared_prefix(&request.prompt, suffixes, max_tokens);
+            let result = cancellable(&handle, async {
+                with_timeout(&model_name, request.timeout, generate)
+                    .await
+                    .and_then(|r| r.map_err(|e| PipelineError::Inference(e.to_string())))
+            })
+            .await;
+            drop(permit);
+            self.finish(
+                &model_name,
+                RequestKind::SharedPrefix,
+                started,
+                req,
+                result.as_ref().map(|generation| {
+                    generation
+                        .completions
+                        .iter()
+                        .map(|c| c.text.split_whitespace().count())
+                        .sum()
+                }),
+            );
+            result.map(|generation| (model_name, generation))
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// 3d) 接着 `partial` 继续生成。和共享前缀一样不走 fallback：换了实例就没有 cache 可复用
+    pub async fn collect_continuation(
+        &self,
+        req: &InferRequest,
+        partial: &str,
+    ) -> Result<(String, ContinuedGeneration), PipelineError> {
+        let started = Instant::now();
+        let max_tokens = req.max_tokens.unwrap_or(COLLECT_MAX_TOKENS);
+        let request = self.validate(req)?;
+        let model_name = request.model_name.clone();
+        let handle = self.register(&model_name, RequestKind::Continuation, req);
+        let span = request_span(req, &handle);
+        async {
+            let admitted = cancellable(&handle, self.admit(request)).await?;
+            let AdmittedRequest { request, permit } = admitted;
+            handle.set_running();
+            let generate = request.engine.continue_generation(
+                &request.prompt,
+                partial,
+                max_tokens,
+                &request.sampling,
+            );
+            let result = cancellable(&handle, async {
+                with_timeout(&model_name, request.timeout, generate)
+                    .await
+                    .and_then(|r| r.map_err(|e| PipelineError::Inference(e.to_string())))
+            })
+            .await;
+            drop(permit);
+            self.finish(
+                &model_name,
+                RequestKind::Continuation,
+                started,
+                req,
+                result.as_ref().map(|generation| {
+                    generation.text[partial.len().min(generation.text.len())..]
+                        .split_whitespace()
+                        .count()
+                }),
+            );
+            result.map(|generation| (model_name, generation))
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// 登记单实例执行（共享前缀、续写），`GET /admin/requests/active` 里能看到
+    fn register(
+        &self,
+        model_name: &str,
+        kind: RequestKind,
+        req: &InferRequest,
+    ) -> GenerationHandle {
+        self.state.generations.register(
+            model_name,
+            kind,
+            req.client.as_deref(),
+            req.request_id.as_deref(),
+        )
+    }
+
+    /// 记录单实例执行（共享前缀、续写）的成败；`result` 是生成的 token 数
+    fn finish(
+        &self,
+        model_name: &str,
+        kind: RequestKind,
+        started: Instant,
+        req: &InferRequest,
+        result: Result<usize, &PipelineError>,
+    ) {
+        match result {
+            Ok(_) => self.state.metrics.record_outcome(true),
+            Err(e) if e.is_server_error() => self.state.metrics.record_outcome(false),
+            Err(_) => {}
+        }
+        record_request(
+            &self.state,
+            model_name,
+            kind,
+            started.elapsed(),
+            req.prompt.len(),
+            &req.tags,
+            result.map_err(|e| e.to_string()),
+        );
+    }
+
+    /// 3b) 执行并流式返回：后台任务持有 permit，chunk 通过 channel 推送。
+    /// 开始输出后就不能再换模型，所以只跳过 fallback 链中不可用的模型。
+    /// throughput 模式不逐 token 推送，整段结果作为一个 chunk
+    pub async fn stream(&self, req: &InferRequest) -> Result<StreamReceiver, PipelineError> {
+        let generations = &self.state.generations;
+        let handle = generations.register(
+            &req.model_name,
+            RequestKind::Stream,
+            req.client.as_deref(),
+            req.request_id.as_deref(),
+        );
+        self.stream_as(req, handle).await
+    }
+
+    /// 同 `stream`，生成以 `handle` 登记，可以暂停 / 继续（见 `generations`）
+    pub async fn stream_as(
+        &self,
+        req: &InferRequest,
+        handle: GenerationHandle,
+    ) -> Result<StreamReceiver, PipelineError> {
+        let span = request_span(req, &handle);
+        self.start_stream(req, handle).instrument(span).await
+    }
+
+    async fn start_stream(
+        &self,
+        req: &InferRequest,
+        handle: GenerationHandle,
+    ) -> Result<StreamReceiver, PipelineError> {
+        let started = Instant::now();
+        if req.mode == InferMode::Throughput {
+            let done = self.collect_as(req, &handle).await?;
+            let (tx, rx) = mpsc::channel(2);
+            let _ = tx.send(StreamChunk::Text(done.output)).await;
+            if let Some(usage) = done.usage {
+                let _ = tx.send(StreamChunk::Usage(usage)).await;
+            }
+            return Ok(StreamReceiver { rx, tally: None });
+        }
+
+        let max_tokens = req.max_tokens.unwrap_or(STREAM_MAX_TOKENS);
+        let mut errors = Vec::new();
+        let mut validated = None;
+        for model_name in self.candidates(req, max_tokens)? {
+            match self.validate_on(&model_name, req) {
+                Ok(request) => {
+                    validated = Some(request);
+                    break;
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        let request = validated.ok_or_else(|| errors.remove(0))?;
+        let admitted = cancellable(&handle, self.admit(request)).await?;
+        handle.set_running();
+        info!(
+            served_by = %admitted.request.model_name,
+            prompt = %describe(&req.prompt, req.private),
+            "streaming"
+        );
+
+        let config = self.state.streaming.clone();
+        let capacity = config.capacity.max(1);
+        let (tx, rx) = mpsc::channel(capacity);
+        let metrics = self.state.metrics.clone();
+        let tally = Arc::new(StreamTally::new(
+            admitted.request.model_name.clone(),
+            metrics.clone(),
+        ));
+        let receiver = StreamReceiver {
+            rx,
+            tally: Some(tally.clone()),
+        };
+        let sampling = admitted.request.sampling;
+        let trace = req.trace;
+        let prompt_bytes = req.prompt.len();
+        let rate_client = req.rate_client.clone();
+        let tags = req.tags.clone();
+        let cancel = handle.cancel_token().clone();
+        let prompt_tokens = estimate_tokens(&req.prompt);
+        let state = self.state.clone();
+        handle.set_model(&admitted.request.model_name);
+        // 后台任务的日志也留在这个请求的 span 里
+        let task = async move {
+            let AdmittedRequest { request, permit } = admitted;
+            let model_name = request.model_name.clone();
+
+            // engine 只认 `Sender<String>`，这里转发一层，结束后再把错误补在最后。
+            // 引擎到转发之间只留一个 chunk 的缓冲，暂停后引擎很快就停在下一次发送上
+            let (engine_tx, engine_rx) = mpsc::channel::<String>(1);
+            let (text_tx, text_rx) = mpsc::channel::<String>(capacity);
+            // 要计时时在引擎后面再接一层，记录每个 chunk 的到达时间
+            let mut recorder = TraceRecorder::new(Instant::now());
+            let (tapped_tx, tapped_rx) = mpsc::channel::<String>(1);
+            let (engine_rx, tapping) = match trace {
+                true => (tapped_rx, Some(tap(engine_rx, tapped_tx, &mut recorder))),
+                false => (engine_rx, None),
+            };
+            let tapping = async {
+                if let Some(tapping) = tapping {
+                    tapping.await;
+                }
+            };
+            let generation = async {
+                if state.time_slicer.covers(request.device) {
+                    let result = generate_time_sliced(
+                        &state,
+                        &request,
+                        &request.prompt,
+                        max_tokens,
+                        engine_tx,
+                    )
+                    .await;
+                    drop(permit);
+                    return result;
+                }
+                if request.priority == Priority::Low {
+                    return generate_preemptible(
+                        &state,
+                        &request.engine,
+                        &request.prompt,
+                        max_tokens,
+                        &sampling,
+                        permit,
+                        engine_tx,
+                    )
+                    .await;
+                }
+                let result = stream_on_task(
+                    &request.engine,
+                    &request.prompt,
+                    "",
+                    max_tokens,
+                    &sampling,
+                    engine_tx,
+                )
+                .await;
+                // 生成一结束就释放 slot，不用等慢客户端读完
+                drop(permit);
+                result
+            };
+            let relay = handle.relay(engine_rx, text_tx);
+            let forward = forward_chunks(text_rx, &tx, &config, &tally);
+            let run = async {
+                let (result, (), (), cutoff) =
+                    rocket::tokio::join!(generation, tapping, relay, forward);
+                (result, cutoff)
+            };
+            // 超时或被取消时丢掉整个 future：engine 停止生成，permit 随之释放
+            let run = cancel::scope(cancel.clone(), run);
+            let limited = async {
+                match config.max_duration() {
+                    Some(limit) => timeout(limit, run)
+                        .await
+                        .unwrap_or((Ok(()), Some(StreamCutoff::MaxDuration))),
+                    None => run.await,
+                }
+            };
+            let (result, cutoff) = select! {
+                biased;
+                _ = cancel.cancelled() => (Err(Cancelled.into()), None),
+                done = limited => done,
+            };
+
+            match result {
+                Ok(()) => {
+                    metrics.record_outcome(true);
+                    let generated = tally.generated.load(Ordering::Relaxed);
+                    state.perf.record(&model_name, started.elapsed(), generated);
+                    if let Some(client) = &rate_client {
+                        state.rate_limits.charge(client, prompt_tokens + generated);
+                    }
+                    record_request(
+                        &state,
+                        &model_name,
+                        RequestKind::Stream,
+                        started.elapsed(),
+                        prompt_bytes,
+                        &tags,
+                        Ok(generated),
+                    );
+                    if let Some(trace) = recorder.finish() {
+                        info!(served_by = %model_name, "token trace: {}", trace.summary());
+                    }
+                }
+                Err(e) => {
+                    // 被取消不算服务端的失败
+                    if !cancel.is_cancelled() {
+                        metrics.record_outcome(false);
+                        metrics.record_stream_error();
+                    }
+                    record_request(
+                        &state,
+                        &model_name,
+                        RequestKind::Stream,
+                        started.elapsed(),
+                        prompt_bytes,
+                        &tags,
+                        Err(format!("{e:#}")),
+                    );
+                    tally.failed.store(true, Ordering::Relaxed);
+                    let _ = tx.send(StreamChunk::Error(format!("{e:#}"))).await;
+                    return;
+                }
+            }
+            if let Some(cutoff) = cutoff {
+                if cutoff == StreamCutoff::Overflow {
+                    tally.overflowed.store(true, Ordering::Relaxed);
+                }
+                // 不读的客户端也收不到这一条，最多再等一个 idle 周期
+                let message = StreamChunk::Error(cutoff.message(&config));
+                let _ = within_idle(&config, tx.send(message)).await;
+                return;
+            }
+            let text = tally.text.lock().clone();
+            let usage = token_usage(request.engine.as_ref(), &request.prompt, None, &text, None);
+            if let Some(usage) = usage {
+                let _ = within_idle(&config, tx.send(StreamChunk::Usage(usage))).await;
+            }
+        };
+        rocket::tokio::spawn(task.in_current_span());
+
+        Ok(receiver)
+    }
+}
+
+/// 按引擎的分词统计 usage：prompt 按实际送进模型的样子（含模板）编码，token 级请求直接数 id。
+/// 引擎不能分词时为 None
+fn token_usage(
+    engine: &dyn InferenceEngine,
+    prompt: &str,
+    input_ids: Option<&[u32]>,
+    output: &str,
+    output_ids: Option<&[u32]>,
+) -> Option<TokenUsage> {
+    let prompt_tokens = match input_ids {
+        Some(ids) => ids.len(),
+        None => engine.encode_prompt(prompt).ok()?.len(),
+    };
+    let completion_tokens = match output_ids {
+        Some(ids) => ids.len(),
+        None => engine.count_tokens(output)?,
+    };
+    Some(TokenUsage::new(prompt_tokens, completion_tokens))
+}
+
+/// 一次推理的日志 span：HTTP 请求的 id（见 `logging`）、登记的 generation id 和请求的模型名
+fn request_span(req: &InferRequest, handle: &GenerationHandle) -> Span {
+    info_span!(
+        "infer",
+        request_id = req.request_id.as_deref(),
+        generation = handle.id(),
+        model = %req.model_name,
+        tags = (!req.tags.is_empty()).then(|| tags::describe(&req.tags)),
+    )
+}
+
+/// 在 `handle` 的取消信号下执行：被取消（`DELETE /requests/<id>`）时丢掉 `fut`，
+/// 排队中的等待、permit 和引擎的 future 一起释放；引擎的同步解码循环通过 `cancel::requested` 提前返回
+async fn cancellable<T>(
+    handle: &GenerationHandle,
+    fut: impl Future<Output = Result<T, PipelineError>>,
+) -> Result<T, PipelineError> {
+    let token = handle.cancel_token().clone();
+    let result = cancel::scope(token.clone(), async {
+        select! {
+            biased;
+            _ = token.cancelled() => Err(PipelineError::Cancelled),
+            result = fut => result,
+        }
+    })
+    .await;
+    match result {
+        Err(_) if token.is_cancelled() => Err(PipelineError::Cancelled),
+        result => result,
+    }
+}
+
+/// 请求结束：写进请求日志，同时更新 Prometheus 的请求数、延迟和 token 计数
+fn record_request(
+    state: &AppState,
+    model: &str,
+    kind: RequestKind,
+    latency: Duration,
+    prompt_bytes: usize,
+    tags: &RequestTags,
+    result: Result<usize, String>,
+) {
+    let tokens = result.as_ref().ok().copied();
+    let latency_ms = latency.as_millis() as u64;
+    match &result {
+        Ok(tokens) => info!(
+            served_by = model,
+            kind = kind.as_str(),
+            tokens,
+            latency_ms,
+            prompt_bytes,
+            "request finished"
+        ),
+        Err(e) => warn!(
+            served_by = model,
+            kind = kind.as_str(),
+            latency_ms,
+            prompt_bytes,
+            error = %e,
+            "request failed"
+        ),
+    }
+    state.metrics.record_request(model, kind, latency, tokens);
+    state.tag_metrics.record(tags, tokens);
+    state
+        .request_log
+        .record(model, kind, latency, prompt_bytes, tags, result);
+}
+
+/// 在单独的 task 里跑引擎的流式生成：candle / tiny 在解码线程上边算边推送，和读 chunk 的 future
+/// 在同一个 task 里时，读的一方要等整段解码完才轮得到。取消和暂停信号一起带过去，
+/// 返回的 future 被丢掉时 abort 这个 task（解码线程在下一次推送时发现接收方已经断开）。
+/// `partial` 非空时接着模型之前的输出续写（`continue_stream`）
+async fn stream_on_task(
+    engine: &Arc<dyn InferenceEngine>,
+    prompt: &str,
+    partial: &str,
+    max_tokens: usize,
+    sampling: &SamplingParams,
+    tx: mpsc::Sender<String>,
+) -> anyhow::Result<()> {
+    struct AbortOnDrop(JoinHandle<anyhow::Result<()>>);
+    impl Drop for AbortOnDrop {
+        fn drop(&mut self) {
+            self.0.abort();
+        }
+    }
+
+    let (engine, prompt, partial) = (engine.clone(), prompt.to_string(), partial.to_string());
+    let sampling = *sampling;
+    let generation = async move {
+        match partial.is_empty() {
+            true => {
+                engine
+                    .generate_stream_sampled(&prompt, max_tokens, &sampling, tx)
+                    .await
+            }
+            false => {
+                engine
+                    .continue_stream(&prompt, &partial, max_tokens, &sampling, tx)
+                    .await
+            }
+        }
+    };
+    let mut task = AbortOnDrop(match cancel::current() {
+        Some(token) => rocket::tokio::spawn(cancel::scope(token, generation)),
+        None => rocket::tokio::spawn(generation),
+    });
+    match (&mut task.0).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(anyhow::anyhow!("generation task failed: {e}")),
+    }
+}
+
+/// low 优先级的文本生成：按流式执行，文本推给 `out`，最多 `max_tokens` 个 chunk。
+/// 被 high 请求抢占时停止生成、把 permit 交给对方，在 `AdmissionQueue` 里重新排队拿到 permit 后
+/// 用 `continue_stream` 接着已生成的文本续写（排不上时返回 `QueueRefusal`）。结束时释放 permit
+async fn generate_preemptible(
+    state: &AppState,
+    engine: &Arc<dyn InferenceEngine>,
+    prompt: &str,
+    max_tokens: usize,
+    sampling: &SamplingParams,
+    mut permit: OwnedSemaphorePermit,
+    out: mpsc::Sender<String>,
+) -> anyhow::Result<()> {
+    let mut generated: Vec<String> = Vec::new();
+    while generated.len() < max_tokens {
+        let mut ticket = state.preemption.register();
+        // 模型看到的是自己的输出，不是新的用户输入；chunk 之间按一个空格拼回去
+        let partial = generated.join(" ");
+        let remaining = max_tokens - generated.len();
+        let handoff = {
+            let (tx, mut rx) = mpsc::channel::<String>(STREAM_CHANNEL_CAPACITY);
+            let generation = stream_on_task(engine, prompt, &partial, remaining, sampling, tx);
+            let forward = async {
+                while let Some(text) = rx.recv().await {
+                    generated.push(text.clone());
+                    // 客户端断开，或者已经够了（引擎不一定遵守 max_tokens）
+                    if out.send(text).await.is_err() || generated.len() >= max_tokens {
+                        break;
+                    }
+                }
+                drop(rx);
+            };
+            select! {
+                (result, ()) = async { rocket::tokio::join!(generation, forward) } => {
+                    return result;
+                }
+                Ok(handoff) = &mut ticket.preempted => handoff,
+            }
+        };
+        // 生成的 future 已经丢弃，引擎停止；permit 直接交给抢占方
+        state.metrics.record_preemption();
+        info!(
+            chunks = generated.len(),
+            "low-priority generation preempted"
+        );
+        let _ = handoff.send(permit);
+        let ticket = state.queue.enter(&state.semaphore)?;
+        permit = ticket
+            .wait(state.metrics.acquire(state.semaphore.clone()))
+            .await??;
+    }
+    drop(permit);
+    Ok(())
+}
+
+/// 分时设备上的文本生成（见 `time_slice`）：每轮先排队拿设备，一轮到时间且有别的请求在等时
+/// 停止生成、交出设备，重新排队后用 `continue_stream` 接着已生成的文本续写。文本推给 `out`，最多 `max_tokens` 个 chunk
+async fn generate_time_sliced(
+    state: &AppState,
+    request: &ValidatedRequest,
+    prompt: &str,
+    max_tokens: usize,
+    out: mpsc::Sender<String>,
+) -> anyhow::Result<()> {
+    let slicer = &state.time_slicer;
+    let mut generated: Vec<String> = Vec::new();
+    while generated.len() < max_tokens {
+        let turn = slicer.acquire(request.device, &request.model_name).await;
+        // 和被抢占的生成一样续写自己的输出
+        let partial = generated.join(" ");
+        let remaining = max_tokens - generated.len();
+        let (tx, mut rx) = mpsc::channel::<String>(STREAM_CHANNEL_CAPACITY);
+        let generation = stream_on_task(
+            &request.engine,
+            prompt,
+            &partial,
+            remaining,
+            &request.sampling,
+            tx,
+        );
+        let forward = async {
+            while let Some(text) = rx.recv().await {
+                generated.push(text.clone());
+                if out.send(text).await.is_err() || generated.len() >= max_tokens {
+                    break;
+                }
+            }
+            drop(rx);
+        };
+        select! {
+            (result, ()) = async { rocket::tokio::join!(generation, forward) } => {
+                return result;
+            }
+            () = turn.yield_requested() => {}
+        }
+        // 生成的 future 已经丢弃，drop turn 时设备交给下一个等待者
+        info!(
+            device = %request.device,
+            chunks = generated.len(),
+            "time slice ended, yielding the device"
+        );
+    }
+    Ok(())
+}
+
+/// `generate_preemptible` 收集到的 chunk 拼回非流式结果
+fn preempted_generation(words: Vec<String>, max_tokens: usize) -> Generation {
+    let finish_reason = if words.len() >= max_tokens {
+        FinishReason::Length
+    } else {
+        FinishReason::Stop
+    };
+    Generation {
+        text: words.join(" "),
+        finish_reason,
+    }
+}
+
+/// `fut` 在 `idle_timeout` 内完成时返回它的结果，否则返回 None
+fn budget_error(refusal: BudgetRefusal) -> PipelineError {
+    let mb = |bytes: u64| bytes.div_ceil(1 << 20);
+    match refusal {
+        BudgetRefusal::TooLarge { required, limit } => PipelineError::ContextTooLarge {
+            required_mb: mb(required),
+            limit_mb: mb(limit),
+        },
+        BudgetRefusal::Busy {
+            required,
+            in_use,
+            limit,
+        } => PipelineError::MemoryBusy {
+            required_mb: mb(required),
+            in_use_mb: mb(in_use),
+            limit_mb: mb(limit),
+        },
+    }
+}
+
+fn queue_error(refusal: QueueRefusal) -> PipelineError {
+    match refusal {
+        QueueRefusal::Full { position, max_len } => PipelineError::QueueFull { position, max_len },
+        QueueRefusal::TimedOut {
+            position,
+            waited_ms,
+        } => PipelineError::QueueTimeout {
+            position,
+            waited_ms,
+        },
+    }
+}
+
+async fn within_idle<F: Future>(config: &StreamConfig, fut: F) -> Option<F::Output> {
+    match config.idle_timeout() {
+        Some(limit) => timeout(limit, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// 把 engine 的文本转发给客户端 channel，按 `config.overflow` 处理客户端读得慢的情况。
+/// 提前停止时返回原因；返回时丢掉 `text_rx`，engine 的下一次发送会失败并停下
+async fn forward_chunks(
+    mut text_rx: mpsc::Receiver<String>,
+    tx: &mpsc::Sender<StreamChunk>,
+    config: &StreamConfig,
+    tally: &StreamTally,
+) -> Option<StreamCutoff> {
+    match config.overflow {
+        OverflowPolicy::Block => {
+            while let Some(text) = text_rx.recv().await {
+                tally.record(&text);
+                match within_idle(config, tx.send(StreamChunk::Text(text))).await {
+                    Some(Ok(())) => {}
+                    Some(Err(_)) => break, // 客户端已断开
+                    None => return Some(StreamCutoff::Idle),
+                }
+            }
+            None
+        }
+        OverflowPolicy::Abort => {
+            while let Some(text) = text_rx.recv().await {
+                tally.record(&text);
+                match tx.try_send(StreamChunk::Text(text)) {
+                    Ok(()) => {}
+                    Err(mpsc::error::TrySendError::Full(_)) => return Some(StreamCutoff::Overflow),
+                    Err(mpsc::error::TrySendError::Closed(_)) => break,
+                }
+            }
+            None
+        }
+        OverflowPolicy::DropOldest => {
+            // channel 满了以后先攒在这里，超过容量就丢最早的
+            let mut pending = VecDeque::new();
+            let mut dropped = 0;
+            let mut finished = false;
+            // 从上次客户端取走 chunk 开始计算 idle
+            let mut last_read = Instant::now();
+            let idle = config.idle_timeout();
+            while !(finished && pending.is_empty() && dropped == 0) {
+                let waiting = dropped > 0 || !pending.is_empty();
+                let idle_deadline = idle.map_or(last_read, |idle| last_read + idle);
+                select! {
+                    biased;
+                    slot = tx.reserve(), if waiting => {
+                        let Ok(slot) = slot else { break };
+                        last_read = Instant::now();
+                        if dropped > 0 {
+                            slot.send(StreamChunk::Gap { dropped });
+                            dropped = 0;
+                        } else if let Some(text) = pending.pop_front() {
+                            slot.send(StreamChunk::Text(text));
+                        }
+                    }
+                    text = text_rx.recv(), if !finished => match text {
+                        Some(text) => {
+                            tally.record(&text);
+                            if !waiting {
+                                last_read = Instant::now();
+                            }
+                            if pending.len() >= config.capacity.max(1) {
+                                pending.pop_front();
+                                dropped += 1;
+                                tally.overflowed.store(true, Ordering::Relaxed);
+                            }
+                            pending.push_back(text);
+                        }
+                        None => finished = true,
+                    },
+                    _ = sleep_until(idle_deadline), if waiting && idle.is_some() => {
+                        return Some(StreamCutoff::Idle);
+                    }
+                }
+            }
+            None
+        }
+    }
+}
+
+/// token 级生成：输入是 id 时跳过 encode，要求返回 id 时跳过 decode
+async fn complete_token_level(
+    engine: &dyn InferenceEngine,
+    req: &InferRequest,
+    max_tokens: usize,
+) -> anyhow::Result<(String, FinishReason, Option<Vec<u32>>)> {
+    let input_ids = match &req.input_ids {
+        Some(ids) => ids.clone(),
+        None => engine.encode_prompt(&req.prompt)?,
+    };
+    let generated = engine.complete_ids(&input_ids, max_tokens).await?;
+    if req.return_token_ids {
+        Ok((String::new(), generated.finish_reason, Some(generated.ids)))
+    } else {
+        let text = engine.decode_ids(&generated.ids)?;
+        Ok((text, generated.finish_reason, None))
+    }
+}
+
+/// 给生成过程加上模型配置的超时
+async fn with_timeout<T>(
+    model_name: &str,
+    timeout: Option<Duration>,
+    fut: impl std::future::Future<Output = T>,
+) -> Result<T, PipelineError> {
+    match timeout {
+        Some(timeout) => rocket::tokio::time::timeout(timeout, fut)
+            .await
+            .map_err(|_| PipelineError::Timeout {
+                model: model_name.to_string(),
+                after_ms: timeout.as_millis() as u64,
+            }),
+        None => Ok(fut.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::SamplingParams;
+    use crate::model_registry::{EngineKind, ModelMetadata};
+    use crate::router::{RouteCondition, RoutingRule, VirtualRouter};
+    use crate::testing::fake_registry;
+
+    fn pipeline() -> InferencePipeline {
+        let state = AppState::with_registry(fake_registry(), 1);
+        state.load_model("dummy-a").unwrap();
+        InferencePipeline::new(state)
+    }
+
+    fn request(model_name: &str, prompt: &str) -> InferRequest {
+        InferRequest {
+            model_name: model_name.to_string(),
+            prompt: prompt.to_string(),
+            device: None,
+            mode: InferMode::Interactive,
+            input_ids: None,
+            return_token_ids: false,
+            max_tokens: None,
+            sampling: SamplingParams::default(),
+            priority: Priority::default(),
+            trace: false,
+            auto_template: false,
+            private: false,
+            client: None,
+            validate: None,
+            tags: Default::default(),
+            request_id: None,
+            rate_client: None,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_unknown_and_unloaded_models() {
+        let pipeline = pipeline();
+        assert!(matches!(
+            pipeline.validate(&request("missing", "hi")),
+            Err(PipelineError::ModelNotFound(_))
+        ));
+        assert!(matches!(
+            pipeline.validate(&request("dummy-b", "hi")),
+            Err(PipelineError::NotLoaded { .. })
+        ));
+    }
+
+    #[test]
+    fn validate_resolves_virtual_routers() {
+        let pipeline = pipeline();
+        let rules = vec![RoutingRule {
+            when: RouteCondition::Language("zh".to_string()),
+            model: "dummy-b".to_string(),
+        }];
+        let router = VirtualRouter::new("by-lang", rules, "dummy-a").unwrap();
+        pipeline.state.registry.register_router(router).unwrap();
+
+        let routed = pipeline.validate(&request("by-lang", "hello")).unwrap();
+        assert_eq!(routed.model_name, "dummy-a");
+        assert!(matches!(
+            pipeline.validate(&request("by-lang", "你好")),
+            Err(PipelineError::NotLoaded { model, .. }) if model == "dummy-b"
+        ));
+    }
+
+    #[test]
+    fn validate_checks_device_against_placements() {
+        let pipeline = pipeline();
+        let mut req = request("dummy-a", "hi");
+        req.device = Some(DeviceSpec::Cpu);
+        assert_eq!(pipeline.validate(&req).unwrap().device, DeviceSpec::Cpu);
+
+        req.device = Some(DeviceSpec::Cuda(0));
+        assert!(matches!(
+            pipeline.validate(&req),
+            Err(PipelineError::DeviceUnavailable { .. })
+        ));
+    }
+
+    #[test]
+    fn validate_fills_sampling_from_model_defaults() {
+        let registry = fake_registry();
+        let defaults = SamplingParams {
+            temperature: Some(0.7),
+            top_p: Some(0.9),
+            ..SamplingParams::default()
+        };
+        registry.register(
+            ModelMetadata::new("dummy-a", "", "none", EngineKind::DUMMY).with_sampling(defaults),
+        );
+        let state = AppState::with_registry(registry, 1);
+        state.load_model("dummy-a").unwrap();
+        let pipeline = InferencePipeline::new(state);
+
+        let mut req = request("dummy-a", "hi");
+        req.sampling.temperature = Some(0.0);
+        let sampling = pipeline.validate(&req).unwrap().sampling;
+        assert_eq!(sampling.temperature, Some(0.0));
+        assert_eq!(sampling.top_p, Some(0.9));
+    }
+
+    fn fallback_pipeline(primary: ModelMetadata) -> InferencePipeline {
+        let registry = fake_registry();
+        registry.register(primary.with_fallbacks(vec!["dummy-b".to_string()]));
+        let state = AppState::with_registry(registry, 2);
+        state.load_model("dummy-b").unwrap();
+        InferencePipeline::new(state)
+    }
+
+    #[rocket::async_test]
+    async fn falls_back_when_primary_is_not_loaded() {
+        let meta = ModelMetadata::new("dummy-a", "", "none", EngineKind::DUMMY);
+        let pipeline = fallback_pipeline(meta);
+        let done = pipeline.collect(&request("dummy-a", "hi")).await.unwrap();
+        assert_eq!(done.served_by, "dummy-b");
+        assert_eq!(done.output, "[dummy-b DUMMY] HI");
+
+        let mut rx = pipeline.stream(&request("dummy-a", "hi")).await.unwrap();
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            StreamChunk::Text("[model=dummy-b]".to_string())
+        );
+    }
+
+    #[rocket::async_test]
+    async fn falls_back_on_timeout() {
+        // Dummy 每次生成要 50ms
+        let meta = ModelMetadata::new("dummy-a", "", "none", EngineKind::DUMMY)
+            .with_timeout(Duration::from_millis(1));
+        let pipeline = fallback_pipeline(meta);
+        pipeline.state.load_model("dummy-a").unwrap();
+
+        let done = pipeline.collect(&request("dummy-a", "hi")).await.unwrap();
+        assert_eq!(done.served_by, "dummy-b");
+
+        // 没有可用的 fallback 时返回主模型的错误
+        pipeline
+            .state
+            .registry
+            .set_status("dummy-b", ModelStatus::Unloaded)
+            .unwrap();
+        assert!(matches!(
+            pipeline.collect(&request("dummy-a", "hi")).await,
+            Err(PipelineError::Timeout { model, .. }) if model == "dummy-a"
+        ));
+    }
+
+    #[rocket::async_test]
+    async fn auto_picks_the_least_busy_loaded_model() {
+        let state = AppState::with_registry(fake_registry(), 2);
+        let pipeline = InferencePipeline::new(state.clone());
+        assert!(matches!(
+            pipeline.validate(&request(AUTO_MODEL, "hi")),
+            Err(PipelineError::NoAutoCandidate { .. })
+        ));
+
+        state.load_model("dummy-a").unwrap();
+        state.load_model("dummy-b").unwrap();
+        let busy = pipeline.validate(&request(AUTO_MODEL, "hi")).unwrap();
+        assert_eq!(busy.model_name, "dummy-a");
+        // dummy-a 上还有一个没结束的请求
+        assert_eq!(state.queue_depth("dummy-a"), 1);
+        let done = pipeline.collect(&request(AUTO_MODEL, "hi")).await.unwrap();
+        assert_eq!(done.served_by, "dummy-b");
+
+        drop(busy);
+        assert_eq!(state.queue_depth("dummy-a"), 0);
+    }
+
+    #[rocket::async_test]
+    async fn throughput_mode_batches_and_streams_one_chunk() {
+        let pipeline = pipeline();
+        let mut req = request("dummy-a", "one two");
+        req.mode = InferMode::Throughput;
+
+        let (a, b) = rocket::tokio::join!(pipeline.collect(&req), pipeline.collect(&req));
+        assert_eq!(a.unwrap().output, "[dummy-a DUMMY] ONE TWO");
+        assert_eq!(b.unwrap().output, "[dummy-a DUMMY] ONE TWO");
+
+        let mut rx = pipeline.stream(&req).await.unwrap();
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            StreamChunk::Text("[dummy-a DUMMY] ONE TWO".to_string())
+        );
+        assert_eq!(
+            rx.recv().await.unwrap(),
+            StreamChunk::Usage(TokenUsage::new(7, 23))
+        );
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[rocket::async_test]
+    async fn disconnect_counts_undelivered_tokens() {
+        let pipeline = pipeline();
+        let metrics = pipeline.state.metrics.clone();
+        let mut rx = pipeline
+            .stream(&request("dummy-a", "one two three four"))
+            .await
+            .unwrap();
+        assert!(matches!(rx.recv().await, Some(StreamChunk::Text(_))));
+        // Dummy 每 50ms 推一个词，客户端读了一个就断开
+        rocket::tokio::time::sleep(Duration::from_millis(120)).await;
+        drop(rx);
+
+        for _ in 0..50 {
+            if metrics.stream_tokens_undelivered(UndeliveredReason::Disconnect) > 0 {
+                break;
+            }
+            rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(metrics.stream_tokens_undelivered(UndeliveredReason::Disconnect) >= 2);
+        assert_eq!(
+            metrics.stream_tokens_undelivered(UndeliveredReason::Error),
+            0
+        );
+        assert!(metrics
+            .render()
+            .contains("llm_stream_tokens_undelivered_total{reason=\"disconnect\"}"));
+    }
+
+    /// 把 `texts` 全部放进一个已关闭的 engine 侧 channel
+    fn engine_output(texts: &[&str]) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::channel(texts.len());
+        for text in texts {
+            tx.try_send(text.to_string()).unwrap();
+        }
+        rx
+    }
+
+    #[rocket::async_test]
+    async fn drop_oldest_marks_the_gap() {
+        let config = StreamConfig {
+            capacity: 2,
+            overflow: OverflowPolicy::DropOldest,
+            ..StreamConfig::default()
+        };
+        let (tx, mut rx) = mpsc::channel(config.capacity);
+        let texts = engine_output(&["0", "1", "2", "3", "4", "5"]);
+        let tally = StreamTally::new("test", Arc::new(Metrics::new()));
+        let forward =
+            rocket::tokio::spawn(async move { forward_chunks(texts, &tx, &config, &tally).await });
+        // 客户端先不读：channel 里是 0、1，等待区里只留最新的 4、5
+        rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let mut received = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            received.push(chunk);
+        }
+        let text = |t: &str| StreamChunk::Text(t.to_string());
+        assert_eq!(
+            received,
+            [
+                text("0"),
+                text("1"),
+                StreamChunk::Gap { dropped: 2 },
+                text("4"),
+                text("5")
+            ]
+        );
+        assert_eq!(forward.await.unwrap(), None);
+    }
+
+    #[rocket::async_test]
+    async fn abort_stops_when_the_channel_is_full() {
+        let config = StreamConfig {
+            capacity: 1,
+            overflow: OverflowPolicy::Abort,
+            ..StreamConfig::default()
+        };
+        let (tx, mut rx) = mpsc::channel(config.capacity);
+        let texts = engine_output(&["0", "1", "2"]);
+        let tally = StreamTally::new("test", Arc::new(Metrics::new()));
+        assert_eq!(
+            forward_chunks(texts, &tx, &config, &tally).await,
+            Some(StreamCutoff::Overflow)
+        );
+        assert_eq!(rx.recv().await.unwrap(), StreamChunk::Text("0".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn block_gives_up_on_an_idle_client() {
+        let config = StreamConfig {
+            capacity: 1,
+            idle_timeout_ms: 20,
+            ..StreamConfig::default()
+        };
+        let (tx, mut rx) = mpsc::channel(config.capacity);
+        let texts = engine_output(&["0", "1", "2"]);
+        let tally = StreamTally::new("test", Arc::new(Metrics::new()));
+        assert_eq!(
+            forward_chunks(texts, &tx, &config, &tally).await,
+            Some(StreamCutoff::Idle)
+        );
+        assert_eq!(rx.recv().await.unwrap(), StreamChunk::Text("0".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn max_duration_stops_generation_and_frees_the_permit() {
+        let state = AppState::builder()
+            .registry(fake_registry())
+            .max_concurrent_infer(1)
+            .streaming(StreamConfig {
+                max_duration_ms: 120,
+                ..StreamConfig::default()
+            })
+            .build();
+        state.load_model("dummy-a").unwrap();
+        let pipeline = InferencePipeline::new(state.clone());
+
+        // Dummy 每 50ms 推一个词，一共十几个词
+        let mut rx = pipeline
+            .stream(&request("dummy-a", "a b c d e f g h i j"))
+            .await
+            .unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.push(chunk);
+        }
+        let Some(StreamChunk::Error(message)) = chunks.last() else {
+            panic!("stream did not end with an error: {chunks:?}");
+        };
+        assert!(message.contains("maximum duration"));
+        assert!(chunks.len() < 8);
+        assert_eq!(state.semaphore.available_permits(), 1);
+    }
+
+    #[rocket::async_test]
+    async fn collect_and_stream_agree() {
+        let pipeline = pipeline();
+        let req = request("dummy-a", "one two");
+        let full = pipeline.collect(&req).await.unwrap().output;
+
+        let mut rx = pipeline.stream(&req).await.unwrap();
+        let mut chunks = Vec::new();
+        while let Some(StreamChunk::Text(chunk)) = rx.recv().await {
+            chunks.push(chunk);
+        }
+        // Dummy 的流式输出比完整输出多一个 `[model=..]` 前缀 chunk
+        assert_eq!(chunks[1..].join(" "), full);
+    }
+}
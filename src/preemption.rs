@@ -0,0 +1,124 @@
+//! 优先级抢占：`priority: "high"` 的请求拿不到并发 permit 时，让正在运行时间最长的一个
+//! `priority: "low"` 生成停下来，把它的 permit 直接交给这个请求（不用排在其他等待者后面）。
+//!
+//! 被抢占的生成不会丢掉已经输出的部分：它重新排队，拿到 permit 后以「原 prompt + 已生成的文本」
+//! 继续生成剩下的 token（见 `pipeline::generate_preemptible`）。token 级请求和 throughput 攒批
+//! 没法从中间接着生成，不参与抢占。
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use rocket::tokio::sync::{oneshot, OwnedSemaphorePermit};
+use rocket::tokio::time::Instant;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// 请求优先级
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    /// 后台批量任务：可以被 high 请求抢占
+    Low,
+    #[default]
+    Normal,
+    /// 交互请求：没有空闲 permit 时抢占一个 low 生成
+    High,
+}
+
+/// 被抢占的生成通过它交出 permit
+pub type PermitHandoff = oneshot::Sender<OwnedSemaphorePermit>;
+
+struct Running {
+    id: u64,
+    started: Instant,
+    preempt: oneshot::Sender<PermitHandoff>,
+}
+
+/// 正在运行、可以被抢占的 low 生成
+#[derive(Default)]
+pub struct PreemptionRegistry {
+    running: Arc<Mutex<Vec<Running>>>,
+    next_id: AtomicU64,
+}
+
+/// 一次 low 生成的登记，drop 时注销；`preempted` 收到的 sender 用来交出 permit
+pub struct PreemptionTicket {
+    id: u64,
+    running: Arc<Mutex<Vec<Running>>>,
+    pub preempted: oneshot::Receiver<PermitHandoff>,
+}
+
+impl Drop for PreemptionTicket {
+    fn drop(&mut self) {
+        self.running.lock().retain(|r| r.id != self.id);
+    }
+}
+
+impl PreemptionRegistry {
+    pub fn register(&self) -> PreemptionTicket {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.running.lock().push(Running {
+            id,
+            started: Instant::now(),
+            preempt: tx,
+        });
+        PreemptionTicket {
+            id,
+            running: self.running.clone(),
+            preempted: rx,
+        }
+    }
+
+    /// 正在运行的 low 生成数
+    pub fn running(&self) -> usize {
+        self.running.lock().len()
+    }
+
+    /// 让运行最久的 low 生成停下，返回接收它 permit 的一端；没有可抢占的生成时返回 None
+    pub fn preempt(&self) -> Option<oneshot::Receiver<OwnedSemaphorePermit>> {
+        let mut running = self.running.lock();
+        while !running.is_empty() {
+            let oldest = running
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, r)| r.started)
+                .map(|(i, _)| i)?;
+            let victim = running.swap_remove(oldest);
+            let (tx, rx) = oneshot::channel();
+            // 对方刚好结束时发送失败，换下一个
+            if victim.preempt.send(tx).is_ok() {
+                return Some(rx);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio::sync::Semaphore;
+
+    #[rocket::async_test]
+    async fn preempts_the_oldest_generation_and_hands_over_its_permit() {
+        let registry = PreemptionRegistry::default();
+        let semaphore = Arc::new(Semaphore::new(1));
+        let mut oldest = registry.register();
+        let mut newer = registry.register();
+        assert_eq!(registry.running(), 2);
+
+        let handoff = registry.preempt().unwrap();
+        let give = oldest.preempted.try_recv().unwrap();
+        assert!(newer.preempted.try_recv().is_err());
+        give.send(semaphore.clone().try_acquire_owned().unwrap())
+            .unwrap();
+        assert!(handoff.await.is_ok());
+
+        // 被抢占的已经移出登记表，结束的生成 drop 时自己注销
+        drop(newer);
+        assert_eq!(registry.running(), 0);
+        assert!(registry.preempt().is_none());
+    }
+}
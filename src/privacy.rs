@@ -0,0 +1,48 @@
+//! 隐私模式：prompt 和输出不以原文出现在日志里，只记录 sha256 前缀和长度
+//!
+//! 整个部署（`ServerConfig.privacy`）或单个 API key（`ApiKeyProfile.privacy`）开启，
+//! 由 `ApiKeyProfile::apply` 标在 `InferRequest.private` 上，pipeline 记日志时据此脱敏。
+//! 服务本身不把 prompt 写盘；session 历史只在内存里，关闭 session 即删除。
+
+use sha2::{Digest, Sha256};
+
+/// 非隐私模式下日志里预览的字符数
+const PREVIEW_CHARS: usize = 48;
+
+/// 日志里描述一段文本：隐私模式只给哈希和长度，否则给开头一段预览
+pub fn describe(text: &str, private: bool) -> String {
+    if private {
+        return redact(text);
+    }
+    let mut preview: String = text.chars().take(PREVIEW_CHARS).collect();
+    if preview.len() < text.len() {
+        preview.push('…');
+    }
+    format!("{preview:?} ({} bytes)", text.len())
+}
+
+/// `sha256:<前 12 位> (<字节数> bytes)`，同样的内容得到同样的结果，方便对照
+pub fn redact(text: &str) -> String {
+    let digest = Sha256::digest(text.as_bytes());
+    let hex: String = digest[..6].iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256:{hex} ({} bytes)", text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_text_only_shows_hash_and_length() {
+        let secret = "quarterly numbers: revenue 42M";
+        let line = describe(secret, true);
+        assert!(!line.contains("revenue"));
+        assert!(line.ends_with(&format!("({} bytes)", secret.len())));
+        assert_eq!(line, redact(secret));
+        assert_ne!(redact(secret), redact("something else"));
+
+        assert!(describe(secret, false).starts_with("\"quarterly numbers"));
+        let long = "x".repeat(100);
+        assert!(describe(&long, false).contains("…\" (100 bytes)"));
+    }
+}
@@ -0,0 +1,258 @@
+//! chat session 超出上下文窗口时的 prompt 压缩
+//!
+//! - `truncate`: 直接丢掉最早的轮次（原来的硬截断）
+//! - `prune`: LLMLingua 风格，先删掉较早轮次里的低信息量 token（虚词、重复空白等）
+//! - `summarize`: 把较早的轮次合并成一条摘要（每轮保留第一句）
+//!
+//! 最近的 `keep_recent` 轮始终原样保留；`prune` / `summarize` 之后仍然放不下时
+//! 退回到 `truncate`。token 数用空白分词估算，与具体 tokenizer 无关。
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::session::{ChatRole, ChatTurn};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionStrategy {
+    #[default]
+    Truncate,
+    Prune,
+    Summarize,
+}
+
+/// 每次压缩的结果说明，随回复一起返回
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CompressionReport {
+    pub strategy: CompressionStrategy,
+    pub tokens_before: usize,
+    pub tokens_after: usize,
+    /// 最后仍然被整轮丢弃的轮数
+    pub dropped_turns: usize,
+}
+
+/// 摘要里每轮最多保留的词数
+const SUMMARY_WORDS_PER_TURN: usize = 24;
+
+/// 被 `prune` 删除的低信息量词
+const FILLER_WORDS: &[&str] = &[
+    "a",
+    "an",
+    "the",
+    "and",
+    "or",
+    "but",
+    "so",
+    "of",
+    "to",
+    "in",
+    "on",
+    "at",
+    "for",
+    "with",
+    "by",
+    "is",
+    "are",
+    "was",
+    "were",
+    "be",
+    "been",
+    "it",
+    "this",
+    "that",
+    "these",
+    "those",
+    "just",
+    "really",
+    "very",
+    "quite",
+    "actually",
+    "basically",
+    "please",
+    "um",
+    "uh",
+    "like",
+    "well",
+];
+
+pub fn estimate_tokens(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+fn total_tokens(turns: &[ChatTurn]) -> usize {
+    turns.iter().map(|t| estimate_tokens(&t.content)).sum()
+}
+
+/// 把 `turns` 压到 `budget` 以内；不需要压缩时 report 为 None
+pub fn fit(
+    turns: &[ChatTurn],
+    budget: usize,
+    strategy: CompressionStrategy,
+    keep_recent: usize,
+) -> (Vec<ChatTurn>, Option<CompressionReport>) {
+    let tokens_before = total_tokens(turns);
+    if tokens_before <= budget {
+        return (turns.to_vec(), None);
+    }
+
+    let split = turns.len().saturating_sub(keep_recent.max(1));
+    let (older, recent) = turns.split_at(split);
+    let mut out = match strategy {
+        CompressionStrategy::Truncate => turns.to_vec(),
+        CompressionStrategy::Prune => prune(older, recent, budget),
+        CompressionStrategy::Summarize => summarize(older, recent),
+    };
+    let dropped_turns = truncate(&mut out, budget);
+
+    let report = CompressionReport {
+        strategy,
+        tokens_before,
+        tokens_after: total_tokens(&out),
+        dropped_turns,
+    };
+    (out, Some(report))
+}
+
+/// 从最早的一轮开始逐轮去掉虚词，够了就停
+fn prune(older: &[ChatTurn], recent: &[ChatTurn], budget: usize) -> Vec<ChatTurn> {
+    let mut out: Vec<ChatTurn> = older.iter().chain(recent).cloned().collect();
+    for i in 0..older.len() {
+        if total_tokens(&out) <= budget {
+            break;
+        }
+        let pruned: Vec<&str> = out[i]
+            .content
+            .split_whitespace()
+            .filter(|w| {
+                let bare = w.trim_matches(|c: char| !c.is_alphanumeric());
+                !bare.is_empty() && !FILLER_WORDS.contains(&bare.to_lowercase().as_str())
+            })
+            .collect();
+        out[i].content = pruned.join(" ");
+    }
+    out.retain(|t| !t.content.is_empty());
+    out
+}
+
+/// 较早的轮次合并成一条 system 摘要
+fn summarize(older: &[ChatTurn], recent: &[ChatTurn]) -> Vec<ChatTurn> {
+    if older.is_empty() {
+        return recent.to_vec();
+    }
+    let summary = ChatTurn {
+        role: ChatRole::System,
+        content: format!(
+            "Summary of earlier conversation: {}",
+            summary_lines(older).join(" | ")
+        ),
+    };
+    std::iter::once(summary)
+        .chain(recent.iter().cloned())
+        .collect()
+}
+
+/// 每轮一行 `role: 第一句`，最多 `SUMMARY_WORDS_PER_TURN` 个词
+pub fn summary_lines(turns: &[ChatTurn]) -> Vec<String> {
+    turns
+        .iter()
+        .map(|t| {
+            let first = t
+                .content
+                .split_inclusive(['.', '!', '?', '。', '！', '？'])
+                .next()
+                .unwrap_or("");
+            let words: Vec<&str> = first
+                .split_whitespace()
+                .take(SUMMARY_WORDS_PER_TURN)
+                .collect();
+            format!("{}: {}", t.role, words.join(" "))
+        })
+        .collect()
+}
+
+/// 硬截断：丢最早的轮次；只剩一轮还超出时保留它的结尾部分。返回丢弃的轮数
+fn truncate(turns: &mut Vec<ChatTurn>, budget: usize) -> usize {
+    let mut dropped = 0;
+    while turns.len() > 1 && total_tokens(turns) > budget {
+        turns.remove(0);
+        dropped += 1;
+    }
+    if let Some(last) = turns.first_mut() {
+        let words: Vec<&str> = last.content.split_whitespace().collect();
+        if words.len() > budget {
+            last.content = words[words.len() - budget..].join(" ");
+        }
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn(role: ChatRole, content: &str) -> ChatTurn {
+        ChatTurn {
+            role,
+            content: content.to_string(),
+        }
+    }
+
+    fn history() -> Vec<ChatTurn> {
+        vec![
+            turn(
+                ChatRole::User,
+                "Tell me about the history of the Rust language. Keep it short.",
+            ),
+            turn(
+                ChatRole::Assistant,
+                "Rust was started by Graydon Hoare at Mozilla. It hit 1.0 in 2015.",
+            ),
+            turn(ChatRole::User, "And what is the borrow checker?"),
+        ]
+    }
+
+    #[test]
+    fn fits_without_compression() {
+        let (out, report) = fit(&history(), 100, CompressionStrategy::Summarize, 1);
+        assert_eq!(out, history());
+        assert!(report.is_none());
+    }
+
+    #[test]
+    fn truncate_drops_oldest_turns() {
+        let (out, report) = fit(&history(), 20, CompressionStrategy::Truncate, 1);
+        let report = report.unwrap();
+        assert_eq!(report.dropped_turns, 1);
+        assert_eq!(out.len(), 2);
+        assert!(report.tokens_after <= 20);
+    }
+
+    #[test]
+    fn prune_keeps_all_turns_when_filler_removal_is_enough() {
+        let (out, report) = fit(&history(), 24, CompressionStrategy::Prune, 1);
+        let report = report.unwrap();
+        assert_eq!(report.dropped_turns, 0);
+        assert_eq!(out.len(), 3);
+        assert!(!out[0].content.contains(" the "));
+        assert_eq!(out[2], history()[2]);
+    }
+
+    #[test]
+    fn summarize_collapses_older_turns() {
+        let (out, report) = fit(&history(), 30, CompressionStrategy::Summarize, 1);
+        assert_eq!(report.unwrap().dropped_turns, 0);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].role, ChatRole::System);
+        assert!(out[0]
+            .content
+            .contains("user: Tell me about the history of the Rust language."));
+        assert!(!out[0].content.contains("Keep it short"));
+    }
+
+    #[test]
+    fn single_oversized_turn_keeps_its_tail() {
+        let turns = vec![turn(ChatRole::User, "one two three four five")];
+        let (out, _) = fit(&turns, 2, CompressionStrategy::Prune, 1);
+        assert_eq!(out[0].content, "four five");
+    }
+}
@@ -0,0 +1,433 @@
+//! 提前拉取模型权重：`POST /models/<name>/pull` 在后台 job 里把 GGUF 和 `tokenizer.json`
+//! 下载进 hf-hub 缓存，`GET /models/<name>/pull/events`（SSE）推送已下载字节数、百分比和预计剩余时间。
+//!
+//! 之后的 `/load` 直接命中缓存，不再在第一次加载时闷头下载几个 GB。
+//! 本地权重（`path` 是本地文件）和非 candle 模型没有可拉取的文件；离线模式下不能拉取。
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Json;
+use rocket::tokio::select;
+use rocket::{Shutdown, State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::AppState;
+use crate::engine::candle_source;
+use crate::events::ModelEvent;
+use crate::hub_stream::{self, HubWeights};
+use crate::model_registry::{EngineKind, HubArtifacts, ModelSource};
+use crate::types::JobAcceptedResponse;
+
+/// 下载线程里采样进度的间隔
+const PULL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// SSE 推送进度的间隔
+const PULL_EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PullStatus {
+    Downloading,
+    Completed,
+    Failed,
+}
+
+/// SSE 里的一条进度
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PullProgress {
+    pub model: String,
+    pub job_id: String,
+    pub status: PullStatus,
+    /// 正在下载的文件
+    pub file: Option<String>,
+    /// 包括缓存里已有的文件
+    pub downloaded_bytes: u64,
+    /// 已开始的文件的总大小；有文件大小未知时为 None
+    pub total_bytes: Option<u64>,
+    pub percent: Option<f32>,
+    /// 只算本次实际下载的字节
+    pub bytes_per_sec: f64,
+    pub eta_secs: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// pull job 的结果
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PullResult {
+    pub model: String,
+    pub files: Vec<PulledFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PulledFile {
+    pub repo: String,
+    pub file: String,
+    pub path: String,
+    pub bytes: u64,
+    /// 开始前已在缓存里
+    pub cached: bool,
+}
+
+#[derive(Debug)]
+struct FileProgress {
+    name: String,
+    downloaded: u64,
+    total: Option<u64>,
+    cached: bool,
+}
+
+/// 一次 pull 的进度
+#[derive(Debug)]
+pub struct Pull {
+    model: String,
+    job_id: Mutex<String>,
+    started: Instant,
+    state: Mutex<PullState>,
+}
+
+#[derive(Debug)]
+struct PullState {
+    status: PullStatus,
+    files: Vec<FileProgress>,
+    error: Option<String>,
+}
+
+impl Pull {
+    fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            job_id: Mutex::new(String::new()),
+            started: Instant::now(),
+            state: Mutex::new(PullState {
+                status: PullStatus::Downloading,
+                files: Vec::new(),
+                error: None,
+            }),
+        }
+    }
+
+    fn begin_file(&self, name: &str) {
+        self.state.lock().files.push(FileProgress {
+            name: name.to_string(),
+            downloaded: 0,
+            total: None,
+            cached: false,
+        });
+    }
+
+    /// 更新当前文件的进度
+    fn advance(&self, downloaded: u64, total: Option<u64>, cached: bool) {
+        if let Some(file) = self.state.lock().files.last_mut() {
+            file.downloaded = downloaded;
+            file.total = total;
+            file.cached = cached;
+        }
+    }
+
+    fn finish(&self, result: Result<(), String>) {
+        let mut state = self.state.lock();
+        match result {
+            Ok(()) => state.status = PullStatus::Completed,
+            Err(e) => {
+                state.status = PullStatus::Failed;
+                state.error = Some(e);
+            }
+        }
+    }
+
+    pub fn status(&self) -> PullStatus {
+        self.state.lock().status
+    }
+
+    pub fn snapshot(&self) -> PullProgress {
+        let state = self.state.lock();
+        let downloaded_bytes = state.files.iter().map(|f| f.downloaded).sum();
+        let total_bytes = state.files.iter().map(|f| f.total).sum::<Option<u64>>();
+        let transferred: u64 = state
+            .files
+            .iter()
+            .filter(|f| !f.cached)
+            .map(|f| f.downloaded)
+            .sum();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let bytes_per_sec = match elapsed > 0.0 {
+            true => transferred as f64 / elapsed,
+            false => 0.0,
+        };
+        let remaining = total_bytes.map(|t| t.saturating_sub(downloaded_bytes));
+        let (percent, eta_secs) = match state.status {
+            PullStatus::Completed => (Some(100.0), Some(0)),
+            _ => (
+                total_bytes
+                    .filter(|&t| t > 0)
+                    .map(|t| (downloaded_bytes as f64 * 100.0 / t as f64) as f32),
+                remaining.and_then(|r| eta(r, bytes_per_sec)),
+            ),
+        };
+        PullProgress {
+            model: self.model.clone(),
+            job_id: self.job_id.lock().clone(),
+            status: state.status,
+            file: match state.status {
+                PullStatus::Downloading => state.files.last().map(|f| f.name.clone()),
+                _ => None,
+            },
+            downloaded_bytes,
+            total_bytes,
+            percent,
+            bytes_per_sec,
+            eta_secs,
+            error: state.error.clone(),
+        }
+    }
+}
+
+/// 按目前的平均速度估算剩余秒数（向上取整）；还没有速度时为 None
+fn eta(remaining: u64, bytes_per_sec: f64) -> Option<u64> {
+    (bytes_per_sec > 0.0).then(|| (remaining as f64 / bytes_per_sec).ceil() as u64)
+}
+
+/// 模型名 -> 最近一次 pull
+#[derive(Default)]
+pub struct ModelPulls {
+    pulls: Mutex<HashMap<String, Arc<Pull>>>,
+}
+
+impl ModelPulls {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, model: &str) -> Option<Arc<Pull>> {
+        self.pulls.lock().get(model).cloned()
+    }
+
+    /// 已经在下载时返回 Err(进行中的那次)
+    fn begin(&self, model: &str) -> Result<Arc<Pull>, Arc<Pull>> {
+        let mut pulls = self.pulls.lock();
+        if let Some(pull) = pulls.get(model) {
+            if pull.status() == PullStatus::Downloading {
+                return Err(pull.clone());
+            }
+        }
+        let pull = Arc::new(Pull::new(model));
+        pulls.insert(model.to_string(), pull.clone());
+        Ok(pull)
+    }
+}
+
+/// 依次把 tokenizer 和 GGUF 拉进缓存（tokenizer 很小，先拉它，这样下载 GGUF 时总大小已知）
+fn pull_files(hub: &HubArtifacts, pull: &Pull) -> anyhow::Result<Vec<PulledFile>> {
+    let files = [
+        (hub.tokenizer_repo.as_str(), "tokenizer.json"),
+        (hub.repo.as_str(), hub.file.as_str()),
+    ];
+    let mut pulled = Vec::new();
+    for (repo, file) in files {
+        pull.begin_file(file);
+        let (path, cached) = match hub_stream::open(repo, file)? {
+            HubWeights::Cached(path) => (path, true),
+            HubWeights::Streaming { reader, download } => {
+                let progress = reader.progress().clone();
+                drop(reader);
+                while !download.is_finished() {
+                    pull.advance(progress.written(), progress.total(), false);
+                    std::thread::sleep(PULL_POLL_INTERVAL);
+                }
+                let (path, _) = download
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("download thread for {repo}/{file} panicked"))??;
+                (path, false)
+            }
+        };
+        let bytes = std::fs::metadata(&path)?.len();
+        pull.advance(bytes, Some(bytes), cached);
+        pulled.push(PulledFile {
+            repo: repo.to_string(),
+            file: file.to_string(),
+            path: path.display().to_string(),
+            bytes,
+            cached,
+        });
+    }
+    Ok(pulled)
+}
+
+/// 模型在 hub 上的文件；本地权重和非 candle 模型返回 400
+fn hub_files(state: &AppState, name: &str) -> Result<HubArtifacts, ApiError> {
+    let meta = state.registry.get_model(name).ok_or_else(|| {
+        api_error(
+            Status::NotFound,
+            "model_not_found",
+            format!("model `{name}` not found"),
+        )
+    })?;
+    let not_pullable = |reason: String| api_error(Status::BadRequest, "not_pullable", reason);
+    if meta.engine_kind != EngineKind::CANDLE {
+        return Err(not_pullable(format!(
+            "model `{name}` uses the `{}` engine, which has no hub weights to pull",
+            meta.engine_kind.as_str()
+        )));
+    }
+    match candle_source(&meta) {
+        ModelSource::Hub(hub) => Ok(hub),
+        ModelSource::Local(local) => Err(not_pullable(format!(
+            "model `{name}` loads local weights from {}; nothing to pull",
+            local.gguf.display()
+        ))),
+    }
+}
+
+/// 后台下载模型权重：POST /models/<name>/pull
+///
+/// 同一个模型已经在下载时返回那次的 job id。
+#[utoipa::path(
+    tag = "models",
+    responses(
+        (status = 202, description = "download started; its result is a PullResult, progress at GET /models/{name}/pull/events", body = JobAcceptedResponse),
+        (status = 400, description = "the model has local weights or is not a candle model", body = ErrorResponse),
+        (status = 404, description = "model not found", body = ErrorResponse),
+        (status = 409, description = "the server runs in offline mode", body = ErrorResponse)
+    )
+)]
+#[post("/models/<name>/pull")]
+pub async fn pull_model(
+    state: &State<Arc<AppState>>,
+    name: &str,
+) -> Result<status::Custom<Json<JobAcceptedResponse>>, ApiError> {
+    let hub = hub_files(state, name)?;
+    if state.offline {
+        return Err(api_error(
+            Status::Conflict,
+            "offline",
+            "the server runs in offline mode and does not download models",
+        ));
+    }
+    let pull = match state.pulls.begin(name) {
+        Ok(pull) => pull,
+        Err(running) => {
+            let job_id = running.job_id.lock().clone();
+            return Ok(status::Custom(
+                Status::Accepted,
+                Json(JobAcceptedResponse { job_id }),
+            ));
+        }
+    };
+
+    // 先占住 job_id 的锁，避免 SSE 在 id 写进去之前读到空字符串
+    let mut job_id_slot = pull.job_id.lock();
+    let app = state.inner().clone();
+    let model = name.to_string();
+    let task = pull.clone();
+    let job_id = state.jobs.spawn_blocking("model_pull", move |job| {
+        let result = pull_files(&hub, &task);
+        task.finish(result.as_ref().map(|_| ()).map_err(|e| format!("{e:#}")));
+        match &result {
+            Ok(_) => {
+                job.set_progress(1.0);
+                app.events.emit(ModelEvent::Pulled {
+                    model: model.clone(),
+                });
+            }
+            Err(e) => app.events.emit(ModelEvent::PullFailed {
+                model: model.clone(),
+                error: format!("{e:#}"),
+            }),
+        }
+        result.map(|files| PullResult { model, files })
+    });
+    *job_id_slot = job_id.clone();
+    drop(job_id_slot);
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(JobAcceptedResponse { job_id }),
+    ))
+}
+
+/// 下载进度：GET /models/<name>/pull/events
+///
+/// 每隔一段时间推送一条 `progress`，结束时推送 `completed` 或 `failed` 后关闭。
+#[utoipa::path(
+    tag = "models",
+    responses(
+        (status = 200, description = "SSE stream of PullProgress events", body = String, content_type = "text/event-stream"),
+        (status = 404, description = "the model has never been pulled", body = ErrorResponse)
+    )
+)]
+#[get("/models/<name>/pull/events")]
+pub async fn pull_events(
+    state: &State<Arc<AppState>>,
+    name: &str,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], ApiError> {
+    let pull = state.pulls.get(name).ok_or_else(|| {
+        api_error(
+            Status::NotFound,
+            "pull_not_found",
+            format!("model `{name}` has not been pulled"),
+        )
+    })?;
+    Ok(EventStream! {
+        loop {
+            let progress = pull.snapshot();
+            let event = match progress.status {
+                PullStatus::Downloading => "progress",
+                PullStatus::Completed => "completed",
+                PullStatus::Failed => "failed",
+            };
+            yield Event::json(&progress).event(event);
+            if progress.status != PullStatus::Downloading {
+                break;
+            }
+            select! {
+                _ = rocket::tokio::time::sleep(PULL_EVENT_INTERVAL) => {}
+                _ = &mut shutdown => break,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_sums_files_and_estimates_the_remaining_time() {
+        let pull = Pull::new("m");
+        pull.begin_file("tokenizer.json");
+        pull.advance(100, Some(100), true);
+        pull.begin_file("model.gguf");
+        let progress = pull.snapshot();
+        assert_eq!(progress.total_bytes, None);
+        assert_eq!(progress.percent, None);
+
+        pull.advance(300, Some(900), false);
+        let progress = pull.snapshot();
+        assert_eq!(progress.file.as_deref(), Some("model.gguf"));
+        assert_eq!(progress.downloaded_bytes, 400);
+        assert_eq!(progress.total_bytes, Some(1000));
+        assert_eq!(progress.percent, Some(40.0));
+        assert!(progress.eta_secs.is_some());
+
+        pull.finish(Ok(()));
+        let progress = pull.snapshot();
+        assert_eq!(progress.status, PullStatus::Completed);
+        assert_eq!(
+            (progress.percent, progress.eta_secs),
+            (Some(100.0), Some(0))
+        );
+    }
+
+    #[test]
+    fn eta_uses_the_average_rate() {
+        assert_eq!(eta(1000, 300.0), Some(4));
+        assert_eq!(eta(1000, 0.0), None);
+    }
+}
@@ -0,0 +1,332 @@
+//! 量化版本对比：`POST /catalog/<id>/benchmark` 把同一个目录模型的几个量化版本依次加载，
+//! 跑同一套 prompt，报告速度（tokens/s、加载耗时）和质量指标（参考答案的平均 log p / perplexity），
+//! 帮助在 Q4 / Q5 / Q8 之间按自己的硬件做选择。
+//!
+//! 每个量化版本以 `<id>@<quant>` 的名字临时注册，测完立即删除，同一时间只占一份内存。
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Context;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::AppState;
+use crate::catalog::{CatalogEntry, CatalogQuant};
+use crate::jobs::JobHandle;
+use crate::types::JobAcceptedResponse;
+
+/// 不指定时每个 prompt 生成的 token 数
+const DEFAULT_MAX_TOKENS: usize = 64;
+
+/// 内置的 prompt 套件：（prompt，参考答案）
+const DEFAULT_SUITE: &[(&str, &str)] = &[
+    (
+        "What is the capital of France?",
+        "The capital of France is Paris.",
+    ),
+    (
+        "Translate to English: 'Bonjour, comment ça va ?'",
+        "Hello, how are you?",
+    ),
+    (
+        "What is 12 multiplied by 12?",
+        "12 multiplied by 12 is 144.",
+    ),
+    (
+        "Name the largest planet in the solar system.",
+        "Jupiter is the largest planet in the solar system.",
+    ),
+    (
+        "Write a Python expression that reverses a list called items.",
+        "items[::-1]",
+    ),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BenchmarkCase {
+    pub prompt: String,
+    /// 用来算 log p 的参考答案
+    pub reference: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct QuantBenchmarkRequest {
+    /// 要对比的量化版本，按给定顺序测试；为空时测目录里的全部版本
+    pub quants: Vec<String>,
+    /// 每个 prompt 生成的 token 数，默认 64
+    pub max_tokens: Option<usize>,
+    /// 自定义 prompt 套件，为空时用内置的
+    pub cases: Vec<BenchmarkCase>,
+}
+
+/// 单个量化版本的结果；加载或运行失败时只有 `error`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuantBenchmarkResult {
+    pub quantization: String,
+    pub size_gb: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_ms: Option<u64>,
+    pub generated_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens_per_second: Option<f64>,
+    /// 参考答案每个 token 的平均 log p，越接近 0 越好
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_logprob: Option<f64>,
+    /// exp(-mean_logprob)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perplexity: Option<f64>,
+    /// 和本次最好的版本相比的 mean_logprob 差值（最好的为 0）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprob_delta: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl QuantBenchmarkResult {
+    fn failed(quant: &CatalogQuant, error: String) -> Self {
+        Self {
+            quantization: quant.quantization.clone(),
+            size_gb: quant.size_gb,
+            load_ms: None,
+            generated_tokens: 0,
+            tokens_per_second: None,
+            mean_logprob: None,
+            perplexity: None,
+            logprob_delta: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// 对比 job 成功时的结果
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuantBenchmarkReport {
+    pub model_id: String,
+    pub cases: usize,
+    pub max_tokens: usize,
+    pub results: Vec<QuantBenchmarkResult>,
+    /// mean_logprob 最高的版本
+    pub best_quality: Option<String>,
+    /// tokens_per_second 最高的版本
+    pub fastest: Option<String>,
+}
+
+/// 测试期间临时注册的模型名
+fn bench_model_name(id: &str, quant: &CatalogQuant) -> String {
+    format!("{id}@{}", quant.quantization.to_lowercase())
+}
+
+/// 依次对比同一个模型的几个量化版本：POST /catalog/<id>/benchmark
+#[utoipa::path(
+    tag = "models",
+    request_body(content = Option<QuantBenchmarkRequest>),
+    responses(
+        (status = 202, description = "benchmark started; its result is a QuantBenchmarkReport", body = JobAcceptedResponse),
+        (status = 400, description = "unknown quantization or empty prompt / reference", body = ErrorResponse),
+        (status = 404, description = "not in the catalog", body = ErrorResponse),
+        (status = 409, description = "a benchmark of this model is already running", body = ErrorResponse)
+    )
+)]
+#[post("/catalog/<id>/benchmark", data = "<req>")]
+pub async fn benchmark_catalog_model(
+    state: &State<Arc<AppState>>,
+    id: &str,
+    req: Option<Json<QuantBenchmarkRequest>>,
+) -> Result<status::Custom<Json<JobAcceptedResponse>>, ApiError> {
+    let req = req.map(|r| r.into_inner()).unwrap_or_default();
+    let entry = state.catalog.get(id).cloned().ok_or_else(|| {
+        api_error(
+            Status::NotFound,
+            "catalog_entry_not_found",
+            format!("`{id}` is not in the model catalog"),
+        )
+    })?;
+
+    let quants = if req.quants.is_empty() {
+        entry.quants.clone()
+    } else {
+        req.quants
+            .iter()
+            .map(|q| {
+                entry.quant(Some(q)).cloned().ok_or_else(|| {
+                    api_error(
+                        Status::BadRequest,
+                        "invalid_input",
+                        format!("`{id}` has no quantization `{q}`"),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let cases = if req.cases.is_empty() {
+        DEFAULT_SUITE
+            .iter()
+            .map(|(prompt, reference)| BenchmarkCase {
+                prompt: prompt.to_string(),
+                reference: reference.to_string(),
+            })
+            .collect()
+    } else {
+        req.cases
+    };
+    if cases
+        .iter()
+        .any(|c| c.prompt.trim().is_empty() || c.reference.trim().is_empty())
+    {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            "benchmark cases need a non-empty prompt and reference",
+        ));
+    }
+
+    let busy = quants.iter().any(|quant| {
+        state
+            .registry
+            .get_model(&bench_model_name(id, quant))
+            .is_some()
+    });
+    if busy {
+        return Err(api_error(
+            Status::Conflict,
+            "benchmark_running",
+            format!("a benchmark of `{id}` is already running"),
+        ));
+    }
+
+    let app = state.inner().clone();
+    let max_tokens = req.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+    let job_id = state.jobs.spawn("quant_benchmark", move |job| {
+        run(app, entry, quants, cases, max_tokens, job)
+    });
+
+    Ok(status::Custom(
+        Status::Accepted,
+        Json(JobAcceptedResponse { job_id }),
+    ))
+}
+
+async fn run(
+    app: Arc<AppState>,
+    entry: CatalogEntry,
+    quants: Vec<CatalogQuant>,
+    cases: Vec<BenchmarkCase>,
+    max_tokens: usize,
+    job: JobHandle,
+) -> anyhow::Result<QuantBenchmarkReport> {
+    let mut results = Vec::with_capacity(quants.len());
+    for (i, quant) in quants.iter().enumerate() {
+        let name = bench_model_name(&entry.id, quant);
+        let mut meta = entry.metadata(quant);
+        meta.name = name.clone();
+        app.registry.register(meta);
+
+        let started = Instant::now();
+        let loaded = {
+            let app = app.clone();
+            let name = name.clone();
+            rocket::tokio::task::spawn_blocking(move || app.load_model(&name)).await?
+        };
+        let result = match loaded {
+            Ok(_) => {
+                let load_ms = started.elapsed().as_millis() as u64;
+                measure(&app, &name, quant, load_ms, &cases, max_tokens)
+                    .await
+                    .unwrap_or_else(|e| QuantBenchmarkResult::failed(quant, format!("{e:#}")))
+            }
+            Err(e) => QuantBenchmarkResult::failed(quant, e.to_string()),
+        };
+        info!(
+            model = %entry.id,
+            quantization = %quant.quantization,
+            tokens_per_second = ?result.tokens_per_second,
+            mean_logprob = ?result.mean_logprob,
+            "quantization benchmarked"
+        );
+        results.push(result);
+
+        // 卸载并移除，下一个版本才有内存可用
+        let _ = app.delete_model(&name);
+        job.set_progress((i + 1) as f32 / quants.len() as f32);
+    }
+
+    let best = results
+        .iter()
+        .filter_map(|r| r.mean_logprob)
+        .fold(None, |best: Option<f64>, lp| {
+            Some(best.map_or(lp, |b| b.max(lp)))
+        });
+    for result in &mut results {
+        result.logprob_delta = result.mean_logprob.zip(best).map(|(lp, best)| lp - best);
+    }
+    let top_by = |key: fn(&QuantBenchmarkResult) -> Option<f64>| {
+        results
+            .iter()
+            .filter_map(|r| key(r).map(|value| (value, r)))
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, r)| r.quantization.clone())
+    };
+    let best_quality = top_by(|r| r.mean_logprob);
+    let fastest = top_by(|r| r.tokens_per_second);
+
+    Ok(QuantBenchmarkReport {
+        model_id: entry.id,
+        cases: cases.len(),
+        max_tokens,
+        results,
+        best_quality,
+        fastest,
+    })
+}
+
+/// 在已加载的模型上跑完整个套件：生成测速度，参考答案打分测质量
+async fn measure(
+    app: &AppState,
+    model_name: &str,
+    quant: &CatalogQuant,
+    load_ms: u64,
+    cases: &[BenchmarkCase],
+    max_tokens: usize,
+) -> anyhow::Result<QuantBenchmarkResult> {
+    let engine = app
+        .get_engine(model_name)
+        .with_context(|| format!("`{model_name}` is not loaded"))?;
+    let mut generated_tokens = 0;
+    let mut generation_secs = 0.0;
+    let mut logprob = 0.0;
+    let mut reference_tokens = 0;
+    for case in cases {
+        let input_ids = engine.encode_prompt(&case.prompt)?;
+        let started = Instant::now();
+        let generated = engine.complete_ids(&input_ids, max_tokens).await?;
+        generation_secs += started.elapsed().as_secs_f64();
+        generated_tokens += generated.ids.len();
+
+        let score = engine.score(&case.prompt, &case.reference).await?;
+        logprob += score.logprob;
+        reference_tokens += score.tokens;
+    }
+
+    let mean_logprob = logprob / reference_tokens.max(1) as f64;
+    Ok(QuantBenchmarkResult {
+        quantization: quant.quantization.clone(),
+        size_gb: quant.size_gb,
+        load_ms: Some(load_ms),
+        generated_tokens,
+        tokens_per_second: (generation_secs > 0.0)
+            .then(|| generated_tokens as f64 / generation_secs),
+        mean_logprob: Some(mean_logprob),
+        perplexity: Some((-mean_logprob).exp()),
+        logprob_delta: None,
+        error: None,
+    })
+}
@@ -0,0 +1,178 @@
+//! 等推理 permit 的请求队列（`[default.queue]`）：permit 都被占用时请求按到达顺序排队，
+//! 队列已经有 `max_len` 个请求时新请求直接拒绝（429 `queue_full`），排了 `max_wait_ms` 还没轮到的
+//! 放弃（503 `queue_timeout`），两种错误都带上请求在队列里的位置（`queue_position`），
+//! 而不是让客户端无限期挂着。有空闲 permit 的请求不受队列长度限制。
+//!
+//! 所有要 permit 的地方都从这里排队：普通请求、throughput 模式的一整批（算一个位置）、
+//! 被抢占后重新排队的 low 生成。
+//!
+//! ```toml
+//! [default.queue]
+//! max_len = 64
+//! max_wait_ms = 30000
+//! ```
+
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rocket::tokio::sync::Semaphore;
+use rocket::tokio::time::{timeout, Instant};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// 默认最多排 5 分钟
+pub const DEFAULT_MAX_WAIT_MS: u64 = 5 * 60 * 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    /// 最多几个请求同时排队，None 表示不限
+    pub max_len: Option<usize>,
+    /// 最多排多久（毫秒），0 表示不限
+    pub max_wait_ms: u64,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_len: None,
+            max_wait_ms: DEFAULT_MAX_WAIT_MS,
+        }
+    }
+}
+
+/// 没能排上 / 没等到的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum QueueRefusal {
+    /// 队列满了，`position` 是排进去的话会在的位置
+    #[error("request queue is full ({max_len} requests waiting)")]
+    Full { position: usize, max_len: usize },
+    /// 等了 `waited_ms` 还没轮到，放弃时排在 `position`
+    #[error("no inference slot after waiting {waited_ms} ms (queue position {position})")]
+    TimedOut { position: usize, waited_ms: u64 },
+}
+
+/// 排队中的请求（`AppState.queue`）
+pub struct AdmissionQueue {
+    max_len: Option<usize>,
+    max_wait: Option<Duration>,
+    next: AtomicU64,
+    /// 排队中的请求的序号，越小越靠前
+    waiting: Mutex<BTreeSet<u64>>,
+}
+
+impl AdmissionQueue {
+    pub fn new(config: &QueueConfig) -> Self {
+        Self {
+            max_len: config.max_len,
+            max_wait: (config.max_wait_ms > 0).then(|| Duration::from_millis(config.max_wait_ms)),
+            next: AtomicU64::new(0),
+            waiting: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// 排队中的请求数
+    pub fn len(&self) -> usize {
+        self.waiting.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    /// 进入队列；`semaphore` 没有空闲 permit 且队列已满时拒绝
+    pub fn enter(&self, semaphore: &Semaphore) -> Result<QueueTicket<'_>, QueueRefusal> {
+        let mut waiting = self.waiting.lock();
+        if let Some(max_len) = self.max_len {
+            if semaphore.available_permits() == 0 && waiting.len() >= max_len {
+                return Err(QueueRefusal::Full {
+                    position: waiting.len() + 1,
+                    max_len,
+                });
+            }
+        }
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        waiting.insert(seq);
+        Ok(QueueTicket { queue: self, seq })
+    }
+}
+
+/// 队列里的一个位置，drop 时离开队列
+pub struct QueueTicket<'a> {
+    queue: &'a AdmissionQueue,
+    seq: u64,
+}
+
+impl QueueTicket<'_> {
+    /// 从 1 开始的位置
+    pub fn position(&self) -> usize {
+        self.queue.waiting.lock().range(..=self.seq).count()
+    }
+
+    /// 排队等 `acquire` 完成，最多等 `max_wait_ms`
+    pub async fn wait<F: Future>(self, acquire: F) -> Result<F::Output, QueueRefusal> {
+        let Some(max_wait) = self.queue.max_wait else {
+            return Ok(acquire.await);
+        };
+        let started = Instant::now();
+        timeout(max_wait, acquire)
+            .await
+            .map_err(|_| QueueRefusal::TimedOut {
+                position: self.position(),
+                waited_ms: started.elapsed().as_millis() as u64,
+            })
+    }
+}
+
+impl Drop for QueueTicket<'_> {
+    fn drop(&mut self) {
+        self.queue.waiting.lock().remove(&self.seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn full_queues_refuse_and_waiters_time_out_with_their_position() {
+        let queue = AdmissionQueue::new(&QueueConfig {
+            max_len: Some(2),
+            max_wait_ms: 20,
+        });
+        let semaphore = Semaphore::new(1);
+        // 有空闲 permit 时不受长度限制
+        let held = queue.enter(&semaphore).unwrap();
+        let _permit = held.wait(semaphore.acquire()).await.unwrap().unwrap();
+        assert!(queue.is_empty());
+
+        let first = queue.enter(&semaphore).unwrap();
+        let second = queue.enter(&semaphore).unwrap();
+        assert_eq!((first.position(), second.position()), (1, 2));
+        assert_eq!(
+            queue.enter(&semaphore).err(),
+            Some(QueueRefusal::Full {
+                position: 3,
+                max_len: 2
+            })
+        );
+
+        drop(first);
+        assert_eq!(second.position(), 1);
+        match second.wait(semaphore.acquire()).await {
+            Err(QueueRefusal::TimedOut {
+                position,
+                waited_ms,
+            }) => assert!(position == 1 && waited_ms >= 20),
+            other => panic!("expected a timeout, got {:?}", other.map(|_| ())),
+        }
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,1145 @@
+//! RAG profile：把一个 embedding 模型和一个对话模型配成一对（`ServerConfig.rag`）
+//!
+//! - `POST /rag/<profile>/documents` 把文档按段落切块，用 embedding 模型编码后存在内存里
+//! - `PUT /rag/<profile>/documents` 按外部 id 更新文档：内容哈希没变的块原样保留，只编码变了的块；
+//!   `DELETE /rag/<profile>/documents/<id>` 删除文档。被替换 / 删除的块先只做标记，
+//!   `POST /rag/<profile>/compact` 把它们从向量索引里真正清掉
+//! - `/v1/chat/completions` 的 `model` 写 profile 名时，先用 embedding 模型编码最后一条用户消息，
+//!   把最相近的 `top_k` 段作为 system 消息插进对话，再交给对话模型生成。
+//!   相近程度是向量余弦相似度和 BM25 关键词得分的加权和（`keyword_weight`），
+//!   纯向量检索容易漏掉错误码、人名这类要精确匹配的词。
+//!   非流式响应的 `rag_sources` 给出检索到的段（文档 id、字符位置），请求带 `grounding` 时
+//!   `grounding` 标出回答的每一句依据的是哪一段，前端可以据此渲染引用
+//!   API key 的模型白名单对 profile 名和对话模型都生效
+//! - `POST /rag/<profile>/load` 一起加载两个模型；卸载其中一个时另一个也跟着卸载
+//!   （见 `AppState::unload_model`）
+//! - 文档按命名空间隔离：命名空间取 API key 的 `rag_namespace`，没写时就是 key 本身，
+//!   不带 key 的请求共用空命名空间。写入、检索、删除、压缩都只作用于调用方自己的命名空间，
+//!   文档 id 和 BM25 的统计也各自独立，共享部署上一个用户导入的文档不会被检索进另一个用户的 prompt
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::api_keys::ApiKey;
+use crate::app_state::AppState;
+use crate::model_registry::ModelStatus;
+use crate::session::{ChatRole, ChatTurn};
+use crate::types::LoadModelResponse;
+
+/// 切块时每块最多的字符数，超长的段落按字符硬切
+pub const CHUNK_CHARS: usize = 1000;
+
+/// 句子里至少这么大比例的关键词出现在某一段里，才算这句话有依据
+const GROUNDING_MIN_OVERLAP: f32 = 0.5;
+/// 比这短的词（of、is 之类）不参与依据判断
+const GROUNDING_MIN_WORD_CHARS: usize = 3;
+
+/// BM25 的词频饱和参数和长度归一化参数
+const BM25_K1: f32 = 1.2;
+const BM25_B: f32 = 0.75;
+
+fn default_top_k() -> usize {
+    3
+}
+
+fn default_keyword_weight() -> f32 {
+    0.3
+}
+
+/// `ServerConfig.rag` 里的一个 profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RagProfileConfig {
+    pub embedding_model: String,
+    pub chat_model: String,
+    /// 每次检索插入的段数
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    /// 关键词得分的权重：0 为纯向量检索，1 为纯 BM25
+    #[serde(default = "default_keyword_weight")]
+    pub keyword_weight: f32,
+}
+
+/// 切出来的一块及其在原文档里的位置（字符下标，左闭右开）。
+/// 块里相邻段落之间统一用一个空行连接，所以 `text` 和原文的这一段只在段间空白上可能不同
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub text: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl TextChunk {
+    /// 整篇文本作为一块
+    pub fn whole(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            start: 0,
+            end: text.chars().count(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Chunk {
+    id: u64,
+    /// 所属文档：`PUT` 时由调用方给出，`POST` 时自动分配
+    doc_id: String,
+    start: usize,
+    end: usize,
+    hash: [u8; 32],
+    text: String,
+    embedding: Vec<f32>,
+    /// 词 -> 词频，BM25 用
+    terms: HashMap<String, u32>,
+    /// 词数
+    length: usize,
+    /// 被替换或删除，检索时跳过，压缩时清掉
+    deleted: bool,
+}
+
+fn chunk_hash(text: &str) -> [u8; 32] {
+    Sha256::digest(text.as_bytes()).into()
+}
+
+/// 小写后按字母数字和 `_` 切词，`ERR_CONN_RESET`、`0x80070005` 这样的标识符保持完整
+fn keywords(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[derive(Default)]
+struct Store {
+    chunks: Vec<Chunk>,
+    /// 文档 id -> 当前的块
+    documents: HashMap<String, Vec<u64>>,
+    tombstones: usize,
+    /// 词 -> 包含它的有效块数
+    doc_freq: HashMap<String, usize>,
+    /// 有效块的总词数，算平均长度用
+    total_length: usize,
+}
+
+impl Store {
+    fn push(&mut self, id: u64, doc_id: &str, chunk: TextChunk, embedding: Vec<f32>) {
+        let TextChunk { text, start, end } = chunk;
+        let mut terms: HashMap<String, u32> = HashMap::new();
+        let mut length = 0;
+        for word in keywords(&text) {
+            *terms.entry(word).or_default() += 1;
+            length += 1;
+        }
+        for term in terms.keys() {
+            *self.doc_freq.entry(term.clone()).or_default() += 1;
+        }
+        self.total_length += length;
+        self.chunks.push(Chunk {
+            id,
+            doc_id: doc_id.to_string(),
+            start,
+            end,
+            hash: chunk_hash(&text),
+            text,
+            embedding,
+            terms,
+            length,
+            deleted: false,
+        });
+    }
+
+    /// 标记删除 `doc_id` 里不在 `keep` 中的块，返回标记的数量
+    fn retire(&mut self, doc_id: &str, keep: &[u64]) -> usize {
+        let mut retired = 0;
+        for chunk in &mut self.chunks {
+            if !chunk.deleted && chunk.doc_id == doc_id && !keep.contains(&chunk.id) {
+                chunk.deleted = true;
+                retired += 1;
+                self.total_length -= chunk.length;
+                for term in chunk.terms.keys() {
+                    if let Some(n) = self.doc_freq.get_mut(term) {
+                        *n -= 1;
+                        if *n == 0 {
+                            self.doc_freq.remove(term);
+                        }
+                    }
+                }
+            }
+        }
+        self.tombstones += retired;
+        retired
+    }
+}
+
+/// 检索到的一段文档
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RagSource {
+    pub id: u64,
+    pub document_id: String,
+    /// 在文档里的字符下标（左闭右开）
+    pub start: usize,
+    pub end: usize,
+    /// 按 `keyword_weight` 混合后的得分
+    pub score: f32,
+    /// 余弦相似度
+    pub vector_score: f32,
+    /// BM25 得分除以这次检索里的最大值，落在 0..=1
+    pub keyword_score: f32,
+    pub text: String,
+}
+
+/// 回答里的一句话及其依据
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct GroundedSentence {
+    /// 在回答里的字符下标（左闭右开）
+    pub start: usize,
+    pub end: usize,
+    /// 依据的段在 `rag_sources` 里的下标，没有依据时为空
+    pub source: Option<usize>,
+    /// 句子关键词出现在该段里的比例
+    pub overlap: f32,
+}
+
+/// 按句末标点和换行切句，返回去掉首尾空白后的字符范围
+fn sentences(text: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for (i, &c) in chars.iter().enumerate() {
+        let next_is_space = chars.get(i + 1).is_none_or(|n| n.is_whitespace());
+        let ends = match c {
+            '.' | '!' | '?' => next_is_space,
+            '。' | '！' | '？' | '\n' => true,
+            _ => false,
+        };
+        if ends || i + 1 == chars.len() {
+            spans.push((start, i + 1));
+            start = i + 1;
+        }
+    }
+    spans
+        .into_iter()
+        .filter_map(|(start, end)| {
+            let start = start
+                + chars[start..end]
+                    .iter()
+                    .take_while(|c| c.is_whitespace())
+                    .count();
+            let end = end
+                - chars[start..end]
+                    .iter()
+                    .rev()
+                    .take_while(|c| c.is_whitespace())
+                    .count();
+            (start < end).then_some((start, end))
+        })
+        .collect()
+}
+
+/// 给回答的每一句找出关键词重合最多的段，重合比例不到 `GROUNDING_MIN_OVERLAP` 的没有依据
+pub fn ground(answer: &str, sources: &[RagSource]) -> Vec<GroundedSentence> {
+    let words = |text: &str| -> Vec<String> {
+        let mut words: Vec<String> = keywords(text)
+            .filter(|w| w.chars().count() >= GROUNDING_MIN_WORD_CHARS)
+            .collect();
+        words.sort();
+        words.dedup();
+        words
+    };
+    let source_words: Vec<Vec<String>> = sources.iter().map(|s| words(&s.text)).collect();
+    let chars: Vec<char> = answer.chars().collect();
+    sentences(answer)
+        .into_iter()
+        .map(|(start, end)| {
+            let sentence = words(&chars[start..end].iter().collect::<String>());
+            let best = source_words
+                .iter()
+                .enumerate()
+                .map(|(i, source)| {
+                    let shared = sentence
+                        .iter()
+                        .filter(|w| source.binary_search(w).is_ok())
+                        .count();
+                    (i, shared as f32 / sentence.len().max(1) as f32)
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            let (source, overlap) = match best {
+                Some((i, overlap)) if overlap >= GROUNDING_MIN_OVERLAP => (Some(i), overlap),
+                Some((_, overlap)) => (None, overlap),
+                None => (None, 0.0),
+            };
+            GroundedSentence {
+                start,
+                end,
+                source,
+                overlap,
+            }
+        })
+        .collect()
+}
+
+/// 一次 upsert 里一篇文档的变化
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DocumentUpsert {
+    pub id: String,
+    /// 文档当前的块
+    pub chunk_ids: Vec<u64>,
+    /// 新编码的块数
+    pub embedded: usize,
+    /// 内容没变、原样保留的块数
+    pub unchanged: usize,
+    /// 被替换掉的旧块数
+    pub removed: usize,
+}
+
+/// 一个 profile 及其文档
+pub struct RagProfile {
+    pub name: String,
+    pub config: RagProfileConfig,
+    /// 命名空间 -> 该命名空间的文档
+    collections: RwLock<HashMap<String, Store>>,
+    next_id: AtomicU64,
+    next_doc: AtomicU64,
+}
+
+impl RagProfile {
+    pub fn new(name: &str, config: RagProfileConfig) -> Self {
+        Self {
+            name: name.to_string(),
+            config,
+            collections: RwLock::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            next_doc: AtomicU64::new(0),
+        }
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// 把已经编码好的块存成 `namespace` 里的一篇新文档，返回自动分配的文档 id（`doc-<n>`）和块 id
+    pub fn add(
+        &self,
+        namespace: &str,
+        chunks: Vec<TextChunk>,
+        embeddings: Vec<Vec<f32>>,
+    ) -> (String, Vec<u64>) {
+        let mut collections = self.collections.write();
+        let store = collections.entry(namespace.to_string()).or_default();
+        // 跳过调用方用 `PUT` 占用的 id
+        let doc_id = loop {
+            let id = format!("doc-{}", self.next_doc.fetch_add(1, Ordering::Relaxed) + 1);
+            if !store.documents.contains_key(&id) {
+                break id;
+            }
+        };
+        let chunk_ids: Vec<u64> = chunks
+            .into_iter()
+            .zip(embeddings)
+            .map(|(chunk, embedding)| {
+                let id = self.next_id();
+                store.push(id, &doc_id, chunk, embedding);
+                id
+            })
+            .collect();
+        store.documents.insert(doc_id.clone(), chunk_ids.clone());
+        (doc_id, chunk_ids)
+    }
+
+    /// `doc_id` 现有的块里和 `chunks` 内容相同的块 id（每个旧块最多对应一次），None 表示要重新编码
+    pub fn unchanged_chunks(
+        &self,
+        namespace: &str,
+        doc_id: &str,
+        chunks: &[TextChunk],
+    ) -> Vec<Option<u64>> {
+        let collections = self.collections.read();
+        let mut old: Vec<&Chunk> = collections
+            .get(namespace)
+            .map_or(&[][..], |store| &store.chunks)
+            .iter()
+            .filter(|c| !c.deleted && c.doc_id == doc_id)
+            .collect();
+        chunks
+            .iter()
+            .map(|chunk| {
+                let hash = chunk_hash(&chunk.text);
+                let at = old.iter().position(|c| c.hash == hash)?;
+                Some(old.swap_remove(at).id)
+            })
+            .collect()
+    }
+
+    /// 用 `chunks` 替换 `doc_id` 的内容。`reused` 来自 `unchanged_chunks`，
+    /// `embeddings` 依次对应其中为 None 的块
+    pub fn upsert(
+        &self,
+        namespace: &str,
+        doc_id: &str,
+        chunks: Vec<TextChunk>,
+        reused: Vec<Option<u64>>,
+        embeddings: Vec<Vec<f32>>,
+    ) -> DocumentUpsert {
+        let mut collections = self.collections.write();
+        let store = collections.entry(namespace.to_string()).or_default();
+        let mut embeddings = embeddings.into_iter();
+        let mut chunk_ids = Vec::with_capacity(chunks.len());
+        let (mut embedded, mut unchanged) = (0, 0);
+        for (chunk, reused) in chunks.into_iter().zip(reused) {
+            // 并发的 upsert 可能已经把旧块标记删除了，这时按新块写入
+            let live =
+                reused.and_then(|id| store.chunks.iter_mut().find(|c| c.id == id && !c.deleted));
+            if let Some(old) = live {
+                // 内容没变，位置可能随前面的段落变了
+                old.start = chunk.start;
+                old.end = chunk.end;
+                chunk_ids.push(old.id);
+                unchanged += 1;
+                continue;
+            }
+            let Some(embedding) = embeddings.next() else {
+                continue;
+            };
+            let id = self.next_id();
+            store.push(id, doc_id, chunk, embedding);
+            chunk_ids.push(id);
+            embedded += 1;
+        }
+        let removed = store.retire(doc_id, &chunk_ids);
+        store
+            .documents
+            .insert(doc_id.to_string(), chunk_ids.clone());
+        DocumentUpsert {
+            id: doc_id.to_string(),
+            chunk_ids,
+            embedded,
+            unchanged,
+            removed,
+        }
+    }
+
+    /// 删除文档，返回标记删除的块数；文档不存在时为 None
+    pub fn delete(&self, namespace: &str, doc_id: &str) -> Option<usize> {
+        let mut collections = self.collections.write();
+        let store = collections.get_mut(namespace)?;
+        store.documents.remove(doc_id)?;
+        Some(store.retire(doc_id, &[]))
+    }
+
+    /// 从索引里清掉标记删除的块，返回清掉的数量
+    pub fn compact(&self, namespace: &str) -> usize {
+        let mut collections = self.collections.write();
+        let Some(store) = collections.get_mut(namespace) else {
+            return 0;
+        };
+        store.chunks.retain(|c| !c.deleted);
+        store.chunks.shrink_to_fit();
+        std::mem::take(&mut store.tombstones)
+    }
+
+    /// `namespace` 里有效的块数
+    pub fn len(&self, namespace: &str) -> usize {
+        self.collections
+            .read()
+            .get(namespace)
+            .map_or(0, |store| store.chunks.len() - store.tombstones)
+    }
+
+    pub fn is_empty(&self, namespace: &str) -> bool {
+        self.len(namespace) == 0
+    }
+
+    /// `namespace` 里等待压缩的块数
+    pub fn tombstones(&self, namespace: &str) -> usize {
+        self.collections
+            .read()
+            .get(namespace)
+            .map_or(0, |store| store.tombstones)
+    }
+
+    /// `namespace` 里和查询最相近的 `k` 段：`query` 是查询的向量（都是归一化的，点积即余弦相似度），
+    /// `query_text` 用来算 BM25
+    pub fn search(
+        &self,
+        namespace: &str,
+        query: &[f32],
+        query_text: &str,
+        k: usize,
+    ) -> Vec<RagSource> {
+        let collections = self.collections.read();
+        let Some(store) = collections.get(namespace) else {
+            return Vec::new();
+        };
+        let live = store.chunks.len() - store.tombstones;
+        let avg_length = store.total_length as f32 / live.max(1) as f32;
+        let mut query_terms: Vec<String> = keywords(query_text).collect();
+        query_terms.sort();
+        query_terms.dedup();
+        let bm25 = |chunk: &Chunk| -> f32 {
+            query_terms
+                .iter()
+                .filter_map(|term| {
+                    let tf = *chunk.terms.get(term)? as f32;
+                    let df = store.doc_freq.get(term).copied().unwrap_or(0) as f32;
+                    let idf = ((live as f32 - df + 0.5) / (df + 0.5) + 1.0).ln();
+                    let norm = 1.0 - BM25_B + BM25_B * chunk.length as f32 / avg_length.max(1.0);
+                    Some(idf * tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * norm))
+                })
+                .sum()
+        };
+        let mut scored: Vec<RagSource> = store
+            .chunks
+            .iter()
+            .filter(|chunk| !chunk.deleted)
+            .map(|chunk| RagSource {
+                id: chunk.id,
+                document_id: chunk.doc_id.clone(),
+                start: chunk.start,
+                end: chunk.end,
+                score: 0.0,
+                vector_score: chunk.embedding.iter().zip(query).map(|(a, b)| a * b).sum(),
+                keyword_score: bm25(chunk),
+                text: chunk.text.clone(),
+            })
+            .collect();
+        let max_keyword = scored.iter().map(|s| s.keyword_score).fold(0.0, f32::max);
+        let weight = self.config.keyword_weight;
+        for source in &mut scored {
+            if max_keyword > 0.0 {
+                source.keyword_score /= max_keyword;
+            }
+            source.score = (1.0 - weight) * source.vector_score + weight * source.keyword_score;
+        }
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        scored.truncate(k);
+        scored
+    }
+}
+
+/// profile 名 -> profile
+#[derive(Default)]
+pub struct RagProfiles {
+    profiles: HashMap<String, Arc<RagProfile>>,
+}
+
+impl RagProfiles {
+    pub fn from_config(config: &HashMap<String, RagProfileConfig>) -> anyhow::Result<Self> {
+        let mut profiles = HashMap::new();
+        for (name, profile) in config {
+            if profile.embedding_model.is_empty() || profile.chat_model.is_empty() {
+                anyhow::bail!("rag profile `{name}` needs both embedding_model and chat_model");
+            }
+            if profile.embedding_model == profile.chat_model {
+                anyhow::bail!("rag profile `{name}` uses the same model for embedding and chat");
+            }
+            if profile.top_k == 0 {
+                anyhow::bail!("rag profile `{name}` has top_k = 0");
+            }
+            if !(0.0..=1.0).contains(&profile.keyword_weight) {
+                anyhow::bail!("rag profile `{name}` needs keyword_weight between 0 and 1");
+            }
+            profiles.insert(
+                name.clone(),
+                Arc::new(RagProfile::new(name, profile.clone())),
+            );
+        }
+        Ok(Self { profiles })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<RagProfile>> {
+        self.profiles.get(name).cloned()
+    }
+
+    /// 和 `model_name` 配成一对的模型，卸载时一起卸载
+    pub fn partners_of(&self, model_name: &str) -> Vec<String> {
+        self.profiles
+            .values()
+            .filter_map(|p| {
+                if p.config.embedding_model == model_name {
+                    Some(p.config.chat_model.clone())
+                } else if p.config.chat_model == model_name {
+                    Some(p.config.embedding_model.clone())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// 按空行分段，相邻的短段合并，每块不超过 `max_chars` 个字符
+pub fn chunk_text(text: &str, max_chars: usize) -> Vec<String> {
+    chunk_document(text, max_chars)
+        .into_iter()
+        .map(|chunk| chunk.text)
+        .collect()
+}
+
+/// 同 `chunk_text`，同时记下每块在原文里的字符位置
+pub fn chunk_document(text: &str, max_chars: usize) -> Vec<TextChunk> {
+    let mut chunks = Vec::new();
+    let mut current: Option<TextChunk> = None;
+    // 当前段落在原文里的字符位置
+    let mut char_at = 0;
+    for part in text.split("\n\n") {
+        let leading = part.len() - part.trim_start().len();
+        let paragraph = part.trim();
+        let paragraph_start = char_at + part[..leading].chars().count();
+        char_at += part.chars().count() + 2;
+        if paragraph.is_empty() {
+            continue;
+        }
+        let chars: Vec<char> = paragraph.chars().collect();
+        for (i, piece) in chars.chunks(max_chars).enumerate() {
+            let start = paragraph_start + i * max_chars;
+            let piece = TextChunk {
+                text: piece.iter().collect(),
+                start,
+                end: start + piece.len(),
+            };
+            current = match current.take() {
+                Some(mut chunk)
+                    if chunk.text.chars().count() + 2 + piece.text.chars().count() <= max_chars =>
+                {
+                    chunk.text.push_str("\n\n");
+                    chunk.text.push_str(&piece.text);
+                    chunk.end = piece.end;
+                    Some(chunk)
+                }
+                Some(chunk) => {
+                    chunks.push(chunk);
+                    Some(piece)
+                }
+                None => Some(piece),
+            };
+        }
+    }
+    chunks.extend(current);
+    chunks
+}
+
+/// 用 profile 的 embedding 模型编码；`state.embedding_cache` 里已有的文本不再编码，
+/// 同一批里重复的文本只编码一次
+async fn embed(
+    state: &AppState,
+    profile: &RagProfile,
+    texts: &[String],
+) -> Result<Vec<Vec<f32>>, ApiError> {
+    let model = &profile.config.embedding_model;
+    let engine = state.get_engine(model).ok_or_else(|| {
+        api_error(
+            Status::Conflict,
+            "model_not_loaded",
+            format!(
+                "embedding model `{model}` is not loaded (POST /rag/{}/load loads the pair)",
+                profile.name
+            ),
+        )
+    })?;
+    let cached = state.embedding_cache.get_many(model, texts);
+    let mut missing: Vec<String> = Vec::new();
+    for (text, found) in texts.iter().zip(&cached) {
+        if found.is_none() && !missing.contains(text) {
+            missing.push(text.clone());
+        }
+    }
+    if missing.is_empty() {
+        return Ok(cached.into_iter().flatten().map(|v| (*v).clone()).collect());
+    }
+
+    let embeddings = engine.embed(&missing).await.map_err(|e| {
+        api_error(
+            Status::InternalServerError,
+            "inference_failed",
+            format!("embedding with `{model}` failed: {e}"),
+        )
+    })?;
+    if embeddings.len() != missing.len() {
+        return Err(api_error(
+            Status::InternalServerError,
+            "inference_failed",
+            format!(
+                "`{model}` returned {} embeddings for {} texts",
+                embeddings.len(),
+                missing.len()
+            ),
+        ));
+    }
+    let fresh: HashMap<String, Vec<f32>> = missing.into_iter().zip(embeddings).collect();
+    for (text, vector) in &fresh {
+        state.embedding_cache.insert(model, text, vector.clone());
+    }
+    Ok(texts
+        .iter()
+        .zip(cached)
+        .map(|(text, found)| match found {
+            Some(vector) => (*vector).clone(),
+            None => fresh[text].clone(),
+        })
+        .collect())
+}
+
+/// 在 `namespace` 里检索最后一条用户消息相关的文档，作为 system 消息插在已有的 system 消息之后。
+/// 没有文档或没有用户消息时不改动对话
+pub(crate) async fn augment(
+    state: &AppState,
+    profile: &RagProfile,
+    namespace: &str,
+    messages: &mut Vec<ChatTurn>,
+) -> Result<Vec<RagSource>, ApiError> {
+    let Some(query) = messages
+        .iter()
+        .rev()
+        .find(|turn| turn.role == ChatRole::User)
+        .map(|turn| turn.content.clone())
+    else {
+        return Ok(Vec::new());
+    };
+    if profile.is_empty(namespace) {
+        return Ok(Vec::new());
+    }
+    let vector = embed(state, profile, std::slice::from_ref(&query))
+        .await?
+        .remove(0);
+    let sources = profile.search(namespace, &vector, &query, profile.config.top_k);
+    if sources.is_empty() {
+        return Ok(sources);
+    }
+
+    let mut context = String::from("Answer using the following context when it is relevant.");
+    for (i, source) in sources.iter().enumerate() {
+        context.push_str(&format!("\n\n[{}] {}", i + 1, source.text));
+    }
+    let at = messages
+        .iter()
+        .take_while(|turn| turn.role == ChatRole::System)
+        .count();
+    messages.insert(
+        at,
+        ChatTurn {
+            role: ChatRole::System,
+            content: context,
+        },
+    );
+    Ok(sources)
+}
+
+fn profile_not_found(name: &str) -> ApiError {
+    api_error(
+        Status::NotFound,
+        "rag_profile_not_found",
+        format!("rag profile `{name}` is not configured"),
+    )
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RagDocumentsRequest {
+    /// 每篇文档会按段落切块
+    pub documents: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RagDocumentsResponse {
+    pub profile: String,
+    /// 自动分配的文档 id，和请求里非空的文档一一对应，可用于 `PUT` / `DELETE`
+    pub document_ids: Vec<String>,
+    /// 新增文本块的 id
+    pub chunk_ids: Vec<u64>,
+    pub total_chunks: usize,
+}
+
+/// 给 profile 添加文档：POST /rag/<profile>/documents
+#[utoipa::path(
+    tag = "rag",
+    request_body = RagDocumentsRequest,
+    responses(
+        (status = 200, body = RagDocumentsResponse),
+        (status = 400, description = "no non-empty documents", body = ErrorResponse),
+        (status = 404, description = "profile not configured", body = ErrorResponse),
+        (status = 409, description = "embedding model not loaded", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/rag/<profile>/documents", data = "<req>")]
+pub async fn add_documents(
+    state: &State<Arc<AppState>>,
+    key: ApiKey,
+    profile: &str,
+    req: Json<RagDocumentsRequest>,
+) -> Result<Json<RagDocumentsResponse>, ApiError> {
+    let rag = state
+        .rag
+        .get(profile)
+        .ok_or_else(|| profile_not_found(profile))?;
+    let namespace = key.rag_namespace();
+    let documents: Vec<Vec<TextChunk>> = req
+        .documents
+        .iter()
+        .map(|doc| chunk_document(doc, CHUNK_CHARS))
+        .filter(|chunks| !chunks.is_empty())
+        .collect();
+    if documents.is_empty() {
+        return Err(api_error(
+            Status::BadRequest,
+            "invalid_input",
+            "documents must contain some text",
+        ));
+    }
+    let texts: Vec<String> = documents.iter().flatten().map(|c| c.text.clone()).collect();
+    let mut embeddings = embed(state, &rag, &texts).await?.into_iter();
+    let mut document_ids = Vec::with_capacity(documents.len());
+    let mut chunk_ids = Vec::with_capacity(texts.len());
+    for chunks in documents {
+        let count = chunks.len();
+        let (doc_id, ids) = rag.add(
+            &namespace,
+            chunks,
+            embeddings.by_ref().take(count).collect(),
+        );
+        document_ids.push(doc_id);
+        chunk_ids.extend(ids);
+    }
+    Ok(Json(RagDocumentsResponse {
+        profile: rag.name.clone(),
+        document_ids,
+        chunk_ids,
+        total_chunks: rag.len(&namespace),
+    }))
+}
+
+/// 带外部 id 的文档
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RagDocument {
+    pub id: String,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RagUpsertRequest {
+    pub documents: Vec<RagDocument>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RagUpsertResponse {
+    pub profile: String,
+    pub documents: Vec<DocumentUpsert>,
+    pub total_chunks: usize,
+    /// 已替换 / 删除、等待 `compact` 的块数
+    pub tombstones: usize,
+}
+
+/// 按 id 新增或替换文档：PUT /rag/<profile>/documents
+///
+/// 内容没变的块不重新编码
+#[utoipa::path(
+    tag = "rag",
+    request_body = RagUpsertRequest,
+    responses(
+        (status = 200, body = RagUpsertResponse),
+        (status = 400, description = "empty or duplicate document ids, or a document without text", body = ErrorResponse),
+        (status = 404, description = "profile not configured", body = ErrorResponse),
+        (status = 409, description = "embedding model not loaded", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[put("/rag/<profile>/documents", data = "<req>")]
+pub async fn upsert_documents(
+    state: &State<Arc<AppState>>,
+    key: ApiKey,
+    profile: &str,
+    req: Json<RagUpsertRequest>,
+) -> Result<Json<RagUpsertResponse>, ApiError> {
+    let rag = state
+        .rag
+        .get(profile)
+        .ok_or_else(|| profile_not_found(profile))?;
+    let namespace = key.rag_namespace();
+    let invalid = |message: String| api_error(Status::BadRequest, "invalid_input", message);
+    let mut documents = Vec::with_capacity(req.documents.len());
+    for doc in &req.documents {
+        if doc.id.is_empty() {
+            return Err(invalid("document ids must not be empty".to_string()));
+        }
+        if documents.iter().any(|(id, _, _)| id == &doc.id) {
+            return Err(invalid(format!("document `{}` appears twice", doc.id)));
+        }
+        let chunks = chunk_document(&doc.text, CHUNK_CHARS);
+        if chunks.is_empty() {
+            return Err(invalid(format!(
+                "document `{}` has no text (DELETE /rag/{profile}/documents/{} removes it)",
+                doc.id, doc.id
+            )));
+        }
+        let reused = rag.unchanged_chunks(&namespace, &doc.id, &chunks);
+        documents.push((doc.id.clone(), chunks, reused));
+    }
+
+    // 所有文档里变了的块一起编码
+    let changed: Vec<String> = documents
+        .iter()
+        .flat_map(|(_, chunks, reused)| {
+            chunks
+                .iter()
+                .zip(reused)
+                .filter(|(_, reused)| reused.is_none())
+                .map(|(chunk, _)| chunk.text.clone())
+        })
+        .collect();
+    let mut embeddings = if changed.is_empty() {
+        Vec::new()
+    } else {
+        embed(state, &rag, &changed).await?
+    }
+    .into_iter();
+
+    let documents = documents
+        .into_iter()
+        .map(|(id, chunks, reused)| {
+            let count = reused.iter().filter(|r| r.is_none()).count();
+            let fresh = embeddings.by_ref().take(count).collect();
+            rag.upsert(&namespace, &id, chunks, reused, fresh)
+        })
+        .collect();
+    Ok(Json(RagUpsertResponse {
+        profile: rag.name.clone(),
+        documents,
+        total_chunks: rag.len(&namespace),
+        tombstones: rag.tombstones(&namespace),
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RagDeleteResponse {
+    pub profile: String,
+    pub id: String,
+    /// 标记删除的块数
+    pub removed: usize,
+    pub total_chunks: usize,
+    pub tombstones: usize,
+}
+
+/// 删除文档：DELETE /rag/<profile>/documents/<id>
+#[utoipa::path(
+    tag = "rag",
+    responses(
+        (status = 200, body = RagDeleteResponse),
+        (status = 404, description = "profile not configured or unknown document id", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[delete("/rag/<profile>/documents/<id>")]
+pub async fn delete_document(
+    state: &State<Arc<AppState>>,
+    key: ApiKey,
+    profile: &str,
+    id: &str,
+) -> Result<Json<RagDeleteResponse>, ApiError> {
+    let rag = state
+        .rag
+        .get(profile)
+        .ok_or_else(|| profile_not_found(profile))?;
+    let namespace = key.rag_namespace();
+    let removed = rag.delete(&namespace, id).ok_or_else(|| {
+        api_error(
+            Status::NotFound,
+            "document_not_found",
+            format!("rag profile `{profile}` has no document `{id}`"),
+        )
+    })?;
+    Ok(Json(RagDeleteResponse {
+        profile: rag.name.clone(),
+        id: id.to_string(),
+        removed,
+        total_chunks: rag.len(&namespace),
+        tombstones: rag.tombstones(&namespace),
+    }))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RagCompactResponse {
+    pub profile: String,
+    /// 从索引里清掉的块数
+    pub compacted: usize,
+    pub total_chunks: usize,
+}
+
+/// 清掉已替换 / 删除的块：POST /rag/<profile>/compact
+#[utoipa::path(
+    tag = "rag",
+    responses(
+        (status = 200, body = RagCompactResponse),
+        (status = 404, description = "profile not configured", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/rag/<profile>/compact")]
+pub async fn compact_profile(
+    state: &State<Arc<AppState>>,
+    key: ApiKey,
+    profile: &str,
+) -> Result<Json<RagCompactResponse>, ApiError> {
+    let rag = state
+        .rag
+        .get(profile)
+        .ok_or_else(|| profile_not_found(profile))?;
+    let namespace = key.rag_namespace();
+    let compacted = rag.compact(&namespace);
+    if compacted > 0 {
+        info!(profile = %rag.name, compacted, "compacted chunks");
+    }
+    Ok(Json(RagCompactResponse {
+        profile: rag.name.clone(),
+        compacted,
+        total_chunks: rag.len(&namespace),
+    }))
+}
+
+/// 一起加载 profile 的两个模型：POST /rag/<profile>/load
+#[utoipa::path(
+    tag = "rag",
+    responses(
+        (status = 200, description = "load result of the embedding model, then the chat model", body = [LoadModelResponse]),
+        (status = 404, description = "profile not configured", body = ErrorResponse)
+    ),
+    security((), ("api_key" = []))
+)]
+#[post("/rag/<profile>/load")]
+pub async fn load_profile(
+    state: &State<Arc<AppState>>,
+    profile: &str,
+) -> Result<Json<Vec<LoadModelResponse>>, ApiError> {
+    let rag = state
+        .rag
+        .get(profile)
+        .ok_or_else(|| profile_not_found(profile))?;
+    let mut results = Vec::new();
+    for model_name in [&rag.config.embedding_model, &rag.config.chat_model] {
+        let loaded = state
+            .registry
+            .get_model(model_name)
+            .is_some_and(|m| m.status == ModelStatus::Loaded);
+        let result = if loaded {
+            Ok("already loaded".to_string())
+        } else {
+            state
+                .load_model_and_wait(model_name)
+                .await
+                .map(|meta| format!("model loaded ({} engine)", meta.engine_kind))
+        };
+        let meta = state.registry.get_model(model_name);
+        results.push(LoadModelResponse {
+            model_name: model_name.clone(),
+            status: meta.as_ref().map_or(ModelStatus::Error, |m| m.status),
+            message: result.unwrap_or_else(|e| e.to_string()),
+            error: None,
+            load_timings: Vec::new(),
+        });
+    }
+    Ok(Json(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_merge_short_paragraphs_and_split_long_ones() {
+        let text = "first\n\nsecond\n\n\n\nthird";
+        assert_eq!(chunk_text(text, 100), vec!["first\n\nsecond\n\nthird"]);
+        assert_eq!(chunk_text(text, 14), vec!["first\n\nsecond", "third"]);
+
+        let long = "x".repeat(25);
+        let chunks = chunk_text(&long, 10);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[2], "xxxxx");
+        assert!(chunk_text(" \n\n ", 10).is_empty());
+    }
+
+    #[test]
+    fn chunks_remember_their_offsets() {
+        let text = "  héllo\n\n\n\nworld  \n\nagain";
+        let chars: Vec<char> = text.chars().collect();
+        let chunks = chunk_document(text, 12);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].text, "héllo\n\nworld");
+        assert_eq!((chunks[0].start, chunks[0].end), (2, 16));
+        let span: String = chars[chunks[1].start..chunks[1].end].iter().collect();
+        assert_eq!(span, "again");
+    }
+
+    #[test]
+    fn sentences_are_matched_to_their_sources() {
+        let source = |text: &str| RagSource {
+            id: 1,
+            document_id: "d".to_string(),
+            start: 0,
+            end: 0,
+            score: 1.0,
+            vector_score: 1.0,
+            keyword_score: 0.0,
+            text: text.to_string(),
+        };
+        let sources = [
+            source("Paris is the capital of France."),
+            source("Mitochondria are the powerhouse of the cell."),
+        ];
+        let answer = "The capital of France is Paris. I also like cheese! Mitochondria are the powerhouse of cells.";
+        let grounding = ground(answer, &sources);
+        let spans: Vec<&str> = grounding.iter().map(|g| &answer[g.start..g.end]).collect();
+        assert_eq!(
+            spans,
+            [
+                "The capital of France is Paris.",
+                "I also like cheese!",
+                "Mitochondria are the powerhouse of cells."
+            ]
+        );
+        let picked: Vec<_> = grounding.iter().map(|g| g.source).collect();
+        assert_eq!(picked, [Some(0), None, Some(1)]);
+    }
+
+    #[test]
+    fn keyword_scores_find_exact_identifiers() {
+        let profile = |keyword_weight| {
+            let profile = RagProfile::new(
+                "p",
+                RagProfileConfig {
+                    embedding_model: "e".to_string(),
+                    chat_model: "c".to_string(),
+                    top_k: 1,
+                    keyword_weight,
+                },
+            );
+            profile.add(
+                "",
+                vec![TextChunk::whole("Restart fails with ERR_CONN_RESET")],
+                vec![vec![0.0, 1.0]],
+            );
+            profile.add(
+                "",
+                vec![TextChunk::whole("Network connection errors in general")],
+                vec![vec![1.0, 0.0]],
+            );
+            profile
+        };
+        let query = "what does err_conn_reset mean";
+
+        let dense = profile(0.0).search("", &[0.9, 0.1], query, 1);
+        assert!(dense[0].text.starts_with("Network"));
+
+        let hybrid = profile(0.5).search("", &[0.9, 0.1], query, 2);
+        assert!(hybrid[0].text.contains("ERR_CONN_RESET"));
+        assert_eq!(hybrid[0].keyword_score, 1.0);
+        assert_eq!(hybrid[1].keyword_score, 0.0);
+    }
+}
@@ -0,0 +1,292 @@
+//! 按客户端限流（`[default.rate_limit]`）：每个 API key（没带 key 时按客户端 IP）各有两个令牌桶，
+//! 每分钟的请求数和每分钟的 token 数（prompt + 生成，请求结束后按实际用量扣）。
+//!
+//! 请求桶空了、或者 token 桶已经透支时，推理请求（`/infer*`、`/v1/*`、`/sessions`、`/assistant`、`/rag`）
+//! 返回 429 `rate_limited`，`Retry-After` 是桶里重新攒够所需的秒数。一个客户端的突发不会占满所有 permit。
+//!
+//! ```toml
+//! [default.rate_limit]
+//! requests_per_minute = 60
+//! tokens_per_minute = 20000
+//! ```
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Header, Method, Status};
+use rocket::{Data, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use crate::api::api_error;
+use crate::api_keys::key_from_headers;
+use crate::app_state::AppState;
+use crate::versioning::API_V1;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// 每个客户端每分钟最多几个请求，None 表示不限
+    pub requests_per_minute: Option<u32>,
+    /// 每个客户端每分钟最多用多少 token，None 表示不限
+    pub tokens_per_minute: Option<u64>,
+}
+
+impl RateLimitConfig {
+    pub fn enabled(&self) -> bool {
+        self.requests_per_minute.is_some() || self.tokens_per_minute.is_some()
+    }
+}
+
+/// 令牌桶：容量是每分钟的额度，按秒匀速补充。`charge` 可以把桶扣成负数（事后才知道用了多少）
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_minute: f64, now: Instant) -> Self {
+        Self {
+            capacity: per_minute,
+            available: per_minute,
+            refilled: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.available = (self.available + elapsed * self.capacity / 60.0).min(self.capacity);
+        self.refilled = now;
+    }
+
+    /// 攒到 `amount` 还要等多久
+    fn wait_for(&self, amount: f64) -> Duration {
+        let missing = amount - self.available;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing * 60.0 / self.capacity)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ClientBuckets {
+    requests: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+}
+
+/// 每个客户端的令牌桶（`AppState.rate_limits`）
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    clients: Mutex<HashMap<String, ClientBuckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled()
+    }
+
+    fn with_buckets<T>(&self, client: &str, f: impl FnOnce(&mut ClientBuckets) -> T) -> T {
+        let now = Instant::now();
+        let mut clients = self.clients.lock();
+        let buckets = clients
+            .entry(client.to_string())
+            .or_insert_with(|| ClientBuckets {
+                requests: self
+                    .config
+                    .requests_per_minute
+                    .map(|n| TokenBucket::new(n as f64, now)),
+                tokens: self
+                    .config
+                    .tokens_per_minute
+                    .map(|n| TokenBucket::new(n as f64, now)),
+            });
+        for bucket in buckets.requests.iter_mut().chain(buckets.tokens.iter_mut()) {
+            bucket.refill(now);
+        }
+        f(buckets)
+    }
+
+    /// 放行一个请求时扣掉一次请求额度；被拒绝时返回需要等多久
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        if !self.enabled() {
+            return Ok(());
+        }
+        self.with_buckets(client, |buckets| {
+            // token 桶只要没透支就放行，用量在请求结束后再扣
+            let token_wait = buckets
+                .tokens
+                .as_ref()
+                .filter(|b| b.available < 0.0)
+                .map_or(Duration::ZERO, |b| b.wait_for(0.0));
+            let request_wait = buckets
+                .requests
+                .as_ref()
+                .map_or(Duration::ZERO, |b| b.wait_for(1.0));
+            let wait = token_wait.max(request_wait);
+            if !wait.is_zero() {
+                return Err(wait);
+            }
+            if let Some(requests) = &mut buckets.requests {
+                requests.available -= 1.0;
+            }
+            Ok(())
+        })
+    }
+
+    /// 请求结束后按实际用量扣 token 额度
+    pub fn charge(&self, client: &str, tokens: usize) {
+        if self.config.tokens_per_minute.is_none() {
+            return;
+        }
+        self.with_buckets(client, |buckets| {
+            if let Some(bucket) = &mut buckets.tokens {
+                bucket.available -= tokens as f64;
+            }
+        });
+    }
+}
+
+/// 限流的路径前缀（`/api/v1` 下的同名路径也一样）
+const LIMITED_PREFIXES: &[&str] = &["/infer", "/v1/", "/sessions", "/assistant", "/rag"];
+
+/// 被拒绝的请求改写到这个不存在的路径，不会进入任何 handler
+const LIMITED_PATH: &str = "/__rate_limited";
+
+pub fn is_limited(path: &str) -> bool {
+    let path = path.strip_prefix(API_V1).unwrap_or(path);
+    LIMITED_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+}
+
+/// 限流按哪个客户端记账：带了 API key 时按 key，否则按 TCP 对端地址。
+/// 通过了限流的请求记在 request-local cache 里，`ApiKey` 从这里带进推理请求
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateClient(pub String);
+
+impl RateClient {
+    fn of(req: &Request<'_>) -> RateClient {
+        RateClient(match (key_from_headers(req), req.remote()) {
+            (Some(key), _) => format!("key:{key}"),
+            (None, Some(addr)) => format!("ip:{}", addr.ip()),
+            (None, None) => "ip:unknown".to_string(),
+        })
+    }
+
+    /// 这个请求通过限流时的客户端
+    pub fn admitted(req: &Request<'_>) -> Option<RateClient> {
+        req.local_cache(|| None::<RateClient>).clone()
+    }
+}
+
+/// 被拒绝时记在 request-local cache 里，响应阶段替换成 429
+#[derive(Debug, Clone, Copy)]
+struct Limited(Duration);
+
+/// 配置了 `rate_limit` 时按客户端限制推理请求
+pub struct RateLimit;
+
+#[rocket::async_trait]
+impl Fairing for RateLimit {
+    fn info(&self) -> Info {
+        Info {
+            name: "Per-client rate limiting",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let Some(state) = req.rocket().state::<Arc<AppState>>() else {
+            return;
+        };
+        if !state.rate_limits.enabled() || !is_limited(req.uri().path().as_str()) {
+            return;
+        }
+        let client = RateClient::of(req);
+        match state.rate_limits.check(&client.0) {
+            Ok(()) => {
+                req.local_cache(|| Some(client));
+            }
+            Err(wait) => {
+                req.local_cache(|| Some(Limited(wait)));
+                req.set_method(Method::Get);
+                req.set_uri(Origin::parse(LIMITED_PATH).expect("valid path"));
+            }
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(Limited(wait)) = *req.local_cache(|| None::<Limited>) else {
+            return;
+        };
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        let error = api_error(
+            Status::TooManyRequests,
+            "rate_limited",
+            format!("rate limit exceeded, retry in {retry_after}s"),
+        );
+        let body = serde_json::to_string(&error.into_response()).unwrap_or_default();
+        *res = Response::new();
+        res.set_status(Status::TooManyRequests);
+        res.set_header(ContentType::JSON);
+        res.set_header(Header::new("Retry-After", retry_after.to_string()));
+        res.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests: Option<u32>, tokens: Option<u64>) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            requests_per_minute: requests,
+            tokens_per_minute: tokens,
+        })
+    }
+
+    #[test]
+    fn request_bucket_empties_and_clients_are_separate() {
+        let limits = limiter(Some(2), None);
+        assert!(limits.check("key:a").is_ok());
+        assert!(limits.check("key:a").is_ok());
+        let wait = limits.check("key:a").unwrap_err();
+        // 每 30 秒补一个
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+        assert!(limits.check("ip:10.0.0.1").is_ok());
+    }
+
+    #[test]
+    fn overdrawn_token_bucket_blocks_until_refilled() {
+        let limits = limiter(None, Some(600));
+        assert!(limits.check("key:a").is_ok());
+        limits.charge("key:a", 700);
+        // 透支 100，每秒补 10
+        let wait = limits.check("key:a").unwrap_err();
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10));
+        assert!(limiter(None, None).check("key:a").is_ok());
+    }
+
+    #[test]
+    fn only_inference_paths_are_limited() {
+        assert!(is_limited("/infer"));
+        assert!(is_limited("/api/v1/infer_stream"));
+        assert!(is_limited("/v1/chat/completions"));
+        assert!(!is_limited("/health"));
+        assert!(!is_limited("/load"));
+    }
+}
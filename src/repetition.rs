@@ -0,0 +1,85 @@
+//! 解码过程中的重复检测：同一个 n-gram 连续出现 K 次就认为生成已经退化，
+//! 提前停止（`finish_reason: "repetition"`），不再白白跑到 max_tokens。
+
+/// 检测参数
+#[derive(Debug, Clone)]
+pub struct RepetitionConfig {
+    /// 检查 1..=max_ngram 长度的 n-gram
+    pub max_ngram: usize,
+    /// 至少连续重复的次数 K
+    pub repeats: usize,
+    /// 重复部分至少覆盖的 token 数，避免短 n-gram 误判（例如连续几个换行）
+    pub min_span: usize,
+}
+
+impl Default for RepetitionConfig {
+    fn default() -> Self {
+        Self {
+            max_ngram: 8,
+            repeats: 4,
+            min_span: 16,
+        }
+    }
+}
+
+/// 逐个喂入生成的 token（或词），返回是否已经陷入重复
+#[derive(Debug, Clone)]
+pub struct RepetitionDetector<T> {
+    config: RepetitionConfig,
+    history: Vec<T>,
+}
+
+impl<T: PartialEq> RepetitionDetector<T> {
+    pub fn new(config: RepetitionConfig) -> Self {
+        Self {
+            config,
+            history: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, token: T) -> bool {
+        self.history.push(token);
+        (1..=self.config.max_ngram).any(|n| self.repeats_tail(n))
+    }
+
+    /// 结尾是否是长度为 n 的 n-gram 连续重复了足够多次
+    fn repeats_tail(&self, n: usize) -> bool {
+        let times = self.config.repeats.max(self.config.min_span.div_ceil(n));
+        let span = n * times;
+        if self.history.len() < span {
+            return false;
+        }
+        let tail = &self.history[self.history.len() - span..];
+        let gram = &tail[span - n..];
+        tail.chunks(n).all(|chunk| chunk == gram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_hit(tokens: &[u32]) -> Option<usize> {
+        let mut detector = RepetitionDetector::new(RepetitionConfig::default());
+        tokens.iter().position(|&t| detector.push(t))
+    }
+
+    #[test]
+    fn detects_repeated_ngrams() {
+        // 4-gram 重复 4 次 = 16 个 token
+        let looped: Vec<u32> = [1, 2, 3, 4].repeat(6);
+        assert_eq!(first_hit(&looped), Some(15));
+
+        // 单个 token 需要覆盖 min_span 才算
+        let same = vec![7; 20];
+        assert_eq!(first_hit(&same), Some(15));
+    }
+
+    #[test]
+    fn ignores_normal_text() {
+        let tokens: Vec<u32> = (0..64).collect();
+        assert_eq!(first_hit(&tokens), None);
+        let short_loop: Vec<u32> = [1, 2].repeat(5);
+        assert_eq!(first_hit(&short_loop), None);
+    }
+}
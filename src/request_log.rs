@@ -0,0 +1,379 @@
+//! 请求日志和离线导出：每个推理请求完成时记一行（时间、模型、类型、成败、延迟、token 数），
+//! 内存里保留最近 `MAX_ENTRIES` 条。
+//!
+//! `GET /admin/export?from=&to=&format=&kind=` 把一段时间内的请求明细（`kind=requests`）或
+//! 按模型、按 `bucket` 秒聚合的指标（`kind=metrics`）导出成 JSON Lines 或 CSV，
+//! 直接交给 pandas / 表格软件分析，不用另外搭 Prometheus + Grafana。
+//! 只记 prompt 的字节数，不记原文；请求带的 `tags` 原样记下（CSV 里是 `app=notes,feature=summarize`）。
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rocket::http::{ContentType, Status};
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::api::{api_error, ApiError};
+use crate::app_state::AppState;
+use crate::perf_history::{now_ms, Percentiles};
+use crate::tags::{self, RequestTags};
+
+/// 内存里最多保留的请求数
+const MAX_ENTRIES: usize = 50_000;
+/// `kind=metrics` 默认的聚合粒度（秒）
+const DEFAULT_BUCKET_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestKind {
+    Collect,
+    Stream,
+    SharedPrefix,
+    Continuation,
+}
+
+impl RequestKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Collect => "collect",
+            Self::Stream => "stream",
+            Self::SharedPrefix => "shared_prefix",
+            Self::Continuation => "continuation",
+        }
+    }
+}
+
+/// 一个请求
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RequestLogEntry {
+    /// 完成时间（unix 毫秒）
+    pub at_ms: u64,
+    pub model: String,
+    pub kind: RequestKind,
+    pub ok: bool,
+    /// 端到端延迟（含排队）
+    pub latency_ms: u64,
+    pub prompt_bytes: usize,
+    /// 生成的 token 数，文本输出按词近似；失败时为 0
+    pub tokens: usize,
+    pub error: Option<String>,
+    /// 请求带的标签
+    #[serde(default, skip_serializing_if = "RequestTags::is_empty")]
+    pub tags: RequestTags,
+}
+
+/// 一个模型在一个时间段里的聚合指标
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MetricsRow {
+    /// 时间段起点（unix 秒）
+    pub bucket_start: u64,
+    pub model: String,
+    pub requests: usize,
+    pub errors: usize,
+    pub latency_p50_ms: Option<f64>,
+    pub latency_p95_ms: Option<f64>,
+    pub tokens: usize,
+    /// 成功请求的 tokens/s 中位数
+    pub tokens_per_second_p50: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct RequestLog {
+    entries: Mutex<VecDeque<RequestLogEntry>>,
+}
+
+impl RequestLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记一个刚结束的请求
+    pub fn record(
+        &self,
+        model: &str,
+        kind: RequestKind,
+        latency: Duration,
+        prompt_bytes: usize,
+        tags: &RequestTags,
+        result: Result<usize, String>,
+    ) {
+        let (tokens, error) = match result {
+            Ok(tokens) => (tokens, None),
+            Err(e) => (0, Some(e)),
+        };
+        self.push(RequestLogEntry {
+            at_ms: now_ms(),
+            model: model.to_string(),
+            kind,
+            ok: error.is_none(),
+            latency_ms: latency.as_millis() as u64,
+            prompt_bytes,
+            tokens,
+            error,
+            tags: tags.clone(),
+        });
+    }
+
+    pub fn push(&self, entry: RequestLogEntry) {
+        let mut entries = self.entries.lock();
+        if entries.len() == MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// `[from_ms, to_ms)` 内完成的请求，按时间排序
+    pub fn range(&self, from_ms: u64, to_ms: u64) -> Vec<RequestLogEntry> {
+        let mut found: Vec<_> = self
+            .entries
+            .lock()
+            .iter()
+            .filter(|e| (from_ms..to_ms).contains(&e.at_ms))
+            .cloned()
+            .collect();
+        found.sort_by_key(|e| e.at_ms);
+        found
+    }
+}
+
+/// 按（时间段，模型）聚合
+pub fn aggregate(entries: &[RequestLogEntry], bucket_secs: u64) -> Vec<MetricsRow> {
+    let mut groups: BTreeMap<(u64, &str), Vec<&RequestLogEntry>> = BTreeMap::new();
+    for entry in entries {
+        let secs = entry.at_ms / 1000;
+        let bucket = secs - secs % bucket_secs;
+        groups
+            .entry((bucket, &entry.model))
+            .or_default()
+            .push(entry);
+    }
+    groups
+        .into_iter()
+        .map(|((bucket_start, model), entries)| {
+            let latency = Percentiles::of(entries.iter().map(|e| e.latency_ms as f64).collect());
+            let speed = Percentiles::of(
+                entries
+                    .iter()
+                    .filter(|e| e.ok && e.latency_ms > 0)
+                    .map(|e| e.tokens as f64 * 1000.0 / e.latency_ms as f64)
+                    .collect(),
+            );
+            MetricsRow {
+                bucket_start,
+                model: model.to_string(),
+                requests: entries.len(),
+                errors: entries.iter().filter(|e| !e.ok).count(),
+                latency_p50_ms: latency.map(|p| p.p50),
+                latency_p95_ms: latency.map(|p| p.p95),
+                tokens: entries.iter().map(|e| e.tokens).sum(),
+                tokens_per_second_p50: speed.map(|p| p.p50),
+            }
+        })
+        .collect()
+}
+
+/// 含逗号、引号或换行的字段加引号，引号写两遍
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_row(out: &mut String, fields: &[String]) {
+    let fields: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+    out.push_str(&fields.join(","));
+    out.push('\n');
+}
+
+fn opt<T: ToString>(value: Option<T>) -> String {
+    value.map_or_else(String::new, |v| v.to_string())
+}
+
+fn requests_csv(entries: &[RequestLogEntry]) -> String {
+    let mut out = String::new();
+    let header = [
+        "at_ms",
+        "model",
+        "kind",
+        "ok",
+        "latency_ms",
+        "prompt_bytes",
+        "tokens",
+        "error",
+        "tags",
+    ];
+    csv_row(&mut out, &header.map(String::from));
+    for e in entries {
+        csv_row(
+            &mut out,
+            &[
+                e.at_ms.to_string(),
+                e.model.clone(),
+                e.kind.as_str().to_string(),
+                e.ok.to_string(),
+                e.latency_ms.to_string(),
+                e.prompt_bytes.to_string(),
+                e.tokens.to_string(),
+                opt(e.error.as_deref()),
+                tags::describe(&e.tags),
+            ],
+        );
+    }
+    out
+}
+
+fn metrics_csv(rows: &[MetricsRow]) -> String {
+    let mut out = String::new();
+    let header = [
+        "bucket_start",
+        "model",
+        "requests",
+        "errors",
+        "latency_p50_ms",
+        "latency_p95_ms",
+        "tokens",
+        "tokens_per_second_p50",
+    ];
+    csv_row(&mut out, &header.map(String::from));
+    for r in rows {
+        csv_row(
+            &mut out,
+            &[
+                r.bucket_start.to_string(),
+                r.model.clone(),
+                r.requests.to_string(),
+                r.errors.to_string(),
+                opt(r.latency_p50_ms),
+                opt(r.latency_p95_ms),
+                r.tokens.to_string(),
+                opt(r.tokens_per_second_p50),
+            ],
+        );
+    }
+    out
+}
+
+fn jsonl<T: Serialize>(rows: &[T]) -> String {
+    rows.iter()
+        .filter_map(|row| serde_json::to_string(row).ok())
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// 导出请求日志或聚合指标：GET /admin/export?from=&to=&format=jsonl|csv&kind=requests|metrics&bucket=
+///
+/// `from` / `to` 是 unix 秒（左闭右开），默认从最早的记录到现在；`bucket` 只对 `kind=metrics` 有效
+#[utoipa::path(
+    tag = "admin",
+    params(
+        ("from" = Option<u64>, Query, description = "start (unix seconds, inclusive)"),
+        ("to" = Option<u64>, Query, description = "end (unix seconds, exclusive)"),
+        ("format" = Option<String>, Query, description = "`jsonl` (default) or `csv`"),
+        ("kind" = Option<String>, Query, description = "`requests` (default) or `metrics`"),
+        ("bucket" = Option<u64>, Query, description = "aggregation interval in seconds for `kind=metrics` (default 3600)")
+    ),
+    responses(
+        (status = 200, description = "one RequestLogEntry or MetricsRow per line", body = String, content_type = "application/x-ndjson"),
+        (status = 400, description = "invalid range, format, kind or bucket", body = ErrorResponse)
+    )
+)]
+#[get("/admin/export?<from>&<to>&<format>&<kind>&<bucket>")]
+pub async fn export(
+    state: &State<Arc<AppState>>,
+    from: Option<u64>,
+    to: Option<u64>,
+    format: Option<&str>,
+    kind: Option<&str>,
+    bucket: Option<u64>,
+) -> Result<(ContentType, String), ApiError> {
+    let invalid = |message: String| api_error(Status::BadRequest, "invalid_input", message);
+    let from_ms = from.unwrap_or(0).saturating_mul(1000);
+    let to_ms = to.map_or(u64::MAX, |t| t.saturating_mul(1000));
+    if from_ms > to_ms {
+        return Err(invalid("`from` must not be after `to`".to_string()));
+    }
+    let csv = match format.unwrap_or("jsonl") {
+        "jsonl" => false,
+        "csv" => true,
+        other => {
+            return Err(invalid(format!(
+                "unknown format `{other}` (expected jsonl or csv)"
+            )))
+        }
+    };
+    let bucket = bucket.unwrap_or(DEFAULT_BUCKET_SECS);
+    if bucket == 0 {
+        return Err(invalid("`bucket` must be positive".to_string()));
+    }
+
+    let entries = state.request_log.range(from_ms, to_ms);
+    let body = match (kind.unwrap_or("requests"), csv) {
+        ("requests", false) => jsonl(&entries),
+        ("requests", true) => requests_csv(&entries),
+        ("metrics", false) => jsonl(&aggregate(&entries, bucket)),
+        ("metrics", true) => metrics_csv(&aggregate(&entries, bucket)),
+        (other, _) => {
+            return Err(invalid(format!(
+                "unknown kind `{other}` (expected requests or metrics)"
+            )))
+        }
+    };
+    let content_type = match csv {
+        true => ContentType::CSV,
+        false => ContentType::new("application", "x-ndjson"),
+    };
+    Ok((content_type, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(at_ms: u64, model: &str, latency_ms: u64, ok: bool) -> RequestLogEntry {
+        RequestLogEntry {
+            at_ms,
+            model: model.to_string(),
+            kind: RequestKind::Collect,
+            ok,
+            latency_ms,
+            prompt_bytes: 5,
+            tokens: if ok { 10 } else { 0 },
+            error: (!ok).then(|| "boom, \"bad\"".to_string()),
+            tags: RequestTags::new(),
+        }
+    }
+
+    #[test]
+    fn metrics_are_grouped_by_bucket_and_model() {
+        let entries = [
+            entry(1_000, "a", 100, true),
+            entry(2_000, "a", 300, false),
+            entry(3_000, "b", 500, true),
+            entry(61_000, "a", 1000, true),
+        ];
+        let rows = aggregate(&entries, 60);
+        let keys: Vec<_> = rows
+            .iter()
+            .map(|r| (r.bucket_start, r.model.as_str(), r.requests, r.errors))
+            .collect();
+        assert_eq!(keys, [(0, "a", 2, 1), (0, "b", 1, 0), (60, "a", 1, 0)]);
+        assert_eq!(rows[0].latency_p95_ms, Some(300.0));
+        assert_eq!(rows[0].tokens_per_second_p50, Some(100.0));
+    }
+
+    #[test]
+    fn csv_quotes_awkward_fields() {
+        let csv = requests_csv(&[entry(1, "a", 1, false)]);
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("at_ms,model,kind,ok"));
+        assert_eq!(
+            lines.next().unwrap(),
+            "1,a,collect,false,1,5,0,\"boom, \"\"bad\"\"\","
+        );
+    }
+}
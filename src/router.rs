@@ -0,0 +1,232 @@
+//! 虚拟 router 模型：请求的 model_name 是 router 时，按规则检查 prompt
+//! （检测到的语言或正则），转发到指定的真实模型，例如“中文用 qwen，其余用 mistral”。
+//!
+//! 规则按顺序匹配，第一条命中的生效；都不命中时使用 `default`。
+//!
+//! 另外保留了一个内置的虚拟模型名 `auto`：在已加载的模型里按 `pick_auto` 打分选一个，
+//! 客户端不需要知道当前加载了什么。
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::model_registry::Modality;
+
+/// 保留的虚拟模型名，不能用作模型或 router 的名字
+pub const AUTO_MODEL: &str = "auto";
+
+/// `auto` 选模型时看到的一个已加载模型
+#[derive(Debug, Clone)]
+pub struct AutoCandidate {
+    pub name: String,
+    pub context_window: usize,
+    pub modalities: Vec<Modality>,
+    /// 未完成的请求数
+    pub queue_depth: usize,
+}
+
+/// `auto` 的打分策略：先排除上下文不够或不支持所需输入类型的模型，
+/// 再选排队最少的；一样时选上下文更大的，最后按名字保证结果稳定
+pub fn pick_auto(
+    candidates: &[AutoCandidate],
+    required_tokens: usize,
+    modality: Modality,
+) -> Option<&str> {
+    candidates
+        .iter()
+        .filter(|c| c.context_window >= required_tokens && c.modalities.contains(&modality))
+        .min_by(|a, b| {
+            a.queue_depth
+                .cmp(&b.queue_depth)
+                .then(b.context_window.cmp(&a.context_window))
+                .then(a.name.cmp(&b.name))
+        })
+        .map(|c| c.name.as_str())
+}
+
+/// 规则的匹配条件（配置格式：`{ language = "zh" }` 或 `{ regex = "(?i)sql" }`）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteCondition {
+    /// `detect_language` 返回的语言代码
+    Language(String),
+    Regex(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct RoutingRule {
+    pub when: RouteCondition,
+    pub model: String,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Language(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn matches(&self, prompt: &str, language: &str) -> bool {
+        match self {
+            Matcher::Language(lang) => lang.eq_ignore_ascii_case(language),
+            Matcher::Regex(re) => re.is_match(prompt),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VirtualRouter {
+    pub name: String,
+    pub rules: Vec<RoutingRule>,
+    pub default: String,
+    matchers: Vec<Matcher>,
+}
+
+impl VirtualRouter {
+    /// 正则在这里编译，配置错误在注册时就报出来
+    pub fn new(name: &str, rules: Vec<RoutingRule>, default: &str) -> anyhow::Result<Self> {
+        let matchers = rules
+            .iter()
+            .map(|rule| match &rule.when {
+                RouteCondition::Language(lang) => Ok(Matcher::Language(lang.clone())),
+                RouteCondition::Regex(pattern) => {
+                    Regex::new(pattern).map(Matcher::Regex).map_err(|e| {
+                        anyhow::anyhow!("router `{name}`: invalid regex `{pattern}`: {e}")
+                    })
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            name: name.to_string(),
+            rules,
+            default: default.to_string(),
+            matchers,
+        })
+    }
+
+    /// 选出处理这个 prompt 的真实模型
+    pub fn route(&self, prompt: &str) -> &str {
+        let language = detect_language(prompt);
+        self.matchers
+            .iter()
+            .zip(&self.rules)
+            .find(|(matcher, _)| matcher.matches(prompt, language))
+            .map_or(self.default.as_str(), |(_, rule)| rule.model.as_str())
+    }
+
+    /// 所有可能的目标模型（规则 + default）
+    pub fn targets(&self) -> impl Iterator<Item = &str> {
+        self.rules
+            .iter()
+            .map(|r| r.model.as_str())
+            .chain(std::iter::once(self.default.as_str()))
+    }
+}
+
+/// 按文字系统粗略判断语言：zh / ja / ko / ru / ar / en，无法判断时返回 "unknown"。
+/// 日文常混用汉字，所以只要出现假名就算 ja
+pub fn detect_language(text: &str) -> &'static str {
+    let (mut han, mut kana, mut hangul, mut cyrillic, mut arabic, mut latin) = (0, 0, 0, 0, 0, 0);
+    for c in text.chars() {
+        match c as u32 {
+            0x4E00..=0x9FFF | 0x3400..=0x4DBF => han += 1,
+            0x3040..=0x30FF => kana += 1,
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => hangul += 1,
+            0x0400..=0x04FF => cyrillic += 1,
+            0x0600..=0x06FF => arabic += 1,
+            _ if c.is_ascii_alphabetic() => latin += 1,
+            _ => {}
+        }
+    }
+
+    if kana > 0 {
+        return "ja";
+    }
+    let counts = [
+        ("zh", han),
+        ("ko", hangul),
+        ("ru", cyrillic),
+        ("ar", arabic),
+        ("en", latin),
+    ];
+    // 汉字信息密度高，一个字抵得上几个拉丁字母
+    counts
+        .iter()
+        .map(|&(lang, n)| (lang, if lang == "en" { n } else { n * 3 }))
+        .filter(|&(_, n)| n > 0)
+        .max_by_key(|&(_, n)| n)
+        .map_or("unknown", |(lang, _)| lang)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> VirtualRouter {
+        VirtualRouter::new(
+            "auto",
+            vec![
+                RoutingRule {
+                    when: RouteCondition::Language("zh".to_string()),
+                    model: "qwen".to_string(),
+                },
+                RoutingRule {
+                    when: RouteCondition::Regex(r"(?i)\bselect\b.+\bfrom\b".to_string()),
+                    model: "coder".to_string(),
+                },
+            ],
+            "mistral",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn detects_scripts() {
+        assert_eq!(detect_language("今天天气怎么样？"), "zh");
+        assert_eq!(detect_language("請用 Rust 寫一個 hello world"), "zh");
+        assert_eq!(detect_language("今日はいい天気ですね"), "ja");
+        assert_eq!(detect_language("안녕하세요"), "ko");
+        assert_eq!(detect_language("Привет, как дела?"), "ru");
+        assert_eq!(detect_language("How are you?"), "en");
+        assert_eq!(detect_language("1234 !!"), "unknown");
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let router = router();
+        assert_eq!(router.route("帮我写一首诗"), "qwen");
+        assert_eq!(router.route("SELECT name FROM users"), "coder");
+        assert_eq!(router.route("write me a poem"), "mistral");
+    }
+
+    #[test]
+    fn auto_prefers_idle_models_that_fit() {
+        let candidate = |name: &str, context_window, queue_depth| AutoCandidate {
+            name: name.to_string(),
+            context_window,
+            modalities: vec![Modality::Text],
+            queue_depth,
+        };
+        let candidates = vec![
+            candidate("small", 2048, 0),
+            candidate("big", 8192, 3),
+            candidate("big-idle", 8192, 1),
+        ];
+        assert_eq!(pick_auto(&candidates, 1000, Modality::Text), Some("small"));
+        assert_eq!(
+            pick_auto(&candidates, 4000, Modality::Text),
+            Some("big-idle")
+        );
+        assert_eq!(pick_auto(&candidates, 10_000, Modality::Text), None);
+        assert_eq!(pick_auto(&candidates, 10, Modality::Vision), None);
+    }
+
+    #[test]
+    fn invalid_regex_is_rejected() {
+        let rules = vec![RoutingRule {
+            when: RouteCondition::Regex("(".to_string()),
+            model: "x".to_string(),
+        }];
+        assert!(VirtualRouter::new("bad", rules, "y").is_err());
+    }
+}
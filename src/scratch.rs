@@ -0,0 +1,150 @@
+//! 临时（scratch）加载：实验用的模型带一个租约，到期或创建者离开时自动卸载，
+//! 免得共享机器上的内存被忘记卸载的模型一直占着。
+//!
+//! - 硬 TTL：到期一定卸载，不管有没有请求在跑
+//! - 创建者：session（`DELETE /sessions/<id>` 时释放）或 API key（`DELETE /scratch` 时释放）
+//! - 用普通方式再加载一次会把它转成常驻模型
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// 没指定 ttl 时的默认租期
+pub const DEFAULT_SCRATCH_TTL: Duration = Duration::from_secs(60 * 60);
+/// ttl 上限
+pub const MAX_SCRATCH_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// `POST /load` 里的 scratch 选项
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ScratchOptions {
+    /// 硬 TTL（秒），默认 1 小时，最多 24 小时
+    pub ttl_secs: Option<u64>,
+    /// 绑定到某个 session；不填则绑定到请求的 API key
+    pub session_id: Option<String>,
+}
+
+impl ScratchOptions {
+    pub fn ttl(&self) -> Duration {
+        self.ttl_secs
+            .map_or(DEFAULT_SCRATCH_TTL, Duration::from_secs)
+            .min(MAX_SCRATCH_TTL)
+    }
+}
+
+/// 谁创建的 scratch 模型
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ScratchOwner {
+    Session(String),
+    ApiKey(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ScratchLease {
+    /// 匿名请求创建的只靠 TTL 回收
+    pub owner: Option<ScratchOwner>,
+    pub expires_at: Instant,
+    /// 每次加载一个新 id，防止旧的定时器卸掉重新加载的模型
+    id: u64,
+}
+
+impl ScratchLease {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn expires_in(&self) -> Duration {
+        self.expires_at.saturating_duration_since(Instant::now())
+    }
+}
+
+/// model_name -> 租约
+#[derive(Default)]
+pub struct ScratchRegistry {
+    leases: Mutex<HashMap<String, ScratchLease>>,
+    next_id: AtomicU64,
+}
+
+impl ScratchRegistry {
+    pub fn insert(&self, model_name: &str, owner: Option<ScratchOwner>, ttl: Duration) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.leases.lock().insert(
+            model_name.to_string(),
+            ScratchLease {
+                owner,
+                expires_at: Instant::now() + ttl,
+                id,
+            },
+        );
+        id
+    }
+
+    pub fn get(&self, model_name: &str) -> Option<ScratchLease> {
+        self.leases.lock().get(model_name).cloned()
+    }
+
+    pub fn remove(&self, model_name: &str) -> Option<ScratchLease> {
+        self.leases.lock().remove(model_name)
+    }
+
+    /// 只有租约还是 `id` 那一次时才移除（定时器用）
+    pub fn remove_if(&self, model_name: &str, id: u64) -> bool {
+        let mut leases = self.leases.lock();
+        if leases.get(model_name).is_some_and(|l| l.id == id) {
+            leases.remove(model_name);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 移除某个创建者的全部租约，返回对应的模型名
+    pub fn remove_owned_by(&self, owner: &ScratchOwner) -> Vec<String> {
+        let mut leases = self.leases.lock();
+        let mut owned: Vec<String> = leases
+            .iter()
+            .filter(|(_, l)| l.owner.as_ref() == Some(owner))
+            .map(|(name, _)| name.clone())
+            .collect();
+        owned.sort();
+        for name in &owned {
+            leases.remove(name);
+        }
+        owned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leases_are_released_by_owner_and_id() {
+        let scratch = ScratchRegistry::default();
+        let owner = ScratchOwner::Session("session-1".to_string());
+        let first = scratch.insert("a", Some(owner.clone()), DEFAULT_SCRATCH_TTL);
+        scratch.insert("b", None, DEFAULT_SCRATCH_TTL);
+
+        // 重新加载后旧 id 不再生效
+        let second = scratch.insert("a", Some(owner.clone()), DEFAULT_SCRATCH_TTL);
+        assert!(!scratch.remove_if("a", first));
+
+        assert_eq!(scratch.remove_owned_by(&owner), vec!["a".to_string()]);
+        assert!(!scratch.remove_if("a", second));
+        assert!(scratch.get("b").is_some());
+    }
+
+    #[test]
+    fn ttl_is_capped() {
+        let options = ScratchOptions {
+            ttl_secs: Some(u64::MAX),
+            session_id: None,
+        };
+        assert_eq!(options.ttl(), MAX_SCRATCH_TTL);
+        assert_eq!(ScratchOptions::default().ttl(), DEFAULT_SCRATCH_TTL);
+    }
+}
@@ -0,0 +1,185 @@
+//! 加载后的自检（`[default.self_test] enabled = true`）：引擎建好之后、状态变成 Loaded 之前，
+//! 在第一个 placement 上跑几条内置 prompt，检查输出非空、能在 `max_tokens` 以内输出结束符、
+//! 每条都在 `max_latency_ms` 以内。没通过时加载失败（不重试），模型停在 Error，
+//! 坏掉的量化文件在接到用户请求之前就能发现。结果挂在模型信息上（`GET /models` 的 `self_test`）。
+//!
+//! 只对文本生成模型做；embedding / 文生图模型跳过。
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rocket::tokio;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::engine::{FinishReason, InferenceEngine};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelfTestConfig {
+    pub enabled: bool,
+    /// 每条 prompt 的生成长度
+    pub max_tokens: usize,
+    /// 每条 prompt 允许的最长耗时
+    pub max_latency_ms: u64,
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_tokens: 32,
+            max_latency_ms: 30_000,
+        }
+    }
+}
+
+/// 内置的自检 prompt：(名字, prompt)
+const CASES: &[(&str, &str)] = &[
+    ("greeting", "Say hello."),
+    ("short_answer", "Reply with the single word: OK"),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SelfTestCheck {
+    /// `<case>/<检查项>`，例如 `greeting/non_empty`
+    pub name: String,
+    pub passed: bool,
+    /// 没通过时的说明
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SelfTestReport {
+    pub passed: bool,
+    /// 运行时间（Unix 秒）
+    pub ran_at: u64,
+    pub checks: Vec<SelfTestCheck>,
+}
+
+impl SelfTestReport {
+    fn new(checks: Vec<SelfTestCheck>) -> Self {
+        Self {
+            passed: checks.iter().all(|c| c.passed),
+            ran_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            checks,
+        }
+    }
+
+    /// 没通过的检查，拼成一行放进加载错误里
+    pub fn failures(&self) -> String {
+        self.checks
+            .iter()
+            .filter(|c| !c.passed)
+            .map(|c| match &c.detail {
+                Some(detail) => format!("{} ({detail})", c.name),
+                None => c.name.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn check(name: String, failure: Option<String>) -> SelfTestCheck {
+    SelfTestCheck {
+        name,
+        passed: failure.is_none(),
+        detail: failure,
+    }
+}
+
+pub async fn run(engine: &dyn InferenceEngine, config: &SelfTestConfig) -> SelfTestReport {
+    let max_latency = Duration::from_millis(config.max_latency_ms);
+    let mut checks = Vec::new();
+    for &(case, prompt) in CASES {
+        let name = |what: &str| format!("{case}/{what}");
+        let started = Instant::now();
+        let generation = engine.complete(prompt, config.max_tokens).await;
+        let elapsed = started.elapsed();
+        let generation = match generation {
+            Ok(generation) => generation,
+            Err(e) => {
+                checks.push(check(name("generate"), Some(e.to_string())));
+                continue;
+            }
+        };
+        checks.push(check(
+            name("non_empty"),
+            generation
+                .text
+                .trim()
+                .is_empty()
+                .then(|| "empty output".to_string()),
+        ));
+        checks.push(check(
+            name("eos"),
+            (generation.finish_reason != FinishReason::Stop).then(|| {
+                format!(
+                    "finished with {:?} instead of an end-of-sequence token",
+                    generation.finish_reason
+                )
+            }),
+        ));
+        checks.push(check(
+            name("latency"),
+            (elapsed > max_latency).then(|| {
+                format!(
+                    "took {} ms, limit {} ms",
+                    elapsed.as_millis(),
+                    config.max_latency_ms
+                )
+            }),
+        ));
+    }
+    SelfTestReport::new(checks)
+}
+
+/// 在单独的线程和 runtime 上跑 `run`：加载是同步的，调用方可能就在 async runtime 里
+pub fn run_blocking(engine: Arc<dyn InferenceEngine>, config: &SelfTestConfig) -> SelfTestReport {
+    let config = config.clone();
+    let result = std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map(|runtime| runtime.block_on(run(engine.as_ref(), &config)))
+    })
+    .join();
+    match result {
+        Ok(Ok(report)) => report,
+        Ok(Err(e)) => SelfTestReport::new(vec![check("runtime".to_string(), Some(e.to_string()))]),
+        Err(_) => SelfTestReport::new(vec![check(
+            "generate".to_string(),
+            Some("engine panicked".to_string()),
+        )]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::DummyEngine;
+
+    #[rocket::async_test]
+    async fn dummy_engine_passes_and_a_tight_latency_bound_fails() {
+        let engine = DummyEngine::new("dummy-a");
+        let report = run(engine.as_ref(), &SelfTestConfig::default()).await;
+        assert!(report.passed, "{report:?}");
+        assert_eq!(report.checks.len(), 3 * CASES.len());
+
+        // DummyEngine 每次生成要 50ms
+        let config = SelfTestConfig {
+            max_latency_ms: 10,
+            ..SelfTestConfig::default()
+        };
+        let report = run_blocking(engine, &config);
+        assert!(!report.passed);
+        assert!(
+            report.failures().starts_with("greeting/latency (took"),
+            "{}",
+            report.failures()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! session 的实时输出广播（`GET /sessions/<id>/stream`）
+//!
+//! 同一个 session 同时只有一次生成。每个 token 先写进缓冲再广播，
+//! 中途加入的订阅者先收到这次生成已经输出的部分，再接着收实时 token。
+
+use std::collections::HashMap;
+
+use parking_lot::Mutex;
+use rocket::tokio::sync::broadcast;
+use serde::Serialize;
+
+/// 每个订阅者最多缓存的事件数，慢订阅者会丢最旧的事件
+const LIVE_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LiveEvent {
+    /// 开始回答一条用户消息
+    Start {
+        message: String,
+    },
+    Token {
+        text: String,
+    },
+    /// 回复已写入历史
+    Done {
+        reply: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl LiveEvent {
+    /// SSE 的 `event:` 名
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            LiveEvent::Start { .. } => "start",
+            LiveEvent::Token { .. } => "token",
+            LiveEvent::Done { .. } => "done",
+            LiveEvent::Error { .. } => "error",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Channel {
+    sender: broadcast::Sender<LiveEvent>,
+    /// 正在进行的生成：用户消息和已经输出的 token
+    current: Option<(String, Vec<String>)>,
+}
+
+impl Channel {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(LIVE_BUFFER);
+        Self {
+            sender,
+            current: None,
+        }
+    }
+}
+
+/// 订阅时正在进行的生成先以 `backlog` 补发，之后从 `receiver` 收实时事件
+pub struct LiveSubscription {
+    pub backlog: Vec<LiveEvent>,
+    pub receiver: broadcast::Receiver<LiveEvent>,
+}
+
+#[derive(Debug, Default)]
+pub struct SessionStreams {
+    channels: Mutex<HashMap<String, Channel>>,
+}
+
+impl SessionStreams {
+    pub fn subscribe(&self, id: &str) -> LiveSubscription {
+        let mut channels = self.channels.lock();
+        let channel = channels.entry(id.to_string()).or_insert_with(Channel::new);
+        let backlog = match &channel.current {
+            Some((message, tokens)) => std::iter::once(LiveEvent::Start {
+                message: message.clone(),
+            })
+            .chain(
+                tokens
+                    .iter()
+                    .map(|text| LiveEvent::Token { text: text.clone() }),
+            )
+            .collect(),
+            None => Vec::new(),
+        };
+        LiveSubscription {
+            backlog,
+            receiver: channel.sender.subscribe(),
+        }
+    }
+
+    /// 开始一次生成；上一次还没结束时返回 false
+    pub fn begin(&self, id: &str, message: &str) -> bool {
+        let mut channels = self.channels.lock();
+        let channel = channels.entry(id.to_string()).or_insert_with(Channel::new);
+        if channel.current.is_some() {
+            return false;
+        }
+        channel.current = Some((message.to_string(), Vec::new()));
+        let _ = channel.sender.send(LiveEvent::Start {
+            message: message.to_string(),
+        });
+        true
+    }
+
+    pub fn token(&self, id: &str, text: &str) {
+        let mut channels = self.channels.lock();
+        let Some(channel) = channels.get_mut(id) else {
+            return;
+        };
+        if let Some((_, tokens)) = &mut channel.current {
+            tokens.push(text.to_string());
+        }
+        let _ = channel.sender.send(LiveEvent::Token {
+            text: text.to_string(),
+        });
+    }
+
+    /// 以 `Done` / `Error` 结束当前这次生成
+    pub fn finish(&self, id: &str, event: LiveEvent) {
+        let mut channels = self.channels.lock();
+        if let Some(channel) = channels.get_mut(id) {
+            channel.current = None;
+            let _ = channel.sender.send(event);
+        }
+    }
+
+    /// session 关闭：丢掉 sender，所有订阅者的流随之结束
+    pub fn close(&self, id: &str) {
+        self.channels.lock().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn late_subscriber_gets_partial_output_first() {
+        let streams = SessionStreams::default();
+        let mut early = streams.subscribe("s");
+        assert!(early.backlog.is_empty());
+
+        assert!(streams.begin("s", "hi"));
+        assert!(!streams.begin("s", "again"));
+        streams.token("s", "hello");
+        let mut late = streams.subscribe("s");
+        streams.token("s", "world");
+        streams.finish(
+            "s",
+            LiveEvent::Done {
+                reply: "hello world".to_string(),
+            },
+        );
+
+        let token = |text: &str| LiveEvent::Token {
+            text: text.to_string(),
+        };
+        let start = LiveEvent::Start {
+            message: "hi".to_string(),
+        };
+        assert_eq!(late.backlog, vec![start.clone(), token("hello")]);
+        assert_eq!(late.receiver.try_recv().unwrap(), token("world"));
+        assert_eq!(early.receiver.try_recv().unwrap(), start);
+        assert_eq!(early.receiver.try_recv().unwrap(), token("hello"));
+
+        // 结束后新的订阅者没有 backlog，可以开始下一次生成
+        assert!(streams.subscribe("s").backlog.is_empty());
+        assert!(streams.begin("s", "next"));
+    }
+}
@@ -0,0 +1,98 @@
+//! 主模型的热备实例（`[default.standby] model = "mistral-7b"`, `device = "cuda:1"`）：
+//! 主模型加载成功后，在 `device` 上再建一个同样的 engine 实例，不参与负载均衡。
+//!
+//! 主模型重新加载（换权重）或者加载失败停在 Error 时，请求由热备实例接着服务，而不是返回 409；
+//! 非流式请求在主实例生成出错时也会在热备上重试一次。主模型重新加载成功后热备换成新权重，
+//! 失败时保留旧的。卸载主模型时热备一起释放。
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::app_state::EngineInstance;
+use crate::device::DeviceSpec;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StandbyConfig {
+    /// 要热备的主模型，None 表示不启用
+    pub model: Option<String>,
+    /// 热备实例放在哪个设备上（默认 cpu）
+    pub device: DeviceSpec,
+}
+
+/// 热备实例：只有配置了 `model` 且主模型加载过以后才有
+#[derive(Default)]
+pub struct Standby {
+    config: StandbyConfig,
+    instance: RwLock<Option<EngineInstance>>,
+}
+
+impl Standby {
+    pub fn new(config: StandbyConfig) -> Self {
+        Self {
+            config,
+            instance: RwLock::new(None),
+        }
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.config.model.as_deref()
+    }
+
+    pub fn device(&self) -> DeviceSpec {
+        self.config.device
+    }
+
+    /// 是不是给这个模型做热备
+    pub fn covers(&self, model_name: &str) -> bool {
+        self.model() == Some(model_name)
+    }
+
+    /// 这个模型的热备实例；请求指定了别的设备时不接管
+    pub fn get(&self, model_name: &str, device: Option<DeviceSpec>) -> Option<EngineInstance> {
+        if !self.covers(model_name) || device.is_some_and(|d| d != self.config.device) {
+            return None;
+        }
+        self.instance.read().clone()
+    }
+
+    pub fn is_warm(&self) -> bool {
+        self.instance.read().is_some()
+    }
+
+    /// 换成新建好的实例，旧实例上进行中的请求继续执行完
+    pub fn replace(&self, instance: EngineInstance) {
+        *self.instance.write() = Some(instance);
+    }
+
+    pub fn clear(&self) {
+        self.instance.write().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::DummyEngine;
+
+    #[test]
+    fn only_the_configured_model_on_a_matching_device_is_covered() {
+        let standby = Standby::new(StandbyConfig {
+            model: Some("dummy-a".to_string()),
+            device: DeviceSpec::Cuda(1),
+        });
+        assert!(standby.get("dummy-a", None).is_none());
+
+        standby.replace(EngineInstance::new(
+            DeviceSpec::Cuda(1),
+            DummyEngine::new("dummy-a"),
+        ));
+        assert!(standby.get("dummy-a", None).is_some());
+        assert!(standby.get("dummy-a", Some(DeviceSpec::Cuda(1))).is_some());
+        assert!(standby.get("dummy-a", Some(DeviceSpec::Cpu)).is_none());
+        assert!(standby.get("dummy-b", None).is_none());
+
+        standby.clear();
+        assert!(!standby.is_warm());
+    }
+}
@@ -0,0 +1,266 @@
+//! 解码时的停止条件：每采样一个 token 调用一次 `StopCriteria::should_stop`，返回 Some 就停下，
+//! 结果里的 `finish_reason` 用返回的原因。
+//!
+//! 内置结束符（`EosTokens`）、停止串（`StopStrings`）、时间上限（`MaxTime`）和重复检测（`RepetitionStop`）；
+//! `candle` 引擎默认用结束符 + 重复检测，`tiny` 只用结束符。嵌入本库的程序可以实现自己的 `StopCriteria`，
+//! 在引擎工厂里用 `CandleEngine::add_stop_criteria`（或 `TinyEngine` 的同名方法）挂到引擎上：
+//!
+//! ```ignore
+//! factories.register(EngineKind::CANDLE, |meta: &ModelMetadata, device| {
+//!     let engine = CandleEngine::new(meta, device)?;
+//!     engine.add_stop_criteria(Arc::new(|| Box::new(MaxTime(Duration::from_secs(30))) as _));
+//!     Ok(engine as Arc<dyn InferenceEngine>)
+//! });
+//! ```
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+
+use crate::engine::FinishReason;
+use crate::repetition::{RepetitionConfig, RepetitionDetector};
+
+/// 解码到某一步时的状态
+pub struct DecodeStep<'a> {
+    /// 到目前为止生成的 token，最后一个是刚采样出来的
+    pub tokens: &'a [u32],
+    /// 从开始解码（含 prefill）到现在
+    pub elapsed: Duration,
+    decode: &'a dyn Fn(&[u32]) -> String,
+}
+
+impl<'a> DecodeStep<'a> {
+    pub fn new(tokens: &'a [u32], elapsed: Duration, decode: &'a dyn Fn(&[u32]) -> String) -> Self {
+        Self {
+            tokens,
+            elapsed,
+            decode,
+        }
+    }
+
+    pub fn last(&self) -> Option<u32> {
+        self.tokens.last().copied()
+    }
+
+    /// 最后 `n` 个 token 解码成的文本
+    pub fn tail_text(&self, n: usize) -> String {
+        (self.decode)(&self.tokens[self.tokens.len().saturating_sub(n)..])
+    }
+}
+
+/// 一次生成的停止条件，可以带状态（每次生成由 `StopCriteriaFactory` 新建一份）
+pub trait StopCriteria: Send {
+    /// 每采样一个 token 调用一次；返回 Some 时停止生成
+    fn should_stop(&mut self, step: &DecodeStep<'_>) -> Option<FinishReason>;
+}
+
+/// 每次生成开始时新建一份停止条件
+pub type StopCriteriaFactory = Arc<dyn Fn() -> Box<dyn StopCriteria> + Send + Sync>;
+
+/// 采样到其中任何一个 token 时停止（`stop`）。引擎负责把结束符从输出里去掉
+#[derive(Debug, Clone)]
+pub struct EosTokens(pub HashSet<u32>);
+
+impl EosTokens {
+    pub fn new(ids: impl IntoIterator<Item = u32>) -> Self {
+        Self(ids.into_iter().collect())
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.0.contains(&id)
+    }
+}
+
+impl StopCriteria for EosTokens {
+    fn should_stop(&mut self, step: &DecodeStep<'_>) -> Option<FinishReason> {
+        step.last()
+            .filter(|&id| self.contains(id))
+            .map(|_| FinishReason::Stop)
+    }
+}
+
+/// 生成的文本里出现其中任何一个串时停止（`stop`），停止串本身留在输出里
+#[derive(Debug, Clone)]
+pub struct StopStrings {
+    stops: Vec<String>,
+    /// 只解码结尾这么多个 token 来找停止串，每个 token 至少一个字符
+    window: usize,
+}
+
+impl StopStrings {
+    pub fn new(stops: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        let stops: Vec<String> = stops
+            .into_iter()
+            .map(Into::into)
+            .filter(|s| !s.is_empty())
+            .collect();
+        let window = stops.iter().map(|s| s.chars().count()).max().unwrap_or(0) + 1;
+        Self { stops, window }
+    }
+}
+
+impl StopCriteria for StopStrings {
+    fn should_stop(&mut self, step: &DecodeStep<'_>) -> Option<FinishReason> {
+        if self.stops.is_empty() {
+            return None;
+        }
+        let tail = step.tail_text(self.window);
+        self.stops
+            .iter()
+            .any(|stop| tail.contains(stop.as_str()))
+            .then_some(FinishReason::Stop)
+    }
+}
+
+/// 解码超过这么久就停止（`length`），即使还没到 max_tokens
+#[derive(Debug, Clone, Copy)]
+pub struct MaxTime(pub Duration);
+
+impl StopCriteria for MaxTime {
+    fn should_stop(&mut self, step: &DecodeStep<'_>) -> Option<FinishReason> {
+        (step.elapsed >= self.0).then_some(FinishReason::Length)
+    }
+}
+
+/// 陷入重复时停止（`repetition`），见 `repetition`
+#[derive(Debug, Clone)]
+pub struct RepetitionStop(RepetitionDetector<u32>);
+
+impl RepetitionStop {
+    pub fn new(config: RepetitionConfig) -> Self {
+        Self(RepetitionDetector::new(config))
+    }
+}
+
+impl Default for RepetitionStop {
+    fn default() -> Self {
+        Self::new(RepetitionConfig::default())
+    }
+}
+
+impl StopCriteria for RepetitionStop {
+    fn should_stop(&mut self, step: &DecodeStep<'_>) -> Option<FinishReason> {
+        let id = step.last()?;
+        self.0.push(id).then_some(FinishReason::Repetition)
+    }
+}
+
+/// 一组停止条件，按顺序取第一个要求停止的原因。每一步都会问到每个条件，带状态的条件不会漏 token
+#[derive(Default)]
+pub struct StopSet(Vec<Box<dyn StopCriteria>>);
+
+impl StopSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, criteria: impl StopCriteria + 'static) -> Self {
+        self.0.push(Box::new(criteria));
+        self
+    }
+
+    pub fn push(&mut self, criteria: Box<dyn StopCriteria>) {
+        self.0.push(criteria);
+    }
+}
+
+impl StopCriteria for StopSet {
+    fn should_stop(&mut self, step: &DecodeStep<'_>) -> Option<FinishReason> {
+        self.0
+            .iter_mut()
+            .map(|criteria| criteria.should_stop(step))
+            .fold(None, |first, reason| first.or(reason))
+    }
+}
+
+/// 引擎上额外挂的停止条件（见 `CandleEngine::add_stop_criteria`），每次生成各建一份
+#[derive(Default)]
+pub struct CustomStops(RwLock<Vec<StopCriteriaFactory>>);
+
+impl CustomStops {
+    pub fn add(&self, factory: StopCriteriaFactory) {
+        self.0.write().push(factory);
+    }
+
+    /// 内置条件后面接上额外的条件
+    pub fn extend(&self, mut stops: StopSet) -> StopSet {
+        for factory in self.0.read().iter() {
+            stops.push(factory());
+        }
+        stops
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每个 token 解码成一个小写字母
+    fn letters(ids: &[u32]) -> String {
+        ids.iter().map(|&id| (b'a' + id as u8) as char).collect()
+    }
+
+    /// 逐个喂 token，返回第一次要求停止的位置和原因
+    fn first_stop(criteria: &mut dyn StopCriteria, tokens: &[u32]) -> Option<(usize, FinishReason)> {
+        (1..=tokens.len()).find_map(|n| {
+            let step = DecodeStep::new(&tokens[..n], Duration::ZERO, &letters);
+            criteria.should_stop(&step).map(|reason| (n - 1, reason))
+        })
+    }
+
+    #[test]
+    fn builtin_criteria_stop_at_the_right_token() {
+        let tokens = [7, 4, 11, 11, 14, 25];
+        assert_eq!(
+            first_stop(&mut EosTokens::new([25]), &tokens),
+            Some((5, FinishReason::Stop))
+        );
+        // "hello" 在第 5 个 token 上凑齐
+        assert_eq!(
+            first_stop(&mut StopStrings::new(["llo"]), &tokens),
+            Some((4, FinishReason::Stop))
+        );
+        assert_eq!(first_stop(&mut StopStrings::new(["xyz", ""]), &tokens), None);
+
+        let step = DecodeStep::new(&tokens, Duration::from_secs(2), &letters);
+        assert_eq!(
+            MaxTime(Duration::from_secs(1)).should_stop(&step),
+            Some(FinishReason::Length)
+        );
+        assert_eq!(MaxTime(Duration::from_secs(3)).should_stop(&step), None);
+    }
+
+    #[test]
+    fn stop_set_asks_every_criterion() {
+        let looped: Vec<u32> = [1, 2].repeat(8);
+        let mut stops = StopSet::new()
+            .with(EosTokens::new([9]))
+            .with(RepetitionStop::default());
+        assert_eq!(
+            first_stop(&mut stops, &looped),
+            Some((15, FinishReason::Repetition))
+        );
+    }
+
+    #[test]
+    fn custom_stops_are_built_per_generation() {
+        struct AfterThree(usize);
+        impl StopCriteria for AfterThree {
+            fn should_stop(&mut self, _: &DecodeStep<'_>) -> Option<FinishReason> {
+                self.0 += 1;
+                (self.0 >= 3).then_some(FinishReason::Length)
+            }
+        }
+        let custom = CustomStops::default();
+        custom.add(Arc::new(|| Box::new(AfterThree(0))));
+        for _ in 0..2 {
+            let mut stops = custom.extend(StopSet::new());
+            assert_eq!(
+                first_stop(&mut stops, &[1, 2, 3, 4]),
+                Some((2, FinishReason::Length))
+            );
+        }
+    }
+}
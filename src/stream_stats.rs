@@ -0,0 +1,127 @@
+//! 流式输出的速度指示（SSE 的 `event: stats`）
+//!
+//! 每隔 `StreamConfig.stats_interval_ms` 发一次已推送的 chunk 数、最近一段时间的瞬时速度和
+//! 从第一个 chunk 起算的平均速度，流正常结束时再发一次最终值。引擎按词推送，一个 chunk 可能是
+//! 好几个 token，所以这里统计的是 chunk（词）的速度，不是解码的 token 速度。
+//! OpenAI 兼容的流不发，免得 SDK 解析出错。
+
+use std::future::pending;
+use std::time::Duration;
+
+use rocket::tokio::time::{interval_at, Instant, Interval, MissedTickBehavior};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StreamStats {
+    /// 到目前为止推送的 chunk 数
+    pub chunks: usize,
+    /// 上一次统计以来每秒的 chunk 数
+    pub chunks_per_second: f64,
+    /// 从第一个 chunk 开始的平均速度
+    pub average_chunks_per_second: f64,
+    pub elapsed_ms: u64,
+}
+
+/// 统计 chunk 速度；平均速度从第一个 chunk 开始算，不含排队和 prefill
+#[derive(Debug, Clone)]
+pub struct ChunkRate {
+    started: Instant,
+    first_chunk: Option<Instant>,
+    chunks: usize,
+    window_start: Instant,
+    window_chunks: usize,
+}
+
+impl Default for ChunkRate {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl ChunkRate {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            first_chunk: None,
+            chunks: 0,
+            window_start: now,
+            window_chunks: 0,
+        }
+    }
+
+    pub fn record(&mut self, now: Instant, chunks: usize) {
+        self.first_chunk.get_or_insert(now);
+        self.chunks += chunks;
+        self.window_chunks += chunks;
+    }
+
+    /// 当前的统计，同时开始新的瞬时窗口
+    pub fn snapshot(&mut self, now: Instant) -> StreamStats {
+        let per_second = |chunks: usize, since: Instant| {
+            let secs = now.saturating_duration_since(since).as_secs_f64();
+            if secs > 0.0 {
+                chunks as f64 / secs
+            } else {
+                0.0
+            }
+        };
+        let stats = StreamStats {
+            chunks: self.chunks,
+            chunks_per_second: per_second(self.window_chunks, self.window_start),
+            average_chunks_per_second: self
+                .first_chunk
+                .map_or(0.0, |first| per_second(self.chunks, first)),
+            elapsed_ms: now.saturating_duration_since(self.started).as_millis() as u64,
+        };
+        self.window_start = now;
+        self.window_chunks = 0;
+        stats
+    }
+}
+
+/// 定时触发 stats 事件；间隔为 None 时永远不触发
+pub struct StatsTicker(Option<Interval>);
+
+impl StatsTicker {
+    pub fn new(every: Option<Duration>) -> Self {
+        Self(every.map(|every| {
+            let mut interval = interval_at(Instant::now() + every, every);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        }))
+    }
+
+    pub async fn tick(&mut self) {
+        match &mut self.0 {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instantaneous_rate_uses_the_last_window() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut rate = ChunkRate::new(start);
+        // 第一个 chunk 之前的排队时间不算进平均速度
+        rate.record(at(500), 1);
+        rate.record(at(1000), 9);
+        let first = rate.snapshot(at(1500));
+        assert_eq!(first.chunks, 10);
+        assert_eq!(first.average_chunks_per_second, 10.0);
+        assert_eq!(first.elapsed_ms, 1500);
+
+        rate.record(at(2000), 2);
+        let second = rate.snapshot(at(2500));
+        assert_eq!(second.chunks, 12);
+        assert_eq!(second.chunks_per_second, 2.0);
+        assert_eq!(second.average_chunks_per_second, 6.0);
+    }
+}
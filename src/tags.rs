@@ -0,0 +1,213 @@
+//! 请求标签：请求里带 `"tags": {"app": "notes", "feature": "summarize"}`，用来把用量归到具体的功能上。
+//!
+//! 标签会出现在推理日志的 `infer` span 里、请求日志（`GET /admin/export`）的每一行里；
+//! 配置了 `[default.tags] metric_keys = ["app", "feature"]` 时，这些键还会作为 `/metrics` 的标签
+//! （`llm_tagged_requests_total{tag, value}` / `llm_tagged_tokens_total{tag, value}`）。
+//! 为了控制 Prometheus 的基数，每个键最多记 `max_values` 个不同的值，之后的值都记到 `other` 下。
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::label_value;
+
+/// 请求里的标签，按键排序
+pub type RequestTags = BTreeMap<String, String>;
+
+/// 一个请求最多几个标签
+pub const MAX_TAGS: usize = 16;
+const MAX_KEY_LEN: usize = 64;
+const MAX_VALUE_LEN: usize = 128;
+/// 超过基数上限的值记在这个值下面
+pub const OTHER_VALUE: &str = "other";
+
+/// 键只能是小写字母、数字、`_`、`-`、`.`，值不能太长
+pub fn check(tags: &RequestTags) -> Result<(), String> {
+    if tags.len() > MAX_TAGS {
+        return Err(format!(
+            "at most {MAX_TAGS} tags are allowed, got {}",
+            tags.len()
+        ));
+    }
+    for (key, value) in tags {
+        let valid_key = !key.is_empty()
+            && key.len() <= MAX_KEY_LEN
+            && key
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-.".contains(c));
+        if !valid_key {
+            return Err(format!(
+                "invalid tag key `{key}` (1-{MAX_KEY_LEN} characters of a-z, 0-9, `_`, `-`, `.`)"
+            ));
+        }
+        if value.chars().count() > MAX_VALUE_LEN {
+            return Err(format!(
+                "tag `{key}` is longer than {MAX_VALUE_LEN} characters"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// 日志里的写法：`app=notes,feature=summarize`
+pub fn describe(tags: &RequestTags) -> String {
+    tags.iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TagsConfig {
+    /// 作为 `/metrics` 标签的键，为空表示标签不进指标
+    pub metric_keys: Vec<String>,
+    /// 每个键最多记多少个不同的值
+    pub max_values: usize,
+}
+
+impl Default for TagsConfig {
+    fn default() -> Self {
+        Self {
+            metric_keys: Vec::new(),
+            max_values: 20,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TagCounts {
+    requests: u64,
+    tokens: u64,
+}
+
+/// 按（标签键，值）统计的请求数和 token 数（`AppState.tag_metrics`）
+#[derive(Default)]
+pub struct TagMetrics {
+    config: TagsConfig,
+    counts: Mutex<HashMap<String, BTreeMap<String, TagCounts>>>,
+}
+
+impl TagMetrics {
+    pub fn new(config: TagsConfig) -> Self {
+        Self {
+            config,
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 一个请求结束时记账；`tokens` 为 None 表示失败
+    pub fn record(&self, tags: &RequestTags, tokens: Option<usize>) {
+        if self.config.metric_keys.is_empty() {
+            return;
+        }
+        let mut counts = self.counts.lock();
+        for key in &self.config.metric_keys {
+            let Some(value) = tags.get(key) else {
+                continue;
+            };
+            let values = counts.entry(key.clone()).or_default();
+            let value = match values.contains_key(value) || values.len() < self.config.max_values {
+                true => value.as_str(),
+                false => OTHER_VALUE,
+            };
+            let entry = values.entry(value.to_string()).or_default();
+            entry.requests += 1;
+            entry.tokens += tokens.unwrap_or(0) as u64;
+        }
+    }
+
+    /// 某个标签值上结束的请求数
+    pub fn requests(&self, key: &str, value: &str) -> u64 {
+        self.counts
+            .lock()
+            .get(key)
+            .and_then(|values| values.get(value))
+            .map_or(0, |c| c.requests)
+    }
+
+    /// 追加 Prometheus 文本；没有配置 `metric_keys` 时什么都不写
+    pub fn render(&self, out: &mut String) {
+        if self.config.metric_keys.is_empty() {
+            return;
+        }
+        let counts = self.counts.lock();
+        let mut keys: Vec<_> = counts.keys().collect();
+        keys.sort();
+        for (name, help, pick) in [
+            (
+                "llm_tagged_requests_total",
+                "Inference requests that finished, by request tag.",
+                (|c: &TagCounts| c.requests) as fn(&TagCounts) -> u64,
+            ),
+            (
+                "llm_tagged_tokens_total",
+                "Tokens generated, by request tag (text output approximated by words).",
+                |c: &TagCounts| c.tokens,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for key in &keys {
+                for (value, c) in &counts[*key] {
+                    let _ = writeln!(
+                        out,
+                        "{name}{{tag=\"{}\",value=\"{}\"}} {}",
+                        label_value(key),
+                        label_value(value),
+                        pick(c)
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> RequestTags {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn keys_and_values_are_checked() {
+        assert!(check(&tags(&[("app", "notes"), ("feature.v2", "x")])).is_ok());
+        assert!(check(&tags(&[("App", "notes")]))
+            .unwrap_err()
+            .contains("invalid tag key"));
+        assert!(check(&tags(&[("app", &"x".repeat(200))])).is_err());
+        let many: RequestTags = (0..=MAX_TAGS)
+            .map(|i| (format!("k{i}"), String::new()))
+            .collect();
+        assert!(check(&many).is_err());
+        assert_eq!(describe(&tags(&[("b", "2"), ("a", "1")])), "a=1,b=2");
+    }
+
+    #[test]
+    fn only_configured_keys_become_labels_with_bounded_values() {
+        let metrics = TagMetrics::new(TagsConfig {
+            metric_keys: vec!["app".to_string()],
+            max_values: 2,
+        });
+        for app in ["notes", "mail", "notes", "chat", "todo"] {
+            metrics.record(&tags(&[("app", app), ("user", "u1")]), Some(3));
+        }
+        metrics.record(&tags(&[("app", "mail")]), None);
+        assert_eq!(metrics.requests("app", "notes"), 2);
+        assert_eq!(metrics.requests("app", "mail"), 2);
+        assert_eq!(metrics.requests("app", OTHER_VALUE), 2);
+
+        let mut out = String::new();
+        metrics.render(&mut out);
+        assert!(out.contains("llm_tagged_requests_total{tag=\"app\",value=\"notes\"} 2\n"));
+        assert!(out.contains("llm_tagged_tokens_total{tag=\"app\",value=\"mail\"} 3\n"));
+        assert!(!out.contains("user"));
+    }
+}
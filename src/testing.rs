@@ -0,0 +1,83 @@
+//! 测试用 fixture：假 registry + 本地 Rocket client
+//!
+//! 全部基于 DummyEngine，不会触发 hf-hub 下载；供 `tests/` 下的集成测试使用。
+
+use std::sync::Arc;
+
+use rocket::figment::Figment;
+use rocket::http::ContentType;
+use rocket::local::asynchronous::Client;
+
+use crate::app_state::AppState;
+use crate::{build_rocket, build_rocket_with};
+use crate::model_registry::{EngineKind, ModelMetadata, ModelRegistry};
+
+/// 只包含 Dummy 模型的 registry
+pub fn fake_registry() -> ModelRegistry {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "dummy-a",
+        "./models/dummy-a",
+        "none",
+        EngineKind::DUMMY,
+    ));
+    registry.register(ModelMetadata::new(
+        "dummy-b",
+        "./models/dummy-b",
+        "none",
+        EngineKind::DUMMY,
+    ));
+    registry
+}
+
+pub fn test_state() -> Arc<AppState> {
+    AppState::with_registry(fake_registry(), 2)
+}
+
+pub async fn client_with(state: Arc<AppState>) -> Client {
+    Client::tracked(build_rocket(state))
+        .await
+        .expect("valid rocket instance")
+}
+
+/// 在默认 figment 上叠加额外配置，例如 `("serve_static", false)`
+pub async fn client_with_config(state: Arc<AppState>, figment: Figment) -> Client {
+    Client::tracked(build_rocket_with(figment, state))
+        .await
+        .expect("valid rocket instance")
+}
+
+pub async fn client() -> Client {
+    client_with(test_state()).await
+}
+
+/// 通过 HTTP 加载模型（等加载完成），返回响应 JSON
+pub async fn load(client: &Client, model_name: &str) -> serde_json::Value {
+    let resp = client
+        .post("/load")
+        .header(ContentType::JSON)
+        .body(serde_json::json!({ "model_name": model_name, "wait": true }).to_string())
+        .dispatch()
+        .await;
+    resp.into_json().await.expect("json body")
+}
+
+/// 把 SSE 响应体拆成每个事件的 data 字段，跳过开头的 `event: request`、`event: stats` 速度事件和结尾的 `event: usage`
+pub fn sse_data(body: &str) -> Vec<String> {
+    body.split("\n\n")
+        .filter(|chunk| !chunk.trim().is_empty())
+        .filter(|chunk| {
+            !chunk
+                .lines()
+                .any(|line| matches!(line, "event:stats" | "event:usage" | "event:request"))
+        })
+        .map(|chunk| {
+            chunk
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|line| line.strip_prefix(' ').unwrap_or(line))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect()
+}
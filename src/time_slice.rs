@@ -0,0 +1,279 @@
+//! 同一块 GPU 上的分时调度（`[default.time_slice] devices = ["cuda:0"]`）：
+//! 显存只放得下几个模型的权重、放不下它们同时生成时的激活，这些设备上同一时刻只让一个生成在跑。
+//!
+//! 生成按轮次（turn）拿设备，一轮最长 `turn_ms`；到时间且有别的请求在等时，停下当前生成、
+//! 把设备交给等待中的请求（优先交给另一个模型），重新排队后以「原 prompt + 已生成的文本」继续，
+//! 和被 high 请求抢占（见 `preemption`）一样。这样一个模型的长任务不会把另一个模型完全堵住。
+//!
+//! 只对交互式的文本生成（`/infer`、流式）生效；token 级请求、throughput 攒批和共享前缀批量不分时。
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rocket::tokio::sync::{oneshot, Notify};
+use rocket::tokio::time::{sleep_until, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::device::DeviceSpec;
+
+/// 一轮默认最长多久（毫秒）
+pub const DEFAULT_TURN_MS: u64 = 2_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeSliceConfig {
+    /// 分时的设备，为空表示不启用
+    pub devices: Vec<DeviceSpec>,
+    /// 一轮最长多久（毫秒），到时间且有别的请求在等时让出设备
+    pub turn_ms: u64,
+}
+
+impl Default for TimeSliceConfig {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            turn_ms: DEFAULT_TURN_MS,
+        }
+    }
+}
+
+struct Waiter {
+    model: String,
+    wake: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct DeviceTurns {
+    busy: bool,
+    /// 最近一次拿到设备的模型，交接时优先给别的模型
+    last_model: Option<String>,
+    waiting: VecDeque<Waiter>,
+    /// 有请求开始排队时通知当前持有者
+    arrived: Arc<Notify>,
+}
+
+type Devices = Arc<Mutex<HashMap<DeviceSpec, DeviceTurns>>>;
+
+/// 每个分时设备上的轮次（`AppState.time_slicer`）
+pub struct TimeSlicer {
+    devices: Vec<DeviceSpec>,
+    turn: Duration,
+    turns: Devices,
+}
+
+impl TimeSlicer {
+    pub fn new(config: &TimeSliceConfig) -> Self {
+        Self {
+            devices: config.devices.clone(),
+            turn: Duration::from_millis(config.turn_ms.max(1)),
+            turns: Devices::default(),
+        }
+    }
+
+    /// 这个设备上的生成要不要分时
+    pub fn covers(&self, device: DeviceSpec) -> bool {
+        self.devices.contains(&device)
+    }
+
+    /// 排队等这个设备；空闲时立即拿到
+    pub async fn acquire(&self, device: DeviceSpec, model: &str) -> Turn {
+        let (wake, rx) = oneshot::channel();
+        let arrived = {
+            let mut turns = self.turns.lock();
+            let device_turns = turns.entry(device).or_default();
+            if !device_turns.busy {
+                device_turns.busy = true;
+                device_turns.last_model = Some(model.to_string());
+                return self.turn(device, model, device_turns);
+            }
+            device_turns.waiting.push_back(Waiter {
+                model: model.to_string(),
+                wake,
+            });
+            device_turns.arrived.clone()
+        };
+        arrived.notify_waiters();
+
+        let mut pending = Pending {
+            rx: Some(rx),
+            turns: self.turns.clone(),
+            device,
+            model: model.to_string(),
+        };
+        // 发送方只在交接时发送，不会先被丢掉
+        let _ = pending.rx.as_mut().expect("pending receiver").await;
+        pending.rx = None;
+        let mut turns = self.turns.lock();
+        self.turn(device, model, turns.entry(device).or_default())
+    }
+
+    fn turn(&self, device: DeviceSpec, model: &str, device_turns: &DeviceTurns) -> Turn {
+        Turn {
+            turns: self.turns.clone(),
+            device,
+            model: model.to_string(),
+            deadline: Instant::now() + self.turn,
+            arrived: device_turns.arrived.clone(),
+        }
+    }
+
+    /// 这个设备上排队中的请求数
+    pub fn waiting(&self, device: DeviceSpec) -> usize {
+        self.turns
+            .lock()
+            .get(&device)
+            .map_or(0, |turns| turns.waiting.len())
+    }
+}
+
+/// 把设备交给下一个等待者（优先另一个模型）；没人等时设为空闲
+fn hand_over(turns: &Devices, device: DeviceSpec) {
+    let mut turns = turns.lock();
+    let Some(device_turns) = turns.get_mut(&device) else {
+        return;
+    };
+    loop {
+        let last = device_turns.last_model.as_deref();
+        let next = device_turns
+            .waiting
+            .iter()
+            .position(|w| Some(w.model.as_str()) != last)
+            .unwrap_or(0);
+        let Some(waiter) = device_turns.waiting.remove(next) else {
+            device_turns.busy = false;
+            return;
+        };
+        // 已经放弃排队的跳过
+        if waiter.wake.send(()).is_ok() {
+            device_turns.last_model = Some(waiter.model);
+            return;
+        }
+    }
+}
+
+/// 排队中的请求被取消时：如果设备已经交过来了，转交给下一个
+struct Pending {
+    rx: Option<oneshot::Receiver<()>>,
+    turns: Devices,
+    device: DeviceSpec,
+    model: String,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let Some(mut rx) = self.rx.take() else {
+            return;
+        };
+        if rx.try_recv().is_ok() {
+            tracing::debug!(model = %self.model, "cancelled while receiving a turn");
+            hand_over(&self.turns, self.device);
+            return;
+        }
+        drop(rx);
+        if let Some(turns) = self.turns.lock().get_mut(&self.device) {
+            turns.waiting.retain(|w| !w.wake.is_closed());
+        }
+    }
+}
+
+/// 持有设备的一轮，drop 时交给下一个等待者
+pub struct Turn {
+    turns: Devices,
+    device: DeviceSpec,
+    model: String,
+    deadline: Instant,
+    arrived: Arc<Notify>,
+}
+
+impl Turn {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// 到了一轮的时间上限、并且有别的请求在排队时完成
+    pub async fn yield_requested(&self) {
+        sleep_until(self.deadline).await;
+        loop {
+            let arrived = self.arrived.notified();
+            let waiting = self
+                .turns
+                .lock()
+                .get(&self.device)
+                .is_some_and(|turns| !turns.waiting.is_empty());
+            if waiting {
+                return;
+            }
+            arrived.await;
+        }
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        hand_over(&self.turns, self.device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio::task::yield_now;
+
+    fn slicer(turn_ms: u64) -> Arc<TimeSlicer> {
+        Arc::new(TimeSlicer::new(&TimeSliceConfig {
+            devices: vec![DeviceSpec::Cuda(0)],
+            turn_ms,
+        }))
+    }
+
+    #[rocket::async_test]
+    async fn turns_go_to_the_other_model_first() {
+        let slicer = slicer(1_000);
+        let gpu = DeviceSpec::Cuda(0);
+        assert!(slicer.covers(gpu) && !slicer.covers(DeviceSpec::Cpu));
+        let first = slicer.acquire(gpu, "a").await;
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for model in ["a", "b"] {
+            let (queued, order) = (slicer.clone(), order.clone());
+            tasks.push(rocket::tokio::spawn(async move {
+                let turn = queued.acquire(gpu, model).await;
+                order.lock().push(turn.model().to_string());
+            }));
+            // 保证 "a" 先排队
+            while slicer.waiting(gpu) < tasks.len() {
+                yield_now().await;
+            }
+        }
+
+        drop(first);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*order.lock(), ["b", "a"]);
+        assert_eq!(slicer.waiting(gpu), 0);
+    }
+
+    #[rocket::async_test]
+    async fn yield_is_requested_only_after_the_turn_and_with_waiters() {
+        let slicer = slicer(20);
+        let gpu = DeviceSpec::Cuda(0);
+        let turn = slicer.acquire(gpu, "a").await;
+        // 没人排队：到时间了也不用让
+        let idle = rocket::tokio::time::timeout(Duration::from_millis(60), turn.yield_requested());
+        assert!(idle.await.is_err());
+
+        let waiter = {
+            let slicer = slicer.clone();
+            rocket::tokio::spawn(async move { slicer.acquire(gpu, "b").await.model().to_string() })
+        };
+        rocket::tokio::time::timeout(Duration::from_secs(1), turn.yield_requested())
+            .await
+            .expect("yield requested once b is waiting");
+        drop(turn);
+        assert_eq!(waiter.await.unwrap(), "b");
+    }
+}
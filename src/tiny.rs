@@ -0,0 +1,472 @@
+//! 很小的真实模型（`engine_kind = "tiny"`）：2 层、64 维的 llama，走和 `candle` 引擎相同的
+//! `quantized_llama` 前向（GGUF 加载、Q8_0 矩阵乘、KV cache），只在 CPU 上跑，几毫秒一个 token。
+//!
+//! `path` 为空时权重由固定 seed 生成后写成内存里的 GGUF 再加载，不需要下载任何文件；
+//! 也可以指向一个同样结构、同样词表的 GGUF 文件。词表是可打印 ASCII 逐字符，外加 BOS / EOS。
+//! 默认 greedy 解码，同样的输入总是得到同样的输出，测试可以直接和 golden 输出比较。
+//! 输出没有语义，只用来端到端检查推理链路。
+
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{Device, Tensor};
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::quantized_llama as qllama;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rocket::tokio::sync::mpsc;
+
+use crate::cancel::{self, Cancelled};
+use crate::device::DeviceSpec;
+use crate::engine::{
+    FinishReason, Generation, InferenceEngine, SamplingParams, TokenGeneration, WordSender,
+};
+use crate::memory;
+use crate::model_registry::ModelMetadata;
+use crate::stop::{CustomStops, DecodeStep, EosTokens, StopCriteria, StopCriteriaFactory, StopSet};
+
+/// 生成内置权重用的 seed，改了它 golden 输出就全变了
+const WEIGHTS_SEED: u64 = 1724;
+const EMBEDDING_LENGTH: usize = 64;
+const FEED_FORWARD_LENGTH: usize = 128;
+const HEAD_COUNT: usize = 4;
+const BLOCK_COUNT: usize = 2;
+
+/// 可打印 ASCII（空格到 `~`）依次是 0..95，之后是 BOS、EOS
+const FIRST_CHAR: u8 = b' ';
+const CHAR_COUNT: u32 = 95;
+const BOS: u32 = CHAR_COUNT;
+const EOS: u32 = CHAR_COUNT + 1;
+pub const TINY_VOCAB_SIZE: usize = CHAR_COUNT as usize + 2;
+
+/// 逐字符编码，词表外的字符当作 `?`；开头加 BOS
+pub fn encode(text: &str) -> Vec<u32> {
+    std::iter::once(BOS)
+        .chain(text.chars().map(|c| match c {
+            ' '..='~' => c as u32 - FIRST_CHAR as u32,
+            _ => '?' as u32 - FIRST_CHAR as u32,
+        }))
+        .collect()
+}
+
+/// BOS / EOS 和词表外的 id 跳过
+pub fn decode(ids: &[u32]) -> String {
+    ids.iter()
+        .filter(|&&id| id < CHAR_COUNT)
+        .map(|&id| (FIRST_CHAR + id as u8) as char)
+        .collect()
+}
+
+/// 按固定 seed 生成权重，写成 GGUF（投影矩阵 Q8_0，norm 用 F32）
+pub fn builtin_gguf() -> Result<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(WEIGHTS_SEED);
+    let mut tensor = |rows: usize, cols: usize, dtype: GgmlDType| -> Result<QTensor> {
+        // 方差约 1/cols，激活不会随层数放大
+        let scale = (3.0 / cols as f32).sqrt();
+        let values: Vec<f32> = (0..rows * cols)
+            .map(|_| rng.gen_range(-scale..scale))
+            .collect();
+        let t = Tensor::from_vec(values, (rows, cols), &Device::Cpu)?;
+        Ok(QTensor::quantize(&t, dtype)?)
+    };
+    let norm = || -> Result<QTensor> {
+        let t = Tensor::ones(EMBEDDING_LENGTH, candle_core::DType::F32, &Device::Cpu)?;
+        Ok(QTensor::quantize(&t, GgmlDType::F32)?)
+    };
+
+    let (dim, ffn) = (EMBEDDING_LENGTH, FEED_FORWARD_LENGTH);
+    let mut tensors = vec![
+        (
+            "token_embd.weight".to_string(),
+            tensor(TINY_VOCAB_SIZE, dim, GgmlDType::F32)?,
+        ),
+        ("output_norm.weight".to_string(), norm()?),
+        (
+            "output.weight".to_string(),
+            tensor(TINY_VOCAB_SIZE, dim, GgmlDType::Q8_0)?,
+        ),
+    ];
+    for layer in 0..BLOCK_COUNT {
+        let name = |part: &str| format!("blk.{layer}.{part}.weight");
+        for part in ["attn_q", "attn_k", "attn_v", "attn_output"] {
+            tensors.push((name(part), tensor(dim, dim, GgmlDType::Q8_0)?));
+        }
+        tensors.push((name("ffn_gate"), tensor(ffn, dim, GgmlDType::Q8_0)?));
+        tensors.push((name("ffn_up"), tensor(ffn, dim, GgmlDType::Q8_0)?));
+        tensors.push((name("ffn_down"), tensor(dim, ffn, GgmlDType::Q8_0)?));
+        tensors.push((name("attn_norm"), norm()?));
+        tensors.push((name("ffn_norm"), norm()?));
+    }
+
+    let u32_value = |v: usize| gguf_file::Value::U32(v as u32);
+    let metadata = [
+        (
+            "general.architecture",
+            gguf_file::Value::String("llama".to_string()),
+        ),
+        ("llama.block_count", u32_value(BLOCK_COUNT)),
+        ("llama.embedding_length", u32_value(dim)),
+        ("llama.feed_forward_length", u32_value(ffn)),
+        ("llama.attention.head_count", u32_value(HEAD_COUNT)),
+        ("llama.attention.head_count_kv", u32_value(HEAD_COUNT)),
+        ("llama.rope.dimension_count", u32_value(dim / HEAD_COUNT)),
+        (
+            "llama.attention.layer_norm_rms_epsilon",
+            gguf_file::Value::F32(1e-5),
+        ),
+    ];
+    let metadata: Vec<_> = metadata.iter().map(|(k, v)| (*k, v)).collect();
+    let tensors: Vec<_> = tensors.iter().map(|(k, t)| (k.as_str(), t)).collect();
+    let mut out = Cursor::new(Vec::new());
+    gguf_file::write(&mut out, &metadata, &tensors)?;
+    Ok(out.into_inner())
+}
+
+pub struct TinyEngine {
+    model_name: String,
+    model: Mutex<qllama::ModelWeights>,
+    kv_bytes_per_token: Option<u64>,
+    stops: CustomStops,
+}
+
+impl TinyEngine {
+    pub fn new(meta: &ModelMetadata, _device: DeviceSpec) -> Result<Arc<Self>> {
+        fn load(
+            reader: &mut (impl std::io::Read + std::io::Seek),
+        ) -> Result<(qllama::ModelWeights, Option<u64>)> {
+            let content = gguf_file::Content::read(reader)?;
+            let kv_bytes_per_token = memory::kv_bytes_per_token(&content).ok();
+            let model = qllama::ModelWeights::from_gguf(content, reader, &Device::Cpu)?;
+            Ok((model, kv_bytes_per_token))
+        }
+        let (model, kv_bytes_per_token) = if meta.path.is_empty() {
+            load(&mut Cursor::new(builtin_gguf()?))?
+        } else {
+            load(
+                &mut std::fs::File::open(&meta.path)
+                    .map_err(|e| anyhow!("failed to open {}: {e}", meta.path))?,
+            )?
+        };
+        Ok(Arc::new(Self {
+            model_name: meta.name.clone(),
+            model: Mutex::new(model),
+            kv_bytes_per_token,
+            stops: CustomStops::default(),
+        }))
+    }
+
+    /// 在结束符之外再加一个停止条件，之后每次生成都生效
+    pub fn add_stop_criteria(&self, factory: StopCriteriaFactory) {
+        self.stops.add(factory);
+    }
+
+    /// 每次都从位置 0 开始（会丢掉上一次的 cache），不填温度时 greedy
+    fn sample(
+        &self,
+        prompt_ids: &[u32],
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        on_token: &mut dyn FnMut(&[u32]) -> bool,
+    ) -> Result<TokenGeneration> {
+        if prompt_ids.is_empty() {
+            anyhow::bail!("prompt has no tokens");
+        }
+        if prompt_ids.len() + max_tokens > qllama::MAX_SEQ_LEN {
+            anyhow::bail!(
+                "prompt ({} tokens) plus {max_tokens} new tokens does not fit the context",
+                prompt_ids.len()
+            );
+        }
+        let mut model = self
+            .model
+            .lock()
+            .map_err(|_| anyhow!("failed to lock model mutex for `{}`", self.model_name))?;
+        let temperature = sampling.temperature.filter(|&t| t > 0.0);
+        let mut logits_processor =
+            LogitsProcessor::new(sampling.seed.unwrap_or(0), temperature, sampling.top_p);
+
+        let mut stops = self.stops.extend(StopSet::new().with(EosTokens::new([EOS])));
+        let started = Instant::now();
+        let mut ids = Vec::new();
+        let mut finish_reason = FinishReason::Length;
+        let mut input = prompt_ids.to_vec();
+        let mut index_pos = 0;
+        while ids.len() < max_tokens {
+            if !ids.is_empty() && !on_token(&ids) {
+                break;
+            }
+            if cancel::requested() {
+                return Err(Cancelled.into());
+            }
+            let tensor = Tensor::new(input.as_slice(), &Device::Cpu)?.unsqueeze(0)?;
+            let logits = model.forward(&tensor, index_pos)?.squeeze(0)?;
+            index_pos += input.len();
+            let next = logits_processor.sample(&logits)?;
+            ids.push(next);
+            if let Some(reason) = stops.should_stop(&DecodeStep::new(&ids, started.elapsed(), &decode)) {
+                finish_reason = reason;
+                break;
+            }
+            input = vec![next];
+        }
+        if ids.last() == Some(&EOS) {
+            ids.pop();
+        }
+        Ok(TokenGeneration { ids, finish_reason })
+    }
+
+    fn complete_inner(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<Generation> {
+        let generated = self.sample(&encode(prompt), max_tokens, sampling, &mut |_| true)?;
+        Ok(Generation {
+            text: decode(&generated.ids),
+            finish_reason: generated.finish_reason,
+        })
+    }
+}
+
+#[async_trait]
+impl InferenceEngine for TinyEngine {
+    async fn generate(&self, prompt: &str, max_tokens: usize) -> Result<String> {
+        Ok(self
+            .complete_inner(prompt, max_tokens, &SamplingParams::default())?
+            .text)
+    }
+
+    async fn complete(&self, prompt: &str, max_tokens: usize) -> Result<Generation> {
+        self.complete_inner(prompt, max_tokens, &SamplingParams::default())
+    }
+
+    async fn complete_sampled(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<Generation> {
+        self.complete_inner(prompt, max_tokens, sampling)
+    }
+
+    fn encode_prompt(&self, prompt: &str) -> Result<Vec<u32>> {
+        Ok(encode(prompt))
+    }
+
+    /// 一个字符一个 token，不算 BOS
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        Some(text.chars().count())
+    }
+
+    fn kv_bytes_per_token(&self) -> Option<u64> {
+        self.kv_bytes_per_token
+    }
+
+    fn decode_ids(&self, ids: &[u32]) -> Result<String> {
+        Ok(decode(ids))
+    }
+
+    async fn complete_ids(&self, input_ids: &[u32], max_tokens: usize) -> Result<TokenGeneration> {
+        if let Some(bad) = input_ids.iter().find(|&&id| id as usize >= TINY_VOCAB_SIZE) {
+            anyhow::bail!("token id {bad} is outside the vocabulary (size {TINY_VOCAB_SIZE})");
+        }
+        self.sample(
+            input_ids,
+            max_tokens,
+            &SamplingParams::default(),
+            &mut |_| true,
+        )
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        self.generate_stream_sampled(prompt, max_tokens, &SamplingParams::default(), sender)
+            .await
+    }
+
+    /// 边解码边按空白切开推送（和 `candle` 引擎一致），暂停时停在解码循环里
+    async fn generate_stream_sampled(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        rocket::tokio::task::block_in_place(|| {
+            let mut words = WordSender::new(&sender);
+            let generated = self.sample(&encode(prompt), max_tokens, sampling, &mut |ids| {
+                words.push(&decode(ids))
+            })?;
+            words.finish(&decode(&generated.ids));
+            Ok(())
+        })
+    }
+
+    /// 没有模板，prompt 后面直接接 `partial` 就是续写
+    async fn continue_stream(
+        &self,
+        prompt: &str,
+        partial: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        self.generate_stream_sampled(&format!("{prompt}{partial}"), max_tokens, sampling, sender)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizer_round_trips_printable_ascii() {
+        let ids = encode("Hi, tiny!");
+        assert_eq!(ids[0], BOS);
+        assert_eq!(ids.len(), 10);
+        assert_eq!(decode(&ids), "Hi, tiny!");
+        assert_eq!(decode(&encode("naïve")), "na?ve");
+    }
+
+    #[test]
+    fn builtin_weights_are_reproducible() {
+        assert_eq!(builtin_gguf().unwrap(), builtin_gguf().unwrap());
+    }
+
+    fn builtin_model() -> qllama::ModelWeights {
+        let mut reader = Cursor::new(builtin_gguf().unwrap());
+        let content = gguf_file::Content::read(&mut reader).unwrap();
+        qllama::ModelWeights::from_gguf(content, &mut reader, &Device::Cpu).unwrap()
+    }
+
+    fn forward(model: &mut qllama::ModelWeights, ids: &[u32], index_pos: usize) -> Vec<f32> {
+        let input = Tensor::new(ids, &Device::Cpu)
+            .unwrap()
+            .unsqueeze(0)
+            .unwrap();
+        let logits = model.forward(&input, index_pos).unwrap();
+        logits.squeeze(0).unwrap().to_vec1().unwrap()
+    }
+
+    /// 不用 cache：新模型从位置 0 跑完整上下文
+    fn full_context(context: &[u32]) -> Vec<f32> {
+        forward(&mut builtin_model(), context, 0)
+    }
+
+    fn argmax(logits: &[f32]) -> u32 {
+        (0..logits.len())
+            .max_by(|&a, &b| logits[a].total_cmp(&logits[b]))
+            .unwrap() as u32
+    }
+
+    fn max_diff(a: &[f32], b: &[f32]) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(x, y)| (x - y).abs())
+            .fold(0.0, f32::max)
+    }
+
+    /// 逐 token 解码时位置要跟着往后走，每一步的 logits 才和完整上下文重算的一致；
+    /// 位置一直传 0 就对不上
+    #[test]
+    fn cached_decoding_matches_full_context_forward() {
+        let mut context = encode("Hello");
+        let mut cached = builtin_model();
+        let mut stale = builtin_model();
+        let mut logits = forward(&mut cached, &context, 0);
+        forward(&mut stale, &context, 0);
+
+        let mut stale_diff: f32 = 0.0;
+        for _ in 0..8 {
+            assert!(max_diff(&logits, &full_context(&context)) < 1e-3);
+            let next = argmax(&logits);
+            let index_pos = context.len();
+            context.push(next);
+            logits = forward(&mut cached, &[next], index_pos);
+            let stale_logits = forward(&mut stale, &[next], 0);
+            stale_diff = stale_diff.max(max_diff(&stale_logits, &full_context(&context)));
+        }
+        assert!(stale_diff > 1e-2, "decoding at position 0 should diverge");
+    }
+
+    /// 引擎的 greedy 输出和每一步都用完整上下文重算的结果一样
+    #[test]
+    fn greedy_generation_matches_full_context_decoding() {
+        let meta = ModelMetadata::new("tiny", "", "", crate::model_registry::EngineKind::TINY);
+        let engine = TinyEngine::new(&meta, DeviceSpec::Cpu).unwrap();
+        let generated = engine
+            .sample(&encode("Hi"), 6, &SamplingParams::default(), &mut |_| true)
+            .unwrap();
+
+        let mut context = encode("Hi");
+        for &id in &generated.ids {
+            let expected = argmax(&full_context(&context));
+            assert_eq!(id, expected);
+            context.push(id);
+        }
+        assert!(!generated.ids.is_empty());
+    }
+
+    /// `add_stop_criteria` 加的条件每一步都会被问到，停下时用它给的原因
+    #[test]
+    fn custom_stop_criteria_cut_generation_short() {
+        use crate::stop::StopStrings;
+
+        let meta = ModelMetadata::new("tiny", "", "", crate::model_registry::EngineKind::TINY);
+        let engine = TinyEngine::new(&meta, DeviceSpec::Cpu).unwrap();
+        let full = engine
+            .sample(&encode("Hi"), 8, &SamplingParams::default(), &mut |_| true)
+            .unwrap();
+        let stop = decode(&full.ids[2..3]);
+        engine.add_stop_criteria(Arc::new(move || Box::new(StopStrings::new([stop.clone()]))));
+
+        let cut = engine
+            .sample(&encode("Hi"), 8, &SamplingParams::default(), &mut |_| true)
+            .unwrap();
+        assert_eq!(cut.finish_reason, FinishReason::Stop);
+        assert!(cut.ids.len() <= 3);
+        assert_eq!(cut.ids, full.ids[..cut.ids.len()]);
+    }
+
+    /// 流式生成暂停时解码停在循环里，继续后接着算完
+    #[test]
+    fn paused_streams_stop_decoding_until_resumed() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let meta = ModelMetadata::new("tiny", "", "", crate::model_registry::EngineKind::TINY);
+        let engine = TinyEngine::new(&meta, DeviceSpec::Cpu).unwrap();
+        let token = cancel::CancelToken::default();
+        token.set_paused(true);
+        let steps = Arc::new(AtomicUsize::new(0));
+        let worker = {
+            let (engine, token, steps) = (engine.clone(), token.clone(), steps.clone());
+            std::thread::spawn(move || {
+                let (tx, _rx) = mpsc::channel(64);
+                let mut words = WordSender::new(&tx);
+                cancel::enter(Some(token), || {
+                    engine.sample(&encode("Hi"), 16, &SamplingParams::default(), &mut |ids| {
+                        steps.store(ids.len(), Ordering::SeqCst);
+                        words.push(&decode(ids))
+                    })
+                })
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(steps.load(Ordering::SeqCst), 1);
+        assert!(!worker.is_finished());
+
+        token.set_paused(false);
+        let generated = worker.join().unwrap().unwrap();
+        assert_eq!(generated.ids.len(), 16);
+    }
+}
@@ -0,0 +1,140 @@
+//! 逐 token 的解码耗时（请求带 `"trace": true`）：排查限频、NUMA、swap 抖动这类
+//! 表现为周期性卡顿的问题。非流式请求在响应的 `trace` 里返回，流式请求只写日志。
+//!
+//! 按引擎推送 chunk 的时间计时（一个 chunk 算一个 token），包含采样和 decode，不含排队。
+//! 比中位数慢 `STALL_FACTOR` 倍以上的 token 记为卡顿。
+
+use rocket::tokio::sync::mpsc;
+use rocket::tokio::time::Instant;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// 比中位数慢这么多倍算一次卡顿
+pub const STALL_FACTOR: f64 = 4.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TokenTrace {
+    /// 从开始生成到第一个 token（主要是 prefill）
+    pub first_token_ms: f64,
+    /// 之后每个 token 距上一个的耗时，第 i 项是第 i + 1 个 token
+    pub token_ms: Vec<f64>,
+    pub p50_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+    /// 卡顿的 token 在 `token_ms` 里的下标
+    pub stalls: Vec<usize>,
+}
+
+impl TokenTrace {
+    /// 日志里的一行摘要
+    pub fn summary(&self) -> String {
+        format!(
+            "first={:.1}ms tokens={} p50={:.1}ms p99={:.1}ms max={:.1}ms stalls={:?}",
+            self.first_token_ms,
+            self.token_ms.len() + 1,
+            self.p50_ms,
+            self.p99_ms,
+            self.max_ms,
+            self.stalls
+        )
+    }
+}
+
+/// 记录每个 token 到达的时间
+#[derive(Debug, Clone)]
+pub struct TraceRecorder {
+    started: Instant,
+    last: Option<Instant>,
+    first_token_ms: f64,
+    token_ms: Vec<f64>,
+}
+
+impl TraceRecorder {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            last: None,
+            first_token_ms: 0.0,
+            token_ms: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        let ms = |since: Instant| now.saturating_duration_since(since).as_secs_f64() * 1000.0;
+        match self.last {
+            None => self.first_token_ms = ms(self.started),
+            Some(last) => self.token_ms.push(ms(last)),
+        }
+        self.last = Some(now);
+    }
+
+    /// 没有生成任何 token 时为 None
+    pub fn finish(self) -> Option<TokenTrace> {
+        self.last?;
+        let mut sorted = self.token_ms.clone();
+        sorted.sort_by(f64::total_cmp);
+        let percentile = |p: f64| match sorted.len() {
+            0 => 0.0,
+            n => sorted[((n - 1) as f64 * p).round() as usize],
+        };
+        let p50_ms = percentile(0.5);
+        let stalls = self
+            .token_ms
+            .iter()
+            .enumerate()
+            .filter(|(_, ms)| p50_ms > 0.0 && **ms > p50_ms * STALL_FACTOR)
+            .map(|(i, _)| i)
+            .collect();
+        Some(TokenTrace {
+            first_token_ms: self.first_token_ms,
+            p50_ms,
+            p99_ms: percentile(0.99),
+            max_ms: sorted.last().copied().unwrap_or(0.0),
+            stalls,
+            token_ms: self.token_ms,
+        })
+    }
+}
+
+/// 把 `rx` 转发给 `tx`，顺便记录每个 chunk 的到达时间；`tx` 关闭时停止
+pub async fn tap(
+    mut rx: mpsc::Receiver<String>,
+    tx: mpsc::Sender<String>,
+    recorder: &mut TraceRecorder,
+) {
+    while let Some(text) = rx.recv().await {
+        recorder.record(Instant::now());
+        if tx.send(text).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn finds_stalls_relative_to_the_median() {
+        let start = Instant::now();
+        let mut recorder = TraceRecorder::new(start);
+        let mut now = start + Duration::from_millis(50);
+        recorder.record(now);
+        for step in [10, 10, 11, 90, 10, 9] {
+            now += Duration::from_millis(step);
+            recorder.record(now);
+        }
+        let trace = recorder.finish().unwrap();
+        assert_eq!(trace.first_token_ms.round(), 50.0);
+        assert_eq!(trace.token_ms.len(), 6);
+        assert_eq!(trace.p50_ms.round(), 10.0);
+        assert_eq!(trace.max_ms.round(), 90.0);
+        assert_eq!(trace.stalls, [3]);
+    }
+
+    #[test]
+    fn no_tokens_means_no_trace() {
+        assert!(TraceRecorder::new(Instant::now()).finish().is_none());
+    }
+}
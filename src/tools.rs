@@ -0,0 +1,759 @@
+//! 服务端工具：assistant 循环里模型可以调用的函数（见 `assistant`），`GET /tools` 列出已注册的工具
+//!
+//! 内置 `calculator`，其余由运维在配置里注册，每个工具有自己的超时和输出上限：
+//! ```toml
+//! [default.tools]
+//! allow_shell = false          # 默认不允许 shell 类工具
+//!
+//! [[default.tools.register]]
+//! name = "weather"
+//! kind = "http_fetch"
+//! allow_hosts = ["api.weather.gov"]
+//! timeout_ms = 5000
+//!
+//! [[default.tools.register]]
+//! name = "docs_search"
+//! kind = "search"
+//! root = "/srv/docs"
+//! max_output_bytes = 8192
+//!
+//! [[default.tools.register]]
+//! name = "disk_usage"
+//! kind = "shell"
+//! command = ["df", "-h"]       # 固定的 argv，模型的 `input` 只会写到 stdin
+//! ```
+//! 其他工具（例如向量检索）实现 `Tool` 后通过 `AppState::builder().tool(...)` 注册。
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::app_state::AppState;
+
+/// 一个可以被模型调用的工具。`call` 可能阻塞（网络、磁盘、子进程），
+/// 由 `ToolRegistry::call` 放到 blocking 线程里执行并套上超时和输出上限
+pub trait Tool: Send + Sync {
+    fn name(&self) -> &str;
+    /// 给模型看的说明，包括参数格式
+    fn description(&self) -> &str;
+    fn call(&self, arguments: &serde_json::Value) -> anyhow::Result<String>;
+}
+
+/// 单个工具的执行限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ToolLimits {
+    pub timeout_ms: u64,
+    /// 超出部分截断，结尾加 `[truncated]`
+    pub max_output_bytes: usize,
+}
+
+impl Default for ToolLimits {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 10_000,
+            max_output_bytes: 16 * 1024,
+        }
+    }
+}
+
+impl ToolLimits {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// 工具相关配置（`ServerConfig::tools`）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToolsConfig {
+    /// 为 false 时配置了 `kind = "shell"` 的工具会导致启动失败
+    pub allow_shell: bool,
+    pub register: Vec<ToolSpec>,
+}
+
+/// 配置里注册的一个工具
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: ToolKind,
+    /// 不填时用该类工具的默认说明
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ToolKind {
+    /// GET 白名单 host 上的 URL
+    HttpFetch { allow_hosts: Vec<String> },
+    /// 在 `root` 下的文本文件里按行搜索
+    Search { root: PathBuf },
+    /// 执行固定命令，需要 `allow_shell = true`
+    Shell { command: Vec<String> },
+}
+
+#[derive(Debug, Error)]
+pub enum ToolError {
+    #[error("tool `{0}` is not available")]
+    NotFound(String),
+    #[error("tool `{name}` timed out after {after_ms} ms")]
+    Timeout { name: String, after_ms: u64 },
+    #[error("{0}")]
+    Failed(String),
+}
+
+/// `GET /tools` 的一项
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ToolInfo {
+    pub name: String,
+    pub description: String,
+    pub limits: ToolLimits,
+}
+
+#[derive(Clone)]
+struct RegisteredTool {
+    tool: Arc<dyn Tool>,
+    limits: ToolLimits,
+}
+
+/// 名字 -> 工具 + 限制
+#[derive(Clone)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, RegisteredTool>,
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Arc::new(Calculator));
+        registry
+    }
+}
+
+impl ToolRegistry {
+    pub fn empty() -> Self {
+        Self {
+            tools: BTreeMap::new(),
+        }
+    }
+
+    /// 内置工具 + 配置里注册的工具
+    pub fn from_config(config: &ToolsConfig) -> anyhow::Result<Self> {
+        let mut registry = Self::default();
+        for spec in &config.register {
+            let defaults = ToolLimits::default();
+            let limits = ToolLimits {
+                timeout_ms: spec.timeout_ms.unwrap_or(defaults.timeout_ms),
+                max_output_bytes: spec.max_output_bytes.unwrap_or(defaults.max_output_bytes),
+            };
+            let name = spec.name.clone();
+            let tool: Arc<dyn Tool> = match &spec.kind {
+                ToolKind::HttpFetch { allow_hosts } => {
+                    Arc::new(HttpFetch::new(name, allow_hosts.clone(), limits.timeout()))
+                }
+                ToolKind::Search { root } => Arc::new(LocalSearch::new(name, root.clone())),
+                ToolKind::Shell { command } => {
+                    if !config.allow_shell {
+                        anyhow::bail!(
+                            "tool `{name}` runs a shell command but tools.allow_shell is false"
+                        );
+                    }
+                    if command.is_empty() {
+                        anyhow::bail!("tool `{name}` has an empty command");
+                    }
+                    Arc::new(Shell::new(name, command.clone(), limits.timeout()))
+                }
+            };
+            let tool = match &spec.description {
+                Some(description) => Arc::new(Described {
+                    inner: tool,
+                    description: description.clone(),
+                }),
+                None => tool,
+            };
+            registry.register_with_limits(tool, limits);
+        }
+        Ok(registry)
+    }
+
+    /// 用默认限制注册，同名工具会被替换
+    pub fn register(&mut self, tool: Arc<dyn Tool>) {
+        self.register_with_limits(tool, ToolLimits::default());
+    }
+
+    pub fn register_with_limits(&mut self, tool: Arc<dyn Tool>, limits: ToolLimits) {
+        self.tools
+            .insert(tool.name().to_string(), RegisteredTool { tool, limits });
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.tools.get(name).map(|t| t.tool.clone())
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.tools.keys().cloned().collect()
+    }
+
+    pub fn list(&self) -> Vec<ToolInfo> {
+        self.tools
+            .values()
+            .map(|t| ToolInfo {
+                name: t.tool.name().to_string(),
+                description: t.tool.description().to_string(),
+                limits: t.limits,
+            })
+            .collect()
+    }
+
+    /// 只保留 `names` 里的工具；有没注册的名字时返回它
+    pub fn subset(&self, names: &[String]) -> Result<ToolRegistry, String> {
+        let mut tools = BTreeMap::new();
+        for name in names {
+            let tool = self.tools.get(name).ok_or_else(|| name.clone())?;
+            tools.insert(name.clone(), tool.clone());
+        }
+        Ok(ToolRegistry { tools })
+    }
+
+    /// 在 blocking 线程里执行，超时后不再等待结果，输出超过上限时截断
+    pub async fn call(
+        &self,
+        name: &str,
+        arguments: serde_json::Value,
+    ) -> Result<String, ToolError> {
+        let RegisteredTool { tool, limits } = self
+            .tools
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ToolError::NotFound(name.to_string()))?;
+        let run = rocket::tokio::task::spawn_blocking(move || tool.call(&arguments));
+        let output = rocket::tokio::time::timeout(limits.timeout(), run)
+            .await
+            .map_err(|_| ToolError::Timeout {
+                name: name.to_string(),
+                after_ms: limits.timeout_ms,
+            })?
+            .map_err(|e| ToolError::Failed(e.to_string()))?
+            .map_err(|e| ToolError::Failed(format!("{e:#}")))?;
+        Ok(truncate_output(output, limits.max_output_bytes))
+    }
+}
+
+fn truncate_output(mut output: String, max_bytes: usize) -> String {
+    if output.len() <= max_bytes {
+        return output;
+    }
+    let mut end = max_bytes;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    output.truncate(end);
+    output.push_str("\n[truncated]");
+    output
+}
+
+/// 已注册的工具和各自的限制：GET /tools
+#[utoipa::path(tag = "ops", responses((status = 200, body = Vec<ToolInfo>)))]
+#[get("/tools")]
+pub async fn list_tools(state: &State<Arc<AppState>>) -> Json<Vec<ToolInfo>> {
+    Json(state.tools.list())
+}
+
+/// 配置里覆盖了 `description` 的工具
+struct Described {
+    inner: Arc<dyn Tool>,
+    description: String,
+}
+
+impl Tool for Described {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn call(&self, arguments: &serde_json::Value) -> anyhow::Result<String> {
+        self.inner.call(arguments)
+    }
+}
+
+/// 四则运算、乘方和括号：`{"expression": "2 * (3 + 4) ^ 2"}`
+pub struct Calculator;
+
+impl Tool for Calculator {
+    fn name(&self) -> &str {
+        "calculator"
+    }
+
+    fn description(&self) -> &str {
+        "Evaluates an arithmetic expression with + - * / ^ and parentheses. \
+         Arguments: {\"expression\": string}"
+    }
+
+    fn call(&self, arguments: &serde_json::Value) -> anyhow::Result<String> {
+        let expression = arguments["expression"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("`expression` must be a string"))?;
+        let value = Expr::new(expression).evaluate()?;
+        Ok(format_number(value))
+    }
+}
+
+/// 整数结果不带小数点
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        value.to_string()
+    }
+}
+
+/// 递归下降：expr = term (('+' | '-') term)*，term = power (('*' | '/') power)*，
+/// power = unary ('^' power)?，unary = '-' unary | atom
+struct Expr<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Expr<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            chars: source.chars().peekable(),
+        }
+    }
+
+    fn evaluate(mut self) -> anyhow::Result<f64> {
+        let value = self.expr()?;
+        match self.peek() {
+            None => Ok(value),
+            Some(c) => anyhow::bail!("unexpected `{c}`"),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn expr(&mut self) -> anyhow::Result<f64> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.chars.next();
+            let rhs = self.term()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> anyhow::Result<f64> {
+        let mut value = self.power()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.chars.next();
+            let rhs = self.power()?;
+            if op == '/' && rhs == 0.0 {
+                anyhow::bail!("division by zero");
+            }
+            value = if op == '*' { value * rhs } else { value / rhs };
+        }
+        Ok(value)
+    }
+
+    fn power(&mut self) -> anyhow::Result<f64> {
+        let base = self.unary()?;
+        if self.peek() == Some('^') {
+            self.chars.next();
+            return Ok(base.powf(self.power()?));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<f64> {
+        if self.peek() == Some('-') {
+            self.chars.next();
+            return Ok(-self.unary()?);
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> anyhow::Result<f64> {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let value = self.expr()?;
+                if self.peek() != Some(')') {
+                    anyhow::bail!("missing `)`");
+                }
+                self.chars.next();
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let mut number = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    number.push(c);
+                }
+                number
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("invalid number `{number}`"))
+            }
+            Some(c) => anyhow::bail!("unexpected `{c}`"),
+            None => anyhow::bail!("unexpected end of expression"),
+        }
+    }
+}
+
+/// 响应体最多读取的字节数，再由工具的 `max_output_bytes` 截断
+const HTTP_FETCH_MAX_BYTES: u64 = 1024 * 1024;
+
+/// GET 一个 URL，只允许配置里的 host：`{"url": "https://api.example.com/..."}`
+pub struct HttpFetch {
+    name: String,
+    allowed_hosts: Vec<String>,
+    timeout: Duration,
+}
+
+impl HttpFetch {
+    pub fn new(name: impl Into<String>, allowed_hosts: Vec<String>, timeout: Duration) -> Self {
+        Self {
+            name: name.into(),
+            allowed_hosts,
+            timeout,
+        }
+    }
+}
+
+impl Tool for HttpFetch {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        "Fetches a URL with HTTP GET and returns the response body as text. \
+         Arguments: {\"url\": string}"
+    }
+
+    fn call(&self, arguments: &serde_json::Value) -> anyhow::Result<String> {
+        let url = arguments["url"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("`url` must be a string"))?;
+        // 不跟随重定向，否则可以绕过 host 白名单
+        let agent = crate::hub_stream::agent_builder()?
+            .redirects(0)
+            .timeout(self.timeout)
+            .build();
+        let request = agent.get(url);
+        let parsed = request.request_url()?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            anyhow::bail!("only http and https URLs can be fetched");
+        }
+        let host = parsed.host();
+        if !self.allowed_hosts.iter().any(|allowed| allowed == host) {
+            anyhow::bail!("host `{host}` is not allowed");
+        }
+        let response = request.call()?;
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(HTTP_FETCH_MAX_BYTES)
+            .read_to_end(&mut body)?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+/// 大于这个尺寸的文件不搜索
+const SEARCH_MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// 最多返回的匹配行数
+const SEARCH_MAX_RESULTS: usize = 50;
+
+/// 在 `root` 下的文本文件里找包含 `query` 的行（不区分大小写）：`{"query": "..."}`
+pub struct LocalSearch {
+    name: String,
+    root: PathBuf,
+}
+
+impl LocalSearch {
+    pub fn new(name: impl Into<String>, root: PathBuf) -> Self {
+        Self {
+            name: name.into(),
+            root,
+        }
+    }
+
+    fn search_file(&self, path: &Path, query: &str, results: &mut Vec<String>) {
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return; // 二进制或读不了的文件直接跳过
+        };
+        let relative = path.strip_prefix(&self.root).unwrap_or(path).display();
+        for (i, line) in text.lines().enumerate() {
+            if results.len() >= SEARCH_MAX_RESULTS {
+                return;
+            }
+            if line.to_lowercase().contains(query) {
+                results.push(format!("{relative}:{}: {}", i + 1, line.trim()));
+            }
+        }
+    }
+}
+
+impl Tool for LocalSearch {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        "Searches local documents for lines containing a phrase and returns `path:line: text` \
+         matches. Arguments: {\"query\": string}"
+    }
+
+    fn call(&self, arguments: &serde_json::Value) -> anyhow::Result<String> {
+        let query = arguments["query"]
+            .as_str()
+            .map(str::to_lowercase)
+            .filter(|q| !q.trim().is_empty())
+            .ok_or_else(|| anyhow::anyhow!("`query` must be a non-empty string"))?;
+
+        // 深度优先，跳过隐藏文件和符号链接，不会走出 root
+        let mut results = Vec::new();
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries: Vec<_> = std::fs::read_dir(&dir)?.flatten().collect();
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                if entry.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                if file_type.is_dir() {
+                    dirs.push(entry.path());
+                } else if file_type.is_file()
+                    && entry
+                        .metadata()
+                        .is_ok_and(|m| m.len() <= SEARCH_MAX_FILE_BYTES)
+                {
+                    self.search_file(&entry.path(), &query, &mut results);
+                }
+            }
+            if results.len() >= SEARCH_MAX_RESULTS {
+                break;
+            }
+        }
+        if results.is_empty() {
+            return Ok("no matches".to_string());
+        }
+        Ok(results.join("\n"))
+    }
+}
+
+/// 执行固定的命令（不经过 shell），模型给的 `input` 写到 stdin：`{"input": "..."}`。
+/// 只继承 `PATH`，超时后杀掉子进程
+pub struct Shell {
+    name: String,
+    command: Vec<String>,
+    timeout: Duration,
+}
+
+impl Shell {
+    pub fn new(name: impl Into<String>, command: Vec<String>, timeout: Duration) -> Self {
+        Self {
+            name: name.into(),
+            command,
+            timeout,
+        }
+    }
+}
+
+impl Tool for Shell {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        "Runs a fixed server-side command and returns its output. \
+         Arguments: {\"input\": string (optional, sent to stdin)}"
+    }
+
+    fn call(&self, arguments: &serde_json::Value) -> anyhow::Result<String> {
+        let input = arguments["input"].as_str().unwrap_or_default().to_string();
+        let mut command = Command::new(&self.command[0]);
+        command
+            .args(&self.command[1..])
+            .env_clear()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+        let mut child = command.spawn()?;
+
+        // 输出在单独的线程里读，避免管道写满后子进程卡住
+        let mut stdin = child.stdin.take();
+        let writer = std::thread::spawn(move || {
+            if let Some(stdin) = stdin.as_mut() {
+                let _ = stdin.write_all(input.as_bytes());
+            }
+        });
+        let read_all = |pipe: Option<Box<dyn Read + Send>>| {
+            std::thread::spawn(move || {
+                let mut buf = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buf);
+                }
+                String::from_utf8_lossy(&buf).into_owned()
+            })
+        };
+        let stdout = read_all(child.stdout.take().map(|p| Box::new(p) as _));
+        let stderr = read_all(child.stderr.take().map(|p| Box::new(p) as _));
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                anyhow::bail!("command timed out after {} ms", self.timeout.as_millis());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        let _ = writer.join();
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if !status.success() {
+            anyhow::bail!("command exited with {status}: {}", stderr.trim());
+        }
+        Ok(stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(expression: &str) -> anyhow::Result<String> {
+        Calculator.call(&serde_json::json!({ "expression": expression }))
+    }
+
+    #[test]
+    fn calculator_follows_precedence() {
+        assert_eq!(calc("2 + 3 * 4").unwrap(), "14");
+        assert_eq!(calc("(2 + 3) * 4").unwrap(), "20");
+        assert_eq!(calc("2 ^ 3 ^ 2").unwrap(), "512");
+        assert_eq!(calc("-3 + 10 / 4").unwrap(), "-0.5");
+        assert!(calc("1 / 0").is_err());
+        assert!(calc("2 +").is_err());
+        assert!(calc("(1").is_err());
+    }
+
+    #[test]
+    fn http_fetch_rejects_hosts_outside_the_allowlist() {
+        let fetch = HttpFetch::new(
+            "http_fetch",
+            vec!["api.example.com".to_string()],
+            Duration::from_secs(1),
+        );
+        let err = fetch
+            .call(&serde_json::json!({ "url": "http://169.254.169.254/latest" }))
+            .unwrap_err();
+        assert!(err.to_string().contains("not allowed"), "{err}");
+        assert!(fetch
+            .call(&serde_json::json!({ "url": "file:///etc/passwd" }))
+            .is_err());
+    }
+
+    #[test]
+    fn shell_tools_need_allow_shell() {
+        let spec = ToolSpec {
+            name: "uptime".to_string(),
+            kind: ToolKind::Shell {
+                command: vec!["uptime".to_string()],
+            },
+            description: None,
+            timeout_ms: None,
+            max_output_bytes: None,
+        };
+        let mut config = ToolsConfig {
+            allow_shell: false,
+            register: vec![spec],
+        };
+        assert!(ToolRegistry::from_config(&config).is_err());
+        config.allow_shell = true;
+        let registry = ToolRegistry::from_config(&config).unwrap();
+        assert_eq!(registry.names(), ["calculator", "uptime"]);
+    }
+
+    #[rocket::async_test]
+    async fn call_enforces_timeout_and_output_limit() {
+        let mut registry = ToolRegistry::empty();
+        let limits = ToolLimits {
+            timeout_ms: 200,
+            max_output_bytes: 4,
+        };
+        let shell = |name: &str, script: &str| {
+            Arc::new(Shell::new(
+                name,
+                vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+                limits.timeout(),
+            ))
+        };
+        registry.register_with_limits(shell("slow", "sleep 5"), limits);
+        registry.register_with_limits(shell("echo", "cat"), limits);
+
+        let started = Instant::now();
+        let err = registry
+            .call("slow", serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ToolError::Timeout { .. }), "{err}");
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let output = registry
+            .call("echo", serde_json::json!({ "input": "hello world" }))
+            .await
+            .unwrap();
+        assert_eq!(output, "hell\n[truncated]");
+        assert!(matches!(
+            registry.call("missing", serde_json::json!({})).await,
+            Err(ToolError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn local_search_finds_matching_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("guide")).unwrap();
+        std::fs::write(
+            dir.path().join("guide/intro.md"),
+            "Hello\nRust borrow checker\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join(".secret"), "borrow me").unwrap();
+
+        let search = LocalSearch::new("docs", dir.path().to_path_buf());
+        let output = search
+            .call(&serde_json::json!({ "query": "BORROW" }))
+            .unwrap();
+        assert_eq!(output, "guide/intro.md:2: Rust borrow checker");
+        let output = search
+            .call(&serde_json::json!({ "query": "missing" }))
+            .unwrap();
+        assert_eq!(output, "no matches");
+    }
+}
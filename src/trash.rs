@@ -0,0 +1,334 @@
+//! 模型的回收站：`DELETE /admin/models/<name>` 只是软删除，条目从 registry（和所有列表）里消失，
+//! 但元数据留在这里、权重文件原样留在磁盘上，`POST /admin/trash/<name>/restore` 可以原样恢复，
+//! 不用重新下载几个 GB 的权重。
+//!
+//! 超过 `retention_secs` 的条目由后台任务定时硬删除（`DELETE /admin/trash/<name>` 立即硬删除）；
+//! 配置了 `purge_weights = true` 时同时删掉本地的 GGUF / tokenizer 文件（其他模型还在用的不删，
+//! hub 缓存里的文件不动）。回收站和运行时注册的模型一样只在内存里，重启后清空。
+//!
+//! ```toml
+//! [default.trash]
+//! retention_secs = 604800
+//! purge_weights = true
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use rocket::fairing::AdHoc;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::admin::load_error;
+use crate::api::{api_error, model_info, ApiError};
+use crate::app_state::AppState;
+use crate::events::ModelEvent;
+use crate::model_registry::{EngineKind, ModelMetadata};
+use crate::perf_history::now_ms;
+use crate::types::ModelInfoResponse;
+
+/// 默认保留 7 天
+pub const DEFAULT_RETENTION_SECS: u64 = 7 * 24 * 3600;
+/// 后台清理最多隔多久检查一次
+const MAX_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    /// 软删除的模型保留多久（秒），之后硬删除
+    pub retention_secs: u64,
+    /// 硬删除时是否删掉本地权重文件
+    pub purge_weights: bool,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention_secs: DEFAULT_RETENTION_SECS,
+            purge_weights: false,
+        }
+    }
+}
+
+struct Trashed {
+    meta: ModelMetadata,
+    deleted_at_ms: u64,
+}
+
+/// 回收站里的一个模型
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TrashEntry {
+    pub model_name: String,
+    #[schema(value_type = String)]
+    pub engine_kind: EngineKind,
+    pub path: String,
+    /// 删除时间（unix 毫秒）
+    pub deleted_at_ms: u64,
+    /// 到这个时间（unix 毫秒）后硬删除
+    pub purge_at_ms: u64,
+}
+
+/// 软删除的模型（`AppState.trash`）
+#[derive(Default)]
+pub struct Trash {
+    config: TrashConfig,
+    entries: Mutex<HashMap<String, Trashed>>,
+}
+
+impl Trash {
+    pub fn new(config: TrashConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &TrashConfig {
+        &self.config
+    }
+
+    fn retention_ms(&self) -> u64 {
+        self.config.retention_secs.saturating_mul(1000)
+    }
+
+    /// 放进回收站；同名的旧条目被替换
+    pub fn put(&self, meta: ModelMetadata) {
+        let name = meta.name.clone();
+        let deleted_at_ms = now_ms();
+        self.entries.lock().insert(
+            name,
+            Trashed {
+                meta,
+                deleted_at_ms,
+            },
+        );
+    }
+
+    /// 拿出来（恢复或硬删除）
+    pub fn take(&self, name: &str) -> Option<ModelMetadata> {
+        self.entries.lock().remove(name).map(|t| t.meta)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.lock().contains_key(name)
+    }
+
+    /// 按删除时间排序，最早的在前
+    pub fn list(&self) -> Vec<TrashEntry> {
+        let mut entries: Vec<_> = self
+            .entries
+            .lock()
+            .values()
+            .map(|t| TrashEntry {
+                model_name: t.meta.name.clone(),
+                engine_kind: t.meta.engine_kind.clone(),
+                path: t.meta.path.clone(),
+                deleted_at_ms: t.deleted_at_ms,
+                purge_at_ms: t.deleted_at_ms.saturating_add(self.retention_ms()),
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            (a.deleted_at_ms, &a.model_name).cmp(&(b.deleted_at_ms, &b.model_name))
+        });
+        entries
+    }
+
+    /// 取出到 `now_ms` 为止超过保留期的条目
+    pub fn take_expired(&self, now_ms: u64) -> Vec<ModelMetadata> {
+        let retention = self.retention_ms();
+        let mut entries = self.entries.lock();
+        let expired: Vec<String> = entries
+            .iter()
+            .filter(|(_, t)| t.deleted_at_ms.saturating_add(retention) <= now_ms)
+            .map(|(name, _)| name.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|name| entries.remove(name).map(|t| t.meta))
+            .collect()
+    }
+
+    /// 所有条目引用的本地文件
+    fn referenced_files(&self) -> Vec<PathBuf> {
+        self.entries
+            .lock()
+            .values()
+            .flat_map(|t| local_files(&t.meta))
+            .collect()
+    }
+}
+
+/// 模型引用的本地权重和 tokenizer 文件
+fn local_files(meta: &ModelMetadata) -> Vec<PathBuf> {
+    let Some(artifacts) = &meta.artifacts else {
+        return Vec::new();
+    };
+    let mut files = vec![artifacts.gguf.clone()];
+    files.extend(artifacts.tokenizer.clone());
+    files
+}
+
+impl AppState {
+    /// 硬删除一个已经取出回收站的模型；`purge_weights` 时删掉没有别的模型在用的本地文件
+    fn purge(&self, meta: &ModelMetadata) {
+        if self.trash.config().purge_weights {
+            let mut in_use: Vec<PathBuf> =
+                self.list_models().iter().flat_map(local_files).collect();
+            in_use.extend(self.trash.referenced_files());
+            for file in local_files(meta) {
+                if in_use.iter().any(|used| same_file(used, &file)) {
+                    continue;
+                }
+                match std::fs::remove_file(&file) {
+                    Ok(()) => info!(model = %meta.name, file = %file.display(), "removed weights"),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        warn!(model = %meta.name, file = %file.display(), error = %e, "failed to remove weights")
+                    }
+                }
+            }
+        }
+        info!(model = %meta.name, "purged from the trash");
+        self.events.emit(ModelEvent::Purged {
+            model: meta.name.clone(),
+        });
+    }
+
+    /// 硬删除超过保留期的模型，返回它们的名字
+    pub fn purge_expired_trash(&self) -> Vec<String> {
+        self.trash
+            .take_expired(now_ms())
+            .into_iter()
+            .map(|meta| {
+                self.purge(&meta);
+                meta.name
+            })
+            .collect()
+    }
+
+    /// 立即硬删除回收站里的一个模型
+    pub fn purge_trashed_model(&self, model_name: &str) -> Option<ModelMetadata> {
+        let meta = self.trash.take(model_name)?;
+        self.purge(&meta);
+        Some(meta)
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// 启动后定时硬删除过期的模型
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Trash purge", |rocket| {
+        Box::pin(async move {
+            let Some(state) = rocket.state::<Arc<AppState>>().cloned() else {
+                return;
+            };
+            let retention = Duration::from_secs(state.trash.config().retention_secs);
+            let every = retention.clamp(Duration::from_secs(1), MAX_SWEEP_INTERVAL);
+            rocket::tokio::spawn(async move {
+                loop {
+                    rocket::tokio::time::sleep(every).await;
+                    state.purge_expired_trash();
+                }
+            });
+        })
+    })
+}
+
+fn not_in_trash(name: &str) -> ApiError {
+    api_error(
+        Status::NotFound,
+        "model_not_in_trash",
+        format!("model `{name}` is not in the trash"),
+    )
+}
+
+/// 软删除的模型：GET /admin/trash
+#[utoipa::path(
+    tag = "admin",
+    responses((status = 200, body = Vec<TrashEntry>))
+)]
+#[get("/admin/trash")]
+pub async fn list_trash(state: &State<Arc<AppState>>) -> Json<Vec<TrashEntry>> {
+    Json(state.trash.list())
+}
+
+/// 恢复软删除的模型（状态为 unloaded）：POST /admin/trash/<name>/restore
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, body = ModelInfoResponse),
+        (status = 404, description = "model is not in the trash", body = ErrorResponse),
+        (status = 409, description = "a model with this name is registered again", body = ErrorResponse)
+    )
+)]
+#[post("/admin/trash/<name>/restore")]
+pub async fn restore_model(
+    state: &State<Arc<AppState>>,
+    name: &str,
+) -> Result<Json<ModelInfoResponse>, ApiError> {
+    if !state.trash.contains(name) {
+        return Err(not_in_trash(name));
+    }
+    let meta = state.restore_model(name).map_err(load_error)?;
+    Ok(Json(model_info(state, meta)))
+}
+
+/// 立即硬删除：DELETE /admin/trash/<name>
+#[utoipa::path(
+    tag = "admin",
+    responses(
+        (status = 200, body = TrashEntry),
+        (status = 404, description = "model is not in the trash", body = ErrorResponse)
+    )
+)]
+#[delete("/admin/trash/<name>")]
+pub async fn purge_model(
+    state: &State<Arc<AppState>>,
+    name: &str,
+) -> Result<Json<TrashEntry>, ApiError> {
+    let entry = state
+        .trash
+        .list()
+        .into_iter()
+        .find(|e| e.model_name == name)
+        .ok_or_else(|| not_in_trash(name))?;
+    state
+        .purge_trashed_model(name)
+        .ok_or_else(|| not_in_trash(name))?;
+    Ok(Json(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_expire_after_the_retention_window() {
+        let trash = Trash::new(TrashConfig {
+            retention_secs: 60,
+            ..TrashConfig::default()
+        });
+        trash.put(ModelMetadata::new("a", "a.gguf", "q4", EngineKind::DUMMY));
+        let entry = &trash.list()[0];
+        assert_eq!(entry.purge_at_ms, entry.deleted_at_ms + 60_000);
+
+        assert!(trash.take_expired(entry.deleted_at_ms + 59_999).is_empty());
+        let expired = trash.take_expired(entry.purge_at_ms);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].name, "a");
+        assert!(!trash.contains("a"));
+    }
+}
@@ -1,39 +1,675 @@
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::chat_template::ChatTemplate;
+use crate::confirm::{AdminAction, ConfirmationRequired, Impact};
+use crate::device::DeviceSpec;
+use crate::engine::{CacheStats, FinishReason, LoadTimings, SamplingParams};
+use crate::health::HealthStatus;
+use crate::jobs::JobStatus;
+use crate::model_registry::{EngineKind, ModelStatus};
+use crate::output_validation::{OutputValidation, ValidationAttempt};
+use crate::preemption::Priority;
+use crate::prompt_compression::CompressionReport;
+use crate::rag::{GroundedSentence, RagSource};
+use crate::router::RoutingRule;
+use crate::scratch::ScratchOptions;
+use crate::self_test::SelfTestReport;
+use crate::session::{ChatRole, ChatTurn, SessionOptions};
+use crate::tags::RequestTags;
+use crate::token_trace::TokenTrace;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct HealthResponse {
-    pub status: String,
+    pub status: HealthStatus,
+    /// 不是 ok 时的原因
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ModelInfoResponse {
     pub name: String,
-    pub status: String,
+    pub status: ModelStatus,
     pub engine_kind: String,
+    /// 可用设备，第一个为默认
+    pub placements: Vec<DeviceSpec>,
+    /// 状态为 Error 时的失败详情
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ModelErrorInfo>,
+    /// scratch 模型距离自动卸载的秒数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scratch_expires_in_secs: Option<u64>,
+    /// 最近一次加载后的自检结果（开启 `self_test` 时）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_test: Option<SelfTestReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelErrorInfo {
+    pub message: String,
+    /// 失败时间（Unix 秒）
+    pub at: u64,
+    /// 连续失败次数
+    pub attempts: u32,
+    /// 这一轮连续失败的原因
+    pub reasons: Vec<String>,
+}
+
+/// 虚拟 router 模型（`GET /routers`）
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RouterInfoResponse {
+    pub name: String,
+    pub rules: Vec<RoutingRule>,
+    pub default: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LoadModelRequest {
     pub model_name: String,
+    /// 临时加载：到期或创建者离开时自动卸载
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scratch: Option<ScratchOptions>,
+    /// 估算内存超过可用内存时仍然加载，见 `memory`
+    #[serde(default)]
+    pub force: bool,
+    /// 等加载（和自检）完成再返回；默认立即返回 `Loading`，在后台加载。scratch 加载总是等待
+    #[serde(default)]
+    pub wait: bool,
+    /// 这次加载到哪些设备上（例如 `["cuda:0"]`），替换 registry 里的 placements；为空时沿用
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub placements: Vec<DeviceSpec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UnloadModelRequest {
+    pub model_name: String,
+    /// 模型还有请求在跑时仍然卸载
+    #[serde(default)]
+    pub force: bool,
+    /// 上一次 409 返回的确认 token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirm: Option<String>,
+}
+
+/// 运行时注册模型（`POST /models`），字段同 manifest；不持久化，重启后需要重新注册。
+/// 权重来源 `gguf` 和 `hf_repo` + `hf_file` 二选一，都不填时由引擎决定
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterModelRequest {
+    pub name: String,
+    /// 默认 candle
+    #[serde(default = "default_engine_kind")]
+    pub engine_kind: String,
+    #[serde(default = "default_quantization")]
+    pub quantization: String,
+    /// 服务器上 GGUF 文件的路径
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub gguf: Option<PathBuf>,
+    /// 不填时使用 GGUF 同目录下的 `tokenizer.json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub tokenizer: Option<PathBuf>,
+    /// 填了就在加载前校验
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hf_repo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hf_file: Option<String>,
+    /// 提供 `tokenizer.json` 的仓库，默认同 `hf_repo`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer_repo: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub placements: Vec<DeviceSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// 默认采样参数，请求里填了的字段优先
+    #[serde(default, skip_serializing_if = "SamplingParams::is_default")]
+    pub sampling: SamplingParams,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn default_engine_kind() -> String {
+    EngineKind::CANDLE.to_string()
+}
+
+fn default_quantization() -> String {
+    "unknown".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LoadModelResponse {
     pub model_name: String,
-    pub status: String,
+    pub status: ModelStatus,
     pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ModelErrorInfo>,
+    /// 加载成功且引擎统计了耗时时才有，每个副本一项
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub load_timings: Vec<ReplicaLoadTimings>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReplicaLoadTimings {
+    pub device: DeviceSpec,
+    pub timings: LoadTimings,
+}
+
+/// 推理模式：`throughput` 允许服务端短暂攒批，适合离线批量生成
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InferMode {
+    #[default]
+    Interactive,
+    Throughput,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct InferRequest {
+    /// API key 的 profile 配了默认模型时可以省略
+    #[serde(default)]
     pub model_name: String,
+    /// 给了 `input_ids` 时可以省略
+    #[serde(default)]
     pub prompt: String,
-    // 未来可以加参数，比如 max_tokens, temperature 等
-    // pub max_tokens: Option<usize>,
+    /// 指定在哪个设备上推理（必须是模型的 placements 之一），默认第一个
+    #[serde(default)]
+    pub device: Option<DeviceSpec>,
+    #[serde(default)]
+    pub mode: InferMode,
+    /// 已经分好词的输入，跳过 encode（和 `prompt` 二选一）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_ids: Option<Vec<u32>>,
+    /// 返回生成的 token id 而不是文本，跳过 decode
+    #[serde(default)]
+    pub return_token_ids: bool,
+    /// 生成长度，默认非流式 64、流式 128；会被 API key 的上限截断
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    /// 顶层的 `temperature`、`top_p`、`top_k`、`seed`；throughput 模式的攒批请求不支持，忽略
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+    /// `low` 的生成可以被 `high` 请求抢占，见 `preemption`
+    #[serde(default)]
+    pub priority: Priority,
+    /// 记录逐 token 的解码耗时（见 `token_trace`），throughput 模式和 token 级请求不支持
+    #[serde(default)]
+    pub trace: bool,
+    /// 模型声明了对话模板、prompt 里又没有模板标记时自动套上（见 `chat_template`），
+    /// token 级请求不支持
+    #[serde(default)]
+    pub auto_template: bool,
+    /// 校验完整输出（JSON / 正则 / 最大长度），没通过时自动重试，见 `output_validation`。
+    /// 只支持非流式文本请求
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate: Option<OutputValidation>,
+    /// 自由格式的标签（`{"app": "notes", "feature": "summarize"}`），记进日志、请求日志和
+    /// 配置过的 `/metrics` 标签，见 `tags`
+    #[serde(default, skip_serializing_if = "RequestTags::is_empty")]
+    pub tags: RequestTags,
+    /// 隐私模式：日志里只记录哈希和长度。由 API key profile 设置，客户端不能直接指定
+    #[serde(skip)]
+    pub private: bool,
+    /// 发起请求的 API key（`ApiKey::label`），只用于 `/admin/requests/active` 展示
+    #[serde(skip)]
+    pub client: Option<String>,
+    /// HTTP 请求的 id（见 `logging`），推理日志的 span 里带着它
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    /// 限流记账用的客户端（见 `rate_limit`），请求结束后按用量扣它的 token 额度
+    #[serde(skip)]
+    pub rate_client: Option<String>,
+}
+
+/// 一个共享前缀 + 多个后缀：前缀只 prefill 一次，每个后缀从前缀的 cache 开始解码
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SharedPrefixRequest {
+    #[serde(default)]
+    pub model_name: String,
+    /// 共享的上下文（例如评分标准）
+    pub prefix: String,
+    /// 接在前缀后面的短问题，每个生成一条结果
+    pub suffixes: Vec<String>,
+    #[serde(default)]
+    pub device: Option<DeviceSpec>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SharedPrefixResponse {
+    pub model_name: String,
+    pub served_by: String,
+    /// 和 `suffixes` 一一对应，只包含续写部分
+    pub completions: Vec<SharedPrefixCompletion>,
+    /// 前缀的 token 数，不按 token 处理的引擎为 0
+    pub prefix_tokens: usize,
+    /// 和逐条完整 prefill 相比省掉的 token 数
+    pub prefill_tokens_saved: usize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SharedPrefixCompletion {
+    pub output: String,
+    pub finish_reason: FinishReason,
+}
+
+/// 接着一段部分输出继续生成：客户端先拿到一部分结果，可以改掉结尾再让服务端接着写
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ContinueRequest {
+    #[serde(default)]
+    pub model_name: String,
+    /// 和第一次生成时相同的 prompt
+    pub prompt: String,
+    /// 已有的输出（可以改过），续写接在它后面
+    pub partial: String,
+    #[serde(default)]
+    pub device: Option<DeviceSpec>,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "SamplingParams::is_default")]
+    pub sampling: SamplingParams,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ContinueResponse {
+    pub model_name: String,
+    pub served_by: String,
+    /// `partial` + 续写
+    pub output: String,
+    pub finish_reason: FinishReason,
+    /// 从 KV cache 复用、没有重新 prefill 的 token 数；改动越靠后越多
+    pub reused_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct InferResponse {
     pub model_name: String,
     pub output: String,
+    /// 实际生成的模型（经过 router / fallback 后可能和 model_name 不同），出错时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub served_by: Option<String>,
+    /// stop / length / repetition，出错时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<FinishReason>,
+    /// `return_token_ids` 时返回新生成的 token id，此时 `output` 为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_ids: Option<Vec<u32>>,
+    /// 请求带 `trace` 时的逐 token 耗时
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TokenTrace>,
+    /// 请求带 `auto_template` 时 prompt 被套上的模型模板
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub applied_template: Option<ChatTemplate>,
+    /// 按模型的 tokenizer 统计的 token 数，引擎不能分词或出错时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+/// 一次生成用掉的 token 数，字段和 OpenAI 的 `usage` 一致。
+/// prompt 按实际送进模型的样子计数（含对话模板和特殊 token）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TokenUsage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl TokenUsage {
+    pub fn new(prompt_tokens: usize, completion_tokens: usize) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        }
+    }
+}
+
+impl InferRequest {
+    /// 输入或输出是否按 token id 处理
+    pub fn uses_token_ids(&self) -> bool {
+        self.input_ids.is_some() || self.return_token_ids
+    }
+}
+
+/// `DELETE /scratch` 与 `DELETE /sessions/<id>`：被自动卸载的 scratch 模型
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScratchReleaseResponse {
+    pub released_models: Vec<String>,
+}
+
+/// 结构化错误响应：`{"error": {"code", "message"}}`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    /// 机器可读的错误码，例如 `model_not_found`
+    pub code: String,
+    pub message: String,
+    /// 413 时的上限
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+    /// 破坏性操作需要二次确认时（409）的影响评估和确认 token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<Box<ConfirmationRequired>>,
+    /// 输出校验一直没通过时（422）每一次的输出和原因
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<Vec<ValidationAttempt>>,
+    /// 请求队列满了（429）或等太久（503）时在队列里的位置
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<usize>,
+}
+
+/// unload / delete 执行成功后的响应
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AdminActionResponse {
+    pub action: AdminAction,
+    pub model_name: String,
+    /// 执行时受影响的请求数（force 时可能非 0）
+    pub impact: Impact,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JobInfoResponse {
+    pub id: String,
+    pub kind: String,
+    pub status: JobStatus,
+    /// Unix 秒
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub progress: Option<f32>,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JobAcceptedResponse {
+    pub job_id: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct IntegrityScanRequest {
+    #[serde(default)]
+    pub redownload: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateSessionRequest {
+    /// API key 的 profile 配了默认模型时可以省略
+    #[serde(default)]
+    pub model_name: String,
+    #[serde(default)]
+    pub options: SessionOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SessionResponse {
+    pub id: String,
+    pub model_name: String,
+    pub options: SessionOptions,
+    pub turns: Vec<ChatTurn>,
+    /// Unix 秒
+    pub created_at: u64,
+}
+
+/// `GET /sessions/<id>/memory`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SessionMemoryResponse {
+    pub session_id: String,
+    pub content: String,
+    /// 累计折叠进记忆的轮数
+    pub summarized_turns: usize,
+    /// Unix 秒，还没有记忆时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+    pub edited: bool,
+}
+
+/// `PUT /sessions/<id>/memory`：整体替换记忆内容
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateSessionMemoryRequest {
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatMessageRequest {
+    pub content: String,
+    #[serde(default)]
+    pub device: Option<DeviceSpec>,
+    /// 为 true 时响应里带上最终送给模型的 prompt
+    #[serde(default)]
+    pub render_debug: bool,
+    /// 覆盖 session 的默认生成长度
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    /// 逐字段覆盖 session 的默认采样参数
+    #[serde(default)]
+    pub sampling: SamplingParams,
+    /// 以 SSE 返回，事件和 `GET /sessions/<id>/stream` 相同
+    #[serde(default)]
+    pub stream: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatMessageResponse {
+    pub session_id: String,
+    pub model_name: String,
+    pub reply: String,
+    pub served_by: String,
+    pub finish_reason: FinishReason,
+    /// 这次请求的历史被压缩过时才有
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<CompressionReport>,
+    /// `render_debug` 时才有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rendered_prompt: Option<String>,
+}
+
+/// `GET /models/<name>/cache`：每个副本一项
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ModelCacheResponse {
+    pub model_name: String,
+    pub replicas: Vec<ReplicaCacheInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReplicaCacheInfo {
+    pub device: DeviceSpec,
+    /// 引擎不维护 KV cache 时为空
+    pub stats: Option<CacheStats>,
+}
+
+/// OpenAI 兼容的 `POST /v1/chat/completions`，只用到这几个字段，其余（temperature 等）忽略
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatCompletionRequest {
+    /// API key 的 profile 配了默认模型时可以省略
+    #[serde(default)]
+    pub model: String,
+    pub messages: Vec<ChatTurn>,
+    #[serde(default)]
+    pub stream: bool,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    /// 为 true 时响应里带上最终送给模型的 prompt，只支持非流式
+    #[serde(default)]
+    pub render_debug: bool,
+    /// `model` 是 RAG profile 时，标出回答的每一句依据的是哪一段，只支持非流式
+    #[serde(default)]
+    pub grounding: bool,
+}
+
+/// 非流式响应（`object: "chat.completion"`）
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatCompletionResponse {
+    pub id: String,
+    pub object: String,
+    /// Unix 秒
+    pub created: u64,
+    /// 实际生成的模型
+    pub model: String,
+    pub choices: Vec<ChatCompletionChoice>,
+    /// `render_debug` 时才有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rendered_prompt: Option<String>,
+    /// RAG profile 检索到、插进对话的段
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rag_sources: Vec<RagSource>,
+    /// 请求带 `grounding` 时才有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grounding: Option<Vec<GroundedSentence>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatCompletionChoice {
+    pub index: usize,
+    pub message: ChatTurn,
+    /// `stop` / `length`
+    pub finish_reason: String,
+}
+
+/// 流式响应的一个 SSE 事件（`object: "chat.completion.chunk"`）
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: String,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChunkChoice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChatCompletionChunkChoice {
+    pub index: usize,
+    pub delta: ChatDelta,
+    /// 只在最后一个 chunk 里有值，其余为 null
+    pub finish_reason: Option<String>,
+}
+
+/// 第一个 chunk 只带 role，之后只带 content，最后一个为空
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ChatDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<ChatRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// OpenAI 兼容的 `GET /v1/models`（`object: "list"`）
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelListResponse {
+    pub object: String,
+    pub data: Vec<ModelObject>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ModelObject {
+    pub id: String,
+    /// 固定为 `model`
+    pub object: String,
+    /// 最后一次状态变化的时间（Unix 秒），从没变过时为 0
+    pub created: u64,
+    pub owned_by: String,
+    /// 扩展字段：和 `GET /models` 的 `status` 一样（unloaded / loading / loaded / error）
+    pub status: ModelStatus,
+}
+
+/// OpenAI 的 `stop`：一个字符串或字符串数组
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl StopSequences {
+    /// 去掉空字符串
+    pub fn into_vec(self) -> Vec<String> {
+        let stops = match self {
+            StopSequences::One(stop) => vec![stop],
+            StopSequences::Many(stops) => stops,
+        };
+        stops.into_iter().filter(|s| !s.is_empty()).collect()
+    }
+}
+
+/// 旧版 OpenAI 文本补全 `POST /v1/completions`，只用到这几个字段，其余（`n`、`echo` 等）忽略
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TextCompletionRequest {
+    /// API key 的 profile 配了默认模型时可以省略
+    #[serde(default)]
+    pub model: String,
+    pub prompt: String,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    /// 输出遇到其中任意一个就截断（不包含 stop 本身）
+    #[serde(default)]
+    pub stop: Option<StopSequences>,
+    #[serde(default)]
+    pub stream: bool,
+}
+
+/// 非流式响应和流式的每个 SSE 事件都是这个结构（`object: "text_completion"`）
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TextCompletionResponse {
+    pub id: String,
+    pub object: String,
+    /// Unix 秒
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<TextCompletionChoice>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TextCompletionChoice {
+    pub text: String,
+    pub index: usize,
+    /// `stop` / `length`；流式时只在最后一个事件里有值
+    pub finish_reason: Option<String>,
+}
+
+/// `POST /v1/images/generations`，字段沿用 OpenAI 的命名
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ImageGenerationRequest {
+    /// API key 的 profile 配了默认模型时可以省略
+    #[serde(default)]
+    pub model: String,
+    pub prompt: String,
+    /// 张数，默认 1，最多 4
+    #[serde(default)]
+    pub n: Option<usize>,
+    /// `宽x高`，默认 `512x512`，必须是 8 的倍数
+    #[serde(default)]
+    pub size: Option<String>,
+    /// 去噪步数，默认 30
+    #[serde(default)]
+    pub steps: Option<usize>,
+    /// classifier-free guidance，默认 7.5
+    #[serde(default)]
+    pub guidance_scale: Option<f64>,
+    /// 不填时随机，实际使用的值会写在 job 结果里
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub device: Option<DeviceSpec>,
+}
+
+/// 文生图 job 成功后的 `result`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ImageGenerationResult {
+    pub model: String,
+    pub seed: u64,
+    /// 每张图的下载地址（`GET /v1/images/<job_id>/<index>`）
+    pub images: Vec<String>,
 }
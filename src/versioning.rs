@@ -0,0 +1,83 @@
+//! API 版本：原生接口挂在 `/api/v1/...`，原来不带前缀的路径作为兼容别名继续可用，
+//! 之后改错误格式、换 schema 时可以放到 `/api/v2`，不会悄悄弄坏已有的脚本。
+//!
+//! 原生接口的响应都带 `X-API-Version: 1`；走旧路径时另外带 `Deprecation: true` 和指向新路径的
+//! `Link: </api/v1/...>; rel="successor-version"`。OpenAI 兼容接口（`/v1/*`）跟着 OpenAI 的版本走，
+//! 文档、静态文件和管理页专用的 `/admin/api/*`（见 `admin_api`）也不受影响。
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::route::Route;
+use rocket::{Request, Response};
+use utoipa::openapi::path::PathItem;
+use utoipa::openapi::{Deprecated, OpenApi};
+use utoipa::Modify;
+
+use crate::admin_api::ADMIN_API;
+
+pub const API_V1: &str = "/api/v1";
+pub const API_VERSION_HEADER: &str = "X-API-Version";
+/// 当前的原生 API 版本
+pub const CURRENT_VERSION: &str = "1";
+
+/// 旧路径：挂在 `/` 下，并且 `/api/v1` 下有同一个方法、同一个路径的路由
+fn is_legacy_alias(request: &Request<'_>, route: &Route) -> bool {
+    route.uri.base() == "/"
+        && request.rocket().routes().any(|r| {
+            r.uri.base() == API_V1
+                && r.method == route.method
+                && r.uri.unmounted_origin == route.uri.unmounted_origin
+        })
+}
+
+/// 给原生接口的响应加版本头，旧路径加弃用头
+pub struct ApiVersioning;
+
+#[rocket::async_trait]
+impl Fairing for ApiVersioning {
+    fn info(&self) -> Info {
+        Info {
+            name: "API versioning headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(route) = request.route() else {
+            return;
+        };
+        if route.uri.base() == API_V1 {
+            response.set_header(Header::new(API_VERSION_HEADER, CURRENT_VERSION));
+        } else if is_legacy_alias(request, route) {
+            response.set_header(Header::new(API_VERSION_HEADER, CURRENT_VERSION));
+            response.set_header(Header::new("Deprecation", "true"));
+            response.set_header(Header::new(
+                "Link",
+                format!("<{API_V1}{}>; rel=\"successor-version\"", request.uri()),
+            ));
+        }
+    }
+}
+
+/// OpenAPI 里每个原生路径都加一份 `/api/v1` 版本，旧路径标成 deprecated
+pub struct VersionedPaths;
+
+impl Modify for VersionedPaths {
+    fn modify(&self, openapi: &mut OpenApi) {
+        let legacy: Vec<(String, PathItem)> = openapi
+            .paths
+            .paths
+            .iter()
+            .filter(|(path, _)| !path.starts_with("/v1/") && !path.starts_with(ADMIN_API))
+            .map(|(path, item)| (path.clone(), item.clone()))
+            .collect();
+        for (path, item) in legacy {
+            let mut deprecated = item.clone();
+            for operation in deprecated.operations.values_mut() {
+                operation.deprecated = Some(Deprecated::True);
+            }
+            openapi.paths.paths.insert(path.clone(), deprecated);
+            openapi.paths.paths.insert(format!("{API_V1}{path}"), item);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use rocket::http::{ContentType, Status};
+use serde_json::json;
+
+use local_llm_server::model_registry::ModelStatus;
+use local_llm_server::testing::{client_with_config, test_state};
+
+fn peer(ip: &str) -> SocketAddr {
+    SocketAddr::new(ip.parse().unwrap(), 40000)
+}
+
+#[rocket::async_test]
+async fn allowlist_rejects_other_clients_before_any_handler_runs() {
+    let figment = rocket::Config::figment().merge((
+        "access",
+        json!({ "allow_ips": ["127.0.0.1", "192.168.1.0/24"] }),
+    ));
+    let state = test_state();
+    let client = client_with_config(state.clone(), figment).await;
+
+    let resp = client
+        .get("/health")
+        .remote(peer("192.168.1.20"))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+
+    let resp = client
+        .post("/load")
+        .remote(peer("10.1.2.3"))
+        .header(ContentType::JSON)
+        .body(json!({ "model_name": "dummy-a" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "ip_not_allowed");
+    assert_eq!(
+        state.registry.get_model("dummy-a").unwrap().status,
+        ModelStatus::Unloaded
+    );
+}
+
+#[rocket::async_test]
+async fn localhost_only_overrides_a_public_bind_address() {
+    let figment = rocket::Config::figment()
+        .merge(("address", "0.0.0.0"))
+        .merge(("access", json!({ "localhost_only": true })));
+    let client = client_with_config(test_state(), figment).await;
+    assert_eq!(
+        client.rocket().config().address,
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    );
+
+    let status = |ip: &'static str| {
+        let client = &client;
+        async move {
+            client
+                .get("/health")
+                .remote(peer(ip))
+                .dispatch()
+                .await
+                .status()
+        }
+    };
+    assert_eq!(status("127.0.0.1").await, Status::Ok);
+    assert_eq!(status("::1").await, Status::Ok);
+    assert_eq!(status("192.168.1.20").await, Status::Forbidden);
+}
+
+#[rocket::async_test]
+async fn invalid_allowlist_entry_fails_ignite() {
+    let figment =
+        rocket::Config::figment().merge(("access", json!({ "allow_ips": ["10.0.0.0/40"] })));
+    let rocket = local_llm_server::build_rocket_with(figment, test_state());
+    let error = rocket.ignite().await.unwrap_err();
+    assert!(matches!(
+        error.kind(),
+        rocket::error::ErrorKind::FailedFairings(_)
+    ));
+}
@@ -0,0 +1,405 @@
+use std::time::Duration;
+
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use serde_json::Value;
+
+use local_llm_server::app_state::AppState;
+use local_llm_server::model_registry::{EngineKind, LocalArtifacts, ModelMetadata};
+use local_llm_server::testing::{client, fake_registry, load};
+use local_llm_server::trash::TrashConfig;
+
+async fn send(client: &Client, method: &str, uri: &str) -> (Status, Value) {
+    let resp = match method {
+        "DELETE" => client.delete(uri.to_string()),
+        _ => client.post(uri.to_string()),
+    }
+    .dispatch()
+    .await;
+    (resp.status(), resp.into_json().await.unwrap())
+}
+
+async fn get(client: &Client, uri: &str) -> Value {
+    client
+        .get(uri.to_string())
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap()
+}
+
+#[rocket::async_test]
+async fn unload_of_busy_model_requires_confirmation() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let infer = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"long job"}"#)
+        .dispatch();
+    // DummyEngine 每次生成 50ms，期间发起卸载
+    let unload = async {
+        rocket::tokio::time::sleep(Duration::from_millis(15)).await;
+        send(&client, "POST", "/admin/models/dummy-a/unload").await
+    };
+    let (_, (status, body)) = rocket::tokio::join!(infer, unload);
+
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(body["error"]["code"], "confirmation_required");
+    let confirmation = &body["error"]["confirmation"];
+    assert_eq!(confirmation["action"], "unload");
+    assert_eq!(confirmation["impact"]["in_flight"], 1);
+    assert_eq!(confirmation["impact"]["queued"], 0);
+    let token = confirmation["confirm_token"].as_str().unwrap().to_string();
+
+    let uri = format!("/admin/models/dummy-a/unload?confirm={token}");
+    let (status, body) = send(&client, "POST", &uri).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["model_name"], "dummy-a");
+
+    // token 只能用一次
+    let (status, body) = send(&client, "POST", &uri).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(body["error"]["code"], "invalid_confirmation");
+}
+
+#[rocket::async_test]
+async fn active_requests_list_running_generations() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let infer = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"long job"}"#)
+        .dispatch();
+    let list = async {
+        rocket::tokio::time::sleep(Duration::from_millis(15)).await;
+        let resp = client.get("/admin/requests/active").dispatch().await;
+        resp.into_json::<Value>().await.unwrap()
+    };
+    let (done, active) = rocket::tokio::join!(infer, list);
+    assert_eq!(done.status(), Status::Ok);
+
+    let active = active.as_array().unwrap();
+    assert_eq!(active.len(), 1);
+    assert!(active[0]["request_id"]
+        .as_str()
+        .unwrap()
+        .starts_with("gen-"));
+    assert_eq!(active[0]["model"], "dummy-a");
+    assert_eq!(active[0]["kind"], "collect");
+    assert_eq!(active[0]["state"], "running");
+    assert_eq!(active[0]["client"], Value::Null);
+
+    let resp = client.get("/admin/requests/active").dispatch().await;
+    assert_eq!(
+        resp.into_json::<Value>().await.unwrap(),
+        serde_json::json!([])
+    );
+}
+
+#[rocket::async_test]
+async fn idle_or_forced_actions_run_immediately() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let (status, body) = send(&client, "POST", "/models/dummy-a/cache/clear?force=true").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["model_name"], "dummy-a");
+
+    let (status, body) = send(&client, "DELETE", "/admin/models/dummy-a").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["action"], "delete");
+
+    let (status, body) = send(&client, "DELETE", "/admin/models/dummy-a").await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(body["error"]["code"], "model_not_found");
+
+    // 没加载的模型不能卸载
+    let (status, body) = send(&client, "POST", "/admin/models/dummy-b/unload").await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(body["error"]["code"], "invalid_state");
+}
+
+#[rocket::async_test]
+async fn unload_endpoint_frees_the_model() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let unload = |body: &'static str| {
+        let client = &client;
+        async move {
+            let resp = client
+                .post("/unload")
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await;
+            (resp.status(), resp.into_json::<Value>().await.unwrap())
+        }
+    };
+    let (status, body) = unload(r#"{"model_name":"dummy-a"}"#).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["action"], "unload");
+    assert_eq!(body["impact"]["in_flight"], 0);
+
+    let models: Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let dummy_a = models
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["name"] == "dummy-a")
+        .unwrap();
+    assert_eq!(dummy_a["status"], "unloaded");
+
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Conflict);
+    let infer: Value = resp.into_json().await.unwrap();
+    assert_eq!(infer["error"]["code"], "model_not_loaded");
+
+    let (status, body) = unload(r#"{"model_name":"dummy-a"}"#).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(body["error"]["code"], "invalid_state");
+    let (status, _) = unload(r#"{"model_name":"missing"}"#).await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn request_logs_export_as_jsonl_and_csv() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+    for body in [
+        r#"{"model_name":"dummy-a","prompt":"hello there"}"#,
+        r#"{"model_name":"dummy-b","prompt":"not loaded"}"#,
+    ] {
+        client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+    }
+
+    let resp = client.get("/admin/export").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(
+        resp.content_type(),
+        Some(ContentType::new("application", "x-ndjson"))
+    );
+    let text = resp.into_string().await.unwrap();
+    let rows: Vec<Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        (&rows[0]["model"], &rows[0]["ok"]),
+        (&"dummy-a".into(), &true.into())
+    );
+    assert_eq!(rows[0]["prompt_bytes"], 11);
+    assert_eq!(rows[1]["ok"], false);
+
+    let csv = client
+        .get("/admin/export?kind=metrics&format=csv")
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert!(lines[0].starts_with("bucket_start,model,requests,errors"));
+    assert_eq!(lines.len(), 3, "{csv}");
+
+    // 时间范围之外的没有
+    let resp = client.get("/admin/export?from=0&to=1").dispatch().await;
+    assert_eq!(resp.into_string().await.unwrap(), "");
+    for bad in ["format=xml", "kind=everything", "bucket=0", "from=10&to=5"] {
+        let resp = client.get(format!("/admin/export?{bad}")).dispatch().await;
+        assert_eq!(resp.status(), Status::BadRequest, "{bad}");
+    }
+}
+
+#[rocket::async_test]
+async fn deleted_models_can_be_restored_until_purged() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let (status, _) = send(&client, "DELETE", "/admin/models/dummy-a").await;
+    assert_eq!(status, Status::Ok);
+    let names = |models: Value| -> Vec<String> {
+        models
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let models = get(&client, "/models").await;
+    assert!(!names(models).contains(&"dummy-a".to_string()));
+
+    let trash = get(&client, "/admin/trash").await;
+    assert_eq!(trash[0]["model_name"], "dummy-a");
+    assert!(trash[0]["purge_at_ms"].as_u64() > trash[0]["deleted_at_ms"].as_u64());
+
+    // 恢复后是未加载状态，可以重新加载
+    let (status, body) = send(&client, "POST", "/admin/trash/dummy-a/restore").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["name"], "dummy-a");
+    assert_eq!(body["status"], "unloaded");
+    let models = get(&client, "/models").await;
+    assert!(names(models).contains(&"dummy-a".to_string()));
+    load(&client, "dummy-a").await;
+
+    let (status, body) = send(&client, "POST", "/admin/trash/dummy-a/restore").await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(body["error"]["code"], "model_not_in_trash");
+
+    send(&client, "DELETE", "/admin/models/dummy-a").await;
+    let (status, body) = send(&client, "DELETE", "/admin/trash/dummy-a").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["model_name"], "dummy-a");
+    let trash = get(&client, "/admin/trash").await;
+    assert_eq!(trash, serde_json::json!([]));
+    let (status, _) = send(&client, "POST", "/admin/trash/dummy-a/restore").await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn expired_models_are_purged_with_their_weights() {
+    let dir = tempfile::tempdir().unwrap();
+    let weights = |name: &str| {
+        let path = dir.path().join(format!("{name}.gguf"));
+        std::fs::write(&path, b"GGUF").unwrap();
+        LocalArtifacts {
+            gguf: path,
+            tokenizer: None,
+            sha256: None,
+        }
+    };
+    let registry = fake_registry();
+    let own = weights("own");
+    let shared = weights("shared");
+    registry.register(
+        ModelMetadata::new("gone", "", "none", EngineKind::DUMMY).with_artifacts(own.clone()),
+    );
+    registry.register(
+        ModelMetadata::new("gone-shared", "", "none", EngineKind::DUMMY)
+            .with_artifacts(shared.clone()),
+    );
+    registry.register(
+        ModelMetadata::new("kept", "", "none", EngineKind::DUMMY).with_artifacts(shared.clone()),
+    );
+    let state = AppState::builder()
+        .registry(registry)
+        .trash(TrashConfig {
+            retention_secs: 0,
+            purge_weights: true,
+        })
+        .build();
+
+    // 重名的模型又注册了就不能恢复
+    state.delete_model("gone").unwrap();
+    state
+        .registry
+        .register(ModelMetadata::new("gone", "", "none", EngineKind::DUMMY));
+    assert!(state.restore_model("gone").is_err());
+    state.registry.unregister("gone");
+
+    state.delete_model("gone-shared").unwrap();
+    let mut purged = state.purge_expired_trash();
+    purged.sort();
+    assert_eq!(purged, ["gone", "gone-shared"]);
+    assert!(state.trash.list().is_empty());
+    assert!(!own.gguf.exists());
+    // `kept` 还在用的文件不删
+    assert!(shared.gguf.exists());
+}
+
+#[rocket::async_test]
+async fn admin_page_api_drives_loading_queue_and_usage() {
+    let client = client().await;
+
+    let (status, body) = send(&client, "POST", "/admin/api/models/dummy-a/load").await;
+    assert_eq!(status, Status::Accepted);
+    assert_eq!(body["name"], "dummy-a");
+    let mut loaded = false;
+    for _ in 0..100 {
+        let models = get(&client, "/admin/api/models").await;
+        let dummy_a = models
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["name"] == "dummy-a")
+            .unwrap()
+            .clone();
+        match dummy_a["status"].as_str() {
+            Some("loading") => assert!(dummy_a["load"]["elapsed_ms"].is_u64()),
+            Some("loaded") => {
+                assert!(dummy_a["load"].is_null());
+                loaded = true;
+                break;
+            }
+            other => panic!("unexpected status {other:?}"),
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(loaded);
+
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+
+    let queue = get(&client, "/admin/api/queue").await;
+    assert_eq!(queue["capacity"], 2);
+    assert_eq!(queue["in_use"], 0);
+    assert_eq!(queue["waiting"], 0);
+    assert_eq!(queue["requests"], serde_json::json!([]));
+    let (status, body) = send(&client, "DELETE", "/admin/api/requests/nope").await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(body["error"]["code"], "request_not_found");
+
+    let usage = get(&client, "/admin/api/usage?minutes=5&bucket=60").await;
+    assert_eq!(usage["bucket_secs"], 60);
+    let total = usage["total"].as_array().unwrap();
+    assert!((5..=6).contains(&total.len()));
+    let requests: u64 = total.iter().map(|p| p["requests"].as_u64().unwrap()).sum();
+    assert_eq!(requests, 1);
+    assert_eq!(
+        usage["models"]["dummy-a"].as_array().unwrap().len(),
+        total.len()
+    );
+    let resp = client.get("/admin/api/usage?bucket=0").dispatch().await;
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    let (status, body) = send(&client, "POST", "/admin/api/models/dummy-a/unload").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["status"], "unloaded");
+    assert_eq!(body["requests_total"], 1);
+
+    // 不是稳定接口：没有 /api/v1 别名，也不带版本头
+    let resp = client.get("/api/v1/admin/api/queue").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let resp = client.get("/admin/api/queue").dispatch().await;
+    assert_eq!(resp.headers().get_one("Deprecation"), None);
+    let spec = get(&client, "/openapi.json").await;
+    assert!(spec["paths"]["/admin/api/queue"]["get"]["deprecated"].is_null());
+    assert!(spec["paths"]["/api/v1/admin/api/queue"].is_null());
+}
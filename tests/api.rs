@@ -0,0 +1,1432 @@
+use std::time::{Duration, Instant};
+
+use rocket::http::{ContentType, Header, Status};
+
+use local_llm_server::app_state::AppState;
+use local_llm_server::chat_template::ChatTemplate;
+use local_llm_server::jobs::JobStatus;
+use local_llm_server::model_registry::{EngineKind, ModelMetadata, ModelStatus};
+use local_llm_server::pipeline::StreamConfig;
+use local_llm_server::queue::QueueConfig;
+use local_llm_server::rate_limit::RateLimitConfig;
+use local_llm_server::tags::TagsConfig;
+use local_llm_server::testing::{
+    client, client_with, client_with_config, fake_registry, load, sse_data, test_state,
+};
+
+#[rocket::async_test]
+async fn health_is_ok() {
+    let client = client().await;
+    let resp = client.get("/health").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["status"], "ok");
+}
+
+#[rocket::async_test]
+async fn info_reports_build_and_capabilities() {
+    let client = client().await;
+    let body: serde_json::Value = client
+        .get("/info")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert!(body["git_commit"].as_str().is_some_and(|c| !c.is_empty()));
+    assert!(body["candle_version"].as_str().unwrap().starts_with("0."));
+    assert_eq!(
+        body["features"].as_array().unwrap().is_empty(),
+        !cfg!(feature = "cuda")
+    );
+    let kinds = body["engine_kinds"].as_array().unwrap();
+    assert!(kinds.contains(&"candle".into()) && kinds.contains(&"tiny".into()));
+    assert!(body["architectures"]
+        .as_array()
+        .unwrap()
+        .contains(&"llama".into()));
+    assert_eq!(body["limits"]["max_seq_len"], 4096);
+}
+
+#[rocket::async_test]
+async fn models_lists_fake_registry() {
+    let client = client().await;
+    let body: serde_json::Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let mut names: Vec<_> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["name"].as_str().unwrap().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["dummy-a", "dummy-b"]);
+}
+
+/// 状态、引擎类型在 JSON 里是稳定的小写字符串，不是 Rust 的 Debug 输出
+#[rocket::async_test]
+async fn model_fields_use_snake_case_wire_format() {
+    let client = client().await;
+    let models: serde_json::Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let model = &models.as_array().unwrap()[0];
+    assert_eq!(model["status"], "unloaded");
+    assert_eq!(model["engine_kind"], "dummy");
+
+    let loaded = load(&client, "dummy-a").await;
+    assert_eq!(loaded["status"], "loaded");
+    let openai: serde_json::Value = client
+        .get("/v1/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(openai["data"][0]["status"], "loaded");
+
+    for (status, wire) in [
+        (ModelStatus::Unloaded, "unloaded"),
+        (ModelStatus::Loading, "loading"),
+        (ModelStatus::Loaded, "loaded"),
+        (ModelStatus::Error, "error"),
+    ] {
+        assert_eq!(serde_json::to_value(status).unwrap(), wire);
+        assert_eq!(
+            serde_json::from_value::<ModelStatus>(wire.into()).unwrap(),
+            status
+        );
+    }
+    assert_eq!(
+        serde_json::to_value(JobStatus::Succeeded).unwrap(),
+        "succeeded"
+    );
+}
+
+#[rocket::async_test]
+async fn load_then_infer() {
+    let client = client().await;
+
+    let loaded = load(&client, "dummy-a").await;
+    assert_eq!(loaded["status"], "loaded");
+
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hello world"}"#)
+        .dispatch()
+        .await;
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["output"], "[dummy-a DUMMY] HELLO WORLD");
+}
+
+#[rocket::async_test]
+async fn shared_prefix_answers_every_suffix() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let shared_prefix = |body: serde_json::Value| {
+        client
+            .post("/infer/shared_prefix")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+    };
+    let resp = shared_prefix(serde_json::json!({
+        "model_name": "dummy-a",
+        "prefix": "rubric: ",
+        "suffixes": ["answer one", "answer two"],
+    }))
+    .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["served_by"], "dummy-a");
+    let outputs: Vec<_> = body["completions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["output"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        outputs,
+        [
+            "[dummy-a DUMMY] RUBRIC: ANSWER ONE",
+            "[dummy-a DUMMY] RUBRIC: ANSWER TWO"
+        ]
+    );
+
+    let resp = shared_prefix(serde_json::json!({
+        "model_name": "dummy-a",
+        "prefix": "rubric: ",
+        "suffixes": [],
+    }))
+    .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    let resp = shared_prefix(serde_json::json!({
+        "model_name": "dummy-b",
+        "prefix": "rubric: ",
+        "suffixes": ["x"],
+    }))
+    .await;
+    assert_eq!(resp.status(), Status::Conflict);
+}
+
+#[rocket::async_test]
+async fn continue_appends_to_the_edited_partial_output() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let resp = client
+        .post("/infer/continue")
+        .header(ContentType::JSON)
+        .body(
+            serde_json::json!({
+                "model_name": "dummy-a",
+                "prompt": "write a story. ",
+                "partial": "once upon a time",
+            })
+            .to_string(),
+        )
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["served_by"], "dummy-a");
+    // DummyEngine 没有 cache，拼成新 prompt 重新生成
+    assert_eq!(
+        body["output"],
+        "once upon a time[dummy-a DUMMY] WRITE A STORY. ONCE UPON A TIME"
+    );
+    assert_eq!(body["reused_tokens"], 0);
+
+    let resp = client
+        .post("/infer/continue")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-b","prompt":"p","partial":"x"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Conflict);
+}
+
+/// 一次调用把 dummy-a 换成 dummy-b：先卸载再加载，结果在 job 里
+#[rocket::async_test]
+async fn bulk_swaps_models_in_one_job() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let resp = client
+        .post("/models/bulk")
+        .header(ContentType::JSON)
+        .body(
+            r#"{"operations": [
+                {"action": "load", "model_name": "dummy-b"},
+                {"action": "unload", "model_name": "dummy-a"}
+            ]}"#,
+        )
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Accepted);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    let job_id = body["job_id"].as_str().unwrap().to_string();
+
+    let mut job = serde_json::Value::Null;
+    for _ in 0..50 {
+        job = client
+            .get(format!("/jobs/{job_id}"))
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        if job["status"] == "succeeded" {
+            break;
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(job["status"], "succeeded", "{job}");
+    let result = &job["result"];
+    assert_eq!(result["completed"], true);
+    let ops = result["operations"].as_array().unwrap();
+    assert_eq!(ops[0]["action"], "unload");
+    assert_eq!(ops[0]["model_name"], "dummy-a");
+    assert_eq!(ops[0]["status"], "unloaded");
+    assert_eq!(ops[1]["action"], "load");
+    assert_eq!(ops[1]["outcome"], "done");
+    assert_eq!(ops[1]["status"], "loaded");
+
+    // 未知模型整批拒绝，什么都不执行
+    let resp = client
+        .post("/models/bulk")
+        .header(ContentType::JSON)
+        .body(
+            r#"{"operations": [
+                {"action": "unload", "model_name": "dummy-b"},
+                {"action": "load", "model_name": "missing"}
+            ]}"#,
+        )
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let models: serde_json::Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let dummy_b = models
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["name"] == "dummy-b")
+        .unwrap();
+    assert_eq!(dummy_b["status"], "loaded");
+}
+
+#[rocket::async_test]
+async fn load_unknown_model_reports_error() {
+    let client = client().await;
+    let body = load(&client, "nope").await;
+    assert_eq!(body["status"], "error");
+}
+
+#[rocket::async_test]
+async fn infer_before_load_is_rejected() {
+    let client = client().await;
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-b","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Conflict);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "model_not_loaded");
+    assert!(body["error"]["message"].as_str().unwrap().contains("is not loaded"));
+}
+
+#[rocket::async_test]
+async fn errors_use_status_codes_and_an_envelope() {
+    let client = client().await;
+    for (body, status, code) in [
+        (r#"{"model_name":"nope","prompt":"hi"}"#, Status::NotFound, "model_not_found"),
+        (r#"{"model_name":"dummy-a","prompt":5}"#, Status::UnprocessableEntity, "invalid_input"),
+        (r#"{"model_name":"dummy-a","prompt":"hi","input_ids":[]}"#, Status::UnprocessableEntity, "invalid_input"),
+    ] {
+        let resp = client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), status, "{body}");
+        let body: serde_json::Value = resp.into_json().await.unwrap();
+        assert_eq!(body["error"]["code"], code);
+        assert!(body["error"]["message"].is_string());
+    }
+
+    let resp = client.get("/no/such/route").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "not_found");
+}
+
+#[rocket::async_test]
+async fn post_stream_emits_word_chunks() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let resp = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"a b"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.content_type(), Some(ContentType::EventStream));
+    let body = resp.into_string().await.unwrap();
+    assert_eq!(
+        sse_data(&body),
+        vec!["[model=dummy-a]", "[dummy-a", "DUMMY]", "A", "B"]
+    );
+}
+
+#[rocket::async_test]
+async fn get_stream_emits_word_chunks() {
+    let client = client().await;
+    load(&client, "dummy-b").await;
+
+    let resp = client
+        .get("/infer_stream?model_name=dummy-b&prompt=hey")
+        .dispatch()
+        .await;
+    let body = resp.into_string().await.unwrap();
+    assert_eq!(
+        sse_data(&body),
+        vec!["[model=dummy-b]", "[dummy-b", "DUMMY]", "HEY"]
+    );
+}
+
+#[rocket::async_test]
+async fn oversized_prompt_returns_structured_413() {
+    let figment = rocket::Config::figment().merge(("max_prompt_bytes", 8));
+    let client = client_with_config(test_state(), figment).await;
+    load(&client, "dummy-a").await;
+
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"this prompt is too long"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::PayloadTooLarge);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "prompt_too_large");
+    assert_eq!(body["error"]["max_bytes"], 8);
+
+    let resp = client
+        .get("/infer_stream?model_name=dummy-a&prompt=this%20prompt%20is%20too%20long")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::PayloadTooLarge);
+}
+
+#[rocket::async_test]
+async fn oversized_body_returns_structured_413() {
+    let figment = rocket::Config::figment().merge(("limits.json", 64));
+    let client = client_with_config(test_state(), figment).await;
+
+    let prompt = "x".repeat(256);
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(format!(r#"{{"model_name":"dummy-a","prompt":"{prompt}"}}"#))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::PayloadTooLarge);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "payload_too_large");
+    assert_eq!(body["error"]["max_bytes"], 64);
+}
+
+#[rocket::async_test]
+async fn infer_through_virtual_router() {
+    use local_llm_server::router::{RouteCondition, RoutingRule, VirtualRouter};
+
+    let state = test_state();
+    let rules = vec![RoutingRule {
+        when: RouteCondition::Language("zh".to_string()),
+        model: "dummy-b".to_string(),
+    }];
+    let router = VirtualRouter::new("by-lang", rules, "dummy-a").unwrap();
+    state.registry.register_router(router).unwrap();
+    let client = local_llm_server::testing::client_with(state).await;
+    load(&client, "dummy-a").await;
+    load(&client, "dummy-b").await;
+
+    let routers: serde_json::Value = client
+        .get("/routers")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(routers[0]["rules"][0]["when"]["language"], "zh");
+
+    for (prompt, expected) in [
+        ("hello", "[dummy-a DUMMY] HELLO"),
+        ("你好", "[dummy-b DUMMY] 你好"),
+    ] {
+        let body: serde_json::Value = client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(serde_json::json!({ "model_name": "by-lang", "prompt": prompt }).to_string())
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(body["output"], expected);
+    }
+}
+
+#[rocket::async_test]
+async fn infer_reports_fallback_model() {
+    use local_llm_server::model_registry::{EngineKind, ModelMetadata};
+
+    let state = test_state();
+    state.registry.register(
+        ModelMetadata::new("primary", "", "none", EngineKind::DUMMY)
+            .with_fallbacks(vec!["dummy-a".to_string()]),
+    );
+    let client = local_llm_server::testing::client_with(state).await;
+    load(&client, "dummy-a").await;
+
+    let body: serde_json::Value = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(serde_json::json!({ "model_name": "primary", "prompt": "hi" }).to_string())
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body["model_name"], "primary");
+    assert_eq!(body["served_by"], "dummy-a");
+    assert_eq!(body["output"], "[dummy-a DUMMY] HI");
+}
+
+#[rocket::async_test]
+async fn infer_stops_on_repetition() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let infer = |prompt: String| {
+        let client = &client;
+        async move {
+            client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(serde_json::json!({ "model_name": "dummy-a", "prompt": prompt }).to_string())
+                .dispatch()
+                .await
+                .into_json::<serde_json::Value>()
+                .await
+                .unwrap()
+        }
+    };
+
+    let body = infer("la ".repeat(40)).await;
+    assert_eq!(body["finish_reason"], "repetition");
+    // 检测到重复后就不再继续输出
+    let words = body["output"].as_str().unwrap().split_whitespace().count();
+    assert_eq!(words, 2 + 16);
+
+    let body = infer("hi".to_string()).await;
+    assert_eq!(body["finish_reason"], "stop");
+}
+
+#[rocket::async_test]
+async fn infer_accepts_and_returns_token_ids() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let infer = |body: serde_json::Value| {
+        let client = &client;
+        async move {
+            let resp = client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+                .await;
+            (
+                resp.status(),
+                resp.into_json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+
+    // DummyEngine 把字节当 token："hi" = [104, 105]
+    let (status, body) = infer(serde_json::json!({
+        "model_name": "dummy-a",
+        "input_ids": [104, 105],
+        "return_token_ids": true
+    }))
+    .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["output_ids"], serde_json::json!([72, 73]));
+    assert_eq!(body["output"], "");
+
+    let (_, body) =
+        infer(serde_json::json!({ "model_name": "dummy-a", "input_ids": [104, 105] })).await;
+    assert_eq!(body["output"], "HI");
+    assert!(body.get("output_ids").is_none());
+
+    let (_, body) = infer(serde_json::json!({
+        "model_name": "dummy-a",
+        "prompt": "hi",
+        "return_token_ids": true
+    }))
+    .await;
+    assert_eq!(body["output_ids"], serde_json::json!([72, 73]));
+
+    let (status, body) = infer(serde_json::json!({
+        "model_name": "dummy-a",
+        "prompt": "hi",
+        "input_ids": [104]
+    }))
+    .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["error"]["code"], "invalid_input");
+}
+
+#[rocket::async_test]
+async fn api_key_profile_is_applied() {
+    let figment = rocket::Config::figment().merge((
+        "api_keys.sk-team",
+        serde_json::json!({
+            "default_model": "dummy-a",
+            "allowed_models": ["dummy-a"],
+            "system_prompt": "be brief"
+        }),
+    ));
+    let client = client_with_config(test_state(), figment).await;
+    load(&client, "dummy-a").await;
+    load(&client, "dummy-b").await;
+
+    let infer = |key: &'static str, body: &'static str| {
+        let client = &client;
+        async move {
+            let resp = client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .header(rocket::http::Header::new("X-API-Key", key))
+                .body(body)
+                .dispatch()
+                .await;
+            (
+                resp.status(),
+                resp.into_json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+
+    // 没写 model_name 时用 profile 的默认模型，并强制加上 system prompt
+    let (status, body) = infer("sk-team", r#"{"prompt":"hi"}"#).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["model_name"], "dummy-a");
+    assert_eq!(body["output"], "[dummy-a DUMMY] BE BRIEF\n\nHI");
+
+    let (status, body) = infer("sk-team", r#"{"model_name":"dummy-b","prompt":"hi"}"#).await;
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(body["error"]["code"], "model_not_allowed");
+
+    let (status, body) = infer("sk-unknown", r#"{"prompt":"hi"}"#).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(body["error"]["code"], "invalid_api_key");
+}
+
+#[rocket::async_test]
+async fn required_api_key_protects_inference_and_management() {
+    let figment = rocket::Config::figment()
+        .merge(("require_api_key", true))
+        .merge(("api_keys.sk-team", serde_json::json!({})));
+    let client = client_with_config(test_state(), figment).await;
+
+    let load_model = |auth: Option<&'static str>| {
+        let client = &client;
+        async move {
+            let mut req = client
+                .post("/api/v1/load")
+                .header(ContentType::JSON)
+                .body(r#"{"model_name":"dummy-a","wait":true}"#);
+            if let Some(auth) = auth {
+                req = req.header(rocket::http::Header::new("Authorization", auth));
+            }
+            let resp = req.dispatch().await;
+            (
+                resp.status(),
+                resp.into_json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+
+    let (status, body) = load_model(None).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(body["error"]["code"], "api_key_required");
+    let (status, body) = load_model(Some("Bearer sk-other")).await;
+    assert_eq!(status, Status::Unauthorized);
+    assert_eq!(body["error"]["code"], "invalid_api_key");
+    let (status, body) = load_model(Some("Bearer sk-team")).await;
+    assert_eq!(status, Status::Ok, "{body}");
+
+    for path in [
+        "/admin/requests/active",
+        "/infer_stream?model_name=dummy-a&prompt=hi",
+        "/jobs",
+        "/jobs/1",
+        "/events",
+        "/routers",
+        "/tools",
+    ] {
+        let resp = client.get(path).dispatch().await;
+        assert_eq!(resp.status(), Status::Unauthorized, "{path}");
+        assert_eq!(resp.headers().get_one("WWW-Authenticate"), Some("Bearer"));
+    }
+    // Rocket 路由前会合并空段、percent-decode，这些写法同样要 key
+    for path in ["/%6Coad", "/api/v1//load", "/api//v1/load"] {
+        let resp = client
+            .post(path)
+            .header(ContentType::JSON)
+            .body(r#"{"model_name":"dummy-b","wait":true}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Unauthorized, "{path}");
+    }
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .header(rocket::http::Header::new("Authorization", "Bearer sk-team"))
+        .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+
+    // 健康检查仍然公开
+    assert_eq!(client.get("/health").dispatch().await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn clients_are_rate_limited_per_key() {
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .rate_limit(RateLimitConfig {
+            requests_per_minute: Some(2),
+            tokens_per_minute: None,
+        })
+        .build();
+    let figment = rocket::Config::figment()
+        .merge(("api_keys.sk-a", serde_json::json!({})))
+        .merge(("api_keys.sk-b", serde_json::json!({})));
+    let client = client_with_config(state, figment).await;
+    load(&client, "dummy-a").await;
+
+    let infer = |key: &'static str| {
+        let client = &client;
+        async move {
+            client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .header(rocket::http::Header::new("X-API-Key", key))
+                .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+                .dispatch()
+                .await
+        }
+    };
+
+    assert_eq!(infer("sk-a").await.status(), Status::Ok);
+    assert_eq!(infer("sk-a").await.status(), Status::Ok);
+    let resp = infer("sk-a").await;
+    assert_eq!(resp.status(), Status::TooManyRequests);
+    let retry_after: u64 = resp
+        .headers()
+        .get_one("Retry-After")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=30).contains(&retry_after), "{retry_after}");
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "rate_limited");
+
+    // 别的 key 有自己的额度，模型管理不限流
+    assert_eq!(infer("sk-b").await.status(), Status::Ok);
+    assert_eq!(client.get("/models").dispatch().await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn token_budget_is_charged_after_each_request() {
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .rate_limit(RateLimitConfig {
+            requests_per_minute: None,
+            tokens_per_minute: Some(5),
+        })
+        .build();
+    let client = client_with(state.clone()).await;
+    load(&client, "dummy-a").await;
+
+    let infer = || {
+        client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(r#"{"model_name":"dummy-a","prompt":"one two three four five six"}"#)
+            .dispatch()
+    };
+    assert_eq!(infer().await.status(), Status::Ok);
+    // 第一条就用完了这一分钟的 token
+    let resp = infer().await;
+    assert_eq!(resp.status(), Status::TooManyRequests);
+    assert!(resp.headers().get_one("Retry-After").is_some());
+}
+
+#[rocket::async_test]
+async fn metrics_report_permit_wait() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+    client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+
+    let resp = client.get("/metrics").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(resp
+        .content_type()
+        .is_some_and(|ct| ct.top() == "text" && ct.sub() == "plain"));
+    let body = resp.into_string().await.unwrap();
+    assert!(body.contains("# TYPE llm_permit_wait_seconds histogram"));
+    assert!(body.contains("llm_permit_wait_seconds_count 1\n"));
+    assert!(body.contains("llm_waiting_requests 0\n"));
+    assert!(
+        body.contains("llm_requests_total{model=\"dummy-a\",kind=\"collect\",outcome=\"ok\"} 1\n")
+    );
+    assert!(body.contains("llm_inference_duration_seconds_count{model=\"dummy-a\"} 1\n"));
+    assert!(body.contains("llm_tokens_generated_total{model=\"dummy-a\"} "));
+    assert!(body.contains("llm_inference_permits{state=\"in_use\"} 0\n"));
+    assert!(body.contains("# TYPE llm_inference_permits_total gauge"));
+}
+
+#[rocket::async_test]
+async fn request_tags_reach_the_request_log_and_metrics() {
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .tags(TagsConfig {
+            metric_keys: vec!["feature".to_string()],
+            ..TagsConfig::default()
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "dummy-a").await;
+
+    let tagged = r#"{"model_name":"dummy-a","prompt":"hi","tags":{"app":"notes","feature":"summarize"}}"#;
+    for path in ["/infer", "/infer?stream=true"] {
+        let resp = client
+            .post(path)
+            .header(ContentType::JSON)
+            .body(tagged)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        resp.into_string().await.unwrap();
+    }
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hi","tags":{"Bad Key":"x"}}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::UnprocessableEntity);
+
+    let metrics = client.get("/metrics").dispatch().await;
+    let metrics = metrics.into_string().await.unwrap();
+    assert!(metrics
+        .contains("llm_tagged_requests_total{tag=\"feature\",value=\"summarize\"} 2\n"));
+    assert!(!metrics.contains("value=\"notes\""));
+
+    let export = client.get("/admin/export?format=csv").dispatch().await;
+    let export = export.into_string().await.unwrap();
+    assert!(export.lines().next().unwrap().ends_with(",error,tags"));
+    assert_eq!(
+        export
+            .lines()
+            .filter(|line| line.ends_with(",\"app=notes,feature=summarize\""))
+            .count(),
+        2
+    );
+}
+
+#[rocket::async_test]
+async fn stream_reports_chunk_rate() {
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .streaming(StreamConfig {
+            stats_interval_ms: 60,
+            ..StreamConfig::default()
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "dummy-a").await;
+
+    let resp = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"a b c d"}"#)
+        .dispatch()
+        .await;
+    let body = resp.into_string().await.unwrap();
+    let stats: Vec<serde_json::Value> = body
+        .split("\n\n")
+        .filter(|chunk| chunk.starts_with("event:stats"))
+        .map(|chunk| serde_json::from_str(chunk.split_once("data:").unwrap().1.trim()).unwrap())
+        .collect();
+    // 中途至少一次，结束时再一次，最后一次是全部 chunk
+    assert!(stats.len() >= 2, "{body}");
+    let last = stats.last().unwrap();
+    assert_eq!(last["chunks"], 7);
+    assert!(last["average_chunks_per_second"].as_f64().unwrap() > 0.0);
+    // 文本 chunk 不受影响
+    assert_eq!(sse_data(&body).len(), 7);
+}
+
+#[rocket::async_test]
+async fn perf_reports_recent_requests() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+    for stream in ["false", "true"] {
+        let resp = client
+            .post(format!("/infer?stream={stream}"))
+            .header(ContentType::JSON)
+            .body(r#"{"model_name":"dummy-a","prompt":"a b"}"#)
+            .dispatch()
+            .await;
+        resp.into_string().await.unwrap();
+    }
+
+    let perf: serde_json::Value = client
+        .get("/models/dummy-a/perf")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(perf["last_hour"]["requests"], 2);
+    assert!(perf["last_day"]["latency_ms"]["p50"].as_f64().unwrap() >= 50.0);
+    assert!(
+        perf["last_hour"]["tokens_per_second"]["p95"]
+            .as_f64()
+            .unwrap()
+            > 0.0
+    );
+
+    // 注册了但还没有请求的模型返回空窗口，不存在的模型 404
+    let idle: serde_json::Value = client
+        .get("/models/dummy-b/perf")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(idle["last_day"]["requests"], 0);
+    assert!(idle["last_day"]["latency_ms"].is_null());
+    let missing = client.get("/models/nope/perf").dispatch().await;
+    assert_eq!(missing.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn high_priority_preempts_a_low_priority_stream() {
+    let client = client_with(AppState::with_registry(fake_registry(), 1)).await;
+    load(&client, "dummy-a").await;
+
+    let words: Vec<String> = (0..30).map(|i| format!("w{i}")).collect();
+    let low_body = serde_json::json!({
+        "model_name": "dummy-a",
+        "prompt": words.join(" "),
+        "max_tokens": 20,
+        "priority": "low",
+    })
+    .to_string();
+    let low = async {
+        let resp = client
+            .post("/infer?stream=true")
+            .header(ContentType::JSON)
+            .body(low_body)
+            .dispatch()
+            .await;
+        resp.into_string().await.unwrap()
+    };
+    let high = async {
+        rocket::tokio::time::sleep(Duration::from_millis(200)).await;
+        let started = Instant::now();
+        let resp = client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(r#"{"model_name":"dummy-a","prompt":"urgent","priority":"high"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        started.elapsed()
+    };
+    let (low, high_latency) = rocket::tokio::join!(low, high);
+
+    // 不抢占的话要等 low 的 20 个 chunk（约 1 秒）生成完
+    assert!(
+        high_latency < Duration::from_millis(500),
+        "{high_latency:?}"
+    );
+    // low 被抢占后接着生成，总数不变，也没有出错
+    let chunks = sse_data(&low);
+    assert_eq!(chunks.len(), 20, "{chunks:?}");
+    assert!(!low.contains("event:error"), "{low}");
+
+    let metrics = client
+        .get("/metrics")
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(metrics.contains("llm_preemptions_total 1"), "{metrics}");
+}
+
+#[rocket::async_test]
+async fn stream_can_be_paused_and_resumed() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let started = Instant::now();
+    let resp = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"one two three four five six"}"#)
+        .dispatch()
+        .await;
+    let id = resp.headers().get_one("X-Request-Id").unwrap().to_string();
+
+    // 一边读流一边暂停：已经输出的几个 chunk 之后停 600ms 再继续
+    let control = async {
+        rocket::tokio::time::sleep(Duration::from_millis(150)).await;
+        let paused: serde_json::Value = client
+            .post(format!("/infer/{id}/pause"))
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(paused["paused"], true);
+        assert_eq!(paused["model"], "dummy-a");
+        rocket::tokio::time::sleep(Duration::from_millis(600)).await;
+        let resumed = client.post(format!("/infer/{id}/resume")).dispatch().await;
+        assert_eq!(resumed.status(), Status::Ok);
+    };
+    // 9 个 chunk 本来约 450ms，暂停的时间要加上去，输出不受影响
+    let (body, ()) = rocket::tokio::join!(resp.into_string(), control);
+    let body = body.unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(900));
+    assert_eq!(sse_data(&body).len(), 9, "{body}");
+
+    // 结束的生成已经注销
+    let gone = client.post(format!("/infer/{id}/pause")).dispatch().await;
+    assert_eq!(gone.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn requests_can_be_cancelled_while_queued_or_running() {
+    let client = client_with(AppState::with_registry(fake_registry(), 1)).await;
+    load(&client, "dummy-a").await;
+
+    // 流式请求占着唯一的 permit，非流式请求带着自己的 id 排在后面
+    let stream = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"one two three four five six"}"#)
+        .dispatch()
+        .await;
+    let stream_id = stream.headers().get_one("X-Request-Id").unwrap().to_string();
+    let queued = async {
+        rocket::tokio::time::sleep(Duration::from_millis(40)).await;
+        client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .header(Header::new("X-Request-Id", "job-42"))
+            .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+            .dispatch()
+            .await
+    };
+    let control = async {
+        rocket::tokio::time::sleep(Duration::from_millis(120)).await;
+        let resp = client.delete("/requests/job-42").dispatch().await;
+        assert_eq!(resp.status(), Status::Ok);
+        let body: serde_json::Value = resp.into_json().await.unwrap();
+        assert_eq!(body["was"], "queued");
+        let resp = client.delete(format!("/requests/{stream_id}")).dispatch().await;
+        let body: serde_json::Value = resp.into_json().await.unwrap();
+        assert_eq!(body["was"], "running");
+    };
+    let (queued, body, ()) = rocket::tokio::join!(queued, stream.into_string(), control);
+
+    assert_eq!(queued.status().code, 499);
+    assert_eq!(queued.headers().get_one("X-Request-Id"), Some("job-42"));
+    let error: serde_json::Value = queued.into_json().await.unwrap();
+    assert_eq!(error["error"]["code"], "request_cancelled");
+
+    let body = body.unwrap();
+    let first: Vec<_> = body.split("\n\n").next().unwrap().lines().collect();
+    assert!(first.contains(&"event:request"), "{body}");
+    assert!(first.contains(&format!("data:{stream_id}").as_str()), "{body}");
+    assert!(body.contains("data:Error: request was cancelled"), "{body}");
+    assert!(sse_data(&body).len() < 9, "{body}");
+
+    // permit 已经还回来，取消不计入错误
+    let metrics = client.get("/metrics").dispatch().await;
+    let metrics = metrics.into_string().await.unwrap();
+    assert!(metrics.contains("llm_inference_permits{state=\"in_use\"} 0\n"));
+    let health: serde_json::Value = client
+        .get("/health")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(health["status"], "ok");
+    let gone = client.delete(format!("/requests/{stream_id}")).dispatch().await;
+    assert_eq!(gone.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn full_or_slow_queues_reject_with_the_queue_position() {
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .max_concurrent_infer(1)
+        .queue(QueueConfig {
+            max_len: Some(1),
+            max_wait_ms: 80,
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "dummy-a").await;
+
+    let stream = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"one two three four five six"}"#)
+        .dispatch()
+        .await;
+    let infer = |delay_ms: u64| {
+        let client = &client;
+        async move {
+            rocket::tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            let resp = client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+                .dispatch()
+                .await;
+            (
+                resp.status(),
+                resp.into_json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+    // 流式请求占着唯一的 permit，第一个请求排队，第二个发现队列满了
+    let (body, (waited, timed_out), (rejected, full)) =
+        rocket::tokio::join!(stream.into_string(), infer(20), infer(50));
+
+    assert_eq!(rejected, Status::TooManyRequests);
+    assert_eq!(full["error"]["code"], "queue_full");
+    assert_eq!(full["error"]["queue_position"], 2);
+    assert_eq!(waited, Status::ServiceUnavailable);
+    assert_eq!(timed_out["error"]["code"], "queue_timeout");
+    assert_eq!(timed_out["error"]["queue_position"], 1);
+
+    assert!(!sse_data(&body.unwrap()).is_empty());
+    let (status, _) = infer(0).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn preempted_generations_requeue_through_the_admission_queue() {
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .max_concurrent_infer(1)
+        .queue(QueueConfig {
+            max_len: None,
+            max_wait_ms: 30,
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "dummy-a").await;
+
+    let words: Vec<String> = (0..30).map(|i| format!("w{i}")).collect();
+    let low_body = serde_json::json!({
+        "model_name": "dummy-a",
+        "prompt": words.join(" "),
+        "max_tokens": 20,
+        "priority": "low",
+    })
+    .to_string();
+    let low = async {
+        let resp = client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(low_body)
+            .dispatch()
+            .await;
+        (
+            resp.status(),
+            resp.into_json::<serde_json::Value>().await.unwrap(),
+        )
+    };
+    let high = async {
+        rocket::tokio::time::sleep(Duration::from_millis(200)).await;
+        client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(r#"{"model_name":"dummy-a","prompt":"urgent","priority":"high"}"#)
+            .dispatch()
+            .await
+            .status()
+    };
+    let ((status, body), high) = rocket::tokio::join!(low, high);
+
+    // high 拿走 permit 后，low 重新排队，等不到 30ms 就按排队超时处理
+    assert_eq!(high, Status::Ok);
+    assert_eq!(status, Status::ServiceUnavailable, "{body}");
+    assert_eq!(body["error"]["code"], "queue_timeout");
+}
+
+#[rocket::async_test]
+async fn trace_flag_returns_per_token_latencies() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let infer = |trace: bool| {
+        let client = &client;
+        async move {
+            client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(
+                    serde_json::json!({
+                        "model_name": "dummy-a",
+                        "prompt": "hello world",
+                        "trace": trace
+                    })
+                    .to_string(),
+                )
+                .dispatch()
+                .await
+                .into_json::<serde_json::Value>()
+                .await
+                .unwrap()
+        }
+    };
+
+    let body = infer(true).await;
+    assert!(body["output"].as_str().unwrap().ends_with("HELLO WORLD"));
+    let trace = &body["trace"];
+    // DummyEngine 按词推送 5 个 chunk：第一个计入 first_token_ms，其余逐个计时
+    assert_eq!(trace["token_ms"].as_array().unwrap().len(), 4);
+    assert!(trace["first_token_ms"].as_f64().unwrap() >= 0.0);
+    assert!(trace["max_ms"].as_f64().unwrap() >= trace["p50_ms"].as_f64().unwrap());
+    assert!(trace["stalls"].is_array());
+
+    assert!(infer(false).await.get("trace").is_none());
+}
+
+#[rocket::async_test]
+async fn models_can_be_registered_at_runtime() {
+    let client = client().await;
+    let register = |body: serde_json::Value| {
+        let client = &client;
+        async move {
+            let resp = client
+                .post("/models")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+                .await;
+            (
+                resp.status(),
+                resp.into_json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+
+    let (status, body) = register(serde_json::json!({
+        "name": "my-model",
+        "engine_kind": "dummy",
+        "hf_repo": "someone/my-model-GGUF",
+        "hf_file": "my-model.Q4_K_M.gguf"
+    }))
+    .await;
+    assert_eq!(status, Status::Created);
+    assert_eq!(body["name"], "my-model");
+    assert_eq!(body["status"], "unloaded");
+    assert_eq!(body["engine_kind"], "dummy");
+
+    // 注册后可以正常加载和推理
+    load(&client, "my-model").await;
+    let output = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"my-model","prompt":"hi"}"#)
+        .dispatch()
+        .await
+        .into_json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(output["served_by"], "my-model");
+
+    // 已加载的同名模型不能替换
+    let (status, body) =
+        register(serde_json::json!({ "name": "my-model", "engine_kind": "dummy" })).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(body["error"]["code"], "model_exists");
+
+    for invalid in [
+        serde_json::json!({ "name": "auto", "engine_kind": "dummy" }),
+        serde_json::json!({ "name": "x", "engine_kind": "no-such-engine" }),
+        serde_json::json!({ "name": "x", "engine_kind": "dummy", "hf_repo": "a/b" }),
+        serde_json::json!({ "name": "x", "gguf": "/no/such/file.gguf" }),
+    ] {
+        let (status, body) = register(invalid).await;
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(body["error"]["code"], "invalid_input");
+    }
+    assert!(client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap()
+        .contains("my-model"));
+}
+
+#[rocket::async_test]
+async fn only_hub_models_can_be_pulled() {
+    let registry = fake_registry();
+    registry.register(ModelMetadata::new(
+        "local-gguf",
+        "/srv/models/local.gguf",
+        "q4_k_m",
+        EngineKind::CANDLE,
+    ));
+    let client = client_with(AppState::with_registry(registry, 2)).await;
+
+    for (name, status, error) in [
+        ("no-such-model", Status::NotFound, "model_not_found"),
+        ("dummy-a", Status::BadRequest, "not_pullable"),
+        ("local-gguf", Status::BadRequest, "not_pullable"),
+    ] {
+        let resp = client.post(format!("/models/{name}/pull")).dispatch().await;
+        assert_eq!(resp.status(), status, "{name}");
+        let body = resp.into_json::<serde_json::Value>().await.unwrap();
+        assert_eq!(body["error"]["code"], error, "{name}");
+    }
+
+    // 没有 pull 过的模型没有进度可看
+    let resp = client
+        .get("/models/local-gguf/pull/events")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn auto_template_wraps_raw_prompts_for_chat_models() {
+    let registry = fake_registry();
+    registry.register(
+        ModelMetadata::new("chat", "", "q4_k_m", EngineKind::DUMMY)
+            .with_chat_template(Some(ChatTemplate::Zephyr)),
+    );
+    let client = client_with(AppState::with_registry(registry, 2)).await;
+    load(&client, "chat").await;
+
+    let infer = |body: &'static str| {
+        let client = &client;
+        async move {
+            client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await
+                .into_json::<serde_json::Value>()
+                .await
+                .unwrap()
+        }
+    };
+
+    let wrapped = infer(r#"{"model_name":"chat","prompt":"hi","auto_template":true}"#).await;
+    assert_eq!(wrapped["applied_template"], "zephyr");
+    assert_eq!(
+        wrapped["output"],
+        "[chat DUMMY] <|USER|>\nHI</S>\n<|ASSISTANT|>\n"
+    );
+
+    // 已经带标记、或者没开 auto_template 时原样发给引擎
+    let body = r#"{"model_name":"chat","prompt":"[INST] hi [/INST]","auto_template":true}"#;
+    let marked = infer(body).await;
+    assert!(marked.get("applied_template").is_none());
+    assert_eq!(marked["output"], "[chat DUMMY] [INST] HI [/INST]");
+    let raw = infer(r#"{"model_name":"chat","prompt":"hi"}"#).await;
+    assert!(raw.get("applied_template").is_none());
+    assert_eq!(raw["output"], "[chat DUMMY] HI");
+}
+
+#[rocket::async_test]
+async fn every_response_carries_a_request_id() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let request_id = |resp: &rocket::local::asynchronous::LocalResponse<'_>| {
+        resp.headers().get_one("X-Request-Id").unwrap().to_string()
+    };
+    let first = request_id(&client.get("/health").dispatch().await);
+    let second = request_id(&client.get("/health").dispatch().await);
+    assert_eq!(first.len(), 36, "{first}");
+    assert_ne!(first, second);
+
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(request_id(&resp).len(), 36);
+
+    // 流式生成保留自己的 generation id，暂停 / 继续用的是它
+    let resp = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+    assert!(request_id(&resp).starts_with("gen-"));
+}
+
+#[rocket::async_test]
+async fn responses_report_token_usage() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    // Dummy 引擎把每个字节当一个 token
+    let body: serde_json::Value = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hello world"}"#)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body["output"], "[dummy-a DUMMY] HELLO WORLD");
+    assert_eq!(
+        body["usage"],
+        serde_json::json!({"prompt_tokens": 11, "completion_tokens": 27, "total_tokens": 38})
+    );
+
+    // 流式在结束前发一条 `event: usage`；Dummy 的流前面多一个 `[model=dummy-a]` chunk
+    let body = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"dummy-a","prompt":"hello world"}"#)
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    let usage = body
+        .split("\n\n")
+        .find(|event| event.lines().any(|line| line == "event:usage"))
+        .and_then(|event| event.lines().find_map(|line| line.strip_prefix("data:")))
+        .unwrap();
+    let usage: serde_json::Value = serde_json::from_str(usage).unwrap();
+    assert_eq!(usage["prompt_tokens"], 11);
+    assert_eq!(usage["completion_tokens"], 43);
+    assert_eq!(usage["total_tokens"], 54);
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rocket::http::{ContentType, Status};
+use rocket::tokio::sync::mpsc;
+
+use local_llm_server::app_state::AppState;
+use local_llm_server::engine::InferenceEngine;
+use local_llm_server::model_registry::{EngineKind, ModelMetadata};
+use local_llm_server::testing::{client_with, fake_registry, load, sse_data};
+
+/// 还没拿到工具结果时调用 calculator，拿到后把结果当作回答
+struct CalculatorAgent;
+
+#[async_trait]
+impl InferenceEngine for CalculatorAgent {
+    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+        Ok(match prompt.lines().rev().find(|l| l.starts_with("tool: ")) {
+            Some(line) => format!("The answer is {}", &line["tool: ".len()..]),
+            None => r#"<tool_call>{"name": "calculator", "arguments": {"expression": "6 * 7"}}</tool_call>"#
+                .to_string(),
+        })
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let _ = sender.send(self.generate(prompt, max_tokens).await?).await;
+        Ok(())
+    }
+}
+
+async fn agent_client() -> rocket::local::asynchronous::Client {
+    let registry = fake_registry();
+    registry.register(ModelMetadata::new(
+        "agent",
+        "",
+        "none",
+        EngineKind::new("agent"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("agent", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(CalculatorAgent) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "agent").await;
+    client
+}
+
+fn run_body(stream: bool) -> String {
+    serde_json::json!({
+        "model": "agent",
+        "messages": [{ "role": "user", "content": "what is 6 times 7?" }],
+        "tools": ["calculator"],
+        "stream": stream,
+    })
+    .to_string()
+}
+
+#[rocket::async_test]
+async fn run_executes_tool_calls_until_an_answer() {
+    let client = agent_client().await;
+    let resp = client
+        .post("/assistant/runs")
+        .header(ContentType::JSON)
+        .body(run_body(false))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["answer"], "The answer is 42");
+    assert_eq!(body["finish_reason"], "stop");
+    let types: Vec<_> = body["steps"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["tool_call", "tool_result", "answer"]);
+    assert_eq!(body["steps"][0]["arguments"]["expression"], "6 * 7");
+    assert_eq!(body["steps"][1]["output"], "42");
+}
+
+#[rocket::async_test]
+async fn run_streams_typed_events() {
+    let client = agent_client().await;
+    let resp = client
+        .post("/assistant/runs")
+        .header(ContentType::JSON)
+        .body(run_body(true))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body = resp.into_string().await.unwrap();
+    let events: Vec<_> = body
+        .lines()
+        .filter_map(|l| l.strip_prefix("event:"))
+        .map(str::trim)
+        .collect();
+    assert_eq!(events, ["tool_call", "tool_result", "answer"]);
+    let last: serde_json::Value = serde_json::from_str(sse_data(&body).last().unwrap()).unwrap();
+    assert_eq!(last["content"], "The answer is 42");
+}
+
+#[rocket::async_test]
+async fn run_rejects_unknown_tools() {
+    let client = agent_client().await;
+    let resp = client
+        .post("/assistant/runs")
+        .header(ContentType::JSON)
+        .body(
+            serde_json::json!({
+                "model": "agent",
+                "messages": [{ "role": "user", "content": "hi" }],
+                "tools": ["shell"],
+            })
+            .to_string(),
+        )
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "unknown_tool");
+}
+
+#[rocket::async_test]
+async fn tools_lists_registered_tools_with_limits() {
+    let client = agent_client().await;
+    let resp = client.get("/tools").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body[0]["name"], "calculator");
+    assert!(body[0]["limits"]["timeout_ms"].as_u64().unwrap() > 0);
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+
+use local_llm_server::app_state::AppState;
+use local_llm_server::catalog::Catalog;
+use local_llm_server::testing::{client_with, fake_registry};
+
+/// 用 dummy 引擎的目录，安装时不会去 hub 下载
+const CATALOG: &str = r#"
+[[models]]
+id = "tiny"
+name = "Tiny Test Model"
+repo = "acme/tiny-GGUF"
+tokenizer_repo = "acme/tiny"
+parameters = "0.1B"
+context_window = 1024
+default_quant = "Q4_K_M"
+engine_kind = "dummy"
+quants = [
+    { quantization = "Q4_K_M", file = "tiny.Q4_K_M.gguf", size_gb = 0.1, ram_gb = 0.5 },
+    { quantization = "Q8_0", file = "tiny.Q8_0.gguf", size_gb = 0.2, ram_gb = 0.6 },
+]
+"#;
+
+async fn catalog_client() -> Client {
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .catalog(Catalog::parse(CATALOG).unwrap())
+        .build();
+    client_with(state).await
+}
+
+async fn wait_for_job(client: &Client, job_id: &str) -> serde_json::Value {
+    let mut job = serde_json::Value::Null;
+    for _ in 0..50 {
+        job = client
+            .get(format!("/jobs/{job_id}"))
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        if job["status"] == "succeeded" || job["status"] == "failed" {
+            break;
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    job
+}
+
+#[rocket::async_test]
+async fn catalog_lists_entries() {
+    let client = catalog_client().await;
+    let body: serde_json::Value = client
+        .get("/catalog")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body[0]["id"], "tiny");
+    assert_eq!(body[0]["quants"][1]["ram_gb"], 0.6);
+}
+
+#[rocket::async_test]
+async fn install_registers_and_loads() {
+    let client = catalog_client().await;
+
+    let resp = client.post("/catalog/nope/install").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let resp = client
+        .post("/catalog/tiny/install?quant=Q3_K")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    let resp = client
+        .post("/catalog/tiny/install?quant=q8_0")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Accepted);
+    let job_id = resp.into_json::<serde_json::Value>().await.unwrap()["job_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let job = wait_for_job(&client, &job_id).await;
+    assert_eq!(job["status"], "succeeded", "{job}");
+    assert_eq!(job["result"]["quantization"], "Q8_0");
+    assert_eq!(job["result"]["status"], "loaded");
+
+    let models: serde_json::Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let tiny = models
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["name"] == "tiny")
+        .unwrap();
+    assert_eq!(tiny["status"], "loaded");
+
+    // 已经加载的模型不能再装一次
+    let resp = client.post("/catalog/tiny/install").dispatch().await;
+    assert_eq!(resp.status(), Status::Conflict);
+}
+
+#[rocket::async_test]
+async fn benchmark_compares_quants_one_at_a_time() {
+    let client = catalog_client().await;
+
+    let resp = client
+        .post("/catalog/tiny/benchmark")
+        .json(&serde_json::json!({ "quants": ["Q3_K"] }))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    let resp = client
+        .post("/catalog/tiny/benchmark")
+        .json(&serde_json::json!({
+            "quants": ["q8_0", "Q4_K_M"],
+            "max_tokens": 8,
+            "cases": [{ "prompt": "capital of france", "reference": "Paris" }],
+        }))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Accepted);
+    let job_id = resp.into_json::<serde_json::Value>().await.unwrap()["job_id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let job = wait_for_job(&client, &job_id).await;
+    assert_eq!(job["status"], "succeeded", "{job}");
+
+    let report = &job["result"];
+    assert_eq!(report["cases"], 1);
+    let results = report["results"].as_array().unwrap();
+    assert_eq!(results[0]["quantization"], "Q8_0");
+    assert_eq!(results[1]["quantization"], "Q4_K_M");
+    for result in results {
+        assert_eq!(result["generated_tokens"], 8, "{result}");
+        assert!(result["tokens_per_second"].as_f64().unwrap() > 0.0);
+        assert!(result["mean_logprob"].as_f64().unwrap() < 0.0);
+        assert_eq!(result["logprob_delta"], 0.0);
+    }
+    assert!(report["best_quality"].is_string());
+
+    // 测完的临时模型不留在 registry 里
+    let models: serde_json::Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert!(models
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|m| !m["name"].as_str().unwrap().starts_with("tiny@")));
+}
@@ -0,0 +1,56 @@
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use rocket::http::{ContentType, Header, Status};
+
+use local_llm_server::testing::{client_with_config, load, test_state};
+
+fn figment() -> rocket::figment::Figment {
+    rocket::Config::figment().merge(("compression_min_bytes", 0))
+}
+
+#[rocket::async_test]
+async fn json_responses_are_gzipped_when_accepted() {
+    let client = client_with_config(test_state(), figment()).await;
+
+    let resp = client
+        .get("/models")
+        .header(Header::new("Accept-Encoding", "gzip"))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.headers().get_one("Content-Encoding"), Some("gzip"));
+
+    let bytes = resp.into_bytes().await.unwrap();
+    let mut json = String::new();
+    GzDecoder::new(&bytes[..])
+        .read_to_string(&mut json)
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(body.as_array().unwrap().len(), 2);
+}
+
+#[rocket::async_test]
+async fn no_accept_encoding_means_identity() {
+    let client = client_with_config(test_state(), figment()).await;
+
+    let resp = client.get("/models").dispatch().await;
+    assert_eq!(resp.headers().get_one("Content-Encoding"), None);
+}
+
+#[rocket::async_test]
+async fn sse_streams_are_not_compressed() {
+    let client = client_with_config(test_state(), figment()).await;
+    load(&client, "dummy-a").await;
+
+    let resp = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .header(Header::new("Accept-Encoding", "gzip"))
+        .body(r#"{"model_name":"dummy-a","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.content_type(), Some(ContentType::EventStream));
+    assert_eq!(resp.headers().get_one("Content-Encoding"), None);
+    assert!(resp.into_string().await.unwrap().contains("HI"));
+}
@@ -0,0 +1,95 @@
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{Device, Tensor};
+use rocket::http::Status;
+use serde_json::Value;
+
+use local_llm_server::app_state::AppState;
+use local_llm_server::discovery::DiscoveryConfig;
+use local_llm_server::testing::{client_with, fake_registry};
+
+fn write_gguf(path: &std::path::Path, metadata: &[(&str, gguf_file::Value)]) {
+    let tensor = Tensor::zeros((4, 32), candle_core::DType::F32, &Device::Cpu).unwrap();
+    let qtensor = QTensor::quantize(&tensor, GgmlDType::F32).unwrap();
+    let metadata: Vec<_> = metadata.iter().map(|(k, v)| (*k, v)).collect();
+    let mut file = std::fs::File::create(path).unwrap();
+    gguf_file::write(&mut file, &metadata, &[("w", &qtensor)]).unwrap();
+}
+
+fn llama(context_length: u32) -> Vec<(&'static str, gguf_file::Value)> {
+    vec![
+        (
+            "general.architecture",
+            gguf_file::Value::String("llama".to_string()),
+        ),
+        ("general.file_type", gguf_file::Value::U32(15)),
+        (
+            "llama.context_length",
+            gguf_file::Value::U32(context_length),
+        ),
+    ]
+}
+
+async fn rescan(client: &rocket::local::asynchronous::Client) -> Value {
+    let resp = client.post("/models/rescan").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    resp.into_json().await.unwrap()
+}
+
+#[rocket::async_test]
+async fn rescan_registers_and_removes_gguf_files() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("nested")).unwrap();
+    let mistral = dir
+        .path()
+        .join("nested/Mistral-7B-Instruct-v0.1.Q4_K_M.gguf");
+    write_gguf(&mistral, &llama(8192));
+    // 和 fake_registry 里的 dummy-a 重名，加上量化后缀
+    write_gguf(&dir.path().join("dummy-a.gguf"), &llama(2048));
+    write_gguf(
+        &dir.path().join("phi-2.Q8_0.gguf"),
+        &[(
+            "general.architecture",
+            gguf_file::Value::String("phi2".to_string()),
+        )],
+    );
+    std::fs::write(dir.path().join("broken.gguf"), b"not gguf").unwrap();
+
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .discovery(DiscoveryConfig {
+            dir: dir.path().to_path_buf(),
+            on_startup: false,
+        })
+        .build();
+    let client = client_with(state.clone()).await;
+
+    let report = rescan(&client).await;
+    let added: Vec<&str> = report["added"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(added, ["dummy-a-q4_k_m", "mistral-7b-instruct-v0.1"]);
+    assert_eq!(report["skipped"].as_array().unwrap().len(), 2);
+    let meta = state
+        .registry
+        .get_model("mistral-7b-instruct-v0.1")
+        .unwrap();
+    assert_eq!(meta.quantization, "q4_k_m");
+    assert_eq!(meta.context_window, 8192);
+    assert_eq!(meta.path, mistral.display().to_string());
+
+    // 已经注册的文件不重复注册
+    let report = rescan(&client).await;
+    assert!(report["added"].as_array().unwrap().is_empty());
+
+    std::fs::remove_file(&mistral).unwrap();
+    let report = rescan(&client).await;
+    assert_eq!(report["removed"][0], "mistral-7b-instruct-v0.1");
+    assert!(state
+        .registry
+        .get_model("mistral-7b-instruct-v0.1")
+        .is_none());
+    assert!(state.registry.get_model("dummy-a-q4_k_m").is_some());
+}
@@ -0,0 +1,1487 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rocket::http::{ContentType, Status};
+use rocket::tokio::sync::mpsc;
+
+use local_llm_server::app_state::{AppState, AppStateBuilder, LoadRetryPolicy};
+use local_llm_server::device::DeviceSpec;
+use local_llm_server::engine::{
+    CacheStats, FinishReason, Generation, InferenceEngine, SamplingParams,
+};
+use local_llm_server::events::ModelEvent;
+use local_llm_server::kv_budget::KvBudgetConfig;
+use local_llm_server::model_registry::{EngineKind, ModelMetadata, ModelRegistry, ModelStatus};
+use local_llm_server::self_test::SelfTestConfig;
+use local_llm_server::standby::StandbyConfig;
+use local_llm_server::time_slice::TimeSliceConfig;
+use local_llm_server::testing::{client_with, load, sse_data};
+
+/// 下游自定义引擎：原样回显 prompt
+struct EchoEngine;
+
+#[async_trait]
+impl InferenceEngine for EchoEngine {
+    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+        Ok(format!("echo: {prompt}"))
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        _max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let _ = sender.send(prompt.to_string()).await;
+        Ok(())
+    }
+}
+
+fn echo_state() -> Arc<AppState> {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "echo",
+        "",
+        "none",
+        EngineKind::new("echo"),
+    ));
+    registry.register(ModelMetadata::new(
+        "orphan",
+        "",
+        "none",
+        EngineKind::new("unknown"),
+    ));
+
+    AppState::builder()
+        .registry(registry)
+        .max_concurrent_infer(1)
+        .engine_factory("echo", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(EchoEngine) as Arc<dyn InferenceEngine>)
+        })
+        .build()
+}
+
+#[rocket::async_test]
+async fn custom_engine_is_loaded_via_factory() {
+    let client = client_with(echo_state()).await;
+
+    let loaded = load(&client, "echo").await;
+    assert_eq!(loaded["status"], "loaded");
+
+    let body: serde_json::Value = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"echo","prompt":"hi"}"#)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body["output"], "echo: hi");
+}
+
+#[rocket::async_test]
+async fn unknown_engine_kind_fails_to_load() {
+    let state = echo_state();
+    let err = state.load_model("orphan").unwrap_err();
+    assert!(!err.is_transient());
+    assert!(err
+        .to_string()
+        .contains("no engine factory registered for kind `unknown`"));
+    // 加载失败的模型标记为 Error，而不是一直停在 Loading
+    assert!(matches!(
+        state.registry.get_model("orphan").unwrap().status,
+        ModelStatus::Error
+    ));
+}
+
+#[rocket::async_test]
+async fn failed_load_exposes_error_details() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "broken",
+        "",
+        "none",
+        EngineKind::new("broken"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .load_retry(LoadRetryPolicy::no_retry())
+        .engine_factory("broken", |_meta: &ModelMetadata, _device| {
+            Err(anyhow::anyhow!("weights are corrupt"))
+        })
+        .build();
+    let client = client_with(state).await;
+
+    let body = load(&client, "broken").await;
+    assert_eq!(body["status"], "error");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("weights are corrupt"));
+    assert_eq!(body["error"]["attempts"], 1);
+
+    let body = load(&client, "broken").await;
+    assert_eq!(body["error"]["attempts"], 2);
+
+    let models: serde_json::Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(models[0]["status"], "error");
+    assert_eq!(models[0]["error"]["attempts"], 2);
+}
+
+/// 前 `failures` 次构造失败，之后成功
+fn flaky_state(failures: usize, max_attempts: u32) -> Arc<AppState> {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "flaky",
+        "",
+        "none",
+        EngineKind::new("flaky"),
+    ));
+    let calls = AtomicUsize::new(0);
+    AppState::builder()
+        .registry(registry)
+        .load_retry(LoadRetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(5),
+            max_backoff: Duration::from_millis(20),
+            multiplier: 2.0,
+        })
+        .engine_factory("flaky", move |_meta: &ModelMetadata, _device| {
+            let n = calls.fetch_add(1, Ordering::SeqCst);
+            if n < failures {
+                Err(anyhow::anyhow!("network hiccup #{}", n + 1))
+            } else {
+                Ok(Arc::new(EchoEngine) as Arc<dyn InferenceEngine>)
+            }
+        })
+        .build()
+}
+
+async fn wait_for_settled(
+    mut events: rocket::tokio::sync::broadcast::Receiver<ModelEvent>,
+) -> Vec<ModelEvent> {
+    let mut seen = Vec::new();
+    loop {
+        let event = rocket::tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("load settled in time")
+            .unwrap();
+        let done = matches!(event, ModelEvent::Loaded { .. } | ModelEvent::GaveUp { .. });
+        seen.push(event);
+        if done {
+            return seen;
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn transient_failures_are_retried_until_loaded() {
+    let state = flaky_state(2, 5);
+    let events = state.events.subscribe();
+
+    assert!(state.load_model_with_retries("flaky").is_err());
+    let seen = wait_for_settled(events).await;
+
+    assert_eq!(
+        seen.last(),
+        Some(&ModelEvent::Loaded {
+            model: "flaky".to_string(),
+            attempt: 3
+        })
+    );
+    let scheduled = seen
+        .iter()
+        .filter(|e| matches!(e, ModelEvent::RetryScheduled { .. }))
+        .count();
+    assert_eq!(scheduled, 2);
+
+    let meta = state.registry.get_model("flaky").unwrap();
+    assert_eq!(meta.status, ModelStatus::Loaded);
+    assert!(meta.error.is_none());
+}
+
+#[rocket::async_test]
+async fn retries_give_up_with_accumulated_reasons() {
+    let state = flaky_state(usize::MAX, 3);
+    let events = state.events.subscribe();
+
+    assert!(state.load_model_with_retries("flaky").is_err());
+    let seen = wait_for_settled(events).await;
+
+    match seen.last().unwrap() {
+        ModelEvent::GaveUp { reasons, .. } => {
+            assert_eq!(reasons.len(), 3);
+            assert!(reasons[2].contains("network hiccup #3"));
+        }
+        other => panic!("unexpected final event: {other:?}"),
+    }
+    let meta = state.registry.get_model("flaky").unwrap();
+    assert_eq!(meta.status, ModelStatus::Error);
+    assert_eq!(meta.error.unwrap().attempts, 3);
+}
+
+/// 重试在 loader 线程池里构造引擎，慢的加载不会卡住 tokio 的 worker
+#[rocket::async_test]
+async fn retries_do_not_block_the_runtime() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "slow",
+        "",
+        "none",
+        EngineKind::new("slow"),
+    ));
+    let calls = AtomicUsize::new(0);
+    let state = AppState::builder()
+        .registry(registry)
+        .load_retry(LoadRetryPolicy {
+            max_attempts: 2,
+            initial_backoff: Duration::from_millis(5),
+            max_backoff: Duration::from_millis(5),
+            multiplier: 1.0,
+        })
+        .engine_factory("slow", move |_meta: &ModelMetadata, _device| {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err(anyhow::anyhow!("network hiccup"));
+            }
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(Arc::new(EchoEngine) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let events = state.events.subscribe();
+
+    // 测试 runtime 只有一个 worker：重试阻塞它的话，这个任务的 tick 会停 300ms
+    let ticker = rocket::tokio::spawn(async {
+        let mut longest = Duration::ZERO;
+        for _ in 0..40 {
+            let started = std::time::Instant::now();
+            rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+            longest = longest.max(started.elapsed());
+        }
+        longest
+    });
+    assert!(state.load_model_with_retries("slow").is_err());
+    let seen = wait_for_settled(events).await;
+    assert!(matches!(seen.last(), Some(ModelEvent::Loaded { .. })));
+    let longest = ticker.await.unwrap();
+    assert!(longest < Duration::from_millis(200), "{longest:?}");
+}
+
+/// scratch 加载同样不占 tokio 的 worker
+#[rocket::async_test]
+async fn scratch_loads_do_not_block_the_runtime() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "slow",
+        "",
+        "none",
+        EngineKind::new("slow"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("slow", |_meta: &ModelMetadata, _device| {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(Arc::new(EchoEngine) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+
+    let ticker = rocket::tokio::spawn(async {
+        let mut longest = Duration::ZERO;
+        for _ in 0..40 {
+            let started = std::time::Instant::now();
+            rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+            longest = longest.max(started.elapsed());
+        }
+        longest
+    });
+    rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+    // 像 /load handler 一样在 worker 上调用
+    let meta = rocket::tokio::spawn(async move {
+        state
+            .load_scratch("slow", None, Duration::from_secs(60))
+            .await
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(meta.status, ModelStatus::Loaded);
+    let longest = ticker.await.unwrap();
+    assert!(longest < Duration::from_millis(200), "{longest:?}");
+}
+
+/// 按设备区分输出的引擎
+struct DeviceEngine(DeviceSpec);
+
+#[async_trait]
+impl InferenceEngine for DeviceEngine {
+    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+        Ok(format!("{} on {}", prompt, self.0))
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        _max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let _ = sender.send(format!("{} on {}", prompt, self.0)).await;
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn request_device_selects_placement() {
+    let registry = ModelRegistry::empty();
+    registry.register(
+        ModelMetadata::new("multi", "", "none", EngineKind::new("device"))
+            .with_placements(vec![DeviceSpec::Cuda(0), DeviceSpec::Cpu]),
+    );
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("device", |_meta: &ModelMetadata, device| {
+            Ok(Arc::new(DeviceEngine(device)) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "multi").await;
+
+    let infer = |body: &'static str| {
+        let client = &client;
+        async move {
+            let v: serde_json::Value = client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(body)
+                .dispatch()
+                .await
+                .into_json()
+                .await
+                .unwrap();
+            // 失败时（422 device_unavailable）取错误信息
+            v["output"]
+                .as_str()
+                .or(v["error"]["message"].as_str())
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    assert_eq!(
+        infer(r#"{"model_name":"multi","prompt":"x"}"#).await,
+        "x on cuda:0"
+    );
+    assert_eq!(
+        infer(r#"{"model_name":"multi","prompt":"x","device":"cpu"}"#).await,
+        "x on cpu"
+    );
+    assert!(
+        infer(r#"{"model_name":"multi","prompt":"x","device":"metal:0"}"#)
+            .await
+            .contains("not available on metal:0 (placements: cuda:0, cpu)")
+    );
+
+    let body = client
+        .get("/infer_stream?model_name=multi&prompt=y&device=cpu")
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert!(body.contains("y on cpu"));
+
+    let resp = client
+        .get("/infer_stream?model_name=multi&prompt=y&device=tpu")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), rocket::http::Status::UnprocessableEntity);
+}
+
+#[rocket::async_test]
+async fn load_request_overrides_placements() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "multi",
+        "",
+        "none",
+        EngineKind::new("device"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("device", |_meta: &ModelMetadata, device| {
+            Ok(Arc::new(DeviceEngine(device)) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+
+    let loaded: serde_json::Value = client
+        .post("/load")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"multi","wait":true,"placements":["cuda:1"]}"#)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(loaded["status"], "loaded");
+
+    let out: serde_json::Value = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"multi","prompt":"x"}"#)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(out["output"], "x on cuda:1");
+
+    let models: serde_json::Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(models[0]["placements"], serde_json::json!(["cuda:1"]));
+}
+
+/// 记住上一个 prompt 的“KV cache”，用来测试 cache 统计接口
+#[derive(Default)]
+struct CachingEngine {
+    cached: parking_lot::Mutex<usize>,
+}
+
+#[async_trait]
+impl InferenceEngine for CachingEngine {
+    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+        *self.cached.lock() = prompt.split_whitespace().count();
+        Ok(prompt.to_string())
+    }
+
+    async fn generate_stream(
+        &self,
+        _prompt: &str,
+        _max_tokens: usize,
+        _sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        let cached_tokens = *self.cached.lock();
+        Some(CacheStats {
+            cached_tokens,
+            block_size: 1,
+            blocks_allocated: cached_tokens,
+            blocks_free: 100 - cached_tokens,
+            ..CacheStats::default()
+        })
+    }
+
+    fn clear_cache(&self) -> Result<()> {
+        *self.cached.lock() = 0;
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn cache_stats_and_clear() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "cached",
+        "",
+        "none",
+        EngineKind::new("caching"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("caching", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(CachingEngine::default()) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+
+    let resp = client.get("/models/cached/cache").dispatch().await;
+    assert_eq!(resp.status(), rocket::http::Status::NotFound);
+
+    load(&client, "cached").await;
+    client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"cached","prompt":"one two three"}"#)
+        .dispatch()
+        .await;
+
+    let body: serde_json::Value = client
+        .get("/models/cached/cache")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body["replicas"][0]["device"], "cpu");
+    assert_eq!(body["replicas"][0]["stats"]["cached_tokens"], 3);
+
+    let body: serde_json::Value = client
+        .post("/models/cached/cache/clear")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(body["replicas"][0]["stats"]["cached_tokens"], 0);
+    assert_eq!(body["replicas"][0]["stats"]["blocks_free"], 100);
+}
+
+/// 清理 KV cache 要等一会儿的引擎（像 candle 等正在跑的 decode 释放锁）
+struct SlowClearEngine;
+
+#[async_trait]
+impl InferenceEngine for SlowClearEngine {
+    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+        Ok(prompt.to_string())
+    }
+
+    async fn generate_stream(
+        &self,
+        _prompt: &str,
+        _max_tokens: usize,
+        _sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn clear_cache(&self) -> Result<()> {
+        std::thread::sleep(Duration::from_millis(300));
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn cache_clear_does_not_block_the_runtime() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "slow-clear",
+        "",
+        "none",
+        EngineKind::new("slow-clear"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("slow-clear", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(SlowClearEngine) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "slow-clear").await;
+
+    let ticker = rocket::tokio::spawn(async {
+        let mut longest = Duration::ZERO;
+        for _ in 0..40 {
+            let started = std::time::Instant::now();
+            rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+            longest = longest.max(started.elapsed());
+        }
+        longest
+    });
+    rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+    // handler 跑在 worker 上
+    let status = rocket::tokio::spawn(async move {
+        client
+            .post("/models/slow-clear/cache/clear")
+            .dispatch()
+            .await
+            .status()
+    })
+    .await
+    .unwrap();
+    assert_eq!(status, rocket::http::Status::Ok);
+    let longest = ticker.await.unwrap();
+    assert!(longest < Duration::from_millis(200), "{longest:?}");
+}
+
+/// 推了一段之后失败的流式引擎
+struct BrokenStreamEngine;
+
+#[async_trait]
+impl InferenceEngine for BrokenStreamEngine {
+    async fn generate(&self, _prompt: &str, _max_tokens: usize) -> Result<String> {
+        anyhow::bail!("device lost")
+    }
+
+    async fn generate_stream(
+        &self,
+        _prompt: &str,
+        _max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let _ = sender.send("partial".to_string()).await;
+        anyhow::bail!("device lost")
+    }
+}
+
+#[rocket::async_test]
+async fn stream_failure_ends_with_error_event() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "broken",
+        "",
+        "none",
+        EngineKind::new("broken"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("broken", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(BrokenStreamEngine) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state.clone()).await;
+    load(&client, "broken").await;
+
+    let body = client
+        .post("/infer?stream=true")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"broken","prompt":"hi"}"#)
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    assert_eq!(sse_data(&body), ["partial", "Error: device lost"]);
+    assert!(body.contains("event:error\n"), "{body}");
+    assert_eq!(state.metrics.stream_errors(), 1);
+
+    let body = client
+        .post("/v1/chat/completions")
+        .header(ContentType::JSON)
+        .body(r#"{"model":"broken","messages":[{"role":"user","content":"hi"}],"stream":true}"#)
+        .dispatch()
+        .await
+        .into_string()
+        .await
+        .unwrap();
+    let events = sse_data(&body);
+    let error: serde_json::Value = serde_json::from_str(events.last().unwrap()).unwrap();
+    assert_eq!(error["error"]["message"], "device lost");
+    assert!(!events.iter().any(|e| e == "[DONE]"));
+    assert_eq!(state.metrics.stream_errors(), 2);
+}
+
+fn tiny_state() -> Arc<AppState> {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new("tiny", "", "q8_0", EngineKind::TINY));
+    AppState::builder().registry(registry).build()
+}
+
+/// 内置权重由固定 seed 生成、greedy 解码，输出没有语义但逐字节稳定；
+/// 改了 `tiny` 的权重生成或解码逻辑时需要更新这里
+#[rocket::async_test]
+async fn tiny_model_matches_golden_outputs() {
+    let client = client_with(tiny_state()).await;
+    assert_eq!(load(&client, "tiny").await["status"], "loaded");
+
+    let golden = [
+        ("Hello", "b7N7NH`NNNNNNNNNHmHmHm=N"),
+        ("The quick brown fox", "hLPjMhjaMM>fWfQj0OjMPzAb"),
+    ];
+    for (prompt, expected) in golden {
+        let request =
+            serde_json::json!({ "model_name": "tiny", "prompt": prompt, "max_tokens": 24 });
+        let body: serde_json::Value = client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(request.to_string())
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(body["output"], expected, "{prompt}");
+        assert_eq!(body["finish_reason"], "length");
+
+        // 流式走同一条生成路径（输出里没有空白，只有一个 chunk）
+        let body = client
+            .post("/infer?stream=true")
+            .header(ContentType::JSON)
+            .body(request.to_string())
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert_eq!(sse_data(&body), [expected]);
+    }
+}
+
+#[rocket::async_test]
+async fn self_test_gates_loading() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new("dummy", "", "none", EngineKind::DUMMY));
+    registry.register(ModelMetadata::new("tiny", "", "q8_0", EngineKind::TINY));
+    let state = AppState::builder()
+        .registry(registry)
+        .load_retry(LoadRetryPolicy::no_retry())
+        .self_test(SelfTestConfig {
+            enabled: true,
+            ..SelfTestConfig::default()
+        })
+        .build();
+    let client = client_with(state).await;
+
+    assert_eq!(load(&client, "dummy").await["status"], "loaded");
+    // tiny 的随机权重不会在 32 个 token 以内输出结束符
+    let failed = load(&client, "tiny").await;
+    assert_eq!(failed["status"], "error");
+    assert!(
+        failed["message"]
+            .as_str()
+            .unwrap()
+            .contains("failed its self-test: greeting/eos"),
+        "{failed}"
+    );
+
+    let models: serde_json::Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let model = |name: &str| {
+        models
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["name"] == name)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(model("dummy")["self_test"]["passed"], true);
+    let tiny = model("tiny");
+    assert_eq!(tiny["status"], "error");
+    assert_eq!(tiny["self_test"]["passed"], false);
+    assert_eq!(tiny["self_test"]["checks"][0]["name"], "greeting/non_empty");
+    assert_eq!(tiny["self_test"]["checks"][0]["passed"], true);
+}
+
+#[rocket::async_test]
+async fn load_returns_immediately_and_finishes_in_background() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "slow",
+        "",
+        "none",
+        EngineKind::new("slow"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("slow", |_meta: &ModelMetadata, _device| {
+            // 模拟读 GGUF
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(Arc::new(EchoEngine) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let events = state.events.subscribe();
+    let client = client_with(state.clone()).await;
+
+    let resp = client
+        .post("/load")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"slow"}"#)
+        .dispatch()
+        .await;
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["status"], "loading");
+
+    // 加载期间的请求被拒绝，再次加载也一样
+    let resp = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(r#"{"model_name":"slow","prompt":"hi"}"#)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Conflict);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert!(
+        body["error"]["message"].as_str().unwrap().contains("not loaded"),
+        "{body}"
+    );
+    let again = load(&client, "slow").await;
+    assert_eq!(again["status"], "loading");
+    assert!(again["message"].as_str().unwrap().contains("Loading"));
+
+    wait_for_settled(events).await;
+    assert_eq!(
+        state.registry.get_model("slow").unwrap().status,
+        ModelStatus::Loaded
+    );
+}
+
+/// 每个 token 1 MiB KV cache 的慢引擎
+struct WideKvEngine;
+
+#[async_trait]
+impl InferenceEngine for WideKvEngine {
+    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+        rocket::tokio::time::sleep(Duration::from_millis(100)).await;
+        Ok(prompt.to_string())
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        _max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let _ = sender.send(prompt.to_string()).await;
+        Ok(())
+    }
+
+    fn kv_bytes_per_token(&self) -> Option<u64> {
+        Some(1 << 20)
+    }
+}
+
+#[rocket::async_test]
+async fn kv_budget_refuses_long_contexts_instead_of_overcommitting() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "wide",
+        "",
+        "none",
+        EngineKind::new("wide"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("wide", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(WideKvEngine) as Arc<dyn InferenceEngine>)
+        })
+        .kv_budget(KvBudgetConfig {
+            soft_limit_mb: Some(300),
+            queue_timeout_ms: 0,
+        })
+        .build();
+    let client = client_with(state.clone()).await;
+    load(&client, "wide").await;
+
+    let infer = |max_tokens: usize| {
+        let client = &client;
+        async move {
+            let resp = client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{"model_name":"wide","prompt":"x","max_tokens":{max_tokens}}}"#
+                ))
+                .dispatch()
+                .await;
+            (
+                resp.status(),
+                resp.into_json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+
+    // 单条就超过上限：413，重试也没用
+    let (status, body) = infer(400).await;
+    assert_eq!(status, Status::PayloadTooLarge);
+    assert_eq!(body["error"]["code"], "context_too_large");
+
+    // 第一条占着约 200 MiB 时，第二条放不下：429，稍后重试
+    let second = async {
+        rocket::tokio::time::sleep(Duration::from_millis(30)).await;
+        infer(200).await
+    };
+    let ((first_status, _), (status, body)) = rocket::tokio::join!(infer(200), second);
+    assert_eq!(first_status, Status::Ok);
+    assert_eq!(status, Status::TooManyRequests);
+    assert_eq!(body["error"]["code"], "memory_busy");
+    assert!(body["error"]["message"].as_str().unwrap().contains("retry later"));
+
+    // 第一条结束后归还
+    assert_eq!(state.kv_budget.in_use_bytes(), 0);
+    let (status, _) = infer(200).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn candle_models_load_from_the_registry_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let gguf = dir.path().join("tiny.gguf");
+    std::fs::write(&gguf, local_llm_server::tiny::builtin_gguf().unwrap()).unwrap();
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "local",
+        gguf.to_str().unwrap(),
+        "q8_0",
+        EngineKind::CANDLE,
+    ));
+    let state = AppState::with_registry(registry, 1);
+
+    // 没有 tokenizer.json：报本地路径的错，不会去 hub 下载默认模型
+    let err = state.load_model("local").unwrap_err().to_string();
+    let tokenizer = dir.path().join("tokenizer.json");
+    assert!(err.contains(&tokenizer.display().to_string()), "{err}");
+}
+
+#[rocket::async_test]
+async fn offline_mode_reports_missing_hub_files() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "remote",
+        "hf://nobody/not-downloaded-GGUF/model.Q4_K_M.gguf",
+        "q4_k_m",
+        EngineKind::CANDLE,
+    ));
+    let state = AppState::builder().registry(registry).offline(true).build();
+
+    // 缓存里没有时直接报错，不去连 hub
+    let err = state.load_model("remote").unwrap_err().to_string();
+    assert!(err.contains("offline mode"), "{err}");
+    assert!(
+        err.contains("nobody/not-downloaded-GGUF/model.Q4_K_M.gguf"),
+        "{err}"
+    );
+
+    let client = client_with(state).await;
+    let resp = client.post("/models/remote/pull").dispatch().await;
+    assert_eq!(resp.status(), Status::Conflict);
+}
+
+/// 输出就是采样用的 seed，方便看出重试换了参数
+struct SeedEngine;
+
+#[async_trait]
+impl InferenceEngine for SeedEngine {
+    async fn generate(&self, _prompt: &str, _max_tokens: usize) -> Result<String> {
+        Ok("seed=none".to_string())
+    }
+
+    async fn complete_sampled(
+        &self,
+        _prompt: &str,
+        _max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<Generation> {
+        Ok(Generation {
+            text: format!("seed={}", sampling.seed.unwrap_or(0)),
+            finish_reason: FinishReason::Stop,
+        })
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let _ = sender.send(self.generate(prompt, max_tokens).await?).await;
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn invalid_outputs_are_retried_with_new_sampling() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new("seeded", "", "none", EngineKind::new("seed")));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("seed", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(SeedEngine) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "seeded").await;
+
+    let infer = |validate: serde_json::Value| {
+        let client = &client;
+        async move {
+            let resp = client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(
+                    serde_json::json!({
+                        "model_name": "seeded",
+                        "prompt": "x",
+                        "validate": validate
+                    })
+                    .to_string(),
+                )
+                .dispatch()
+                .await;
+            (
+                resp.status(),
+                resp.into_json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+
+    // 第三次（seed 2）才通过
+    let (status, body) = infer(serde_json::json!({
+        "check": { "kind": "regex", "pattern": "^seed=2$" },
+        "retries": 3
+    }))
+    .await;
+    assert_eq!(status, Status::Ok, "{body}");
+    assert_eq!(body["output"], "seed=2");
+
+    // 一直不通过：422，带着每一次的输出
+    let (status, body) = infer(serde_json::json!({ "check": { "kind": "json" } })).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["error"]["code"], "output_validation_failed");
+    let attempts = body["error"]["attempts"].as_array().unwrap();
+    let outputs: Vec<_> = attempts.iter().map(|a| a["output"].clone()).collect();
+    assert_eq!(outputs, ["seed=0", "seed=1", "seed=2"]);
+    assert!(attempts[1]["sampling"]["temperature"].as_f64().unwrap() > 0.0);
+
+    let (status, body) = infer(serde_json::json!({
+        "check": { "kind": "regex", "pattern": "(" }
+    }))
+    .await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    assert_eq!(body["error"]["code"], "invalid_input");
+}
+
+/// 输出收到的采样参数
+struct SamplingEcho;
+
+#[async_trait]
+impl InferenceEngine for SamplingEcho {
+    async fn generate(&self, _prompt: &str, _max_tokens: usize) -> Result<String> {
+        Ok(String::new())
+    }
+
+    async fn complete_sampled(
+        &self,
+        _prompt: &str,
+        _max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<Generation> {
+        Ok(Generation {
+            text: serde_json::to_string(sampling)?,
+            finish_reason: FinishReason::Stop,
+        })
+    }
+
+    async fn generate_stream(
+        &self,
+        _prompt: &str,
+        _max_tokens: usize,
+        _sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn top_level_sampling_fields_reach_the_engine() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "echo",
+        "",
+        "none",
+        EngineKind::new("sampling"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("sampling", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(SamplingEcho) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "echo").await;
+
+    let body: serde_json::Value = client
+        .post("/infer")
+        .header(ContentType::JSON)
+        .body(
+            r#"{"model_name":"echo","prompt":"x","temperature":0.2,"top_p":0.9,"top_k":40,"seed":7}"#,
+        )
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let sampling: serde_json::Value =
+        serde_json::from_str(body["output"].as_str().unwrap()).unwrap();
+    assert_eq!(
+        sampling,
+        serde_json::json!({ "temperature": 0.2, "top_p": 0.9, "top_k": 40, "seed": 7 })
+    );
+}
+
+/// 主实例（cpu）遇到 "boom" 时出错，其他设备上正常回显
+struct PlacedEngine(DeviceSpec);
+
+#[async_trait]
+impl InferenceEngine for PlacedEngine {
+    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+        if prompt == "boom" && self.0 == DeviceSpec::Cpu {
+            anyhow::bail!("device lost");
+        }
+        Ok(format!("{prompt} on {}", self.0))
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let _ = sender.send(self.generate(prompt, max_tokens).await?).await;
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn standby_takes_over_while_the_primary_reloads_or_fails() {
+    let registry = ModelRegistry::empty();
+    registry.register(ModelMetadata::new(
+        "primary",
+        "",
+        "none",
+        EngineKind::new("placed"),
+    ));
+    let builds = AtomicUsize::new(0);
+    let state = AppState::builder()
+        .registry(registry)
+        .load_retry(LoadRetryPolicy::no_retry())
+        .standby(StandbyConfig {
+            model: Some("primary".to_string()),
+            device: DeviceSpec::Cuda(1),
+        })
+        .engine_factory("placed", move |_meta: &ModelMetadata, device| {
+            // 主实例和热备各建一次，之后的重新加载换权重失败
+            if builds.fetch_add(1, Ordering::SeqCst) >= 2 {
+                std::thread::sleep(Duration::from_millis(200));
+                anyhow::bail!("corrupt weights");
+            }
+            Ok(Arc::new(PlacedEngine(device)) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state.clone()).await;
+    load(&client, "primary").await;
+    assert!(state.standby.is_warm());
+
+    let infer = |prompt: &'static str| {
+        let client = &client;
+        async move {
+            let resp = client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(serde_json::json!({ "model_name": "primary", "prompt": prompt }).to_string())
+                .dispatch()
+                .await;
+            (
+                resp.status(),
+                resp.into_json::<serde_json::Value>().await.unwrap(),
+            )
+        }
+    };
+
+    let (_, body) = infer("hi").await;
+    assert_eq!(body["output"], "hi on cpu");
+    // 主实例出错：在热备上重试
+    let (status, body) = infer("boom").await;
+    assert_eq!(status, Status::Ok, "{body}");
+    assert_eq!(body["output"], "boom on cuda:1");
+    assert_eq!(body["served_by"], "primary");
+
+    // 重新加载期间、加载失败之后都由热备服务
+    state.load_model_in_background("primary").unwrap();
+    let (status, body) = infer("hi").await;
+    assert_eq!(status, Status::Ok, "{body}");
+    assert_eq!(body["output"], "hi on cuda:1");
+    while state.registry.get_model("primary").unwrap().status == ModelStatus::Loading {
+        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(
+        state.registry.get_model("primary").unwrap().status,
+        ModelStatus::Error
+    );
+    let (_, body) = infer("hi").await;
+    assert_eq!(body["output"], "hi on cuda:1");
+
+    // 卸载后热备一起释放
+    state.unload_model("primary").unwrap();
+    assert!(!state.standby.is_warm());
+    let (status, _) = infer("hi").await;
+    assert_eq!(status, Status::Conflict);
+}
+
+/// 每 5ms 输出一个词，记录同时在生成的个数
+struct TickEngine {
+    word: String,
+    active: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+}
+
+struct Active(Arc<AtomicUsize>);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl InferenceEngine for TickEngine {
+    async fn generate(&self, _prompt: &str, max_tokens: usize) -> Result<String> {
+        Ok(vec![self.word.as_str(); max_tokens].join(" "))
+    }
+
+    async fn generate_stream(
+        &self,
+        _prompt: &str,
+        max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let now = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+        let _active = Active(self.active.clone());
+        self.peak.fetch_max(now, Ordering::SeqCst);
+        for _ in 0..max_tokens {
+            rocket::tokio::time::sleep(Duration::from_millis(5)).await;
+            if sender.send(self.word.clone()).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn time_sliced_device_alternates_between_models() {
+    let registry = ModelRegistry::empty();
+    for name in ["long", "short"] {
+        registry.register(ModelMetadata::new(
+            name,
+            "",
+            "none",
+            EngineKind::new("tick"),
+        ));
+    }
+    let active = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let (a, p) = (active.clone(), peak.clone());
+    let state = AppState::builder()
+        .registry(registry)
+        .time_slice(TimeSliceConfig {
+            devices: vec![DeviceSpec::Cpu],
+            turn_ms: 30,
+        })
+        .engine_factory("tick", move |meta: &ModelMetadata, _device| {
+            Ok(Arc::new(TickEngine {
+                word: meta.name.clone(),
+                active: a.clone(),
+                peak: p.clone(),
+            }) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "long").await;
+    load(&client, "short").await;
+
+    let infer = |model: &'static str, max_tokens: usize, delay_ms: u64| {
+        let client = &client;
+        async move {
+            rocket::tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            let resp = client
+                .post("/infer")
+                .header(ContentType::JSON)
+                .body(
+                    serde_json::json!({
+                        "model_name": model,
+                        "prompt": "go",
+                        "max_tokens": max_tokens
+                    })
+                    .to_string(),
+                )
+                .dispatch()
+                .await;
+            let body: serde_json::Value = resp.into_json().await.unwrap();
+            (Instant::now(), body)
+        }
+    };
+    let ((long_done, long), (short_done, short)) =
+        rocket::tokio::join!(infer("long", 60, 0), infer("short", 4, 20));
+
+    // 短任务在长任务的一轮结束后就能跑完，长任务被切开后仍然是完整的输出
+    assert!(short_done < long_done);
+    assert_eq!(short["output"], "short short short short");
+    assert_eq!(long["output"].as_str().unwrap().split(' ').count(), 60);
+    assert_eq!(peak.load(Ordering::SeqCst), 1);
+    assert_eq!(active.load(Ordering::SeqCst), 0);
+}
+
+/// 续写时收到的 (prompt, partial)
+type Continuations = Arc<std::sync::Mutex<Vec<(String, String)>>>;
+
+/// 每 10ms 输出一个词（`w0`、`w1`…）；续写时记下 prompt 和 partial，接着输出 `c0`、`c1`…
+struct ResumeEngine {
+    continued: Continuations,
+}
+
+async fn stream_words(prefix: &str, max_tokens: usize, sender: mpsc::Sender<String>) {
+    for i in 0..max_tokens {
+        rocket::tokio::time::sleep(Duration::from_millis(10)).await;
+        if sender.send(format!("{prefix}{i}")).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[async_trait]
+impl InferenceEngine for ResumeEngine {
+    async fn generate(&self, _prompt: &str, _max_tokens: usize) -> Result<String> {
+        Ok("done".to_string())
+    }
+
+    async fn generate_stream(
+        &self,
+        _prompt: &str,
+        max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        stream_words("w", max_tokens, sender).await;
+        Ok(())
+    }
+
+    async fn continue_stream(
+        &self,
+        prompt: &str,
+        partial: &str,
+        max_tokens: usize,
+        _sampling: &SamplingParams,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        self.continued
+            .lock()
+            .unwrap()
+            .push((prompt.to_string(), partial.to_string()));
+        stream_words("c", max_tokens, sender).await;
+        Ok(())
+    }
+}
+
+fn resume_state(
+    configure: impl FnOnce(AppStateBuilder) -> AppStateBuilder,
+) -> (Arc<AppState>, Continuations) {
+    let registry = ModelRegistry::empty();
+    for name in ["resume", "other"] {
+        registry.register(ModelMetadata::new(
+            name,
+            "",
+            "none",
+            EngineKind::new("resume"),
+        ));
+    }
+    let continued = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = continued.clone();
+    let builder = AppState::builder().registry(registry).engine_factory(
+        "resume",
+        move |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(ResumeEngine {
+                continued: recorded.clone(),
+            }) as Arc<dyn InferenceEngine>)
+        },
+    );
+    (configure(builder).build(), continued)
+}
+
+/// 被抢占的 low 生成重新拿到 permit 后续写自己的输出，而不是把它拼进 prompt 重新生成
+#[rocket::async_test]
+async fn preempted_generations_resume_as_continuations() {
+    let (state, continued) = resume_state(|builder| builder.max_concurrent_infer(1));
+    let client = client_with(state).await;
+    load(&client, "resume").await;
+
+    let low = async {
+        client
+            .post("/infer?stream=true")
+            .header(ContentType::JSON)
+            .body(r#"{"model_name":"resume","prompt":"go","max_tokens":20,"priority":"low"}"#)
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap()
+    };
+    let high = async {
+        rocket::tokio::time::sleep(Duration::from_millis(55)).await;
+        client
+            .post("/infer")
+            .header(ContentType::JSON)
+            .body(r#"{"model_name":"resume","prompt":"urgent","priority":"high"}"#)
+            .dispatch()
+            .await
+            .status()
+    };
+    let (low, high) = rocket::tokio::join!(low, high);
+    assert_eq!(high, Status::Ok);
+
+    let chunks = sse_data(&low);
+    assert_eq!(chunks.len(), 20, "{chunks:?}");
+    let resumed_at = chunks.iter().position(|c| c == "c0").unwrap();
+    assert!(resumed_at > 0);
+    let continued = continued.lock().unwrap();
+    assert_eq!(
+        *continued,
+        [("go".to_string(), chunks[..resumed_at].join(" "))]
+    );
+}
+
+/// 分时设备上交出设备后同样续写自己的输出
+#[rocket::async_test]
+async fn time_sliced_generations_resume_as_continuations() {
+    let (state, continued) = resume_state(|builder| {
+        builder.time_slice(TimeSliceConfig {
+            devices: vec![DeviceSpec::Cpu],
+            turn_ms: 30,
+        })
+    });
+    let client = client_with(state).await;
+    load(&client, "resume").await;
+    load(&client, "other").await;
+
+    let infer = |model: &'static str, max_tokens: usize, delay_ms: u64| {
+        let client = &client;
+        async move {
+            rocket::tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+            let body = serde_json::json!({
+                "model_name": model,
+                "prompt": "go",
+                "max_tokens": max_tokens
+            });
+            client
+                .post("/infer?stream=true")
+                .header(ContentType::JSON)
+                .body(body.to_string())
+                .dispatch()
+                .await
+                .into_string()
+                .await
+                .unwrap()
+        }
+    };
+    let (long, short) = rocket::tokio::join!(infer("resume", 20, 0), infer("other", 2, 10));
+    assert_eq!(sse_data(&short), ["w0", "w1"]);
+
+    let chunks = sse_data(&long);
+    assert_eq!(chunks.len(), 20, "{chunks:?}");
+    let resumed_at = chunks.iter().position(|c| c == "c0").unwrap();
+    assert!(resumed_at > 0);
+    let continued = continued.lock().unwrap();
+    assert_eq!(
+        continued[0],
+        ("go".to_string(), chunks[..resumed_at].join(" "))
+    );
+}
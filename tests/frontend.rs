@@ -0,0 +1,60 @@
+use rocket::http::{Accept, Status};
+
+use local_llm_server::testing::{client_with_config, test_state};
+
+#[rocket::async_test]
+async fn serves_index_from_static_dir() {
+    let figment = rocket::Config::figment().merge(("static_dir", "static"));
+    let client = client_with_config(test_state(), figment).await;
+
+    let resp = client.get("/").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(resp
+        .into_string()
+        .await
+        .unwrap()
+        .contains("Local LLM Chat Demo"));
+}
+
+#[rocket::async_test]
+async fn missing_static_dir_serves_api_only() {
+    let figment = rocket::Config::figment().merge(("static_dir", "does/not/exist"));
+    let client = client_with_config(test_state(), figment).await;
+
+    assert_eq!(client.get("/").dispatch().await.status(), Status::NotFound);
+    assert_eq!(client.get("/health").dispatch().await.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn serve_static_false_disables_frontend() {
+    let figment = rocket::Config::figment().merge(("serve_static", false));
+    let client = client_with_config(test_state(), figment).await;
+
+    assert_eq!(client.get("/").dispatch().await.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn spa_fallback_serves_index_for_html_requests() {
+    let figment = rocket::Config::figment().merge(("spa_fallback", true));
+    let client = client_with_config(test_state(), figment).await;
+
+    let resp = client
+        .get("/chat/some/client/route")
+        .header(Accept::HTML)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(resp
+        .into_string()
+        .await
+        .unwrap()
+        .contains("Local LLM Chat Demo"));
+
+    // 非页面请求不受影响
+    let resp = client
+        .get("/chat/some/client/route")
+        .header(Accept::JSON)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+}
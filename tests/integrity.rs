@@ -0,0 +1,136 @@
+use std::path::Path;
+use std::time::Duration;
+
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{Device, Tensor};
+use rocket::http::{ContentType, Status};
+use sha2::{Digest, Sha256};
+
+use local_llm_server::testing::{client_with_config, test_state};
+
+fn tiny_gguf() -> Vec<u8> {
+    let tensor = Tensor::zeros((2, 32), candle_core::DType::F32, &Device::Cpu).unwrap();
+    let qtensor = QTensor::quantize(&tensor, GgmlDType::F32).unwrap();
+    let mut buf = std::io::Cursor::new(Vec::new());
+    let arch = gguf_file::Value::String("llama".to_string());
+    gguf_file::write(
+        &mut buf,
+        &[("general.architecture", &arch)],
+        &[("w", &qtensor)],
+    )
+    .unwrap();
+    buf.into_inner()
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// 模拟 hf-hub 缓存：blob 以 `blob_name` 命名，snapshot 里放指向它的符号链接
+fn put_hub_file(cache: &Path, repo_dir: &str, filename: &str, blob_name: &str, bytes: &[u8]) {
+    let repo = cache.join(repo_dir);
+    std::fs::create_dir_all(repo.join("blobs")).unwrap();
+    std::fs::create_dir_all(repo.join("snapshots/rev1")).unwrap();
+    std::fs::write(repo.join("blobs").join(blob_name), bytes).unwrap();
+    std::os::unix::fs::symlink(
+        Path::new("../../blobs").join(blob_name),
+        repo.join("snapshots/rev1").join(filename),
+    )
+    .unwrap();
+}
+
+#[rocket::async_test]
+async fn scan_flags_corrupt_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path();
+    let good = tiny_gguf();
+
+    put_hub_file(
+        cache,
+        "models--acme--tiny",
+        "good.gguf",
+        &sha256(&good),
+        &good,
+    );
+    put_hub_file(
+        cache,
+        "models--acme--tiny",
+        "mismatch.gguf",
+        &"0".repeat(64),
+        &good,
+    );
+    put_hub_file(
+        cache,
+        "models--acme--tiny",
+        "garbage.gguf",
+        &"1".repeat(64),
+        b"not a gguf",
+    );
+    put_hub_file(
+        cache,
+        "models--acme--tiny",
+        "truncated.gguf",
+        &"2".repeat(64),
+        &good[..good.len() - 16],
+    );
+    std::fs::create_dir_all(cache.join("local")).unwrap();
+    std::fs::write(cache.join("local/unrecorded.gguf"), &good).unwrap();
+
+    let figment = rocket::Config::figment().merge(("model_cache_dir", cache));
+    let client = client_with_config(test_state(), figment).await;
+
+    let resp = client
+        .post("/admin/integrity/scan")
+        .header(ContentType::JSON)
+        .body("{}")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Accepted);
+    let accepted: serde_json::Value = resp.into_json().await.unwrap();
+    let job_id = accepted["job_id"].as_str().unwrap().to_string();
+
+    let mut job = serde_json::Value::Null;
+    for _ in 0..100 {
+        job = client
+            .get(format!("/jobs/{job_id}"))
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        if job["status"] == "succeeded" || job["status"] == "failed" {
+            break;
+        }
+        rocket::tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(job["status"], "succeeded", "{job}");
+
+    let report = &job["result"];
+    assert_eq!(report["scanned"], 5);
+    assert_eq!(report["corrupt"], 3);
+
+    let status_of = |name: &str| {
+        report["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| f["path"].as_str().unwrap().ends_with(name))
+            .map(|f| f["status"].as_str().unwrap().to_string())
+            .unwrap()
+    };
+    assert_eq!(status_of("good.gguf"), "ok");
+    assert_eq!(status_of("mismatch.gguf"), "checksum_mismatch");
+    assert_eq!(status_of("garbage.gguf"), "parse_error");
+    assert_eq!(status_of("truncated.gguf"), "truncated");
+    assert_eq!(status_of("unrecorded.gguf"), "unverified");
+}
+
+#[rocket::async_test]
+async fn unknown_job_is_404() {
+    let client = client_with_config(test_state(), rocket::Config::figment()).await;
+    let resp = client.get("/jobs/job-999").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{Device, Tensor};
+use rocket::http::{ContentType, Status};
+
+use local_llm_server::app_state::AppState;
+use local_llm_server::model_registry::{EngineKind, LocalArtifacts, ModelMetadata};
+use local_llm_server::testing::{client, client_with, fake_registry};
+
+/// 2 层、4 个 head（2 个 KV head）、64 维的假 llama
+fn write_tiny_gguf(path: &std::path::Path) {
+    let tensor = Tensor::zeros((8, 32), candle_core::DType::F32, &Device::Cpu).unwrap();
+    let qtensor = QTensor::quantize(&tensor, GgmlDType::F32).unwrap();
+    let arch = gguf_file::Value::String("llama".to_string());
+    let blocks = gguf_file::Value::U32(2);
+    let embedding = gguf_file::Value::U32(64);
+    let heads = gguf_file::Value::U32(4);
+    let kv_heads = gguf_file::Value::U32(2);
+    let mut file = std::fs::File::create(path).unwrap();
+    gguf_file::write(
+        &mut file,
+        &[
+            ("general.architecture", &arch),
+            ("llama.block_count", &blocks),
+            ("llama.embedding_length", &embedding),
+            ("llama.attention.head_count", &heads),
+            ("llama.attention.head_count_kv", &kv_heads),
+        ],
+        &[("w", &qtensor)],
+    )
+    .unwrap();
+}
+
+fn state_with_gguf(dir: &std::path::Path, context_window: usize) -> Arc<AppState> {
+    let gguf = dir.join("tiny.gguf");
+    write_tiny_gguf(&gguf);
+    let registry = fake_registry();
+    registry.register(
+        ModelMetadata::new("tiny", "", "none", EngineKind::DUMMY)
+            .with_artifacts(LocalArtifacts {
+                gguf,
+                tokenizer: None,
+                sha256: None,
+            })
+            .with_context_window(context_window),
+    );
+    AppState::builder().registry(registry).build()
+}
+
+#[rocket::async_test]
+async fn estimate_reports_weights_and_kv_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let client = client_with(state_with_gguf(dir.path(), 1024)).await;
+
+    let resp = client.get("/models/tiny/estimate?ctx=100").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["context_length"], 100);
+    assert_eq!(body["weights_bytes"], 8 * 32 * 4);
+    assert_eq!(body["kv_cache_bytes"], 2 * 2 * 2 * 16 * 4 * 100);
+
+    // 不带 ctx 时用模型的上下文窗口
+    let resp = client.get("/models/tiny/estimate").dispatch().await;
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["context_length"], 1024);
+}
+
+#[rocket::async_test]
+async fn estimate_without_local_weights_is_404() {
+    let client = client().await;
+    let resp = client.get("/models/dummy-a/estimate").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "weights_not_found");
+
+    let resp = client.get("/models/missing/estimate").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn load_refuses_when_estimate_exceeds_available_memory() {
+    if local_llm_server::memory::available_memory().is_none() {
+        return;
+    }
+    // 上下文窗口大到 KV cache 不可能放得下
+    let dir = tempfile::tempdir().unwrap();
+    let client = client_with(state_with_gguf(dir.path(), 1 << 40)).await;
+
+    let load = |body: serde_json::Value| {
+        client
+            .post("/load")
+            .header(ContentType::JSON)
+            .body(body.to_string())
+            .dispatch()
+    };
+    let resp = load(serde_json::json!({ "model_name": "tiny" })).await;
+    assert_eq!(resp.status(), Status::InsufficientStorage);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["error"]["code"], "insufficient_memory");
+
+    let resp = load(serde_json::json!({ "model_name": "tiny", "force": true, "wait": true })).await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body: serde_json::Value = resp.into_json().await.unwrap();
+    assert_eq!(body["status"], "loaded");
+}
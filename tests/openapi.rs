@@ -0,0 +1,78 @@
+use rocket::http::{ContentType, Status};
+
+use local_llm_server::testing::client;
+
+/// 挂载的每个 API 路由都要出现在 /openapi.json 里
+#[rocket::async_test]
+async fn openapi_covers_every_route() {
+    let client = client().await;
+    let resp = client.get("/openapi.json").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    let spec: serde_json::Value = resp.into_json().await.unwrap();
+    assert!(spec["components"]["schemas"]["InferRequest"].is_object());
+    assert!(spec["components"]["securitySchemes"]["api_key"].is_object());
+
+    let mut missing = Vec::new();
+    for route in client.rocket().routes() {
+        let path = route.uri.path().to_string();
+        // 静态文件和文档本身不算
+        if route.name.is_none()
+            || path.contains("..>")
+            || path == "/openapi.json"
+            || path == "/docs"
+        {
+            continue;
+        }
+        let path = path.replace('<', "{").replace('>', "}");
+        let method = route.method.as_str().to_lowercase();
+        if !spec["paths"][&path][&method].is_object() {
+            missing.push(format!("{method} {path}"));
+        }
+    }
+    assert!(missing.is_empty(), "routes missing from spec: {missing:?}");
+}
+
+#[rocket::async_test]
+async fn docs_serves_swagger_ui() {
+    let client = client().await;
+    let resp = client.get("/docs").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.content_type(), Some(ContentType::HTML));
+    assert!(resp.into_string().await.unwrap().contains("/openapi.json"));
+}
+
+/// 原生接口也挂在 /api/v1 下；旧路径带弃用头，OpenAI 兼容接口不受影响
+#[rocket::async_test]
+async fn api_v1_prefix_and_legacy_deprecation_headers() {
+    let client = client().await;
+
+    let resp = client.get("/api/v1/health").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.headers().get_one("X-API-Version"), Some("1"));
+    assert_eq!(resp.headers().get_one("Deprecation"), None);
+
+    let resp = client.get("/health").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(resp.headers().get_one("X-API-Version"), Some("1"));
+    assert_eq!(resp.headers().get_one("Deprecation"), Some("true"));
+    assert_eq!(
+        resp.headers().get_one("Link"),
+        Some("</api/v1/health>; rel=\"successor-version\"")
+    );
+
+    let resp = client.get("/v1/models").dispatch().await;
+    assert_eq!(resp.headers().get_one("X-API-Version"), None);
+    assert_eq!(resp.headers().get_one("Deprecation"), None);
+
+    let spec: serde_json::Value = client
+        .get("/openapi.json")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert!(spec["paths"]["/api/v1/infer"]["post"].is_object());
+    assert_eq!(spec["paths"]["/infer"]["post"]["deprecated"], true);
+    assert!(spec["paths"]["/api/v1/infer"]["post"]["deprecated"].is_null());
+    assert!(spec["paths"]["/v1/models"]["get"]["deprecated"].is_null());
+}
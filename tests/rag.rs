@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use rocket::http::Status;
+use rocket::local::asynchronous::Client;
+use serde_json::{json, Value};
+
+use local_llm_server::app_state::AppState;
+use local_llm_server::rag::{RagProfileConfig, RagProfiles};
+use local_llm_server::testing::{client_with, client_with_config, fake_registry};
+
+/// dummy-b 当 embedding 模型（dummy 引擎按词袋编码），dummy-a 负责回答
+async fn rag_client() -> Client {
+    client_with(rag_state()).await
+}
+
+fn rag_state() -> std::sync::Arc<AppState> {
+    let mut profiles = HashMap::new();
+    profiles.insert(
+        "docs".to_string(),
+        RagProfileConfig {
+            embedding_model: "dummy-b".to_string(),
+            chat_model: "dummy-a".to_string(),
+            top_k: 1,
+            keyword_weight: 0.3,
+        },
+    );
+    AppState::builder()
+        .registry(fake_registry())
+        .rag(RagProfiles::from_config(&profiles).unwrap())
+        .build()
+}
+
+async fn model_status(client: &Client, name: &str) -> Value {
+    let models: Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    models
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["name"] == name)
+        .unwrap()["status"]
+        .clone()
+}
+
+#[rocket::async_test]
+async fn chat_against_a_profile_retrieves_context() {
+    let client = rag_client().await;
+    let documents = json!({
+        "documents": [
+            "Paris is the capital of France.",
+            "The mitochondria is the powerhouse of the cell.",
+        ]
+    });
+
+    let resp = client.post("/rag/nope/load").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+    // embedding 模型还没加载
+    let resp = client
+        .post("/rag/docs/documents")
+        .json(&documents)
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Conflict);
+
+    let loaded: Value = client
+        .post("/rag/docs/load")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(loaded[0]["model_name"], "dummy-b");
+    assert_eq!(loaded[1]["status"], "loaded");
+
+    let added: Value = client
+        .post("/rag/docs/documents")
+        .json(&documents)
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(added["total_chunks"], 2);
+
+    let resp: Value = client
+        .post("/v1/chat/completions")
+        .json(&json!({
+            "model": "docs",
+            "messages": [{ "role": "user", "content": "what is the capital of france" }],
+            "render_debug": true,
+        }))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(resp["model"], "dummy-a");
+    let prompt = resp["rendered_prompt"].as_str().unwrap();
+    assert!(
+        prompt.contains("[1] Paris is the capital of France."),
+        "{prompt}"
+    );
+    assert!(!prompt.contains("mitochondria"), "{prompt}");
+}
+
+#[rocket::async_test]
+async fn pair_is_unloaded_together() {
+    let client = rag_client().await;
+    client.post("/rag/docs/load").dispatch().await;
+    assert_eq!(model_status(&client, "dummy-a").await, "loaded");
+
+    let resp = client
+        .post("/admin/models/dummy-b/unload?force=true")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(model_status(&client, "dummy-a").await, "unloaded");
+    assert_eq!(model_status(&client, "dummy-b").await, "unloaded");
+}
+
+#[rocket::async_test]
+async fn reingesting_documents_hits_the_embedding_cache() {
+    let mut profiles = HashMap::new();
+    profiles.insert(
+        "docs".to_string(),
+        RagProfileConfig {
+            embedding_model: "dummy-b".to_string(),
+            chat_model: "dummy-a".to_string(),
+            top_k: 1,
+            keyword_weight: 0.3,
+        },
+    );
+    let state = AppState::builder()
+        .registry(fake_registry())
+        .rag(RagProfiles::from_config(&profiles).unwrap())
+        .build();
+    let client = client_with(state.clone()).await;
+    client.post("/rag/docs/load").dispatch().await;
+
+    // 同一批里重复的段落只编码一次
+    let documents = json!({ "documents": ["alpha beta", "gamma delta", "alpha beta"] });
+    for _ in 0..2 {
+        let resp = client
+            .post("/rag/docs/documents")
+            .json(&documents)
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+    }
+    let stats = state.embedding_cache.stats();
+    assert_eq!((stats.misses, stats.hits, stats.entries), (3, 3, 2));
+}
+
+#[rocket::async_test]
+async fn upserts_only_reembed_changed_chunks() {
+    let client = rag_client().await;
+    client.post("/rag/docs/load").dispatch().await;
+    // 每段都超过半个块，不会和相邻的段合并
+    let paris = "Paris is the capital of France. ".repeat(20);
+    let rome = "Rome is the capital of Italy. ".repeat(20);
+    let berlin = "Berlin is the capital of Germany. ".repeat(20);
+    let madrid = "Madrid is the capital of Spain. ".repeat(20);
+
+    let upsert = |documents: Value| {
+        let client = &client;
+        async move {
+            let resp = client
+                .put("/rag/docs/documents")
+                .json(&json!({ "documents": documents }))
+                .dispatch()
+                .await;
+            assert_eq!(resp.status(), Status::Ok);
+            resp.into_json::<Value>().await.unwrap()
+        }
+    };
+    let body = upsert(json!([
+        { "id": "europe", "text": format!("{paris}\n\n{rome}") },
+        { "id": "germany", "text": berlin },
+    ]))
+    .await;
+    assert_eq!(body["documents"][0]["embedded"], 2);
+    assert_eq!(body["total_chunks"], 3);
+
+    let body = upsert(json!([
+        { "id": "europe", "text": format!("{paris}\n\n{madrid}") },
+    ]))
+    .await;
+    let europe = &body["documents"][0];
+    assert_eq!(
+        (
+            &europe["embedded"],
+            &europe["unchanged"],
+            &europe["removed"]
+        ),
+        (&json!(1), &json!(1), &json!(1))
+    );
+    assert_eq!(body["total_chunks"], 3);
+    assert_eq!(body["tombstones"], 1);
+
+    let resp = client
+        .put("/rag/docs/documents")
+        .json(&json!({ "documents": [{ "id": "x", "text": "a" }, { "id": "x", "text": "b" }] }))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    let resp = client
+        .delete("/rag/docs/documents/missing")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let body: Value = client
+        .delete("/rag/docs/documents/germany")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(
+        (&body["removed"], &body["tombstones"]),
+        (&json!(1), &json!(2))
+    );
+
+    // 删掉的文档不再被检索到
+    let resp: Value = client
+        .post("/v1/chat/completions")
+        .json(&json!({
+            "model": "docs",
+            "messages": [{ "role": "user", "content": "what is the capital of germany" }],
+            "render_debug": true,
+        }))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert!(!resp["rendered_prompt"].as_str().unwrap().contains("Berlin"));
+
+    let body: Value = client
+        .post("/rag/docs/compact")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(
+        (&body["compacted"], &body["total_chunks"]),
+        (&json!(2), &json!(2))
+    );
+}
+
+#[rocket::async_test]
+async fn answers_cite_document_spans() {
+    let client = rag_client().await;
+    client.post("/rag/docs/load").dispatch().await;
+    let document = "  Paris is the capital of France.";
+    let added: Value = client
+        .post("/rag/docs/documents")
+        .json(&json!({ "documents": [document, "Mitochondria power the cell."] }))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(added["document_ids"], json!(["doc-1", "doc-2"]));
+
+    let resp: Value = client
+        .post("/v1/chat/completions")
+        .json(&json!({
+            "model": "docs",
+            "messages": [{ "role": "user", "content": "what is the capital of france" }],
+            "grounding": true,
+        }))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let source = &resp["rag_sources"][0];
+    assert_eq!(source["document_id"], "doc-1");
+    let (start, end) = (
+        source["start"].as_u64().unwrap() as usize,
+        source["end"].as_u64().unwrap() as usize,
+    );
+    assert_eq!(&document[start..end], source["text"]);
+    // dummy 引擎把 prompt（含检索到的段）大写后原样输出
+    let grounding = resp["grounding"].as_array().unwrap();
+    assert!(grounding.iter().any(|g| g["source"] == 0), "{grounding:?}");
+
+    let resp = client
+        .post("/v1/chat/completions")
+        .json(&json!({
+            "model": "dummy-a",
+            "messages": [{ "role": "user", "content": "hi" }],
+            "grounding": true,
+        }))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn documents_are_scoped_to_the_api_key_namespace() {
+    let figment = rocket::Config::figment()
+        .merge(("api_keys.sk-alice", json!({})))
+        .merge(("api_keys.sk-bob", json!({})))
+        .merge(("api_keys.sk-team-1", json!({ "rag_namespace": "team" })))
+        .merge(("api_keys.sk-team-2", json!({ "rag_namespace": "team" })));
+    let client = client_with_config(rag_state(), figment).await;
+    client.post("/rag/docs/load").dispatch().await;
+    let key = |key: &'static str| rocket::http::Header::new("X-API-Key", key);
+    let sources = |key_name: &'static str| {
+        let client = &client;
+        async move {
+            let resp: Value = client
+                .post("/v1/chat/completions")
+                .header(key(key_name))
+                .json(&json!({
+                    "model": "docs",
+                    "messages": [{ "role": "user", "content": "what is the capital of france" }],
+                }))
+                .dispatch()
+                .await
+                .into_json()
+                .await
+                .unwrap();
+            resp["rag_sources"].as_array().cloned().unwrap_or_default()
+        }
+    };
+
+    let resp = client
+        .post("/rag/docs/documents")
+        .header(key("sk-alice"))
+        .json(&json!({ "documents": ["Paris is the capital of France."] }))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(sources("sk-alice").await.len(), 1);
+    // 别的 key 和不带 key 的请求都检索不到
+    assert!(sources("sk-bob").await.is_empty());
+    let resp: Value = client
+        .post("/v1/chat/completions")
+        .json(&json!({
+            "model": "docs",
+            "messages": [{ "role": "user", "content": "what is the capital of france" }],
+        }))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert!(resp.get("rag_sources").is_none());
+    let resp = client
+        .delete("/rag/docs/documents/doc-1")
+        .header(key("sk-bob"))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+
+    // 同一个 rag_namespace 的 key 共享文档
+    let resp = client
+        .put("/rag/docs/documents")
+        .header(key("sk-team-1"))
+        .json(&json!({ "documents": [{ "id": "faq", "text": "The capital of France is Paris." }] }))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let shared = sources("sk-team-2").await;
+    assert_eq!(shared[0]["document_id"], "faq");
+    assert_eq!(sources("sk-alice").await[0]["document_id"], "doc-1");
+}
@@ -0,0 +1,409 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use rocket::http::{ContentType, Status};
+use rocket::local::asynchronous::Client;
+use rocket::tokio::sync::mpsc;
+use serde_json::{json, Value};
+
+use local_llm_server::app_state::AppState;
+use local_llm_server::engine::{FinishReason, Generation, InferenceEngine, SamplingParams};
+use local_llm_server::model_registry::{EngineKind, ModelMetadata};
+use local_llm_server::testing::{client, client_with, fake_registry, load, sse_data};
+
+async fn post(client: &Client, uri: &str, body: Value) -> (Status, Value) {
+    let resp = client
+        .post(uri.to_string())
+        .header(ContentType::JSON)
+        .body(body.to_string())
+        .dispatch()
+        .await;
+    (resp.status(), resp.into_json().await.unwrap())
+}
+
+#[rocket::async_test]
+async fn session_keeps_history_and_compresses_when_over_window() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    // 64 token 留给生成，prompt 预算只有 24
+    let (status, session) = post(
+        &client,
+        "/sessions",
+        json!({
+            "model_name": "dummy-a",
+            "options": { "compression": "summarize", "keep_recent": 1, "context_window": 88 }
+        }),
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+    let id = session["id"].as_str().unwrap().to_string();
+    let uri = format!("/sessions/{id}/messages");
+
+    let (status, first) = post(&client, &uri, json!({ "content": "hello there. the rest is not the first sentence so the summary drops it" })).await;
+    assert_eq!(status, Status::Ok);
+    assert!(first["reply"]
+        .as_str()
+        .unwrap()
+        .starts_with("[dummy-a DUMMY] USER: HELLO THERE."));
+    assert!(first.get("compression").is_none());
+
+    let (_, second) = post(&client, &uri, json!({ "content": "tell me more please" })).await;
+    let report = &second["compression"];
+    assert_eq!(report["strategy"], "summarize");
+    assert_eq!(report["dropped_turns"], 0);
+    assert!(report["tokens_after"].as_u64().unwrap() <= 24);
+    let reply = second["reply"].as_str().unwrap();
+    assert!(reply.contains("SUMMARY OF EARLIER CONVERSATION"));
+    assert!(!reply.contains("THE REST IS NOT"));
+    assert!(reply.ends_with("USER: TELL ME MORE PLEASE"));
+
+    // 存下来的历史不受压缩影响
+    let session: Value = client
+        .get(format!("/sessions/{id}"))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(session["turns"].as_array().unwrap().len(), 4);
+}
+
+#[rocket::async_test]
+async fn session_errors_use_status_codes() {
+    let client = client().await;
+
+    let (status, body) = post(&client, "/sessions", json!({ "model_name": "nope" })).await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(body["error"]["code"], "model_not_found");
+
+    let (status, body) = post(
+        &client,
+        "/sessions/missing/messages",
+        json!({ "content": "hi" }),
+    )
+    .await;
+    assert_eq!(status, Status::NotFound);
+    assert_eq!(body["error"]["code"], "session_not_found");
+
+    let (_, session) = post(&client, "/sessions", json!({ "model_name": "dummy-b" })).await;
+    let uri = format!("/sessions/{}/messages", session["id"].as_str().unwrap());
+    let (status, body) = post(&client, &uri, json!({ "content": "hi" })).await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(body["error"]["code"], "model_not_loaded");
+}
+
+#[rocket::async_test]
+async fn closing_session_unloads_its_scratch_models() {
+    let client = client().await;
+    let (_, session) = post(&client, "/sessions", json!({ "model_name": "dummy-a" })).await;
+    let id = session["id"].as_str().unwrap().to_string();
+
+    let (status, loaded) = post(
+        &client,
+        "/load",
+        json!({ "model_name": "dummy-a", "scratch": { "ttl_secs": 600, "session_id": id } }),
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(loaded["status"], "loaded");
+
+    let models: Value = client
+        .get("/models")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    let model = models
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|m| m["name"] == "dummy-a")
+        .unwrap();
+    assert!(model["scratch_expires_in_secs"].as_u64().unwrap() > 590);
+
+    let resp = client.delete(format!("/sessions/{id}")).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    let body: Value = resp.into_json().await.unwrap();
+    assert_eq!(body["released_models"], json!(["dummy-a"]));
+
+    let (status, reply) = post(
+        &client,
+        "/infer",
+        json!({ "model_name": "dummy-a", "prompt": "hi" }),
+    )
+    .await;
+    assert_eq!(status, Status::Conflict);
+    assert_eq!(reply["error"]["code"], "model_not_loaded");
+}
+
+#[rocket::async_test]
+async fn memory_folds_older_turns_and_can_be_edited() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+
+    let (_, session) = post(
+        &client,
+        "/sessions",
+        json!({
+            "model_name": "dummy-a",
+            "options": { "memory": true, "keep_recent": 2, "context_window": 124 }
+        }),
+    )
+    .await;
+    let id = session["id"].as_str().unwrap().to_string();
+    let uri = format!("/sessions/{id}/messages");
+    let memory_uri = format!("/sessions/{id}/memory");
+    let get_memory = || async {
+        let resp = client.get(memory_uri.clone()).dispatch().await;
+        assert_eq!(resp.status(), Status::Ok);
+        resp.into_json::<Value>().await.unwrap()
+    };
+
+    post(
+        &client,
+        &uri,
+        json!({ "content": "my name is ada. i write compilers" }),
+    )
+    .await;
+    assert_eq!(get_memory().await["summarized_turns"], 0);
+
+    // 历史接近预算后较早的一问一答折叠进记忆，最近两轮原样保留
+    post(&client, &uri, json!({ "content": "what do i do" })).await;
+    let (_, third) = post(
+        &client,
+        &uri,
+        json!({ "content": "and my name?", "render_debug": true }),
+    )
+    .await;
+    let memory = get_memory().await;
+    assert_eq!(memory["summarized_turns"], 2);
+    assert!(memory["content"]
+        .as_str()
+        .unwrap()
+        .starts_with("user: my name is ada."));
+    assert!(third["rendered_prompt"]
+        .as_str()
+        .unwrap()
+        .starts_with("system: Conversation memory: user: my name is ada."));
+
+    let resp = client
+        .put(memory_uri.clone())
+        .header(ContentType::JSON)
+        .body(json!({ "content": "the user is called grace" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let edited: Value = resp.into_json().await.unwrap();
+    assert_eq!(edited["edited"], true);
+
+    let (_, reply) = post(
+        &client,
+        &uri,
+        json!({ "content": "who am i", "render_debug": true }),
+    )
+    .await;
+    assert!(reply["rendered_prompt"]
+        .as_str()
+        .unwrap()
+        .starts_with("system: Conversation memory: the user is called grace"));
+
+    let resp = client.get("/sessions/missing/memory").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+}
+
+/// 回复里只报告收到的采样参数和生成长度
+struct SamplingEcho;
+
+#[async_trait]
+impl InferenceEngine for SamplingEcho {
+    async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
+        Ok(prompt.to_string())
+    }
+
+    async fn complete_sampled(
+        &self,
+        _prompt: &str,
+        max_tokens: usize,
+        sampling: &SamplingParams,
+    ) -> Result<Generation> {
+        Ok(Generation {
+            text: format!(
+                "temperature={:?} top_p={:?} max_tokens={max_tokens}",
+                sampling.temperature, sampling.top_p
+            ),
+            finish_reason: FinishReason::Stop,
+        })
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        _max_tokens: usize,
+        sender: mpsc::Sender<String>,
+    ) -> Result<()> {
+        let _ = sender.send(prompt.to_string()).await;
+        Ok(())
+    }
+}
+
+#[rocket::async_test]
+async fn session_defaults_apply_under_message_overrides() {
+    let registry = fake_registry();
+    registry.register(ModelMetadata::new(
+        "echo",
+        "",
+        "none",
+        EngineKind::new("echo"),
+    ));
+    let state = AppState::builder()
+        .registry(registry)
+        .engine_factory("echo", |_meta: &ModelMetadata, _device| {
+            Ok(Arc::new(SamplingEcho) as Arc<dyn InferenceEngine>)
+        })
+        .build();
+    let client = client_with(state).await;
+    load(&client, "echo").await;
+
+    let (_, session) = post(
+        &client,
+        "/sessions",
+        json!({
+            "model_name": "echo",
+            "options": { "defaults": {
+                "system_prompt": "You write poems.",
+                "max_tokens": 20,
+                "sampling": { "temperature": 1.2 }
+            } }
+        }),
+    )
+    .await;
+    let id = session["id"].as_str().unwrap().to_string();
+    let uri = format!("/sessions/{id}/messages");
+
+    let (_, reply) = post(
+        &client,
+        &uri,
+        json!({ "content": "a poem", "sampling": { "top_p": 0.9 }, "render_debug": true }),
+    )
+    .await;
+    assert_eq!(
+        reply["reply"],
+        "temperature=Some(1.2) top_p=Some(0.9) max_tokens=20"
+    );
+    assert!(reply["rendered_prompt"]
+        .as_str()
+        .unwrap()
+        .starts_with("system: You write poems.\nuser: a poem"));
+
+    let (_, reply) = post(
+        &client,
+        &uri,
+        json!({ "content": "again", "max_tokens": 5, "sampling": { "temperature": 0.1 } }),
+    )
+    .await;
+    assert_eq!(
+        reply["reply"],
+        "temperature=Some(0.1) top_p=None max_tokens=5"
+    );
+
+    // PATCH 只覆盖给了的字段
+    let resp = client
+        .patch(format!("/sessions/{id}"))
+        .header(ContentType::JSON)
+        .body(
+            json!({ "system_prompt": "You review code.", "sampling": { "temperature": 0.2 } })
+                .to_string(),
+        )
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let updated: Value = resp.into_json().await.unwrap();
+    assert_eq!(updated["options"]["defaults"]["max_tokens"], 20);
+    assert_eq!(
+        updated["options"]["defaults"]["system_prompt"],
+        "You review code."
+    );
+
+    let (_, reply) = post(
+        &client,
+        &uri,
+        json!({ "content": "fix this", "render_debug": true }),
+    )
+    .await;
+    assert_eq!(
+        reply["reply"],
+        "temperature=Some(0.2) top_p=None max_tokens=20"
+    );
+    assert!(reply["rendered_prompt"]
+        .as_str()
+        .unwrap()
+        .starts_with("system: You review code."));
+
+    let resp = client
+        .patch("/sessions/missing")
+        .header(ContentType::JSON)
+        .body("{}")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn watchers_see_the_same_live_generation() {
+    let client = client().await;
+    load(&client, "dummy-a").await;
+    let (_, session) = post(&client, "/sessions", json!({ "model_name": "dummy-a" })).await;
+    let id = session["id"].as_str().unwrap().to_string();
+
+    let watcher = client
+        .get(format!("/sessions/{id}/stream"))
+        .dispatch()
+        .await;
+    assert_eq!(watcher.status(), Status::Ok);
+
+    let sender = client
+        .post(format!("/sessions/{id}/messages"))
+        .header(ContentType::JSON)
+        .body(json!({ "content": "hi there", "stream": true }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(sender.status(), Status::Ok);
+    let sent = sender.into_string().await.unwrap();
+    let events = sse_data(&sent);
+    let first: Value = serde_json::from_str(&events[0]).unwrap();
+    assert_eq!(first, json!({ "type": "start", "message": "hi there" }));
+    let last: Value = serde_json::from_str(events.last().unwrap()).unwrap();
+    assert_eq!(last["type"], "done");
+    let reply = last["reply"].as_str().unwrap().to_string();
+    assert!(reply.ends_with("USER: HI THERE"));
+
+    // 写入历史的是拼好的完整回复
+    let stored: Value = client
+        .get(format!("/sessions/{id}"))
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    assert_eq!(stored["turns"][1]["content"], reply);
+
+    // 关闭 session 后订阅者的流结束，内容和发送方看到的一样
+    client.delete(format!("/sessions/{id}")).dispatch().await;
+    let watched = watcher.into_string().await.unwrap();
+    assert_eq!(sse_data(&watched), events);
+
+    let (_, session) = post(&client, "/sessions", json!({ "model_name": "dummy-a" })).await;
+    let id = session["id"].as_str().unwrap().to_string();
+    let resp = client.get("/sessions/missing/stream").dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let (_, reply) = post(
+        &client,
+        &format!("/sessions/{id}/messages"),
+        json!({ "content": "x", "stream": true, "render_debug": true }),
+    )
+    .await;
+    assert_eq!(reply["error"]["code"], "invalid_input");
+}